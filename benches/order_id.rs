@@ -0,0 +1,37 @@
+// 订单ID分配的开销：单线程递增和多线程共享分配器
+use criterion::{criterion_group, criterion_main, Criterion};
+use lightning::matching::OrderIdAllocator;
+use std::hint::black_box;
+use std::sync::Arc;
+
+const THREADS: usize = 4;
+const IDS_PER_THREAD: usize = 10_000;
+
+fn bench_order_ids(c: &mut Criterion) {
+    c.bench_function("fetch_order_id", |b| {
+        let allocator = OrderIdAllocator::default();
+        b.iter(|| black_box(allocator.fetch_order_id()));
+    });
+
+    c.bench_function("fetch_order_id_contended", |b| {
+        b.iter(|| {
+            let allocator = Arc::new(OrderIdAllocator::default());
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let allocator = allocator.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..IDS_PER_THREAD {
+                            black_box(allocator.fetch_order_id());
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        });
+    });
+}
+
+criterion_group!(benches, bench_order_ids);
+criterion_main!(benches);
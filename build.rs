@@ -1,5 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_prost_build::compile_protos("schema/proto/lightning.proto")?;
-    tonic_prost_build::compile_protos("schema/proto/management.proto")?;
+    // 消息类型同时支持 JSON 序列化，供 REST 网关使用，字段名与 proto 一致 (camelCase)
+    tonic_prost_build::configure()
+        .message_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".", "#[serde(rename_all = \"camelCase\", default)]")
+        .compile_protos(
+            &["schema/proto/lightning.proto", "schema/proto/management.proto"],
+            &["schema/proto"],
+        )?;
     Ok(())
-}
\ No newline at end of file
+}
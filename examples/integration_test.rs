@@ -71,6 +71,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         volume: None,
         taker_rate: None,
         maker_rate: None,
+        time_in_force: None,
+        post_only: None,
+        display_quantity: None,
+        stop_price: None,
+        trigger_direction: None,
+        protection_price: None,
+        expires_at: None,
+        validate_only: None,
+        client_order_id: None,
+        nonce: None,
     });
     let buy_order_response = client.place_order(buy_order_request).await?;
     let buy_order = buy_order_response.into_inner();
@@ -94,6 +104,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         volume: None,
         taker_rate: None,
         maker_rate: None,
+        time_in_force: None,
+        post_only: None,
+        display_quantity: None,
+        stop_price: None,
+        trigger_direction: None,
+        protection_price: None,
+        expires_at: None,
+        validate_only: None,
+        client_order_id: None,
+        nonce: None,
     });
     let sell_order_response = client.place_order(sell_order_request).await?;
     let sell_order = sell_order_response.into_inner();
@@ -110,6 +130,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         request_id: 5,
         symbol_id: SYMBOL_ID,
         levels: Some(10),
+        bucket: None,
     });
     let orderbook_response = client.get_order_book(orderbook_request).await?;
     let orderbook = orderbook_response.into_inner();
@@ -0,0 +1,139 @@
+use lightning::models::schema::lightning_client::LightningClient;
+use lightning::models::schema::{
+    GetTradesRequest, IncreaseRequest, PlaceOrderRequest, PlaceOrderResponse, Side, Type,
+};
+use std::time::Duration;
+use tokio::time::sleep;
+use tonic::transport::Channel;
+use tonic::Request;
+
+// 常量定义，使用与 integration_test 不同的账户，避免互相影响
+const BUYER: i32 = 3;
+const SELLER: i32 = 4;
+const BTC_CURRENCY_ID: i32 = 1;  // BTC
+const USDT_CURRENCY_ID: i32 = 2; // USDT
+const SYMBOL_ID: i32 = 1;        // BTC-USDT
+
+async fn increase(
+    client: &mut LightningClient<Channel>,
+    request_id: i64,
+    account_id: i32,
+    currency_id: i32,
+    amount: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .increase(Request::new(IncreaseRequest {
+            request_id,
+            account_id,
+            currency_id,
+            amount: amount.to_string(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(response.code, 0, "Failed to increase balance for account {}", account_id);
+    Ok(())
+}
+
+async fn place_limit(
+    client: &mut LightningClient<Channel>,
+    request_id: i64,
+    account_id: i32,
+    side: Side,
+    price: &str,
+    quantity: &str,
+) -> Result<PlaceOrderResponse, Box<dyn std::error::Error>> {
+    let response = client
+        .place_order(Request::new(PlaceOrderRequest {
+            request_id,
+            symbol_id: SYMBOL_ID,
+            account_id,
+            r#type: Type::Limit as i32,
+            side: side as i32,
+            price: Some(price.to_string()),
+            quantity: Some(quantity.to_string()),
+            volume: None,
+            taker_rate: None,
+            maker_rate: None,
+            time_in_force: None,
+            post_only: None,
+            display_quantity: None,
+            stop_price: None,
+            trigger_direction: None,
+            protection_price: None,
+            expires_at: None,
+            validate_only: None,
+            client_order_id: None,
+            nonce: None,
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(response.code, 0, "Failed to place order: {:?}", response.message);
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("=== Lightning Trade History Test ===");
+
+    // 连接到 gRPC 服务
+    let mut client = LightningClient::connect("http://127.0.0.1:50051").await?;
+    println!("✓ Connected to gRPC server");
+
+    // 1. 准备余额
+    println!("\n--- Step 1: Fund buyer and seller ---");
+    increase(&mut client, 1, BUYER, USDT_CURRENCY_ID, "100000.0").await?;
+    increase(&mut client, 2, SELLER, BTC_CURRENCY_ID, "1.0").await?;
+    println!("✓ Buyer: 100000.0 USDT, Seller: 1.0 BTC");
+
+    sleep(Duration::from_millis(100)).await;
+
+    // 2. 卖方挂单，买方吃单，成交两笔
+    println!("\n--- Step 2: Place matching orders ---");
+    place_limit(&mut client, 3, SELLER, Side::Ask, "60000.0", "0.1").await?;
+    sleep(Duration::from_millis(200)).await;
+    place_limit(&mut client, 4, BUYER, Side::Bid, "60000.0", "0.1").await?;
+    sleep(Duration::from_millis(200)).await;
+    place_limit(&mut client, 5, SELLER, Side::Ask, "60100.0", "0.2").await?;
+    sleep(Duration::from_millis(200)).await;
+    place_limit(&mut client, 6, BUYER, Side::Bid, "60100.0", "0.2").await?;
+    println!("✓ Two trades executed: 0.1 @ 60000, 0.2 @ 60100");
+
+    sleep(Duration::from_millis(500)).await;
+
+    // 3. 查询最近成交
+    println!("\n--- Step 3: Query recent trades ---");
+    let trades_response = client
+        .get_trades(Request::new(GetTradesRequest {
+            request_id: 7,
+            symbol_id: SYMBOL_ID,
+            limit: Some(2),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(trades_response.code, 0, "Failed to get trades");
+    assert_eq!(trades_response.trades.len(), 2, "Expected 2 trades");
+    for trade in &trades_response.trades {
+        println!(
+            "  Trade {}: {} @ {}, taker={:?}, timestamp={}",
+            trade.trade_id,
+            trade.quantity,
+            trade.price,
+            Side::try_from(trade.taker_side).unwrap_or(Side::Bid),
+            trade.timestamp
+        );
+    }
+
+    // 最新的成交排在最前面
+    let latest = &trades_response.trades[0];
+    let previous = &trades_response.trades[1];
+    assert_eq!(latest.price.parse::<f64>()?, 60100.0);
+    assert_eq!(latest.quantity.parse::<f64>()?, 0.2);
+    assert_eq!(previous.price.parse::<f64>()?, 60000.0);
+    assert_eq!(previous.quantity.parse::<f64>()?, 0.1);
+    assert!(latest.timestamp >= previous.timestamp, "Trades should be newest-first");
+    assert_eq!(latest.taker_side, Side::Bid as i32, "Buyer should be the taker");
+    println!("  ✓ Trades returned newest-first");
+
+    println!("\n=== All tests passed! ===");
+    Ok(())
+}
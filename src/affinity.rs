@@ -0,0 +1,88 @@
+use core_affinity::CoreId;
+use tracing::{info, warn};
+
+// 工作线程绑核：按启动顺序轮流分配核心，线程多于核心时从头开始复用；
+// 平台不支持或获取不到核心列表时不绑核
+#[derive(Debug, Clone, Default)]
+pub struct CoreAssigner {
+    cores: Vec<CoreId>,
+    next: usize,
+}
+
+impl CoreAssigner {
+    // 未启用时不分配核心
+    pub fn new(enabled: bool) -> Self {
+        let cores = if enabled {
+            core_affinity::get_core_ids().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        Self::with_cores(cores)
+    }
+
+    pub fn with_cores(cores: Vec<CoreId>) -> Self {
+        Self { cores, next: 0 }
+    }
+
+    pub fn core_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    pub fn next_core(&mut self) -> Option<CoreId> {
+        if self.cores.is_empty() {
+            return None;
+        }
+        let core = self.cores[self.next % self.cores.len()];
+        self.next += 1;
+        Some(core)
+    }
+}
+
+// 把当前线程绑定到指定核心，在工作线程开始处理消息前调用；失败时只记录，线程照常运行
+pub fn pin_current_thread(name: &str, core: Option<CoreId>) -> bool {
+    let Some(core) = core else {
+        return false;
+    };
+    let pinned = core_affinity::set_for_current(core);
+    if pinned {
+        info!(thread = name, core = core.id, "Pinned thread to core");
+    } else {
+        warn!(thread = name, core = core.id, "Failed to pin thread to core, running unpinned");
+    }
+    pinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cores_are_assigned_round_robin() {
+        let mut assigner = CoreAssigner::with_cores(vec![CoreId { id: 0 }, CoreId { id: 1 }]);
+        let assigned: Vec<usize> = (0..5).map(|_| assigner.next_core().unwrap().id).collect();
+        assert_eq!(assigned, vec![0, 1, 0, 1, 0]);
+
+        // 未启用或没有核心列表时不绑核
+        let mut disabled = CoreAssigner::new(false);
+        assert_eq!(disabled.next_core(), None);
+        assert!(!pin_current_thread("test", None));
+    }
+
+    // 只在能获取到多个核心的平台上检查实际绑核
+    #[test]
+    fn test_pinning_succeeds_on_multi_core_host() {
+        let mut assigner = CoreAssigner::new(true);
+        if assigner.core_count() < 2 {
+            return;
+        }
+        let cores: Vec<_> = (0..2).map(|_| assigner.next_core()).collect();
+        for (index, core) in cores.into_iter().enumerate() {
+            let pinned = std::thread::spawn(move || {
+                pin_current_thread(&format!("test-worker-{}", index), core)
+            })
+            .join()
+            .unwrap();
+            assert!(pinned);
+        }
+    }
+}
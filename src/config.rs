@@ -0,0 +1,530 @@
+use crate::grpc::{ServerLimits, MAX_FRAME_SIZE_RANGE};
+use crate::matching::{
+    CircuitBreaker, MatchMode, RoundingPolicy, SelfTradePrevention, TradeRetention,
+    DEFAULT_TRADE_RETENTION,
+};
+use rust_decimal::Decimal;
+use crate::risk::{PlacementMode, RiskLimits};
+
+// 默认分片数：SequencerProcessor 和 MatchProcessor 各启动这么多个
+pub const DEFAULT_SHARD_COUNT: usize = 10;
+
+// 默认每个工作线程驱动的分片数，1 表示每个分片独占一个线程
+pub const DEFAULT_SHARDS_PER_WORKER: usize = 1;
+
+// 默认每个处理器队列的容量，队列满时 gRPC 请求返回 resource_exhausted
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 10_000;
+
+// 默认 MatchProcessor 每次连续处理的消息数，1 表示逐条处理并逐条发布深度
+pub const DEFAULT_MATCH_BATCH_SIZE: usize = 1;
+
+// 默认预写日志目录
+pub const DEFAULT_WAL_DIR: &str = "data/wal";
+
+// 默认 Prometheus 指标监听地址，与 gRPC 端口分开
+pub const DEFAULT_METRICS_ADDR: &str = "0.0.0.0:9100";
+
+// WebSocket 行情网关监听地址的环境变量，未设置时不启动网关
+pub const WS_ADDR_ENV: &str = "LIGHTNING_WS_ADDR";
+
+// REST 网关监听地址的环境变量，未设置时不启动网关
+pub const REST_ADDR_ENV: &str = "LIGHTNING_REST_ADDR";
+
+// 熔断触发后默认暂停的秒数
+pub const DEFAULT_CIRCUIT_BREAKER_HALT_SECS: u64 = 300;
+
+// 启动时加载币种和交易对的配置文件的环境变量，未设置时从空的管理器开始，通过管理接口创建
+pub const MARKETS_FILE_ENV: &str = "LIGHTNING_MARKETS_FILE";
+
+// 服务启动配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // 账户按 account_id % shard_count 分片，交易对按 symbol_id % shard_count 分片
+    // 分片数变化后已有的预写日志无法按原分片重放，需要使用新的日志目录
+    pub shard_count: usize,
+    // SequencerProcessor 和 MatchProcessor 分别按相邻分片分组，每组共用一个线程，
+    // 分片数多于核数时可避免线程过多
+    pub shards_per_worker: usize,
+    // 工作线程按启动顺序轮流绑定到 CPU 核心，平台不支持时忽略
+    pub pin_cores: bool,
+    // SequencerProcessor、MatchProcessor、成交回调队列的容量
+    pub channel_capacity: usize,
+    pub wal_dir: String,
+    pub metrics_addr: String,
+    pub ws_addr: Option<String>,
+    pub rest_addr: Option<String>,
+    pub markets_file: Option<String>,
+    // 每个账户每秒允许的下单/撤单/改单次数和可积累的突发次数，未设置速率时不限流，
+    // 未设置突发次数时等于速率
+    pub order_rate: Option<u32>,
+    pub order_burst: Option<u32>,
+    // 每个账户的未完成订单数和名义价值上限，未设置时不限制
+    pub risk_limits: RiskLimits,
+    // 每个撮合分片保留的最近成交笔数和时长，未设置时长时只按笔数淘汰
+    pub trade_retention: TradeRetention,
+    // 有成交的下单响应等 maker 和 taker 的结算都完成后再回复，收到响应时余额已更新
+    pub confirm_settlement: bool,
+    // 成交金额和手续费除不尽时的舍入方向，零头计入手续费账户
+    pub rounding_policy: RoundingPolicy,
+    // 同一账户的买卖单相遇时的处理方式，默认照常成交
+    pub self_trade_prevention: SelfTradePrevention,
+    // 同一价格级别内 taker 数量的分配方式，默认价格-时间优先
+    pub match_mode: MatchMode,
+    // 成交价偏离上一笔订单的成交价超过设定百分比时暂停交易对，未设置百分比时不熔断
+    pub circuit_breaker: Option<CircuitBreaker>,
+    // 账户所在分片的请求队列积压达到该长度时，余额查询转给空闲的 Sequencer 工作线程，未设置时不转移
+    pub read_overflow_threshold: Option<usize>,
+    // MatchProcessor 每次最多连续处理的消息数，批内的深度更新按交易对合并为一次
+    pub match_batch_size: usize,
+    // 下单时预冻结每笔订单的余额，或只检查未完成订单的总占用（保证金模式）
+    pub placement_mode: PlacementMode,
+    // maker 负费率（返佣）的上限，单位百万分之一，未设置时不支持返佣
+    pub max_maker_rebate: Option<u32>,
+    // gRPC 每个连接的并发流数、帧大小、消息大小和请求超时，未设置的项取默认值
+    pub server_limits: ServerLimits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shard_count: DEFAULT_SHARD_COUNT,
+            shards_per_worker: DEFAULT_SHARDS_PER_WORKER,
+            pin_cores: false,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            wal_dir: DEFAULT_WAL_DIR.to_string(),
+            metrics_addr: DEFAULT_METRICS_ADDR.to_string(),
+            ws_addr: None,
+            rest_addr: None,
+            markets_file: None,
+            order_rate: None,
+            order_burst: None,
+            risk_limits: RiskLimits::default(),
+            trade_retention: TradeRetention::default(),
+            confirm_settlement: false,
+            rounding_policy: RoundingPolicy::default(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            match_mode: MatchMode::default(),
+            circuit_breaker: None,
+            read_overflow_threshold: None,
+            match_batch_size: DEFAULT_MATCH_BATCH_SIZE,
+            placement_mode: PlacementMode::default(),
+            max_maker_rebate: None,
+            server_limits: ServerLimits::default(),
+        }
+    }
+}
+
+impl Config {
+    // 从环境变量读取：LIGHTNING_SHARD_COUNT、LIGHTNING_SHARDS_PER_WORKER、LIGHTNING_PIN_CORES、
+    // LIGHTNING_CHANNEL_CAPACITY、LIGHTNING_WAL_DIR、LIGHTNING_METRICS_ADDR、LIGHTNING_WS_ADDR、
+    // LIGHTNING_REST_ADDR、LIGHTNING_MAX_OPEN_ORDERS、LIGHTNING_MAX_OPEN_NOTIONAL、
+    // LIGHTNING_MARKETS_FILE、LIGHTNING_ORDER_RATE、LIGHTNING_ORDER_BURST、
+    // LIGHTNING_TRADE_RETENTION、LIGHTNING_TRADE_RETENTION_SECS、LIGHTNING_CONFIRM_SETTLEMENT、
+    // LIGHTNING_ROUNDING_POLICY、LIGHTNING_SELF_TRADE_PREVENTION、LIGHTNING_MATCH_MODE、
+    // LIGHTNING_CIRCUIT_BREAKER_PERCENT、LIGHTNING_CIRCUIT_BREAKER_HALT_SECS、
+    // LIGHTNING_READ_OVERFLOW_THRESHOLD、LIGHTNING_MATCH_BATCH_SIZE、LIGHTNING_PLACEMENT_MODE、
+    // LIGHTNING_MAX_MAKER_REBATE、LIGHTNING_GRPC_MAX_CONCURRENT_STREAMS、LIGHTNING_GRPC_MAX_FRAME_SIZE、
+    // LIGHTNING_GRPC_MAX_MESSAGE_SIZE、LIGHTNING_GRPC_REQUEST_TIMEOUT_SECS
+    pub fn from_env() -> Result<Self, String> {
+        let shard_count = parse_positive(
+            "LIGHTNING_SHARD_COUNT",
+            std::env::var("LIGHTNING_SHARD_COUNT").ok().as_deref(),
+            DEFAULT_SHARD_COUNT,
+        )?;
+        let shards_per_worker = parse_positive(
+            "LIGHTNING_SHARDS_PER_WORKER",
+            std::env::var("LIGHTNING_SHARDS_PER_WORKER").ok().as_deref(),
+            DEFAULT_SHARDS_PER_WORKER,
+        )?;
+        let pin_cores = parse_flag(
+            "LIGHTNING_PIN_CORES",
+            std::env::var("LIGHTNING_PIN_CORES").ok().as_deref(),
+        )?;
+        let channel_capacity = parse_positive(
+            "LIGHTNING_CHANNEL_CAPACITY",
+            std::env::var("LIGHTNING_CHANNEL_CAPACITY").ok().as_deref(),
+            DEFAULT_CHANNEL_CAPACITY,
+        )?;
+        let wal_dir = std::env::var("LIGHTNING_WAL_DIR").unwrap_or_else(|_| DEFAULT_WAL_DIR.to_string());
+        let metrics_addr = std::env::var("LIGHTNING_METRICS_ADDR")
+            .unwrap_or_else(|_| DEFAULT_METRICS_ADDR.to_string());
+        let ws_addr = std::env::var(WS_ADDR_ENV)
+            .ok()
+            .filter(|addr| !addr.trim().is_empty());
+        let rest_addr = std::env::var(REST_ADDR_ENV)
+            .ok()
+            .filter(|addr| !addr.trim().is_empty());
+        let markets_file = std::env::var(MARKETS_FILE_ENV)
+            .ok()
+            .filter(|path| !path.trim().is_empty());
+        let order_rate = parse_limit(
+            "LIGHTNING_ORDER_RATE",
+            std::env::var("LIGHTNING_ORDER_RATE").ok().as_deref(),
+        )?;
+        let order_burst = parse_limit(
+            "LIGHTNING_ORDER_BURST",
+            std::env::var("LIGHTNING_ORDER_BURST").ok().as_deref(),
+        )?;
+        let risk_limits = RiskLimits {
+            max_open_orders: parse_limit(
+                "LIGHTNING_MAX_OPEN_ORDERS",
+                std::env::var("LIGHTNING_MAX_OPEN_ORDERS").ok().as_deref(),
+            )?,
+            max_open_notional: parse_limit(
+                "LIGHTNING_MAX_OPEN_NOTIONAL",
+                std::env::var("LIGHTNING_MAX_OPEN_NOTIONAL").ok().as_deref(),
+            )?,
+        };
+        let trade_retention = TradeRetention {
+            max_trades: parse_positive(
+                "LIGHTNING_TRADE_RETENTION",
+                std::env::var("LIGHTNING_TRADE_RETENTION").ok().as_deref(),
+                DEFAULT_TRADE_RETENTION,
+            )?,
+            max_age_ms: parse_limit::<u64>(
+                "LIGHTNING_TRADE_RETENTION_SECS",
+                std::env::var("LIGHTNING_TRADE_RETENTION_SECS").ok().as_deref(),
+            )?
+            .map(|secs| secs.saturating_mul(1000)),
+        };
+        let confirm_settlement = parse_flag(
+            "LIGHTNING_CONFIRM_SETTLEMENT",
+            std::env::var("LIGHTNING_CONFIRM_SETTLEMENT").ok().as_deref(),
+        )?;
+        let rounding_policy = parse_rounding_policy(
+            std::env::var("LIGHTNING_ROUNDING_POLICY").ok().as_deref(),
+        )?;
+        let self_trade_prevention = parse_self_trade_prevention(
+            std::env::var("LIGHTNING_SELF_TRADE_PREVENTION").ok().as_deref(),
+        )?;
+        let match_mode = parse_match_mode(std::env::var("LIGHTNING_MATCH_MODE").ok().as_deref())?;
+        let circuit_breaker = parse_circuit_breaker(
+            std::env::var("LIGHTNING_CIRCUIT_BREAKER_PERCENT").ok().as_deref(),
+            std::env::var("LIGHTNING_CIRCUIT_BREAKER_HALT_SECS").ok().as_deref(),
+        )?;
+        let read_overflow_threshold = parse_limit(
+            "LIGHTNING_READ_OVERFLOW_THRESHOLD",
+            std::env::var("LIGHTNING_READ_OVERFLOW_THRESHOLD").ok().as_deref(),
+        )?;
+        let match_batch_size = parse_positive(
+            "LIGHTNING_MATCH_BATCH_SIZE",
+            std::env::var("LIGHTNING_MATCH_BATCH_SIZE").ok().as_deref(),
+            DEFAULT_MATCH_BATCH_SIZE,
+        )?;
+        let placement_mode = parse_placement_mode(
+            std::env::var("LIGHTNING_PLACEMENT_MODE").ok().as_deref(),
+        )?;
+        let max_maker_rebate = parse_limit(
+            "LIGHTNING_MAX_MAKER_REBATE",
+            std::env::var("LIGHTNING_MAX_MAKER_REBATE").ok().as_deref(),
+        )?;
+        let server_limits = parse_server_limits(
+            std::env::var("LIGHTNING_GRPC_MAX_CONCURRENT_STREAMS").ok().as_deref(),
+            std::env::var("LIGHTNING_GRPC_MAX_FRAME_SIZE").ok().as_deref(),
+            std::env::var("LIGHTNING_GRPC_MAX_MESSAGE_SIZE").ok().as_deref(),
+            std::env::var("LIGHTNING_GRPC_REQUEST_TIMEOUT_SECS").ok().as_deref(),
+        )?;
+        Ok(Self {
+            shard_count,
+            shards_per_worker,
+            pin_cores,
+            channel_capacity,
+            wal_dir,
+            metrics_addr,
+            ws_addr,
+            rest_addr,
+            markets_file,
+            order_rate,
+            order_burst,
+            risk_limits,
+            trade_retention,
+            confirm_settlement,
+            rounding_policy,
+            self_trade_prevention,
+            match_mode,
+            circuit_breaker,
+            read_overflow_threshold,
+            match_batch_size,
+            placement_mode,
+            max_maker_rebate,
+            server_limits,
+        })
+    }
+}
+
+fn parse_positive(name: &str, value: Option<&str>, default: usize) -> Result<usize, String> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+    match value.trim().parse::<usize>() {
+        Ok(0) => Err(format!("{} must be greater than 0", name)),
+        Ok(parsed) => Ok(parsed),
+        Err(e) => Err(format!("Invalid {} '{}': {}", name, value, e)),
+    }
+}
+
+// 可选的上限：未设置或为空时不限制，设置时必须大于 0
+fn parse_limit<T>(name: &str, value: Option<&str>) -> Result<Option<T>, String>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return Ok(None);
+    };
+    match value.trim().parse::<T>() {
+        Ok(parsed) if parsed <= T::default() => Err(format!("{} must be greater than 0", name)),
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(e) => Err(format!("Invalid {} '{}': {}", name, value, e)),
+    }
+}
+
+// 开关类配置：未设置时关闭，接受 1/0、true/false
+fn parse_flag(name: &str, value: Option<&str>) -> Result<bool, String> {
+    let Some(value) = value else {
+        return Ok(false);
+    };
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" | "" => Ok(false),
+        _ => Err(format!("Invalid {} '{}': expected true or false", name, value)),
+    }
+}
+
+// 舍入策略：未设置时四舍六入五成双
+fn parse_rounding_policy(value: Option<&str>) -> Result<RoundingPolicy, String> {
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return Ok(RoundingPolicy::default());
+    };
+    RoundingPolicy::parse(value).ok_or_else(|| {
+        format!(
+            "Invalid LIGHTNING_ROUNDING_POLICY '{}': expected half-even or floor-to-exchange",
+            value
+        )
+    })
+}
+
+// 自成交保护：未设置时不保护，同一账户的订单照常成交
+fn parse_self_trade_prevention(value: Option<&str>) -> Result<SelfTradePrevention, String> {
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return Ok(SelfTradePrevention::default());
+    };
+    SelfTradePrevention::parse(value).ok_or_else(|| {
+        format!(
+            "Invalid LIGHTNING_SELF_TRADE_PREVENTION '{}': expected none, cancel-taker, cancel-maker or cancel-both",
+            value
+        )
+    })
+}
+
+// 撮合方式：未设置时价格-时间优先
+fn parse_match_mode(value: Option<&str>) -> Result<MatchMode, String> {
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return Ok(MatchMode::default());
+    };
+    MatchMode::parse(value).ok_or_else(|| {
+        format!("Invalid LIGHTNING_MATCH_MODE '{}': expected fifo or pro-rata", value)
+    })
+}
+
+// 下单占用方式：未设置时每笔订单预冻结
+fn parse_placement_mode(value: Option<&str>) -> Result<PlacementMode, String> {
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return Ok(PlacementMode::default());
+    };
+    PlacementMode::parse(value).ok_or_else(|| {
+        format!(
+            "Invalid LIGHTNING_PLACEMENT_MODE '{}': expected prefreeze or margin",
+            value
+        )
+    })
+}
+
+// 熔断：百分比未设置时关闭，暂停秒数未设置时取默认值
+fn parse_circuit_breaker(
+    percent: Option<&str>,
+    halt_secs: Option<&str>,
+) -> Result<Option<CircuitBreaker>, String> {
+    let Some(percent) = parse_limit::<Decimal>("LIGHTNING_CIRCUIT_BREAKER_PERCENT", percent)? else {
+        return Ok(None);
+    };
+    let halt_secs = parse_limit::<u64>("LIGHTNING_CIRCUIT_BREAKER_HALT_SECS", halt_secs)?
+        .unwrap_or(DEFAULT_CIRCUIT_BREAKER_HALT_SECS);
+    Ok(Some(CircuitBreaker {
+        band: percent / Decimal::ONE_HUNDRED,
+        halt_ms: halt_secs.saturating_mul(1000),
+    }))
+}
+
+// gRPC 服务端限制：未设置的项取默认值，帧大小必须在 HTTP/2 允许的范围内
+fn parse_server_limits(
+    max_concurrent_streams: Option<&str>,
+    max_frame_size: Option<&str>,
+    max_message_size: Option<&str>,
+    request_timeout_secs: Option<&str>,
+) -> Result<ServerLimits, String> {
+    let defaults = ServerLimits::default();
+    let max_frame_size = parse_limit::<u32>("LIGHTNING_GRPC_MAX_FRAME_SIZE", max_frame_size)?
+        .unwrap_or(defaults.max_frame_size);
+    if !MAX_FRAME_SIZE_RANGE.contains(&max_frame_size) {
+        return Err(format!(
+            "LIGHTNING_GRPC_MAX_FRAME_SIZE must be between {} and {}",
+            MAX_FRAME_SIZE_RANGE.start(),
+            MAX_FRAME_SIZE_RANGE.end()
+        ));
+    }
+    Ok(ServerLimits {
+        max_concurrent_streams: parse_limit(
+            "LIGHTNING_GRPC_MAX_CONCURRENT_STREAMS",
+            max_concurrent_streams,
+        )?
+        .unwrap_or(defaults.max_concurrent_streams),
+        max_frame_size,
+        max_message_size: parse_limit("LIGHTNING_GRPC_MAX_MESSAGE_SIZE", max_message_size)?
+            .unwrap_or(defaults.max_message_size),
+        request_timeout: parse_limit::<u64>(
+            "LIGHTNING_GRPC_REQUEST_TIMEOUT_SECS",
+            request_timeout_secs,
+        )?
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(defaults.request_timeout),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shard_count() {
+        let parse = |value| parse_positive("LIGHTNING_SHARD_COUNT", value, DEFAULT_SHARD_COUNT);
+        assert_eq!(parse(None), Ok(DEFAULT_SHARD_COUNT));
+        assert_eq!(parse(Some("4")), Ok(4));
+        assert_eq!(parse(Some(" 16 ")), Ok(16));
+        assert!(parse(Some("0")).is_err());
+        assert!(parse(Some("-1")).is_err());
+        assert!(parse(Some("ten")).is_err());
+    }
+
+    #[test]
+    fn test_parse_channel_capacity() {
+        let parse =
+            |value| parse_positive("LIGHTNING_CHANNEL_CAPACITY", value, DEFAULT_CHANNEL_CAPACITY);
+        assert_eq!(parse(None), Ok(DEFAULT_CHANNEL_CAPACITY));
+        assert_eq!(parse(Some("128")), Ok(128));
+        assert_eq!(
+            parse(Some("0")),
+            Err("LIGHTNING_CHANNEL_CAPACITY must be greater than 0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_pin_cores() {
+        let parse = |value| parse_flag("LIGHTNING_PIN_CORES", value);
+        assert_eq!(parse(None), Ok(false));
+        assert_eq!(parse(Some("1")), Ok(true));
+        assert_eq!(parse(Some(" TRUE ")), Ok(true));
+        assert_eq!(parse(Some("false")), Ok(false));
+        assert!(parse(Some("yes")).is_err());
+    }
+
+    #[test]
+    fn test_parse_risk_limits() {
+        assert_eq!(parse_limit::<usize>("LIGHTNING_MAX_OPEN_ORDERS", None), Ok(None));
+        assert_eq!(parse_limit::<usize>("LIGHTNING_MAX_OPEN_ORDERS", Some("")), Ok(None));
+        assert_eq!(parse_limit("LIGHTNING_MAX_OPEN_ORDERS", Some("50")), Ok(Some(50usize)));
+        assert!(parse_limit::<usize>("LIGHTNING_MAX_OPEN_ORDERS", Some("0")).is_err());
+        assert_eq!(
+            parse_limit("LIGHTNING_MAX_OPEN_NOTIONAL", Some("2500.5")),
+            Ok(Some(Decimal::new(25005, 1)))
+        );
+        assert!(parse_limit::<Decimal>("LIGHTNING_MAX_OPEN_NOTIONAL", Some("-1")).is_err());
+        assert!(parse_limit::<Decimal>("LIGHTNING_MAX_OPEN_NOTIONAL", Some("lots")).is_err());
+    }
+
+    #[test]
+    fn test_parse_rounding_policy() {
+        assert_eq!(parse_rounding_policy(None), Ok(RoundingPolicy::HalfEven));
+        assert_eq!(parse_rounding_policy(Some("")), Ok(RoundingPolicy::HalfEven));
+        assert_eq!(
+            parse_rounding_policy(Some(" Floor-To-Exchange ")),
+            Ok(RoundingPolicy::FloorToExchange)
+        );
+        assert!(parse_rounding_policy(Some("ceil")).is_err());
+    }
+
+    #[test]
+    fn test_parse_self_trade_prevention() {
+        assert_eq!(parse_self_trade_prevention(None), Ok(SelfTradePrevention::None));
+        assert_eq!(parse_self_trade_prevention(Some(" ")), Ok(SelfTradePrevention::None));
+        assert_eq!(
+            parse_self_trade_prevention(Some("Cancel-Taker")),
+            Ok(SelfTradePrevention::CancelTaker)
+        );
+        assert_eq!(
+            parse_self_trade_prevention(Some(" cancel-maker ")),
+            Ok(SelfTradePrevention::CancelMaker)
+        );
+        assert_eq!(
+            parse_self_trade_prevention(Some("cancel-both")),
+            Ok(SelfTradePrevention::CancelBoth)
+        );
+        assert!(parse_self_trade_prevention(Some("cancel")).is_err());
+    }
+
+    #[test]
+    fn test_parse_match_mode() {
+        assert_eq!(parse_match_mode(None), Ok(MatchMode::Fifo));
+        assert_eq!(parse_match_mode(Some("")), Ok(MatchMode::Fifo));
+        assert_eq!(parse_match_mode(Some("FIFO")), Ok(MatchMode::Fifo));
+        assert_eq!(parse_match_mode(Some(" pro-rata ")), Ok(MatchMode::ProRata));
+        assert!(parse_match_mode(Some("prorata")).is_err());
+    }
+
+    #[test]
+    fn test_parse_placement_mode() {
+        assert_eq!(parse_placement_mode(None), Ok(PlacementMode::PrefreezePerOrder));
+        assert_eq!(parse_placement_mode(Some(" ")), Ok(PlacementMode::PrefreezePerOrder));
+        assert_eq!(parse_placement_mode(Some("Margin")), Ok(PlacementMode::MarginCheck));
+        assert!(parse_placement_mode(Some("cross")).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_limits() {
+        assert_eq!(parse_server_limits(None, None, None, Some("")), Ok(ServerLimits::default()));
+        assert_eq!(
+            parse_server_limits(Some("64"), Some("65536"), Some("1048576"), Some("5")),
+            Ok(ServerLimits {
+                max_concurrent_streams: 64,
+                max_frame_size: 65_536,
+                max_message_size: 1_048_576,
+                request_timeout: std::time::Duration::from_secs(5),
+            })
+        );
+        assert!(parse_server_limits(Some("0"), None, None, None).is_err());
+        assert!(parse_server_limits(None, Some("1024"), None, None).is_err());
+        assert!(parse_server_limits(None, Some("16777216"), None, None).is_err());
+        assert!(parse_server_limits(None, None, None, Some("0")).is_err());
+    }
+
+    #[test]
+    fn test_parse_circuit_breaker() {
+        assert_eq!(parse_circuit_breaker(None, Some("60")), Ok(None));
+        assert_eq!(
+            parse_circuit_breaker(Some("10"), None),
+            Ok(Some(CircuitBreaker {
+                band: Decimal::new(1, 1),
+                halt_ms: DEFAULT_CIRCUIT_BREAKER_HALT_SECS * 1000,
+            }))
+        );
+        assert_eq!(
+            parse_circuit_breaker(Some("2.5"), Some("60")),
+            Ok(Some(CircuitBreaker {
+                band: Decimal::new(25, 3),
+                halt_ms: 60_000,
+            }))
+        );
+        assert!(parse_circuit_breaker(Some("0"), None).is_err());
+        assert!(parse_circuit_breaker(Some("10"), Some("0")).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::matching::Order;
+use crate::messages::TradeExecutionMessage;
+use crate::models::schema;
+use crate::wal;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// 无法投递的余额消息：目标分片的成交回调队列已关闭时写入死信日志，重启后重新投递
+// 消息中的响应通道无法持久化，重新投递时不再回复调用方
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DeadLetter {
+    SettleAccount {
+        shard: usize,
+        account_id: i32,
+        symbol_id: i32,
+        #[serde(default)]
+        trade_id: u64, // 早期写入的死信没有成交ID，重新投递时不去重
+        #[serde(default)]
+        taker: bool,
+        deduct_currency_id: i32,
+        deduct_amount: Decimal,
+        add_currency_id: i32,
+        add_amount: Decimal,
+        fee_currency_id: i32,
+        fee_amount: Decimal,
+        #[serde(default)]
+        fee_reserve: Decimal,
+    },
+    CollectFee {
+        shard: usize,
+        currency_id: i32,
+        amount: Decimal,
+    },
+    UnfreezeOrder {
+        shard: usize,
+        order: Order,
+        #[serde(default)]
+        note: Option<String>,
+    },
+    OrderAmended {
+        shard: usize,
+        account_id: i32,
+        symbol_id: i32,
+        side: i32,
+        prefrozen_amount: Decimal,
+        orders: Option<Box<(Order, Order)>>,
+        response: schema::AmendOrderResponse,
+    },
+}
+
+impl DeadLetter {
+    // 只记录影响余额的消息；跨分片划转发送失败时已退回转出账户，停机消息和风控计数无需记录，
+    // 改单冻结请求还没有改动余额，丢弃后改单请求得不到回复
+    pub fn from_message(shard: usize, message: &TradeExecutionMessage) -> Option<Self> {
+        let letter = match message {
+            TradeExecutionMessage::SettleAccount {
+                account_id,
+                symbol_id,
+                trade_id,
+                taker,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+                fee_currency_id,
+                fee_amount,
+                fee_reserve,
+                span: _,
+                ack: _,
+            } => DeadLetter::SettleAccount {
+                shard,
+                account_id: *account_id,
+                symbol_id: *symbol_id,
+                trade_id: *trade_id,
+                taker: *taker,
+                deduct_currency_id: *deduct_currency_id,
+                deduct_amount: *deduct_amount,
+                add_currency_id: *add_currency_id,
+                add_amount: *add_amount,
+                fee_currency_id: *fee_currency_id,
+                fee_amount: *fee_amount,
+                fee_reserve: *fee_reserve,
+            },
+            TradeExecutionMessage::CollectFee {
+                currency_id,
+                amount,
+            } => DeadLetter::CollectFee {
+                shard,
+                currency_id: *currency_id,
+                amount: *amount,
+            },
+            TradeExecutionMessage::UnfreezeOrder { order, note, .. } => {
+                DeadLetter::UnfreezeOrder {
+                    shard,
+                    order: order.clone(),
+                    note: note.clone(),
+                }
+            }
+            TradeExecutionMessage::OrderAmended {
+                account_id,
+                symbol_id,
+                side,
+                prefrozen_amount,
+                orders,
+                response,
+                ..
+            } => DeadLetter::OrderAmended {
+                shard,
+                account_id: *account_id,
+                symbol_id: *symbol_id,
+                side: *side,
+                prefrozen_amount: *prefrozen_amount,
+                orders: orders.clone(),
+                response: response.clone(),
+            },
+            TradeExecutionMessage::TransferIn { .. }
+            | TradeExecutionMessage::AmendNeedsFreeze { .. }
+            | TradeExecutionMessage::OrderProgress { .. }
+            | TradeExecutionMessage::Drain => {
+                return None;
+            }
+        };
+        Some(letter)
+    }
+
+    // 目标分片
+    pub fn shard(&self) -> usize {
+        match self {
+            DeadLetter::SettleAccount { shard, .. }
+            | DeadLetter::CollectFee { shard, .. }
+            | DeadLetter::UnfreezeOrder { shard, .. }
+            | DeadLetter::OrderAmended { shard, .. } => *shard,
+        }
+    }
+
+    // 还原为成交回调消息，交给目标分片重新处理
+    pub fn into_message(self) -> TradeExecutionMessage {
+        match self {
+            DeadLetter::SettleAccount {
+                account_id,
+                symbol_id,
+                trade_id,
+                taker,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+                fee_currency_id,
+                fee_amount,
+                fee_reserve,
+                ..
+            } => TradeExecutionMessage::SettleAccount {
+                account_id,
+                symbol_id,
+                trade_id,
+                taker,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+                fee_currency_id,
+                fee_amount,
+                fee_reserve,
+                span: tracing::Span::none(),
+                ack: None,
+            },
+            DeadLetter::CollectFee {
+                currency_id,
+                amount,
+                ..
+            } => TradeExecutionMessage::CollectFee {
+                currency_id,
+                amount,
+            },
+            DeadLetter::UnfreezeOrder { order, note, .. } => TradeExecutionMessage::UnfreezeOrder {
+                order,
+                response_sender: None,
+                note,
+            },
+            DeadLetter::OrderAmended {
+                account_id,
+                symbol_id,
+                side,
+                prefrozen_amount,
+                orders,
+                response,
+                ..
+            } => TradeExecutionMessage::OrderAmended {
+                account_id,
+                symbol_id,
+                side,
+                prefrozen_amount,
+                orders,
+                response,
+                response_sender: oneshot::channel().0,
+            },
+        }
+    }
+}
+
+// 仅追加的死信日志，与预写日志使用相同的记录格式
+#[derive(Debug)]
+pub struct DeadLetterLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl DeadLetterLog {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        read_dead_letters(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, letter: &DeadLetter) -> io::Result<()> {
+        wal::append_frame(&mut self.file, letter).map(|_| ())
+    }
+}
+
+// 多个处理器线程共用一个死信日志
+#[derive(Debug, Clone)]
+pub struct DeadLetterSink(Arc<Mutex<DeadLetterLog>>);
+
+impl DeadLetterSink {
+    pub fn new(log: DeadLetterLog) -> Self {
+        Self(Arc::new(Mutex::new(log)))
+    }
+
+    // 记录发送失败的消息；写入失败时只能打印，消息随之丢失
+    pub fn record(&self, shard: usize, message: &TradeExecutionMessage) {
+        if let Some(letter) = DeadLetter::from_message(shard, message) {
+            self.record_letter(letter);
+        }
+    }
+
+    // 记录已经转换好的死信，如分片的预写日志写入失败、没有生效的成交回调
+    pub fn record_letter(&self, letter: DeadLetter) {
+        let shard = letter.shard();
+        let mut log = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match log.append(&letter) {
+            Ok(()) => println!(
+                "Dead letter for sequencer {} written to {}: {:?}",
+                shard,
+                log.path().display(),
+                letter
+            ),
+            Err(e) => println!(
+                "Failed to write dead letter to {}: {} - {:?}",
+                log.path().display(),
+                e,
+                letter
+            ),
+        }
+    }
+}
+
+pub fn dead_letter_path(dir: impl AsRef<Path>) -> PathBuf {
+    dir.as_ref().join("dead-letter.wal")
+}
+
+// 读取全部死信；末尾不完整的记录按预写日志的规则截断
+pub fn read_dead_letters(path: impl AsRef<Path>) -> io::Result<Vec<DeadLetter>> {
+    wal::read_frames_from(path, 0)
+}
+
+// 启动时重新投递完成后调用：原文件改名归档，没有投递成功的死信（目标分片不存在、
+// 目标分片预写日志写入失败）写回新文件，下次启动再处理；先写好新文件再归档，中途失败不丢死信
+pub fn archive_dead_letters(path: impl AsRef<Path>, remaining: &[DeadLetter]) -> io::Result<()> {
+    let path = path.as_ref();
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > 0 => {}
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    let pending = path.with_extension("wal.pending");
+    let mut file = File::create(&pending)?;
+    for letter in remaining {
+        wal::append_frame(&mut file, letter)?;
+    }
+    file.sync_all()?;
+    let archived = path.with_extension(format!("{}.redelivered", crate::matching::now_millis()));
+    fs::rename(path, &archived)?;
+    fs::rename(&pending, path)?;
+    println!(
+        "Dead letters archived to {}, {} kept for the next start",
+        archived.display(),
+        remaining.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters_round_trip_and_are_archived_once() {
+        let dir = std::env::temp_dir().join(format!("lightning-test-{}", uuid::Uuid::new_v4()));
+        let path = dead_letter_path(&dir);
+        let sink = DeadLetterSink::new(DeadLetterLog::open(&path).unwrap());
+
+        sink.record(
+            1,
+            &TradeExecutionMessage::CollectFee {
+                currency_id: 2,
+                amount: Decimal::new(15, 1),
+            },
+        );
+        // 停机消息不记录
+        sink.record(1, &TradeExecutionMessage::Drain);
+
+        let letters = read_dead_letters(&path).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].shard(), 1);
+        assert!(matches!(
+            letters[0].clone().into_message(),
+            TradeExecutionMessage::CollectFee { currency_id: 2, amount }
+                if amount == Decimal::new(15, 1)
+        ));
+        // 投递完成前死信留在原文件中
+        assert_eq!(read_dead_letters(&path).unwrap().len(), 1);
+        archive_dead_letters(&path, &[]).unwrap();
+        assert!(read_dead_letters(&path).unwrap().is_empty());
+        // 空文件不再归档
+        archive_dead_letters(&path, &[]).unwrap();
+        let archived = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry.as_ref().unwrap().path().to_string_lossy().ends_with(".redelivered")
+            })
+            .count();
+        assert_eq!(archived, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_undelivered_dead_letters_are_kept_for_the_next_start() {
+        let dir = std::env::temp_dir().join(format!("lightning-test-{}", uuid::Uuid::new_v4()));
+        let path = dead_letter_path(&dir);
+        let sink = DeadLetterSink::new(DeadLetterLog::open(&path).unwrap());
+        for shard in [0, 7] {
+            sink.record(
+                shard,
+                &TradeExecutionMessage::CollectFee {
+                    currency_id: 2,
+                    amount: Decimal::ONE,
+                },
+            );
+        }
+        drop(sink);
+
+        // 分片 7 不存在，死信写回新文件
+        let letters = read_dead_letters(&path).unwrap();
+        let remaining: Vec<_> = letters.into_iter().filter(|letter| letter.shard() == 7).collect();
+        archive_dead_letters(&path, &remaining).unwrap();
+        let kept = read_dead_letters(&path).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].shard(), 7);
+
+        // 新文件可以继续追加
+        let sink = DeadLetterSink::new(DeadLetterLog::open(&path).unwrap());
+        sink.record(0, &TradeExecutionMessage::CollectFee { currency_id: 2, amount: Decimal::ONE });
+        assert_eq!(read_dead_letters(&path).unwrap().len(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,30 +1,201 @@
-use crate::models::{schema, ManagementManager};
-use crossbeam_channel::Sender;
+use crate::health::ProcessorHealth;
+use crate::idempotency::idempotency_key;
+use crate::market_data::{
+    bbo_from_depth, DepthCache, OrderBookPublisher, TradePublisher, ORDER_BOOK_STREAM_LEVELS,
+};
+use crate::matching::{MatchingEngine, Order, TradingRules, ALL_ACCOUNTS};
+use crate::models::{
+    schema, Currency, ManagementManager, Symbol, FEE_ACCOUNT_ID, MAX_CURRENCY_SCALE,
+};
+use crate::overflow::{OverflowRead, ReadOverflow};
+use crate::processor::match_shard;
+use crate::rate_limit::OrderRateLimiter;
+use crate::telemetry::place_order_span;
+use crate::valuation::{value_account, LastTradePrices, PriceMap};
+use crossbeam_channel::{Sender, TrySendError};
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::oneshot;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::transport::Server;
 use tonic::{Request, Response, Status};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::messages::{MatchMessage, SequencerMessage};
 use schema::lightning_server::{Lightning, LightningServer};
 use schema::management_server::{Management, ManagementServer};
 use schema::{
+    AdjustReserveRequest, AdjustReserveResponse,
+    AdminAdjustBalanceRequest, AdminAdjustBalanceResponse, AdminForceCancelRequest,
+    AmendOrderRequest, AmendOrderResponse, BatchIncreaseRequest, BatchIncreaseResponse,
+    BboResponse, CancelAllOrdersRequest, CancelAllOrdersResponse,
     CancelOrderRequest, CancelOrderResponse, CreateCurrencyRequest, CreateCurrencyResponse,
     CreateSymbolRequest, CreateSymbolResponse, DecreaseRequest, DecreaseResponse,
     DeleteCurrencyRequest, DeleteCurrencyResponse, DeleteSymbolRequest, DeleteSymbolResponse,
-    GetAccountRequest, GetAccountResponse, GetCurrencyRequest, GetCurrencyResponse,
-    GetOrderBookRequest, GetOrderBookResponse, GetSymbolRequest, GetSymbolResponse,
+    DumpOrderBookSnapshotRequest, DumpOrderBookSnapshotResponse,
+    EstimateOrderRequest, EstimateOrderResponse,
+    GetAccountRequest, GetAccountResponse, GetAccountValueRequest, GetAccountValueResponse,
+    GetAccountsBatchRequest, GetAccountsBatchResponse,
+    GetFeeAccountRequest, GetBboRequest,
+    GetBalanceHistoryRequest, GetBalanceHistoryResponse,
+    GetCurrencyRequest, GetCurrencyResponse,
+    GetOpenInterestRequest, GetOpenInterestResponse,
+    GetOpenOrdersRequest, GetOpenOrdersResponse, GetOrderBookRequest, GetOrderBookResponse,
+    GetOrderRequest, GetOrderResponse,
+    GetSymbolRequest, GetSymbolResponse, HealthCheckRequest, HealthCheckResponse,
+    GetTickerRequest, GetTradesRequest, GetTradesResponse,
     IncreaseRequest, IncreaseResponse, ListCurrenciesRequest, ListCurrenciesResponse,
-    ListSymbolsRequest, ListSymbolsResponse, UpdateCurrencyRequest, UpdateCurrencyResponse,
-    UpdateSymbolRequest, UpdateSymbolResponse,
+    ListSymbolsRequest, ListSymbolsResponse, LoadOrderBookSnapshotRequest,
+    LoadOrderBookSnapshotResponse, PlaceOrdersBatchRequest, PlaceOrdersBatchResponse,
+    RequestWithdrawalRequest, SetSymbolStatusRequest,
+    UpdateCurrencyRequest, UpdateCurrencyResponse,
+    StreamTradesRequest, TickerResponse, TradeEvent, TransferRequest, TransferResponse,
+    UpdateSymbolRequest, UpdateSymbolResponse, WithdrawalHoldRequest, WithdrawalResponse,
 };
 
 
+pub type OrderBookStream =
+    Pin<Box<dyn Stream<Item = Result<GetOrderBookResponse, Status>> + Send + 'static>>;
+
+pub type TradeStream = Pin<Box<dyn Stream<Item = Result<TradeEvent, Status>> + Send + 'static>>;
+
+// 单次批量下单的最大订单数
+pub const MAX_BATCH_ORDERS: usize = 100;
+
+// 单次批量充值的最大条目数
+pub const MAX_BATCH_INCREASE_ENTRIES: usize = 10_000;
+
+// 单次批量查询的最大账户数
+pub const MAX_BATCH_ACCOUNTS: usize = 1_000;
+
+// 每个 HTTP/2 连接默认允许的并发请求（流）数
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 1_024;
+
+// 默认 HTTP/2 帧大小上限，与协议默认值相同
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+
+// HTTP/2 允许的帧大小范围
+pub const MAX_FRAME_SIZE_RANGE: std::ops::RangeInclusive<u32> = 16_384..=16_777_215;
+
+// 单条请求或响应消息的默认大小上限
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+// 默认请求超时，推送流只限制建立响应的时间
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// gRPC 服务端的资源限制，防止单个客户端占满连接或内存
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerLimits {
+    pub max_concurrent_streams: u32,
+    pub max_frame_size: u32,
+    pub max_message_size: usize,
+    pub request_timeout: Duration,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+// 按限制创建服务端；消息大小限制在 create_server 中设置到各个服务上
+pub fn server_builder(limits: &ServerLimits) -> Server {
+    Server::builder()
+        .max_concurrent_streams(limits.max_concurrent_streams)
+        .max_frame_size(limits.max_frame_size)
+        .timeout(limits.request_timeout)
+}
+
+// 处理器队列已满时立即返回 resource_exhausted，不阻塞 tokio 工作线程
+fn send_to_processor<T>(sender: &Sender<T>, message: T) -> Result<(), Status> {
+    sender.try_send(message).map_err(|e| match e {
+        TrySendError::Full(_) => Status::resource_exhausted("Server busy, please retry later"),
+        TrySendError::Disconnected(_) => {
+            Status::internal(format!("Failed to send message: {}", e))
+        }
+    })
+}
+
+fn currency_data(currency: Currency) -> schema::Currency {
+    schema::Currency {
+        id: currency.id,
+        name: currency.name,
+        display_name: currency.display_name,
+        scale: currency.scale.map(|scale| scale as i32),
+        display_scale: currency.display_scale.map(|scale| scale as i32),
+    }
+}
+
+fn symbol_data(symbol: Symbol) -> schema::Symbol {
+    schema::Symbol {
+        id: symbol.id,
+        name: symbol.name,
+        base: symbol.base,
+        quote: symbol.quote,
+        price_tick: symbol.price_tick.to_string(),
+        quantity_step: symbol.quantity_step.to_string(),
+        min_notional: symbol.min_notional.to_string(),
+        min_quantity: symbol.min_quantity.to_string(),
+        max_quantity: symbol.max_quantity.to_string(),
+        max_notional: symbol.max_notional.to_string(),
+        status: schema::SymbolStatus::from(symbol.status) as i32,
+    }
+}
+
+// 合并请求中的精度规则，未提供的字段沿用 current；格式错误、为负数或上下限矛盾时返回 None
+fn parse_trading_rules(
+    current: TradingRules,
+    price_tick: Option<&str>,
+    quantity_step: Option<&str>,
+    min_notional: Option<&str>,
+    min_quantity: Option<&str>,
+    max_quantity: Option<&str>,
+    max_notional: Option<&str>,
+) -> Option<TradingRules> {
+    let parse = |value: Option<&str>, current: Decimal| match value {
+        Some(value) => Decimal::from_str_exact(value).ok(),
+        None => Some(current),
+    };
+    let trading_rules = TradingRules {
+        price_tick: parse(price_tick, current.price_tick)?,
+        quantity_step: parse(quantity_step, current.quantity_step)?,
+        min_notional: parse(min_notional, current.min_notional)?,
+        min_quantity: parse(min_quantity, current.min_quantity)?,
+        max_quantity: parse(max_quantity, current.max_quantity)?,
+        max_notional: parse(max_notional, current.max_notional)?,
+    };
+    trading_rules.is_valid().then_some(trading_rules)
+}
+
+// 币种精度必须在 0 到 MAX_CURRENCY_SCALE 之间
+fn currency_scale(scale: i32) -> Result<u32, String> {
+    u32::try_from(scale)
+        .ok()
+        .filter(|&scale| scale <= MAX_CURRENCY_SCALE)
+        .ok_or_else(|| format!("Scale must be between 0 and {}", MAX_CURRENCY_SCALE))
+}
+
 pub struct LightningService {
     sequencer_senders: Vec<Sender<SequencerMessage>>,
     match_senders: Vec<Sender<MatchMessage>>,
     shard_count: usize,
     management_manager: ManagementManager,
+    order_book_publisher: Arc<OrderBookPublisher>,
+    trade_publisher: Arc<TradePublisher>,
+    processor_health: ProcessorHealth,
+    order_rate_limiter: Option<Arc<OrderRateLimiter>>,
+    depth_cache: Option<Arc<DepthCache>>,
+    read_overflow: Option<Arc<ReadOverflow>>,
 }
 
 impl LightningService {
@@ -33,56 +204,470 @@ impl LightningService {
         match_senders: Vec<Sender<MatchMessage>>,
         shard_count: usize,
         management_manager: ManagementManager,
+        order_book_publisher: Arc<OrderBookPublisher>,
+        trade_publisher: Arc<TradePublisher>,
+        processor_health: ProcessorHealth,
     ) -> Self {
         Self {
             sequencer_senders,
             match_senders,
             shard_count,
             management_manager,
+            order_book_publisher,
+            trade_publisher,
+            processor_health,
+            order_rate_limiter: None,
+            depth_cache: None,
+            read_overflow: None,
         }
     }
-}
 
-#[tonic::async_trait]
-impl Lightning for LightningService {
-    async fn get_account(
+    // 深度查询优先读取撮合线程发布的快照缓存，未设置或未命中时发给撮合线程
+    pub fn set_depth_cache(&mut self, depth_cache: Arc<DepthCache>) {
+        self.depth_cache = Some(depth_cache);
+    }
+
+    // 账户所在分片积压时余额查询转入溢出队列，由空闲的 Sequencer 工作线程回复；未设置时都发给所在分片
+    pub fn set_read_overflow(&mut self, read_overflow: Arc<ReadOverflow>) {
+        self.read_overflow = Some(read_overflow);
+    }
+
+    // 下单、撤单和改单按账户限流，未设置时不限流；查询请求不消耗令牌
+    pub fn set_order_rate_limiter(&mut self, limiter: Arc<OrderRateLimiter>) {
+        self.order_rate_limiter = Some(limiter);
+    }
+
+    fn check_order_rate(&self, account_id: i32) -> Result<(), Status> {
+        match &self.order_rate_limiter {
+            Some(limiter) if !limiter.try_acquire(account_id) => Err(Status::resource_exhausted(
+                format!("Order rate limit exceeded for account {}", account_id),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // 手续费账户只能通过 getFeeAccount 查询，用户接口不能操作
+    fn check_user_account(account_id: i32) -> Result<(), Status> {
+        if account_id == FEE_ACCOUNT_ID {
+            return Err(Status::permission_denied(format!(
+                "Account {} is reserved for the system",
+                account_id
+            )));
+        }
+        Ok(())
+    }
+
+    // 确认或取消提现冻结单，由冻结单所属账户的分片处理
+    async fn finish_withdrawal(
         &self,
-        request: Request<GetAccountRequest>,
-    ) -> Result<Response<GetAccountResponse>, Status> {
-        let req = request.into_inner();
+        req: WithdrawalHoldRequest,
+        confirm: bool,
+    ) -> Result<Response<WithdrawalResponse>, Status> {
+        Self::check_user_account(req.account_id)?;
         let request_id = Uuid::new_v4();
 
-        // 使用oneshot channel，开销更小
         let (response_sender, response_receiver) = oneshot::channel();
 
-        let message = SequencerMessage::GetAccount {
+        let message = SequencerMessage::FinishWithdrawal {
             request_id,
             account_id: req.account_id,
-            currency_id: req.currency_id,
+            hold_id: req.hold_id,
+            confirm,
             response_sender,
         };
 
-        // 计算分片索引
-        let shard_index = (req.account_id % self.shard_count as i32).abs() as usize;
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
         let sender = &self.sequencer_senders[shard_index];
 
-        // 发送消息到 channel
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
-        // 异步等待响应，不阻塞tokio线程
         match response_receiver.await {
             Ok(response) => Ok(Response::new(response)),
             Err(_) => Err(Status::internal("Failed to receive response")),
         }
     }
 
+    async fn fund_loaded_orders(
+        &self,
+        symbol_id: i32,
+        shard_index: usize,
+        orders: Vec<Order>,
+        release: bool,
+    ) -> Result<LoadOrderBookSnapshotResponse, Status> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = SequencerMessage::FundLoadedOrders {
+            request_id: Uuid::new_v4(),
+            symbol_id,
+            orders,
+            release,
+            response_sender,
+        };
+        send_to_processor(&self.sequencer_senders[shard_index], message)?;
+        response_receiver
+            .await
+            .map_err(|_| Status::internal("Failed to receive response"))
+    }
+
+    // 订单簿没有装入时退回已冻结的余额；退回失败只记录，需要人工核对
+    async fn release_loaded_orders(&self, symbol_id: i32, funded: Vec<(usize, Vec<Order>)>) {
+        for (shard_index, orders) in funded {
+            let released = self.fund_loaded_orders(symbol_id, shard_index, orders, true).await;
+            if !matches!(&released, Ok(response) if response.code == 0) {
+                warn!(
+                    symbol_id,
+                    sequencer = shard_index,
+                    response = ?released,
+                    "Failed to release balances of order book that was not loaded"
+                );
+            }
+        }
+    }
+
+    async fn request_account(
+        &self,
+        account_id: i32,
+        currency_id: Option<i32>,
+    ) -> Result<GetAccountResponse, Status> {
+        let response_receiver = self.submit_get_account(account_id, currency_id)?;
+
+        // 异步等待响应，不阻塞tokio线程
+        response_receiver
+            .await
+            .map_err(|_| Status::internal("Failed to receive response"))
+    }
+
+    // 查询请求路由到账户所在的 SequencerProcessor，返回等待响应的接收端
+    fn submit_get_account(
+        &self,
+        account_id: i32,
+        currency_id: Option<i32>,
+    ) -> Result<oneshot::Receiver<GetAccountResponse>, Status> {
+        let request_id = Uuid::new_v4();
+
+        // 使用oneshot channel，开销更小
+        let (mut response_sender, response_receiver) = oneshot::channel();
+
+        // 计算分片索引
+        let shard_index = (account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let sender = &self.sequencer_senders[shard_index];
+
+        if let Some(read_overflow) = &self.read_overflow {
+            let read = OverflowRead {
+                shard: shard_index,
+                account_id,
+                currency_id,
+                response_sender,
+            };
+            match read_overflow.offer(sender.len(), read) {
+                Ok(()) => return Ok(response_receiver),
+                Err(read) => response_sender = read.response_sender,
+            }
+        }
+
+        let message = SequencerMessage::GetAccount {
+            request_id,
+            account_id,
+            currency_id,
+            response_sender,
+        };
+
+        // 发送消息到 channel
+        send_to_processor(sender, message)?;
+        Ok(response_receiver)
+    }
+
+    // 下单请求路由到账户所在的 SequencerProcessor，返回等待响应的接收端
+    fn submit_order(
+        &self,
+        req: schema::PlaceOrderRequest,
+    ) -> Result<oneshot::Receiver<schema::PlaceOrderResponse>, Status> {
+        Self::check_user_account(req.account_id)?;
+        self.check_order_rate(req.account_id)?;
+        let (response_sender, response_receiver) = oneshot::channel();
+        let request_id = Uuid::new_v4();
+
+        let message = SequencerMessage::PlaceOrder {
+            request_id,
+            symbol_id: req.symbol_id,
+            account_id: req.account_id,
+            order_type: req.r#type,
+            side: req.side,
+            time_in_force: req.time_in_force.unwrap_or_default(),
+            price: req.price.unwrap_or_default(),
+            quantity: req.quantity.unwrap_or_default(),
+            taker_rate: req.taker_rate.unwrap_or_default(),
+            maker_rate: req.maker_rate.unwrap_or_default(),
+            post_only: req.post_only.unwrap_or_default(),
+            display_quantity: req.display_quantity,
+            stop_price: req.stop_price,
+            trigger_direction: req.trigger_direction.unwrap_or_default(),
+            protection_price: req.protection_price,
+            expires_at: req.expires_at.map(|expires_at| expires_at.max(0) as u64),
+            volume: req.volume.filter(|volume| !volume.is_empty()),
+            client_order_id: req.client_order_id,
+            validate_only: req.validate_only.unwrap_or_default(),
+            nonce: req.nonce,
+            span: place_order_span(request_id, req.account_id, req.symbol_id),
+            response_sender,
+        };
+
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        send_to_processor(&self.sequencer_senders[shard_index], message)?;
+        Ok(response_receiver)
+    }
+
+    async fn request_order_book(
+        &self,
+        symbol_id: i32,
+        levels: i32,
+        bucket: Option<Decimal>,
+    ) -> Result<GetOrderBookResponse, Status> {
+        // 缓存的快照只有 ORDER_BOOK_STREAM_LEVELS 档且未聚合，更深或聚合的查询仍由撮合线程处理
+        let cached_levels = if levels <= 0 { 20 } else { levels as usize };
+        if bucket.is_none() && cached_levels <= ORDER_BOOK_STREAM_LEVELS {
+            if let Some(snapshot) = self.depth_cache.as_ref().and_then(|c| c.load(symbol_id)) {
+                let mut response = (*snapshot).clone();
+                response.bids.truncate(cached_levels);
+                response.asks.truncate(cached_levels);
+                return Ok(response);
+            }
+        }
+
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetOrderBook {
+            request_id,
+            symbol_id,
+            levels,
+            bucket,
+            response_sender,
+        };
+
+        // 路由到对应的 MatchProcessor (按symbol_id分片)
+        let shard_index = match_shard(symbol_id, self.shard_count);
+        let sender = &self.match_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        response_receiver
+            .await
+            .map_err(|_| Status::internal("Failed to receive response"))
+    }
+
+    async fn request_cancel_all(
+        &self,
+        symbol_id: i32,
+        account_id: i32,
+        nonce: Option<i64>,
+    ) -> Result<CancelAllOrdersResponse, Status> {
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = SequencerMessage::CancelAllOrders {
+            request_id,
+            symbol_id,
+            account_id,
+            nonce,
+            response_sender,
+        };
+
+        // 路由到对应的 SequencerProcessor (按account_id分片)
+        let shard_index = (account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let sender = &self.sequencer_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        response_receiver
+            .await
+            .map_err(|_| Status::internal("Failed to receive response"))
+    }
+}
+
+#[tonic::async_trait]
+impl Lightning for LightningService {
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<GetAccountResponse>, Status> {
+        let req = request.into_inner();
+        let response = self.request_account(req.account_id, req.currency_id).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn get_accounts_batch(
+        &self,
+        request: Request<GetAccountsBatchRequest>,
+    ) -> Result<Response<GetAccountsBatchResponse>, Status> {
+        let req = request.into_inner();
+        if req.account_ids.len() > MAX_BATCH_ACCOUNTS {
+            return Err(Status::invalid_argument(format!(
+                "Batch contains {} accounts, at most {} allowed",
+                req.account_ids.len(),
+                MAX_BATCH_ACCOUNTS
+            )));
+        }
+
+        // 先把所有查询发到各自的分片，不同分片并发处理，再按请求顺序收集结果；
+        // 某个账户查询失败时只有该条目返回错误
+        let pending: Vec<_> = req
+            .account_ids
+            .iter()
+            .map(|&account_id| self.submit_get_account(account_id, req.currency_id))
+            .collect();
+        let mut responses = Vec::with_capacity(pending.len());
+        for submitted in pending {
+            let response = match submitted {
+                Ok(response_receiver) => response_receiver
+                    .await
+                    .map_err(|_| (500, "Failed to receive response".to_string())),
+                Err(status) => {
+                    let code = match status.code() {
+                        tonic::Code::ResourceExhausted => 503,
+                        _ => 500,
+                    };
+                    Err((code, status.message().to_string()))
+                }
+            };
+            responses.push(response.unwrap_or_else(|(code, message)| GetAccountResponse {
+                code,
+                message: Some(message),
+                data: HashMap::new(),
+            }));
+        }
+
+        Ok(Response::new(GetAccountsBatchResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            responses,
+        }))
+    }
+
+    async fn get_fee_account(
+        &self,
+        request: Request<GetFeeAccountRequest>,
+    ) -> Result<Response<GetAccountResponse>, Status> {
+        let req = request.into_inner();
+        let mut response = self.request_account(FEE_ACCOUNT_ID, req.currency_id).await?;
+        // 还没有收取过手续费时账户不存在，按零余额返回
+        if response.code == 404 {
+            response = GetAccountResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                data: HashMap::new(),
+            };
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn get_account_value(
+        &self,
+        request: Request<GetAccountValueRequest>,
+    ) -> Result<Response<GetAccountValueResponse>, Status> {
+        let req = request.into_inner();
+        let quote_currency_id = req.quote_currency_id;
+        if self.management_manager.get_currency(quote_currency_id).is_none() {
+            return Ok(Response::new(GetAccountValueResponse {
+                code: 404,
+                message: Some("Quote currency not found".to_string()),
+                ..Default::default()
+            }));
+        }
+        let mut provided = PriceMap {
+            quote_currency_id,
+            prices: HashMap::new(),
+        };
+        for (&currency_id, price) in &req.prices {
+            match Decimal::from_str_exact(price) {
+                Ok(price) if price >= Decimal::ZERO => {
+                    provided.prices.insert(currency_id, price);
+                }
+                _ => {
+                    return Ok(Response::new(GetAccountValueResponse {
+                        code: 400,
+                        message: Some(format!("Invalid price for currency {}", currency_id)),
+                        ..Default::default()
+                    }));
+                }
+            }
+        }
+
+        let account = self
+            .get_account(Request::new(GetAccountRequest {
+                account_id: req.account_id,
+                currency_id: None,
+            }))
+            .await?
+            .into_inner();
+        if account.code != 0 {
+            return Ok(Response::new(GetAccountValueResponse {
+                code: account.code,
+                message: account.message,
+                ..Default::default()
+            }));
+        }
+        let balances: BTreeMap<i32, Decimal> = account
+            .data
+            .iter()
+            .filter_map(|(&currency_id, balance)| {
+                Some((currency_id, Decimal::from_str_exact(&balance.value).ok()?))
+            })
+            .collect();
+
+        // 调用方没有提供价格的币种，查询与计价币种之间交易对的最新成交价
+        let mut last_trades = LastTradePrices::default();
+        for &currency_id in balances.keys() {
+            if currency_id == quote_currency_id || provided.prices.contains_key(&currency_id) {
+                continue;
+            }
+            for symbol in self
+                .management_manager
+                .symbols_between(currency_id, quote_currency_id)
+            {
+                let ticker = self
+                    .get_ticker(Request::new(GetTickerRequest {
+                        request_id: 0,
+                        symbol_id: symbol.id,
+                    }))
+                    .await?
+                    .into_inner();
+                if let Some(last_price) = ticker
+                    .last_price
+                    .and_then(|price| Decimal::from_str_exact(&price).ok())
+                {
+                    last_trades.last_prices.insert(symbol.id, last_price);
+                }
+                last_trades.symbols.push(symbol);
+            }
+        }
+
+        let account_value = value_account(&balances, quote_currency_id, &[&provided, &last_trades]);
+        Ok(Response::new(GetAccountValueResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            total_value: account_value.total.to_string(),
+            values: account_value
+                .values
+                .into_iter()
+                .map(|value| schema::CurrencyValue {
+                    currency_id: value.currency_id,
+                    balance: value.balance.to_string(),
+                    price: value.price.to_string(),
+                    value: value.value.to_string(),
+                })
+                .collect(),
+            unpriced_currency_ids: account_value.unpriced_currency_ids,
+        }))
+    }
+
     async fn increase(
         &self,
         request: Request<IncreaseRequest>,
     ) -> Result<Response<IncreaseResponse>, Status> {
         let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
         let request_id = Uuid::new_v4();
 
         // 使用oneshot channel
@@ -93,15 +678,14 @@ impl Lightning for LightningService {
             account_id: req.account_id,
             currency_id: req.currency_id,
             amount: req.amount,
+            idempotency_key: idempotency_key(req.request_id),
             response_sender,
         };
 
-        let shard_index = (req.account_id % self.shard_count as i32).abs() as usize;
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
         let sender = &self.sequencer_senders[shard_index];
 
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
         // 异步等待响应
         match response_receiver.await {
@@ -110,11 +694,94 @@ impl Lightning for LightningService {
         }
     }
 
+    async fn batch_increase(
+        &self,
+        request: Request<BatchIncreaseRequest>,
+    ) -> Result<Response<BatchIncreaseResponse>, Status> {
+        let entries = request.into_inner().entries;
+        if entries.len() > MAX_BATCH_INCREASE_ENTRIES {
+            return Err(Status::invalid_argument(format!(
+                "Batch contains {} entries, at most {} allowed",
+                entries.len(),
+                MAX_BATCH_INCREASE_ENTRIES
+            )));
+        }
+        for entry in &entries {
+            Self::check_user_account(entry.account_id)?;
+        }
+
+        // 按账户所在分片分组，每个分片只发送一条消息，同时记录条目在请求中的位置
+        let total = entries.len();
+        let mut shards: Vec<(Vec<usize>, Vec<IncreaseRequest>)> =
+            vec![(Vec::new(), Vec::new()); self.shard_count];
+        for (index, entry) in entries.into_iter().enumerate() {
+            let shard_index = (entry.account_id % self.shard_count as i32).unsigned_abs() as usize;
+            shards[shard_index].0.push(index);
+            shards[shard_index].1.push(entry);
+        }
+        let pending: Vec<_> = shards
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (indexes, _))| !indexes.is_empty())
+            .map(|(shard_index, (indexes, entries))| {
+                let (response_sender, response_receiver) = oneshot::channel();
+                let message = SequencerMessage::BatchIncrease {
+                    request_id: Uuid::new_v4(),
+                    entries,
+                    response_sender,
+                };
+                let submitted = send_to_processor(&self.sequencer_senders[shard_index], message)
+                    .map(|_| response_receiver);
+                (indexes, submitted)
+            })
+            .collect();
+
+        // 某个分片失败时只有该分片的条目返回错误
+        let mut responses = vec![IncreaseResponse::default(); total];
+        for (indexes, submitted) in pending {
+            let shard_responses = match submitted {
+                Ok(response_receiver) => response_receiver
+                    .await
+                    .map_err(|_| (500, "Failed to receive response".to_string())),
+                Err(status) => {
+                    let code = match status.code() {
+                        tonic::Code::ResourceExhausted => 503,
+                        _ => 500,
+                    };
+                    Err((code, status.message().to_string()))
+                }
+            };
+            match shard_responses {
+                Ok(shard_responses) => {
+                    for (index, response) in indexes.into_iter().zip(shard_responses) {
+                        responses[index] = response;
+                    }
+                }
+                Err((code, message)) => {
+                    for index in indexes {
+                        responses[index] = IncreaseResponse {
+                            code,
+                            message: Some(message.clone()),
+                            data: None,
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(Response::new(BatchIncreaseResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            responses,
+        }))
+    }
+
     async fn decrease(
         &self,
         request: Request<DecreaseRequest>,
     ) -> Result<Response<DecreaseResponse>, Status> {
         let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
         let request_id = Uuid::new_v4();
 
         // 使用oneshot channel
@@ -125,15 +792,15 @@ impl Lightning for LightningService {
             account_id: req.account_id,
             currency_id: req.currency_id,
             amount: req.amount,
+            idempotency_key: idempotency_key(req.request_id),
+            nonce: req.nonce,
             response_sender,
         };
 
-        let shard_index = (req.account_id % self.shard_count as i32).abs() as usize;
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
         let sender = &self.sequencer_senders[shard_index];
 
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
         // 异步等待响应
         match response_receiver.await {
@@ -142,32 +809,33 @@ impl Lightning for LightningService {
         }
     }
 
-    async fn place_order(
+    async fn transfer(
         &self,
-        request: Request<schema::PlaceOrderRequest>,
-    ) -> Result<Response<schema::PlaceOrderResponse>, Status> {
+        request: Request<TransferRequest>,
+    ) -> Result<Response<TransferResponse>, Status> {
         let req = request.into_inner();
+        Self::check_user_account(req.from_account_id)?;
+        Self::check_user_account(req.to_account_id)?;
         let request_id = Uuid::new_v4();
 
         let (response_sender, response_receiver) = oneshot::channel();
 
-        let message = SequencerMessage::PlaceOrder {
+        let message = SequencerMessage::Transfer {
             request_id,
-            symbol_id: req.symbol_id,
-            account_id: req.account_id,
-            order_type: req.r#type,
-            side: req.side,
-            price: req.price.unwrap_or_default(),
-            quantity: req.quantity.unwrap_or_default(),
+            from_account_id: req.from_account_id,
+            to_account_id: req.to_account_id,
+            currency_id: req.currency_id,
+            amount: req.amount,
+            idempotency_key: idempotency_key(req.request_id),
+            nonce: req.nonce,
             response_sender,
         };
 
-        let shard_index = (req.account_id % self.shard_count as i32).abs() as usize;
+        // 由转出账户所在分片发起，跨分片时转入分片回复
+        let shard_index = (req.from_account_id % self.shard_count as i32).unsigned_abs() as usize;
         let sender = &self.sequencer_senders[shard_index];
 
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
         match response_receiver.await {
             Ok(response) => Ok(Response::new(response)),
@@ -175,29 +843,28 @@ impl Lightning for LightningService {
         }
     }
 
-    async fn get_order_book(
+    async fn request_withdrawal(
         &self,
-        request: Request<GetOrderBookRequest>,
-    ) -> Result<Response<GetOrderBookResponse>, Status> {
+        request: Request<RequestWithdrawalRequest>,
+    ) -> Result<Response<WithdrawalResponse>, Status> {
         let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
         let request_id = Uuid::new_v4();
 
         let (response_sender, response_receiver) = oneshot::channel();
 
-        let message = MatchMessage::GetOrderBook {
+        let message = SequencerMessage::RequestWithdrawal {
             request_id,
-            symbol_id: req.symbol_id,
-            levels: req.levels.unwrap_or(20),
+            account_id: req.account_id,
+            currency_id: req.currency_id,
+            amount: req.amount,
             response_sender,
         };
 
-        // 路由到对应的 MatchProcessor (按symbol_id分片)
-        let shard_index = (req.symbol_id % self.shard_count as i32).abs() as usize;
-        let sender = &self.match_senders[shard_index];
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let sender = &self.sequencer_senders[shard_index];
 
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
         match response_receiver.await {
             Ok(response) => Ok(Response::new(response)),
@@ -205,55 +872,553 @@ impl Lightning for LightningService {
         }
     }
 
-    async fn cancel_order(
+    async fn confirm_withdrawal(
         &self,
-        request: Request<CancelOrderRequest>,
-    ) -> Result<Response<CancelOrderResponse>, Status> {
+        request: Request<WithdrawalHoldRequest>,
+    ) -> Result<Response<WithdrawalResponse>, Status> {
+        self.finish_withdrawal(request.into_inner(), true).await
+    }
+
+    async fn cancel_withdrawal(
+        &self,
+        request: Request<WithdrawalHoldRequest>,
+    ) -> Result<Response<WithdrawalResponse>, Status> {
+        self.finish_withdrawal(request.into_inner(), false).await
+    }
+
+    async fn get_balance_history(
+        &self,
+        request: Request<GetBalanceHistoryRequest>,
+    ) -> Result<Response<GetBalanceHistoryResponse>, Status> {
         let req = request.into_inner();
         let request_id = Uuid::new_v4();
 
         let (response_sender, response_receiver) = oneshot::channel();
 
-        let message = SequencerMessage::CancelOrder {
+        let message = SequencerMessage::GetBalanceHistory {
             request_id,
-            symbol_id: req.symbol_id,
             account_id: req.account_id,
-            order_id: req.order_id as u64,
+            currency_id: req.currency_id,
+            limit: req.limit.unwrap_or(0),
             response_sender,
         };
 
-        // 路由到对应的 SequencerProcessor (按account_id分片)
-        let shard_index = (req.account_id % self.shard_count as i32).abs() as usize;
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
         let sender = &self.sequencer_senders[shard_index];
 
-        if let Err(e) = sender.send(message) {
-            return Err(Status::internal(format!("Failed to send message: {}", e)));
-        }
+        send_to_processor(sender, message)?;
 
         match response_receiver.await {
             Ok(response) => Ok(Response::new(response)),
             Err(_) => Err(Status::internal("Failed to receive response")),
         }
     }
-}
 
-#[tonic::async_trait]
-impl Management for LightningService {
-    async fn create_currency(
+    async fn place_order(
         &self,
-        request: Request<CreateCurrencyRequest>,
-    ) -> Result<Response<CreateCurrencyResponse>, Status> {
+        request: Request<schema::PlaceOrderRequest>,
+    ) -> Result<Response<schema::PlaceOrderResponse>, Status> {
+        let response_receiver = self.submit_order(request.into_inner())?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    // 批量下单：按顺序全部入队后再等待响应，不同分片的订单并行处理；
+    // 单个订单入队失败或被拒绝只影响对应位置的响应
+    async fn place_orders_batch(
+        &self,
+        request: Request<PlaceOrdersBatchRequest>,
+    ) -> Result<Response<PlaceOrdersBatchResponse>, Status> {
+        let orders = request.into_inner().orders;
+        if orders.len() > MAX_BATCH_ORDERS {
+            return Err(Status::invalid_argument(format!(
+                "Batch contains {} orders, at most {} allowed",
+                orders.len(),
+                MAX_BATCH_ORDERS
+            )));
+        }
+
+        let pending: Vec<_> = orders
+            .into_iter()
+            .map(|order| self.submit_order(order))
+            .collect();
+
+        let mut responses = Vec::with_capacity(pending.len());
+        for submitted in pending {
+            let response = match submitted {
+                Ok(response_receiver) => {
+                    response_receiver
+                        .await
+                        .unwrap_or_else(|_| {
+                            schema::PlaceOrderResponse::with_reason(
+                                500,
+                                "Failed to receive response".to_string(),
+                                0,
+                                schema::RejectReason::InternalError,
+                            )
+                        })
+                }
+                Err(status) => {
+                    // 与单笔下单撮合队列满时一致，队列满返回 503
+                    let (code, reason) = match status.code() {
+                        tonic::Code::ResourceExhausted => (503, schema::RejectReason::ServerBusy),
+                        _ => (500, schema::RejectReason::InternalError),
+                    };
+                    schema::PlaceOrderResponse::with_reason(
+                        code,
+                        status.message().to_string(),
+                        0,
+                        reason,
+                    )
+                }
+            };
+            responses.push(response);
+        }
+
+        Ok(Response::new(PlaceOrdersBatchResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            responses,
+        }))
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<GetOrderBookRequest>,
+    ) -> Result<Response<GetOrderBookResponse>, Status> {
+        let req = request.into_inner();
+        // 价格聚合粒度必须为正数
+        let bucket = match req.bucket.as_deref().map(Decimal::from_str_exact) {
+            None => None,
+            Some(Ok(bucket)) if bucket > Decimal::ZERO => Some(bucket),
+            Some(_) => {
+                return Ok(Response::new(GetOrderBookResponse {
+                    code: 400,
+                    message: Some("Bucket must be a positive number".to_string()),
+                    symbol_id: req.symbol_id,
+                    ..Default::default()
+                }));
+            }
+        };
+        let response = self
+            .request_order_book(req.symbol_id, req.levels.unwrap_or(20), bucket)
+            .await?;
+        Ok(Response::new(response))
+    }
+
+    async fn get_bbo(
+        &self,
+        request: Request<GetBboRequest>,
+    ) -> Result<Response<BboResponse>, Status> {
+        let req = request.into_inner();
+        // 深度缓存的快照在每次订单簿变更后替换，直接取第一档
+        if let Some(snapshot) = self.depth_cache.as_ref().and_then(|c| c.load(req.symbol_id)) {
+            return Ok(Response::new(bbo_from_depth(&snapshot)));
+        }
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = MatchMessage::GetBbo {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            response_sender,
+        };
+        let sender = &self.match_senders[match_shard(req.symbol_id, self.shard_count)];
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn get_trades(
+        &self,
+        request: Request<GetTradesRequest>,
+    ) -> Result<Response<GetTradesResponse>, Status> {
+        let req = request.into_inner();
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetTrades {
+            request_id,
+            symbol_id: req.symbol_id,
+            limit: req.limit.unwrap_or(0),
+            response_sender,
+        };
+
+        // 成交记录保存在撮合引擎中，按symbol_id路由到对应的 MatchProcessor
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        let sender = &self.match_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn get_ticker(
+        &self,
+        request: Request<GetTickerRequest>,
+    ) -> Result<Response<TickerResponse>, Status> {
+        let req = request.into_inner();
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetTicker {
+            request_id,
+            symbol_id: req.symbol_id,
+            response_sender,
+        };
+
+        // 成交统计保存在订单簿中，按symbol_id路由到对应的 MatchProcessor
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        let sender = &self.match_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn estimate_order(
+        &self,
+        request: Request<EstimateOrderRequest>,
+    ) -> Result<Response<EstimateOrderResponse>, Status> {
+        let req = request.into_inner();
+        let invalid = |code: i32, message: String| {
+            Ok(Response::new(EstimateOrderResponse {
+                code,
+                message: Some(message),
+                symbol_id: req.symbol_id,
+                ..Default::default()
+            }))
+        };
+        if self.management_manager.get_symbol(req.symbol_id).is_none() {
+            return invalid(404, format!("Symbol {} not found", req.symbol_id));
+        }
+        let quantity = match Decimal::from_str_exact(&req.quantity) {
+            Ok(quantity) if quantity > Decimal::ZERO => quantity,
+            _ => return invalid(400, "Quantity must be a positive number".to_string()),
+        };
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = MatchMessage::EstimateOrder {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            side: req.side,
+            quantity,
+            response_sender,
+        };
+
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn get_open_orders(
+        &self,
+        request: Request<GetOpenOrdersRequest>,
+    ) -> Result<Response<GetOpenOrdersResponse>, Status> {
+        let req = request.into_inner();
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetOpenOrders {
+            request_id,
+            account_id: req.account_id,
+            symbol_id: req.symbol_id,
+            response_sender,
+        };
+
+        // 订单保存在撮合引擎中，按symbol_id路由到对应的 MatchProcessor
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        let sender = &self.match_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<GetOrderRequest>,
+    ) -> Result<Response<GetOrderResponse>, Status> {
+        let req = request.into_inner();
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetOrder {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            order_id: req.order_id.max(0) as u64,
+            response_sender,
+        };
+
+        // 订单保存在撮合引擎中，按symbol_id路由到对应的 MatchProcessor
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    type streamOrderBookStream = OrderBookStream;
+
+    async fn stream_order_book(
+        &self,
+        request: Request<GetOrderBookRequest>,
+    ) -> Result<Response<Self::streamOrderBookStream>, Status> {
+        let req = request.into_inner();
+        let symbol_id = req.symbol_id;
+        let levels = match req.levels.unwrap_or(20) {
+            levels if levels <= 0 => 20,
+            levels => levels as usize,
+        };
+
+        // 先订阅增量更新再获取初始快照，避免遗漏两者之间的变化
+        let mut updates = self.order_book_publisher.subscribe(symbol_id);
+        let snapshot = self.request_order_book(symbol_id, levels as i32, None).await?;
+
+        let (stream_sender, stream_receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            if stream_sender.send(Ok(snapshot)).await.is_err() {
+                return;
+            }
+            loop {
+                match updates.recv().await {
+                    Ok(mut update) => {
+                        update.bids.truncate(levels);
+                        update.asks.truncate(levels);
+                        if stream_sender.send(Ok(update)).await.is_err() {
+                            break;
+                        }
+                    }
+                    // 慢订阅者跳过积压的快照，下一次推送即为最新状态
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(symbol_id, skipped, "Order book subscriber lagged");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(stream_receiver)) as Self::streamOrderBookStream
+        ))
+    }
+
+    type streamTradesStream = TradeStream;
+
+    async fn stream_trades(
+        &self,
+        request: Request<StreamTradesRequest>,
+    ) -> Result<Response<Self::streamTradesStream>, Status> {
+        let symbol_id = request.into_inner().symbol_id;
+        if self.management_manager.get_symbol(symbol_id).is_none() {
+            return Err(Status::not_found(format!("Symbol {} not found", symbol_id)));
+        }
+
+        let mut trades = self.trade_publisher.subscribe(symbol_id);
+
+        let (stream_sender, stream_receiver) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                match trades.recv().await {
+                    Ok(trade) => {
+                        if stream_sender.send(Ok(trade)).await.is_err() {
+                            break;
+                        }
+                    }
+                    // 慢订阅者丢失的成交笔数记录到日志，继续推送后续成交
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(symbol_id, skipped, "Trade subscriber lagged");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(stream_receiver)) as Self::streamTradesStream
+        ))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<CancelOrderRequest>,
+    ) -> Result<Response<CancelOrderResponse>, Status> {
+        let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
+        self.check_order_rate(req.account_id)?;
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = SequencerMessage::CancelOrder {
+            request_id,
+            symbol_id: req.symbol_id,
+            account_id: req.account_id,
+            order_id: req.order_id as u64,
+            client_order_id: req.client_order_id,
+            nonce: req.nonce,
+            response_sender,
+        };
+
+        // 路由到对应的 SequencerProcessor (按account_id分片)
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let sender = &self.sequencer_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn cancel_all_orders(
+        &self,
+        request: Request<CancelAllOrdersRequest>,
+    ) -> Result<Response<CancelAllOrdersResponse>, Status> {
+        let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
+
+        // 撤销所有账户的订单只能通过管理接口
+        if req.account_id == ALL_ACCOUNTS {
+            return Ok(Response::new(CancelAllOrdersResponse {
+                code: 400,
+                message: Some("Invalid account id".to_string()),
+                order_ids: vec![],
+            }));
+        }
+
+        let response = self
+            .request_cancel_all(req.symbol_id, req.account_id, req.nonce)
+            .await?;
+        Ok(Response::new(response))
+    }
+
+    async fn amend_order(
+        &self,
+        request: Request<AmendOrderRequest>,
+    ) -> Result<Response<AmendOrderResponse>, Status> {
+        let req = request.into_inner();
+        Self::check_user_account(req.account_id)?;
+        self.check_order_rate(req.account_id)?;
+        let request_id = Uuid::new_v4();
+
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = SequencerMessage::AmendOrder {
+            request_id,
+            symbol_id: req.symbol_id,
+            account_id: req.account_id,
+            order_id: req.order_id as u64,
+            side: req.side,
+            price: req.price,
+            quantity: req.quantity,
+            nonce: req.nonce,
+            response_sender,
+        };
+
+        // 路由到对应的 SequencerProcessor (按account_id分片)，先冻结新订单所需余额
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let sender = &self.sequencer_senders[shard_index];
+
+        send_to_processor(sender, message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    // 所有处理器线程都在运行时返回 SERVING，不经过处理器队列
+    async fn health_check(
+        &self,
+        _request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let status = if self.processor_health.is_serving() {
+            schema::ServingStatus::Serving
+        } else {
+            schema::ServingStatus::NotServing
+        };
+        let processors = self
+            .processor_health
+            .statuses()
+            .into_iter()
+            .map(|(name, running)| schema::ProcessorStatus { name, running })
+            .collect();
+        Ok(Response::new(HealthCheckResponse {
+            status: status as i32,
+            processors,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Management for LightningService {
+    async fn create_currency(
+        &self,
+        request: Request<CreateCurrencyRequest>,
+    ) -> Result<Response<CreateCurrencyResponse>, Status> {
         let req = request.into_inner();
-        let currency = self.management_manager.create_currency(req.name, req.display_name);
+        let scale = match req.scale.map(currency_scale).transpose() {
+            Ok(scale) => scale,
+            Err(message) => {
+                return Ok(Response::new(CreateCurrencyResponse {
+                    code: 400,
+                    message: Some(message),
+                    data: None,
+                }));
+            }
+        };
+        let display_scale = match req.display_scale.map(currency_scale).transpose() {
+            Ok(display_scale) => display_scale,
+            Err(message) => {
+                return Ok(Response::new(CreateCurrencyResponse {
+                    code: 400,
+                    message: Some(message),
+                    data: None,
+                }));
+            }
+        };
+        let mut currency = self.management_manager.create_currency(req.name, req.display_name);
+        if scale.is_some() {
+            currency = self
+                .management_manager
+                .set_currency_scale(currency.id, scale)
+                .unwrap_or(currency);
+        }
+        if display_scale.is_some() {
+            currency = self
+                .management_manager
+                .set_currency_display_scale(currency.id, display_scale)
+                .unwrap_or(currency);
+        }
 
         Ok(Response::new(CreateCurrencyResponse {
             code: 0,
             message: Some("Success".to_string()),
-            data: Some(schema::Currency {
-                id: currency.id,
-                name: currency.name,
-                display_name: currency.display_name,
-            }),
+            data: Some(currency_data(currency)),
         }))
     }
 
@@ -266,11 +1431,7 @@ impl Management for LightningService {
             Some(currency) => Ok(Response::new(GetCurrencyResponse {
                 code: 0,
                 message: Some("Success".to_string()),
-                data: Some(schema::Currency {
-                    id: currency.id,
-                    name: currency.name,
-                    display_name: currency.display_name,
-                }),
+                data: Some(currency_data(currency)),
             })),
             None => Ok(Response::new(GetCurrencyResponse {
                 code: 404,
@@ -290,11 +1451,7 @@ impl Management for LightningService {
 
         let data: Vec<schema::Currency> = currencies
             .into_iter()
-            .map(|c| schema::Currency {
-                id: c.id,
-                name: c.name,
-                display_name: c.display_name,
-            })
+            .map(currency_data)
             .collect();
 
         Ok(Response::new(ListCurrenciesResponse {
@@ -310,15 +1467,48 @@ impl Management for LightningService {
         request: Request<UpdateCurrencyRequest>,
     ) -> Result<Response<UpdateCurrencyResponse>, Status> {
         let req = request.into_inner();
-        match self.management_manager.update_currency(req.id, req.name, req.display_name) {
+        let scale = match req.scale.map(currency_scale).transpose() {
+            Ok(scale) => scale,
+            Err(message) => {
+                return Ok(Response::new(UpdateCurrencyResponse {
+                    code: 400,
+                    message: Some(message),
+                    data: None,
+                }));
+            }
+        };
+        let display_scale = match req.display_scale.map(currency_scale).transpose() {
+            Ok(display_scale) => display_scale,
+            Err(message) => {
+                return Ok(Response::new(UpdateCurrencyResponse {
+                    code: 400,
+                    message: Some(message),
+                    data: None,
+                }));
+            }
+        };
+        let updated = self
+            .management_manager
+            .update_currency(req.id, req.name, req.display_name)
+            .map(|currency| match scale {
+                Some(scale) => self
+                    .management_manager
+                    .set_currency_scale(currency.id, Some(scale))
+                    .unwrap_or(currency),
+                None => currency,
+            })
+            .map(|currency| match display_scale {
+                Some(display_scale) => self
+                    .management_manager
+                    .set_currency_display_scale(currency.id, Some(display_scale))
+                    .unwrap_or(currency),
+                None => currency,
+            });
+        match updated {
             Some(currency) => Ok(Response::new(UpdateCurrencyResponse {
                 code: 0,
                 message: Some("Success".to_string()),
-                data: Some(schema::Currency {
-                    id: currency.id,
-                    name: currency.name,
-                    display_name: currency.display_name,
-                }),
+                data: Some(currency_data(currency)),
             })),
             None => Ok(Response::new(UpdateCurrencyResponse {
                 code: 404,
@@ -351,16 +1541,29 @@ impl Management for LightningService {
         request: Request<CreateSymbolRequest>,
     ) -> Result<Response<CreateSymbolResponse>, Status> {
         let req = request.into_inner();
-        match self.management_manager.create_symbol(req.name, req.base, req.quote) {
+        let Some(trading_rules) = parse_trading_rules(
+            TradingRules::default(),
+            req.price_tick.as_deref(),
+            req.quantity_step.as_deref(),
+            req.min_notional.as_deref(),
+            req.min_quantity.as_deref(),
+            req.max_quantity.as_deref(),
+            req.max_notional.as_deref(),
+        ) else {
+            return Ok(Response::new(CreateSymbolResponse {
+                code: 400,
+                message: Some("Invalid trading rules".to_string()),
+                data: None,
+            }));
+        };
+        match self
+            .management_manager
+            .create_symbol(req.name, req.base, req.quote, trading_rules)
+        {
             Ok(symbol) => Ok(Response::new(CreateSymbolResponse {
                 code: 0,
                 message: Some("Success".to_string()),
-                data: Some(schema::Symbol {
-                    id: symbol.id,
-                    name: symbol.name,
-                    base: symbol.base,
-                    quote: symbol.quote,
-                }),
+                data: Some(symbol_data(symbol)),
             })),
             Err(_) => Ok(Response::new(CreateSymbolResponse {
                 code: 400,
@@ -379,12 +1582,7 @@ impl Management for LightningService {
             Some(symbol) => Ok(Response::new(GetSymbolResponse {
                 code: 0,
                 message: Some("Success".to_string()),
-                data: Some(schema::Symbol {
-                    id: symbol.id,
-                    name: symbol.name,
-                    base: symbol.base,
-                    quote: symbol.quote,
-                }),
+                data: Some(symbol_data(symbol)),
             })),
             None => Ok(Response::new(GetSymbolResponse {
                 code: 404,
@@ -404,12 +1602,7 @@ impl Management for LightningService {
 
         let data: Vec<schema::Symbol> = symbols
             .into_iter()
-            .map(|s| schema::Symbol {
-                id: s.id,
-                name: s.name,
-                base: s.base,
-                quote: s.quote,
-            })
+            .map(symbol_data)
             .collect();
 
         Ok(Response::new(ListSymbolsResponse {
@@ -425,16 +1618,39 @@ impl Management for LightningService {
         request: Request<UpdateSymbolRequest>,
     ) -> Result<Response<UpdateSymbolResponse>, Status> {
         let req = request.into_inner();
-        match self.management_manager.update_symbol(req.id, req.name, req.base, req.quote) {
+        let Some(current) = self.management_manager.get_symbol(req.id) else {
+            return Ok(Response::new(UpdateSymbolResponse {
+                code: 404,
+                message: Some("Symbol not found".to_string()),
+                data: None,
+            }));
+        };
+        let Some(trading_rules) = parse_trading_rules(
+            current.trading_rules(),
+            req.price_tick.as_deref(),
+            req.quantity_step.as_deref(),
+            req.min_notional.as_deref(),
+            req.min_quantity.as_deref(),
+            req.max_quantity.as_deref(),
+            req.max_notional.as_deref(),
+        ) else {
+            return Ok(Response::new(UpdateSymbolResponse {
+                code: 400,
+                message: Some("Invalid trading rules".to_string()),
+                data: None,
+            }));
+        };
+        match self.management_manager.update_symbol(
+            req.id,
+            req.name,
+            req.base,
+            req.quote,
+            Some(trading_rules),
+        ) {
             Some(symbol) => Ok(Response::new(UpdateSymbolResponse {
                 code: 0,
                 message: Some("Success".to_string()),
-                data: Some(schema::Symbol {
-                    id: symbol.id,
-                    name: symbol.name,
-                    base: symbol.base,
-                    quote: symbol.quote,
-                }),
+                data: Some(symbol_data(symbol)),
             })),
             None => Ok(Response::new(UpdateSymbolResponse {
                 code: 404,
@@ -444,45 +1660,1288 @@ impl Management for LightningService {
         }
     }
 
-    async fn delete_symbol(
+    async fn set_symbol_status(
         &self,
-        request: Request<DeleteSymbolRequest>,
-    ) -> Result<Response<DeleteSymbolResponse>, Status> {
+        request: Request<SetSymbolStatusRequest>,
+    ) -> Result<Response<UpdateSymbolResponse>, Status> {
         let req = request.into_inner();
-        if self.management_manager.delete_symbol(req.id) {
-            Ok(Response::new(DeleteSymbolResponse {
-                code: 0,
-                message: Some("Success".to_string()),
-            }))
-        } else {
-            Ok(Response::new(DeleteSymbolResponse {
+        let Ok(status) = schema::SymbolStatus::try_from(req.status) else {
+            return Ok(Response::new(UpdateSymbolResponse {
+                code: 400,
+                message: Some("Invalid symbol status".to_string()),
+                data: None,
+            }));
+        };
+        match self.management_manager.set_symbol_status(req.symbol_id, status.into()) {
+            Some(symbol) => {
+                info!(symbol_id = symbol.id, status = symbol.status.as_str(), "Symbol status set");
+                Ok(Response::new(UpdateSymbolResponse {
+                    code: 0,
+                    message: Some("Success".to_string()),
+                    data: Some(symbol_data(symbol)),
+                }))
+            }
+            None => Ok(Response::new(UpdateSymbolResponse {
                 code: 404,
                 message: Some("Symbol not found".to_string()),
-            }))
+                data: None,
+            })),
+        }
+    }
+
+    async fn delete_symbol(
+        &self,
+        request: Request<DeleteSymbolRequest>,
+    ) -> Result<Response<DeleteSymbolResponse>, Status> {
+        let req = request.into_inner();
+        if self.management_manager.get_symbol(req.id).is_none() {
+            return Ok(Response::new(DeleteSymbolResponse {
+                code: 404,
+                message: Some("Symbol not found".to_string()),
+            }));
+        }
+
+        // 订单簿由撮合线程持有，按symbol_id路由，有挂单时拒绝删除
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = MatchMessage::DeleteSymbol {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.id,
+            response_sender,
+        };
+        let shard_index = (req.id % self.shard_count as i32).unsigned_abs() as usize;
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn dump_order_book_snapshot(
+        &self,
+        request: Request<DumpOrderBookSnapshotRequest>,
+    ) -> Result<Response<DumpOrderBookSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::DumpOrderBook {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            response_sender,
+        };
+
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn load_order_book_snapshot(
+        &self,
+        request: Request<LoadOrderBookSnapshotRequest>,
+    ) -> Result<Response<LoadOrderBookSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        let orders = match MatchingEngine::snapshot_open_orders(req.symbol_id, &req.snapshot) {
+            Ok(orders) => orders,
+            Err(e) => {
+                return Ok(Response::new(LoadOrderBookSnapshotResponse {
+                    code: 400,
+                    message: Some(e.to_string()),
+                }))
+            }
+        };
+
+        // 先在各账户所在的排序器上冻结快照中订单的余额，任一分片失败时退回已冻结的分片
+        let mut shard_orders: BTreeMap<usize, Vec<Order>> = BTreeMap::new();
+        for order in orders {
+            let shard_index = (order.account_id % self.shard_count as i32).unsigned_abs() as usize;
+            shard_orders.entry(shard_index).or_default().push(order);
+        }
+        let mut funded = Vec::new();
+        for (shard_index, orders) in shard_orders {
+            let response = self
+                .fund_loaded_orders(req.symbol_id, shard_index, orders.clone(), false)
+                .await;
+            match response {
+                Ok(response) if response.code == 0 => funded.push((shard_index, orders)),
+                response => {
+                    self.release_loaded_orders(req.symbol_id, funded).await;
+                    return response.map(Response::new);
+                }
+            }
+        }
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        let message = MatchMessage::LoadOrderBook {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            snapshot: req.snapshot,
+            force: req.force,
+            funded: true,
+            response_sender,
+        };
+
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        let response = match send_to_processor(&self.match_senders[shard_index], message) {
+            Ok(()) => response_receiver
+                .await
+                .map_err(|_| Status::internal("Failed to receive response")),
+            Err(status) => Err(status),
+        };
+        if !matches!(&response, Ok(response) if response.code == 0) {
+            self.release_loaded_orders(req.symbol_id, funded).await;
+        }
+        response.map(Response::new)
+    }
+
+    // 管理员批量撤单，account_id 为 ALL_ACCOUNTS 时撤销交易对上所有账户的订单
+    async fn admin_cancel_all_orders(
+        &self,
+        request: Request<CancelAllOrdersRequest>,
+    ) -> Result<Response<CancelAllOrdersResponse>, Status> {
+        let req = request.into_inner();
+        let response = self.request_cancel_all(req.symbol_id, req.account_id, None).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn admin_force_cancel(
+        &self,
+        request: Request<AdminForceCancelRequest>,
+    ) -> Result<Response<CancelOrderResponse>, Status> {
+        let req = request.into_inner();
+        // 管理操作必须说明原因，写入审计记录
+        if req.reason.trim().is_empty() {
+            return Err(Status::invalid_argument("Reason is required"));
+        }
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::ForceCancelOrder {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            order_id: req.order_id.max(0) as u64,
+            reason: req.reason,
+            response_sender,
+        };
+
+        // 不经过 SequencerProcessor 校验归属，直接发往交易对所在的 MatchProcessor
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn get_open_interest(
+        &self,
+        request: Request<GetOpenInterestRequest>,
+    ) -> Result<Response<GetOpenInterestResponse>, Status> {
+        let req = request.into_inner();
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = MatchMessage::GetOpenInterest {
+            request_id: Uuid::new_v4(),
+            symbol_id: req.symbol_id,
+            response_sender,
+        };
+
+        let shard_index = match_shard(req.symbol_id, self.shard_count);
+        send_to_processor(&self.match_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn admin_adjust_balance(
+        &self,
+        request: Request<AdminAdjustBalanceRequest>,
+    ) -> Result<Response<AdminAdjustBalanceResponse>, Status> {
+        let req = request.into_inner();
+        if req.reason.trim().is_empty() {
+            return Err(Status::invalid_argument("Reason is required"));
+        }
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = SequencerMessage::AdminAdjustBalance {
+            request_id: Uuid::new_v4(),
+            account_id: req.account_id,
+            currency_id: req.currency_id,
+            delta: req.delta,
+            reason: req.reason,
+            response_sender,
+        };
+
+        // 路由到对应的 SequencerProcessor (按account_id分片)
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        send_to_processor(&self.sequencer_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
+        }
+    }
+
+    async fn adjust_reserve(
+        &self,
+        request: Request<AdjustReserveRequest>,
+    ) -> Result<Response<AdjustReserveResponse>, Status> {
+        let req = request.into_inner();
+        if req.tag.trim().is_empty() {
+            return Err(Status::invalid_argument("Tag is required"));
+        }
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let message = SequencerMessage::AdjustReserve {
+            request_id: Uuid::new_v4(),
+            account_id: req.account_id,
+            currency_id: req.currency_id,
+            tag: req.tag,
+            amount: req.amount,
+            release: req.release,
+            response_sender,
+        };
+
+        let shard_index = (req.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        send_to_processor(&self.sequencer_senders[shard_index], message)?;
+
+        match response_receiver.await {
+            Ok(response) => Ok(Response::new(response)),
+            Err(_) => Err(Status::internal("Failed to receive response")),
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_server(
     sequencer_senders: Vec<Sender<SequencerMessage>>,
     match_senders: Vec<Sender<MatchMessage>>,
     shard_count: usize,
     management_manager: ManagementManager,
+    order_book_publisher: Arc<OrderBookPublisher>,
+    trade_publisher: Arc<TradePublisher>,
+    processor_health: ProcessorHealth,
+    order_rate_limiter: Option<Arc<OrderRateLimiter>>,
+    depth_cache: Arc<DepthCache>,
+    read_overflow: Option<Arc<ReadOverflow>>,
+    limits: &ServerLimits,
 ) -> (LightningServer<LightningService>, ManagementServer<LightningService>) {
-    let service1 = LightningService::new(
+    let mut service1 = LightningService::new(
         sequencer_senders.clone(),
         match_senders.clone(),
         shard_count,
         management_manager.clone(),
+        order_book_publisher.clone(),
+        trade_publisher.clone(),
+        processor_health.clone(),
     );
     let service2 = LightningService::new(
         sequencer_senders,
         match_senders,
         shard_count,
         management_manager,
+        order_book_publisher,
+        trade_publisher,
+        processor_health,
     );
+    if let Some(limiter) = order_rate_limiter {
+        service1.set_order_rate_limiter(limiter);
+    }
+    service1.set_depth_cache(depth_cache);
+    if let Some(read_overflow) = read_overflow {
+        service1.set_read_overflow(read_overflow);
+    }
     (
-        LightningServer::new(service1),
-        ManagementServer::new(service2),
+        LightningServer::new(service1)
+            .max_decoding_message_size(limits.max_message_size)
+            .max_encoding_message_size(limits.max_message_size),
+        ManagementServer::new(service2)
+            .max_decoding_message_size(limits.max_message_size)
+            .max_encoding_message_size(limits.max_message_size),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Liveness;
+    use crate::market_data::{ORDER_BOOK_CHANNEL_CAPACITY, TRADE_CHANNEL_CAPACITY};
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    fn service(
+        sequencer_senders: Vec<Sender<SequencerMessage>>,
+        match_senders: Vec<Sender<MatchMessage>>,
+    ) -> LightningService {
+        LightningService::new(
+            sequencer_senders,
+            match_senders,
+            1,
+            ManagementManager::new(),
+            Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+            Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+            ProcessorHealth::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_returns_resource_exhausted() {
+        let (sequencer_sender, _sequencer_receiver) = crossbeam_channel::bounded(1);
+        let (match_sender, _match_receiver) = crossbeam_channel::bounded(1);
+        let service = service(vec![sequencer_sender.clone()], vec![match_sender.clone()]);
+
+        // 占满队列，没有处理器消费
+        let (response_sender, _response_receiver) = oneshot::channel();
+        sequencer_sender
+            .try_send(SequencerMessage::GetAccount {
+                request_id: Uuid::new_v4(),
+                account_id: 1,
+                currency_id: None,
+                response_sender,
+            })
+            .unwrap();
+        let (response_sender, _response_receiver) = oneshot::channel();
+        match_sender
+            .try_send(MatchMessage::GetOrderBook {
+                request_id: Uuid::new_v4(),
+                symbol_id: 1,
+                levels: 1,
+                bucket: None,
+                response_sender,
+            })
+            .unwrap();
+
+        let status = tokio::time::timeout(
+            Duration::from_secs(1),
+            service.get_account(Request::new(GetAccountRequest {
+                account_id: 1,
+                currency_id: None,
+            })),
+        )
+        .await
+        .expect("get_account should not block on a full queue")
+        .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        let status = tokio::time::timeout(
+            Duration::from_secs(1),
+            service.get_order_book(Request::new(GetOrderBookRequest {
+                request_id: 1,
+                symbol_id: 1,
+                levels: Some(5),
+                bucket: None,
+            })),
+        )
+        .await
+        .expect("get_order_book should not block on a full queue")
+        .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_server_limits_reject_oversized_messages() {
+        let limits = ServerLimits {
+            max_message_size: 1024,
+            ..ServerLimits::default()
+        };
+        let (_, management) = create_server(
+            Vec::new(),
+            Vec::new(),
+            1,
+            ManagementManager::new(),
+            Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+            Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+            ProcessorHealth::new(),
+            None,
+            Arc::new(DepthCache::new()),
+            None,
+            &limits,
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = server_builder(&limits)
+            .add_service(management)
+            .serve_with_incoming(tonic::transport::server::TcpIncoming::from(listener));
+        tokio::spawn(server);
+
+        let mut client =
+            schema::management_client::ManagementClient::connect(format!("http://{}", addr))
+                .await
+                .unwrap();
+        let create = |name: String| CreateCurrencyRequest {
+            name,
+            display_name: "Test".to_string(),
+            ..Default::default()
+        };
+
+        // 限制以内的请求正常处理
+        let response = client.create_currency(create("ETH".to_string())).await.unwrap();
+        assert_eq!(response.into_inner().code, 0);
+
+        // 超过消息大小上限的请求在进入服务前被拒绝
+        let status = client.create_currency(create("X".repeat(2048))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    }
+
+    #[tokio::test]
+    async fn test_closed_queue_returns_internal() {
+        let (sequencer_sender, sequencer_receiver) = crossbeam_channel::bounded(1);
+        drop(sequencer_receiver);
+        let service = service(vec![sequencer_sender], Vec::new());
+
+        let status = service
+            .get_account(Request::new(GetAccountRequest {
+                account_id: 1,
+                currency_id: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn test_accounts_batch_fans_out_across_shards() {
+        // 每个分片只应收到本分片的账户；账户 3 不存在
+        let mut sequencer_senders = Vec::new();
+        for shard in 0..2 {
+            let (sequencer_sender, sequencer_receiver) = crossbeam_channel::unbounded();
+            sequencer_senders.push(sequencer_sender);
+            std::thread::spawn(move || {
+                for message in sequencer_receiver {
+                    let SequencerMessage::GetAccount { account_id, response_sender, .. } = message
+                    else {
+                        continue;
+                    };
+                    assert_eq!(account_id % 2, shard);
+                    let response = match account_id {
+                        3 => GetAccountResponse {
+                            code: 404,
+                            message: Some("Account not found".to_string()),
+                            data: HashMap::new(),
+                        },
+                        _ => GetAccountResponse {
+                            code: 0,
+                            message: Some("Success".to_string()),
+                            data: HashMap::from([(
+                                2,
+                                schema::Balance {
+                                    currency: "2".to_string(),
+                                    value: account_id.to_string(),
+                                    ..Default::default()
+                                },
+                            )]),
+                        },
+                    };
+                    let _ = response_sender.send(response);
+                }
+            });
+        }
+        let service = LightningService::new(
+            sequencer_senders,
+            Vec::new(),
+            2,
+            ManagementManager::new(),
+            Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+            Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+            ProcessorHealth::new(),
+        );
+
+        let response = service
+            .get_accounts_batch(Request::new(GetAccountsBatchRequest {
+                account_ids: vec![10, 3, 11],
+                currency_id: Some(2),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.code, 0);
+        let codes: Vec<i32> = response.responses.iter().map(|entry| entry.code).collect();
+        assert_eq!(codes, vec![0, 404, 0]);
+        assert_eq!(response.responses[0].data[&2].value, "10");
+        assert_eq!(response.responses[2].data[&2].value, "11");
+
+        let status = service
+            .get_accounts_batch(Request::new(GetAccountsBatchRequest {
+                account_ids: vec![1; MAX_BATCH_ACCOUNTS + 1],
+                currency_id: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_order_book_served_from_depth_cache() {
+        // 撮合队列已关闭，只有缓存命中的查询能成功
+        let (match_sender, match_receiver) = crossbeam_channel::bounded(1);
+        drop(match_receiver);
+        let mut service = service(Vec::new(), vec![match_sender]);
+        let depth_cache = Arc::new(DepthCache::new());
+        service.set_depth_cache(depth_cache.clone());
+        let level = |price: &str| schema::PriceLevel {
+            price: price.to_string(),
+            quantity: "1".to_string(),
+        };
+        depth_cache.store(
+            1,
+            GetOrderBookResponse {
+                symbol_id: 1,
+                bids: vec![level("99"), level("98")],
+                asks: vec![level("101"), level("102")],
+                sequence: 4,
+                ..Default::default()
+            },
+        );
+
+        let request = |symbol_id: i32, bucket: Option<&str>| {
+            Request::new(GetOrderBookRequest {
+                request_id: 1,
+                symbol_id,
+                levels: Some(1),
+                bucket: bucket.map(str::to_string),
+            })
+        };
+        let response = service.get_order_book(request(1, None)).await.unwrap().into_inner();
+        assert_eq!(response.bids, vec![level("99")]);
+        assert_eq!(response.asks, vec![level("101")]);
+        assert_eq!(response.sequence, 4);
+
+        // 聚合查询和未缓存的交易对仍发给撮合线程
+        let status = service.get_order_book(request(1, Some("10"))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        let status = service.get_order_book(request(2, None)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+    }
+
+    // 等待推送任务在下一次推送失败后退出并释放订阅
+    async fn wait_until_unsubscribed(has_subscribers: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while has_subscribers() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("subscription should be released after the client disconnects");
+    }
+
+    #[tokio::test]
+    async fn test_order_book_stream_sends_snapshot_then_truncated_updates() {
+        let mut service = service(Vec::new(), Vec::new());
+        let depth_cache = Arc::new(DepthCache::new());
+        service.set_depth_cache(depth_cache.clone());
+        let level = |price: &str| schema::PriceLevel {
+            price: price.to_string(),
+            quantity: "1".to_string(),
+        };
+        let book = |sequence: i64| GetOrderBookResponse {
+            symbol_id: 1,
+            bids: vec![level("99"), level("98"), level("97")],
+            asks: vec![level("101"), level("102"), level("103")],
+            sequence,
+            ..Default::default()
+        };
+        depth_cache.store(1, book(1));
+
+        let mut stream = service
+            .stream_order_book(Request::new(GetOrderBookRequest {
+                request_id: 1,
+                symbol_id: 1,
+                levels: Some(2),
+                bucket: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // 第一条是订阅时的快照，之后是按请求档数截断的增量
+        let snapshot = stream.next().await.unwrap().unwrap();
+        assert_eq!(snapshot.sequence, 1);
+        assert_eq!(snapshot.bids, vec![level("99"), level("98")]);
+        service.order_book_publisher.publish(1, book(2));
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(update.sequence, 2);
+        assert_eq!(update.asks, vec![level("101"), level("102")]);
+
+        // 客户端断开后推送任务退出，不再占用订阅
+        drop(stream);
+        service.order_book_publisher.publish(1, book(3));
+        wait_until_unsubscribed(|| service.order_book_publisher.has_subscribers(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_lagged_order_book_subscriber_skips_to_latest() {
+        let mut service = service(Vec::new(), Vec::new());
+        let depth_cache = Arc::new(DepthCache::new());
+        service.set_depth_cache(depth_cache.clone());
+        let book = |sequence: i64| GetOrderBookResponse {
+            symbol_id: 1,
+            sequence,
+            ..Default::default()
+        };
+        depth_cache.store(1, book(0));
+
+        let mut stream = service
+            .stream_order_book(Request::new(GetOrderBookRequest {
+                request_id: 1,
+                symbol_id: 1,
+                levels: None,
+                bucket: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // 推送任务还没有运行，超过广播队列容量的快照被跳过
+        let published = (ORDER_BOOK_CHANNEL_CAPACITY * 2) as i64;
+        for sequence in 1..=published {
+            service.order_book_publisher.publish(1, book(sequence));
+        }
+
+        assert_eq!(stream.next().await.unwrap().unwrap().sequence, 0);
+        let mut received = Vec::new();
+        while received.last() != Some(&published) {
+            received.push(stream.next().await.unwrap().unwrap().sequence);
+        }
+        assert_eq!(received.len(), ORDER_BOOK_CHANNEL_CAPACITY);
+        assert!(received.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    }
+
+    #[tokio::test]
+    async fn test_trade_stream_delivers_trades_and_handles_lag_and_disconnect() {
+        let service = service(Vec::new(), Vec::new());
+        service.management_manager.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        service.management_manager.create_currency("USDT".to_string(), "Tether".to_string());
+        let symbol = service
+            .management_manager
+            .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+            .unwrap();
+        let trade = |trade_id: i64| TradeEvent {
+            trade_id,
+            symbol_id: symbol.id,
+            ..Default::default()
+        };
+
+        // 不存在的交易对直接拒绝
+        let status = service
+            .stream_trades(Request::new(StreamTradesRequest { symbol_id: 99 }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let mut stream = service
+            .stream_trades(Request::new(StreamTradesRequest { symbol_id: symbol.id }))
+            .await
+            .unwrap()
+            .into_inner();
+        service.trade_publisher.publish(symbol.id, trade(1));
+        assert_eq!(stream.next().await.unwrap().unwrap().trade_id, 1);
+
+        // 慢订阅者丢失超过队列容量的旧成交，之后的成交按顺序继续推送
+        let published = (TRADE_CHANNEL_CAPACITY + 10) as i64;
+        for trade_id in 2..=published + 1 {
+            service.trade_publisher.publish(symbol.id, trade(trade_id));
+        }
+        let first = stream.next().await.unwrap().unwrap().trade_id;
+        assert_eq!(first, published + 2 - TRADE_CHANNEL_CAPACITY as i64);
+        let mut last = first;
+        while last != published + 1 {
+            let next = stream.next().await.unwrap().unwrap().trade_id;
+            assert_eq!(next, last + 1);
+            last = next;
+        }
+
+        drop(stream);
+        service.trade_publisher.publish(symbol.id, trade(published + 2));
+        wait_until_unsubscribed(|| service.trade_publisher.has_subscribers(symbol.id)).await;
+    }
+
+    #[tokio::test]
+    async fn test_order_rate_limit_rejects_before_queueing() {
+        // 队列已关闭：通过限流的请求返回 internal，被限流的请求返回 resource_exhausted
+        let (sequencer_sender, sequencer_receiver) = crossbeam_channel::bounded(1);
+        drop(sequencer_receiver);
+        let mut service = service(vec![sequencer_sender], Vec::new());
+        service.set_order_rate_limiter(Arc::new(OrderRateLimiter::new(1, 2)));
+
+        let place = |account_id| {
+            Request::new(schema::PlaceOrderRequest {
+                account_id,
+                symbol_id: 1,
+                price: Some("100".to_string()),
+                quantity: Some("1".to_string()),
+                ..Default::default()
+            })
+        };
+        let mut codes = Vec::new();
+        for _ in 0..2 {
+            codes.push(service.place_order(place(1)).await.unwrap_err().code());
+        }
+        let cancel = Request::new(CancelOrderRequest {
+            account_id: 1,
+            symbol_id: 1,
+            order_id: 1,
+            ..Default::default()
+        });
+        codes.push(service.cancel_order(cancel).await.unwrap_err().code());
+        codes.push(service.place_order(place(1)).await.unwrap_err().code());
+        assert_eq!(
+            codes,
+            vec![
+                tonic::Code::Internal,
+                tonic::Code::Internal,
+                tonic::Code::ResourceExhausted,
+                tonic::Code::ResourceExhausted,
+            ]
+        );
+
+        // 查询不消耗令牌，其他账户不受影响
+        for _ in 0..5 {
+            let status = service
+                .get_account(Request::new(GetAccountRequest {
+                    account_id: 2,
+                    currency_id: None,
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Internal);
+        }
+        let status = service.place_order(place(2)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_reports_stopped_processor() {
+        let sequencer = Liveness::new();
+        let matcher = Liveness::new();
+        let mut processor_health = ProcessorHealth::new();
+        processor_health.register("sequencer-0".to_string(), sequencer.clone());
+        processor_health.register("matcher-0".to_string(), matcher.clone());
+        let mut service = service(Vec::new(), Vec::new());
+        service.processor_health = processor_health;
+
+        let _sequencer_alive = sequencer.guard();
+        let matcher_alive = matcher.guard();
+        let response = service
+            .health_check(Request::new(HealthCheckRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status, schema::ServingStatus::Serving as i32);
+        assert_eq!(response.processors.len(), 2);
+
+        // 撮合线程退出
+        drop(matcher_alive);
+        let response = service
+            .health_check(Request::new(HealthCheckRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status, schema::ServingStatus::NotServing as i32);
+        assert!(response.processors[0].running);
+        assert!(!response.processors[1].running);
+        assert_eq!(response.processors[1].name, "matcher-0");
+    }
+
+    // 在后台线程运行的真实处理器，测试结束后停机并删除预写日志
+    struct RunningProcessors {
+        service: LightningService,
+        sequencer_senders: Vec<Sender<SequencerMessage>>,
+        match_senders: Vec<Sender<MatchMessage>>,
+        trade_execution_senders: Vec<Sender<crate::messages::TradeExecutionMessage>>,
+        sequencer_handles: Vec<std::thread::JoinHandle<()>>,
+        match_handles: Vec<std::thread::JoinHandle<()>>,
+        wal_dir: std::path::PathBuf,
+    }
+
+    impl RunningProcessors {
+        fn start(shard_count: usize) -> Self {
+            Self::start_with_workers(shard_count, 1)
+        }
+
+        fn start_with_workers(shard_count: usize, shards_per_worker: usize) -> Self {
+            use crate::matching::MatchingEngine;
+            use crate::models::BalanceManager;
+            use crate::processor::{
+                group_shards, run_matchers, run_sequencers, MatchProcessor, SequencerProcessor,
+            };
+            use crate::wal::{self, WriteAheadLog};
+
+            let management = Arc::new(ManagementManager::new());
+            management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+            management.create_currency("USDT".to_string(), "Tether USD".to_string());
+            management
+                .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+                .unwrap();
+            let wal_dir = std::env::temp_dir().join(format!("lightning-test-{}", Uuid::new_v4()));
+
+            let (match_senders, match_receivers): (Vec<_>, Vec<_>) =
+                (0..shard_count).map(|_| crossbeam_channel::unbounded()).unzip();
+            let (trade_execution_senders, trade_execution_receivers): (Vec<_>, Vec<_>) =
+                (0..shard_count).map(|_| crossbeam_channel::unbounded()).unzip();
+            let mut sequencer_senders = Vec::new();
+            let mut sequencers = Vec::new();
+            for (i, trade_execution_receiver) in trade_execution_receivers.into_iter().enumerate() {
+                let (sequencer_sender, sequencer_receiver) = crossbeam_channel::unbounded();
+                sequencer_senders.push(sequencer_sender);
+                let sequencer = SequencerProcessor::new(
+                    i,
+                    shard_count,
+                    sequencer_receiver,
+                    match_senders.clone(),
+                    trade_execution_receiver,
+                    management.clone(),
+                    BalanceManager::new(),
+                    WriteAheadLog::open(wal::sequencer_log_path(&wal_dir, i)).unwrap(),
+                    trade_execution_senders.clone(),
+                );
+                sequencers.push(sequencer);
+            }
+            let sequencer_handles = group_shards(sequencers, shards_per_worker)
+                .into_iter()
+                .map(|group| std::thread::spawn(move || run_sequencers(group)))
+                .collect();
+            let matchers = match_receivers
+                .into_iter()
+                .enumerate()
+                .map(|(i, match_receiver)| {
+                    MatchProcessor::new(
+                        i,
+                        match_receiver,
+                        trade_execution_senders.clone(),
+                        management.clone(),
+                        Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+                        Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+                        MatchingEngine::new(),
+                        WriteAheadLog::open(wal::match_log_path(&wal_dir, i)).unwrap(),
+                    )
+                })
+                .collect();
+            let match_handles = group_shards(matchers, shards_per_worker)
+                .into_iter()
+                .map(|group| std::thread::spawn(move || run_matchers(group)))
+                .collect();
+
+            let service = LightningService::new(
+                sequencer_senders.clone(),
+                match_senders.clone(),
+                shard_count,
+                (*management).clone(),
+                Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+                Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+                ProcessorHealth::new(),
+            );
+            Self {
+                service,
+                sequencer_senders,
+                match_senders,
+                trade_execution_senders,
+                sequencer_handles,
+                match_handles,
+                wal_dir,
+            }
+        }
+
+        async fn stop(self) {
+            let RunningProcessors {
+                service,
+                sequencer_senders,
+                match_senders,
+                trade_execution_senders,
+                sequencer_handles,
+                match_handles,
+                wal_dir,
+            } = self;
+            drop(service);
+            tokio::task::spawn_blocking(move || {
+                crate::processor::drain_processors(
+                    sequencer_senders,
+                    match_senders,
+                    trade_execution_senders,
+                    sequencer_handles,
+                    match_handles,
+                )
+            })
+            .await
+            .unwrap();
+            let _ = std::fs::remove_dir_all(&wal_dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shards_share_worker_threads() {
+        // 4 个分片由 2 个工作线程驱动
+        let processors = RunningProcessors::start_with_workers(4, 2);
+        assert_eq!(processors.sequencer_handles.len(), 2);
+        assert_eq!(processors.match_handles.len(), 2);
+
+        // 每个 SequencerProcessor 分片都能处理请求
+        for account_id in 0..4 {
+            let response = processors
+                .service
+                .increase(Request::new(IncreaseRequest {
+                    request_id: 0,
+                    account_id,
+                    currency_id: 2,
+                    amount: "1000".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0, "account {}", account_id);
+        }
+        let response = processors
+            .service
+            .increase(Request::new(IncreaseRequest {
+                request_id: 0,
+                account_id: 2,
+                currency_id: 1,
+                amount: "1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.code, 0);
+
+        // 每个 MatchProcessor 分片都能回复，还没有订单簿时返回 404
+        for symbol_id in 0..4 {
+            let response = processors
+                .service
+                .get_ticker(Request::new(GetTickerRequest {
+                    request_id: 0,
+                    symbol_id,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 404, "symbol {}", symbol_id);
+        }
+
+        // 成交结算跨分片回到同一工作线程上的其他分片
+        for (account_id, side) in [(2, 1), (3, 0)] {
+            let response = processors
+                .service
+                .place_order(Request::new(schema::PlaceOrderRequest {
+                    symbol_id: 1,
+                    account_id,
+                    side,
+                    price: Some("100".to_string()),
+                    quantity: Some("1".to_string()),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+        // 结算消息和查询请求走不同队列，等待结算完成
+        let mut available = None;
+        for _ in 0..100 {
+            let response = processors
+                .service
+                .get_account(Request::new(GetAccountRequest {
+                    account_id: 3,
+                    currency_id: Some(1),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            available = response.data.get(&1).map(|balance| balance.available.clone());
+            if available.as_deref() == Some("1") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(available.as_deref(), Some("1"));
+
+        processors.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_batch_returns_per_order_results() {
+        let processors = RunningProcessors::start(2);
+        // 卖方在分片 0，买方在分片 1
+        for (account_id, currency_id, amount) in [(2, 1, "1"), (1, 2, "1000")] {
+            let response = processors
+                .service
+                .increase(Request::new(IncreaseRequest {
+                    request_id: 0,
+                    account_id,
+                    currency_id,
+                    amount: amount.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+
+        let order = |symbol_id: i32, account_id: i32, side: i32, price: &str, quantity: &str| {
+            schema::PlaceOrderRequest {
+                symbol_id,
+                account_id,
+                side,
+                price: Some(price.to_string()),
+                quantity: Some(quantity.to_string()),
+                ..Default::default()
+            }
+        };
+        let response = processors
+            .service
+            .place_orders_batch(Request::new(PlaceOrdersBatchRequest {
+                orders: vec![
+                    order(1, 2, 1, "100", "1"),     // 卖单挂单
+                    order(1, 1, 0, "100", "abc"),   // 数量格式错误
+                    order(1, 1, 0, "100", "1"),     // 买单与卖单成交
+                    order(99, 2, 1, "100", "1"),    // 交易对不存在
+                    order(1, 1, 0, "100", "1000"),  // 余额不足
+                ],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let codes: Vec<i32> = response.responses.iter().map(|r| r.code).collect();
+        assert_eq!(codes, vec![0, 400, 0, 404, 400]);
+        assert!(response.responses[0].id > 0);
+        assert!(response.responses[2].id > 0);
+
+        let too_many = vec![order(1, 1, 0, "100", "1"); MAX_BATCH_ORDERS + 1];
+        let status = processors
+            .service
+            .place_orders_batch(Request::new(PlaceOrdersBatchRequest { orders: too_many }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        processors.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_account_value_uses_last_trade_price() {
+        let processors = RunningProcessors::start(2);
+        for (account_id, currency_id, amount) in
+            [(1, 2, "50000"), (2, 1, "1"), (3, 1, "0.5"), (3, 2, "100")]
+        {
+            let response = processors
+                .service
+                .increase(Request::new(IncreaseRequest {
+                    request_id: 0,
+                    account_id,
+                    currency_id,
+                    amount: amount.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+        let value = |quote_currency_id: i32, prices: Vec<(i32, &str)>| {
+            processors.service.get_account_value(Request::new(GetAccountValueRequest {
+                account_id: 3,
+                quote_currency_id,
+                prices: prices.into_iter().map(|(id, price)| (id, price.to_string())).collect(),
+            }))
+        };
+
+        // 还没有成交价，BTC 单独列出
+        let response = value(2, Vec::new()).await.unwrap().into_inner();
+        assert_eq!(response.total_value, "100");
+        assert_eq!(response.unpriced_currency_ids, vec![1]);
+
+        // BTC-USDT 以 40000 成交
+        for (account_id, side) in [(2, 1), (1, 0)] {
+            let response = processors
+                .service
+                .place_order(Request::new(schema::PlaceOrderRequest {
+                    symbol_id: 1,
+                    account_id,
+                    side,
+                    price: Some("40000".to_string()),
+                    quantity: Some("1".to_string()),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+
+        let response = value(2, Vec::new()).await.unwrap().into_inner();
+        assert_eq!(response.code, 0);
+        assert_eq!(response.total_value.parse::<Decimal>().unwrap(), Decimal::from(20100));
+        assert!(response.unpriced_currency_ids.is_empty());
+        let btc = response.values.iter().find(|v| v.currency_id == 1).unwrap();
+        assert_eq!(btc.price, "40000");
+        assert_eq!(btc.value.parse::<Decimal>().unwrap(), Decimal::from(20000));
+
+        // 以 BTC 计价时使用成交价的倒数
+        let response = value(1, Vec::new()).await.unwrap().into_inner();
+        assert_eq!(response.total_value.parse::<Decimal>().unwrap(), Decimal::new(5025, 4));
+
+        // 调用方提供的价格优先
+        let response = value(2, vec![(1, "50000")]).await.unwrap().into_inner();
+        assert_eq!(response.total_value.parse::<Decimal>().unwrap(), Decimal::from(25100));
+
+        assert_eq!(value(2, vec![(1, "abc")]).await.unwrap().into_inner().code, 400);
+        assert_eq!(value(99, Vec::new()).await.unwrap().into_inner().code, 404);
+
+        processors.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_batch_increase_funds_accounts_across_shards() {
+        let processors = RunningProcessors::start(4);
+        let mut entries: Vec<_> = (1..=1000)
+            .map(|account_id| IncreaseRequest {
+                request_id: 0,
+                account_id,
+                currency_id: 2,
+                amount: format!("{}", account_id),
+            })
+            .collect();
+        // 单条金额错误只影响该条目
+        entries[500].amount = "abc".to_string();
+
+        let response = processors
+            .service
+            .batch_increase(Request::new(BatchIncreaseRequest { entries }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.code, 0);
+        assert_eq!(response.responses.len(), 1000);
+        for (index, entry) in response.responses.iter().enumerate() {
+            let expected = if index == 500 { 400 } else { 0 };
+            assert_eq!(entry.code, expected, "entry {}", index);
+        }
+
+        for account_id in [1, 2, 3, 4, 502, 1000] {
+            let response = processors
+                .service
+                .get_account(Request::new(GetAccountRequest {
+                    account_id,
+                    currency_id: Some(2),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.data[&2].available, account_id.to_string());
+        }
+        let response = processors
+            .service
+            .get_account(Request::new(GetAccountRequest {
+                account_id: 501,
+                currency_id: Some(2),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.data.get(&2).is_none_or(|balance| balance.available == "0"));
+
+        let too_many = vec![IncreaseRequest::default(); MAX_BATCH_INCREASE_ENTRIES + 1];
+        let status = processors
+            .service
+            .batch_increase(Request::new(BatchIncreaseRequest { entries: too_many }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        processors.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_fee_account_collects_trade_fees() {
+        let processors = RunningProcessors::start(3);
+        let fee_account = || {
+            processors.service.get_fee_account(Request::new(GetFeeAccountRequest {
+                currency_id: Some(2),
+            }))
+        };
+        let response = fee_account().await.unwrap().into_inner();
+        assert_eq!(response.code, 0);
+        assert!(response.data.is_empty());
+
+        // 用户接口不能操作手续费账户
+        let status = processors
+            .service
+            .increase(Request::new(IncreaseRequest {
+                request_id: 0,
+                account_id: FEE_ACCOUNT_ID,
+                currency_id: 2,
+                amount: "1".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = processors
+            .service
+            .transfer(Request::new(schema::TransferRequest {
+                from_account_id: 1,
+                to_account_id: FEE_ACCOUNT_ID,
+                currency_id: 2,
+                amount: "1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        for (account_id, currency_id, amount) in [(1, 2, "1000"), (2, 1, "2")] {
+            let response = processors
+                .service
+                .increase(Request::new(IncreaseRequest {
+                    request_id: 0,
+                    account_id,
+                    currency_id,
+                    amount: amount.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+        // 两笔 maker 卖单各付 0.1% 手续费，taker 买单付 0.2%
+        for (account_id, side, quantity) in [(2, 1, "1"), (2, 1, "1"), (1, 0, "2")] {
+            let response = processors
+                .service
+                .place_order(Request::new(schema::PlaceOrderRequest {
+                    symbol_id: 1,
+                    account_id,
+                    side,
+                    price: Some("100".to_string()),
+                    quantity: Some(quantity.to_string()),
+                    taker_rate: Some(2000),
+                    maker_rate: Some(1000),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.code, 0);
+        }
+
+        // 结算异步进行，等手续费全部入账
+        let expected = Decimal::new(1, 1) * Decimal::TWO + Decimal::new(4, 1);
+        let mut collected = Decimal::ZERO;
+        for _ in 0..100 {
+            let response = fee_account().await.unwrap().into_inner();
+            collected = response
+                .data
+                .get(&2)
+                .map_or(Decimal::ZERO, |balance| balance.available.parse().unwrap());
+            if collected == expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(collected, expected);
+
+        processors.stop().await;
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// 处理器存活标记，run() 循环运行期间为 true
+#[derive(Debug, Clone, Default)]
+pub struct Liveness(Arc<AtomicBool>);
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn set_alive(&self, alive: bool) {
+        self.0.store(alive, Ordering::Release);
+    }
+
+    // 置为存活，返回的守卫在 run() 退出（包括 panic）时清除标记
+    pub fn guard(&self) -> LivenessGuard {
+        self.set_alive(true);
+        LivenessGuard(self.clone())
+    }
+}
+
+pub struct LivenessGuard(Liveness);
+
+impl Drop for LivenessGuard {
+    fn drop(&mut self) {
+        self.0.set_alive(false);
+    }
+}
+
+// 所有处理器的存活状态，供健康检查接口查询
+#[derive(Debug, Clone, Default)]
+pub struct ProcessorHealth {
+    processors: Vec<(String, Liveness)>,
+}
+
+impl ProcessorHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: String, liveness: Liveness) {
+        self.processors.push((name, liveness));
+    }
+
+    // (处理器名称, 是否在运行)，按注册顺序
+    pub fn statuses(&self) -> Vec<(String, bool)> {
+        self.processors
+            .iter()
+            .map(|(name, liveness)| (name.clone(), liveness.is_alive()))
+            .collect()
+    }
+
+    // 没有注册处理器时视为未就绪
+    pub fn is_serving(&self) -> bool {
+        !self.processors.is_empty()
+            && self.processors.iter().all(|(_, liveness)| liveness.is_alive())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_clears_liveness_on_drop() {
+        let liveness = Liveness::new();
+        assert!(!liveness.is_alive());
+
+        let guard = liveness.guard();
+        assert!(liveness.is_alive());
+        drop(guard);
+        assert!(!liveness.is_alive());
+    }
+
+    #[test]
+    fn test_serving_requires_all_processors_alive() {
+        let mut health = ProcessorHealth::new();
+        assert!(!health.is_serving());
+
+        let sequencer = Liveness::new();
+        let matcher = Liveness::new();
+        health.register("sequencer-0".to_string(), sequencer.clone());
+        health.register("matcher-0".to_string(), matcher.clone());
+        let _sequencer_guard = sequencer.guard();
+        assert!(!health.is_serving());
+
+        let matcher_guard = matcher.guard();
+        assert!(health.is_serving());
+
+        drop(matcher_guard);
+        assert!(!health.is_serving());
+        assert_eq!(
+            health.statuses(),
+            vec![("sequencer-0".to_string(), true), ("matcher-0".to_string(), false)]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::models::schema::{DecreaseResponse, IncreaseResponse, TransferResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// 每个账户最多记住的请求数，超出后淘汰最早的请求
+pub const IDEMPOTENCY_KEYS_PER_ACCOUNT: usize = 1000;
+// 请求键的有效期（毫秒），超过后同一键按新请求处理
+pub const IDEMPOTENCY_KEY_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+// 每个分片最多记住的已结算成交数，超出后淘汰最早结算的
+pub const SETTLED_TRADES_CAPACITY: usize = 100_000;
+
+// 请求中的 requestId 作为幂等键，0 表示客户端未提供
+pub fn idempotency_key(request_id: i64) -> Option<i64> {
+    (request_id != 0).then_some(request_id)
+}
+
+// 客户端提供的幂等键及首次处理时间，随预写日志记录一起写入，重放时恢复
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct RequestKey {
+    pub key: i64,
+    pub seen_at: u64, // 毫秒时间戳
+}
+
+// 首次处理时的响应，重复请求直接返回
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedResponse {
+    Increase(IncreaseResponse),
+    Decrease(DecreaseResponse),
+    Transfer(TransferResponse),
+}
+
+#[derive(Debug, Default)]
+struct AccountRequests {
+    order: VecDeque<RequestKey>, // 按首次处理时间排列
+    responses: HashMap<i64, CachedResponse>,
+}
+
+// 按账户划分的请求去重表：不同账户可以使用相同的键，按时间和数量淘汰
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    accounts: HashMap<i32, AccountRequests>,
+    capacity: usize,
+    ttl_ms: u64,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_KEYS_PER_ACCOUNT, IDEMPOTENCY_KEY_TTL_MS)
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize, ttl_ms: u64) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            capacity: capacity.max(1),
+            ttl_ms,
+        }
+    }
+
+    // 查找未过期的响应，顺带淘汰该账户已过期的键
+    pub fn get(&mut self, account_id: i32, key: i64, now: u64) -> Option<&CachedResponse> {
+        let requests = self.accounts.get_mut(&account_id)?;
+        Self::evict_expired(requests, now, self.ttl_ms);
+        requests.responses.get(&key)
+    }
+
+    pub fn insert(&mut self, account_id: i32, request: RequestKey, response: CachedResponse) {
+        let requests = self.accounts.entry(account_id).or_default();
+        Self::evict_expired(requests, request.seen_at, self.ttl_ms);
+        if requests.responses.insert(request.key, response).is_none() {
+            requests.order.push_back(request);
+        }
+        while requests.order.len() > self.capacity {
+            if let Some(oldest) = requests.order.pop_front() {
+                requests.responses.remove(&oldest.key);
+            }
+        }
+    }
+
+    // 请求最终未生效（如跨分片划转被退回），允许客户端用同一个键重试
+    pub fn remove(&mut self, account_id: i32, key: i64) {
+        let Some(requests) = self.accounts.get_mut(&account_id) else {
+            return;
+        };
+        requests.responses.remove(&key);
+        requests.order.retain(|request| request.key != key);
+        if requests.order.is_empty() {
+            self.accounts.remove(&account_id);
+        }
+    }
+
+    fn evict_expired(requests: &mut AccountRequests, now: u64, ttl_ms: u64) {
+        while let Some(oldest) = requests.order.front() {
+            if now.saturating_sub(oldest.seen_at) < ttl_ms {
+                break;
+            }
+            requests.responses.remove(&oldest.key);
+            requests.order.pop_front();
+        }
+    }
+}
+
+// 成交结算的去重键：成交ID只在撮合分片内唯一，需要加上交易对；
+// taker 的多笔成交汇总为一条结算，使用第一笔成交的ID，自成交时与同一账户的 maker 结算按 taker 区分
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SettlementKey {
+    pub symbol_id: i32,
+    pub trade_id: u64,
+    pub account_id: i32,
+    #[serde(default)]
+    pub taker: bool,
+}
+
+// 已结算的成交，重复投递或重放的结算消息不再记账；按结算顺序淘汰
+#[derive(Debug)]
+pub struct SettledTrades {
+    keys: HashSet<SettlementKey>,
+    order: VecDeque<SettlementKey>,
+    capacity: usize,
+}
+
+impl Default for SettledTrades {
+    fn default() -> Self {
+        Self::new(SETTLED_TRADES_CAPACITY)
+    }
+}
+
+impl SettledTrades {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn contains(&self, key: &SettlementKey) -> bool {
+        self.keys.contains(key)
+    }
+
+    // 首次结算时记录并返回 true，已经结算过返回 false
+    pub fn insert(&mut self, key: SettlementKey) -> bool {
+        if !self.keys.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn increase(code: i32) -> CachedResponse {
+        CachedResponse::Increase(IncreaseResponse {
+            code,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_keys_are_scoped_per_account_and_evicted() {
+        let mut cache = IdempotencyCache::new(2, 1000);
+        cache.insert(1, RequestKey { key: 7, seen_at: 0 }, increase(0));
+        assert_eq!(cache.get(1, 7, 10), Some(&increase(0)));
+        // 其他账户的同一个键互不影响
+        assert_eq!(cache.get(2, 7, 10), None);
+
+        // 超出容量淘汰最早的键
+        cache.insert(1, RequestKey { key: 8, seen_at: 100 }, increase(400));
+        cache.insert(1, RequestKey { key: 9, seen_at: 200 }, increase(0));
+        assert_eq!(cache.get(1, 7, 300), None);
+        assert_eq!(cache.get(1, 8, 300), Some(&increase(400)));
+
+        // 超过有效期的键被淘汰
+        assert_eq!(cache.get(1, 8, 1100), None);
+        assert_eq!(cache.get(1, 9, 1100), Some(&increase(0)));
+
+        cache.remove(1, 9);
+        assert_eq!(cache.get(1, 9, 1100), None);
+    }
+
+    #[test]
+    fn test_settled_trades_are_bounded() {
+        let key = |trade_id| SettlementKey {
+            symbol_id: 1,
+            trade_id,
+            account_id: 10,
+            taker: false,
+        };
+        let mut settled = SettledTrades::new(2);
+        assert!(settled.insert(key(1)));
+        assert!(!settled.insert(key(1)));
+        // 同一成交的另一方和其他交易对的同号成交分别记录
+        assert!(settled.insert(SettlementKey { account_id: 20, ..key(1) }));
+        assert!(settled.insert(SettlementKey { symbol_id: 2, ..key(1) }));
+        assert!(settled.insert(SettlementKey { taker: true, ..key(1) }));
+
+        // 超出容量后最早的键被淘汰
+        assert!(settled.insert(SettlementKey { account_id: 20, ..key(1) }));
+    }
+}
@@ -1,10 +1,25 @@
+pub mod affinity;
+pub mod config;
+pub mod dead_letter;
 pub mod grpc;
+pub mod health;
+pub mod idempotency;
+pub mod market_data;
 pub mod matching;
 pub mod messages;
+pub mod metrics;
 pub mod models;
+pub mod overflow;
 pub mod processor;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod rest;
+pub mod risk;
+pub mod telemetry;
+pub mod valuation;
+pub mod wal;
+pub mod websocket;
 
 pub use messages::{MatchMessage, SequencerMessage};
 pub use models::BalanceManager;
-
-pub const SHARD_COUNT: usize = 10;
+pub use config::Config;
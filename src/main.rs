@@ -1,82 +1,369 @@
-mod grpc;
-mod matching;
-mod messages;
-mod models;
-mod processor;
-
-use crossbeam_channel;
-use grpc::create_server;
-use messages::{MatchMessage, SequencerMessage, TradeExecutionMessage};
-use models::ManagementManager;
-use processor::{MatchProcessor, SequencerProcessor};
+use lightning::affinity::{pin_current_thread, CoreAssigner};
+use lightning::dead_letter::{self, DeadLetter, DeadLetterLog, DeadLetterSink};
+use lightning::grpc::{create_server, server_builder, LightningService};
+use lightning::health::ProcessorHealth;
+use lightning::market_data::{
+    DepthCache, OrderBookPublisher, TradePublisher, ORDER_BOOK_CHANNEL_CAPACITY,
+    TRADE_CHANNEL_CAPACITY,
+};
+use lightning::messages::{MatchMessage, SequencerMessage, TradeExecutionMessage};
+use lightning::metrics;
+use lightning::models::{ManagementManager, MarketConfig};
+use lightning::overflow::ReadOverflow;
+use lightning::processor::{
+    drain_processors, group_shards, run_matchers, run_sequencers, MatchProcessor,
+    SequencerProcessor,
+};
+use lightning::rate_limit::OrderRateLimiter;
+use lightning::reconcile;
+use lightning::risk::PlacementMode;
+use lightning::rest;
+use lightning::wal::{self, WriteAheadLog};
+use lightning::websocket::WsGateway;
+use lightning::Config;
+use std::sync::Arc;
 use std::thread;
-use tonic::transport::Server;
+use std::time::Duration;
+use tracing::{error, info, warn};
 
-pub const SHARD_COUNT: usize = 10;
+// 停机时等待处理中的 gRPC 请求完成的最长时间
+const SERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting High-Performance Lightning Balance Service...");
 
+    let config = Config::from_env()?;
+    let telemetry = lightning::telemetry::init()?;
+    let shard_count = config.shard_count;
+    let channel_capacity = config.channel_capacity;
+    println!(
+        "Using {} shards ({} per worker thread), channel capacity {}",
+        shard_count, config.shards_per_worker, channel_capacity
+    );
+
     // 创建高性能channel列表
     let mut sequencer_senders = Vec::new();
     let mut processor_handles = Vec::new();
 
-    // 创建撮合引擎channel列表
+    // 创建撮合引擎channel列表 - 需在启动 SequencerProcessor 之前创建，否则其持有的发送端列表为空
     let mut match_senders = Vec::new();
+    let mut match_receivers = Vec::new();
     let mut match_handles = Vec::new();
 
+    for _ in 0..shard_count {
+        let (sender, receiver) = crossbeam_channel::bounded::<MatchMessage>(channel_capacity);
+        match_senders.push(sender);
+        match_receivers.push(receiver);
+    }
+
     // 创建成交执行channel列表 - 每个SequencerProcessor一个
     let mut trade_execution_senders = Vec::new();
     let mut trade_execution_receivers = Vec::new();
 
-    for _ in 0..SHARD_COUNT {
-        let (sender, receiver) = crossbeam_channel::unbounded::<TradeExecutionMessage>();
+    for _ in 0..shard_count {
+        let (sender, receiver) = crossbeam_channel::bounded::<TradeExecutionMessage>(channel_capacity);
         trade_execution_senders.push(sender);
         trade_execution_receivers.push(receiver);
     }
 
-    // 创建管理管理器
-    let management_manager = std::sync::Arc::new(ManagementManager::new());
+    // 创建管理管理器，配置了币种和交易对文件时按文件初始化
+    let management_manager = match &config.markets_file {
+        Some(path) => {
+            let markets = MarketConfig::load(path)?;
+            info!(
+                currencies = markets.currencies.len(),
+                symbols = markets.symbols.len(),
+                path = %path,
+                "Loaded markets file"
+            );
+            ManagementManager::from_market_config(markets)?
+        }
+        None => ManagementManager::new(),
+    };
+    let management_manager = std::sync::Arc::new(management_manager);
+
+    // 创建订单簿、成交行情发布器
+    let order_book_publisher =
+        std::sync::Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY));
+    let trade_publisher = std::sync::Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY));
+    // 撮合线程发布的深度快照，深度查询直接读取，不占用撮合线程
+    let depth_cache = std::sync::Arc::new(DepthCache::new());
+
+    // 处理器存活状态，健康检查接口据此判断服务是否就绪
+    let mut processor_health = ProcessorHealth::new();
+
+    // 预写日志目录，启动时按分片重放恢复余额和订单簿（撮合分片先加载快照）
+    let wal_dir = config.wal_dir;
+    println!("Using WAL directory {}", wal_dir);
+
+    // 上次运行中无法投递的结算消息，由目标分片在启动前重新处理；全部处理完后才归档原文件，
+    // 目标分片不存在或重新投递失败的死信保留到下次启动
+    let dead_letter_path = dead_letter::dead_letter_path(&wal_dir);
+    let mut dead_letters: Vec<Vec<DeadLetter>> = (0..shard_count).map(|_| Vec::new()).collect();
+    let mut undelivered_letters = Vec::new();
+    for letter in dead_letter::read_dead_letters(&dead_letter_path)? {
+        match dead_letters.get_mut(letter.shard()) {
+            Some(letters) => letters.push(letter),
+            None => {
+                warn!(
+                    shard = letter.shard(),
+                    shard_count,
+                    ?letter,
+                    "Keeping dead letter for unknown shard"
+                );
+                undelivered_letters.push(letter);
+            }
+        }
+    }
+
+    // 可选绑核：工作线程按启动顺序轮流绑定核心，线程多于核心时共用核心
+    let mut core_assigner = CoreAssigner::new(config.pin_cores);
+    if config.pin_cores {
+        let worker_count = 2 * shard_count.div_ceil(config.shards_per_worker);
+        match core_assigner.core_count() {
+            0 => warn!("CPU pinning is not supported on this platform, running unpinned"),
+            cores if cores < worker_count => info!(
+                workers = worker_count,
+                cores,
+                "Pinning worker threads to cores, some cores are shared"
+            ),
+            cores => info!(workers = worker_count, cores, "Pinning worker threads to cores"),
+        }
+    }
+
+    // 先恢复所有分片的余额和订单簿，核对冻结余额与未完成订单后再启动处理器
+    let mut replayed_states = Vec::new();
+    let mut matching_engines = Vec::new();
+    for i in 0..shard_count {
+        replayed_states.push(wal::replay(wal::sequencer_log_path(&wal_dir, i))?);
+        let mut matching_engine =
+            wal::recover_matching_engine(wal::match_log_path(&wal_dir, i), config.circuit_breaker)?;
+        matching_engine.set_trade_retention(config.trade_retention);
+        matching_engine.set_rounding_policy(config.rounding_policy);
+        matching_engine.set_self_trade_prevention(config.self_trade_prevention);
+        matching_engine.set_match_mode(config.match_mode);
+        matching_engines.push(matching_engine);
+    }
+    // 跨分片划转停在两个阶段之间时由转入账户所在分片补记入账
+    let mut interrupted_transfers: Vec<Vec<wal::PendingTransfer>> =
+        (0..shard_count).map(|_| Vec::new()).collect();
+    for transfer in wal::unmatched_transfers(&replayed_states) {
+        let shard = (transfer.to_account_id % shard_count as i32).unsigned_abs() as usize;
+        interrupted_transfers[shard].push(transfer);
+    }
+    // 各分片日志中最后的下单占用方式，现有的未完成订单按此方式占用余额
+    let placement_modes: Vec<_> = replayed_states.iter().map(|state| state.placement_mode).collect();
+    let balance_managers: Vec<_> =
+        replayed_states.into_iter().map(|state| state.balance_manager).collect();
+    // 死信中的结算尚未处理，对应账户的偏差可能在重新投递后消失，这里只报告不修正；
+    // 保证金模式下挂单不冻结余额，不做核对
+    if placement_modes.iter().all(|mode| *mode == PlacementMode::PrefreezePerOrder) {
+        for discrepancy in
+            reconcile::reconcile(&balance_managers, &matching_engines, &management_manager)
+        {
+            warn!(
+                account_id = discrepancy.account_id,
+                currency_id = discrepancy.currency_id,
+                frozen = %discrepancy.frozen,
+                expected = %discrepancy.expected,
+                delta = %discrepancy.delta(),
+                "Frozen balance mismatch"
+            );
+        }
+    }
+
+    // 可选的查询溢出：热点分片积压时余额查询由空闲的 Sequencer 工作线程回复
+    let read_overflow = config.read_overflow_threshold.map(|threshold| {
+        Arc::new(ReadOverflow::new(
+            shard_count,
+            threshold,
+            channel_capacity,
+            management_manager.clone(),
+        ))
+    });
+
+    // 启动高性能消息处理器（SequencerProcessor），按分组交给工作线程
+    let mut sequencer_processors = Vec::new();
+    for (i, ((letters, transfers), balance_manager)) in dead_letters
+        .into_iter()
+        .zip(interrupted_transfers)
+        .zip(balance_managers)
+        .enumerate()
+    {
+        let wal_path = wal::sequencer_log_path(&wal_dir, i);
+        let sequencer_wal = WriteAheadLog::open(&wal_path)?;
 
-    // 启动高性能消息处理器（SequencerProcessor）
-    for i in 0..SHARD_COUNT {
-        let (message_sender, message_receiver) = crossbeam_channel::unbounded::<SequencerMessage>();
+        let (message_sender, message_receiver) = crossbeam_channel::bounded::<SequencerMessage>(channel_capacity);
         sequencer_senders.push(message_sender);
 
-        let processor = SequencerProcessor::new(
+        let mut processor = SequencerProcessor::new(
             i,
+            shard_count,
             message_receiver,
             match_senders.clone(),
             trade_execution_receivers.remove(0),
             management_manager.clone(),
+            balance_manager,
+            sequencer_wal,
+            trade_execution_senders.clone(),
         );
-        let handle = thread::spawn(move || {
-            processor.run();
-        });
-        processor_handles.push(handle);
+        processor.set_risk_limits(config.risk_limits);
+        // 有未完成订单时拒绝切换占用方式，需要先撤销全部订单
+        processor.restore_open_orders(placement_modes[i], &matching_engines);
+        processor.set_placement_mode(config.placement_mode)?;
+        for letter in letters {
+            if let Err(e) = processor.redeliver(&letter) {
+                error!(sequencer = i, error = %e, ?letter, "Failed to redeliver dead letter");
+                undelivered_letters.push(letter);
+            }
+        }
+        for transfer in transfers {
+            processor.complete_transfer(transfer);
+        }
+        if let Some(read_overflow) = &read_overflow {
+            processor.set_read_overflow(read_overflow.clone());
+        }
+        processor_health.register(format!("sequencer-{}", i), processor.liveness());
+        sequencer_processors.push(processor);
+    }
+    // 重新投递结束后再打开死信日志，运行中的死信追加到保留下来的死信之后
+    dead_letter::archive_dead_letters(&dead_letter_path, &undelivered_letters)?;
+    let dead_letter_sink = DeadLetterSink::new(DeadLetterLog::open(&dead_letter_path)?);
+    for processor in &mut sequencer_processors {
+        processor.set_dead_letters(dead_letter_sink.clone());
+    }
+    for (worker, group) in group_shards(sequencer_processors, config.shards_per_worker)
+        .into_iter()
+        .enumerate()
+    {
+        let core = core_assigner.next_core();
+        processor_handles.push(thread::spawn(move || {
+            pin_current_thread(&format!("Sequencer worker {}", worker), core);
+            run_sequencers(group)
+        }));
     }
 
     // 启动撮合引擎处理器
-    for i in 0..SHARD_COUNT {
-        let (match_sender, match_receiver) = crossbeam_channel::unbounded::<MatchMessage>();
-        match_senders.push(match_sender);
+    let mut match_processors = Vec::new();
+    for (i, matching_engine) in matching_engines.into_iter().enumerate() {
+        let wal_path = wal::match_log_path(&wal_dir, i);
+        let match_wal = WriteAheadLog::open(&wal_path)?;
 
-        let processor = MatchProcessor::new(i, match_receiver, trade_execution_senders.clone(), management_manager.clone());
-        let handle = thread::spawn(move || {
-            processor.run();
-        });
-        match_handles.push(handle);
+        let mut processor = MatchProcessor::new(
+            i,
+            match_receivers.remove(0),
+            trade_execution_senders.clone(),
+            management_manager.clone(),
+            order_book_publisher.clone(),
+            trade_publisher.clone(),
+            matching_engine,
+            match_wal,
+        );
+        processor.set_dead_letters(dead_letter_sink.clone());
+        processor.set_depth_cache(depth_cache.clone());
+        processor.set_confirm_settlement(config.confirm_settlement);
+        processor.set_batch_size(config.match_batch_size);
+        processor.set_max_maker_rebate(config.max_maker_rebate.unwrap_or_default());
+        processor_health.register(format!("matcher-{}", i), processor.liveness());
+        match_processors.push(processor);
+    }
+    for (worker, group) in group_shards(match_processors, config.shards_per_worker)
+        .into_iter()
+        .enumerate()
+    {
+        let core = core_assigner.next_core();
+        match_handles.push(thread::spawn(move || {
+            pin_current_thread(&format!("Match worker {}", worker), core);
+            run_matchers(group)
+        }));
     }
 
+    // 账户级下单限流，gRPC 和 REST 网关共用同一组令牌桶
+    let order_rate_limiter = config.order_rate.map(|rate| {
+        Arc::new(OrderRateLimiter::new(rate, config.order_burst.unwrap_or(rate)))
+    });
+
+    // REST 网关复用 gRPC 服务的分片路由和校验
+    let rest_service = config.rest_addr.as_ref().map(|_| {
+        let mut service = LightningService::new(
+            sequencer_senders.clone(),
+            match_senders.clone(),
+            shard_count,
+            (*management_manager).clone(),
+            order_book_publisher.clone(),
+            trade_publisher.clone(),
+            processor_health.clone(),
+        );
+        if let Some(limiter) = &order_rate_limiter {
+            service.set_order_rate_limiter(limiter.clone());
+        }
+        service.set_depth_cache(depth_cache.clone());
+        if let Some(read_overflow) = &read_overflow {
+            service.set_read_overflow(read_overflow.clone());
+        }
+        Arc::new(service)
+    });
+
     // 创建高性能gRPC服务
     let (lightning_service, management_service) = create_server(
         sequencer_senders.clone(),
         match_senders.clone(),
-        SHARD_COUNT,
+        shard_count,
         (*management_manager).clone(),
+        order_book_publisher.clone(),
+        trade_publisher.clone(),
+        processor_health,
+        order_rate_limiter,
+        depth_cache,
+        read_overflow,
+        &config.server_limits,
     );
 
+    // Prometheus 指标在独立端口提供
+    let metrics_listener = tokio::net::TcpListener::bind(&config.metrics_addr).await?;
+    println!("Metrics endpoint listening on http://{}/metrics", config.metrics_addr);
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(metrics_listener).await {
+            eprintln!("Metrics server error: {}", e);
+        }
+    });
+
+    // 可选的 WebSocket 行情网关，与 gRPC 推送共用发布器
+    if let Some(ws_addr) = &config.ws_addr {
+        let ws_listener = tokio::net::TcpListener::bind(ws_addr).await?;
+        println!("WebSocket gateway listening on ws://{}", ws_addr);
+        let gateway = WsGateway::new(
+            (*management_manager).clone(),
+            order_book_publisher.clone(),
+            trade_publisher.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = gateway.serve(ws_listener).await {
+                eprintln!("WebSocket gateway error: {}", e);
+            }
+        });
+    }
+
+    // 可选的 REST 网关，与 gRPC 服务一起停止，停止后才能排空处理器队列
+    let (rest_shutdown_tx, rest_shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let rest_handle = match (&config.rest_addr, rest_service) {
+        (Some(rest_addr), Some(rest_service)) => {
+            let rest_listener = tokio::net::TcpListener::bind(rest_addr).await?;
+            println!("REST gateway listening on http://{}", rest_addr);
+            let server = axum::serve(rest_listener, rest::router(rest_service))
+                .with_graceful_shutdown(async {
+                    rest_shutdown_rx.await.ok();
+                });
+            Some(tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    eprintln!("REST gateway error: {}", e);
+                }
+            }))
+        }
+        _ => None,
+    };
+
     // 配置高性能服务器
     let addr = "0.0.0.0:50051".parse()?;
     println!("High-performance gRPC server listening on {}", addr);
@@ -85,7 +372,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
 
     // 启动服务器，使用 graceful shutdown
-    let server_future = Server::builder()
+    let server_future = server_builder(&config.server_limits)
         .add_service(lightning_service)
         .add_service(management_service)
         .serve_with_shutdown(addr, async {
@@ -93,36 +380,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
 
     // 等待 Ctrl+C 信号或服务器错误
+    tokio::pin!(server_future);
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             println!("\nReceived Ctrl+C, shutting down gracefully...");
 
-            // 触发服务器关闭
+            // 停止接受新的请求，等待处理中的请求返回；推送流不会自行结束，超时后直接关闭
             let _ = shutdown_tx.send(());
-
-            // 关闭所有 channel，让处理器线程退出
-            drop(sequencer_senders);
-            drop(match_senders);
-            drop(trade_execution_senders);
+            match tokio::time::timeout(SERVER_SHUTDOWN_TIMEOUT, &mut server_future).await {
+                Ok(Err(e)) => eprintln!("Server error: {}", e),
+                Ok(Ok(())) => {}
+                Err(_) => println!("Timed out waiting for open streams, closing server"),
+            }
         }
-        result = server_future => {
+        result = &mut server_future => {
             if let Err(e) = result {
                 eprintln!("Server error: {}", e);
             }
         }
     }
 
-    // 等待处理器线程结束
-    println!("Waiting for processors to finish...");
-    for handle in processor_handles {
-        let _ = handle.join();
+    let _ = rest_shutdown_tx.send(());
+    if let Some(mut rest_handle) = rest_handle {
+        if tokio::time::timeout(SERVER_SHUTDOWN_TIMEOUT, &mut rest_handle).await.is_err() {
+            println!("Timed out waiting for REST requests, closing gateway");
+            rest_handle.abort();
+            let _ = rest_handle.await;
+        }
     }
 
-    // 等待撮合引擎线程结束
-    for handle in match_handles {
-        let _ = handle.join();
-    }
+    // 按顺序排空请求队列、撮合队列和成交回调队列，等待处理器线程结束
+    println!("Waiting for processors to finish...");
+    tokio::task::spawn_blocking(move || {
+        drain_processors(
+            sequencer_senders,
+            match_senders,
+            trade_execution_senders,
+            processor_handles,
+            match_handles,
+        )
+    })
+    .await?;
 
+    telemetry.shutdown();
     println!("Shutdown complete");
     Ok(())
 }
@@ -0,0 +1,153 @@
+use crate::matching::{spread_bps, Trade};
+use crate::models::schema::{BboResponse, GetOrderBookResponse, Side, TradeEvent};
+use arc_swap::ArcSwap;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
+
+// 每个交易对广播队列的容量，慢订阅者超出后会被跳过（lag），不会阻塞撮合线程
+pub const ORDER_BOOK_CHANNEL_CAPACITY: usize = 64;
+
+// 成交推送是逐笔的，队列容量比订单簿快照更大
+pub const TRADE_CHANNEL_CAPACITY: usize = 1024;
+
+// 推送给订阅者的最大深度档数，订阅者按各自请求的档数截断
+pub const ORDER_BOOK_STREAM_LEVELS: usize = 100;
+
+// 最近成交查询的默认条数和上限
+pub const DEFAULT_TRADES_LIMIT: usize = 50;
+pub const MAX_TRADES_LIMIT: usize = 500;
+
+// 订单簿行情发布器：按交易对分别广播订单簿快照
+pub type OrderBookPublisher = SymbolPublisher<GetOrderBookResponse>;
+
+// 成交行情发布器：按交易对分别广播逐笔成交
+pub type TradePublisher = SymbolPublisher<TradeEvent>;
+
+// 按交易对分组的有界广播发布器
+#[derive(Debug)]
+pub struct SymbolPublisher<T> {
+    capacity: usize,
+    channels: Mutex<HashMap<i32, broadcast::Sender<T>>>,
+}
+
+impl<T: Clone> SymbolPublisher<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, symbol_id: i32) -> broadcast::Receiver<T> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(symbol_id)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    // 没有订阅者时撮合线程无需构建推送内容
+    pub fn has_subscribers(&self, symbol_id: i32) -> bool {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(&symbol_id)
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    pub fn publish(&self, symbol_id: i32, event: T) {
+        if let Some(sender) = self.channels.lock().unwrap().get(&symbol_id) {
+            // broadcast::send 不会阻塞，没有订阅者时返回错误，直接忽略
+            let _ = sender.send(event);
+        }
+    }
+}
+
+// 订单簿深度快照，与推送给订阅者的快照相同，包含 ORDER_BOOK_STREAM_LEVELS 档
+pub type DepthSnapshot = GetOrderBookResponse;
+
+// 每个交易对最新的深度快照：撮合线程每次修改订单簿后整体替换，
+// 查询直接读取而不经过撮合线程；读到的快照可能稍旧，但总是某一时刻完整的订单簿
+#[derive(Debug, Default)]
+pub struct DepthCache {
+    snapshots: RwLock<HashMap<i32, Arc<ArcSwap<DepthSnapshot>>>>,
+}
+
+impl DepthCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(&self, symbol_id: i32) -> Option<Arc<DepthSnapshot>> {
+        let snapshots = self.snapshots.read().unwrap();
+        snapshots.get(&symbol_id).map(|snapshot| snapshot.load_full())
+    }
+
+    pub fn contains(&self, symbol_id: i32) -> bool {
+        self.snapshots.read().unwrap().contains_key(&symbol_id)
+    }
+
+    // 已有的交易对只在读锁下替换快照，新交易对才需要写锁
+    pub fn store(&self, symbol_id: i32, snapshot: DepthSnapshot) {
+        if let Some(current) = self.snapshots.read().unwrap().get(&symbol_id) {
+            current.store(Arc::new(snapshot));
+            return;
+        }
+        self.snapshots
+            .write()
+            .unwrap()
+            .insert(symbol_id, Arc::new(ArcSwap::from_pointee(snapshot)));
+    }
+
+    pub fn remove(&self, symbol_id: i32) {
+        self.snapshots.write().unwrap().remove(&symbol_id);
+    }
+}
+
+// 从深度快照取出最优买卖价，快照的第一档就是最优价位
+pub fn bbo_from_depth(snapshot: &DepthSnapshot) -> BboResponse {
+    BboResponse {
+        code: snapshot.code,
+        message: snapshot.message.clone(),
+        symbol_id: snapshot.symbol_id,
+        best_bid: snapshot.best_bid.clone(),
+        best_bid_quantity: snapshot.bids.first().map(|level| level.quantity.clone()),
+        best_ask: snapshot.best_ask.clone(),
+        best_ask_quantity: snapshot.asks.first().map(|level| level.quantity.clone()),
+        spread: snapshot.spread.clone(),
+        spread_bps: snapshot
+            .best_bid
+            .as_deref()
+            .zip(snapshot.best_ask.as_deref())
+            .and_then(|(bid, ask)| {
+                spread_bps(Decimal::from_str_exact(bid).ok()?, Decimal::from_str_exact(ask).ok()?)
+            })
+            .map(|bps| bps.to_string()),
+        timestamp: snapshot.timestamp,
+        sequence: snapshot.sequence,
+    }
+}
+
+// 将撮合产生的成交转换为推送消息
+pub fn trade_event(trade: &Trade) -> TradeEvent {
+    let taker_side = if trade.taker_is_buyer() {
+        Side::Bid
+    } else {
+        Side::Ask
+    };
+    TradeEvent {
+        trade_id: trade.id as i64,
+        symbol_id: trade.symbol_id,
+        price: trade.price.to_string(),
+        quantity: trade.quantity.to_string(),
+        buy_order_id: trade.buy_order_id as i64,
+        sell_order_id: trade.sell_order_id as i64,
+        buy_account_id: trade.buy_account_id,
+        sell_account_id: trade.sell_account_id,
+        taker_side: taker_side as i32,
+        timestamp: trade.created_at as i64,
+        sequence: trade.sequence as i64,
+    }
+}
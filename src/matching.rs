@@ -1,7 +1,10 @@
-use crate::models::BalanceError;
-use rust_decimal::Decimal;
+use crate::models::{checked_notional, BalanceError, ManagementManager};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use uuid::Uuid;
 
 // 订单状态
@@ -16,8 +19,10 @@ pub enum OrderStatus {
 // 订单类型
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum OrderType {
-    Limit = 0,  // 限价单
-    Market = 1, // 市价单
+    Limit = 0,      // 限价单
+    Market = 1,     // 市价单
+    StopMarket = 2, // 止损市价单，触发后按市价单撮合
+    StopLimit = 3,  // 止损限价单，触发后按委托价作为限价单撮合，不能立即成交的部分挂单
 }
 
 impl From<i32> for OrderType {
@@ -25,11 +30,35 @@ impl From<i32> for OrderType {
         match value {
             0 => OrderType::Limit,
             1 => OrderType::Market,
+            2 => OrderType::StopMarket,
+            3 => OrderType::StopLimit,
             _ => OrderType::Limit, // 默认限价单
         }
     }
 }
 
+impl OrderType {
+    pub fn is_stop(&self) -> bool {
+        matches!(self, OrderType::StopMarket | OrderType::StopLimit)
+    }
+
+    // 止损单激活后的撮合方式，普通订单不变
+    pub fn on_trigger(&self) -> OrderType {
+        match self {
+            OrderType::StopMarket | OrderType::Market => OrderType::Market,
+            OrderType::StopLimit | OrderType::Limit => OrderType::Limit,
+        }
+    }
+
+    // 带触发价的订单挂起时的类型：市价单为止损市价单，限价单为止损限价单
+    fn with_stop(&self) -> OrderType {
+        match self.on_trigger() {
+            OrderType::Market => OrderType::StopMarket,
+            _ => OrderType::StopLimit,
+        }
+    }
+}
+
 // 订单方向
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum OrderSide {
@@ -47,6 +76,459 @@ impl From<i32> for OrderSide {
     }
 }
 
+// 订单有效期
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TimeInForce {
+    Gtc = 0, // 撤销前一直有效
+    Ioc = 1, // 立即成交，剩余部分撤销
+    Fok = 2, // 全部成交，否则整单撤销
+}
+
+impl From<i32> for TimeInForce {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => TimeInForce::Gtc,
+            1 => TimeInForce::Ioc,
+            2 => TimeInForce::Fok,
+            _ => TimeInForce::Gtc, // 默认GTC
+        }
+    }
+}
+
+// 止损单触发方向
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum TriggerDirection {
+    #[default]
+    Rising = 0,  // 最新成交价 >= 触发价时激活
+    Falling = 1, // 最新成交价 <= 触发价时激活
+}
+
+impl From<i32> for TriggerDirection {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => TriggerDirection::Rising,
+            1 => TriggerDirection::Falling,
+            _ => TriggerDirection::Rising, // 默认向上触发
+        }
+    }
+}
+
+// 自成交保护模式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum SelfTradePrevention {
+    #[default]
+    None,        // 不做自成交保护，同一账户的挂单照常成交
+    CancelTaker, // 撤销 taker 剩余部分
+    CancelMaker, // 撤销 maker，taker 继续撮合下一笔挂单
+    CancelBoth,  // 同时撤销 taker 和 maker
+}
+
+impl SelfTradePrevention {
+    // 配置取值：none、cancel-taker、cancel-maker、cancel-both
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Some(Self::None),
+            "cancel-taker" => Some(Self::CancelTaker),
+            "cancel-maker" => Some(Self::CancelMaker),
+            "cancel-both" => Some(Self::CancelBoth),
+            _ => None,
+        }
+    }
+}
+
+// 同一价格级别内 taker 数量的分配方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum MatchMode {
+    #[default]
+    Fifo,    // 价格-时间优先，先到的挂单先成交
+    ProRata, // 按挂单显示数量的比例分配
+}
+
+impl MatchMode {
+    // 配置取值：fifo、pro-rata
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fifo" => Some(Self::Fifo),
+            "pro-rata" => Some(Self::ProRata),
+            _ => None,
+        }
+    }
+}
+
+// 手续费率（小数形式，0.001 = 0.1%）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct FeeRates {
+    pub taker: Decimal,
+    pub maker: Decimal,
+}
+
+impl FeeRates {
+    // 请求中的费率单位为百万分之一；taker 负费率按 0 处理，maker 负费率为返佣，
+    // 最多返还 max_maker_rebate，为 0 时不支持返佣
+    pub fn from_ppm(taker_rate: i32, maker_rate: i32, max_maker_rebate: u32) -> Self {
+        let to_rate = |rate: i32| Decimal::new(rate as i64, 6);
+        let min_maker_rate = -(max_maker_rebate.min(i32::MAX as u32) as i32);
+        Self {
+            taker: to_rate(taker_rate.max(0)),
+            maker: to_rate(maker_rate.max(min_maker_rate)),
+        }
+    }
+
+    // 买单下单时按 taker、maker 中较高的费率预留手续费，返佣不预留
+    pub fn reserve_rate(&self) -> Decimal {
+        self.taker.max(self.maker).max(Decimal::ZERO)
+    }
+}
+
+// 交易对的价格、数量精度规则，取值为零表示不限制
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct TradingRules {
+    pub price_tick: Decimal,    // 价格最小变动单位
+    pub quantity_step: Decimal, // 数量最小变动单位
+    pub min_notional: Decimal,  // 最小成交额（价格 * 数量）
+    #[serde(default)]
+    pub min_quantity: Decimal, // 单笔最小数量
+    #[serde(default)]
+    pub max_quantity: Decimal, // 单笔最大数量
+    #[serde(default)]
+    pub max_notional: Decimal, // 单笔最大成交额，防止误操作下出超大订单
+}
+
+impl TradingRules {
+    pub fn is_valid(&self) -> bool {
+        self.price_tick >= Decimal::ZERO
+            && self.quantity_step >= Decimal::ZERO
+            && self.min_notional >= Decimal::ZERO
+            && self.min_quantity >= Decimal::ZERO
+            && self.max_quantity >= Decimal::ZERO
+            && self.max_notional >= Decimal::ZERO
+            && (self.max_quantity.is_zero() || self.max_quantity >= self.min_quantity)
+    }
+
+    pub fn check_quantity_range(&self, quantity: Decimal) -> Result<(), BalanceError> {
+        if self.min_quantity > Decimal::ZERO && quantity < self.min_quantity {
+            return Err(BalanceError::InvalidQuantity(format!(
+                "Quantity {} is below minimum {}",
+                quantity, self.min_quantity
+            )));
+        }
+        if self.max_quantity > Decimal::ZERO && quantity > self.max_quantity {
+            return Err(BalanceError::InvalidQuantity(format!(
+                "Quantity {} is above maximum {}",
+                quantity, self.max_quantity
+            )));
+        }
+        Ok(())
+    }
+
+    // 按金额下单的市价买单：金额即成交额上限，按最小、最大成交额校验
+    pub fn check_quote_volume(&self, volume: Decimal) -> Result<(), BalanceError> {
+        if volume <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount("Volume must be positive".to_string()));
+        }
+        if volume < self.min_notional {
+            return Err(BalanceError::BelowMinNotional(format!(
+                "Volume {} is below minimum notional {}",
+                volume, self.min_notional
+            )));
+        }
+        self.check_max_notional(volume)
+    }
+
+    pub fn check_max_notional(&self, notional: Decimal) -> Result<(), BalanceError> {
+        if self.max_notional > Decimal::ZERO && notional > self.max_notional {
+            return Err(BalanceError::AboveMaxNotional(format!(
+                "Notional {} is above maximum {}",
+                notional, self.max_notional
+            )));
+        }
+        Ok(())
+    }
+
+    // 市价单没有限价，只校验数量和触发价；带报价的市价单按报价校验最大成交额，
+    // 没有报价的由撮合引擎按订单簿估算
+    pub fn check_order(
+        &self,
+        order_type: &OrderType,
+        price: Decimal,
+        quantity: Decimal,
+        stop_price: Option<Decimal>,
+    ) -> Result<(), BalanceError> {
+        if self.quantity_step > Decimal::ZERO
+            && (quantity < self.quantity_step || !(quantity % self.quantity_step).is_zero())
+        {
+            return Err(BalanceError::InvalidQuantity(format!(
+                "Quantity {} is not a multiple of step {}",
+                quantity, self.quantity_step
+            )));
+        }
+        self.check_quantity_range(quantity)?;
+
+        let off_tick = |value: Decimal| {
+            self.price_tick > Decimal::ZERO && !(value % self.price_tick).is_zero()
+        };
+        if let Some(stop_price) = stop_price {
+            if off_tick(stop_price) {
+                return Err(BalanceError::InvalidPrice(format!(
+                    "Stop price {} is not a multiple of tick {}",
+                    stop_price, self.price_tick
+                )));
+            }
+        }
+        if *order_type == OrderType::Market {
+            if price > Decimal::ZERO && price < Decimal::MAX {
+                return self.check_max_notional(checked_notional(price, quantity)?);
+            }
+            return Ok(());
+        }
+
+        if off_tick(price) {
+            return Err(BalanceError::InvalidPrice(format!(
+                "Price {} is not a multiple of tick {}",
+                price, self.price_tick
+            )));
+        }
+        let notional = checked_notional(price, quantity)?;
+        if notional < self.min_notional {
+            return Err(BalanceError::BelowMinNotional(format!(
+                "Notional {} is below minimum {}",
+                notional, self.min_notional
+            )));
+        }
+        self.check_max_notional(notional)
+    }
+}
+
+// 按金额下单的市价买单在交易对没有数量步长时，成交数量按该小数位数向下取整
+pub const QUOTE_VOLUME_QUANTITY_SCALE: u32 = 8;
+
+// 按金额下单的市价买单：最多花费的 quote 数量，以及成交数量的取整步长
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct QuoteVolume {
+    pub volume: Decimal,
+    pub quantity_step: Decimal, // 0 表示按 QUOTE_VOLUME_QUANTITY_SCALE 取整
+}
+
+impl QuoteVolume {
+    // 剩余金额按该价格最多可买的数量，不足一个步长时为 0
+    pub fn affordable_quantity(&self, remaining: Decimal, price: Decimal) -> Decimal {
+        if remaining <= Decimal::ZERO || price <= Decimal::ZERO {
+            return Decimal::ZERO;
+        }
+        let quantity = remaining / price;
+        if self.quantity_step > Decimal::ZERO {
+            (quantity / self.quantity_step).floor() * self.quantity_step
+        } else {
+            quantity.round_dp_with_strategy(QUOTE_VOLUME_QUANTITY_SCALE, RoundingStrategy::ToZero)
+        }
+    }
+}
+
+// 买方手续费币种，卖方手续费总是收取 quote
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum FeeCurrency {
+    #[default]
+    Quote, // 按成交金额收取 quote
+    Base,  // 按成交数量收取 base
+}
+
+// 成交金额和手续费除不尽时的舍入方向，付款方总是按冻结时的四舍六入五成双支付，
+// 与收款方实收之间的零头计入手续费账户
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum RoundingPolicy {
+    #[default]
+    HalfEven, // 收款方和手续费都四舍六入五成双
+    FloorToExchange, // 收款方向下取整、手续费和返佣向上取整，零头归交易所
+}
+
+impl RoundingPolicy {
+    // 配置取值：half-even、floor-to-exchange
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "half-even" => Some(Self::HalfEven),
+            "floor-to-exchange" => Some(Self::FloorToExchange),
+            _ => None,
+        }
+    }
+
+    fn fee_strategy(self) -> RoundingStrategy {
+        match self {
+            Self::HalfEven => RoundingStrategy::MidpointNearestEven,
+            // 返佣为负数，向正无穷舍入同样偏向交易所
+            Self::FloorToExchange => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+
+    // 单笔成交的 quote 金额按币种精度舍入，返回（付款方支付，收款方收到）；
+    // 未设置精度时不舍入
+    pub fn split_quote(self, gross: Decimal, scale: Option<u32>) -> (Decimal, Decimal) {
+        let Some(scale) = scale else {
+            return (gross, gross);
+        };
+        let paid = gross.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+        let received = match self {
+            Self::HalfEven => paid,
+            Self::FloorToExchange => gross.round_dp_with_strategy(scale, RoundingStrategy::ToZero),
+        };
+        (paid, received)
+    }
+}
+
+// 手续费计算配置
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct FeeConfig {
+    pub precision: u32, // 手续费保留的小数位数
+    pub buyer_fee_currency: FeeCurrency,
+    #[serde(default)]
+    pub rounding: RoundingPolicy,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            precision: 8,
+            buyer_fee_currency: FeeCurrency::Quote,
+            rounding: RoundingPolicy::default(),
+        }
+    }
+}
+
+impl FeeConfig {
+    // 计算单笔成交手续费：费率 * 成交金额（买方收 base 时为 费率 * 成交数量）
+    pub fn fee(&self, rate: Decimal, price: Decimal, quantity: Decimal, is_buyer: bool) -> Decimal {
+        let base = if is_buyer && self.buyer_fee_currency == FeeCurrency::Base {
+            quantity
+        } else {
+            price * quantity
+        };
+        (rate * base)
+            .round_dp_with_strategy(self.precision, self.rounding.fee_strategy())
+            .normalize()
+    }
+}
+
+// 价差的基点数：(卖一 - 买一) / 中间价 × 10000，全程用 Decimal 计算；
+// 买一不低于卖一、中间价为 0 或超出 Decimal 范围时为 None
+pub fn spread_bps(best_bid: Decimal, best_ask: Decimal) -> Option<Decimal> {
+    if best_bid >= best_ask {
+        return None;
+    }
+    let mid = best_bid.checked_add(best_ask)? / Decimal::TWO;
+    if mid.is_zero() {
+        return None;
+    }
+    (best_ask - best_bid)
+        .checked_mul(Decimal::from(10_000))?
+        .checked_div(mid)
+        .map(|bps| bps.normalize())
+}
+
+// 当前毫秒时间戳：订单创建时间、成交时间和订单到期都使用同一时钟
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// 撮合引擎的时钟：订单创建时间和成交时间都从这里读取，测试和重放可以注入确定的时钟
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now_millis(&self) -> u64;
+}
+
+// 系统时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        now_millis()
+    }
+}
+
+// 手动时钟：从 start 开始，每次读取后前进 step 毫秒，step 为 0 时保持不变
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+    step: u64,
+}
+
+impl ManualClock {
+    pub fn new(start: u64, step: u64) -> Self {
+        Self {
+            now: AtomicU64::new(start),
+            step,
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.now.fetch_add(self.step, Ordering::Relaxed)
+    }
+}
+
+// 订单ID分配器：原子递增，多个线程共享时也不会分配重复的ID；下一个ID随快照保存，
+// 快照之后的下单由预写日志按原顺序重放重新分配，重启后不会重复使用已分配的ID
+#[derive(Debug)]
+pub struct OrderIdAllocator {
+    next: AtomicU64,
+}
+
+impl Default for OrderIdAllocator {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl OrderIdAllocator {
+    pub fn new(next: u64) -> Self {
+        Self {
+            next: AtomicU64::new(next),
+        }
+    }
+
+    pub fn fetch_order_id(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // 下一个将要分配的ID
+    pub fn peek(&self) -> u64 {
+        self.next.load(Ordering::Relaxed)
+    }
+
+    // 保证之后分配的ID不小于 next，用于装入外部订单簿后避开其中已有的订单ID
+    pub fn advance_to(&self, next: u64) {
+        self.next.fetch_max(next, Ordering::Relaxed);
+    }
+}
+
+// 下单参数：价格、数量等保持请求中的字符串，由 place_order 解析和校验
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrderParams<'a> {
+    pub request_id: Uuid,
+    pub symbol_id: i32,
+    pub account_id: i32,
+    pub order_type: i32,
+    pub side: i32,
+    pub time_in_force: i32,
+    pub price: &'a str,
+    pub quantity: &'a str,
+    pub fee_rates: FeeRates,
+    pub trading_rules: TradingRules,
+    pub post_only: bool,
+    pub display_quantity: Option<&'a str>,
+    pub stop_price: Option<&'a str>,
+    pub trigger_direction: i32,
+    pub protection_price: Option<&'a str>,
+    pub expires_at: Option<u64>,
+    pub client_order_id: Option<&'a str>,
+}
+
 // 订单结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
@@ -56,14 +538,42 @@ pub struct Order {
     pub account_id: i32,
     pub order_type: OrderType,
     pub side: OrderSide,
+    pub time_in_force: TimeInForce,
     pub price: Decimal,
     pub quantity: Decimal,
     pub filled_quantity: Decimal,
     pub status: OrderStatus,
     pub created_at: u64, // 时间戳
+    #[serde(default)]
+    pub fee_rates: FeeRates,
+    #[serde(default)]
+    pub post_only: bool, // 只做 maker，下单时会立即成交则整单撤销
+    #[serde(default)]
+    pub display_quantity: Option<Decimal>, // 冰山单每次显示的数量，None 表示全部显示
+    #[serde(default)]
+    pub slice_remaining: Decimal, // 冰山单当前显示部分的剩余数量
+    #[serde(default)]
+    pub stop_price: Option<Decimal>, // 止损单触发价，None 表示普通订单
+    #[serde(default)]
+    pub trigger_direction: TriggerDirection,
+    #[serde(default)]
+    pub protection_price: Option<Decimal>, // 市价单保护价：买单不吃高于该价的卖单，卖单不吃低于该价的买单
+    #[serde(default)]
+    pub expires_at: Option<u64>, // 到期时间（毫秒时间戳），None 表示撤销前一直有效
+    #[serde(default)]
+    pub self_trade_prevented: bool, // 作为 taker 时被自成交保护撤销
+    #[serde(default)]
+    pub circuit_breaker_tripped: bool, // 作为 taker 时触发熔断，剩余部分被撤销
+    #[serde(default)]
+    pub quote_volume: Option<QuoteVolume>, // 市价买单最多花费的 quote：按金额下单时数量为 0，成交完成后等于已成交数量；按报价下单时为报价 * 数量
+    #[serde(default)]
+    pub client_order_id: Option<String>, // 客户端订单ID，同一账户在交易对上的未完成订单中唯一
+    #[serde(default)]
+    pub quantity_step: Decimal, // 下单时交易对的数量步长，按比例分配时按此取整
 }
 
 impl Order {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u64,
         request_id: Uuid,
@@ -71,8 +581,10 @@ impl Order {
         account_id: i32,
         order_type: OrderType,
         side: OrderSide,
+        time_in_force: TimeInForce,
         price: Decimal,
         quantity: Decimal,
+        created_at: u64,
     ) -> Self {
         Self {
             id,
@@ -81,14 +593,25 @@ impl Order {
             account_id,
             order_type,
             side,
+            time_in_force,
             price,
             quantity,
             filled_quantity: Decimal::ZERO,
             status: OrderStatus::Pending,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
+            created_at,
+            fee_rates: FeeRates::default(),
+            post_only: false,
+            display_quantity: None,
+            slice_remaining: Decimal::ZERO,
+            stop_price: None,
+            trigger_direction: TriggerDirection::default(),
+            protection_price: None,
+            expires_at: None,
+            self_trade_prevented: false,
+            circuit_breaker_tripped: false,
+            quote_volume: None,
+            client_order_id: None,
+            quantity_step: Decimal::ZERO,
         }
     }
 
@@ -96,10 +619,53 @@ impl Order {
         self.quantity - self.filled_quantity
     }
 
+    // 订单簿中对外显示的数量，冰山单只显示当前切片
+    pub fn visible_quantity(&self) -> Decimal {
+        match self.display_quantity {
+            Some(_) => self.slice_remaining.min(self.remaining_quantity()),
+            None => self.remaining_quantity(),
+        }
+    }
+
+    // 冰山单从隐藏数量中补充显示部分
+    pub fn refill_display(&mut self) {
+        if let Some(display_quantity) = self.display_quantity {
+            self.slice_remaining = display_quantity.min(self.remaining_quantity());
+        }
+    }
+
+    fn fill(&mut self, quantity: Decimal) {
+        self.filled_quantity += quantity;
+        if self.display_quantity.is_some() {
+            self.slice_remaining = (self.slice_remaining - quantity).max(Decimal::ZERO);
+        }
+    }
+
     pub fn is_filled(&self) -> bool {
         self.filled_quantity >= self.quantity
     }
 
+    // 已成交或已撤销的订单不会再变化
+    pub fn is_terminal(&self) -> bool {
+        self.status == OrderStatus::Filled || self.status == OrderStatus::Cancelled
+    }
+
+    // 指定数量对应的冻结金额：买单冻结 quote (价格 * 数量，加上按最高费率预留的手续费)，
+    // 卖单冻结 base (数量)
+    pub fn freeze_amount(&self, quantity: Decimal) -> Decimal {
+        match self.side {
+            OrderSide::Bid => {
+                let notional = self.price * quantity;
+                notional + (notional * self.fee_rates.reserve_rate()).normalize()
+            }
+            OrderSide::Ask => quantity,
+        }
+    }
+
+    pub fn remaining_freeze_amount(&self) -> Decimal {
+        self.freeze_amount(self.remaining_quantity())
+    }
+
     pub fn can_match(&self, other: &Order) -> bool {
         // 检查基本条件
         if self.symbol_id != other.symbol_id || self.side == other.side {
@@ -133,423 +699,4897 @@ pub struct Trade {
     pub price: Decimal,
     pub quantity: Decimal,
     pub created_at: u64,
+    #[serde(default)]
+    pub taker_fee: Decimal, // taker 手续费
+    #[serde(default)]
+    pub maker_fee: Decimal, // maker 手续费
+    #[serde(default)]
+    pub buyer_fee_currency: FeeCurrency,
+    #[serde(default)]
+    pub taker_side: Option<OrderSide>, // 止损单触发后作为 taker 时订单ID可能小于 maker
+    #[serde(default)]
+    pub sequence: u64, // 成交时订单簿的序号
+    #[serde(default)]
+    pub buyer_fee_reserve: Decimal, // 买单为本笔成交预留的 quote 手续费，结算时解冻
 }
 
-// 价格级别
-#[derive(Debug, Clone)]
-pub struct PriceLevel {
-    pub price: Decimal,
-    pub total_quantity: Decimal,
-    pub orders: VecDeque<Order>,
-}
-
-impl PriceLevel {
-    pub fn new(price: Decimal) -> Self {
-        Self {
-            price,
-            total_quantity: Decimal::ZERO,
-            orders: VecDeque::new(),
+impl Trade {
+    // 旧记录没有 taker_side，按订单ID判断：后到的 taker 订单ID更大
+    pub fn taker_is_buyer(&self) -> bool {
+        match self.taker_side {
+            Some(ref side) => *side == OrderSide::Bid,
+            None => self.buy_order_id > self.sell_order_id,
         }
     }
 
-    pub fn add_order(&mut self, order: Order) {
-        self.total_quantity += order.remaining_quantity();
-        self.orders.push_back(order);
+    // 买方和卖方手续费对应的币种ID (买方, 卖方)
+    pub fn fee_currency_ids(&self, base: i32, quote: i32) -> (i32, i32) {
+        match self.buyer_fee_currency {
+            FeeCurrency::Quote => (quote, quote),
+            FeeCurrency::Base => (base, quote),
+        }
     }
 
-    pub fn remove_order(&mut self, order_id: u64) -> Option<Order> {
-        if let Some(pos) = self.orders.iter().position(|o| o.id == order_id) {
-            let order = self.orders.remove(pos).unwrap();
-            self.total_quantity -= order.remaining_quantity();
-            Some(order)
+    // 买方和卖方各自的手续费 (买方, 卖方)
+    pub fn buyer_seller_fees(&self) -> (Decimal, Decimal) {
+        if self.taker_is_buyer() {
+            (self.taker_fee, self.maker_fee)
         } else {
-            None
+            (self.maker_fee, self.taker_fee)
         }
     }
+}
 
-    pub fn is_empty(&self) -> bool {
-        self.orders.is_empty()
-    }
+// 深度档位 (价格, 数量)
+pub type DepthLevels = Vec<(Decimal, Decimal)>;
 
-    pub fn update_quantity(&mut self) {
-        self.total_quantity = self.orders.iter().map(|o| o.remaining_quantity()).sum();
-    }
+// 假设的市价单按当前订单簿成交的估算结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct FillEstimate {
+    pub filled_quantity: Decimal,
+    pub average_price: Option<Decimal>, // 成交量加权均价，没有成交时为 None
+    pub worst_price: Option<Decimal>,   // 最后一档的成交价
+    pub partial: bool,                  // 对手盘数量不足，只能部分成交
 }
 
-// 订单簿
-#[derive(Debug, Clone)]
-pub struct OrderBook {
-    pub symbol_id: i32,
-    pub bids: BTreeMap<Decimal, PriceLevel>, // 买单，按价格降序
-    pub asks: BTreeMap<Decimal, PriceLevel>, // 卖单，按价格升序
-    pub orders: HashMap<u64, Order>,         // 所有订单的索引
+// 批量撤单时表示所有账户，仅供管理接口使用
+pub const ALL_ACCOUNTS: i32 = 0;
+
+// 订单索引中默认保留的已完成（成交或撤销）订单数量，超出后淘汰最早完成的订单
+pub const DEFAULT_COMPLETED_ORDER_RETENTION: usize = 10_000;
+
+// 客户端订单ID的最大字节数
+pub const MAX_CLIENT_ORDER_ID_LEN: usize = 64;
+
+// 引擎默认保留的最近成交笔数（所有交易对合计），超出后淘汰最早的成交
+pub const DEFAULT_TRADE_RETENTION: usize = 100_000;
+
+// 最近成交的保留策略：最多保留 max_trades 笔；设置 max_age_ms 时同时淘汰
+// 比最新一笔成交早超过该时长的成交，按成交时间而不是当前时间计算，重放结果一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradeRetention {
+    pub max_trades: usize,
+    pub max_age_ms: Option<u64>,
 }
 
-impl OrderBook {
-    pub fn new(symbol_id: i32) -> Self {
+impl Default for TradeRetention {
+    fn default() -> Self {
         Self {
-            symbol_id,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
-            orders: HashMap::new(),
+            max_trades: DEFAULT_TRADE_RETENTION,
+            max_age_ms: None,
         }
     }
+}
 
-    pub fn add_order(&mut self, mut order: Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
+// 熔断：成交价偏离参考价超过 band（小数形式，0.1 = 10%）时撤销 taker 的剩余部分，
+// 并暂停交易对 halt_ms 毫秒；参考价为上一笔订单撮合结束时的最新成交价，熔断时清空，
+// 由恢复后的第一笔订单重新确定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreaker {
+    pub band: Decimal,
+    pub halt_ms: u64,
+}
 
-        // 尝试撮合
-        if order.order_type == OrderType::Market {
-            trades.extend(self.match_market_order(&mut order));
-        } else {
-            trades.extend(self.match_limit_order(&mut order));
-        }
+impl CircuitBreaker {
+    pub fn breaches(&self, reference_price: Decimal, price: Decimal) -> bool {
+        (price - reference_price).abs() > reference_price * self.band
+    }
+}
 
-        // 如果订单还有剩余数量且不是市价单，添加到订单簿
-        if order.remaining_quantity() > Decimal::ZERO && order.order_type == OrderType::Limit {
-            self.add_order_to_book(order.clone());
-        }
+// 订单簿校验和覆盖的每侧档数
+pub const CHECKSUM_LEVELS: usize = 10;
 
-        // 更新订单状态
-        if order.filled_quantity > Decimal::ZERO {
-            if order.is_filled() {
-                order.status = OrderStatus::Filled;
-            } else {
-                order.status = OrderStatus::Partial;
+// 激活后的止损单及其产生的成交
+pub type TriggeredOrder = (Order, Vec<Trade>);
+
+// 24 小时滚动统计按小时分桶，窗口随时间滑动而不是在零点重置
+const STATS_BUCKET_MS: u64 = 60 * 60 * 1000;
+const STATS_WINDOW_MS: u64 = 24 * STATS_BUCKET_MS;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StatsBucket {
+    start: u64, // 桶起始时间（毫秒，按小时对齐）
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    volume: Decimal,
+}
+
+// 最近 24 小时的成交统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeStats {
+    pub volume_24h: Decimal,
+    pub high_24h: Option<Decimal>,
+    pub low_24h: Option<Decimal>,
+    pub open_24h: Option<Decimal>,
+    buckets: VecDeque<StatsBucket>,
+}
+
+impl TradeStats {
+    pub fn record(&mut self, price: Decimal, quantity: Decimal, timestamp: u64) {
+        self.expire(timestamp);
+
+        let start = timestamp - timestamp % STATS_BUCKET_MS;
+        match self.buckets.back_mut() {
+            // 时钟回拨时并入最新的桶
+            Some(bucket) if bucket.start >= start => {
+                bucket.high = bucket.high.max(price);
+                bucket.low = bucket.low.min(price);
+                bucket.volume += quantity;
             }
+            _ => self.buckets.push_back(StatsBucket {
+                start,
+                open: price,
+                high: price,
+                low: price,
+                volume: quantity,
+            }),
         }
 
-        self.orders.insert(order.id, order);
-        trades
+        self.volume_24h += quantity;
+        self.high_24h = Some(self.high_24h.map_or(price, |high| high.max(price)));
+        self.low_24h = Some(self.low_24h.map_or(price, |low| low.min(price)));
+        self.open_24h.get_or_insert(price);
     }
 
-    fn match_market_order(&mut self, order: &mut Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
+    // 丢弃完全滑出窗口的桶，并用剩余的桶重新汇总
+    pub fn expire(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(STATS_WINDOW_MS);
+        let before = self.buckets.len();
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.start + STATS_BUCKET_MS <= cutoff)
+        {
+            self.buckets.pop_front();
+        }
+        if self.buckets.len() == before {
+            return;
+        }
 
-        match order.side {
-            OrderSide::Bid => {
-                // 市价买单，从最优卖价开始撮合
-                while order.remaining_quantity() > Decimal::ZERO && !self.asks.is_empty() {
-                    let best_price = *self.asks.keys().next().unwrap();
-                    if let Some(trade) = self.match_at_price(order, best_price) {
-                        trades.push(trade);
-                    } else {
-                        break;
-                    }
-                }
-            }
-            OrderSide::Ask => {
-                // 市价卖单，从最优买价开始撮合
-                while order.remaining_quantity() > Decimal::ZERO && !self.bids.is_empty() {
-                    let best_price = *self.bids.keys().next_back().unwrap();
-                    if let Some(trade) = self.match_at_price(order, best_price) {
-                        trades.push(trade);
-                    } else {
-                        break;
-                    }
-                }
-            }
-        }
-
-        trades
+        self.volume_24h = self.buckets.iter().map(|bucket| bucket.volume).sum();
+        self.high_24h = self.buckets.iter().map(|bucket| bucket.high).max();
+        self.low_24h = self.buckets.iter().map(|bucket| bucket.low).min();
+        self.open_24h = self.buckets.front().map(|bucket| bucket.open);
     }
+}
 
-    fn match_limit_order(&mut self, order: &mut Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
+// 价格级别内按时间优先排队的订单：按排队序号有序保存，并按订单ID索引序号，
+// 撤单和改单不需要线性扫描队列；序列化为按队列顺序排列的订单数组
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<Order>", into = "Vec<Order>")]
+pub struct OrderQueue {
+    orders: BTreeMap<i64, Order>, // 排队序号 -> 订单，序号越小越靠前
+    slots: HashMap<u64, i64>,     // 订单ID -> 排队序号
+}
 
-        match order.side {
-            OrderSide::Bid => {
-                // 限价买单，撮合所有价格 <= 买单价格的卖单
-                let mut prices_to_match: Vec<Decimal> = self
-                    .asks
-                    .keys()
-                    .filter(|&&price| price <= order.price)
-                    .cloned()
-                    .collect();
-                prices_to_match.sort();
-
-                for price in prices_to_match {
-                    if order.remaining_quantity() <= Decimal::ZERO {
-                        break;
-                    }
-                    if let Some(trade) = self.match_at_price(order, price) {
-                        trades.push(trade);
-                    }
-                }
-            }
-            OrderSide::Ask => {
-                // 限价卖单，撮合所有价格 >= 卖单价格的买单
-                let mut prices_to_match: Vec<Decimal> = self
-                    .bids
-                    .keys()
-                    .filter(|&&price| price >= order.price)
-                    .cloned()
-                    .collect();
-                prices_to_match.sort_by(|a, b| b.cmp(a)); // 降序
-
-                for price in prices_to_match {
-                    if order.remaining_quantity() <= Decimal::ZERO {
-                        break;
-                    }
-                    if let Some(trade) = self.match_at_price(order, price) {
-                        trades.push(trade);
-                    }
-                }
-            }
-        }
+impl OrderQueue {
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
 
-        trades
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
     }
 
-    fn match_at_price(&mut self, taker_order: &mut Order, price: Decimal) -> Option<Trade> {
-        // Generate trade ID first to avoid borrowing issues
-        let trade_id = self.generate_trade_id();
+    pub fn front(&self) -> Option<&Order> {
+        self.orders.values().next()
+    }
 
-        let book = match taker_order.side {
-            OrderSide::Bid => &mut self.asks,
-            OrderSide::Ask => &mut self.bids,
-        };
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Order> {
+        self.orders.values()
+    }
 
-        if let Some(price_level) = book.get_mut(&price) {
-            if let Some(mut maker_order) = price_level.orders.pop_front() {
-                let trade_quantity = taker_order
-                    .remaining_quantity()
-                    .min(maker_order.remaining_quantity());
-
-                // 更新订单成交量
-                taker_order.filled_quantity += trade_quantity;
-                maker_order.filled_quantity += trade_quantity;
-
-                // 创建成交记录
-                let (buy_order_id, sell_order_id, buy_account_id, sell_account_id) =
-                    match taker_order.side {
-                        OrderSide::Bid => (
-                            taker_order.id,
-                            maker_order.id,
-                            taker_order.account_id,
-                            maker_order.account_id,
-                        ),
-                        OrderSide::Ask => (
-                            maker_order.id,
-                            taker_order.id,
-                            maker_order.account_id,
-                            taker_order.account_id,
-                        ),
-                    };
+    pub fn push_back(&mut self, order: Order) {
+        let slot = self.orders.last_key_value().map_or(0, |(&slot, _)| slot + 1);
+        self.insert(slot, order);
+    }
 
-                let trade = Trade {
-                    id: trade_id,
-                    symbol_id: taker_order.symbol_id,
-                    buy_order_id,
-                    sell_order_id,
-                    buy_account_id,
-                    sell_account_id,
-                    price,
-                    quantity: trade_quantity,
-                    created_at: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                };
+    pub fn push_front(&mut self, order: Order) {
+        let slot = self.orders.first_key_value().map_or(0, |(&slot, _)| slot - 1);
+        self.insert(slot, order);
+    }
 
-                // 更新 maker 订单状态
-                if maker_order.is_filled() {
-                    maker_order.status = OrderStatus::Filled;
-                } else {
-                    maker_order.status = OrderStatus::Partial;
-                    // 如果 maker 订单还有剩余，放回订单簿
-                    price_level.orders.push_front(maker_order.clone());
-                }
+    fn insert(&mut self, slot: i64, order: Order) {
+        self.slots.insert(order.id, slot);
+        self.orders.insert(slot, order);
+    }
 
-                // 更新订单索引
-                self.orders.insert(maker_order.id, maker_order);
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let (_, order) = self.orders.pop_first()?;
+        self.slots.remove(&order.id);
+        Some(order)
+    }
 
-                // 更新价格级别
-                price_level.update_quantity();
+    pub fn get_mut(&mut self, order_id: u64) -> Option<&mut Order> {
+        let slot = self.slots.get(&order_id)?;
+        self.orders.get_mut(slot)
+    }
 
-                // 如果价格级别为空，移除它
-                if price_level.is_empty() {
-                    book.remove(&price);
-                }
+    pub fn remove(&mut self, order_id: u64) -> Option<Order> {
+        let slot = self.slots.remove(&order_id)?;
+        self.orders.remove(&slot)
+    }
 
-                Some(trade)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+    // 按队列顺序取出全部订单
+    pub fn drain(&mut self) -> Vec<Order> {
+        self.slots.clear();
+        std::mem::take(&mut self.orders).into_values().collect()
     }
+}
 
-    fn add_order_to_book(&mut self, order: Order) {
-        let book = match order.side {
-            OrderSide::Bid => &mut self.bids,
-            OrderSide::Ask => &mut self.asks,
-        };
+impl Extend<Order> for OrderQueue {
+    fn extend<I: IntoIterator<Item = Order>>(&mut self, orders: I) {
+        for order in orders {
+            self.push_back(order);
+        }
+    }
+}
 
-        book.entry(order.price)
-            .or_insert_with(|| PriceLevel::new(order.price))
-            .add_order(order);
+impl From<Vec<Order>> for OrderQueue {
+    fn from(orders: Vec<Order>) -> Self {
+        let mut queue = Self::default();
+        queue.extend(orders);
+        queue
     }
+}
 
-    pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
-        if let Some(order) = self.orders.get(&order_id).cloned() {
-            let book = match order.side {
-                OrderSide::Bid => &mut self.bids,
-                OrderSide::Ask => &mut self.asks,
-            };
+impl From<OrderQueue> for Vec<Order> {
+    fn from(queue: OrderQueue) -> Self {
+        queue.orders.into_values().collect()
+    }
+}
 
-            if let Some(price_level) = book.get_mut(&order.price) {
-                if let Some(mut cancelled_order) = price_level.remove_order(order_id) {
-                    cancelled_order.status = OrderStatus::Cancelled;
-                    self.orders.insert(order_id, cancelled_order.clone());
+// 按队列位置取订单，需要遍历队列，只用于检查排队顺序
+impl std::ops::Index<usize> for OrderQueue {
+    type Output = Order;
 
-                    // 如果价格级别为空，移除它
-                    if price_level.is_empty() {
-                        book.remove(&order.price);
-                    }
+    fn index(&self, index: usize) -> &Order {
+        self.orders.values().nth(index).expect("order queue index out of range")
+    }
+}
 
-                    return Some(cancelled_order);
-                }
-            }
+// 价格级别
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub price: Decimal,
+    pub total_quantity: Decimal,
+    pub orders: OrderQueue,
+}
 
-            self.orders.remove(&order_id);
+impl PriceLevel {
+    pub fn new(price: Decimal) -> Self {
+        Self {
+            price,
+            total_quantity: Decimal::ZERO,
+            orders: OrderQueue::default(),
         }
-        None
     }
 
-    pub fn get_best_bid(&self) -> Option<Decimal> {
-        self.bids.keys().next_back().cloned()
+    // 新订单总是排到队尾，保证同价位按到达顺序成交
+    pub fn add_order(&mut self, order: Order) {
+        debug_assert_eq!(order.price, self.price, "order price must match its level");
+        self.total_quantity += order.visible_quantity();
+        self.orders.push_back(order);
     }
 
-    pub fn get_best_ask(&self) -> Option<Decimal> {
-        self.asks.keys().next().cloned()
+    pub fn remove_order(&mut self, order_id: u64) -> Option<Order> {
+        let order = self.orders.remove(order_id)?;
+        self.total_quantity -= order.visible_quantity();
+        Some(order)
     }
 
-    pub fn get_spread(&self) -> Option<Decimal> {
-        if let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask()) {
-            Some(best_ask - best_bid)
-        } else {
-            None
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    // total_quantity 只统计显示数量，冰山单的隐藏部分不计入深度
+    pub fn update_quantity(&mut self) {
+        self.total_quantity = self.orders.iter().map(|o| o.visible_quantity()).sum();
+    }
+
+    // 包含冰山单隐藏部分的可成交数量
+    pub fn executable_quantity(&self) -> Decimal {
+        self.orders.iter().map(|o| o.remaining_quantity()).sum()
+    }
+}
+
+// 订单簿上挂单的未成交总量和名义价值（价格 × 剩余数量），含冰山单隐藏部分，不含未激活的止损单
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OpenInterest {
+    pub bid_quantity: Decimal,
+    pub bid_notional: Decimal,
+    pub ask_quantity: Decimal,
+    pub ask_notional: Decimal,
+}
+
+impl OpenInterest {
+    // 按价格和数量变化（减少时为负）调整一侧的合计
+    fn adjust(&mut self, side: &OrderSide, price: Decimal, quantity: Decimal) {
+        let (total_quantity, total_notional) = match side {
+            OrderSide::Bid => (&mut self.bid_quantity, &mut self.bid_notional),
+            OrderSide::Ask => (&mut self.ask_quantity, &mut self.ask_notional),
+        };
+        *total_quantity += quantity;
+        *total_notional += price * quantity;
+    }
+
+    // 从价格级别重新统计，用于快照恢复
+    fn from_levels<'a>(
+        bids: impl Iterator<Item = &'a PriceLevel>,
+        asks: impl Iterator<Item = &'a PriceLevel>,
+    ) -> Self {
+        let mut open_interest = Self::default();
+        for (side, level) in bids
+            .map(|level| (OrderSide::Bid, level))
+            .chain(asks.map(|level| (OrderSide::Ask, level)))
+        {
+            open_interest.adjust(&side, level.price, level.executable_quantity());
         }
+        open_interest
     }
+}
 
-    fn generate_trade_id(&self) -> u64 {
-        // 简单的 trade ID 生成，实际应用中可能需要更复杂的方案
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64
+// 校验和中的价格或数量：去掉末尾的 0 后再去掉小数点和开头的 0，如 1.50 -> 15、0.05 -> 5
+fn checksum_field(value: Decimal) -> String {
+    let digits = value.normalize().to_string().replace('.', "");
+    digits.trim_start_matches('0').to_string()
+}
+
+// CRC32（IEEE 802.3，反射多项式 0xEDB88320），与 zlib 的 crc32 结果一致
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
     }
+    !crc
+}
 
-    pub fn get_market_depth(
-        &self,
-        levels: usize,
-    ) -> (Vec<(Decimal, Decimal)>, Vec<(Decimal, Decimal)>) {
-        let bids: Vec<(Decimal, Decimal)> = self
-            .bids
-            .iter()
-            .rev()
-            .take(levels)
-            .map(|(price, level)| (*price, level.total_quantity))
-            .collect();
+// 按显示数量比例把 taker 数量分配给价格级别中的挂单，每份按交易对的数量步长向下取整；
+// 取整剩下的余数按时间优先每次补一个步长，没有步长时按数量的最小精度取整
+fn pro_rata_allocations(quantity: Decimal, makers: &[Order], quantity_step: Decimal) -> Vec<Decimal> {
+    let weights: Vec<Decimal> = makers.iter().map(|o| o.visible_quantity()).collect();
+    let total: Decimal = weights.iter().sum();
+    if quantity >= total {
+        return weights;
+    }
 
-        let asks: Vec<(Decimal, Decimal)> = self
-            .asks
+    let unit = if quantity_step > Decimal::ZERO {
+        quantity_step
+    } else {
+        let scale = weights
             .iter()
-            .take(levels)
-            .map(|(price, level)| (*price, level.total_quantity))
-            .collect();
+            .map(|weight| weight.scale())
+            .chain([quantity.scale()])
+            .max()
+            .unwrap_or(0);
+        Decimal::new(1, scale)
+    };
+    let mut allocations: Vec<Decimal> = weights
+        .iter()
+        .map(|weight| (quantity * weight / total / unit).floor() * unit)
+        .collect();
 
-        (bids, asks)
+    // 挂单按队列顺序排列，余数从先到的挂单开始补，补满的挂单跳过
+    let mut remainder = quantity - allocations.iter().sum::<Decimal>();
+    while remainder > Decimal::ZERO {
+        let before = remainder;
+        for (allocation, weight) in allocations.iter_mut().zip(&weights) {
+            let extra = unit.min(remainder).min(*weight - *allocation);
+            *allocation += extra;
+            remainder -= extra;
+            if remainder <= Decimal::ZERO {
+                break;
+            }
+        }
+        if remainder == before {
+            break;
+        }
     }
+    allocations
 }
 
-// 撮合引擎
-#[derive(Debug)]
-pub struct MatchingEngine {
-    pub order_books: HashMap<i32, OrderBook>,
-    pub next_order_id: u64,
-    pub trades: Vec<Trade>,
+// 订单簿
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub symbol_id: i32,
+    pub bids: BTreeMap<Decimal, PriceLevel>, // 买单，按价格降序
+    pub asks: BTreeMap<Decimal, PriceLevel>, // 卖单，按价格升序
+    pub orders: HashMap<u64, Order>,         // 所有订单的索引
+    account_orders: HashMap<i32, HashSet<u64>>, // 账户 -> 未完成订单ID
+    client_orders: HashMap<(i32, String), u64>, // (账户, 客户端订单ID) -> 订单ID，随订单一起淘汰
+    pub self_trade_prevention: SelfTradePrevention,
+    pub match_mode: MatchMode,
+    pub fee_config: FeeConfig,
+    pub completed_order_retention: usize,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    completed_orders: VecDeque<u64>, // 已完成订单ID，按完成顺序排列，用于淘汰
+    pub rising_stops: BTreeMap<Decimal, VecDeque<Order>>, // 向上触发的止损单，按触发价升序激活
+    pub falling_stops: BTreeMap<Decimal, VecDeque<Order>>, // 向下触发的止损单，按触发价降序激活
+    expiries: BTreeSet<(u64, u64)>, // (到期时间, 订单ID)，已成交或撤销的订单在到期扫描时跳过
+    last_trade_price: Option<Decimal>,
+    reference_price: Option<Decimal>, // 熔断参考价
+    halted_until: Option<u64>,        // 熔断暂停的结束时间，只用于拒绝新请求，不影响撮合
+    stats: TradeStats,
+    cancelled_makers: Vec<Order>, // 因自成交保护被撤销、待解冻的 maker 订单
+    filled_makers: Vec<Order>, // 有成交的 maker 订单（成交后的状态），待同步排序器的风控计数
+    triggered_orders: Vec<TriggeredOrder>, // 已激活的止损单及其成交，待结算
+    next_trade_id: Arc<AtomicU64>, // 成交ID计数器，由撮合引擎共享
+    clock: Arc<dyn Clock>,         // 由撮合引擎共享
+    sequence: u64, // 订单簿每次变更（挂单、成交、撤单、改单、止损单挂起和激活）加一
+    open_interest: OpenInterest, // 挂单、成交、撤单和改单时增量维护
 }
 
-impl MatchingEngine {
-    pub fn new() -> Self {
+impl OrderBook {
+    pub fn new(symbol_id: i32) -> Self {
         Self {
-            order_books: HashMap::new(),
-            next_order_id: 1,
-            trades: Vec::new(),
+            symbol_id,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            account_orders: HashMap::new(),
+            client_orders: HashMap::new(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            match_mode: MatchMode::default(),
+            fee_config: FeeConfig::default(),
+            completed_order_retention: DEFAULT_COMPLETED_ORDER_RETENTION,
+            circuit_breaker: None,
+            completed_orders: VecDeque::new(),
+            rising_stops: BTreeMap::new(),
+            falling_stops: BTreeMap::new(),
+            expiries: BTreeSet::new(),
+            last_trade_price: None,
+            reference_price: None,
+            halted_until: None,
+            stats: TradeStats::default(),
+            cancelled_makers: Vec::new(),
+            filled_makers: Vec::new(),
+            triggered_orders: Vec::new(),
+            next_trade_id: Arc::new(AtomicU64::new(1)),
+            clock: Arc::new(SystemClock),
+            sequence: 0,
+            open_interest: OpenInterest::default(),
         }
     }
 
-    pub fn place_order(
-        &mut self,
-        request_id: Uuid,
-        symbol_id: i32,
-        account_id: i32,
-        order_type: i32,
-        side: i32,
-        price_str: &str,
-        quantity_str: &str,
-    ) -> Result<(u64, Vec<Trade>), BalanceError> {
-        // 解析价格和数量
-        let quantity = Decimal::from_str_exact(quantity_str)
-            .map_err(|_| BalanceError::InvalidAmount("Invalid quantity format".to_string()))?;
+    // 取出因自成交保护被撤销的 maker 订单
+    pub fn take_cancelled_makers(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.cancelled_makers)
+    }
 
-        let order_type = OrderType::from(order_type);
-        let side = OrderSide::from(side);
+    // 取出有成交的 maker 订单
+    pub fn take_filled_makers(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.filled_makers)
+    }
 
-        let price = if order_type == OrderType::Market {
-            // 市价单使用特殊价格
-            match side {
-                OrderSide::Bid => Decimal::MAX,
-                OrderSide::Ask => Decimal::ZERO,
+    // 取出已激活的止损单及其成交
+    pub fn take_triggered_orders(&mut self) -> Vec<TriggeredOrder> {
+        std::mem::take(&mut self.triggered_orders)
+    }
+
+    pub fn add_order(&mut self, order: Order) -> (Order, Vec<Trade>) {
+        if let Some(client_order_id) = &order.client_order_id {
+            self.client_orders
+                .insert((order.account_id, client_order_id.clone()), order.id);
+        }
+
+        // 止损单先挂起，等待最新成交价穿过触发价
+        if order.stop_price.is_some() {
+            Self::index_account_order(&mut self.account_orders, &order);
+            self.orders.insert(order.id, order.clone());
+            self.index_expiry(&order);
+            self.add_stop_order(order.clone());
+            return (order, Vec::new());
+        }
+
+        let (order, trades) = self.execute_order(order);
+        self.index_expiry(&order);
+        // 触发熔断后不再激活止损单，恢复后由新的成交触发
+        if !trades.is_empty() && !order.circuit_breaker_tripped {
+            self.activate_stop_orders();
+        }
+        self.evict_completed_orders();
+        self.assert_uncrossed();
+        (order, trades)
+    }
+
+    // 挂单（含未激活的止损单）设置了到期时间时加入到期索引
+    fn index_expiry(&mut self, order: &Order) {
+        if let Some(expires_at) = order.expires_at {
+            if !order.is_terminal() {
+                self.expiries.insert((expires_at, order.id));
             }
-        } else {
-            Decimal::from_str_exact(price_str)
-                .map_err(|_| BalanceError::InvalidAmount("Invalid price format".to_string()))?
-        };
+        }
+    }
 
-        // 生成订单ID
-        let order_id = self.next_order_id;
-        self.next_order_id += 1;
+    // 熔断暂停是否仍在生效；撮合本身不读取时钟，预写日志重放时结果与在线处理一致
+    pub fn circuit_breaker_halted(&self) -> bool {
+        self.halted_until
+            .is_some_and(|halted_until| self.clock.now_millis() < halted_until)
+    }
 
-        // 创建订单
-        let order = Order::new(
-            order_id, request_id, symbol_id, account_id, order_type, side, price, quantity,
-        );
+    // 最早的到期时间，没有待到期订单时为 None
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.expiries.first().map(|&(expires_at, _)| expires_at)
+    }
 
-        // 获取或创建订单簿
-        let order_book = self
-            .order_books
-            .entry(symbol_id)
-            .or_insert_with(|| OrderBook::new(symbol_id));
+    // 撤销到期时间不晚于 now 的未完成订单，按到期时间顺序返回
+    pub fn expire_orders(&mut self, now: u64) -> Vec<Order> {
+        let mut expired = Vec::new();
+        while let Some(&(expires_at, order_id)) = self.expiries.first() {
+            if expires_at > now {
+                break;
+            }
+            self.expiries.pop_first();
+            // 到期前已成交或撤销的订单不再处理
+            if self.orders.get(&order_id).is_none_or(Order::is_terminal) {
+                continue;
+            }
+            if let Some(order) = self.cancel_order(order_id) {
+                expired.push(order);
+            }
+        }
+        expired
+    }
 
-        // 执行撮合
-        let trades = order_book.add_order(order);
+    // 已完成订单超过保留数量时，从索引中淘汰最早完成的订单
+    fn evict_completed_orders(&mut self) {
+        while self.completed_orders.len() > self.completed_order_retention {
+            let Some(order_id) = self.completed_orders.pop_front() else {
+                break;
+            };
+            if self.orders.get(&order_id).is_some_and(Order::is_terminal) {
+                self.remove_order(order_id);
+            }
+        }
+    }
 
-        // 保存成交记录
-        for trade in &trades {
-            self.trades.push(trade.clone());
+    // 立即从索引中移除所有已完成订单，返回移除的数量
+    pub fn prune_completed_orders(&mut self) -> usize {
+        let completed: Vec<u64> = self
+            .orders
+            .values()
+            .filter(|order| order.is_terminal())
+            .map(|order| order.id)
+            .collect();
+        for &order_id in &completed {
+            self.remove_order(order_id);
         }
+        self.completed_orders.clear();
+        completed.len()
+    }
 
-        Ok((order_id, trades))
+    // 从订单索引中移除，客户端订单ID仍指向该订单时一并移除
+    fn remove_order(&mut self, order_id: u64) {
+        let Some(order) = self.orders.remove(&order_id) else {
+            return;
+        };
+        if let Some(client_order_id) = order.client_order_id {
+            let key = (order.account_id, client_order_id);
+            if self.client_orders.get(&key) == Some(&order_id) {
+                self.client_orders.remove(&key);
+            }
+        }
     }
 
-    pub fn cancel_order(&mut self, symbol_id: i32, order_id: u64) -> Option<Order> {
-        self.order_books.get_mut(&symbol_id)?.cancel_order(order_id)
+    // 按客户端订单ID查找账户的未完成订单
+    pub fn find_client_order(&self, account_id: i32, client_order_id: &str) -> Option<&Order> {
+        self.client_orders
+            .get(&(account_id, client_order_id.to_string()))
+            .and_then(|order_id| self.orders.get(order_id))
+            .filter(|order| !order.is_terminal())
     }
 
-    pub fn get_order_book(&self, symbol_id: i32) -> Option<&OrderBook> {
-        self.order_books.get(&symbol_id)
+    // 订单状态变化后同步账户索引：未完成的订单加入，已完成的订单移出
+    fn index_account_order(account_orders: &mut HashMap<i32, HashSet<u64>>, order: &Order) {
+        if !order.is_terminal() {
+            account_orders.entry(order.account_id).or_default().insert(order.id);
+        } else if let Some(order_ids) = account_orders.get_mut(&order.account_id) {
+            order_ids.remove(&order.id);
+            if order_ids.is_empty() {
+                account_orders.remove(&order.account_id);
+            }
+        }
     }
 
-    pub fn get_recent_trades(&self, symbol_id: i32, limit: usize) -> Vec<&Trade> {
-        self.trades
+    // 在该交易对上有未完成订单的账户，顺序不固定
+    pub fn open_order_accounts(&self) -> impl Iterator<Item = i32> + '_ {
+        self.account_orders.keys().copied()
+    }
+
+    // 账户在该交易对上的未完成订单（挂单和未激活的止损单），按订单ID排序
+    pub fn open_orders(&self, account_id: i32) -> Vec<&Order> {
+        let mut orders: Vec<&Order> = self
+            .account_orders
+            .get(&account_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| self.orders.get(order_id))
+            .collect();
+        orders.sort_by_key(|order| order.id);
+        orders
+    }
+
+    // 按订单ID查找挂单、保留的已完成订单和未激活的止损单
+    pub fn find_order(&self, order_id: u64) -> Option<&Order> {
+        self.orders.get(&order_id).or_else(|| {
+            self.rising_stops
+                .values()
+                .chain(self.falling_stops.values())
+                .flatten()
+                .find(|order| order.id == order_id)
+        })
+    }
+
+    pub fn last_trade_price(&self) -> Option<Decimal> {
+        self.last_trade_price
+    }
+
+    // 是否还有挂单或未激活的止损单
+    pub fn has_open_orders(&self) -> bool {
+        !self.bids.is_empty()
+            || !self.asks.is_empty()
+            || !self.rising_stops.is_empty()
+            || !self.falling_stops.is_empty()
+    }
+
+    // 订单簿校验和，客户端用同样的规则校验本地重建的订单簿：取卖盘价格升序、买盘价格降序
+    // 各前 CHECKSUM_LEVELS 档（与深度相同，冰山单只计显示部分），先卖盘后买盘，
+    // 每档依次拼接按 checksum_field 格式化的价格和数量，对拼接结果计算 CRC32
+    pub fn checksum(&self) -> u32 {
+        let (bids, asks) = self.get_market_depth(CHECKSUM_LEVELS);
+        let payload: String = asks
             .iter()
-            .rev()
-            .filter(|trade| trade.symbol_id == symbol_id)
-            .take(limit)
-            .collect()
+            .chain(bids.iter())
+            .flat_map(|(price, quantity)| [checksum_field(*price), checksum_field(*quantity)])
+            .collect();
+        crc32(payload.as_bytes())
+    }
+
+    // 最近一次变更的序号，同一交易对内严格递增且不跳号
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // 最近 24 小时的成交统计，查询时先丢弃过期的桶
+    pub fn stats_24h(&mut self, now: u64) -> &TradeStats {
+        self.stats.expire(now);
+        &self.stats
+    }
+
+    fn add_stop_order(&mut self, order: Order) {
+        self.sequence += 1;
+        let stops = match order.trigger_direction {
+            TriggerDirection::Rising => &mut self.rising_stops,
+            TriggerDirection::Falling => &mut self.falling_stops,
+        };
+        stops
+            .entry(order.stop_price.unwrap_or_default())
+            .or_default()
+            .push_back(order);
     }
-}
\ No newline at end of file
+
+    fn remove_stop_order(&mut self, order: &Order) -> Option<Order> {
+        let stops = match order.trigger_direction {
+            TriggerDirection::Rising => &mut self.rising_stops,
+            TriggerDirection::Falling => &mut self.falling_stops,
+        };
+        let stop_price = order.stop_price?;
+        let queue = stops.get_mut(&stop_price)?;
+        let pos = queue.iter().position(|o| o.id == order.id)?;
+        let removed = queue.remove(pos);
+        if queue.is_empty() {
+            stops.remove(&stop_price);
+        }
+        removed
+    }
+
+    // 按触发价顺序激活所有被最新成交价穿过的止损单，激活后的成交可能继续触发更多止损单
+    fn activate_stop_orders(&mut self) {
+        while let Some(mut order) = self.next_triggered_stop() {
+            // 止损市价单转为市价单，按下单报价冻结，撮合时不限价，撮合后恢复报价用于解冻剩余部分；
+            // 止损限价单转为限价单，不能立即成交的部分照常挂单
+            let reference_price = order.price;
+            order.order_type = order.order_type.on_trigger();
+            if order.order_type == OrderType::Market {
+                order.price = match order.side {
+                    OrderSide::Bid => Decimal::MAX,
+                    OrderSide::Ask => Decimal::ZERO,
+                };
+            }
+
+            let (mut order, trades) = self.execute_order(order);
+            order.price = reference_price;
+            self.orders.insert(order.id, order.clone());
+            let tripped = order.circuit_breaker_tripped;
+            self.triggered_orders.push((order, trades));
+            // 激活的止损单触发熔断后，其余止损单留到恢复后再激活
+            if tripped {
+                break;
+            }
+        }
+    }
+
+    fn next_triggered_stop(&mut self) -> Option<Order> {
+        let last_trade_price = self.last_trade_price?;
+
+        let mut entry = match self.rising_stops.first_entry() {
+            Some(entry) if *entry.key() <= last_trade_price => entry,
+            _ => match self.falling_stops.last_entry() {
+                Some(entry) if *entry.key() >= last_trade_price => entry,
+                _ => return None,
+            },
+        };
+        let order = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        self.sequence += 1;
+        order
+    }
+
+    fn execute_order(&mut self, mut order: Order) -> (Order, Vec<Trade>) {
+        let mut trades = Vec::new();
+
+        // FOK 订单在撮合前检查对手盘深度，无法全部成交则整单撤销，不改动订单簿；
+        // 带保护价的市价单只计算保护价以内的深度，按报价冻结的市价买单只计算报价以内的深度
+        let mut fill_limit = order.protection_price.unwrap_or(order.price);
+        if let Some(quote_volume) = order.quote_volume.filter(|_| !order.quantity.is_zero()) {
+            fill_limit = fill_limit.min(quote_volume.volume / order.quantity);
+        }
+        if order.time_in_force == TimeInForce::Fok
+            && self.fok_fill_quantity(&order, fill_limit) < order.remaining_quantity()
+        {
+            order.status = OrderStatus::Cancelled;
+            return (order, trades);
+        }
+
+        // 只做 maker 的限价单会与对手盘最优价成交时整单撤销，不改动订单簿
+        if order.post_only && order.order_type == OrderType::Limit && self.would_cross(&order) {
+            order.status = OrderStatus::Cancelled;
+            return (order, trades);
+        }
+
+        // 尝试撮合
+        if order.order_type == OrderType::Market {
+            trades.extend(self.match_market_order(&mut order));
+        } else {
+            trades.extend(self.match_limit_order(&mut order));
+        }
+
+        // 因自成交保护或熔断被撤销的 taker 不再更新状态，也不进入订单簿
+        if order.status == OrderStatus::Cancelled {
+            Self::index_account_order(&mut self.account_orders, &order);
+            self.orders.insert(order.id, order.clone());
+            self.completed_orders.push_back(order.id);
+            self.update_reference_price(&order);
+            return (order, trades);
+        }
+
+        // 更新订单状态
+        if order.filled_quantity > Decimal::ZERO {
+            if order.is_filled() {
+                order.status = OrderStatus::Filled;
+            } else {
+                order.status = OrderStatus::Partial;
+            }
+        }
+
+        // 如果订单还有剩余数量且不是市价单，添加到订单簿；IOC 订单和市价单的剩余数量直接撤销
+        if order.remaining_quantity() > Decimal::ZERO {
+            match (&order.order_type, &order.time_in_force) {
+                (OrderType::Limit, TimeInForce::Gtc) => {
+                    order.refill_display();
+                    self.add_order_to_book(order.clone())
+                }
+                _ => order.status = OrderStatus::Cancelled,
+            }
+        }
+
+        Self::index_account_order(&mut self.account_orders, &order);
+        self.orders.insert(order.id, order.clone());
+        if order.is_terminal() {
+            self.completed_orders.push_back(order.id);
+        }
+        self.update_reference_price(&order);
+        (order, trades)
+    }
+
+    // 订单撮合结束后以最新成交价作为下一笔订单的熔断参考价，同一笔订单扫过多档时参考价不变；
+    // 熔断时清空，暂停期间不接受新订单，恢复后的第一笔订单重新确定参考价
+    fn update_reference_price(&mut self, order: &Order) {
+        self.reference_price = if order.circuit_breaker_tripped {
+            None
+        } else {
+            self.last_trade_price
+        };
+    }
+
+    // 限价单是否会与对手盘最优价立即成交
+    pub fn would_cross(&self, order: &Order) -> bool {
+        match order.side {
+            OrderSide::Bid => self.get_best_ask().is_some_and(|best_ask| order.price >= best_ask),
+            OrderSide::Ask => self.get_best_bid().is_some_and(|best_bid| order.price <= best_bid),
+        }
+    }
+
+    // 计算在限价内对手盘可成交的总数量（不修改订单簿）
+    pub fn available_fill_quantity(&self, side: &OrderSide, price: Decimal) -> Decimal {
+        match side {
+            // 买单从最优卖价向上累加
+            OrderSide::Bid => self
+                .asks
+                .range(..=price)
+                .map(|(_, level)| level.executable_quantity())
+                .sum(),
+            // 卖单从最优买价向下累加
+            OrderSide::Ask => self
+                .bids
+                .range(price..)
+                .rev()
+                .map(|(_, level)| level.executable_quantity())
+                .sum(),
+        }
+    }
+
+    // FOK 检查用的可成交数量：开启自成交保护时不计入 taker 自己的挂单。撤销 maker 时跳过自己的挂单；
+    // 撤销 taker 时只累加遇到第一笔自己的挂单之前的数量，该价格级别的冰山单补充后会排到自己的挂单之后，只计显示部分
+    fn fok_fill_quantity(&self, order: &Order, price: Decimal) -> Decimal {
+        if self.self_trade_prevention == SelfTradePrevention::None {
+            return self.available_fill_quantity(&order.side, price);
+        }
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match order.side {
+            OrderSide::Bid => Box::new(self.asks.range(..=price).map(|(_, level)| level)),
+            OrderSide::Ask => Box::new(self.bids.range(price..).rev().map(|(_, level)| level)),
+        };
+        let mut quantity = Decimal::ZERO;
+        for level in levels {
+            if self.self_trade_prevention == SelfTradePrevention::CancelMaker {
+                quantity += level
+                    .orders
+                    .iter()
+                    .filter(|maker| maker.account_id != order.account_id)
+                    .map(|maker| maker.remaining_quantity())
+                    .sum::<Decimal>();
+                continue;
+            }
+            let own_position = level
+                .orders
+                .iter()
+                .position(|maker| maker.account_id == order.account_id);
+            match own_position {
+                Some(position) => {
+                    quantity += level
+                        .orders
+                        .iter()
+                        .take(position)
+                        .map(|maker| maker.visible_quantity())
+                        .sum::<Decimal>();
+                    break;
+                }
+                None => quantity += level.executable_quantity(),
+            }
+        }
+        quantity
+    }
+
+    fn match_market_order(&mut self, order: &mut Order) -> Vec<Trade> {
+        match order.quote_volume {
+            Some(quote_volume) if order.quantity.is_zero() => {
+                return self.match_quote_volume(order, quote_volume);
+            }
+            Some(quote_volume) => {
+                // 按报价下单：买满数量或花完冻结金额后停止，剩余数量由调用方撤销
+                let quantity = order.quantity;
+                let trades = self.sweep_within_budget(order, quote_volume, Some(quantity));
+                order.quantity = quantity;
+                return trades;
+            }
+            None => {}
+        }
+        // 市价单撮合到对手盘为空，设置了保护价时对手价越过保护价后停止
+        self.sweep(order, order.protection_price)
+    }
+
+    // 按金额下单的市价买单：结束后订单数量等于已成交数量，没有成交时撤销，未花完的金额由调用方解冻
+    fn match_quote_volume(&mut self, order: &mut Order, quote_volume: QuoteVolume) -> Vec<Trade> {
+        let trades = self.sweep_within_budget(order, quote_volume, None);
+        order.quantity = order.filled_quantity;
+        if order.filled_quantity.is_zero() {
+            order.status = OrderStatus::Cancelled;
+        }
+        trades
+    }
+
+    // 限定金额的市价买单：逐档按剩余金额计算可买数量（不超过 max_quantity），
+    // 直到金额不足以买入下一档的一个步长；撮合时临时调整订单数量，由调用方恢复
+    fn sweep_within_budget(
+        &mut self,
+        order: &mut Order,
+        budget: QuoteVolume,
+        max_quantity: Option<Decimal>,
+    ) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut spent = Decimal::ZERO;
+        while let Some(best_price) = self.get_best_ask() {
+            if order.protection_price.is_some_and(|limit| best_price > limit) {
+                break;
+            }
+            let mut quantity = budget.affordable_quantity(budget.volume - spent, best_price);
+            if let Some(max_quantity) = max_quantity {
+                quantity = quantity.min(max_quantity - order.filled_quantity);
+            }
+            if quantity <= Decimal::ZERO {
+                break;
+            }
+            order.quantity = order.filled_quantity + quantity;
+            let level_trades = self.match_at_price(order, best_price);
+            // 价格级别已被自成交保护清空时继续下一档
+            if level_trades.is_empty() && order.status == OrderStatus::Cancelled {
+                break;
+            }
+            spent += level_trades
+                .iter()
+                .map(|trade| trade.price * trade.quantity)
+                .sum::<Decimal>();
+            trades.extend(level_trades);
+        }
+        trades
+    }
+
+    fn match_limit_order(&mut self, order: &mut Order) -> Vec<Trade> {
+        // 限价单只撮合委托价以内的对手盘
+        self.sweep(order, Some(order.price))
+    }
+
+    // 对手盘最优价：买单取最低卖价，卖单取最高买价
+    fn best_opposite_price(&self, side: &OrderSide) -> Option<Decimal> {
+        match side {
+            OrderSide::Bid => self.get_best_ask(),
+            OrderSide::Ask => self.get_best_bid(),
+        }
+    }
+
+    // 市价单和限价单共用的价格优先撮合：每次取对手盘最优价，直接按 BTreeMap 顺序推进
+    // （卖盘升序、买盘降序），对手价越过 limit 后停止；同一价格按挂单先后成交
+    fn sweep(&mut self, order: &mut Order, limit: Option<Decimal>) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        let mut last_price: Option<Decimal> = None;
+        while order.remaining_quantity() > Decimal::ZERO {
+            let Some(best_price) = self.best_opposite_price(&order.side) else {
+                break;
+            };
+            // 撮合只会移除对手盘挂单，最优价对 taker 只会越来越差：买单成交价不降，卖单不升
+            debug_assert!(last_price.is_none_or(|last_price| match order.side {
+                OrderSide::Bid => best_price >= last_price,
+                OrderSide::Ask => best_price <= last_price,
+            }));
+            last_price = Some(best_price);
+            let beyond_limit = match order.side {
+                OrderSide::Bid => limit.is_some_and(|limit| best_price > limit),
+                OrderSide::Ask => limit.is_some_and(|limit| best_price < limit),
+            };
+            if beyond_limit {
+                break;
+            }
+            let level_trades = self.match_at_price(order, best_price);
+            // 没有成交时：taker 被自成交保护撤销则停止，价格级别已被自成交保护清空则继续下一档
+            if level_trades.is_empty()
+                && (order.status == OrderStatus::Cancelled
+                    || self.best_opposite_price(&order.side) == Some(best_price))
+            {
+                break;
+            }
+            trades.extend(level_trades);
+        }
+        trades
+    }
+
+    fn match_at_price(&mut self, taker_order: &mut Order, price: Decimal) -> Vec<Trade> {
+        // 熔断：成交价偏离参考价过大时不再成交，taker 已成交的部分照常结算，剩余部分撤销
+        if let Some(circuit_breaker) = self.circuit_breaker {
+            if self
+                .reference_price
+                .is_some_and(|reference_price| circuit_breaker.breaches(reference_price, price))
+            {
+                self.halted_until =
+                    Some(self.clock.now_millis().saturating_add(circuit_breaker.halt_ms));
+                taker_order.status = OrderStatus::Cancelled;
+                taker_order.circuit_breaker_tripped = true;
+                return Vec::new();
+            }
+        }
+
+        let book = match taker_order.side {
+            OrderSide::Bid => &mut self.asks,
+            OrderSide::Ask => &mut self.bids,
+        };
+
+        let Some(price_level) = book.get_mut(&price) else {
+            return Vec::new();
+        };
+
+        // 自成交保护：maker 与 taker 属于同一账户时不产生成交
+        while let Some(maker_order) = price_level.orders.front() {
+            if self.self_trade_prevention == SelfTradePrevention::None
+                || maker_order.account_id != taker_order.account_id
+            {
+                break;
+            }
+            if self.self_trade_prevention != SelfTradePrevention::CancelTaker {
+                let mut maker_order = price_level.orders.pop_front().unwrap();
+                maker_order.status = OrderStatus::Cancelled;
+                price_level.update_quantity();
+                self.open_interest.adjust(
+                    &maker_order.side,
+                    maker_order.price,
+                    -maker_order.remaining_quantity(),
+                );
+                self.sequence += 1;
+                Self::index_account_order(&mut self.account_orders, &maker_order);
+                self.orders.insert(maker_order.id, maker_order.clone());
+                self.completed_orders.push_back(maker_order.id);
+                self.cancelled_makers.push(maker_order);
+            }
+            if self.self_trade_prevention != SelfTradePrevention::CancelMaker {
+                taker_order.status = OrderStatus::Cancelled;
+                taker_order.self_trade_prevented = true;
+                break;
+            }
+        }
+
+        if taker_order.status == OrderStatus::Cancelled || price_level.is_empty() {
+            if price_level.is_empty() {
+                book.remove(&price);
+            }
+            return Vec::new();
+        }
+
+        // 按比例分配时一次与整个价格级别成交；价格级别中有同账户挂单时仍逐笔撮合，由自成交保护处理
+        let pro_rata = self.match_mode == MatchMode::ProRata
+            && price_level.orders.len() > 1
+            && price_level
+                .orders
+                .iter()
+                .all(|maker_order| maker_order.account_id != taker_order.account_id);
+        let (makers, allocations): (Vec<Order>, Vec<Decimal>) = if pro_rata {
+            let makers = price_level.orders.drain();
+            let allocations = pro_rata_allocations(
+                taker_order.remaining_quantity(),
+                &makers,
+                taker_order.quantity_step,
+            );
+            (makers, allocations)
+        } else {
+            let maker_order = price_level.orders.pop_front().unwrap();
+            // 冰山单每次只成交当前显示的部分
+            let trade_quantity = taker_order
+                .remaining_quantity()
+                .min(maker_order.visible_quantity());
+            (vec![maker_order], vec![trade_quantity])
+        };
+
+        let mut trades = Vec::new();
+        let mut resting = Vec::new(); // 未成交完、保留原位置的挂单
+        let mut requeued = Vec::new(); // 显示部分成交完、补充后排到队尾的冰山单
+        for (mut maker_order, trade_quantity) in makers.into_iter().zip(allocations) {
+            if trade_quantity > Decimal::ZERO {
+                let frozen_before = maker_order.remaining_freeze_amount();
+                let trade =
+                    self.execute_trade(taker_order, &mut maker_order, price, trade_quantity);
+                self.open_interest.adjust(&maker_order.side, maker_order.price, -trade_quantity);
+                // 结算按成交价从 maker 冻结余额扣除，必须正好是本次成交部分的冻结金额，剩余部分保持冻结
+                let settled = match maker_order.side {
+                    OrderSide::Bid => trade.price * trade.quantity + trade.buyer_fee_reserve,
+                    OrderSide::Ask => trade.quantity,
+                };
+                debug_assert_eq!(frozen_before - maker_order.remaining_freeze_amount(), settled);
+                trades.push(trade);
+
+                // 更新 maker 订单状态
+                if maker_order.is_filled() {
+                    maker_order.status = OrderStatus::Filled;
+                } else {
+                    maker_order.status = OrderStatus::Partial;
+                }
+                self.filled_makers.push(maker_order.clone());
+            }
+
+            if maker_order.is_filled() {
+                self.completed_orders.push_back(maker_order.id);
+            } else if maker_order.visible_quantity() == Decimal::ZERO {
+                // 冰山单显示部分已成交完，从隐藏数量补充后排到价格级别队尾
+                maker_order.refill_display();
+                requeued.push(maker_order.clone());
+            } else {
+                resting.push(maker_order.clone());
+            }
+
+            // 更新订单索引
+            Self::index_account_order(&mut self.account_orders, &maker_order);
+            self.orders.insert(maker_order.id, maker_order);
+        }
+
+        // 放回未成交完的挂单并更新价格级别
+        let book = match taker_order.side {
+            OrderSide::Bid => &mut self.asks,
+            OrderSide::Ask => &mut self.bids,
+        };
+        if let Some(price_level) = book.get_mut(&price) {
+            for maker_order in resting.into_iter().rev() {
+                price_level.orders.push_front(maker_order);
+            }
+            price_level.orders.extend(requeued);
+            price_level.update_quantity();
+
+            // 如果价格级别为空，移除它
+            if price_level.is_empty() {
+                book.remove(&price);
+            }
+        }
+
+        trades
+    }
+
+    // taker 与一笔 maker 按指定数量成交，更新双方成交量、最新成交价和 24 小时统计
+    fn execute_trade(
+        &mut self,
+        taker_order: &mut Order,
+        maker_order: &mut Order,
+        price: Decimal,
+        trade_quantity: Decimal,
+    ) -> Trade {
+        // 更新订单成交量
+        taker_order.fill(trade_quantity);
+        maker_order.fill(trade_quantity);
+
+        // 创建成交记录
+        let (buyer, _) = match taker_order.side {
+            OrderSide::Bid => (&*taker_order, &*maker_order),
+            OrderSide::Ask => (&*maker_order, &*taker_order),
+        };
+        // 限价买单按委托价预留手续费；市价买单按实付金额释放冻结，预留同样按成交价计算，
+        // 没有报价的市价买单下单时没有冻结，也没有预留
+        let reserve_price = if buyer.quote_volume.is_some() {
+            price
+        } else if buyer.price == Decimal::MAX {
+            Decimal::ZERO
+        } else {
+            buyer.price
+        };
+        let buyer_fee_reserve =
+            (reserve_price * trade_quantity * buyer.fee_rates.reserve_rate()).normalize();
+
+        let (buy_order_id, sell_order_id, buy_account_id, sell_account_id) =
+            match taker_order.side {
+                OrderSide::Bid => (
+                    taker_order.id,
+                    maker_order.id,
+                    taker_order.account_id,
+                    maker_order.account_id,
+                ),
+                OrderSide::Ask => (
+                    maker_order.id,
+                    taker_order.id,
+                    maker_order.account_id,
+                    taker_order.account_id,
+                ),
+            };
+
+        let taker_is_buyer = taker_order.side == OrderSide::Bid;
+        self.sequence += 1;
+        let taker_fee = self.fee_config.fee(
+            taker_order.fee_rates.taker,
+            price,
+            trade_quantity,
+            taker_is_buyer,
+        );
+        let maker_fee = self.fee_config.fee(
+            maker_order.fee_rates.maker,
+            price,
+            trade_quantity,
+            !taker_is_buyer,
+        );
+
+        let trade = Trade {
+            id: self.next_trade_id.fetch_add(1, Ordering::Relaxed),
+            symbol_id: taker_order.symbol_id,
+            buy_order_id,
+            sell_order_id,
+            buy_account_id,
+            sell_account_id,
+            price,
+            quantity: trade_quantity,
+            created_at: self.clock.now_millis(),
+            taker_fee,
+            maker_fee,
+            buyer_fee_currency: self.fee_config.buyer_fee_currency,
+            taker_side: Some(taker_order.side.clone()),
+            sequence: self.sequence,
+            buyer_fee_reserve,
+        };
+
+        // 更新最新成交价和 24 小时统计
+        self.last_trade_price = Some(price);
+        self.stats.record(price, trade_quantity, trade.created_at);
+
+        trade
+    }
+
+    // 挂单优先级规则：
+    // 1. 只有限价单会挂入订单簿；市价单、IOC、FOK 的剩余部分直接撤销，激活后的止损限价单已转为限价单
+    // 2. 同一价格级别内严格按进入该级别的先后排队，与原始订单类型、订单ID、created_at 无关
+    //    （止损单激活时ID比后挂的订单小，仍然排在后面；改单重新排队保留 created_at，也排在队尾）
+    fn add_order_to_book(&mut self, order: Order) {
+        debug_assert_eq!(
+            order.order_type,
+            OrderType::Limit,
+            "only limit orders may rest on the book"
+        );
+        self.sequence += 1;
+        self.open_interest.adjust(&order.side, order.price, order.remaining_quantity());
+        let book = match order.side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+
+        book.entry(order.price)
+            .or_insert_with(|| PriceLevel::new(order.price))
+            .add_order(order);
+    }
+
+    // 撤销账户的所有未完成订单（含未激活的止损单），按订单ID顺序返回
+    pub fn cancel_all(&mut self, account_id: i32) -> Vec<Order> {
+        let mut order_ids: Vec<u64> = if account_id == ALL_ACCOUNTS {
+            self.account_orders.values().flatten().copied().collect()
+        } else {
+            self.account_orders
+                .get(&account_id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect()
+        };
+        order_ids.sort();
+
+        order_ids
+            .into_iter()
+            .filter_map(|order_id| self.cancel_order(order_id))
+            .collect()
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
+        if let Some(order) = self.orders.get(&order_id).cloned() {
+            // 尚未激活的止损单直接从触发队列中撤销
+            if let Some(mut cancelled_order) = self.remove_stop_order(&order) {
+                self.sequence += 1;
+                cancelled_order.status = OrderStatus::Cancelled;
+                Self::index_account_order(&mut self.account_orders, &cancelled_order);
+                self.orders.insert(order_id, cancelled_order.clone());
+                self.completed_orders.push_back(order_id);
+                self.evict_completed_orders();
+                return Some(cancelled_order);
+            }
+
+            let book = match order.side {
+                OrderSide::Bid => &mut self.bids,
+                OrderSide::Ask => &mut self.asks,
+            };
+
+            if let Some(price_level) = book.get_mut(&order.price) {
+                if let Some(mut cancelled_order) = price_level.remove_order(order_id) {
+                    self.sequence += 1;
+                    self.open_interest.adjust(
+                        &cancelled_order.side,
+                        cancelled_order.price,
+                        -cancelled_order.remaining_quantity(),
+                    );
+                    cancelled_order.status = OrderStatus::Cancelled;
+                    Self::index_account_order(&mut self.account_orders, &cancelled_order);
+                    self.orders.insert(order_id, cancelled_order.clone());
+                    self.completed_orders.push_back(order_id);
+
+                    // 如果价格级别为空，移除它
+                    if price_level.is_empty() {
+                        book.remove(&order.price);
+                    }
+
+                    self.evict_completed_orders();
+                    return Some(cancelled_order);
+                }
+            }
+
+            self.remove_order(order_id);
+        }
+        None
+    }
+
+    // 修改挂单：同价减量保留时间优先级，改价或增量则移到新价格级别队尾
+    pub fn amend_order(
+        &mut self,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+    ) -> Result<(Order, Order), BalanceError> {
+        let previous = match self.orders.get(&order_id) {
+            Some(order)
+                if order.status == OrderStatus::Pending || order.status == OrderStatus::Partial =>
+            {
+                order.clone()
+            }
+            _ => return Err(BalanceError::OrderNotFound),
+        };
+
+        if new_price <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Price must be positive".to_string(),
+            ));
+        }
+        if new_quantity <= previous.filled_quantity {
+            return Err(BalanceError::InvalidAmount(
+                "New quantity must exceed filled quantity".to_string(),
+            ));
+        }
+
+        // 改价后不允许与对手盘交叉
+        if new_price != previous.price {
+            let crosses = match previous.side {
+                OrderSide::Bid => self.get_best_ask().is_some_and(|ask| new_price >= ask),
+                OrderSide::Ask => self.get_best_bid().is_some_and(|bid| new_price <= bid),
+            };
+            if crosses {
+                return Err(BalanceError::InvalidAmount(
+                    "New price would cross the book".to_string(),
+                ));
+            }
+        }
+
+        let book = match previous.side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+        let price_level = book
+            .get_mut(&previous.price)
+            .ok_or(BalanceError::OrderNotFound)?;
+
+        let amended = if new_price == previous.price && new_quantity <= previous.quantity {
+            // 同价减量：原地修改，保留时间优先级
+            let order = price_level
+                .orders
+                .get_mut(order_id)
+                .ok_or(BalanceError::OrderNotFound)?;
+            order.quantity = new_quantity;
+            let amended = order.clone();
+            price_level.update_quantity();
+            self.sequence += 1;
+            self.open_interest.adjust(
+                &previous.side,
+                previous.price,
+                new_quantity - previous.quantity,
+            );
+            amended
+        } else {
+            // 改价或增量：移出原价格级别，排到新价格级别队尾
+            let mut order = price_level
+                .remove_order(order_id)
+                .ok_or(BalanceError::OrderNotFound)?;
+            if price_level.is_empty() {
+                book.remove(&previous.price);
+            }
+            self.open_interest.adjust(&order.side, order.price, -order.remaining_quantity());
+            order.price = new_price;
+            order.quantity = new_quantity;
+            order.refill_display();
+            self.add_order_to_book(order.clone());
+            order
+        };
+
+        self.orders.insert(order_id, amended.clone());
+        self.assert_uncrossed();
+        Ok((previous, amended))
+    }
+
+    pub fn open_interest(&self) -> OpenInterest {
+        self.open_interest
+    }
+
+    pub fn get_best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().cloned()
+    }
+
+    pub fn get_best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().cloned()
+    }
+
+    // 最优买价及该价位的挂单数量
+    pub fn best_bid_level(&self) -> Option<(Decimal, Decimal)> {
+        self.bids.iter().next_back().map(|(price, level)| (*price, level.total_quantity))
+    }
+
+    // 最优卖价及该价位的挂单数量
+    pub fn best_ask_level(&self) -> Option<(Decimal, Decimal)> {
+        self.asks.iter().next().map(|(price, level)| (*price, level.total_quantity))
+    }
+
+    // 订单簿交叉时没有有意义的价差，返回 None
+    pub fn get_spread(&self) -> Option<Decimal> {
+        if let (Some(best_bid), Some(best_ask)) = (self.get_best_bid(), self.get_best_ask()) {
+            (best_bid < best_ask).then(|| best_ask - best_bid)
+        } else {
+            None
+        }
+    }
+
+    // 价差的基点数，一侧为空时为 None
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        spread_bps(self.get_best_bid()?, self.get_best_ask()?)
+    }
+
+    // 买卖双方都有挂单且买一价不低于卖一价
+    pub fn is_crossed(&self) -> bool {
+        match (self.get_best_bid(), self.get_best_ask()) {
+            (Some(best_bid), Some(best_ask)) => best_bid >= best_ask,
+            _ => false,
+        }
+    }
+
+    // 健康的订单簿不会交叉，交叉说明撮合有缺陷：调试构建直接 panic，发布构建记录错误日志
+    // 只有挂单和改价会增加挂单，在这两处变更之后检查
+    pub fn assert_uncrossed(&self) {
+        if !self.is_crossed() {
+            return;
+        }
+        if cfg!(debug_assertions) {
+            panic!(
+                "OrderBook {} is crossed: best bid {:?} >= best ask {:?}",
+                self.symbol_id,
+                self.get_best_bid(),
+                self.get_best_ask()
+            );
+        }
+        tracing::error!(
+            symbol_id = self.symbol_id,
+            best_bid = ?self.get_best_bid(),
+            best_ask = ?self.get_best_ask(),
+            "Order book is crossed"
+        );
+    }
+
+    pub fn get_market_depth(&self, levels: usize) -> (DepthLevels, DepthLevels) {
+        let bids: DepthLevels = self
+            .bids
+            .iter()
+            .rev()
+            .take(levels)
+            .map(|(price, level)| (*price, level.total_quantity))
+            .collect();
+
+        let asks: DepthLevels = self
+            .asks
+            .iter()
+            .take(levels)
+            .map(|(price, level)| (*price, level.total_quantity))
+            .collect();
+
+        (bids, asks)
+    }
+
+    // 估算市价单的成交：按价格优先遍历对手盘，不修改订单簿
+    // 冰山单的隐藏部分也会成交，按剩余数量计算；不考虑自成交保护
+    pub fn estimate_market_fill(&self, side: &OrderSide, quantity: Decimal) -> FillEstimate {
+        let opposite: Box<dyn Iterator<Item = &PriceLevel>> = match side {
+            OrderSide::Bid => Box::new(self.asks.values()),
+            OrderSide::Ask => Box::new(self.bids.values().rev()),
+        };
+        let mut filled_quantity = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        let mut worst_price = None;
+        for level in opposite {
+            if filled_quantity >= quantity {
+                break;
+            }
+            let available: Decimal = level.orders.iter().map(Order::remaining_quantity).sum();
+            let fill = available.min(quantity - filled_quantity);
+            if fill <= Decimal::ZERO {
+                continue;
+            }
+            filled_quantity += fill;
+            notional += fill * level.price;
+            worst_price = Some(level.price);
+        }
+        FillEstimate {
+            filled_quantity,
+            average_price: (!filled_quantity.is_zero()).then(|| notional / filled_quantity),
+            worst_price,
+            partial: filled_quantity < quantity,
+        }
+    }
+
+    // 按价格粒度合并档位：价格向下取整到 bucket_size 的整数倍，同一档内数量相加
+    // bucket_size 不为正数时按价格逐档返回
+    pub fn get_aggregated_depth(
+        &self,
+        levels: usize,
+        bucket_size: Decimal,
+    ) -> (DepthLevels, DepthLevels) {
+        if bucket_size <= Decimal::ZERO {
+            return self.get_market_depth(levels);
+        }
+        let bids = Self::aggregate_levels(self.bids.iter().rev(), levels, bucket_size);
+        let asks = Self::aggregate_levels(self.asks.iter(), levels, bucket_size);
+        (bids, asks)
+    }
+
+    // 价格有序，同一档的价格相邻
+    fn aggregate_levels<'a>(
+        price_levels: impl Iterator<Item = (&'a Decimal, &'a PriceLevel)>,
+        levels: usize,
+        bucket_size: Decimal,
+    ) -> DepthLevels {
+        let mut aggregated: DepthLevels = Vec::new();
+        for (price, level) in price_levels {
+            let bucket = (*price / bucket_size).floor() * bucket_size;
+            if let Some((last, quantity)) = aggregated.last_mut() {
+                if *last == bucket {
+                    *quantity += level.total_quantity;
+                    continue;
+                }
+            }
+            if aggregated.len() == levels {
+                break;
+            }
+            aggregated.push((bucket, level.total_quantity));
+        }
+        aggregated
+    }
+}
+
+// 快照格式：魔数 + 版本号（小端 u32）+ CBOR 编码的引擎状态
+const SNAPSHOT_MAGIC: &[u8; 4] = b"LNSP";
+const SNAPSHOT_VERSION: u32 = 1;
+
+// 单个订单簿的快照与引擎快照格式相同，魔数不同，避免把两者混用
+const ORDER_BOOK_SNAPSHOT_MAGIC: &[u8; 4] = b"LNOB";
+
+fn encode_snapshot<T: Serialize>(magic: &[u8; 4], value: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    ciborium::into_writer(value, &mut bytes).expect("serialize snapshot");
+    bytes
+}
+
+fn decode_snapshot<T: DeserializeOwned>(magic: &[u8; 4], bytes: &[u8]) -> Result<T, BalanceError> {
+    let header_len = magic.len() + 4;
+    if bytes.len() < header_len || &bytes[..magic.len()] != magic {
+        return Err(BalanceError::InvalidSnapshot("bad magic".to_string()));
+    }
+    let version = u32::from_le_bytes(bytes[magic.len()..header_len].try_into().unwrap());
+    if version != SNAPSHOT_VERSION {
+        return Err(BalanceError::InvalidSnapshot(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    ciborium::from_reader(&bytes[header_len..])
+        .map_err(|e| BalanceError::InvalidSnapshot(e.to_string()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct OrderBookSnapshot {
+    symbol_id: i32,
+    self_trade_prevention: SelfTradePrevention,
+    #[serde(default)]
+    match_mode: MatchMode,
+    #[serde(default)]
+    fee_config: FeeConfig,
+    bids: Vec<PriceLevel>, // 按价格升序保存，恢复时重建 BTreeMap
+    asks: Vec<PriceLevel>,
+    orders: Vec<Order>, // 按订单ID排序，保证快照内容确定
+    #[serde(default)]
+    stop_orders: Vec<Order>, // 未激活的止损单，按订单ID排序
+    #[serde(default)]
+    last_trade_price: Option<Decimal>,
+    #[serde(default)]
+    stats: TradeStats,
+    #[serde(default)]
+    sequence: u64,
+}
+
+impl OrderBookSnapshot {
+    fn capture(symbol_id: i32, order_book: &OrderBook) -> Self {
+        let mut orders: Vec<Order> = order_book.orders.values().cloned().collect();
+        orders.sort_by_key(|order| order.id);
+        let mut stop_orders: Vec<Order> = order_book
+            .rising_stops
+            .values()
+            .chain(order_book.falling_stops.values())
+            .flatten()
+            .cloned()
+            .collect();
+        stop_orders.sort_by_key(|order| order.id);
+        Self {
+            symbol_id,
+            self_trade_prevention: order_book.self_trade_prevention,
+            match_mode: order_book.match_mode,
+            fee_config: order_book.fee_config,
+            bids: order_book.bids.values().cloned().collect(),
+            asks: order_book.asks.values().cloned().collect(),
+            orders,
+            stop_orders,
+            last_trade_price: order_book.last_trade_price,
+            stats: order_book.stats.clone(),
+            sequence: order_book.sequence,
+        }
+    }
+
+    // 下一个订单ID至少为快照中最大的订单ID加一
+    fn next_order_id(&self) -> u64 {
+        self.orders
+            .iter()
+            .chain(&self.stop_orders)
+            .map(|order| order.id + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
+    fn into_order_book(self, next_trade_id: Arc<AtomicU64>) -> OrderBook {
+        let mut order_book = OrderBook::new(self.symbol_id);
+        order_book.self_trade_prevention = self.self_trade_prevention;
+        order_book.match_mode = self.match_mode;
+        order_book.fee_config = self.fee_config;
+        order_book.next_trade_id = next_trade_id;
+        order_book.bids = self.bids.into_iter().map(|level| (level.price, level)).collect();
+        order_book.asks = self.asks.into_iter().map(|level| (level.price, level)).collect();
+        order_book.open_interest =
+            OpenInterest::from_levels(order_book.bids.values(), order_book.asks.values());
+        // 快照中的订单按ID排序，近似按完成顺序重建淘汰队列
+        order_book.completed_orders = self
+            .orders
+            .iter()
+            .filter(|order| order.is_terminal())
+            .map(|order| order.id)
+            .collect();
+        for order in self.orders.iter().chain(&self.stop_orders) {
+            if let Some(client_order_id) = &order.client_order_id {
+                order_book
+                    .client_orders
+                    .insert((order.account_id, client_order_id.clone()), order.id);
+            }
+        }
+        for order in &self.orders {
+            OrderBook::index_account_order(&mut order_book.account_orders, order);
+            order_book.index_expiry(order);
+        }
+        order_book.orders = self.orders.into_iter().map(|order| (order.id, order)).collect();
+        for order in self.stop_orders {
+            order_book.add_stop_order(order);
+        }
+        order_book.last_trade_price = self.last_trade_price;
+        order_book.stats = self.stats;
+        // 重建止损队列不算变更，恢复快照时的序号
+        order_book.sequence = self.sequence;
+        order_book
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EngineSnapshot {
+    order_books: Vec<OrderBookSnapshot>,
+    self_trade_prevention: SelfTradePrevention,
+    #[serde(default)]
+    match_mode: MatchMode,
+    #[serde(default)]
+    fee_config: FeeConfig,
+    next_order_id: u64,
+    next_trade_id: u64,
+    trades: VecDeque<Trade>,
+}
+
+// 撮合引擎
+#[derive(Debug)]
+pub struct MatchingEngine {
+    pub order_books: HashMap<i32, OrderBook>,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub match_mode: MatchMode,
+    pub fee_config: FeeConfig,
+    pub completed_order_retention: usize,
+    pub trade_retention: TradeRetention,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    order_ids: OrderIdAllocator,
+    next_trade_id: Arc<AtomicU64>,
+    clock: Arc<dyn Clock>,
+    pub trades: VecDeque<Trade>, // 按成交顺序保存的最近成交，受 trade_retention 限制
+    management_manager: Option<Arc<ManagementManager>>, // 设置后只为已配置的交易对创建订单簿
+}
+
+impl Default for MatchingEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MatchingEngine {
+    pub fn new() -> Self {
+        Self {
+            order_books: HashMap::new(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            match_mode: MatchMode::default(),
+            fee_config: FeeConfig::default(),
+            completed_order_retention: DEFAULT_COMPLETED_ORDER_RETENTION,
+            trade_retention: TradeRetention::default(),
+            circuit_breaker: None,
+            order_ids: OrderIdAllocator::default(),
+            next_trade_id: Arc::new(AtomicU64::new(1)),
+            clock: Arc::new(SystemClock),
+            trades: VecDeque::new(),
+            management_manager: None,
+        }
+    }
+
+    pub fn place_order(&mut self, params: OrderParams) -> Result<(Order, Vec<Trade>), BalanceError> {
+        let mut order = self.prepare_order(params)?;
+        // 校验通过后才生成订单ID，被拒绝的订单不占用ID
+        order.id = self.fetch_order_id();
+        Ok(self.submit_order(order))
+    }
+
+    // 解析并校验下单参数，返回尚未分配订单ID的订单；不改动订单簿，只校验的下单请求同样使用
+    pub fn prepare_order(&self, params: OrderParams) -> Result<Order, BalanceError> {
+        let OrderParams {
+            request_id,
+            symbol_id,
+            account_id,
+            order_type,
+            side,
+            time_in_force,
+            price: price_str,
+            quantity: quantity_str,
+            fee_rates,
+            trading_rules,
+            post_only,
+            display_quantity: display_quantity_str,
+            stop_price: stop_price_str,
+            trigger_direction,
+            protection_price: protection_price_str,
+            expires_at,
+            client_order_id,
+        } = params;
+        self.check_symbol(symbol_id)?;
+        // 解析价格和数量
+        let quantity = Decimal::from_str_exact(quantity_str)
+            .map_err(|_| BalanceError::InvalidQuantity("Invalid quantity format".to_string()))?;
+
+        // 止损单按激活后的类型校验价格和数量；普通订单带触发价时同样作为止损单
+        let requested_type = OrderType::from(order_type);
+        let order_type = requested_type.on_trigger();
+        let side = OrderSide::from(side);
+        let time_in_force = TimeInForce::from(time_in_force);
+
+        let stop_price = match stop_price_str {
+            Some(stop_price_str) => Some(Decimal::from_str_exact(stop_price_str).map_err(|_| {
+                BalanceError::InvalidPrice("Invalid stop price format".to_string())
+            })?),
+            None => None,
+        };
+        if requested_type.is_stop() && stop_price.is_none() {
+            return Err(BalanceError::InvalidOrder(
+                "Stop orders require a stop price".to_string(),
+            ));
+        }
+
+        // 市价止损单保留下单报价，激活前按报价冻结余额
+        let price = if order_type == OrderType::Market
+            && (stop_price.is_none() || price_str.is_empty())
+        {
+            // 市价单使用特殊价格
+            match side {
+                OrderSide::Bid => Decimal::MAX,
+                OrderSide::Ask => Decimal::ZERO,
+            }
+        } else {
+            Decimal::from_str_exact(price_str)
+                .map_err(|_| BalanceError::InvalidPrice("Invalid price format".to_string()))?
+        };
+
+        // 数量必须为正数；市价单没有委托价（或按 0 传入），只检查限价单的价格
+        if quantity <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount("Quantity must be positive".to_string()));
+        }
+        if order_type == OrderType::Limit && price <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount("Price must be positive".to_string()));
+        }
+
+        // 冰山单的显示数量必须为正数且不超过订单数量
+        let display_quantity = match display_quantity_str {
+            Some(display_quantity_str) => {
+                let display_quantity =
+                    Decimal::from_str_exact(display_quantity_str).map_err(|_| {
+                        BalanceError::InvalidQuantity("Invalid display quantity format".to_string())
+                    })?;
+                if display_quantity <= Decimal::ZERO || display_quantity > quantity {
+                    return Err(BalanceError::InvalidQuantity(
+                        "Display quantity must be positive and not exceed quantity".to_string(),
+                    ));
+                }
+                Some(display_quantity)
+            }
+            None => None,
+        };
+
+        // 保护价只对市价单（包括市价止损单）有意义，限价单的价格本身就是保护
+        let protection_price = match protection_price_str {
+            Some(protection_price_str) => {
+                if order_type != OrderType::Market {
+                    return Err(BalanceError::InvalidOrder(
+                        "Protection price is only supported for market orders".to_string(),
+                    ));
+                }
+                let protection_price =
+                    Decimal::from_str_exact(protection_price_str).map_err(|_| {
+                        BalanceError::InvalidPrice("Invalid protection price format".to_string())
+                    })?;
+                if protection_price <= Decimal::ZERO {
+                    return Err(BalanceError::InvalidPrice(
+                        "Protection price must be positive".to_string(),
+                    ));
+                }
+                Some(protection_price)
+            }
+            None => None,
+        };
+
+        // IOC/FOK 订单不会挂单，到期时间只对 GTC 订单有意义；是否已过期由下单入口按当前时间检查
+        if expires_at.is_some() && time_in_force != TimeInForce::Gtc {
+            return Err(BalanceError::InvalidOrder(
+                "Expiry is only supported for GTC orders".to_string(),
+            ));
+        }
+
+        // 价格、数量不符合交易对精度规则的订单直接拒绝，不占用订单ID
+        trading_rules.check_order(&order_type, price, quantity, stop_price)?;
+        // 市价买单按报价冻结 quote，撮合花费不超过报价 * 数量；没有报价时不限制
+        let quote_volume = match Decimal::from_str_exact(price_str) {
+            Ok(quote) if order_type == OrderType::Market && side == OrderSide::Bid && quote > Decimal::ZERO => {
+                Some(QuoteVolume {
+                    volume: checked_notional(quote, quantity)?,
+                    quantity_step: trading_rules.quantity_step,
+                })
+            }
+            _ => None,
+        };
+        self.check_client_order_id(symbol_id, account_id, client_order_id)?;
+        // 没有报价的市价单按当前订单簿估算成交额
+        if order_type == OrderType::Market && stop_price.is_none() {
+            if let Some(order_book) = self.order_books.get(&symbol_id) {
+                let estimate = order_book.estimate_market_fill(&side, quantity);
+                if let Some(average_price) = estimate.average_price {
+                    trading_rules.check_max_notional(average_price * estimate.filled_quantity)?;
+                }
+            }
+        }
+
+        // 创建订单，止损单激活前保留止损类型
+        let order_type = match stop_price {
+            Some(_) => order_type.with_stop(),
+            None => order_type,
+        };
+        let mut order = Order::new(
+            0,
+            request_id,
+            symbol_id,
+            account_id,
+            order_type,
+            side,
+            time_in_force,
+            price,
+            quantity,
+            self.clock.now_millis(),
+        );
+        order.fee_rates = fee_rates;
+        order.quantity_step = trading_rules.quantity_step;
+        order.post_only = post_only;
+        order.display_quantity = display_quantity;
+        order.stop_price = stop_price;
+        order.trigger_direction = TriggerDirection::from(trigger_direction);
+        order.protection_price = protection_price;
+        order.expires_at = expires_at;
+        order.quote_volume = quote_volume;
+        order.client_order_id = client_order_id.map(str::to_string);
+        Ok(order)
+    }
+
+    // 按金额下单的市价买单：最多花费 volume 个 quote，按 IOC 处理，剩余金额不挂单
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_quote_order(
+        &mut self,
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        volume_str: &str,
+        fee_rates: FeeRates,
+        trading_rules: TradingRules,
+        protection_price_str: Option<&str>,
+        client_order_id: Option<&str>,
+    ) -> Result<(Order, Vec<Trade>), BalanceError> {
+        let mut order = self.prepare_quote_order(
+            request_id,
+            symbol_id,
+            account_id,
+            volume_str,
+            fee_rates,
+            trading_rules,
+            protection_price_str,
+            client_order_id,
+        )?;
+        order.id = self.fetch_order_id();
+        Ok(self.submit_order(order))
+    }
+
+    // 按金额下单的市价买单的解析和校验，返回尚未分配订单ID的订单
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare_quote_order(
+        &self,
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        volume_str: &str,
+        fee_rates: FeeRates,
+        trading_rules: TradingRules,
+        protection_price_str: Option<&str>,
+        client_order_id: Option<&str>,
+    ) -> Result<Order, BalanceError> {
+        self.check_symbol(symbol_id)?;
+        let volume = Decimal::from_str_exact(volume_str)
+            .map_err(|_| BalanceError::InvalidAmount("Invalid volume format".to_string()))?;
+        trading_rules.check_quote_volume(volume)?;
+        self.check_client_order_id(symbol_id, account_id, client_order_id)?;
+        let protection_price = match protection_price_str {
+            Some(protection_price_str) => {
+                let protection_price =
+                    Decimal::from_str_exact(protection_price_str).map_err(|_| {
+                        BalanceError::InvalidPrice("Invalid protection price format".to_string())
+                    })?;
+                if protection_price <= Decimal::ZERO {
+                    return Err(BalanceError::InvalidPrice(
+                        "Protection price must be positive".to_string(),
+                    ));
+                }
+                Some(protection_price)
+            }
+            None => None,
+        };
+
+        let mut order = Order::new(
+            0,
+            request_id,
+            symbol_id,
+            account_id,
+            OrderType::Market,
+            OrderSide::Bid,
+            TimeInForce::Ioc,
+            Decimal::MAX,
+            Decimal::ZERO,
+            self.clock.now_millis(),
+        );
+        order.fee_rates = fee_rates;
+        order.quantity_step = trading_rules.quantity_step;
+        order.protection_price = protection_price;
+        order.quote_volume = Some(QuoteVolume {
+            volume,
+            quantity_step: trading_rules.quantity_step,
+        });
+        order.client_order_id = client_order_id.map(str::to_string);
+        Ok(order)
+    }
+
+    // 只做 maker 的限价单在当前订单簿上会立即成交，下单时会被整单撤销
+    pub fn would_take_liquidity(&self, order: &Order) -> bool {
+        order.post_only
+            && order.order_type == OrderType::Limit
+            && self
+                .order_books
+                .get(&order.symbol_id)
+                .is_some_and(|order_book| order_book.would_cross(order))
+    }
+
+    // 余额层找不到交易对时会拒绝下单，撮合层同样拒绝，避免为未配置的交易对创建订单簿
+    fn check_symbol(&self, symbol_id: i32) -> Result<(), BalanceError> {
+        match &self.management_manager {
+            Some(management) if management.get_symbol(symbol_id).is_none() => {
+                Err(BalanceError::CurrencyNotFound)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 客户端订单ID不能为空或过长，也不能与账户在该交易对上的未完成订单重复
+    fn check_client_order_id(
+        &self,
+        symbol_id: i32,
+        account_id: i32,
+        client_order_id: Option<&str>,
+    ) -> Result<(), BalanceError> {
+        let Some(client_order_id) = client_order_id else {
+            return Ok(());
+        };
+        if client_order_id.is_empty() || client_order_id.len() > MAX_CLIENT_ORDER_ID_LEN {
+            return Err(BalanceError::InvalidOrder(format!(
+                "Client order id must be 1 to {} bytes",
+                MAX_CLIENT_ORDER_ID_LEN
+            )));
+        }
+        if self.find_client_order(symbol_id, account_id, client_order_id).is_some() {
+            return Err(BalanceError::InvalidOrder(format!(
+                "Duplicate client order id {}",
+                client_order_id
+            )));
+        }
+        Ok(())
+    }
+
+    // 把已校验的订单交给交易对的订单簿撮合，订单簿不存在时按引擎配置创建
+    fn submit_order(&mut self, order: Order) -> (Order, Vec<Trade>) {
+        let symbol_id = order.symbol_id;
+
+        // 获取或创建订单簿
+        let self_trade_prevention = self.self_trade_prevention;
+        let match_mode = self.match_mode;
+        let fee_config = self.fee_config;
+        let completed_order_retention = self.completed_order_retention;
+        let circuit_breaker = self.circuit_breaker;
+        let next_trade_id = &self.next_trade_id;
+        let clock = &self.clock;
+        let order_book = self.order_books.entry(symbol_id).or_insert_with(|| {
+            let mut order_book = OrderBook::new(symbol_id);
+            order_book.self_trade_prevention = self_trade_prevention;
+            order_book.match_mode = match_mode;
+            order_book.fee_config = fee_config;
+            order_book.completed_order_retention = completed_order_retention;
+            order_book.circuit_breaker = circuit_breaker;
+            order_book.next_trade_id = next_trade_id.clone();
+            order_book.clock = clock.clone();
+            order_book
+        });
+
+        // 执行撮合，成交可能激活止损单
+        let already_triggered = order_book.triggered_orders.len();
+        let (order, trades) = order_book.add_order(order);
+
+        // 保存成交记录
+        self.trades.extend(trades.iter().cloned());
+        for (_, triggered_trades) in &order_book.triggered_orders[already_triggered..] {
+            self.trades.extend(triggered_trades.iter().cloned());
+        }
+        self.evict_trades();
+
+        (order, trades)
+    }
+
+    pub fn set_trade_retention(&mut self, retention: TradeRetention) {
+        self.trade_retention = retention;
+        self.evict_trades();
+    }
+
+    // 淘汰超出保留笔数或时长的最早成交
+    fn evict_trades(&mut self) {
+        let excess = self.trades.len().saturating_sub(self.trade_retention.max_trades);
+        self.trades.drain(..excess);
+        let latest = self.trades.back();
+        if let (Some(max_age_ms), Some(latest)) = (self.trade_retention.max_age_ms, latest) {
+            let cutoff = latest.created_at.saturating_sub(max_age_ms);
+            while self.trades.front().is_some_and(|trade| trade.created_at < cutoff) {
+                self.trades.pop_front();
+            }
+        }
+    }
+
+    pub fn set_self_trade_prevention(&mut self, mode: SelfTradePrevention) {
+        self.self_trade_prevention = mode;
+        for order_book in self.order_books.values_mut() {
+            order_book.self_trade_prevention = mode;
+        }
+    }
+
+    pub fn set_match_mode(&mut self, mode: MatchMode) {
+        self.match_mode = mode;
+        for order_book in self.order_books.values_mut() {
+            order_book.match_mode = mode;
+        }
+    }
+
+    pub fn set_fee_config(&mut self, fee_config: FeeConfig) {
+        self.fee_config = fee_config;
+        for order_book in self.order_books.values_mut() {
+            order_book.fee_config = fee_config;
+        }
+    }
+
+    pub fn set_rounding_policy(&mut self, rounding: RoundingPolicy) {
+        self.set_fee_config(FeeConfig {
+            rounding,
+            ..self.fee_config
+        });
+    }
+
+    pub fn set_circuit_breaker(&mut self, circuit_breaker: Option<CircuitBreaker>) {
+        self.circuit_breaker = circuit_breaker;
+        for order_book in self.order_books.values_mut() {
+            order_book.circuit_breaker = circuit_breaker;
+        }
+    }
+
+    // 交易对是否处于熔断暂停中，没有订单簿时为 false
+    pub fn circuit_breaker_halted(&self, symbol_id: i32) -> bool {
+        self.order_books
+            .get(&symbol_id)
+            .is_some_and(OrderBook::circuit_breaker_halted)
+    }
+
+    // 下单前按管理器校验交易对是否存在；未设置时不校验
+    pub fn set_management_manager(&mut self, management_manager: Arc<ManagementManager>) {
+        self.management_manager = Some(management_manager);
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for order_book in self.order_books.values_mut() {
+            order_book.clock = clock.clone();
+        }
+        self.clock = clock;
+    }
+
+    // 有订单簿的交易对数量，每个交易对的订单簿相互独立
+    pub fn symbol_count(&self) -> usize {
+        self.order_books.len()
+    }
+
+    pub fn set_completed_order_retention(&mut self, retention: usize) {
+        self.completed_order_retention = retention;
+        for order_book in self.order_books.values_mut() {
+            order_book.completed_order_retention = retention;
+            order_book.evict_completed_orders();
+        }
+    }
+
+    pub fn prune_completed_orders(&mut self) -> usize {
+        self.order_books
+            .values_mut()
+            .map(|order_book| order_book.prune_completed_orders())
+            .sum()
+    }
+
+    pub fn take_cancelled_makers(&mut self, symbol_id: i32) -> Vec<Order> {
+        self.order_books
+            .get_mut(&symbol_id)
+            .map(|order_book| order_book.take_cancelled_makers())
+            .unwrap_or_default()
+    }
+
+    pub fn take_filled_makers(&mut self, symbol_id: i32) -> Vec<Order> {
+        self.order_books
+            .get_mut(&symbol_id)
+            .map(|order_book| order_book.take_filled_makers())
+            .unwrap_or_default()
+    }
+
+    pub fn take_triggered_orders(&mut self, symbol_id: i32) -> Vec<TriggeredOrder> {
+        self.order_books
+            .get_mut(&symbol_id)
+            .map(|order_book| order_book.take_triggered_orders())
+            .unwrap_or_default()
+    }
+
+    pub fn cancel_order(&mut self, symbol_id: i32, order_id: u64) -> Option<Order> {
+        self.order_books.get_mut(&symbol_id)?.cancel_order(order_id)
+    }
+
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.order_books.values().filter_map(OrderBook::next_expiry).min()
+    }
+
+    // 撤销所有交易对上已到期的订单，按交易对ID顺序返回，保证重放结果一致
+    pub fn expire_orders(&mut self, now: u64) -> Vec<Order> {
+        let mut symbol_ids: Vec<_> = self.order_books.keys().copied().collect();
+        symbol_ids.sort();
+        symbol_ids
+            .into_iter()
+            .flat_map(|symbol_id| self.order_books.get_mut(&symbol_id).unwrap().expire_orders(now))
+            .collect()
+    }
+
+    // account_id 为 ALL_ACCOUNTS 时撤销交易对上所有账户的订单
+    pub fn cancel_all(&mut self, symbol_id: i32, account_id: i32) -> Vec<Order> {
+        self.order_books
+            .get_mut(&symbol_id)
+            .map(|order_book| order_book.cancel_all(account_id))
+            .unwrap_or_default()
+    }
+
+    pub fn amend_order(
+        &mut self,
+        symbol_id: i32,
+        order_id: u64,
+        price_str: &str,
+        quantity_str: &str,
+    ) -> Result<(Order, Order), BalanceError> {
+        let price = Decimal::from_str_exact(price_str)
+            .map_err(|_| BalanceError::InvalidAmount("Invalid price format".to_string()))?;
+        let quantity = Decimal::from_str_exact(quantity_str)
+            .map_err(|_| BalanceError::InvalidAmount("Invalid quantity format".to_string()))?;
+
+        self.order_books
+            .get_mut(&symbol_id)
+            .ok_or(BalanceError::OrderNotFound)?
+            .amend_order(order_id, price, quantity)
+    }
+
+    pub fn get_order_book(&self, symbol_id: i32) -> Option<&OrderBook> {
+        self.order_books.get(&symbol_id)
+    }
+
+    pub fn get_open_orders(&self, account_id: i32, symbol_id: i32) -> Vec<&Order> {
+        self.order_books
+            .get(&symbol_id)
+            .map(|order_book| order_book.open_orders(account_id))
+            .unwrap_or_default()
+    }
+
+    pub fn find_client_order(
+        &self,
+        symbol_id: i32,
+        account_id: i32,
+        client_order_id: &str,
+    ) -> Option<&Order> {
+        self.order_books
+            .get(&symbol_id)
+            .and_then(|order_book| order_book.find_client_order(account_id, client_order_id))
+    }
+
+    // 按客户端订单ID撤销账户的未完成订单
+    pub fn cancel_by_client_id(
+        &mut self,
+        symbol_id: i32,
+        account_id: i32,
+        client_order_id: &str,
+    ) -> Option<Order> {
+        let order_id = self.find_client_order(symbol_id, account_id, client_order_id)?.id;
+        self.cancel_order(symbol_id, order_id)
+    }
+
+    // 分配订单ID，只在订单通过校验后调用，拒绝的订单不占用ID
+    pub fn fetch_order_id(&self) -> u64 {
+        self.order_ids.fetch_order_id()
+    }
+
+    pub fn next_order_id(&self) -> u64 {
+        self.order_ids.peek()
+    }
+
+    pub fn get_order(&self, symbol_id: i32, order_id: u64) -> Option<&Order> {
+        self.order_books
+            .get(&symbol_id)
+            .and_then(|order_book| order_book.find_order(order_id))
+    }
+
+    // 订单ID已分配但不在任何订单簿中：已完成的订单超出保留数量被清理，或下单时被拒绝
+    pub fn is_order_pruned(&self, order_id: u64) -> bool {
+        order_id > 0
+            && order_id < self.next_order_id()
+            && self
+                .order_books
+                .values()
+                .all(|order_book| order_book.find_order(order_id).is_none())
+    }
+
+    pub fn get_order_book_mut(&mut self, symbol_id: i32) -> Option<&mut OrderBook> {
+        self.order_books.get_mut(&symbol_id)
+    }
+
+    pub fn get_recent_trades(&self, symbol_id: i32, limit: usize) -> Vec<&Trade> {
+        self.trades
+            .iter()
+            .rev()
+            .filter(|trade| trade.symbol_id == symbol_id)
+            .take(limit)
+            .collect()
+    }
+
+    // 导出单个交易对的订单簿，供新节点通过 load_order_book 装入
+    pub fn dump_order_book(&self, symbol_id: i32) -> Option<Vec<u8>> {
+        let order_book = self.order_books.get(&symbol_id)?;
+        Some(encode_snapshot(
+            ORDER_BOOK_SNAPSHOT_MAGIC,
+            &OrderBookSnapshot::capture(symbol_id, order_book),
+        ))
+    }
+
+    // 解析其他节点导出的订单簿快照，返回其中的未完成订单（挂单和未激活的止损单），按账户和订单ID排序；
+    // 装入前由排序器为这些订单冻结余额
+    pub fn snapshot_open_orders(symbol_id: i32, bytes: &[u8]) -> Result<Vec<Order>, BalanceError> {
+        let snapshot = Self::decode_order_book_snapshot(symbol_id, bytes)?;
+        let order_book = snapshot.into_order_book(Arc::new(AtomicU64::new(0)));
+        let mut account_ids: Vec<i32> = order_book.open_order_accounts().collect();
+        account_ids.sort_unstable();
+        Ok(account_ids
+            .into_iter()
+            .flat_map(|account_id| order_book.open_orders(account_id))
+            .cloned()
+            .collect())
+    }
+
+    // 装入前的检查：快照属于该交易对，其中的订单ID不与本引擎分配过的ID冲突
+    pub fn check_order_book_snapshot(&self, symbol_id: i32, bytes: &[u8]) -> Result<(), BalanceError> {
+        let snapshot = Self::decode_order_book_snapshot(symbol_id, bytes)?;
+        self.check_order_id_collisions(&snapshot)
+    }
+
+    fn decode_order_book_snapshot(
+        symbol_id: i32,
+        bytes: &[u8],
+    ) -> Result<OrderBookSnapshot, BalanceError> {
+        let snapshot: OrderBookSnapshot = decode_snapshot(ORDER_BOOK_SNAPSHOT_MAGIC, bytes)?;
+        if snapshot.symbol_id != symbol_id {
+            return Err(BalanceError::InvalidSnapshot(format!(
+                "snapshot is for symbol {}",
+                snapshot.symbol_id
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    // 小于下一个待分配ID的订单ID已经由本引擎分配过，包括已撤销和被替换的订单
+    fn check_order_id_collisions(&self, snapshot: &OrderBookSnapshot) -> Result<(), BalanceError> {
+        let next_order_id = self.next_order_id();
+        let collision = snapshot
+            .orders
+            .iter()
+            .chain(&snapshot.stop_orders)
+            .find(|order| order.id < next_order_id);
+        match collision {
+            Some(order) => Err(BalanceError::InvalidSnapshot(format!(
+                "order id {} collides with an order id issued by this node",
+                order.id
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // 装入其他节点导出的订单簿；已有挂单或止损单时只有 force 才替换，被替换的订单由调用方先撤销解冻。
+    // 订单ID不能与本引擎分配过的ID冲突，之后分配的ID跳过快照中已有的ID，成交ID继续使用本引擎的计数器
+    pub fn load_order_book(
+        &mut self,
+        symbol_id: i32,
+        bytes: &[u8],
+        force: bool,
+    ) -> Result<(), BalanceError> {
+        let snapshot = Self::decode_order_book_snapshot(symbol_id, bytes)?;
+        if !force
+            && self
+                .order_books
+                .get(&symbol_id)
+                .is_some_and(OrderBook::has_open_orders)
+        {
+            return Err(BalanceError::InvalidSnapshot(
+                "order book has open orders".to_string(),
+            ));
+        }
+        self.check_order_id_collisions(&snapshot)?;
+
+        self.order_ids.advance_to(snapshot.next_order_id());
+        let mut order_book = snapshot.into_order_book(self.next_trade_id.clone());
+        // 自成交保护、撮合方式和手续费配置按本引擎的设置，与新建的订单簿一致；
+        // 结算时按本引擎的舍入方式拆分成交额
+        order_book.self_trade_prevention = self.self_trade_prevention;
+        order_book.match_mode = self.match_mode;
+        order_book.fee_config = self.fee_config;
+        order_book.completed_order_retention = self.completed_order_retention;
+        order_book.circuit_breaker = self.circuit_breaker;
+        order_book.clock = self.clock.clone();
+        self.order_books.insert(symbol_id, order_book);
+        Ok(())
+    }
+
+    // 序列化引擎状态，用于快速恢复（配合预写日志只重放快照之后的记录）
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut symbol_ids: Vec<_> = self.order_books.keys().copied().collect();
+        symbol_ids.sort();
+
+        let order_books = symbol_ids
+            .into_iter()
+            .map(|symbol_id| OrderBookSnapshot::capture(symbol_id, &self.order_books[&symbol_id]))
+            .collect();
+
+        let snapshot = EngineSnapshot {
+            order_books,
+            self_trade_prevention: self.self_trade_prevention,
+            match_mode: self.match_mode,
+            fee_config: self.fee_config,
+            next_order_id: self.next_order_id(),
+            next_trade_id: self.next_trade_id.load(Ordering::Relaxed),
+            trades: self.trades.clone(),
+        };
+
+        encode_snapshot(SNAPSHOT_MAGIC, &snapshot)
+    }
+
+    // 从快照恢复引擎状态
+    pub fn restore(bytes: &[u8]) -> Result<Self, BalanceError> {
+        let snapshot: EngineSnapshot = decode_snapshot(SNAPSHOT_MAGIC, bytes)?;
+
+        let next_trade_id = Arc::new(AtomicU64::new(snapshot.next_trade_id));
+        // 下一个订单ID不小于快照中已有的订单，防止重复分配
+        let next_order_id = snapshot
+            .order_books
+            .iter()
+            .map(OrderBookSnapshot::next_order_id)
+            .fold(snapshot.next_order_id, u64::max);
+        let order_books = snapshot
+            .order_books
+            .into_iter()
+            .map(|book| (book.symbol_id, book.into_order_book(next_trade_id.clone())))
+            .collect();
+
+        Ok(Self {
+            order_books,
+            self_trade_prevention: snapshot.self_trade_prevention,
+            match_mode: snapshot.match_mode,
+            fee_config: snapshot.fee_config,
+            completed_order_retention: DEFAULT_COMPLETED_ORDER_RETENTION,
+            trade_retention: TradeRetention::default(),
+            circuit_breaker: None,
+            order_ids: OrderIdAllocator::new(next_order_id),
+            next_trade_id,
+            clock: Arc::new(SystemClock),
+            trades: snapshot.trades,
+            management_manager: None,
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL_ID: i32 = 1;
+
+    fn place(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        price: &str,
+        quantity: &str,
+    ) -> (Order, Vec<Trade>) {
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: time_in_force as i32,
+                price,
+                quantity,
+                ..OrderParams::default()
+            })
+            .unwrap()
+    }
+
+    fn place_post_only(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        side: OrderSide,
+        price: &str,
+        quantity: &str,
+    ) -> (Order, Vec<Trade>) {
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price,
+                quantity,
+                post_only: true,
+                ..OrderParams::default()
+            })
+            .unwrap()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn place_stop(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        order_type: OrderType,
+        side: OrderSide,
+        price: &str,
+        quantity: &str,
+        stop_price: &str,
+        trigger_direction: TriggerDirection,
+    ) -> (Order, Vec<Trade>) {
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: order_type as i32,
+                side: side as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price,
+                quantity,
+                stop_price: Some(stop_price),
+                trigger_direction: trigger_direction as i32,
+                ..OrderParams::default()
+            })
+            .unwrap()
+    }
+
+    fn place_expiring(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        price: &str,
+        quantity: &str,
+        expires_at: u64,
+    ) -> Result<(Order, Vec<Trade>), BalanceError> {
+        engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id,
+            order_type: OrderType::Limit as i32,
+            side: side as i32,
+            time_in_force: time_in_force as i32,
+            price,
+            quantity,
+            expires_at: Some(expires_at),
+            ..OrderParams::default()
+        })
+    }
+
+    fn place_iceberg(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        side: OrderSide,
+        price: &str,
+        quantity: &str,
+        display_quantity: &str,
+    ) -> (Order, Vec<Trade>) {
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price,
+                quantity,
+                display_quantity: Some(display_quantity),
+                ..OrderParams::default()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ioc_full_fill() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Ioc, "100", "1.0");
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::new(10, 1));
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_ioc_partial_fill() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.4");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Ioc, "100", "1.0");
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, Decimal::new(4, 1));
+        assert_eq!(order.filled_quantity, Decimal::new(4, 1));
+        assert_eq!(order.remaining_quantity(), Decimal::new(6, 1));
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        // 剩余数量不应进入订单簿
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert!(book.asks.is_empty());
+        assert_eq!(book.orders[&order.id].status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_ioc_no_fill() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1.0");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Ioc, "100", "1.0");
+
+        assert!(trades.is_empty());
+        assert_eq!(order.filled_quantity, Decimal::ZERO);
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        // 不应创建新的价格级别，卖盘保持不变
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.asks[&Decimal::new(101, 0)].total_quantity, Decimal::new(10, 1));
+    }
+
+    fn book_snapshot(engine: &MatchingEngine) -> (DepthLevels, DepthLevels, Vec<u64>) {
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let (bids, asks) = book.get_market_depth(usize::MAX);
+        let mut order_ids: Vec<u64> = book.orders.keys().cloned().collect();
+        order_ids.sort();
+        (bids, asks, order_ids)
+    }
+
+    #[test]
+    fn test_fok_full_fill_across_levels() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.3");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "0.3");
+        place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "101", "0.5");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Fok, "101", "1.0");
+
+        assert_eq!(trades.len(), 3);
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks[&Decimal::new(101, 0)].total_quantity, Decimal::new(1, 1));
+    }
+
+    #[test]
+    fn test_fok_rejected_leaves_book_unchanged() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.3");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "101", "0.5");
+        place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "102", "5.0");
+        place(&mut engine, 5, OrderSide::Bid, TimeInForce::Gtc, "99", "1.0");
+        let before = book_snapshot(&engine);
+
+        // 限价内只有 0.8 可成交
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Fok, "101", "1.0");
+
+        assert!(trades.is_empty());
+        assert_eq!(order.filled_quantity, Decimal::ZERO);
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        assert_eq!(book_snapshot(&engine), before);
+        assert!(engine.trades.is_empty());
+    }
+
+    #[test]
+    fn test_fok_market_buy_sums_asks_from_best() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "105", "0.5");
+        let before = book_snapshot(&engine);
+
+        let market_fok = |engine: &mut MatchingEngine, quantity: &str| {
+            engine
+                .place_order(OrderParams {
+                    request_id: Uuid::new_v4(),
+                    symbol_id: SYMBOL_ID,
+                    account_id: 2,
+                    order_type: OrderType::Market as i32,
+                    side: OrderSide::Bid as i32,
+                    time_in_force: TimeInForce::Fok as i32,
+                    price: "",
+                    quantity,
+                    ..OrderParams::default()
+                })
+                .unwrap()
+        };
+
+        // 全部卖盘只有 1.0，无法满足 1.5
+        let (order, trades) = market_fok(&mut engine, "1.5");
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        assert_eq!(book_snapshot(&engine), before);
+
+        let (order, trades) = market_fok(&mut engine, "1.0");
+        assert_eq!(trades.len(), 2);
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().asks.is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_cancel_taker() {
+        let mut engine = MatchingEngine::new();
+        engine.set_self_trade_prevention(SelfTradePrevention::CancelTaker);
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+
+        let (order, trades) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        // maker 保留在订单簿中，taker 不挂单
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks[&Decimal::new(100, 0)].total_quantity, Decimal::new(10, 1));
+        assert!(engine.take_cancelled_makers(SYMBOL_ID).is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_cancel_maker() {
+        let mut engine = MatchingEngine::new();
+        engine.set_self_trade_prevention(SelfTradePrevention::CancelMaker);
+        let (own_maker, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+
+        let (order, trades) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+
+        // 同账户 maker 被撤销，taker 继续与下一笔挂单成交
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].sell_account_id, 3);
+        assert_eq!(trades[0].quantity, Decimal::new(5, 1));
+        assert_eq!(order.status, OrderStatus::Partial);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.asks.is_empty());
+        assert_eq!(book.bids[&Decimal::new(100, 0)].total_quantity, Decimal::new(5, 1));
+        assert_eq!(book.orders[&own_maker.id].status, OrderStatus::Cancelled);
+
+        let cancelled = engine.take_cancelled_makers(SYMBOL_ID);
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].id, own_maker.id);
+    }
+
+    #[test]
+    fn test_self_trade_cancel_both() {
+        let mut engine = MatchingEngine::new();
+        engine.set_self_trade_prevention(SelfTradePrevention::CancelBoth);
+        let (own_maker, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+
+        let (order, trades) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        // 只撤销同账户的 maker，其它挂单不受影响
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+        assert_eq!(book.asks[&Decimal::new(100, 0)].orders.len(), 1);
+        assert_eq!(book.asks[&Decimal::new(100, 0)].orders[0].account_id, 3);
+        assert_eq!(book.orders[&own_maker.id].status, OrderStatus::Cancelled);
+        assert_eq!(engine.take_cancelled_makers(SYMBOL_ID).len(), 1);
+    }
+
+    #[test]
+    fn test_self_trade_allowed_by_default() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+
+        let (order, trades) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert!(engine.take_cancelled_makers(SYMBOL_ID).is_empty());
+    }
+
+    #[test]
+    fn test_fok_depth_check_excludes_own_orders_under_self_trade_prevention() {
+        for mode in [
+            SelfTradePrevention::CancelTaker,
+            SelfTradePrevention::CancelMaker,
+            SelfTradePrevention::CancelBoth,
+        ] {
+            let mut engine = MatchingEngine::new();
+            engine.set_self_trade_prevention(mode);
+            place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+            place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+            let before = book_snapshot(&engine);
+
+            // 卖盘共 1.0，其中 0.5 是自己的挂单，不能用来满足 FOK
+            let (order, trades) =
+                place(&mut engine, 1, OrderSide::Bid, TimeInForce::Fok, "100", "1.0");
+
+            assert!(trades.is_empty(), "{:?}", mode);
+            assert_eq!(order.status, OrderStatus::Cancelled);
+            assert_eq!(book_snapshot(&engine), before);
+            assert!(engine.take_cancelled_makers(SYMBOL_ID).is_empty());
+
+            let (order, trades) =
+                place(&mut engine, 1, OrderSide::Bid, TimeInForce::Fok, "100", "0.5");
+            assert_eq!(trades.len(), 1, "{:?}", mode);
+            assert_eq!(order.status, OrderStatus::Filled);
+        }
+    }
+
+    #[test]
+    fn test_trade_ids_unique_and_increasing() {
+        let mut engine = MatchingEngine::new();
+        for i in 0..500 {
+            let price = format!("{}", 100 + i % 10);
+            place(&mut engine, 10 + i, OrderSide::Ask, TimeInForce::Gtc, &price, "0.01");
+        }
+
+        let (order, trades) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "110", "5.0");
+
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 500);
+        for pair in trades.windows(2) {
+            assert!(pair[0].id < pair[1].id);
+        }
+
+        // 不同交易对共享同一个成交ID序列
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: 2,
+                account_id: 1,
+                order_type: 0,
+                side: 1,
+                time_in_force: 0,
+                price: "100",
+                quantity: "1.0",
+                ..OrderParams::default()
+            })
+            .unwrap();
+        let (_, more_trades) = engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: 2,
+                account_id: 2,
+                order_type: 0,
+                side: 0,
+                time_in_force: 0,
+                price: "100",
+                quantity: "1.0",
+                ..OrderParams::default()
+            })
+            .unwrap();
+        assert_eq!(more_trades.len(), 1);
+        assert!(more_trades[0].id > trades.last().unwrap().id);
+    }
+
+    #[test]
+    fn test_amend_reduce_quantity_keeps_priority() {
+        let mut engine = MatchingEngine::new();
+        let (first, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+
+        let (previous, amended) = engine.amend_order(SYMBOL_ID, first.id, "100", "0.5").unwrap();
+        assert_eq!(previous.quantity, Decimal::new(10, 1));
+        assert_eq!(amended.quantity, Decimal::new(5, 1));
+
+        let level = &engine.get_order_book(SYMBOL_ID).unwrap().asks[&Decimal::new(100, 0)];
+        assert_eq!(level.orders[0].id, first.id);
+        assert_eq!(level.total_quantity, Decimal::new(15, 1));
+
+        // 减量后仍排在队首，先与之成交
+        let (_, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "0.5");
+        assert_eq!(trades[0].sell_order_id, first.id);
+    }
+
+    #[test]
+    fn test_amend_price_or_increase_resets_priority() {
+        let mut engine = MatchingEngine::new();
+        let (first, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        let (second, _) = place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        let (third, _) = place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "101", "1.0");
+
+        // 同价增量：移到队尾
+        engine.amend_order(SYMBOL_ID, first.id, "100", "2.0").unwrap();
+        let level = &engine.get_order_book(SYMBOL_ID).unwrap().asks[&Decimal::new(100, 0)];
+        assert_eq!(level.orders[0].id, second.id);
+        assert_eq!(level.orders[1].id, first.id);
+        assert_eq!(level.total_quantity, Decimal::new(30, 1));
+
+        // 改价：移到新价格级别队尾
+        engine.amend_order(SYMBOL_ID, second.id, "101", "1.0").unwrap();
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let level = &book.asks[&Decimal::new(101, 0)];
+        assert_eq!(level.orders[0].id, third.id);
+        assert_eq!(level.orders[1].id, second.id);
+        assert_eq!(book.orders[&second.id].price, Decimal::new(101, 0));
+        assert_eq!(book.asks[&Decimal::new(100, 0)].orders.len(), 1);
+    }
+
+    #[test]
+    fn test_amend_rejections() {
+        let mut engine = MatchingEngine::new();
+        let (ask, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "0.6");
+        place(&mut engine, 3, OrderSide::Bid, TimeInForce::Gtc, "99", "1.0");
+
+        // 新数量不能低于已成交数量
+        assert!(engine.amend_order(SYMBOL_ID, ask.id, "100", "0.5").is_err());
+        // 改价后不能与对手盘交叉
+        assert!(engine.amend_order(SYMBOL_ID, ask.id, "99", "1.0").is_err());
+        // 已完成或不存在的订单不能修改
+        assert!(engine.amend_order(SYMBOL_ID, 999, "100", "1.0").is_err());
+
+        let (_, amended) = engine.amend_order(SYMBOL_ID, ask.id, "100", "0.8").unwrap();
+        assert_eq!(amended.remaining_quantity(), Decimal::new(2, 1));
+        assert_eq!(amended.status, OrderStatus::Partial);
+    }
+
+    #[test]
+    fn test_expire_orders_cancels_only_open_orders_due() {
+        let mut engine = MatchingEngine::new();
+        let early = place_expiring(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1", 10)
+            .unwrap()
+            .0;
+        let late = place_expiring(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1", 20)
+            .unwrap()
+            .0;
+        let filled = place_expiring(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1", 10)
+            .unwrap()
+            .0;
+        place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "99", "1");
+        assert_eq!(engine.next_expiry(), Some(10));
+
+        // 快照恢复后到期索引保持一致
+        let mut engine = MatchingEngine::restore(&engine.snapshot()).unwrap();
+        assert!(engine.expire_orders(9).is_empty());
+        let expired = engine.expire_orders(10);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, early.id);
+        assert_eq!(expired[0].status, OrderStatus::Cancelled);
+        assert_eq!(engine.next_expiry(), Some(20));
+
+        // 已成交的订单仍保留在索引中，不会被到期扫描撤销
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.orders[&filled.id].status, OrderStatus::Filled);
+        assert_eq!(book.get_best_ask(), Some(Decimal::new(101, 0)));
+
+        assert_eq!(engine.expire_orders(u64::MAX)[0].id, late.id);
+        assert_eq!(engine.next_expiry(), None);
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().asks.is_empty());
+    }
+
+    #[test]
+    fn test_expiry_rejected_for_non_gtc_orders() {
+        let mut engine = MatchingEngine::new();
+        let result =
+            place_expiring(&mut engine, 1, OrderSide::Bid, TimeInForce::Ioc, "100", "1", 10);
+        assert!(result.is_err());
+        assert_eq!(engine.next_order_id(), 1);
+    }
+
+    #[test]
+    fn test_order_id_allocator_is_unique_across_threads() {
+        let allocator = Arc::new(OrderIdAllocator::new(1));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let allocator = allocator.clone();
+                std::thread::spawn(move || {
+                    (0..1000).map(|_| allocator.fetch_order_id()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut ids: Vec<u64> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 8000);
+        assert_eq!(ids.first(), Some(&1));
+        assert_eq!(allocator.peek(), 8001);
+    }
+
+    #[test]
+    fn test_snapshot_restore_round_trip() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1.0");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "100", "2.0");
+        place(&mut engine, 5, OrderSide::Bid, TimeInForce::Gtc, "98", "1.5");
+        place(&mut engine, 6, OrderSide::Bid, TimeInForce::Gtc, "99", "0.5");
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "0.4");
+
+        let mut restored = MatchingEngine::restore(&engine.snapshot()).unwrap();
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let restored_book = restored.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_market_depth(10), restored_book.get_market_depth(10));
+        assert_eq!(book_snapshot(&engine), book_snapshot(&restored));
+        assert_eq!(restored.next_order_id(), engine.next_order_id());
+        assert_eq!(restored.trades.len(), engine.trades.len());
+
+        // 价格级别内的时间优先顺序保持不变
+        let queue: Vec<u64> = restored_book.asks[&Decimal::new(100, 0)]
+            .orders
+            .iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(queue, vec![2, 3]);
+
+        // 恢复后的引擎继续撮合，订单ID和成交ID不重复
+        let (order, trades) = place(&mut restored, 7, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+        assert_eq!(order.id, engine.next_order_id());
+        assert_eq!(trades[0].sell_order_id, 2);
+        assert!(trades[0].id > engine.trades.back().unwrap().id);
+    }
+
+    #[test]
+    fn test_order_book_dump_and_load_round_trip() {
+        let mut source = MatchingEngine::new();
+        place(&mut source, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1.0");
+        place(&mut source, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "2.0");
+        place(&mut source, 5, OrderSide::Bid, TimeInForce::Gtc, "99", "1.5");
+        place(&mut source, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "0.5");
+        let bytes = source.dump_order_book(SYMBOL_ID).unwrap();
+        assert!(source.dump_order_book(SYMBOL_ID + 1).is_none());
+
+        // 引擎快照和其他交易对的订单簿快照都不能装入
+        let mut target = MatchingEngine::new();
+        assert!(target.load_order_book(SYMBOL_ID, &source.snapshot(), false).is_err());
+        assert!(target.load_order_book(SYMBOL_ID + 1, &bytes, false).is_err());
+
+        target.load_order_book(SYMBOL_ID, &bytes, false).unwrap();
+        let book = source.get_order_book(SYMBOL_ID).unwrap();
+        let loaded = target.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_market_depth(10), loaded.get_market_depth(10));
+        assert_eq!(book.open_interest(), loaded.open_interest());
+        assert_eq!(book_snapshot(&source), book_snapshot(&target));
+        assert_eq!(
+            target.get_open_orders(3, SYMBOL_ID).len(),
+            source.get_open_orders(3, SYMBOL_ID).len()
+        );
+
+        // 装入后继续撮合，新订单ID不与装入的订单重复
+        let (order, trades) = place(&mut target, 7, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+        assert_eq!(order.id, source.next_order_id());
+        assert_eq!(trades[0].sell_order_id, 2);
+
+        // 已有挂单时不加 force 拒绝覆盖；快照中的订单ID已由本引擎分配过时 force 也拒绝
+        assert!(target.load_order_book(SYMBOL_ID, &bytes, false).is_err());
+        let loaded = book_snapshot(&target);
+        assert!(target.load_order_book(SYMBOL_ID, &bytes, true).is_err());
+        assert_eq!(book_snapshot(&target), loaded);
+    }
+
+    #[test]
+    fn test_loaded_order_book_uses_engine_settings() {
+        let mut source = MatchingEngine::new();
+        source.set_self_trade_prevention(SelfTradePrevention::CancelBoth);
+        source.set_match_mode(MatchMode::ProRata);
+        source.set_rounding_policy(RoundingPolicy::FloorToExchange);
+        place(&mut source, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1.0");
+        let bytes = source.dump_order_book(SYMBOL_ID).unwrap();
+
+        // 快照中的设置属于导出的节点，装入后按本引擎的设置撮合和计算手续费
+        let mut target = MatchingEngine::new();
+        target.load_order_book(SYMBOL_ID, &bytes, false).unwrap();
+        let loaded = target.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(loaded.self_trade_prevention, target.self_trade_prevention);
+        assert_eq!(loaded.match_mode, MatchMode::Fifo);
+        assert_eq!(loaded.fee_config, target.fee_config);
+        assert_eq!(loaded.fee_config.rounding, RoundingPolicy::HalfEven);
+    }
+
+    #[test]
+    fn test_restore_rejects_invalid_snapshot() {
+        let engine = MatchingEngine::new();
+        let mut bytes = engine.snapshot();
+        assert!(MatchingEngine::restore(&bytes[..3]).is_err());
+
+        bytes[4] = 99;
+        assert!(MatchingEngine::restore(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_market_order_remainder_cancelled() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "0.4");
+
+        let (order, trades) = engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: 2,
+                order_type: OrderType::Market as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price: "110",
+                quantity: "1.0",
+                ..OrderParams::default()
+            })
+            .unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(order.filled_quantity, Decimal::new(4, 1));
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.asks.is_empty());
+        assert!(book.bids.is_empty());
+    }
+
+    // 同一价格级别上的三笔卖单，返回各自的订单ID
+    fn ladder_level(engine: &mut MatchingEngine, quantities: [&str; 3]) -> Vec<u64> {
+        quantities
+            .iter()
+            .enumerate()
+            .map(|(i, quantity)| {
+                let account_id = i as i32 + 1;
+                place(engine, account_id, OrderSide::Ask, TimeInForce::Gtc, "100", quantity).0.id
+            })
+            .collect()
+    }
+
+    // 每笔成交的 (maker 订单ID, 成交数量)
+    fn fills(trades: &[Trade]) -> Vec<(u64, Decimal)> {
+        trades
+            .iter()
+            .map(|trade| (trade.sell_order_id, trade.quantity))
+            .collect()
+    }
+
+    #[test]
+    fn test_price_level_fills_in_arrival_order_regardless_of_order_type() {
+        let mut engine = MatchingEngine::new();
+        let first = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1").0;
+        let (stop, _) = place_stop(
+            &mut engine,
+            2,
+            OrderType::StopLimit,
+            OrderSide::Ask,
+            "100",
+            "1",
+            "95",
+            TriggerDirection::Falling,
+        );
+        let second = place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1").0;
+        // IOC 没有对手盘，直接撤销，不占用队列位置
+        let ioc = place(&mut engine, 4, OrderSide::Ask, TimeInForce::Ioc, "100", "1").0;
+        assert_eq!(ioc.status, OrderStatus::Cancelled);
+
+        // 95 成交后止损单激活，订单ID虽小，仍排在已挂的订单之后
+        place(&mut engine, 5, OrderSide::Bid, TimeInForce::Gtc, "95", "1");
+        place(&mut engine, 6, OrderSide::Ask, TimeInForce::Gtc, "95", "1");
+        let triggered = engine.take_triggered_orders(SYMBOL_ID);
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].0.id, stop.id);
+        assert!(stop.id < second.id);
+
+        let last = place(&mut engine, 7, OrderSide::Ask, TimeInForce::Gtc, "100", "1").0;
+        let (_, trades) = place(&mut engine, 8, OrderSide::Bid, TimeInForce::Gtc, "100", "4");
+        assert_eq!(
+            fills(&trades),
+            vec![
+                (first.id, Decimal::ONE),
+                (second.id, Decimal::ONE),
+                (stop.id, Decimal::ONE),
+                (last.id, Decimal::ONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fifo_and_pro_rata_fill_same_level_differently() {
+        let mut fifo = MatchingEngine::new();
+        let ids = ladder_level(&mut fifo, ["1.0", "3.0", "1.0"]);
+        let (_, trades) = place(&mut fifo, 9, OrderSide::Bid, TimeInForce::Gtc, "100", "2.0");
+        assert_eq!(
+            fills(&trades),
+            vec![(ids[0], Decimal::new(10, 1)), (ids[1], Decimal::new(10, 1))]
+        );
+
+        let mut pro_rata = MatchingEngine::new();
+        pro_rata.set_match_mode(MatchMode::ProRata);
+        let ids = ladder_level(&mut pro_rata, ["1.0", "3.0", "1.0"]);
+        let (order, trades) =
+            place(&mut pro_rata, 9, OrderSide::Bid, TimeInForce::Gtc, "100", "2.0");
+        assert_eq!(
+            fills(&trades),
+            vec![
+                (ids[0], Decimal::new(4, 1)),
+                (ids[1], Decimal::new(12, 1)),
+                (ids[2], Decimal::new(4, 1)),
+            ]
+        );
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        // 部分成交的挂单保持原有队列顺序
+        let book = pro_rata.get_order_book(SYMBOL_ID).unwrap();
+        let level = &book.asks[&Decimal::new(100, 0)];
+        let remaining: Vec<_> = level
+            .orders
+            .iter()
+            .map(|order| (order.id, order.remaining_quantity()))
+            .collect();
+        assert_eq!(
+            remaining,
+            vec![
+                (ids[0], Decimal::new(6, 1)),
+                (ids[1], Decimal::new(18, 1)),
+                (ids[2], Decimal::new(6, 1)),
+            ]
+        );
+        assert_eq!(level.total_quantity, Decimal::new(30, 1));
+        assert_eq!(book.orders[&ids[1]].status, OrderStatus::Partial);
+    }
+
+    #[test]
+    fn test_pro_rata_remainder_goes_by_time_priority() {
+        // 余数按时间优先补给先到的挂单
+        let mut engine = MatchingEngine::new();
+        engine.set_match_mode(MatchMode::ProRata);
+        let ids = ladder_level(&mut engine, ["1.0", "1.0", "1.0"]);
+        let (_, trades) = place(&mut engine, 9, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+        assert_eq!(
+            fills(&trades),
+            vec![
+                (ids[0], Decimal::new(4, 1)),
+                (ids[1], Decimal::new(3, 1)),
+                (ids[2], Decimal::new(3, 1)),
+            ]
+        );
+
+        // 数量较大的挂单不优先，余数每次补一个最小单位
+        let mut engine = MatchingEngine::new();
+        engine.set_match_mode(MatchMode::ProRata);
+        let ids = ladder_level(&mut engine, ["1", "2", "2"]);
+        let (_, trades) = place(&mut engine, 9, OrderSide::Bid, TimeInForce::Gtc, "100", "2");
+        assert_eq!(
+            fills(&trades),
+            vec![(ids[0], Decimal::ONE), (ids[1], Decimal::ONE)]
+        );
+
+        // taker 数量超过整个价格级别时全部成交，剩余部分继续挂单
+        let (order, trades) = place(&mut engine, 9, OrderSide::Bid, TimeInForce::Gtc, "100", "5");
+        assert_eq!(
+            fills(&trades),
+            vec![(ids[1], Decimal::ONE), (ids[2], Decimal::new(2, 0))]
+        );
+        assert_eq!(order.filled_quantity, Decimal::new(3, 0));
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.asks.is_empty());
+        assert_eq!(book.get_best_bid(), Some(Decimal::new(100, 0)));
+    }
+
+    #[test]
+    fn test_pro_rata_rounds_shares_down_to_quantity_step() {
+        let trading_rules = TradingRules {
+            quantity_step: Decimal::new(5, 1),
+            ..TradingRules::default()
+        };
+        let place_on_step = |engine: &mut MatchingEngine, account_id, side: OrderSide, quantity| {
+            engine
+                .place_order(OrderParams {
+                    request_id: Uuid::new_v4(),
+                    symbol_id: SYMBOL_ID,
+                    account_id,
+                    order_type: OrderType::Limit as i32,
+                    side: side as i32,
+                    time_in_force: TimeInForce::Gtc as i32,
+                    price: "100",
+                    quantity,
+                    trading_rules,
+                    ..OrderParams::default()
+                })
+                .unwrap()
+        };
+        let mut engine = MatchingEngine::new();
+        engine.set_match_mode(MatchMode::ProRata);
+        let ids: Vec<u64> = [(1, "1.5"), (2, "1.5"), (3, "2.0")]
+            .into_iter()
+            .map(|(account_id, quantity)| {
+                place_on_step(&mut engine, account_id, OrderSide::Ask, quantity).0.id
+            })
+            .collect();
+
+        // 按比例应得 0.75、0.75、1.0，取整到 0.5 后剩下的一个步长给最先到的挂单
+        let (order, trades) = place_on_step(&mut engine, 9, OrderSide::Bid, "2.5");
+        assert_eq!(
+            fills(&trades),
+            vec![
+                (ids[0], Decimal::new(10, 1)),
+                (ids[1], Decimal::new(5, 1)),
+                (ids[2], Decimal::new(10, 1)),
+            ]
+        );
+        assert_eq!(order.status, OrderStatus::Filled);
+        let trade_step = Decimal::new(5, 1);
+        assert!(trades.iter().all(|trade| (trade.quantity % trade_step).is_zero()));
+    }
+
+    fn place_market(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        quantity: &str,
+        protection_price: Option<&str>,
+    ) -> Result<(Order, Vec<Trade>), BalanceError> {
+        engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id,
+            order_type: OrderType::Market as i32,
+            side: side as i32,
+            time_in_force: time_in_force as i32,
+            price: "",
+            quantity,
+            protection_price,
+            ..OrderParams::default()
+        })
+    }
+
+    #[test]
+    fn test_market_buy_stops_at_protection_price() {
+        let mut engine = MatchingEngine::new();
+        for price in ["100", "101", "102", "103"] {
+            place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, price, "1");
+        }
+
+        let (order, trades) =
+            place_market(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "4", Some("101.5"))
+                .unwrap();
+        let prices: Vec<_> = trades.iter().map(|trade| trade.price).collect();
+        assert_eq!(prices, vec![Decimal::new(100, 0), Decimal::new(101, 0)]);
+        assert_eq!(order.filled_quantity, Decimal::new(2, 0));
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        // 保护价以外的卖单留在订单簿上
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_ask(), Some(Decimal::new(102, 0)));
+        assert!(book.bids.is_empty());
+
+        // 不设保护价时扫完整个订单簿
+        let (order, trades) =
+            place_market(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "4", None)
+                .unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(order.filled_quantity, Decimal::new(2, 0));
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().asks.is_empty());
+    }
+
+    #[test]
+    fn test_market_and_aggressive_limit_fill_in_same_order() {
+        // (成交价, maker 订单ID, 数量) 序列
+        fn fills(trades: &[Trade], side: OrderSide) -> Vec<(Decimal, u64, Decimal)> {
+            trades
+                .iter()
+                .map(|trade| {
+                    let maker_order_id = match side {
+                        OrderSide::Bid => trade.sell_order_id,
+                        OrderSide::Ask => trade.buy_order_id,
+                    };
+                    (trade.price, maker_order_id, trade.quantity)
+                })
+                .collect()
+        }
+
+        for (maker_side, taker_side, limit_price) in [
+            (OrderSide::Ask, OrderSide::Bid, "1000"),
+            (OrderSide::Bid, OrderSide::Ask, "1"),
+        ] {
+            // 两个引擎挂相同的流动性：多个价格级别，同价格有多笔挂单，乱序挂入
+            let book = |engine: &mut MatchingEngine| {
+                for (price, quantity) in
+                    [("102", "1"), ("100", "0.5"), ("101", "2"), ("100", "1"), ("102", "0.3")]
+                {
+                    place(engine, 1, maker_side.clone(), TimeInForce::Gtc, price, quantity);
+                }
+            };
+            let mut market_engine = MatchingEngine::new();
+            book(&mut market_engine);
+            let mut limit_engine = MatchingEngine::new();
+            book(&mut limit_engine);
+
+            let (market_order, market_trades) = place_market(
+                &mut market_engine,
+                2,
+                taker_side.clone(),
+                TimeInForce::Ioc,
+                "4.6",
+                None,
+            )
+            .unwrap();
+            let (limit_order, limit_trades) = place(
+                &mut limit_engine,
+                2,
+                taker_side.clone(),
+                TimeInForce::Ioc,
+                limit_price,
+                "4.6",
+            );
+
+            let market_fills = fills(&market_trades, taker_side.clone());
+            assert_eq!(market_fills, fills(&limit_trades, taker_side.clone()));
+            assert_eq!(market_order.filled_quantity, limit_order.filled_quantity);
+            // 价格优先：最优价先成交；时间优先：同价格先挂的先成交
+            let maker_ids: Vec<_> = market_fills.iter().map(|fill| fill.1).collect();
+            let expected = match taker_side {
+                OrderSide::Bid => vec![2, 4, 3, 1, 5],
+                OrderSide::Ask => vec![1, 5, 3, 2, 4],
+            };
+            assert_eq!(maker_ids, expected);
+        }
+    }
+
+    #[test]
+    fn test_non_positive_quantity_and_limit_price_rejected() {
+        let mut engine = MatchingEngine::new();
+        let submit = |engine: &mut MatchingEngine, order_type: OrderType, price, quantity| {
+            engine.place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: 1,
+                order_type: order_type as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price,
+                quantity,
+                ..OrderParams::default()
+            })
+        };
+
+        for (order_type, price, quantity) in [
+            (OrderType::Limit, "100", "0"),
+            (OrderType::Limit, "100", "-1"),
+            (OrderType::Limit, "-100", "1"),
+            (OrderType::Limit, "0", "1"),
+            (OrderType::Market, "0", "0"),
+        ] {
+            assert!(matches!(
+                submit(&mut engine, order_type, price, quantity),
+                Err(BalanceError::InvalidAmount(_))
+            ));
+        }
+        // 拒绝的订单不占用订单ID，也不进入订单簿
+        assert_eq!(engine.next_order_id(), 1);
+        assert!(engine.get_order_book(SYMBOL_ID).is_none());
+
+        // 市价单的 0 价格是合法的
+        let (order, trades) = submit(&mut engine, OrderType::Market, "0", "1").unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_checksum_covers_top_levels_and_changes_on_mutation() {
+        // CRC32 标准校验值
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "0.05");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100.50", "1.20");
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "99", "2");
+        // 卖盘 1005 12、101 5，买盘 99 2，与 zlib.crc32(b"1005121015992") 一致
+        let checksum = engine.get_order_book(SYMBOL_ID).unwrap().checksum();
+        assert_eq!(checksum, 0x2593_F416);
+
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "99", "0.5");
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.checksum(), 0x7040_0D0B);
+
+        // 只计算每侧前 CHECKSUM_LEVELS 档
+        let before = book.checksum();
+        for level in 0..CHECKSUM_LEVELS {
+            let price = (90 - level).to_string();
+            place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, &price, "1");
+        }
+        let before_deep = engine.get_order_book(SYMBOL_ID).unwrap().checksum();
+        assert_ne!(before_deep, before);
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "50", "1");
+        assert_eq!(engine.get_order_book(SYMBOL_ID).unwrap().checksum(), before_deep);
+    }
+
+    #[test]
+    fn test_market_sell_stops_at_protection_price() {
+        let mut engine = MatchingEngine::new();
+        for price in ["100", "99", "98"] {
+            place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, price, "1");
+        }
+
+        let (order, trades) =
+            place_market(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "3", Some("99"))
+                .unwrap();
+        let prices: Vec<_> = trades.iter().map(|trade| trade.price).collect();
+        assert_eq!(prices, vec![Decimal::new(100, 0), Decimal::new(99, 0)]);
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::new(98, 0)));
+
+        // FOK 只计算保护价以内的深度，不够时整单撤销
+        let (order, trades) =
+            place_market(&mut engine, 2, OrderSide::Ask, TimeInForce::Fok, "1", Some("99"))
+                .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::new(98, 0)));
+    }
+
+    #[test]
+    fn test_quote_volume_market_buy_spends_up_to_volume() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "200", "5");
+        let trading_rules = TradingRules {
+            quantity_step: Decimal::from_str_exact("0.01").unwrap(),
+            min_notional: Decimal::from(10),
+            ..TradingRules::default()
+        };
+
+        // 花 100 买完第一档，剩余 255 在第二档按步长向下取整买 1.27，花费 254
+        let (order, trades) = engine
+            .place_quote_order(
+                Uuid::new_v4(),
+                SYMBOL_ID,
+                2,
+                "355",
+                FeeRates::default(),
+                trading_rules,
+                None,
+                None,
+            )
+            .unwrap();
+        let spent: Decimal = trades.iter().map(|trade| trade.price * trade.quantity).sum();
+        assert_eq!(spent, Decimal::from(354));
+        assert_eq!(order.filled_quantity, Decimal::from_str_exact("2.27").unwrap());
+        assert_eq!(order.quantity, order.filled_quantity);
+        assert_eq!(order.status, OrderStatus::Filled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_market_depth(1).1, vec![(
+            Decimal::from(200),
+            Decimal::from_str_exact("3.73").unwrap()
+        )]);
+        assert!(book.bids.is_empty());
+
+        // 金额不足一个步长时不成交，整单撤销；低于最小成交额直接拒绝
+        let (order, trades) = engine
+            .place_quote_order(
+                Uuid::new_v4(),
+                SYMBOL_ID,
+                2,
+                "150",
+                FeeRates::default(),
+                TradingRules {
+                    quantity_step: Decimal::ONE,
+                    ..TradingRules::default()
+                },
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+        assert!(matches!(
+            engine.place_quote_order(
+                Uuid::new_v4(),
+                SYMBOL_ID,
+                2,
+                "5",
+                FeeRates::default(),
+                trading_rules,
+                None,
+                None,
+            ),
+            Err(BalanceError::BelowMinNotional(_))
+        ));
+    }
+
+    #[test]
+    fn test_protection_price_rejected_for_limit_orders() {
+        let mut engine = MatchingEngine::new();
+        let result = engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: 1,
+            order_type: OrderType::Limit as i32,
+            side: OrderSide::Bid as i32,
+            time_in_force: TimeInForce::Gtc as i32,
+            price: "100",
+            quantity: "1",
+            protection_price: Some("101"),
+            ..OrderParams::default()
+        });
+        assert!(result.is_err());
+        let result =
+            place_market(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "1", Some("0"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_trade_fees_rounded_to_configured_precision() {
+        let mut engine = MatchingEngine::new();
+        engine.set_fee_config(FeeConfig {
+            precision: 2,
+            buyer_fee_currency: FeeCurrency::Base,
+            ..FeeConfig::default()
+        });
+
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: 1,
+                order_type: OrderType::Limit as i32,
+                side: OrderSide::Ask as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price: "33.33",
+                quantity: "3",
+                fee_rates: FeeRates::from_ppm(0, 1000, 0),
+                ..OrderParams::default()
+            })
+            .unwrap();
+        let (_, trades) = engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: 2,
+                order_type: OrderType::Limit as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price: "33.33",
+                quantity: "3",
+                fee_rates: FeeRates::from_ppm(2000, -500, 0),
+                ..OrderParams::default()
+            })
+            .unwrap();
+
+        // 卖方 maker：0.1% * 99.99 USDT = 0.09999 -> 0.10
+        assert_eq!(trades[0].maker_fee, Decimal::new(10, 2));
+        // 买方 taker 按 base 收取：0.2% * 3 BTC = 0.006 -> 0.01
+        assert_eq!(trades[0].taker_fee, Decimal::new(1, 2));
+        assert_eq!(trades[0].buyer_seller_fees(), (Decimal::new(1, 2), Decimal::new(10, 2)));
+        assert_eq!(trades[0].fee_currency_ids(1, 2), (1, 2));
+    }
+
+    #[test]
+    fn test_zero_fee_rates_produce_zero_fees() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+        let (_, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+
+        assert_eq!(trades[0].taker_fee, Decimal::ZERO);
+        assert_eq!(trades[0].maker_fee, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_post_only_bid_below_best_ask_rests() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+
+        let (order, trades) = place_post_only(&mut engine, 2, OrderSide::Bid, "100", "1");
+
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Pending);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::from(100)));
+        assert_eq!(book.get_best_ask(), Some(Decimal::from(101)));
+    }
+
+    #[test]
+    fn test_post_only_bid_crossing_best_ask_rejected() {
+        for price in ["101", "102"] {
+            let mut engine = MatchingEngine::new();
+            place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+
+            let (order, trades) = place_post_only(&mut engine, 2, OrderSide::Bid, price, "1");
+
+            assert!(trades.is_empty());
+            assert_eq!(order.status, OrderStatus::Cancelled);
+            assert_eq!(order.filled_quantity, Decimal::ZERO);
+            // 订单簿保持不变
+            let book = engine.get_order_book(SYMBOL_ID).unwrap();
+            assert!(book.bids.is_empty());
+            assert_eq!(book.asks[&Decimal::from(101)].total_quantity, Decimal::ONE);
+            assert!(!book.orders.contains_key(&order.id));
+        }
+    }
+
+    #[test]
+    fn test_post_only_ask_crossing_best_bid_rejected() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+
+        let (order, trades) = place_post_only(&mut engine, 2, OrderSide::Ask, "100", "1");
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::Cancelled);
+
+        let (order, _) = place_post_only(&mut engine, 2, OrderSide::Ask, "100.5", "1");
+        assert_eq!(order.status, OrderStatus::Pending);
+    }
+
+    #[test]
+    fn test_iceberg_depth_shows_only_display_slice() {
+        let mut engine = MatchingEngine::new();
+        let (order, _) = place_iceberg(&mut engine, 1, OrderSide::Ask, "100", "10", "2");
+        assert_eq!(order.status, OrderStatus::Pending);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let (_, asks) = book.get_market_depth(10);
+        assert_eq!(asks, vec![(Decimal::from(100), Decimal::from(2))]);
+        // FOK 按包含隐藏部分的数量检查深度
+        assert_eq!(
+            book.available_fill_quantity(&OrderSide::Bid, Decimal::from(100)),
+            Decimal::from(10)
+        );
+    }
+
+    #[test]
+    fn test_iceberg_sweeping_taker_fills_hidden_quantity() {
+        let mut engine = MatchingEngine::new();
+        let (iceberg, _) = place_iceberg(&mut engine, 1, OrderSide::Ask, "100", "10", "2");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "7");
+
+        assert_eq!(order.status, OrderStatus::Filled);
+        let quantities: Vec<Decimal> = trades.iter().map(|t| t.quantity).collect();
+        assert_eq!(
+            quantities,
+            vec![Decimal::from(2), Decimal::from(2), Decimal::from(2), Decimal::ONE]
+        );
+        assert!(trades.iter().all(|t| t.sell_order_id == iceberg.id));
+
+        // 剩余 3 个仍在簿中，只显示当前切片剩余的 1 个
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let maker = &book.orders[&iceberg.id];
+        assert_eq!(maker.status, OrderStatus::Partial);
+        assert_eq!(maker.remaining_quantity(), Decimal::from(3));
+        assert_eq!(book.asks[&Decimal::from(100)].total_quantity, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_iceberg_refill_requeues_behind_resting_orders() {
+        let mut engine = MatchingEngine::new();
+        let (iceberg, _) = place_iceberg(&mut engine, 1, OrderSide::Ask, "100", "3", "1");
+        let (resting, _) = place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+
+        let (_, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        assert_eq!(trades[0].sell_order_id, iceberg.id);
+
+        // 补充后的切片排到同价位已有挂单之后
+        let (_, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        assert_eq!(trades[0].sell_order_id, resting.id);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.asks[&Decimal::from(100)].total_quantity, Decimal::ONE);
+        assert_eq!(book.orders[&iceberg.id].remaining_quantity(), Decimal::from(2));
+    }
+
+    #[test]
+    fn test_iceberg_display_quantity_exceeding_quantity_rejected() {
+        let mut engine = MatchingEngine::new();
+        for display_quantity in ["0", "11", "abc"] {
+            let result = engine.place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: 1,
+                order_type: OrderType::Limit as i32,
+                side: OrderSide::Ask as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price: "100",
+                quantity: "10",
+                display_quantity: Some(display_quantity),
+                ..OrderParams::default()
+            });
+            assert!(matches!(result, Err(BalanceError::InvalidQuantity(_))));
+        }
+    }
+
+    #[test]
+    fn test_stop_order_rests_until_trade_reaches_stop_price() {
+        let mut engine = MatchingEngine::new();
+        let (stop, trades) = place_stop(
+            &mut engine,
+            3,
+            OrderType::Limit,
+            OrderSide::Bid,
+            "102",
+            "1",
+            "101",
+            TriggerDirection::Rising,
+        );
+        assert!(trades.is_empty());
+        assert_eq!(stop.status, OrderStatus::Pending);
+
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100.5", "1");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "102", "1");
+
+        // 低于触发价的成交不激活止损单，止损单也不出现在深度中
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100.5", "1");
+        assert!(engine.take_triggered_orders(SYMBOL_ID).is_empty());
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.bids.is_empty());
+
+        // 在触发价成交后止损单激活，作为 taker 吃掉 102 的卖单
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "101", "1");
+        let triggered = engine.take_triggered_orders(SYMBOL_ID);
+        assert_eq!(triggered.len(), 1);
+        let (order, trades) = &triggered[0];
+        assert_eq!(order.id, stop.id);
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, Decimal::from(102));
+        assert_eq!(trades[0].buy_order_id, stop.id);
+        // 止损单先于 maker 下单，taker 方向仍然正确
+        assert!(trades[0].taker_is_buyer());
+        assert_eq!(engine.get_recent_trades(SYMBOL_ID, 10).len(), 3);
+    }
+
+    #[test]
+    fn test_sweeping_trade_activates_all_crossed_stops_in_price_order() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "98", "1");
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "90", "10");
+
+        let (stop_99, _) = place_stop(
+            &mut engine,
+            3,
+            OrderType::Market,
+            OrderSide::Ask,
+            "",
+            "1",
+            "99",
+            TriggerDirection::Falling,
+        );
+        let (stop_100, _) = place_stop(
+            &mut engine,
+            4,
+            OrderType::Market,
+            OrderSide::Ask,
+            "",
+            "1",
+            "100",
+            TriggerDirection::Falling,
+        );
+        let (stop_95, _) = place_stop(
+            &mut engine,
+            5,
+            OrderType::Market,
+            OrderSide::Ask,
+            "",
+            "1",
+            "95",
+            TriggerDirection::Falling,
+        );
+
+        // 一笔卖单扫过 100 和 99 两档，两个止损单按触发价从高到低依次激活；
+        // 止损单成交到 90 后又穿过了 95 的触发价，级联激活
+        let (_, trades) = place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "99", "2");
+        assert_eq!(trades.len(), 2);
+
+        let triggered = engine.take_triggered_orders(SYMBOL_ID);
+        let ids: Vec<u64> = triggered.iter().map(|(order, _)| order.id).collect();
+        assert_eq!(ids, vec![stop_100.id, stop_99.id, stop_95.id]);
+        let prices: Vec<Decimal> = triggered
+            .iter()
+            .flat_map(|(_, trades)| trades.iter().map(|t| t.price))
+            .collect();
+        assert_eq!(prices, vec![Decimal::from(98), Decimal::from(90), Decimal::from(90)]);
+        assert!(triggered.iter().all(|(order, _)| order.status == OrderStatus::Filled));
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.falling_stops.is_empty());
+        assert_eq!(book.bids[&Decimal::from(90)].total_quantity, Decimal::from(8));
+    }
+
+    #[test]
+    fn test_cancel_pending_stop_order() {
+        let mut engine = MatchingEngine::new();
+        let (stop, _) = place_stop(
+            &mut engine,
+            3,
+            OrderType::Limit,
+            OrderSide::Ask,
+            "95",
+            "1",
+            "96",
+            TriggerDirection::Falling,
+        );
+
+        let cancelled = engine.cancel_order(SYMBOL_ID, stop.id).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.falling_stops.is_empty());
+
+        // 撤销后的止损单不会再被激活
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "95", "1");
+        place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "95", "1");
+        assert!(engine.take_triggered_orders(SYMBOL_ID).is_empty());
+    }
+
+    #[test]
+    fn test_stop_limit_triggers_into_resting_limit_order() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "103", "2");
+        let (stop, _) = place_stop(
+            &mut engine,
+            3,
+            OrderType::StopLimit,
+            OrderSide::Bid,
+            "102",
+            "2",
+            "101",
+            TriggerDirection::Rising,
+        );
+        assert_eq!(stop.order_type, OrderType::StopLimit);
+        assert_eq!(stop.status, OrderStatus::Pending);
+
+        // 101 成交后激活，转为 102 的限价买单；对手盘只剩 103，不能成交，挂在买一
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "101", "1");
+        let triggered = engine.take_triggered_orders(SYMBOL_ID);
+        assert_eq!(triggered.len(), 1);
+        let (order, trades) = &triggered[0];
+        assert_eq!(order.id, stop.id);
+        assert_eq!(order.order_type, OrderType::Limit);
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert!(trades.is_empty());
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.bids[&Decimal::from(102)].total_quantity, Decimal::from(2));
+        assert!(book.rising_stops.is_empty());
+    }
+
+    #[test]
+    fn test_stop_market_triggers_into_sweeping_market_order() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "98", "1");
+        let (stop, _) = place_stop(
+            &mut engine,
+            3,
+            OrderType::StopMarket,
+            OrderSide::Ask,
+            "",
+            "2",
+            "100",
+            TriggerDirection::Falling,
+        );
+        assert_eq!(stop.order_type, OrderType::StopMarket);
+
+        // 100 成交后激活，转为市价卖单，依次吃掉 99 和 98 两档
+        place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        let triggered = engine.take_triggered_orders(SYMBOL_ID);
+        assert_eq!(triggered.len(), 1);
+        let (order, trades) = &triggered[0];
+        assert_eq!(order.order_type, OrderType::Market);
+        assert_eq!(order.status, OrderStatus::Filled);
+        let prices: Vec<Decimal> = trades.iter().map(|trade| trade.price).collect();
+        assert_eq!(prices, vec![Decimal::from(99), Decimal::from(98)]);
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().bids.is_empty());
+
+        // 止损类型必须带触发价
+        let result = engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: 3,
+            order_type: OrderType::StopMarket as i32,
+            side: OrderSide::Ask as i32,
+            time_in_force: TimeInForce::Gtc as i32,
+            price: "",
+            quantity: "1",
+            ..OrderParams::default()
+        });
+        assert!(matches!(result, Err(BalanceError::InvalidOrder(_))));
+    }
+
+    #[test]
+    fn test_spread_bps_uses_decimal_math() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99.99", "1");
+        // 只有一侧挂单时没有价差
+        assert_eq!(engine.get_order_book(SYMBOL_ID).unwrap().spread_bps(), None);
+
+        // (100.01 - 99.99) / 100 × 10000 = 2
+        place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "100.01", "1");
+        assert_eq!(engine.get_order_book(SYMBOL_ID).unwrap().spread_bps(), Some(Decimal::TWO));
+
+        // (101 - 100) / 100.5 × 10000 = 20000 / 201，不经过浮点
+        assert_eq!(
+            spread_bps(Decimal::from(100), Decimal::from(101)),
+            Some(Decimal::from_str_exact("99.50248756218905472636815920").unwrap())
+        );
+        // 中间价为 0 时不做除法
+        assert_eq!(spread_bps(Decimal::NEGATIVE_ONE, Decimal::ONE), None);
+        assert_eq!(spread_bps(Decimal::ONE, Decimal::ONE), None);
+    }
+
+    #[test]
+    fn test_trade_stats_accumulate_high_low_volume() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "105", "2");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "98", "0.5");
+        assert_eq!(engine.get_order_book(SYMBOL_ID).unwrap().last_trade_price(), None);
+
+        // 一笔买单吃掉 98 和 100 两档，再单独成交 105
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1.5");
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "105", "1");
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let book = engine.get_order_book_mut(SYMBOL_ID).unwrap();
+        assert_eq!(book.last_trade_price(), Some(Decimal::from(105)));
+        let stats = book.stats_24h(now);
+        assert_eq!(stats.open_24h, Some(Decimal::from(98)));
+        assert_eq!(stats.high_24h, Some(Decimal::from(105)));
+        assert_eq!(stats.low_24h, Some(Decimal::from(98)));
+        assert_eq!(stats.volume_24h, Decimal::new(25, 1));
+    }
+
+    #[test]
+    fn test_trade_stats_roll_off_expired_buckets() {
+        let hour = STATS_BUCKET_MS;
+        let start = 1000 * hour;
+        let mut stats = TradeStats::default();
+        stats.record(Decimal::from(100), Decimal::ONE, start);
+        stats.record(Decimal::from(120), Decimal::from(2), start + 5 * hour);
+        stats.record(Decimal::from(90), Decimal::ONE, start + 23 * hour);
+
+        // 第一个桶仍有部分落在窗口内
+        stats.expire(start + 24 * hour + hour / 2);
+        assert_eq!(stats.volume_24h, Decimal::from(4));
+        assert_eq!(stats.open_24h, Some(Decimal::from(100)));
+
+        // 整个桶滑出窗口后只统计剩余的成交
+        stats.expire(start + 25 * hour);
+        assert_eq!(stats.volume_24h, Decimal::from(3));
+        assert_eq!(stats.open_24h, Some(Decimal::from(120)));
+        assert_eq!(stats.high_24h, Some(Decimal::from(120)));
+        assert_eq!(stats.low_24h, Some(Decimal::from(90)));
+
+        stats.expire(start + 48 * hour);
+        assert_eq!(stats.volume_24h, Decimal::ZERO);
+        assert_eq!(stats.high_24h, None);
+        assert_eq!(stats.open_24h, None);
+    }
+
+    #[test]
+    fn test_completed_orders_evicted_beyond_retention() {
+        let mut engine = MatchingEngine::new();
+        engine.set_completed_order_retention(10);
+        let (resting, _) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "90", "1");
+
+        let mut last_id = 0;
+        for _ in 0..100 {
+            let (order, _) = place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+            assert!(engine.cancel_order(SYMBOL_ID, order.id).is_some());
+            last_id = order.id;
+        }
+
+        // 索引只保留挂单和最近完成的订单
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.orders.len(), 11);
+        assert!(book.orders.contains_key(&resting.id));
+        assert_eq!(book.orders[&last_id].status, OrderStatus::Cancelled);
+        assert!(!book.orders.contains_key(&(resting.id + 1)));
+    }
+
+    #[test]
+    fn test_cancel_by_client_order_id() {
+        let mut engine = MatchingEngine::new();
+        let place_with_client_id = |engine: &mut MatchingEngine, account_id, client_order_id| {
+            engine.place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price: "100",
+                quantity: "1",
+                client_order_id: Some(client_order_id),
+                ..OrderParams::default()
+            })
+        };
+        let (order, _) = place_with_client_id(&mut engine, 1, "my-order").unwrap();
+        assert_eq!(order.client_order_id.as_deref(), Some("my-order"));
+
+        // 同一账户的未完成订单不能重复使用客户端订单ID，其他账户不受影响
+        assert!(matches!(
+            place_with_client_id(&mut engine, 1, "my-order"),
+            Err(BalanceError::InvalidOrder(_))
+        ));
+        let (other, _) = place_with_client_id(&mut engine, 2, "my-order").unwrap();
+        assert!(matches!(
+            place_with_client_id(&mut engine, 1, ""),
+            Err(BalanceError::InvalidOrder(_))
+        ));
+
+        // 只能撤销本账户的订单
+        assert!(engine.cancel_by_client_id(SYMBOL_ID, 3, "my-order").is_none());
+        let cancelled = engine.cancel_by_client_id(SYMBOL_ID, 1, "my-order").unwrap();
+        assert_eq!(cancelled.id, order.id);
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert!(engine.cancel_by_client_id(SYMBOL_ID, 1, "my-order").is_none());
+        let found = engine.find_client_order(SYMBOL_ID, 2, "my-order");
+        assert_eq!(found.map(|order| order.id), Some(other.id));
+
+        // 订单完成后客户端订单ID可以再次使用，快照恢复后索引仍然有效
+        let (reused, _) = place_with_client_id(&mut engine, 1, "my-order").unwrap();
+        let mut restored = MatchingEngine::restore(&engine.snapshot()).unwrap();
+        assert_eq!(restored.cancel_by_client_id(SYMBOL_ID, 1, "my-order").unwrap().id, reused.id);
+    }
+
+    #[test]
+    fn test_prune_completed_orders_keeps_open_orders() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        let (resting, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        let (filled, _) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        let (cancelled, _) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        engine.cancel_order(SYMBOL_ID, cancelled.id);
+
+        // 成交的买卖双方和撤销的订单都被移除
+        assert_eq!(engine.prune_completed_orders(), 3);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.orders.len(), 1);
+        assert!(book.orders.contains_key(&resting.id));
+        assert!(!book.orders.contains_key(&filled.id));
+    }
+
+    #[test]
+    fn test_open_orders_cover_both_sides_and_drop_filled() {
+        let mut engine = MatchingEngine::new();
+        let (bid, _) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        let (ask, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        let (partial, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "102", "2");
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "98", "1");
+
+        let ids = |engine: &MatchingEngine| -> Vec<u64> {
+            engine
+                .get_open_orders(1, SYMBOL_ID)
+                .iter()
+                .map(|order| order.id)
+                .collect()
+        };
+        assert_eq!(ids(&engine), vec![bid.id, ask.id, partial.id]);
+
+        // 完全成交的订单移出结果，部分成交的订单保留
+        place(&mut engine, 3, OrderSide::Bid, TimeInForce::Gtc, "102", "2");
+        assert_eq!(ids(&engine), vec![bid.id, partial.id]);
+        let open = engine.get_open_orders(1, SYMBOL_ID);
+        assert_eq!(open[1].status, OrderStatus::Partial);
+        assert_eq!(open[1].remaining_quantity(), Decimal::ONE);
+
+        engine.cancel_order(SYMBOL_ID, bid.id);
+        assert_eq!(ids(&engine), vec![partial.id]);
+        assert!(engine.get_open_orders(1, 999).is_empty());
+        assert!(engine.get_open_orders(4, SYMBOL_ID).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_all_only_cancels_account_orders() {
+        let mut engine = MatchingEngine::new();
+        let (bid, _) = place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        let (ask, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        let (stop, _) = place_stop(
+            &mut engine,
+            1,
+            OrderType::Limit,
+            OrderSide::Ask,
+            "95",
+            "1",
+            "96",
+            TriggerDirection::Falling,
+        );
+        let (other, _) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "98", "1");
+
+        let cancelled = engine.cancel_all(SYMBOL_ID, 1);
+        let ids: Vec<u64> = cancelled.iter().map(|order| order.id).collect();
+        assert_eq!(ids, vec![bid.id, ask.id, stop.id]);
+        assert!(cancelled.iter().all(|order| order.status == OrderStatus::Cancelled));
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(book.asks.is_empty());
+        assert!(book.falling_stops.is_empty());
+        assert_eq!(book.get_best_bid(), Some(Decimal::from(98)));
+        assert_eq!(engine.get_open_orders(2, SYMBOL_ID)[0].id, other.id);
+
+        // ALL_ACCOUNTS 撤销剩余所有账户的订单
+        let cancelled = engine.cancel_all(SYMBOL_ID, ALL_ACCOUNTS);
+        assert_eq!(cancelled.len(), 1);
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().bids.is_empty());
+        assert!(engine.cancel_all(999, 1).is_empty());
+    }
+
+    fn place_with_rules(
+        engine: &mut MatchingEngine,
+        order_type: OrderType,
+        price: &str,
+        quantity: &str,
+        stop_price: Option<&str>,
+    ) -> Result<(Order, Vec<Trade>), BalanceError> {
+        let trading_rules = TradingRules {
+            price_tick: Decimal::from_str_exact("0.01").unwrap(),
+            quantity_step: Decimal::from_str_exact("0.001").unwrap(),
+            min_notional: Decimal::from(10),
+            ..TradingRules::default()
+        };
+        engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: 1,
+            order_type: order_type as i32,
+            side: OrderSide::Bid as i32,
+            time_in_force: TimeInForce::Gtc as i32,
+            price,
+            quantity,
+            trading_rules,
+            stop_price,
+            trigger_direction: TriggerDirection::Rising as i32,
+            ..OrderParams::default()
+        })
+    }
+
+    fn rejection(result: Result<(Order, Vec<Trade>), BalanceError>) -> String {
+        match result {
+            Err(
+                e @ (BalanceError::InvalidPrice(_)
+                | BalanceError::InvalidQuantity(_)
+                | BalanceError::BelowMinNotional(_)),
+            ) => e.to_string(),
+            Err(e) => panic!("Expected trading rule rejection, got {}", e),
+            Ok((order, _)) => panic!("Expected rejection, order {} accepted", order.id),
+        }
+    }
+
+    #[test]
+    fn test_trading_rules_reject_price_off_tick() {
+        let mut engine = MatchingEngine::new();
+        let message = rejection(place_with_rules(
+            &mut engine,
+            OrderType::Limit,
+            "50000.001",
+            "0.01",
+            None,
+        ));
+        assert!(message.contains("Price 50000.001"), "{}", message);
+
+        let message = rejection(place_with_rules(
+            &mut engine,
+            OrderType::Limit,
+            "50000.01",
+            "0.01",
+            Some("49000.005"),
+        ));
+        assert!(message.contains("Stop price"), "{}", message);
+
+        // 被拒绝的订单不占用订单ID
+        let (order, _) =
+            place_with_rules(&mut engine, OrderType::Limit, "50000.01", "0.01", None).unwrap();
+        assert_eq!(order.id, 1);
+    }
+
+    #[test]
+    fn test_trading_rules_reject_quantity_off_step() {
+        let mut engine = MatchingEngine::new();
+        let message = rejection(place_with_rules(
+            &mut engine,
+            OrderType::Limit,
+            "50000",
+            "0.0005",
+            None,
+        ));
+        assert!(message.contains("Quantity 0.0005"), "{}", message);
+
+        let message = rejection(place_with_rules(
+            &mut engine,
+            OrderType::Limit,
+            "50000",
+            "0.0015",
+            None,
+        ));
+        assert!(message.contains("Quantity 0.0015"), "{}", message);
+
+        // 市价单同样校验数量步长
+        let message = rejection(place_with_rules(
+            &mut engine,
+            OrderType::Market,
+            "",
+            "0.0015",
+            None,
+        ));
+        assert!(message.contains("Quantity"), "{}", message);
+    }
+
+    #[test]
+    fn test_trading_rules_reject_notional_below_minimum() {
+        let mut engine = MatchingEngine::new();
+        let message = rejection(place_with_rules(&mut engine, OrderType::Limit, "9.99", "1", None));
+        assert!(message.contains("below minimum 10"), "{}", message);
+
+        assert!(place_with_rules(&mut engine, OrderType::Limit, "10", "1", None).is_ok());
+        // 市价单没有限价，不校验最小成交额
+        assert!(place_with_rules(&mut engine, OrderType::Market, "", "0.001", None).is_ok());
+    }
+
+    fn place_sized(
+        engine: &mut MatchingEngine,
+        account_id: i32,
+        order_type: OrderType,
+        side: OrderSide,
+        price: &str,
+        quantity: &str,
+    ) -> Result<(Order, Vec<Trade>), BalanceError> {
+        let trading_rules = TradingRules {
+            min_quantity: Decimal::from_str_exact("0.01").unwrap(),
+            max_quantity: Decimal::from(10),
+            max_notional: Decimal::from(100_000),
+            ..TradingRules::default()
+        };
+        engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id,
+            order_type: order_type as i32,
+            side: side as i32,
+            time_in_force: TimeInForce::Gtc as i32,
+            price,
+            quantity,
+            trading_rules,
+            trigger_direction: TriggerDirection::Rising as i32,
+            ..OrderParams::default()
+        })
+    }
+
+    #[test]
+    fn test_trading_rules_reject_undersized_and_oversized_orders() {
+        let mut engine = MatchingEngine::new();
+        for (side, price, quantity, expected) in [
+            (OrderSide::Bid, "100", "0.005", "Quantity 0.005 is below minimum 0.01"),
+            (OrderSide::Bid, "100", "11", "Quantity 11 is above maximum 10"),
+            (OrderSide::Ask, "20000", "6", "Notional 120000 is above maximum 100000"),
+        ] {
+            let result = place_sized(&mut engine, 1, OrderType::Limit, side, price, quantity);
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+        for (price, quantity) in [("20000", "4"), ("25000", "2")] {
+            assert!(place_sized(&mut engine, 1, OrderType::Limit, OrderSide::Ask, price, quantity)
+                .is_ok());
+        }
+
+        // 没有报价的市价单按订单簿估算成交额：买 5 需要 20000 x 4 + 25000 x 1
+        let market = place_sized(&mut engine, 2, OrderType::Market, OrderSide::Bid, "", "5");
+        assert!(matches!(market, Err(BalanceError::AboveMaxNotional(message))
+            if message.contains("Notional 105000")));
+        let (order, trades) =
+            place_sized(&mut engine, 2, OrderType::Market, OrderSide::Bid, "", "4").unwrap();
+        assert_eq!(order.filled_quantity, Decimal::from(4));
+        assert_eq!(trades.len(), 1);
+    }
+
+    // 以固定时钟和固定请求ID重放同一组订单，返回引擎快照
+    fn replay_fixed_sequence() -> (Vec<u8>, Vec<Trade>) {
+        let mut engine = MatchingEngine::new();
+        engine.set_clock(Arc::new(ManualClock::new(1_700_000_000_000, 1)));
+        let orders = [
+            (10, OrderSide::Ask, "50000", "1"),
+            (11, OrderSide::Ask, "50100", "2"),
+            (20, OrderSide::Bid, "49900", "1"),
+            (21, OrderSide::Bid, "50100", "1.5"),
+            (22, OrderSide::Ask, "49800", "2"),
+        ];
+        for (index, (account_id, side, price, quantity)) in orders.into_iter().enumerate() {
+            engine
+                .place_order(OrderParams {
+                    request_id: Uuid::from_u128(index as u128 + 1),
+                    symbol_id: SYMBOL_ID,
+                    account_id,
+                    order_type: OrderType::Limit as i32,
+                    side: side as i32,
+                    time_in_force: TimeInForce::Gtc as i32,
+                    price,
+                    quantity,
+                    ..OrderParams::default()
+                })
+                .unwrap();
+        }
+        engine.cancel_order(SYMBOL_ID, 2).unwrap();
+        let trades = engine.trades.iter().cloned().collect();
+        (engine.snapshot(), trades)
+    }
+
+    #[test]
+    fn test_replay_with_fixed_clock_is_byte_identical() {
+        let (first, trades) = replay_fixed_sequence();
+        let (second, _) = replay_fixed_sequence();
+        assert_eq!(first, second);
+
+        // 时间戳来自注入的时钟：每次下单和每笔成交各读取一次
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].created_at, 1_700_000_000_004);
+        let restored = MatchingEngine::restore(&first).unwrap();
+        let order_book = restored.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(order_book.orders[&1].created_at, 1_700_000_000_000);
+    }
+
+    fn resting(id: u64, side: OrderSide, price: &str) -> Order {
+        Order::new(
+            id,
+            Uuid::nil(),
+            SYMBOL_ID,
+            id as i32,
+            OrderType::Limit,
+            side,
+            TimeInForce::Gtc,
+            Decimal::from_str_exact(price).unwrap(),
+            Decimal::ONE,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_crossed_book_is_detected() {
+        let mut order_book = OrderBook::new(SYMBOL_ID);
+        order_book.add_order_to_book(resting(1, OrderSide::Bid, "100"));
+        order_book.add_order_to_book(resting(2, OrderSide::Ask, "101"));
+        assert!(!order_book.is_crossed());
+        assert_eq!(order_book.get_spread(), Some(Decimal::ONE));
+        order_book.assert_uncrossed();
+
+        // 绕过撮合直接挂入会交叉的买单
+        order_book.add_order_to_book(resting(3, OrderSide::Bid, "102"));
+        assert!(order_book.is_crossed());
+        assert_eq!(order_book.get_spread(), None);
+
+        let check = std::panic::AssertUnwindSafe(|| order_book.assert_uncrossed());
+        assert_eq!(std::panic::catch_unwind(check).is_err(), cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn test_aggregated_depth_sums_levels_per_bucket() {
+        let mut engine = MatchingEngine::new();
+        for (side, price, quantity) in [
+            (OrderSide::Bid, "99.5", "1"),
+            (OrderSide::Bid, "99", "2"),
+            (OrderSide::Bid, "98.2", "3"),
+            (OrderSide::Bid, "90", "4"),
+            (OrderSide::Ask, "100.5", "1"),
+            (OrderSide::Ask, "101", "2"),
+            (OrderSide::Ask, "109.9", "3"),
+        ] {
+            place(&mut engine, 1, side, TimeInForce::Gtc, price, quantity);
+        }
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let depth = |levels: &[(&str, &str)]| -> DepthLevels {
+            levels
+                .iter()
+                .map(|(price, quantity)| {
+                    let price = Decimal::from_str_exact(price).unwrap();
+                    (price, Decimal::from_str_exact(quantity).unwrap())
+                })
+                .collect()
+        };
+
+        let (bids, asks) = book.get_market_depth(10);
+        assert_eq!(bids.len(), 4);
+        assert_eq!(asks.len(), 3);
+
+        // 价格向下取整到 5 的整数倍：买盘 95、90 两档，卖盘 100、105 两档
+        let (bids, asks) = book.get_aggregated_depth(10, Decimal::from(5));
+        assert_eq!(bids, depth(&[("95", "6"), ("90", "4")]));
+        assert_eq!(asks, depth(&[("100", "3"), ("105", "3")]));
+
+        // 档数限制按合并后的档位计算，最后一档的数量完整
+        let (bids, asks) = book.get_aggregated_depth(1, Decimal::from(5));
+        assert_eq!(bids, depth(&[("95", "6")]));
+        assert_eq!(asks, depth(&[("100", "3")]));
+
+        // 粒度小于价格间隔时与逐档深度相同
+        assert_eq!(
+            book.get_aggregated_depth(10, Decimal::new(1, 1)),
+            book.get_market_depth(10)
+        );
+
+        // 一侧为空时返回空
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "99", "1");
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let (bids, asks) = book.get_aggregated_depth(10, Decimal::ONE);
+        assert_eq!(bids, depth(&[("99", "1")]));
+        assert!(asks.is_empty());
+    }
+
+    #[test]
+    fn test_estimate_market_fill_walks_the_ladder() {
+        let mut engine = MatchingEngine::new();
+        for (side, price, quantity) in [
+            (OrderSide::Ask, "100", "1"),
+            (OrderSide::Ask, "101", "2"),
+            (OrderSide::Ask, "103", "3"),
+            (OrderSide::Bid, "99", "2"),
+        ] {
+            place(&mut engine, 1, side, TimeInForce::Gtc, price, quantity);
+        }
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        let depth_before = book.get_market_depth(10);
+
+        // 买 4：100 x 1 + 101 x 2 + 103 x 1，均价 101.25
+        let estimate = book.estimate_market_fill(&OrderSide::Bid, Decimal::from(4));
+        assert_eq!(estimate.filled_quantity, Decimal::from(4));
+        assert_eq!(estimate.average_price, Some(Decimal::new(10125, 2)));
+        assert_eq!(estimate.worst_price, Some(Decimal::from(103)));
+        assert!(!estimate.partial);
+
+        // 对手盘只有 6，部分成交
+        let estimate = book.estimate_market_fill(&OrderSide::Bid, Decimal::from(10));
+        assert_eq!(estimate.filled_quantity, Decimal::from(6));
+        assert_eq!(estimate.average_price, Some(Decimal::from(611) / Decimal::from(6)));
+        assert!(estimate.partial);
+
+        // 卖单走买盘
+        let estimate = book.estimate_market_fill(&OrderSide::Ask, Decimal::ONE);
+        assert_eq!(estimate.average_price, Some(Decimal::from(99)));
+        assert!(!estimate.partial);
+
+        // 估算不修改订单簿
+        assert_eq!(book.get_market_depth(10), depth_before);
+
+        let empty = OrderBook::new(SYMBOL_ID);
+        let estimate = empty.estimate_market_fill(&OrderSide::Bid, Decimal::ONE);
+        assert_eq!(estimate.filled_quantity, Decimal::ZERO);
+        assert_eq!(estimate.average_price, None);
+        assert!(estimate.partial);
+    }
+
+    #[test]
+    fn test_sequence_increases_by_one_per_book_change() {
+        let mut engine = MatchingEngine::new();
+        let sequence =
+            |engine: &MatchingEngine| engine.get_order_book(SYMBOL_ID).unwrap().sequence();
+
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        assert_eq!(sequence(&engine), 1);
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+        assert_eq!(sequence(&engine), 2);
+
+        // 两笔成交各占一个序号，剩余部分挂单再占一个
+        let (bid, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "101", "3");
+        let trade_sequences: Vec<u64> = trades.iter().map(|trade| trade.sequence).collect();
+        assert_eq!(trade_sequences, vec![3, 4]);
+        assert_eq!(sequence(&engine), 5);
+
+        engine.cancel_order(SYMBOL_ID, bid.id).unwrap();
+        assert_eq!(sequence(&engine), 6);
+
+        // 没有改动订单簿的 IOC 不占序号
+        place(&mut engine, 2, OrderSide::Bid, TimeInForce::Ioc, "99", "1");
+        assert_eq!(sequence(&engine), 6);
+
+        // 快照恢复后继续递增，不回退
+        let mut restored = MatchingEngine::restore(&engine.snapshot()).unwrap();
+        assert_eq!(sequence(&restored), 6);
+        let (_, trades) = place(&mut restored, 1, OrderSide::Ask, TimeInForce::Gtc, "99", "1");
+        assert!(trades.is_empty());
+        assert_eq!(sequence(&restored), 7);
+    }
+
+    // 逐个挂单重新统计，与增量维护的合计对比
+    fn recompute_open_interest(book: &OrderBook) -> OpenInterest {
+        let mut open_interest = OpenInterest::default();
+        let levels = book.bids.values().chain(book.asks.values());
+        for order in levels.flat_map(|level| level.orders.iter()) {
+            let quantity = order.remaining_quantity();
+            match order.side {
+                OrderSide::Bid => {
+                    open_interest.bid_quantity += quantity;
+                    open_interest.bid_notional += order.price * quantity;
+                }
+                OrderSide::Ask => {
+                    open_interest.ask_quantity += quantity;
+                    open_interest.ask_notional += order.price * quantity;
+                }
+            }
+        }
+        open_interest
+    }
+
+    #[test]
+    fn test_open_interest_tracks_fills_cancels_and_amends() {
+        let mut engine = MatchingEngine::new();
+        let open_interest = |engine: &MatchingEngine| {
+            let book = engine.get_order_book(SYMBOL_ID).unwrap();
+            assert_eq!(book.open_interest(), recompute_open_interest(book));
+            book.open_interest()
+        };
+
+        let (ask, _) = place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "101", "2");
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "102", "1");
+        let (bid, _) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "99", "3");
+        let totals = open_interest(&engine);
+        assert_eq!(totals.bid_quantity, Decimal::from(3));
+        assert_eq!(totals.bid_notional, Decimal::from(297));
+        assert_eq!(totals.ask_quantity, Decimal::from(3));
+        assert_eq!(totals.ask_notional, Decimal::from(304));
+
+        // 部分成交只减去成交数量
+        place(&mut engine, 3, OrderSide::Bid, TimeInForce::Gtc, "101", "0.5");
+        let totals = open_interest(&engine);
+        assert_eq!(totals.ask_quantity, Decimal::new(25, 1));
+        assert_eq!(totals.ask_notional, Decimal::new(2535, 1));
+
+        // 扫过多个价位后剩余部分挂到买盘
+        place(&mut engine, 3, OrderSide::Bid, TimeInForce::Gtc, "102", "3");
+        let totals = open_interest(&engine);
+        assert_eq!(totals.ask_quantity, Decimal::ZERO);
+        assert_eq!(totals.ask_notional, Decimal::ZERO);
+        assert_eq!(totals.bid_quantity, Decimal::new(35, 1));
+        assert_eq!(totals.bid_notional, Decimal::from(348));
+        assert!(engine.get_order_book(SYMBOL_ID).unwrap().orders[&ask.id].is_filled());
+
+        // 部分成交后撤单按剩余数量扣减
+        place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "99", "2");
+        assert_eq!(open_interest(&engine).bid_quantity, Decimal::new(15, 1));
+        engine.cancel_order(SYMBOL_ID, bid.id).unwrap();
+        let totals = open_interest(&engine);
+        assert_eq!(totals.bid_quantity, Decimal::ZERO);
+        assert_eq!(totals.bid_notional, Decimal::ZERO);
+
+        // 改单按新价格和数量计入
+        let (resting, _) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "90", "2");
+        engine.amend_order(SYMBOL_ID, resting.id, "90", "1").unwrap();
+        assert_eq!(open_interest(&engine).bid_notional, Decimal::from(90));
+        engine.amend_order(SYMBOL_ID, resting.id, "95", "2").unwrap();
+        assert_eq!(open_interest(&engine).bid_notional, Decimal::from(190));
+
+        // 快照恢复后重新统计
+        let restored = MatchingEngine::restore(&engine.snapshot()).unwrap();
+        assert_eq!(open_interest(&restored), open_interest(&engine));
+    }
+
+    #[test]
+    fn test_cancel_in_deep_price_level_goes_through_queue_index() {
+        let mut book = OrderBook::new(SYMBOL_ID);
+        let price = Decimal::from(100);
+        for id in 1..=10_000u64 {
+            let order = Order::new(
+                id,
+                Uuid::new_v4(),
+                SYMBOL_ID,
+                (id % 7) as i32,
+                OrderType::Limit,
+                OrderSide::Ask,
+                TimeInForce::Gtc,
+                price,
+                Decimal::ONE,
+                0,
+            );
+            book.add_order(order);
+        }
+
+        // 从队列中间开始撤单，按订单ID索引直接定位排队位置，不逐笔扫描队列
+        for id in (2..=10_000u64).step_by(10) {
+            let cancelled = book.cancel_order(id).unwrap();
+            assert_eq!(cancelled.id, id);
+            assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        }
+
+        assert!(book.cancel_order(2).is_none());
+        let level = &book.asks[&price];
+        assert_eq!(level.orders.len(), 9_000);
+        assert_eq!(level.total_quantity, Decimal::from(9_000));
+        // 索引与队列同步：撤销的订单从索引中删除，其余订单的索引指向各自的排队位置
+        assert_eq!(level.orders.slots.len(), 9_000);
+        assert!(level
+            .orders
+            .slots
+            .iter()
+            .all(|(id, slot)| level.orders.orders[slot].id == *id));
+
+        // 撤单后其余订单保持原有时间优先顺序
+        let queue: Vec<u64> = level.orders.iter().take(3).map(|order| order.id).collect();
+        assert_eq!(queue, vec![1, 3, 4]);
+        let (_, trades) = book.add_order(Order::new(
+            10_001,
+            Uuid::new_v4(),
+            SYMBOL_ID,
+            99,
+            OrderType::Limit,
+            OrderSide::Bid,
+            TimeInForce::Gtc,
+            price,
+            Decimal::from(2),
+            0,
+        ));
+        let makers: Vec<u64> = trades.iter().map(|trade| trade.sell_order_id).collect();
+        assert_eq!(makers, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_trade_retention_bounds_recent_trades() {
+        let mut engine = MatchingEngine::new();
+        engine.set_clock(Arc::new(ManualClock::new(0, 1_000)));
+        engine.set_trade_retention(TradeRetention {
+            max_trades: 10,
+            max_age_ms: None,
+        });
+        let mut trade_ids = Vec::new();
+        for _ in 0..30 {
+            place(&mut engine, 1, OrderSide::Ask, TimeInForce::Gtc, "100", "1.0");
+            let (_, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "100", "1.0");
+            trade_ids.push(trades[0].id);
+        }
+        assert_eq!(engine.trades.len(), 10);
+
+        // 最近成交按从新到旧返回，只包含保留的部分
+        let recent: Vec<u64> =
+            engine.get_recent_trades(SYMBOL_ID, 50).iter().map(|trade| trade.id).collect();
+        let expected: Vec<u64> = trade_ids.iter().rev().take(10).copied().collect();
+        assert_eq!(recent, expected);
+        assert_eq!(engine.get_recent_trades(SYMBOL_ID, 3).len(), 3);
+
+        // 按时长淘汰时以最新一笔成交为准
+        engine.set_trade_retention(TradeRetention {
+            max_trades: 10,
+            max_age_ms: Some(5_000),
+        });
+        let latest = engine.trades.back().unwrap().created_at;
+        assert!(engine.trades.len() < 10);
+        assert!(engine.trades.iter().all(|trade| trade.created_at + 5_000 >= latest));
+        assert_eq!(engine.trades.back().unwrap().id, *trade_ids.last().unwrap());
+    }
+
+    // (成交价, 成交数量)，按成交顺序
+    fn price_fills(trades: &[Trade]) -> Vec<(Decimal, Decimal)> {
+        trades.iter().map(|trade| (trade.price, trade.quantity)).collect()
+    }
+
+    #[test]
+    fn test_limit_bid_fills_cheapest_ask_level_first() {
+        let mut engine = MatchingEngine::new();
+        // 挂单顺序与价格无关，103 超出买单限价
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "102", "1");
+        place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+        place(&mut engine, 5, OrderSide::Ask, TimeInForce::Gtc, "101", "0.7");
+        place(&mut engine, 6, OrderSide::Ask, TimeInForce::Gtc, "100", "0.5");
+        place(&mut engine, 7, OrderSide::Ask, TimeInForce::Gtc, "103", "1");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "102", "2.5");
+
+        // 100 整档成交后才成交 101，101 整档成交后才成交 102
+        assert_eq!(
+            price_fills(&trades),
+            vec![
+                (Decimal::new(100, 0), Decimal::new(5, 1)),
+                (Decimal::new(100, 0), Decimal::new(5, 1)),
+                (Decimal::new(101, 0), Decimal::new(7, 1)),
+                (Decimal::new(102, 0), Decimal::new(8, 1)),
+            ]
+        );
+        assert!(trades.windows(2).all(|pair| pair[0].price <= pair[1].price));
+        assert_eq!(
+            trades.iter().map(|trade| trade.sell_account_id).collect::<Vec<_>>(),
+            vec![4, 6, 5, 3]
+        );
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_ask(), Some(Decimal::new(102, 0)));
+        assert_eq!(book.asks[&Decimal::new(102, 0)].total_quantity, Decimal::new(2, 1));
+        assert_eq!(book.asks[&Decimal::new(103, 0)].total_quantity, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_limit_ask_fills_highest_bid_level_first() {
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, 3, OrderSide::Bid, TimeInForce::Gtc, "98", "1");
+        place(&mut engine, 4, OrderSide::Bid, TimeInForce::Gtc, "100", "0.6");
+        place(&mut engine, 5, OrderSide::Bid, TimeInForce::Gtc, "99", "0.4");
+        place(&mut engine, 6, OrderSide::Bid, TimeInForce::Gtc, "97", "1");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "98", "1.5");
+
+        assert_eq!(
+            price_fills(&trades),
+            vec![
+                (Decimal::new(100, 0), Decimal::new(6, 1)),
+                (Decimal::new(99, 0), Decimal::new(4, 1)),
+                (Decimal::new(98, 0), Decimal::new(5, 1)),
+            ]
+        );
+        assert!(trades.windows(2).all(|pair| pair[0].price >= pair[1].price));
+        assert_eq!(order.status, OrderStatus::Filled);
+
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::new(98, 0)));
+        assert_eq!(book.bids[&Decimal::new(98, 0)].total_quantity, Decimal::new(5, 1));
+        assert_eq!(book.bids[&Decimal::new(97, 0)].total_quantity, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_level_emptied_mid_sweep_continues_at_next_best_price() {
+        let mut engine = MatchingEngine::new();
+        engine.set_self_trade_prevention(SelfTradePrevention::CancelMaker);
+        // 最优一档只有 taker 自己的挂单，被自成交保护撤销后清空
+        place(&mut engine, 2, OrderSide::Ask, TimeInForce::Gtc, "100", "1");
+        place(&mut engine, 4, OrderSide::Ask, TimeInForce::Gtc, "102", "1");
+        place(&mut engine, 3, OrderSide::Ask, TimeInForce::Gtc, "101", "1");
+
+        let (order, trades) = place(&mut engine, 2, OrderSide::Bid, TimeInForce::Gtc, "102", "1.5");
+
+        assert_eq!(
+            price_fills(&trades),
+            vec![
+                (Decimal::new(101, 0), Decimal::ONE),
+                (Decimal::new(102, 0), Decimal::new(5, 1)),
+            ]
+        );
+        assert_eq!(order.status, OrderStatus::Filled);
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert!(!book.asks.contains_key(&Decimal::new(100, 0)));
+        assert_eq!(book.asks[&Decimal::new(102, 0)].total_quantity, Decimal::new(5, 1));
+    }
+
+    #[test]
+    fn test_place_on_unconfigured_symbol_is_rejected_without_creating_book() {
+        let management = ManagementManager::new();
+        management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        management.create_currency("USDT".to_string(), "Tether USD".to_string());
+        management
+            .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+            .unwrap();
+        let mut engine = MatchingEngine::new();
+        engine.set_management_manager(Arc::new(management));
+        let unknown = SYMBOL_ID + 1;
+
+        let result = engine.place_order(OrderParams {
+            request_id: Uuid::new_v4(),
+            symbol_id: unknown,
+            account_id: 1,
+            order_type: OrderType::Limit as i32,
+            side: OrderSide::Bid as i32,
+            time_in_force: TimeInForce::Gtc as i32,
+            price: "100",
+            quantity: "1",
+            ..OrderParams::default()
+        });
+        assert!(matches!(result, Err(BalanceError::CurrencyNotFound)));
+        let result = engine.place_quote_order(
+            Uuid::new_v4(),
+            unknown,
+            1,
+            "100",
+            FeeRates::default(),
+            TradingRules::default(),
+            None,
+            None,
+        );
+        assert!(matches!(result, Err(BalanceError::CurrencyNotFound)));
+        assert!(engine.get_order_book(unknown).is_none());
+        assert_eq!(engine.symbol_count(), 0);
+
+        // 已配置的交易对照常创建订单簿
+        place(&mut engine, 1, OrderSide::Bid, TimeInForce::Gtc, "100", "1");
+        assert!(engine.get_order_book(SYMBOL_ID).is_some());
+    }
+}
@@ -1,5 +1,5 @@
-use crate::matching::Trade;
 use crate::models::schema;
+use std::sync::Arc;
 use tokio::sync::oneshot;
 use uuid::Uuid;
 
@@ -12,18 +12,34 @@ pub enum SequencerMessage {
         currency_id: Option<i32>,
         response_sender: oneshot::Sender<schema::GetAccountResponse>,
     },
+    GetBalanceHistory {
+        request_id: Uuid,
+        account_id: i32,
+        currency_id: Option<i32>,
+        limit: i32,
+        response_sender: oneshot::Sender<schema::GetBalanceHistoryResponse>,
+    },
     Increase {
         request_id: Uuid,
         account_id: i32,
         currency_id: i32,
         amount: String,
+        idempotency_key: Option<i64>, // 客户端幂等键，同一账户重复的键直接返回首次响应
         response_sender: oneshot::Sender<schema::IncreaseResponse>,
     },
+    // 批量充值：同一分片的条目合并为一条消息，按条目顺序返回结果
+    BatchIncrease {
+        request_id: Uuid,
+        entries: Vec<schema::IncreaseRequest>,
+        response_sender: oneshot::Sender<Vec<schema::IncreaseResponse>>,
+    },
     Decrease {
         request_id: Uuid,
         account_id: i32,
         currency_id: i32,
         amount: String,
+        idempotency_key: Option<i64>, // 客户端幂等键，同一账户重复的键直接返回首次响应
+        nonce: Option<i64>,           // 客户端按账户严格递增的序号，在幂等检查之后检查
         response_sender: oneshot::Sender<schema::DecreaseResponse>,
     },
     PlaceOrder {
@@ -32,8 +48,22 @@ pub enum SequencerMessage {
         account_id: i32,
         order_type: i32,
         side: i32,
+        time_in_force: i32,
         price: String,
         quantity: String,
+        taker_rate: i32, // taker 手续费率，单位百万分之一
+        maker_rate: i32, // maker 手续费率，单位百万分之一
+        post_only: bool, // 只做 maker，会立即成交时整单撤销
+        display_quantity: Option<String>, // 冰山单每次显示的数量
+        stop_price: Option<String>, // 止损单触发价
+        trigger_direction: i32,
+        protection_price: Option<String>, // 市价单保护价，超过后停止撮合，剩余部分撤销
+        expires_at: Option<u64>, // 到期时间戳（毫秒），仅 GTC 订单
+        volume: Option<String>, // 按金额下单的市价买单最多花费的 quote 数量
+        client_order_id: Option<String>, // 客户端订单ID，同一账户在交易对上的未完成订单中唯一
+        validate_only: bool, // 只校验并返回需要冻结的金额，不冻结也不转发到撮合
+        nonce: Option<i64>, // 客户端按账户严格递增的序号，未填写时不检查
+        span: tracing::Span, // gRPC 层打开的请求 span，冻结和撮合步骤记录为其子 span
         response_sender: oneshot::Sender<schema::PlaceOrderResponse>,
     },
     CancelOrder {
@@ -41,8 +71,83 @@ pub enum SequencerMessage {
         symbol_id: i32,
         account_id: i32,
         order_id: u64,
+        client_order_id: Option<String>, // 按客户端订单ID撤单，填写时忽略 order_id
+        nonce: Option<i64>,
         response_sender: oneshot::Sender<schema::CancelOrderResponse>,
     },
+    CancelAllOrders {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32, // ALL_ACCOUNTS 表示所有账户，仅管理接口使用
+        nonce: Option<i64>,
+        response_sender: oneshot::Sender<schema::CancelAllOrdersResponse>,
+    },
+    AmendOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_id: u64,
+        side: i32,
+        price: String,
+        quantity: String,
+        nonce: Option<i64>,
+        response_sender: oneshot::Sender<schema::AmendOrderResponse>,
+    },
+    // 运维调账，仅管理接口使用
+    AdminAdjustBalance {
+        request_id: Uuid,
+        account_id: i32,
+        currency_id: i32,
+        delta: String,
+        reason: String,
+        response_sender: oneshot::Sender<schema::AdminAdjustBalanceResponse>,
+    },
+    // 发起提现：冻结金额并返回冻结单ID
+    RequestWithdrawal {
+        request_id: Uuid,
+        account_id: i32,
+        currency_id: i32,
+        amount: String,
+        response_sender: oneshot::Sender<schema::WithdrawalResponse>,
+    },
+    // 确认或取消提现冻结单，发往冻结单所属账户的分片
+    FinishWithdrawal {
+        request_id: Uuid,
+        account_id: i32,
+        hold_id: u64,
+        confirm: bool, // true 扣除冻结金额，false 解冻
+        response_sender: oneshot::Sender<schema::WithdrawalResponse>,
+    },
+    // 划入或退回保留余额，仅管理接口使用
+    AdjustReserve {
+        request_id: Uuid,
+        account_id: i32,
+        currency_id: i32,
+        tag: String,
+        amount: String,
+        release: bool, // true 退回可用余额
+        response_sender: oneshot::Sender<schema::AdjustReserveResponse>,
+    },
+    // 装入订单簿快照前为其中本分片账户的未完成订单冻结余额，任一订单余额不足时都不冻结；
+    // release 为 true 时按撤单的解冻方式退回（装入失败或其他分片冻结失败）
+    FundLoadedOrders {
+        request_id: Uuid,
+        symbol_id: i32,
+        orders: Vec<crate::matching::Order>,
+        release: bool,
+        response_sender: oneshot::Sender<schema::LoadOrderBookSnapshotResponse>,
+    },
+    // 发往转出账户所在分片
+    Transfer {
+        request_id: Uuid,
+        from_account_id: i32,
+        to_account_id: i32,
+        currency_id: i32,
+        amount: String,
+        idempotency_key: Option<i64>, // 按转出账户去重
+        nonce: Option<i64>,           // 转出账户的序号
+        response_sender: oneshot::Sender<schema::TransferResponse>,
+    },
 }
 
 #[derive(Debug)]
@@ -53,42 +158,270 @@ pub enum MatchMessage {
         account_id: i32,
         order_type: i32,
         side: i32,
+        time_in_force: i32,
+        price: String,
+        quantity: String,
+        taker_rate: i32, // taker 手续费率，单位百万分之一
+        maker_rate: i32, // maker 手续费率，单位百万分之一
+        post_only: bool, // 只做 maker，会立即成交时整单撤销
+        display_quantity: Option<String>, // 冰山单每次显示的数量
+        stop_price: Option<String>, // 止损单触发价
+        trigger_direction: i32,
+        protection_price: Option<String>, // 市价单保护价，超过后停止撮合，剩余部分撤销
+        expires_at: Option<u64>, // 到期时间戳（毫秒），仅 GTC 订单
+        volume: Option<String>, // 按金额下单的市价买单最多花费的 quote 数量
+        client_order_id: Option<String>,
+        span: tracing::Span,
+        response_sender: oneshot::Sender<schema::PlaceOrderResponse>,
+    },
+    // 只校验的下单请求：余额检查通过后按下单时的规则校验，不分配订单ID、不改动订单簿
+    ValidateOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_type: i32,
+        side: i32,
+        time_in_force: i32,
         price: String,
         quantity: String,
+        taker_rate: i32,
+        maker_rate: i32,
+        post_only: bool,
+        display_quantity: Option<String>,
+        stop_price: Option<String>,
+        trigger_direction: i32,
+        protection_price: Option<String>,
+        expires_at: Option<u64>,
+        volume: Option<String>,
+        client_order_id: Option<String>,
+        frozen_currency_id: i32, // 排序器算出的需要冻结的币种和金额，校验通过时原样返回
+        frozen_amount: String,
         response_sender: oneshot::Sender<schema::PlaceOrderResponse>,
     },
     GetOrderBook {
         request_id: Uuid,
         symbol_id: i32,
         levels: i32,
+        bucket: Option<rust_decimal::Decimal>, // 价格聚合粒度，None 表示按价格逐档返回
         response_sender: oneshot::Sender<schema::GetOrderBookResponse>,
     },
+    GetTrades {
+        request_id: Uuid,
+        symbol_id: i32,
+        limit: i32,
+        response_sender: oneshot::Sender<schema::GetTradesResponse>,
+    },
+    GetTicker {
+        request_id: Uuid,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::TickerResponse>,
+    },
+    GetBbo {
+        request_id: Uuid,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::BboResponse>,
+    },
+    EstimateOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        side: i32,
+        quantity: rust_decimal::Decimal,
+        response_sender: oneshot::Sender<schema::EstimateOrderResponse>,
+    },
+    GetOpenOrders {
+        request_id: Uuid,
+        account_id: i32,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::GetOpenOrdersResponse>,
+    },
+    GetOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        order_id: u64,
+        response_sender: oneshot::Sender<schema::GetOrderResponse>,
+    },
+    // 运维强制撤单：不检查订单所属账户，仅管理接口使用
+    ForceCancelOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        order_id: u64,
+        reason: String,
+        response_sender: oneshot::Sender<schema::CancelOrderResponse>,
+    },
+    // 订单簿挂单规模，仅管理接口使用
+    GetOpenInterest {
+        request_id: Uuid,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::GetOpenInterestResponse>,
+    },
+    // 导出和装入订单簿快照，用于新节点冷启动
+    DumpOrderBook {
+        request_id: Uuid,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::DumpOrderBookSnapshotResponse>,
+    },
+    LoadOrderBook {
+        request_id: Uuid,
+        symbol_id: i32,
+        snapshot: Vec<u8>,
+        force: bool,  // 替换已有挂单的订单簿，被替换的订单撤销并解冻
+        funded: bool,  // 快照中的未完成订单已由排序器冻结余额，否则只能装入没有未完成订单的快照
+        response_sender: oneshot::Sender<schema::LoadOrderBookSnapshotResponse>,
+    },
+    // 删除交易对：由撮合线程确认订单簿上没有挂单后再删除
+    DeleteSymbol {
+        request_id: Uuid,
+        symbol_id: i32,
+        response_sender: oneshot::Sender<schema::DeleteSymbolResponse>,
+    },
     CancelOrder {
         request_id: Uuid,
         symbol_id: i32,
         account_id: i32,
         order_id: u64,
+        client_order_id: Option<String>,
         response_sender: oneshot::Sender<schema::CancelOrderResponse>,
     },
+    CancelAllOrders {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32, // ALL_ACCOUNTS 表示所有账户，仅管理接口使用
+        response_sender: oneshot::Sender<schema::CancelAllOrdersResponse>,
+    },
+    AmendOrder {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_id: u64,
+        side: i32,
+        price: String,
+        quantity: String,
+        // SequencerProcessor 为改单增加的占用预先冻结（保证金模式下为已检查）的金额；
+        // None 表示尚未冻结，撮合线程算出需要增加的金额后退回排序器冻结
+        prefrozen_amount: Option<rust_decimal::Decimal>,
+        response_sender: oneshot::Sender<schema::AmendOrderResponse>,
+    },
 }
 
 // 新增：成交执行消息，用于从撮合引擎回调到SequencerProcessor
 #[derive(Debug)]
 pub enum TradeExecutionMessage {
-    ExecuteTrade {
-        trade: Trade,
-        original_response_sender: oneshot::Sender<schema::PlaceOrderResponse>,
-    },
-    // 单个账户结算消息：包含该账户的余额变更
+    // 单个账户结算消息：撮合线程按 maker 和 taker 所在分片分别发送
     SettleAccount {
         account_id: i32,
         symbol_id: i32,
+        trade_id: u64, // 结算去重键之一；taker 汇总多笔成交时为第一笔的ID，0 表示不去重
+        taker: bool,   // 结算去重键之一，自成交时区分同一账户的 maker 和 taker 结算
         deduct_currency_id: i32,  // 需要扣除的币种ID（从冻结余额扣除）
         deduct_amount: rust_decimal::Decimal,  // 需要扣除的数量
         add_currency_id: i32,      // 需要增加的币种ID（增加到可用余额）
         add_amount: rust_decimal::Decimal,      // 需要增加的数量
+        fee_currency_id: i32,                   // 手续费币种ID（从可用余额扣除）
+        fee_amount: rust_decimal::Decimal,      // 手续费
+        fee_reserve: rust_decimal::Decimal,     // 买单下单时为这些成交预留的手续费，结算时从冻结余额解冻
+        span: tracing::Span,                    // 产生成交的撮合步骤，结算记录为其子 span
+        ack: Option<Arc<SettlementAck>>,        // 开启结算确认时持有下单响应，所有结算完成后回复
+    },
+    // 手续费入账：由付费账户所在分片发往手续费账户所在分片
+    CollectFee {
+        currency_id: i32,
+        amount: rust_decimal::Decimal,
     },
     UnfreezeOrder {
         order: crate::matching::Order,
+        // 撤单请求：解冻后由 SequencerProcessor 填入退还金额并回复
+        response_sender: Option<oneshot::Sender<schema::CancelOrderResponse>>,
+        note: Option<String>, // 运维强制撤单的原因，写入余额变更记录
+    },
+    // 改单结果：释放预冻结金额中多余的部分
+    OrderAmended {
+        account_id: i32,
+        symbol_id: i32,
+        side: i32,
+        prefrozen_amount: rust_decimal::Decimal,
+        orders: Option<Box<(crate::matching::Order, crate::matching::Order)>>, // (修改前, 修改后)，None 表示修改失败
+        response: schema::AmendOrderResponse,
+        response_sender: oneshot::Sender<schema::AmendOrderResponse>,
+    },
+    // 改单需要增加占用：由撮合线程按原订单剩余冻结额算出差额，排序器冻结后重新转发改单
+    AmendNeedsFreeze {
+        request_id: Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_id: u64,
+        side: i32,
+        price: String,
+        quantity: String,
+        amount: rust_decimal::Decimal,    // 需要增加冻结的金额，0 表示只检查风控
+        order_request_id: Uuid,           // 被修改订单的请求ID，排序器按它替换风控计数中的名义价值
+        notional: rust_decimal::Decimal,  // 改单后剩余部分的名义价值
+        response_sender: oneshot::Sender<schema::AmendOrderResponse>,
     },
+    // 订单成交后的剩余数量，排序器据此更新账户的风控计数；剩余为 0 表示已完成
+    OrderProgress {
+        account_id: i32,
+        request_id: Uuid,
+        remaining_quantity: rust_decimal::Decimal,
+    },
+    // 跨分片划转第二阶段：转出账户已扣减，由转入账户所在分片入账并回复
+    TransferIn {
+        transfer_id: Uuid, // 转入分片随入账记录写入，重启时与转出记录配对
+        account_id: i32,
+        currency_id: i32,
+        amount: rust_decimal::Decimal,
+        response_sender: oneshot::Sender<schema::TransferResponse>,
+    },
+    // 停机排空：所有撮合线程已退出，之后不会再有新的结算消息，收到后不再转发手续费
+    Drain,
+}
+
+// 结算确认：taker 的下单响应随本次成交的所有结算消息分发到各排序器分片，
+// 最后一个引用释放时（全部结算完成，或消息无法投递被丢弃）才回复调用方
+#[derive(Debug)]
+pub struct SettlementAck {
+    pending: Option<(oneshot::Sender<schema::PlaceOrderResponse>, schema::PlaceOrderResponse)>,
+}
+
+impl SettlementAck {
+    pub fn new(
+        response_sender: oneshot::Sender<schema::PlaceOrderResponse>,
+        response: schema::PlaceOrderResponse,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            pending: Some((response_sender, response)),
+        })
+    }
+}
+
+impl Drop for SettlementAck {
+    fn drop(&mut self) {
+        if let Some((response_sender, response)) = self.pending.take() {
+            let _ = response_sender.send(response);
+        }
+    }
+}
+
+impl MatchMessage {
+    // 撮合分片按交易对路由，所有请求都带交易对ID
+    pub fn symbol_id(&self) -> i32 {
+        match self {
+            MatchMessage::PlaceOrder { symbol_id, .. }
+            | MatchMessage::ValidateOrder { symbol_id, .. }
+            | MatchMessage::GetOrderBook { symbol_id, .. }
+            | MatchMessage::GetTrades { symbol_id, .. }
+            | MatchMessage::GetTicker { symbol_id, .. }
+            | MatchMessage::GetBbo { symbol_id, .. }
+            | MatchMessage::EstimateOrder { symbol_id, .. }
+            | MatchMessage::GetOpenOrders { symbol_id, .. }
+            | MatchMessage::GetOrder { symbol_id, .. }
+            | MatchMessage::ForceCancelOrder { symbol_id, .. }
+            | MatchMessage::GetOpenInterest { symbol_id, .. }
+            | MatchMessage::DumpOrderBook { symbol_id, .. }
+            | MatchMessage::LoadOrderBook { symbol_id, .. }
+            | MatchMessage::DeleteSymbol { symbol_id, .. }
+            | MatchMessage::CancelOrder { symbol_id, .. }
+            | MatchMessage::CancelAllOrders { symbol_id, .. }
+            | MatchMessage::AmendOrder { symbol_id, .. } => *symbol_id,
+        }
+    }
 }
@@ -0,0 +1,204 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use tokio::net::TcpListener;
+
+// 延迟直方图的桶上限（秒）
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+// 处理器线程直接累加原子计数，热路径上没有锁
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()], // 各桶自身的计数，输出时累加
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Metrics {
+    pub orders_placed: Counter,
+    pub trades: Counter,
+    pub cancels: Counter,
+    pub rejects: Counter,
+    pub match_latency: Histogram,      // 撮合引擎处理单个下单请求的耗时
+    pub settlement_latency: Histogram, // SequencerProcessor 结算单个账户的耗时
+}
+
+static METRICS: Metrics = Metrics {
+    orders_placed: Counter::new(),
+    trades: Counter::new(),
+    cancels: Counter::new(),
+    rejects: Counter::new(),
+    match_latency: Histogram::new(),
+    settlement_latency: Histogram::new(),
+};
+
+// 进程内所有处理器共享的指标
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    // Prometheus 文本格式
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let counters = [
+            (
+                "lightning_orders_placed_total",
+                "Orders accepted by the matching engine",
+                &self.orders_placed,
+            ),
+            ("lightning_trades_total", "Trades executed", &self.trades),
+            ("lightning_cancels_total", "Orders cancelled by request", &self.cancels),
+            (
+                "lightning_rejects_total",
+                "Orders rejected before or during matching",
+                &self.rejects,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, counter.get());
+        }
+
+        let histograms = [
+            ("lightning_match_latency_seconds", "Matching latency per order", &self.match_latency),
+            (
+                "lightning_settlement_latency_seconds",
+                "Settlement latency per account",
+                &self.settlement_latency,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} histogram", name);
+            let mut cumulative = 0;
+            for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+            }
+            let count = histogram.count();
+            let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+            let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+            let _ = writeln!(output, "{}_sum {}", name, sum);
+            let _ = writeln!(output, "{}_count {}", name, count);
+        }
+        output
+    }
+}
+
+// 指标接口与 REST 网关一样由 axum 提供，连接处理、超时和接受连接出错后的重试都由 axum 负责
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(render_metrics))
+}
+
+async fn render_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(),
+    )
+}
+
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    axum::serve(listener, router()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(5));
+        histogram.observe(Duration::from_micros(200));
+        histogram.observe(Duration::from_secs(2));
+        assert_eq!(histogram.count(), 3);
+
+        let metrics = Metrics {
+            orders_placed: Counter::new(),
+            trades: Counter::new(),
+            cancels: Counter::new(),
+            rejects: Counter::new(),
+            match_latency: histogram,
+            settlement_latency: Histogram::new(),
+        };
+        let output = metrics.render();
+        assert!(output.contains("lightning_match_latency_seconds_bucket{le=\"0.00001\"} 1\n"));
+        assert!(output.contains("lightning_match_latency_seconds_bucket{le=\"0.0005\"} 2\n"));
+        assert!(output.contains("lightning_match_latency_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(output.contains("lightning_match_latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("lightning_match_latency_seconds_count 3\n"));
+        assert!(output.contains("lightning_orders_placed_total 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_router_serves_metrics_and_rejects_other_paths() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router().oneshot(request("/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("lightning_orders_placed_total"));
+
+        let response = router().oneshot(request("/other")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
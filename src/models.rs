@@ -1,26 +1,163 @@
-use rust_decimal::Decimal;
+use crate::idempotency::{
+    CachedResponse, IdempotencyCache, RequestKey, SettledTrades, SettlementKey,
+};
+use crate::matching::TradingRules;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, RwLock};
 use thiserror::Error;
 
-// 生成的 proto 代码
+// 生成的 proto 代码 (服务端流的关联类型按 rpc 名生成，保持 camelCase)
+#[allow(non_camel_case_types)]
 pub mod schema {
     tonic::include_proto!("schema");
 }
 
 use schema::*;
 
+// 系统手续费账户，所有成交手续费和舍入零头汇入该账户；使用负数，不会与用户账户
+// 和表示全部账户的 0 冲突
+pub const FEE_ACCOUNT_ID: i32 = -1;
+
+// 余额审计日志最多保留的记录数，超出后丢弃最早的记录
+pub const AUDIT_LOG_CAPACITY: usize = 100_000;
+pub const DEFAULT_BALANCE_HISTORY_LIMIT: usize = 50;
+pub const MAX_BALANCE_HISTORY_LIMIT: usize = 500;
+
+// 币种精度上限，与 Decimal 支持的最大小数位数一致
+pub const MAX_CURRENCY_SCALE: u32 = 28;
+
 #[derive(Error, Debug)]
 pub enum BalanceError {
     #[error("Insufficient balance")]
     InsufficientBalance,
     #[error("Invalid amount: {0}")]
     InvalidAmount(String),
+    // 以下订单参数错误只返回具体原因
+    #[error("{0}")]
+    InvalidPrice(String),
+    #[error("{0}")]
+    InvalidQuantity(String),
+    #[error("{0}")]
+    BelowMinNotional(String),
+    #[error("{0}")]
+    AboveMaxNotional(String),
+    #[error("{0}")]
+    InvalidOrder(String),
+    #[error("Risk limit exceeded: {0}")]
+    RiskLimitExceeded(String),
     #[error("Account not found")]
     AccountNotFound,
     #[error("Currency not found")]
     CurrencyNotFound,
+    #[error("Order not found")]
+    OrderNotFound,
+    #[error("Withdrawal hold not found")]
+    HoldNotFound,
+    #[error("Failed to write WAL: {0}")]
+    WalWrite(String),
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Inconsistent balance for currency {0}")]
+    InconsistentBalance(i32),
+    #[error("{0}")]
+    MarketHalted(String),
+    #[error("Invalid nonce: {0}")]
+    InvalidNonce(String),
+}
+
+// 价格 * 数量，超出 Decimal 范围时返回错误而不是 panic
+pub fn checked_notional(price: Decimal, quantity: Decimal) -> Result<Decimal, BalanceError> {
+    price.checked_mul(quantity).ok_or_else(|| {
+        BalanceError::InvalidAmount(format!("Notional of {} x {} overflows", price, quantity))
+    })
+}
+
+impl BalanceError {
+    // 下单被拒绝时返回给客户端的原因
+    pub fn reject_reason(&self) -> RejectReason {
+        match self {
+            BalanceError::InsufficientBalance => RejectReason::InsufficientBalance,
+            BalanceError::InvalidAmount(_) => RejectReason::InvalidAmount,
+            BalanceError::InvalidPrice(_) => RejectReason::InvalidPrice,
+            BalanceError::InvalidQuantity(_) => RejectReason::InvalidQuantity,
+            BalanceError::BelowMinNotional(_) => RejectReason::BelowMinNotional,
+            BalanceError::AboveMaxNotional(_) => RejectReason::AboveMaxNotional,
+            BalanceError::InvalidOrder(_) => RejectReason::InvalidOrder,
+            BalanceError::RiskLimitExceeded(_) => RejectReason::RiskLimitExceeded,
+            BalanceError::AccountNotFound => RejectReason::UnknownAccount,
+            // 下单时找不到交易对也报告为 CurrencyNotFound
+            BalanceError::CurrencyNotFound => RejectReason::UnknownSymbol,
+            BalanceError::OrderNotFound => RejectReason::OrderNotFound,
+            BalanceError::MarketHalted(_) => RejectReason::MarketHalted,
+            BalanceError::InvalidNonce(_) => RejectReason::InvalidNonce,
+            BalanceError::HoldNotFound
+            | BalanceError::WalWrite(_)
+            | BalanceError::InvalidSnapshot(_)
+            | BalanceError::InconsistentBalance(_) => RejectReason::InternalError,
+        }
+    }
+}
+
+impl PlaceOrderResponse {
+    // 下单响应；拒绝或整单撤销时同时填入原因编号和原因名称
+    pub fn with_reason(code: i32, message: String, id: i64, reason: RejectReason) -> Self {
+        let reason_code = match reason {
+            RejectReason::NoReject => String::new(),
+            reason => reason.as_str_name().to_string(),
+        };
+        Self {
+            code,
+            message: Some(message),
+            id,
+            reject_reason: reason as i32,
+            reason_code,
+            ..Default::default()
+        }
+    }
+}
+
+// 划转结果转换为响应：日志写入失败返回 500，其余错误返回 400
+pub fn transfer_response(result: Result<(), BalanceError>) -> TransferResponse {
+    match result {
+        Ok(()) => TransferResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+        },
+        Err(e @ BalanceError::WalWrite(_)) => TransferResponse {
+            code: 500,
+            message: Some(e.to_string()),
+        },
+        Err(e) => TransferResponse {
+            code: 400,
+            message: Some(e.to_string()),
+        },
+    }
+}
+
+// 提现结果转换为响应：冻结单不存在返回 404，日志写入失败返回 500，其余错误返回 400
+pub fn withdrawal_response(result: Result<(u64, Balance), BalanceError>) -> WithdrawalResponse {
+    let code = match &result {
+        Ok(_) => 0,
+        Err(BalanceError::HoldNotFound) => 404,
+        Err(BalanceError::WalWrite(_)) => 500,
+        Err(_) => 400,
+    };
+    match result {
+        Ok((hold_id, balance)) => WithdrawalResponse {
+            code,
+            message: Some("Success".to_string()),
+            hold_id,
+            data: Some(balance),
+        },
+        Err(e) => WithdrawalResponse {
+            code,
+            message: Some(e.to_string()),
+            hold_id: 0,
+            data: None,
+        },
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +165,10 @@ pub struct Currency {
     pub id: i32,
     pub name: String,
     pub display_name: String,
+    #[serde(default)]
+    pub scale: Option<u32>, // 余额小数位数，None 表示按输入保留
+    #[serde(default)]
+    pub display_scale: Option<u32>, // 返回给客户端的小数位数，None 表示按余额精度显示
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +177,80 @@ pub struct Symbol {
     pub name: String,
     pub base: i32,  // base currency id
     pub quote: i32, // quote currency id
+    #[serde(default)]
+    pub price_tick: Decimal, // 价格最小变动单位，0 表示不限制
+    #[serde(default)]
+    pub quantity_step: Decimal, // 数量最小变动单位，0 表示不限制
+    #[serde(default)]
+    pub min_notional: Decimal, // 最小成交额，0 表示不限制
+    #[serde(default)]
+    pub min_quantity: Decimal, // 单笔最小数量，0 表示不限制
+    #[serde(default)]
+    pub max_quantity: Decimal, // 单笔最大数量，0 表示不限制
+    #[serde(default)]
+    pub max_notional: Decimal, // 单笔最大成交额，0 表示不限制
+    #[serde(default)]
+    pub status: SymbolStatus,
+}
+
+// 交易对状态，暂停期间挂单保留在订单簿中，恢复后继续撮合
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum SymbolStatus {
+    #[default]
+    Active,
+    Halted,     // 拒绝下单、撤单和改单
+    CancelOnly, // 只接受撤单
+}
+
+impl SymbolStatus {
+    pub fn accepts_orders(self) -> bool {
+        self == SymbolStatus::Active
+    }
+
+    pub fn accepts_cancels(self) -> bool {
+        self != SymbolStatus::Halted
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SymbolStatus::Active => "active",
+            SymbolStatus::Halted => "halted",
+            SymbolStatus::CancelOnly => "cancel-only",
+        }
+    }
+}
+
+impl From<SymbolStatus> for schema::SymbolStatus {
+    fn from(status: SymbolStatus) -> Self {
+        match status {
+            SymbolStatus::Active => schema::SymbolStatus::Active,
+            SymbolStatus::Halted => schema::SymbolStatus::Halted,
+            SymbolStatus::CancelOnly => schema::SymbolStatus::CancelOnly,
+        }
+    }
+}
+
+impl From<schema::SymbolStatus> for SymbolStatus {
+    fn from(status: schema::SymbolStatus) -> Self {
+        match status {
+            schema::SymbolStatus::Active => SymbolStatus::Active,
+            schema::SymbolStatus::Halted => SymbolStatus::Halted,
+            schema::SymbolStatus::CancelOnly => SymbolStatus::CancelOnly,
+        }
+    }
+}
+
+impl Symbol {
+    pub fn trading_rules(&self) -> TradingRules {
+        TradingRules {
+            price_tick: self.price_tick,
+            quantity_step: self.quantity_step,
+            min_notional: self.min_notional,
+            min_quantity: self.min_quantity,
+            max_quantity: self.max_quantity,
+            max_notional: self.max_notional,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +259,10 @@ pub struct AccountBalance {
     pub total: Decimal,
     pub frozen: Decimal,
     pub available: Decimal,
+    // 按用途标记的保留余额（如保证金、质押），不可用于下单或提现
+    // total = available + frozen + 各项保留余额之和
+    #[serde(default)]
+    pub reserved: HashMap<String, Decimal>,
 }
 
 impl AccountBalance {
@@ -53,9 +272,15 @@ impl AccountBalance {
             total: Decimal::ZERO,
             frozen: Decimal::ZERO,
             available: Decimal::ZERO,
+            reserved: HashMap::new(),
         }
     }
 
+    // 总额 = 可用 + 冻结 + 各项保留余额之和
+    pub fn is_consistent(&self) -> bool {
+        self.total == self.available + self.frozen + self.reserved.values().sum::<Decimal>()
+    }
+
     pub fn increase(&mut self, amount: Decimal) -> Result<(), BalanceError> {
         if amount <= Decimal::ZERO {
             return Err(BalanceError::InvalidAmount(
@@ -95,6 +320,21 @@ impl AccountBalance {
         Ok(())
     }
 
+    // 成交结算或提现确认：从冻结余额和总额中扣除，冻结余额不足时不做修改
+    pub fn settle_frozen(&mut self, amount: Decimal) -> Result<(), BalanceError> {
+        if amount < Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must not be negative".to_string(),
+            ));
+        }
+        if self.frozen < amount {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        self.frozen -= amount;
+        self.total -= amount;
+        Ok(())
+    }
+
     pub fn unfreeze(&mut self, amount: Decimal) -> Result<(), BalanceError> {
         if amount <= Decimal::ZERO {
             return Err(BalanceError::InvalidAmount(
@@ -108,6 +348,78 @@ impl AccountBalance {
         self.available += amount;
         Ok(())
     }
+
+    // 从可用余额转入指定用途的保留余额，总额不变
+    pub fn reserve(&mut self, tag: &str, amount: Decimal) -> Result<(), BalanceError> {
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must be positive".to_string(),
+            ));
+        }
+        if self.available < amount {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        self.available -= amount;
+        *self.reserved.entry(tag.to_string()).or_default() += amount;
+        Ok(())
+    }
+
+    // 从指定用途的保留余额退回可用余额，保留余额不足时不做修改
+    pub fn release(&mut self, tag: &str, amount: Decimal) -> Result<(), BalanceError> {
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must be positive".to_string(),
+            ));
+        }
+        let Some(reserved) = self.reserved.get_mut(tag) else {
+            return Err(BalanceError::InsufficientBalance);
+        };
+        if *reserved < amount {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        *reserved -= amount;
+        if reserved.is_zero() {
+            self.reserved.remove(tag);
+        }
+        self.available += amount;
+        Ok(())
+    }
+
+    pub fn reserved_total(&self) -> Decimal {
+        self.reserved.values().sum()
+    }
+}
+
+impl From<&AccountBalance> for Balance {
+    fn from(balance: &AccountBalance) -> Self {
+        display_balance(balance, None)
+    }
+}
+
+// 按显示精度格式化金额，向零截断后补齐小数位，显示的余额不会超过实际余额；
+// 结果只用于返回给客户端，不能再解析回内部状态，内部计算始终使用完整精度
+pub fn display_amount(amount: Decimal, display_scale: Option<u32>) -> String {
+    let Some(display_scale) = display_scale else {
+        return amount.to_string();
+    };
+    let mut amount = amount.round_dp_with_strategy(display_scale, RoundingStrategy::ToZero);
+    amount.rescale(display_scale);
+    amount.to_string()
+}
+
+pub fn display_balance(balance: &AccountBalance, display_scale: Option<u32>) -> Balance {
+    let display = |amount: Decimal| display_amount(amount, display_scale);
+    Balance {
+        currency: balance.currency_id.to_string(),
+        value: display(balance.total),
+        frozen: display(balance.frozen),
+        available: display(balance.available),
+        reserved: balance
+            .reserved
+            .iter()
+            .map(|(tag, &amount)| (tag.clone(), display(amount)))
+            .collect(),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -131,76 +443,371 @@ impl Account {
     }
 }
 
+// 提现冻结：发起提现时冻结，确认后扣除，取消后解冻
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithdrawalHold {
+    pub account_id: i32,
+    pub currency_id: i32,
+    pub amount: Decimal,
+}
+
+// 余额变更原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditReason {
+    Increase,
+    Decrease,
+    Freeze,
+    Unfreeze,
+    TradeSettle,
+    Fee,
+    Transfer,
+    Withdrawal,
+    AdminAdjustment, // 运维调账
+    AdminCancel,     // 运维强制撤单后解冻
+}
+
+// 一次余额变更，总额变化为可用和冻结变化之和
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub account_id: i32,
+    pub currency_id: i32,
+    pub available_delta: Decimal,
+    pub frozen_delta: Decimal,
+    pub reason: AuditReason,
+    pub timestamp: u64, // 毫秒
+    pub note: Option<String>, // 运维操作填写的原因
+}
+
+impl From<&AuditEntry> for BalanceChange {
+    fn from(entry: &AuditEntry) -> Self {
+        let reason = match entry.reason {
+            AuditReason::Increase => BalanceChangeReason::Increase,
+            AuditReason::Decrease => BalanceChangeReason::Decrease,
+            AuditReason::Freeze => BalanceChangeReason::Freeze,
+            AuditReason::Unfreeze => BalanceChangeReason::Unfreeze,
+            AuditReason::TradeSettle => BalanceChangeReason::TradeSettle,
+            AuditReason::Fee => BalanceChangeReason::Fee,
+            AuditReason::Transfer => BalanceChangeReason::Transfer,
+            AuditReason::Withdrawal => BalanceChangeReason::Withdrawal,
+            AuditReason::AdminAdjustment => BalanceChangeReason::AdminAdjustment,
+            AuditReason::AdminCancel => BalanceChangeReason::AdminCancel,
+        };
+        BalanceChange {
+            account_id: entry.account_id,
+            currency_id: entry.currency_id,
+            available_delta: entry.available_delta.to_string(),
+            frozen_delta: entry.frozen_delta.to_string(),
+            reason: reason as i32,
+            timestamp: entry.timestamp as i64,
+            note: entry.note.clone(),
+        }
+    }
+}
+
 // 消息类型定义
 
 // 余额管理器
 #[derive(Debug)]
 pub struct BalanceManager {
     pub accounts: HashMap<i32, Account>,
+    pub withdrawal_holds: HashMap<u64, WithdrawalHold>, // 未确认也未取消的提现
+    next_hold_id: u64,
+    audit_log: VecDeque<AuditEntry>, // 环形缓冲，按发生顺序
+    audit_log_capacity: usize,
+    // 币种精度：余额每次变更后统一为该小数位数，未设置的币种按输入保留
+    currency_scales: HashMap<i32, u32>,
+    // 币种显示精度：只影响查询和充提响应中的余额字符串，不写入预写日志
+    display_scales: HashMap<i32, u32>,
+    // 带幂等键的请求及其响应，重复请求直接返回，不再重复记账
+    request_cache: IdempotencyCache,
+    // 已结算的成交，随预写日志重放恢复，防止重复投递的结算消息重复记账
+    settled_trades: SettledTrades,
+    // 每个账户最后接受的客户端序号，随预写日志重放恢复
+    nonces: HashMap<i32, i64>,
+    // 上次取出后余额可能变化的账户，开启后才记录，用于增量发布账户视图
+    dirty_accounts: Option<HashSet<i32>>,
+}
+
+impl Default for BalanceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 查询余额的响应：账户不存在时返回 404，任一币种余额不一致时返回 500，
+// 未指定币种时返回所有币种，余额按 display_scale 给出的显示精度格式化
+pub fn account_response(
+    account: Option<&Account>,
+    currency_id: Option<i32>,
+    display_scale: impl Fn(i32) -> Option<u32>,
+) -> GetAccountResponse {
+    let Some(account) = account else {
+        return GetAccountResponse {
+            code: 404,
+            message: Some("Account not found".to_string()),
+            data: HashMap::new(),
+        };
+    };
+    if let Some(balance) = account.balances.values().find(|balance| !balance.is_consistent()) {
+        return GetAccountResponse {
+            code: 500,
+            message: Some(BalanceError::InconsistentBalance(balance.currency_id).to_string()),
+            data: HashMap::new(),
+        };
+    }
+
+    let data = account
+        .balances
+        .iter()
+        .filter(|(id, _)| currency_id.is_none_or(|currency_id| **id == currency_id))
+        .map(|(&id, balance)| (id, display_balance(balance, display_scale(id))))
+        .collect();
+
+    GetAccountResponse {
+        code: 0,
+        message: Some("Success".to_string()),
+        data,
+    }
 }
 
 impl BalanceManager {
     pub fn new() -> Self {
         Self {
             accounts: HashMap::new(),
+            withdrawal_holds: HashMap::new(),
+            next_hold_id: 1,
+            audit_log: VecDeque::new(),
+            audit_log_capacity: AUDIT_LOG_CAPACITY,
+            currency_scales: HashMap::new(),
+            display_scales: HashMap::new(),
+            request_cache: IdempotencyCache::default(),
+            settled_trades: SettledTrades::default(),
+            nonces: HashMap::new(),
+            dirty_accounts: None,
         }
     }
 
-    pub fn handle_get_account(
-        &self,
+    // 开始记录余额可能变化的账户，由 take_dirty_accounts 取出
+    pub fn track_dirty_accounts(&mut self) {
+        self.dirty_accounts.get_or_insert_with(HashSet::new);
+    }
+
+    pub fn take_dirty_accounts(&mut self) -> Vec<i32> {
+        match &mut self.dirty_accounts {
+            Some(dirty_accounts) => dirty_accounts.drain().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // 同一账户已处理过的请求，返回首次处理时的响应
+    pub fn cached_response(
+        &mut self,
         account_id: i32,
-        currency_id: Option<i32>,
-    ) -> GetAccountResponse {
-        // 检查账户是否存在
-        let account = match self.accounts.get(&account_id) {
-            Some(account) => account,
-            None => {
-                return GetAccountResponse {
-                    code: 404,
-                    message: Some("Account not found".to_string()),
-                    data: HashMap::new(),
-                };
-            }
+        key: i64,
+        now: u64,
+    ) -> Option<CachedResponse> {
+        self.request_cache.get(account_id, key, now).cloned()
+    }
+
+    pub fn remember_response(
+        &mut self,
+        account_id: i32,
+        request: RequestKey,
+        response: CachedResponse,
+    ) {
+        self.request_cache.insert(account_id, request, response);
+    }
+
+    pub fn forget_request(&mut self, account_id: i32, key: i64) {
+        self.request_cache.remove(account_id, key);
+    }
+
+    pub fn is_settled(&self, key: &SettlementKey) -> bool {
+        self.settled_trades.contains(key)
+    }
+
+    // 记录一次成功的成交结算，已经结算过时返回 false
+    pub fn record_settlement(&mut self, key: SettlementKey) -> bool {
+        self.settled_trades.insert(key)
+    }
+
+    // 账户的第一个序号作为基准，之后必须恰好是上一个序号加 1
+    pub fn check_nonce(&self, account_id: i32, nonce: i64) -> Result<(), BalanceError> {
+        let Some(&last) = self.nonces.get(&account_id) else {
+            return Ok(());
         };
+        let expected = last.checked_add(1);
+        if expected != Some(nonce) {
+            return Err(BalanceError::InvalidNonce(format!(
+                "got {}, expected {}",
+                nonce,
+                expected.map_or_else(|| "none".to_string(), |expected| expected.to_string())
+            )));
+        }
+        Ok(())
+    }
 
-        let mut data = HashMap::new();
-
-        match currency_id {
-            Some(currency_id) => {
-                // 查询特定币种
-                if let Some(balance) = account.balances.get(&currency_id) {
-                    data.insert(
-                        currency_id,
-                        Balance {
-                            currency: currency_id.to_string(),
-                            value: balance.total.to_string(),
-                            frozen: balance.frozen.to_string(),
-                            available: balance.available.to_string(),
-                        },
-                    );
-                }
-            }
-            None => {
-                // 查询所有币种
-                for (&currency_id, balance) in &account.balances {
-                    data.insert(
-                        currency_id,
-                        Balance {
-                            currency: currency_id.to_string(),
-                            value: balance.total.to_string(),
-                            frozen: balance.frozen.to_string(),
-                            available: balance.available.to_string(),
-                        },
-                    );
-                }
+    pub fn accept_nonce(&mut self, account_id: i32, nonce: i64) {
+        self.nonces.insert(account_id, nonce);
+    }
+
+    pub fn currency_scale(&self, currency_id: i32) -> Option<u32> {
+        self.currency_scales.get(&currency_id).copied()
+    }
+
+    // 设置币种精度，已有余额按新精度四舍六入五成双
+    pub fn set_currency_scale(&mut self, currency_id: i32, scale: Option<u32>) {
+        match scale {
+            Some(scale) => self.currency_scales.insert(currency_id, scale),
+            None => self.currency_scales.remove(&currency_id),
+        };
+        let account_ids: Vec<i32> = self.accounts.keys().copied().collect();
+        for account_id in account_ids {
+            if self.accounts[&account_id].balances.contains_key(&currency_id) {
+                self.normalize(account_id, currency_id);
             }
         }
+    }
 
-        GetAccountResponse {
-            code: 0,
-            message: Some("Success".to_string()),
-            data,
+    pub fn display_scale(&self, currency_id: i32) -> Option<u32> {
+        self.display_scales.get(&currency_id).copied()
+    }
+
+    pub fn set_display_scale(&mut self, currency_id: i32, display_scale: Option<u32>) {
+        match display_scale {
+            Some(display_scale) => self.display_scales.insert(currency_id, display_scale),
+            None => self.display_scales.remove(&currency_id),
+        };
+    }
+
+    // 返回给客户端的余额，按币种显示精度格式化
+    fn balance_data(&self, balance: &AccountBalance) -> Balance {
+        display_balance(balance, self.display_scale(balance.currency_id))
+    }
+
+    // 按显示精度返回账户某币种的余额
+    pub fn balance_snapshot(&mut self, account_id: i32, currency_id: i32) -> Balance {
+        let balance = self.account_balance(account_id, currency_id).clone();
+        self.balance_data(&balance)
+    }
+
+    // 按币种精度舍入（四舍六入五成双），未设置精度时原样返回
+    pub fn round_amount(&self, currency_id: i32, amount: Decimal) -> Decimal {
+        let mut amount = amount;
+        if let Some(scale) = self.currency_scale(currency_id) {
+            // rescale 按远离零舍入，先按银行家舍入再补齐小数位
+            amount = amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+            amount.rescale(scale);
+        }
+        amount
+    }
+
+    // 外部输入的金额不能超过币种精度
+    fn check_precision(&self, currency_id: i32, amount: Decimal) -> Result<(), BalanceError> {
+        match self.currency_scale(currency_id) {
+            Some(scale) if amount.normalize().scale() > scale => Err(BalanceError::InvalidAmount(
+                format!("Amount exceeds currency precision of {} decimal places", scale),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // 余额统一为币种精度，保证存储和返回的值格式一致
+    fn normalize(&mut self, account_id: i32, currency_id: i32) {
+        let Some(scale) = self.currency_scale(currency_id) else {
+            return;
+        };
+        let balance = self.account_balance(account_id, currency_id);
+        balance.total.rescale(scale);
+        balance.frozen.rescale(scale);
+        balance.available.rescale(scale);
+        for amount in balance.reserved.values_mut() {
+            amount.rescale(scale);
+        }
+    }
+
+    // 记录一次余额变更，没有实际变化时不记录
+    fn record(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        available_delta: Decimal,
+        frozen_delta: Decimal,
+        reason: AuditReason,
+    ) {
+        self.record_note(account_id, currency_id, available_delta, frozen_delta, reason, None);
+    }
+
+    // 运维操作的余额变更同时记录操作原因
+    fn record_note(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        available_delta: Decimal,
+        frozen_delta: Decimal,
+        reason: AuditReason,
+        note: Option<String>,
+    ) {
+        if available_delta.is_zero() && frozen_delta.is_zero() {
+            return;
+        }
+        if self.audit_log.len() >= self.audit_log_capacity {
+            self.audit_log.pop_front();
+        }
+        self.audit_log.push_back(AuditEntry {
+            account_id,
+            currency_id,
+            available_delta,
+            frozen_delta,
+            reason,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            note,
+        });
+    }
+
+    // 账户的余额变更记录，按时间倒序；currency_id 为 None 时返回所有币种
+    pub fn get_balance_history(
+        &self,
+        account_id: i32,
+        currency_id: Option<i32>,
+        limit: usize,
+    ) -> Vec<&AuditEntry> {
+        self.audit_log
+            .iter()
+            .rev()
+            .filter(|entry| {
+                entry.account_id == account_id
+                    && currency_id.is_none_or(|currency_id| entry.currency_id == currency_id)
+            })
+            .take(limit)
+            .collect()
+    }
+
+    // 账户全部币种余额的一致快照：在所属排序分片线程内整体拷贝，不会读到结算到一半的余额；
+    // 任一币种不满足 总额 = 可用 + 冻结 + 保留 时返回错误
+    pub fn account_snapshot(
+        &self,
+        account_id: i32,
+    ) -> Result<HashMap<i32, AccountBalance>, BalanceError> {
+        let account = self.accounts.get(&account_id).ok_or(BalanceError::AccountNotFound)?;
+        let balances = account.balances.clone();
+        if let Some(balance) = balances.values().find(|balance| !balance.is_consistent()) {
+            return Err(BalanceError::InconsistentBalance(balance.currency_id));
         }
+        Ok(balances)
+    }
+
+    pub fn handle_get_account(
+        &self,
+        account_id: i32,
+        currency_id: Option<i32>,
+    ) -> GetAccountResponse {
+        account_response(self.accounts.get(&account_id), currency_id, |currency_id| {
+            self.display_scale(currency_id)
+        })
     }
 
     pub fn handle_increase(
@@ -219,21 +826,22 @@ impl BalanceManager {
                 };
             }
         };
+        if let Err(e) = self.check_precision(currency_id, amount) {
+            return IncreaseResponse {
+                code: 400,
+                message: Some(e.to_string()),
+                data: None,
+            };
+        }
 
-        let account = self
-            .accounts
-            .entry(account_id)
-            .or_insert_with(|| Account::new(account_id));
-        let balance = account.get_balance(currency_id);
+        let balance = self.account_balance(account_id, currency_id);
 
         match balance.increase(amount) {
             Ok(_) => {
-                let balance_data = Balance {
-                    currency: currency_id.to_string(),
-                    value: balance.total.to_string(),
-                    frozen: balance.frozen.to_string(),
-                    available: balance.available.to_string(),
-                };
+                self.normalize(account_id, currency_id);
+                let balance_data =
+                    self.balance_data(&self.accounts[&account_id].balances[&currency_id]);
+                self.record(account_id, currency_id, amount, Decimal::ZERO, AuditReason::Increase);
                 IncreaseResponse {
                     code: 0,
                     message: Some("Success".to_string()),
@@ -264,21 +872,22 @@ impl BalanceManager {
                 };
             }
         };
+        if let Err(e) = self.check_precision(currency_id, amount) {
+            return DecreaseResponse {
+                code: 400,
+                message: Some(e.to_string()),
+                data: None,
+            };
+        }
 
-        let account = self
-            .accounts
-            .entry(account_id)
-            .or_insert_with(|| Account::new(account_id));
-        let balance = account.get_balance(currency_id);
+        let balance = self.account_balance(account_id, currency_id);
 
         match balance.decrease(amount) {
             Ok(_) => {
-                let balance_data = Balance {
-                    currency: currency_id.to_string(),
-                    value: balance.total.to_string(),
-                    frozen: balance.frozen.to_string(),
-                    available: balance.available.to_string(),
-                };
+                self.normalize(account_id, currency_id);
+                let balance_data =
+                    self.balance_data(&self.accounts[&account_id].balances[&currency_id]);
+                self.record(account_id, currency_id, -amount, Decimal::ZERO, AuditReason::Decrease);
                 DecreaseResponse {
                     code: 0,
                     message: Some("Success".to_string()),
@@ -308,47 +917,399 @@ impl BalanceManager {
             }
         };
 
-        let account = self
+        self.freeze(account_id, currency_id, amount)
+    }
+
+    // 检查可用余额是否足够冻结，不修改余额
+    pub fn check_freeze(
+        &self,
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        self.check_precision(currency_id, amount)?;
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must be positive".to_string(),
+            ));
+        }
+        let available = self
             .accounts
-            .entry(account_id)
-            .or_insert_with(|| Account::new(account_id));
-        let balance = account.get_balance(currency_id);
+            .get(&account_id)
+            .and_then(|account| account.balances.get(&currency_id))
+            .map(|balance| balance.available)
+            .unwrap_or_default();
+        if available < amount {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        Ok(())
+    }
 
-        balance.freeze(amount)
+    pub fn freeze(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        self.check_precision(currency_id, amount)?;
+        self.account_balance(account_id, currency_id).freeze(amount)?;
+        self.normalize(account_id, currency_id);
+        self.record(account_id, currency_id, -amount, amount, AuditReason::Freeze);
+        Ok(())
     }
 
-    pub fn handle_place_order(
+    // 解冻余额；冻结余额不足时解冻全部剩余冻结，返回实际解冻金额
+    pub fn release_frozen(&mut self, account_id: i32, currency_id: i32, amount: Decimal) -> Decimal {
+        self.release_frozen_noted(account_id, currency_id, amount, None)
+    }
+
+    // 带运维操作原因的解冻（强制撤单），记录为 AdminCancel
+    pub fn release_frozen_noted(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+        note: Option<String>,
+    ) -> Decimal {
+        let amount = self.round_amount(currency_id, amount);
+        let balance = self.account_balance(account_id, currency_id);
+        let actual = amount.min(balance.frozen);
+        balance.frozen -= actual;
+        balance.available += actual;
+        self.normalize(account_id, currency_id);
+        let reason = match note {
+            Some(_) => AuditReason::AdminCancel,
+            None => AuditReason::Unfreeze,
+        };
+        self.record_note(account_id, currency_id, actual, -actual, reason, note);
+        actual
+    }
+
+    // 运维调账：delta 为正时增加可用余额，为负时从可用余额扣减，可用余额不足时拒绝，
+    // 冻结部分属于未完成订单，不能被调账扣除
+    pub fn admin_adjust(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        delta: Decimal,
+        note: &str,
+    ) -> Result<Balance, BalanceError> {
+        if delta.is_zero() {
+            return Err(BalanceError::InvalidAmount("Delta must not be zero".to_string()));
+        }
+        self.check_precision(currency_id, delta)?;
+        let balance = self.account_balance(account_id, currency_id);
+        if balance.available + delta < Decimal::ZERO {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        balance.available += delta;
+        balance.total += delta;
+        self.normalize(account_id, currency_id);
+        let note = Some(note.to_string());
+        let reason = AuditReason::AdminAdjustment;
+        self.record_note(account_id, currency_id, delta, Decimal::ZERO, reason, note);
+        Ok(Balance::from(&*self.account_balance(account_id, currency_id)))
+    }
+
+    // 在可用余额和指定用途的保留余额之间划转，总额不变；release 为 true 时退回可用余额
+    pub fn adjust_reserve(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        tag: &str,
+        amount: Decimal,
+        release: bool,
+    ) -> Result<Balance, BalanceError> {
+        self.check_precision(currency_id, amount)?;
+        let balance = self.account_balance(account_id, currency_id);
+        if release {
+            balance.release(tag, amount)?;
+        } else {
+            balance.reserve(tag, amount)?;
+        }
+        self.normalize(account_id, currency_id);
+        Ok(self.balance_snapshot(account_id, currency_id))
+    }
+
+    // 成交结算：从冻结余额中扣除，增加到可用余额；冻结余额不足时返回错误，两个币种都不修改
+    pub fn settle(
+        &mut self,
+        account_id: i32,
+        deduct_currency_id: i32,
+        deduct_amount: Decimal,
+        add_currency_id: i32,
+        add_amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        if add_amount < Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must not be negative".to_string(),
+            ));
+        }
+        let mut deduct_amount = self.round_amount(deduct_currency_id, deduct_amount);
+        let add_amount = self.round_amount(add_currency_id, add_amount);
+        // 各笔成交分别舍入，合计可能比冻结时多出一个最小单位，此时扣除剩余的冻结余额
+        if let Some(scale) = self.currency_scale(deduct_currency_id) {
+            let frozen = self.account_balance(account_id, deduct_currency_id).frozen;
+            if deduct_amount > frozen && deduct_amount - frozen <= Decimal::new(1, scale) {
+                deduct_amount = frozen;
+            }
+        }
+        self.account_balance(account_id, deduct_currency_id)
+            .settle_frozen(deduct_amount)?;
+        self.normalize(account_id, deduct_currency_id);
+
+        let add_balance = self.account_balance(account_id, add_currency_id);
+        add_balance.available += add_amount;
+        add_balance.total += add_amount;
+        self.normalize(account_id, add_currency_id);
+
+        let reason = AuditReason::TradeSettle;
+        self.record(account_id, deduct_currency_id, Decimal::ZERO, -deduct_amount, reason);
+        self.record(account_id, add_currency_id, add_amount, Decimal::ZERO, reason);
+        Ok(())
+    }
+
+    // 保证金模式的结算：下单时没有冻结，直接从可用余额扣除；可用余额不足时拒绝整笔结算，
+    // 两个币种都不修改
+    pub fn settle_on_margin(
+        &mut self,
+        account_id: i32,
+        deduct_currency_id: i32,
+        deduct_amount: Decimal,
+        add_currency_id: i32,
+        add_amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        if deduct_amount < Decimal::ZERO || add_amount < Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must not be negative".to_string(),
+            ));
+        }
+        let deduct_amount = self.round_amount(deduct_currency_id, deduct_amount);
+        let add_amount = self.round_amount(add_currency_id, add_amount);
+        let deduct_balance = self.account_balance(account_id, deduct_currency_id);
+        if deduct_balance.available < deduct_amount {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        deduct_balance.available -= deduct_amount;
+        deduct_balance.total -= deduct_amount;
+        self.normalize(account_id, deduct_currency_id);
+
+        let add_balance = self.account_balance(account_id, add_currency_id);
+        add_balance.available += add_amount;
+        add_balance.total += add_amount;
+        self.normalize(account_id, add_currency_id);
+
+        let reason = AuditReason::TradeSettle;
+        self.record(account_id, deduct_currency_id, -deduct_amount, Decimal::ZERO, reason);
+        self.record(account_id, add_currency_id, add_amount, Decimal::ZERO, reason);
+        Ok(())
+    }
+
+    // 从可用余额扣除手续费；可用余额不足时只扣除剩余可用部分，返回实际扣除金额。
+    // 负数为 maker 返佣，全额计入可用余额
+    pub fn charge_fee(&mut self, account_id: i32, currency_id: i32, amount: Decimal) -> Decimal {
+        let amount = self.round_amount(currency_id, amount);
+        let balance = self.account_balance(account_id, currency_id);
+        let actual = if amount < Decimal::ZERO {
+            amount
+        } else {
+            amount.min(balance.available).max(Decimal::ZERO)
+        };
+        balance.available -= actual;
+        balance.total -= actual;
+        self.normalize(account_id, currency_id);
+        self.record(account_id, currency_id, -actual, Decimal::ZERO, AuditReason::Fee);
+        actual
+    }
+
+    // 直接增加可用余额（手续费入账等内部划转）
+    pub fn credit(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+        reason: AuditReason,
+    ) {
+        let amount = self.round_amount(currency_id, amount);
+        let balance = self.account_balance(account_id, currency_id);
+        balance.available += amount;
+        balance.total += amount;
+        self.normalize(account_id, currency_id);
+        self.record(account_id, currency_id, amount, Decimal::ZERO, reason);
+    }
+
+    // 划转转出：从可用余额扣减；余额不足时不做修改
+    pub fn debit(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        self.check_precision(currency_id, amount)?;
+        self.account_balance(account_id, currency_id).decrease(amount)?;
+        self.normalize(account_id, currency_id);
+        self.record(account_id, currency_id, -amount, Decimal::ZERO, AuditReason::Transfer);
+        Ok(())
+    }
+
+    // 同一分片内的账户间划转：扣减成功后才入账，失败时两个账户都不变
+    pub fn transfer(
+        &mut self,
+        from_account_id: i32,
+        to_account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::InvalidAmount(
+                "Amount must be positive".to_string(),
+            ));
+        }
+        if from_account_id == to_account_id {
+            return Ok(());
+        }
+        self.debit(from_account_id, currency_id, amount)?;
+        self.credit(to_account_id, currency_id, amount, AuditReason::Transfer);
+        Ok(())
+    }
+
+    // 发起提现：冻结金额并返回冻结单ID，余额不足时不创建冻结单
+    pub fn request_withdrawal(
         &mut self,
         account_id: i32,
-        symbol_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    ) -> Result<u64, BalanceError> {
+        self.freeze(account_id, currency_id, amount)?;
+        let hold_id = self.next_hold_id;
+        self.next_hold_id += 1;
+        self.withdrawal_holds.insert(
+            hold_id,
+            WithdrawalHold {
+                account_id,
+                currency_id,
+                amount,
+            },
+        );
+        Ok(hold_id)
+    }
+
+    // 确认提现：从冻结余额和总额中扣除，冻结单随之删除，重复确认返回 HoldNotFound
+    pub fn confirm_withdrawal(&mut self, hold_id: u64) -> Result<WithdrawalHold, BalanceError> {
+        let hold = self
+            .withdrawal_holds
+            .get(&hold_id)
+            .cloned()
+            .ok_or(BalanceError::HoldNotFound)?;
+        self.account_balance(hold.account_id, hold.currency_id)
+            .settle_frozen(hold.amount)?;
+        self.normalize(hold.account_id, hold.currency_id);
+        self.withdrawal_holds.remove(&hold_id);
+        self.record(
+            hold.account_id,
+            hold.currency_id,
+            Decimal::ZERO,
+            -hold.amount,
+            AuditReason::Withdrawal,
+        );
+        Ok(hold)
+    }
+
+    // 取消提现：解冻后删除冻结单，不会重复解冻
+    pub fn cancel_withdrawal(&mut self, hold_id: u64) -> Result<WithdrawalHold, BalanceError> {
+        let hold = self
+            .withdrawal_holds
+            .remove(&hold_id)
+            .ok_or(BalanceError::HoldNotFound)?;
+        self.release_frozen(hold.account_id, hold.currency_id, hold.amount);
+        Ok(hold)
+    }
+
+    fn account_balance(&mut self, account_id: i32, currency_id: i32) -> &mut AccountBalance {
+        if let Some(dirty_accounts) = &mut self.dirty_accounts {
+            dirty_accounts.insert(account_id);
+        }
+        self.accounts
+            .entry(account_id)
+            .or_insert_with(|| Account::new(account_id))
+            .get_balance(currency_id)
+    }
+
+    // 计算下单需要冻结的币种和金额；fee_reserve_rate 为买单预留手续费的费率
+    pub fn order_freeze_amount(
+        &self,
         side: i32,
         price: &str,
         quantity: &str,
+        fee_reserve_rate: Decimal,
         symbol: &Symbol,
-    ) -> Result<(i32, String), BalanceError> {
-
-        let (freeze_currency_id, freeze_amount) = if side == 0 {
-            // BID (买入): 冻结 quote currency，金额 = price * quantity
+    ) -> Result<(i32, Decimal), BalanceError> {
+        if side == 0 {
+            // BID (买入): 冻结 quote currency，金额 = price * quantity 加上预留的手续费，按 quote 精度舍入，
+            // 与撮合引擎中订单的冻结金额一致
             let price_decimal = Decimal::from_str_exact(price)
-                .map_err(|_| BalanceError::InvalidAmount("Invalid price format".to_string()))?;
+                .map_err(|_| BalanceError::InvalidPrice("Invalid price format".to_string()))?;
             let quantity_decimal = Decimal::from_str_exact(quantity)
-                .map_err(|_| BalanceError::InvalidAmount("Invalid quantity format".to_string()))?;
-            let freeze_amount = price_decimal * quantity_decimal;
-            (symbol.quote, freeze_amount)
+                .map_err(|_| BalanceError::InvalidQuantity("Invalid quantity format".to_string()))?;
+            let notional = checked_notional(price_decimal, quantity_decimal)?;
+            let amount = notional.checked_add((notional * fee_reserve_rate).normalize()).ok_or_else(|| {
+                BalanceError::InvalidAmount(format!("Notional of {} x {} overflows", price, quantity))
+            })?;
+            Ok((symbol.quote, self.round_amount(symbol.quote, amount)))
         } else {
             // ASK (卖出): 冻结 base currency，金额 = quantity
             let quantity_decimal = Decimal::from_str_exact(quantity)
-                .map_err(|_| BalanceError::InvalidAmount("Invalid quantity format".to_string()))?;
-            (symbol.base, quantity_decimal)
-        };
+                .map_err(|_| BalanceError::InvalidQuantity("Invalid quantity format".to_string()))?;
+            Ok((symbol.base, quantity_decimal))
+        }
+    }
+
+    pub fn handle_place_order(
+        &mut self,
+        account_id: i32,
+        _symbol_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+        symbol: &Symbol,
+    ) -> Result<(i32, String), BalanceError> {
+        // 超出交易对单笔数量或成交额上限的订单不冻结余额
+        let trading_rules = symbol.trading_rules();
+        if let Ok(quantity) = Decimal::from_str_exact(quantity) {
+            trading_rules.check_quantity_range(quantity)?;
+            if let Ok(price) = Decimal::from_str_exact(price) {
+                trading_rules.check_max_notional(checked_notional(price, quantity)?)?;
+            }
+        }
+
+        let (freeze_currency_id, freeze_amount) =
+            self.order_freeze_amount(side, price, quantity, Decimal::ZERO, symbol)?;
 
         // 尝试冻结余额
-        self.handle_freeze(account_id, freeze_currency_id, &freeze_amount.to_string())?;
+        self.freeze(account_id, freeze_currency_id, freeze_amount)?;
 
         Ok((freeze_currency_id, freeze_amount.to_string()))
     }
 }
 
+// 启动时从文件加载的币种和交易对（JSON，字段与 Currency、Symbol 一致）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketConfig {
+    #[serde(default)]
+    pub currencies: Vec<Currency>,
+    #[serde(default)]
+    pub symbols: Vec<Symbol>,
+}
+
+impl MarketConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read market config {}: {}", path, e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid market config {}: {}", path, e))
+    }
+}
+
 // 货币和交易对管理器
 #[derive(Debug, Clone)]
 pub struct ManagementManager {
@@ -358,6 +1319,12 @@ pub struct ManagementManager {
     next_symbol_id: Arc<RwLock<i32>>,
 }
 
+impl Default for ManagementManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ManagementManager {
     pub fn new() -> Self {
         Self {
@@ -368,6 +1335,55 @@ impl ManagementManager {
         }
     }
 
+    // 按配置创建币种和交易对，保留配置中的ID，之后新建的ID从最大ID之后开始；
+    // ID重复、交易对引用不存在的币种或规则无效时拒绝启动
+    pub fn from_market_config(config: MarketConfig) -> Result<Self, String> {
+        let mut currencies = HashMap::new();
+        for currency in config.currencies {
+            if [currency.scale, currency.display_scale]
+                .into_iter()
+                .flatten()
+                .any(|scale| scale > MAX_CURRENCY_SCALE)
+            {
+                return Err(format!(
+                    "Currency {} scale exceeds {}",
+                    currency.id, MAX_CURRENCY_SCALE
+                ));
+            }
+            let id = currency.id;
+            if currencies.insert(id, currency).is_some() {
+                return Err(format!("Duplicate currency id {}", id));
+            }
+        }
+        let mut symbols = HashMap::new();
+        for symbol in config.symbols {
+            for currency_id in [symbol.base, symbol.quote] {
+                if !currencies.contains_key(&currency_id) {
+                    return Err(format!(
+                        "Symbol {} references unknown currency {}",
+                        symbol.id, currency_id
+                    ));
+                }
+            }
+            if !symbol.trading_rules().is_valid() {
+                return Err(format!("Symbol {} has invalid trading rules", symbol.id));
+            }
+            let id = symbol.id;
+            if symbols.insert(id, symbol).is_some() {
+                return Err(format!("Duplicate symbol id {}", id));
+            }
+        }
+
+        let next_currency_id = currencies.keys().max().map_or(1, |id| id + 1);
+        let next_symbol_id = symbols.keys().max().map_or(1, |id| id + 1);
+        Ok(Self {
+            currencies: Arc::new(RwLock::new(currencies)),
+            symbols: Arc::new(RwLock::new(symbols)),
+            next_currency_id: Arc::new(RwLock::new(next_currency_id)),
+            next_symbol_id: Arc::new(RwLock::new(next_symbol_id)),
+        })
+    }
+
     pub fn get_currency(&self, id: i32) -> Option<Currency> {
         self.currencies.read().ok()?.get(&id).cloned()
     }
@@ -385,6 +1401,8 @@ impl ManagementManager {
             id,
             name: name.clone(),
             display_name: display_name.clone(),
+            scale: None,
+            display_scale: None,
         };
 
         self.currencies.write().unwrap().insert(id, currency.clone());
@@ -405,6 +1423,36 @@ impl ManagementManager {
         Some(currency.clone())
     }
 
+    // 设置币种精度；超过 MAX_CURRENCY_SCALE 时不修改
+    pub fn set_currency_scale(&self, id: i32, scale: Option<u32>) -> Option<Currency> {
+        if scale.is_some_and(|scale| scale > MAX_CURRENCY_SCALE) {
+            return None;
+        }
+        let mut currencies = self.currencies.write().ok()?;
+        let currency = currencies.get_mut(&id)?;
+        currency.scale = scale;
+        Some(currency.clone())
+    }
+
+    pub fn currency_scale(&self, id: i32) -> Option<u32> {
+        self.currencies.read().ok()?.get(&id)?.scale
+    }
+
+    // 设置币种显示精度，只影响返回给客户端的余额；超过 MAX_CURRENCY_SCALE 时不修改
+    pub fn set_currency_display_scale(&self, id: i32, display_scale: Option<u32>) -> Option<Currency> {
+        if display_scale.is_some_and(|scale| scale > MAX_CURRENCY_SCALE) {
+            return None;
+        }
+        let mut currencies = self.currencies.write().ok()?;
+        let currency = currencies.get_mut(&id)?;
+        currency.display_scale = display_scale;
+        Some(currency.clone())
+    }
+
+    pub fn currency_display_scale(&self, id: i32) -> Option<u32> {
+        self.currencies.read().ok()?.get(&id)?.display_scale
+    }
+
     pub fn delete_currency(&self, id: i32) -> bool {
         self.currencies.write().ok().map(|mut c| c.remove(&id).is_some()).unwrap_or(false)
     }
@@ -422,7 +1470,13 @@ impl ManagementManager {
         values[start..end].to_vec()
     }
 
-    pub fn create_symbol(&self, name: String, base: i32, quote: i32) -> Result<Symbol, BalanceError> {
+    pub fn create_symbol(
+        &self,
+        name: String,
+        base: i32,
+        quote: i32,
+        trading_rules: TradingRules,
+    ) -> Result<Symbol, BalanceError> {
         // 验证货币是否存在
         if self.get_currency(base).is_none() {
             return Err(BalanceError::CurrencyNotFound);
@@ -430,6 +1484,11 @@ impl ManagementManager {
         if self.get_currency(quote).is_none() {
             return Err(BalanceError::CurrencyNotFound);
         }
+        if !trading_rules.is_valid() {
+            return Err(BalanceError::InvalidAmount(
+                "Trading rules must not be negative".to_string(),
+            ));
+        }
 
         let mut next_id = self.next_symbol_id.write().unwrap();
         let id = *next_id;
@@ -440,13 +1499,27 @@ impl ManagementManager {
             name: name.clone(),
             base,
             quote,
+            price_tick: trading_rules.price_tick,
+            quantity_step: trading_rules.quantity_step,
+            min_notional: trading_rules.min_notional,
+            min_quantity: trading_rules.min_quantity,
+            max_quantity: trading_rules.max_quantity,
+            max_notional: trading_rules.max_notional,
+            status: SymbolStatus::Active,
         };
 
         self.symbols.write().unwrap().insert(id, symbol.clone());
         Ok(symbol)
     }
 
-    pub fn update_symbol(&self, id: i32, name: Option<String>, base: Option<i32>, quote: Option<i32>) -> Option<Symbol> {
+    pub fn update_symbol(
+        &self,
+        id: i32,
+        name: Option<String>,
+        base: Option<i32>,
+        quote: Option<i32>,
+        trading_rules: Option<TradingRules>,
+    ) -> Option<Symbol> {
         let mut symbols = self.symbols.write().ok()?;
         let symbol = symbols.get_mut(&id)?;
 
@@ -459,10 +1532,26 @@ impl ManagementManager {
         if let Some(quote) = quote {
             symbol.quote = quote;
         }
+        if let Some(trading_rules) = trading_rules.filter(TradingRules::is_valid) {
+            symbol.price_tick = trading_rules.price_tick;
+            symbol.quantity_step = trading_rules.quantity_step;
+            symbol.min_notional = trading_rules.min_notional;
+            symbol.min_quantity = trading_rules.min_quantity;
+            symbol.max_quantity = trading_rules.max_quantity;
+            symbol.max_notional = trading_rules.max_notional;
+        }
 
         Some(symbol.clone())
     }
 
+    // 暂停或恢复交易，撮合线程处理每个请求时读取最新状态
+    pub fn set_symbol_status(&self, id: i32, status: SymbolStatus) -> Option<Symbol> {
+        let mut symbols = self.symbols.write().ok()?;
+        let symbol = symbols.get_mut(&id)?;
+        symbol.status = status;
+        Some(symbol.clone())
+    }
+
     pub fn delete_symbol(&self, id: i32) -> bool {
         self.symbols.write().ok().map(|mut s| s.remove(&id).is_some()).unwrap_or(false)
     }
@@ -479,79 +1568,104 @@ impl ManagementManager {
 
         values[start..end].to_vec()
     }
+
+    // 两个币种之间的交易对，不区分哪个是 base
+    pub fn symbols_between(&self, currency_id: i32, other_currency_id: i32) -> Vec<Symbol> {
+        let symbols = self.symbols.read().unwrap();
+        let mut values: Vec<Symbol> = symbols
+            .values()
+            .filter(|s| {
+                (s.base == currency_id && s.quote == other_currency_id)
+                    || (s.base == other_currency_id && s.quote == currency_id)
+            })
+            .cloned()
+            .collect();
+        values.sort_by_key(|s| s.id);
+        values
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-
-    fn ensure_test_config() {
-        INIT.call_once(|| {
-            // 初始化测试用的货币和交易对
-            GLOBAL_CURRENCIES.get_or_init(|| {
-                let mut currencies = HashMap::new();
-                currencies.insert(
-                    1,
-                    Currency {
-                        id: 1,
-                        name: "BTC".to_string(),
-                        display_name: "Bitcoin".to_string(),
-                    },
-                );
-                currencies.insert(
-                    2,
-                    Currency {
-                        id: 2,
-                        name: "USDT".to_string(),
-                        display_name: "Tether USD".to_string(),
-                    },
-                );
-                currencies
-            });
-
-            GLOBAL_SYMBOLS.get_or_init(|| {
-                let mut symbols = HashMap::new();
-                symbols.insert(
-                    1,
-                    Symbol {
-                        id: 1,
-                        name: "BTC-USDT".to_string(),
-                        base: 1,  // BTC
-                        quote: 2, // USDT
-                    },
-                );
-                symbols
-            });
-        });
+
+    fn ensure_test_config() -> ManagementManager {
+        // 初始化测试用的货币和交易对
+        let management = ManagementManager::new();
+        management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        management.create_currency("USDT".to_string(), "Tether USD".to_string());
+        management
+            .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+            .unwrap();
+        management
     }
 
     #[test]
     fn test_currency_initialization() {
-        ensure_test_config();
+        let management = ensure_test_config();
 
-        let btc = get_currency(1).unwrap();
+        let btc = management.get_currency(1).unwrap();
         assert_eq!(btc.id, 1);
         assert_eq!(btc.name, "BTC");
 
-        let usdt = get_currency(2).unwrap();
+        let usdt = management.get_currency(2).unwrap();
         assert_eq!(usdt.id, 2);
         assert_eq!(usdt.name, "USDT");
     }
 
     #[test]
     fn test_symbol_initialization() {
-        ensure_test_config();
+        let management = ensure_test_config();
 
-        let btc_usdt = get_symbol(1).unwrap();
+        let btc_usdt = management.get_symbol(1).unwrap();
         assert_eq!(btc_usdt.id, 1);
         assert_eq!(btc_usdt.name, "BTC-USDT");
         assert_eq!(btc_usdt.base, 1); // BTC
         assert_eq!(btc_usdt.quote, 2); // USDT
     }
 
+    #[test]
+    fn test_symbol_trading_rules() {
+        let management = ensure_test_config();
+        let rules = TradingRules {
+            price_tick: Decimal::new(1, 2),
+            quantity_step: Decimal::new(1, 4),
+            min_notional: Decimal::new(10, 0),
+            min_quantity: Decimal::new(1, 3),
+            max_quantity: Decimal::new(100, 0),
+            max_notional: Decimal::new(1_000_000, 0),
+        };
+
+        let symbol = management
+            .create_symbol("ETH-USDT".to_string(), 1, 2, rules)
+            .unwrap();
+        assert_eq!(symbol.trading_rules(), rules);
+
+        // 负数规则被拒绝
+        let negative = TradingRules {
+            price_tick: Decimal::new(-1, 2),
+            ..rules
+        };
+        assert!(management
+            .create_symbol("BAD".to_string(), 1, 2, negative)
+            .is_err());
+
+        // 最大数量小于最小数量时被拒绝
+        let inverted = TradingRules {
+            max_quantity: Decimal::new(1, 4),
+            ..rules
+        };
+        assert!(management
+            .create_symbol("BAD".to_string(), 1, 2, inverted)
+            .is_err());
+
+        let updated = management
+            .update_symbol(symbol.id, None, None, None, Some(TradingRules::default()))
+            .unwrap();
+        assert_eq!(updated.trading_rules(), TradingRules::default());
+        assert_eq!(updated.name, "ETH-USDT");
+    }
+
     #[test]
     fn test_balance_operations() {
         let mut balance = AccountBalance::new(1);
@@ -578,16 +1692,376 @@ mod tests {
         assert_eq!(balance.frozen, Decimal::new(20, 0));
     }
 
+    #[test]
+    fn test_reserved_buckets_keep_total_unchanged() {
+        let mut balance = AccountBalance::new(1);
+        let invariant = |b: &AccountBalance| b.total == b.available + b.frozen + b.reserved_total();
+        balance.increase(Decimal::new(100, 0)).unwrap();
+        balance.freeze(Decimal::new(10, 0)).unwrap();
+
+        balance.reserve("margin", Decimal::new(30, 0)).unwrap();
+        balance.reserve("staking", Decimal::new(20, 0)).unwrap();
+        assert_eq!(balance.available, Decimal::new(40, 0));
+        assert_eq!(balance.reserved["margin"], Decimal::new(30, 0));
+        assert_eq!(balance.total, Decimal::new(100, 0));
+        assert!(invariant(&balance));
+
+        // 保留余额不能用于冻结，也不能超过可用余额保留
+        assert!(balance.freeze(Decimal::new(41, 0)).is_err());
+        assert!(balance.reserve("margin", Decimal::new(41, 0)).is_err());
+        // 只能从同一用途退回
+        assert!(balance.release("staking", Decimal::new(21, 0)).is_err());
+        assert!(balance.release("unknown", Decimal::new(1, 0)).is_err());
+        assert!(invariant(&balance));
+
+        balance.release("margin", Decimal::new(10, 0)).unwrap();
+        assert_eq!(balance.reserved["margin"], Decimal::new(20, 0));
+        balance.release("staking", Decimal::new(20, 0)).unwrap();
+        // 全部退回后移除该用途
+        assert!(!balance.reserved.contains_key("staking"));
+        assert_eq!(balance.available, Decimal::new(70, 0));
+        assert!(invariant(&balance));
+
+        let proto = Balance::from(&balance);
+        assert_eq!(proto.reserved.get("margin").map(String::as_str), Some("20"));
+        assert_eq!(proto.value, "100");
+    }
+
+    #[test]
+    fn test_transfer_is_all_or_nothing() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+
+        assert!(manager.transfer(1, 2, 2, Decimal::new(40, 0)).is_ok());
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::new(60, 0));
+        assert_eq!(manager.account_balance(2, 2).total, Decimal::new(40, 0));
+
+        // 余额不足时两个账户都不变
+        assert!(matches!(
+            manager.transfer(1, 2, 2, Decimal::new(61, 0)),
+            Err(BalanceError::InsufficientBalance)
+        ));
+        assert_eq!(manager.account_balance(1, 2).total, Decimal::new(60, 0));
+        assert_eq!(manager.account_balance(2, 2).total, Decimal::new(40, 0));
+
+        // 转给自己不做修改
+        assert!(manager.transfer(1, 1, 2, Decimal::new(60, 0)).is_ok());
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::new(60, 0));
+        assert!(manager.transfer(1, 2, 2, Decimal::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_get_account_rejects_inconsistent_balance() {
+        let mut manager = BalanceManager::new();
+        manager.handle_increase(1, 2, "100");
+        manager.handle_increase(1, 1, "1");
+        let snapshot = manager.account_snapshot(1).unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.values().all(AccountBalance::is_consistent));
+
+        // 总额与可用加冻结不符时不返回余额
+        manager.account_balance(1, 2).frozen += Decimal::ONE;
+        assert!(matches!(
+            manager.account_snapshot(1),
+            Err(BalanceError::InconsistentBalance(2))
+        ));
+        let response = manager.handle_get_account(1, None);
+        assert_eq!(response.code, 500);
+        assert!(response.data.is_empty());
+        assert_eq!(manager.handle_get_account(3, None).code, 404);
+    }
+
+    #[test]
+    fn test_balance_errors_map_to_reject_reasons() {
+        let cases = [
+            (BalanceError::InsufficientBalance, RejectReason::InsufficientBalance),
+            (BalanceError::InvalidAmount(String::new()), RejectReason::InvalidAmount),
+            (BalanceError::InvalidPrice(String::new()), RejectReason::InvalidPrice),
+            (BalanceError::InvalidQuantity(String::new()), RejectReason::InvalidQuantity),
+            (BalanceError::BelowMinNotional(String::new()), RejectReason::BelowMinNotional),
+            (BalanceError::AboveMaxNotional(String::new()), RejectReason::AboveMaxNotional),
+            (BalanceError::RiskLimitExceeded(String::new()), RejectReason::RiskLimitExceeded),
+            (BalanceError::InvalidOrder(String::new()), RejectReason::InvalidOrder),
+            (BalanceError::AccountNotFound, RejectReason::UnknownAccount),
+            (BalanceError::CurrencyNotFound, RejectReason::UnknownSymbol),
+            (BalanceError::OrderNotFound, RejectReason::OrderNotFound),
+            (BalanceError::MarketHalted(String::new()), RejectReason::MarketHalted),
+            (BalanceError::HoldNotFound, RejectReason::InternalError),
+            (BalanceError::WalWrite(String::new()), RejectReason::InternalError),
+            (BalanceError::InvalidSnapshot(String::new()), RejectReason::InternalError),
+            (BalanceError::InconsistentBalance(2), RejectReason::InternalError),
+        ];
+        for (error, reason) in cases {
+            assert_eq!(error.reject_reason(), reason, "{:?}", error);
+        }
+        assert_eq!(RejectReason::InsufficientBalance.as_str_name(), "INSUFFICIENT_BALANCE");
+        assert_eq!(RejectReason::InsufficientBalance as i32, 1);
+
+        // 下单参数格式错误按字段区分
+        let symbol = ensure_test_config().get_symbol(1).unwrap();
+        let manager = BalanceManager::new();
+        let error = manager.order_freeze_amount(0, "abc", "1", Decimal::ZERO, &symbol).unwrap_err();
+        assert_eq!(error.reject_reason(), RejectReason::InvalidPrice);
+        let error = manager.order_freeze_amount(1, "100", "abc", Decimal::ZERO, &symbol).unwrap_err();
+        assert_eq!(error.reject_reason(), RejectReason::InvalidQuantity);
+        let max = Decimal::MAX.to_string();
+        let error = manager.order_freeze_amount(0, &max, "1.5", Decimal::ZERO, &symbol).unwrap_err();
+        assert_eq!(error.reject_reason(), RejectReason::InvalidAmount);
+    }
+
+    #[test]
+    fn test_order_freeze_amount_rounds_to_quote_scale() {
+        let symbol = ensure_test_config().get_symbol(1).unwrap();
+        let mut manager = BalanceManager::new();
+        manager.set_currency_scale(symbol.quote, Some(2));
+        let (currency_id, amount) =
+            manager.order_freeze_amount(0, "1.5", "0.333", Decimal::ZERO, &symbol).unwrap();
+        assert_eq!(currency_id, symbol.quote);
+        assert_eq!(amount.to_string(), "0.50");
+    }
+
+    #[test]
+    fn test_order_freeze_amount_reserves_bid_fee() {
+        let symbol = ensure_test_config().get_symbol(1).unwrap();
+        let mut manager = BalanceManager::new();
+        manager.set_currency_scale(symbol.quote, Some(2));
+        let rate = Decimal::new(1, 3);
+        let (_, amount) = manager.order_freeze_amount(0, "100", "1.5", rate, &symbol).unwrap();
+        assert_eq!(amount.to_string(), "150.15");
+        // 卖单冻结 base，不预留手续费
+        let (currency_id, amount) =
+            manager.order_freeze_amount(1, "100", "1.5", rate, &symbol).unwrap();
+        assert_eq!((currency_id, amount.to_string()), (symbol.base, "1.5".to_string()));
+    }
+
+    #[test]
+    fn test_currency_scale_normalizes_balances() {
+        let mut manager = BalanceManager::new();
+        manager.set_currency_scale(2, Some(2));
+
+        // 不同写法的同一金额得到相同的格式
+        let response = manager.handle_increase(1, 2, "5.0");
+        assert_eq!(response.data.unwrap().available, "5.00");
+        let response = manager.handle_increase(1, 2, "5.000");
+        assert_eq!(response.data.unwrap().value, "10.00");
+        let response = manager.handle_decrease(1, 2, "1.5");
+        assert_eq!(response.data.unwrap().available, "8.50");
+        manager.freeze(1, 2, Decimal::new(25, 1)).unwrap();
+        let balance = &manager.accounts[&1].balances[&2];
+        assert_eq!(balance.total.to_string(), "8.50");
+        assert_eq!(balance.frozen.to_string(), "2.50");
+        assert_eq!(balance.available.to_string(), "6.00");
+
+        // 超过精度的输入被拒绝，余额不变
+        let response = manager.handle_increase(1, 2, "0.001");
+        assert_eq!(response.code, 400);
+        assert_eq!(manager.handle_decrease(1, 2, "0.001").code, 400);
+        assert!(matches!(
+            manager.freeze(1, 2, Decimal::new(1, 3)),
+            Err(BalanceError::InvalidAmount(_))
+        ));
+        assert_eq!(manager.accounts[&1].balances[&2].frozen.to_string(), "2.50");
+
+        // 内部计算的金额四舍六入五成双
+        manager.credit(1, 2, Decimal::new(125, 3), AuditReason::Fee);
+        assert_eq!(manager.accounts[&1].balances[&2].available.to_string(), "6.12");
+        manager.credit(1, 2, Decimal::new(135, 3), AuditReason::Fee);
+        assert_eq!(manager.accounts[&1].balances[&2].available.to_string(), "6.26");
+
+        // 未设置精度的币种保留输入格式
+        let response = manager.handle_increase(1, 1, "5.0");
+        assert_eq!(response.data.unwrap().available, "5.0");
+    }
+
+    #[test]
+    fn test_display_scale_formats_balances_without_touching_state() {
+        let mut manager = BalanceManager::new();
+        manager.set_currency_scale(2, Some(8));
+        manager.set_display_scale(2, Some(2));
+
+        // 显示精度向零截断并补齐小数位，内部余额保留完整精度
+        let response = manager.handle_increase(1, 2, "10.12345678");
+        assert_eq!(response.data.unwrap().value, "10.12");
+        let response = manager.handle_decrease(1, 2, "0.1");
+        assert_eq!(response.data.unwrap().available, "10.02");
+        manager.freeze(1, 2, Decimal::new(5, 1)).unwrap();
+        let response = manager.handle_get_account(1, Some(2));
+        assert_eq!(response.data[&2].value, "10.02");
+        assert_eq!(response.data[&2].frozen, "0.50");
+        assert_eq!(response.data[&2].available, "9.52");
+        assert_eq!(manager.accounts[&1].balances[&2].total.to_string(), "10.02345678");
+
+        // 未设置显示精度的币种按余额精度显示
+        manager.set_display_scale(2, None);
+        let response = manager.handle_get_account(1, Some(2));
+        assert_eq!(response.data[&2].value, "10.02345678");
+        assert_eq!(display_amount(Decimal::new(-15, 1), Some(0)), "-1");
+        assert_eq!(display_amount(Decimal::new(3, 0), Some(3)), "3.000");
+    }
+
+    #[test]
+    fn test_settle_more_than_frozen_is_refused() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+        manager.freeze(1, 2, Decimal::new(30, 0)).unwrap();
+
+        assert!(matches!(
+            manager.settle(1, 2, Decimal::new(31, 0), 1, Decimal::ONE),
+            Err(BalanceError::InsufficientBalance)
+        ));
+        assert!(manager.settle(1, 2, Decimal::new(-1, 0), 1, Decimal::ONE).is_err());
+        assert!(manager.settle(1, 2, Decimal::ONE, 1, Decimal::new(-1, 0)).is_err());
+
+        // 两个币种都没有变化
+        let usdt = manager.account_balance(1, 2).clone();
+        assert_eq!((usdt.total, usdt.frozen), (Decimal::new(100, 0), Decimal::new(30, 0)));
+        assert_eq!(manager.account_balance(1, 1).total, Decimal::ZERO);
+        assert_eq!(manager.get_balance_history(1, None, 10).len(), 2);
+    }
+
+    #[test]
+    fn test_settle_on_margin_more_than_available_is_refused() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+
+        assert!(matches!(
+            manager.settle_on_margin(1, 2, Decimal::new(101, 0), 1, Decimal::ONE),
+            Err(BalanceError::InsufficientBalance)
+        ));
+        let usdt = manager.account_balance(1, 2).clone();
+        assert_eq!((usdt.total, usdt.available), (Decimal::new(100, 0), Decimal::new(100, 0)));
+        assert_eq!(manager.account_balance(1, 1).total, Decimal::ZERO);
+
+        manager.settle_on_margin(1, 2, Decimal::new(100, 0), 1, Decimal::ONE).unwrap();
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::ZERO);
+        assert_eq!(manager.account_balance(1, 1).available, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_withdrawal_lifecycle() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+
+        let confirmed = manager.request_withdrawal(1, 2, Decimal::new(30, 0)).unwrap();
+        let cancelled = manager.request_withdrawal(1, 2, Decimal::new(20, 0)).unwrap();
+        assert_ne!(confirmed, cancelled);
+        assert_eq!(manager.account_balance(1, 2).frozen, Decimal::new(50, 0));
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::new(50, 0));
+
+        let hold = manager.confirm_withdrawal(confirmed).unwrap();
+        assert_eq!(hold.amount, Decimal::new(30, 0));
+        assert_eq!(manager.account_balance(1, 2).total, Decimal::new(70, 0));
+        assert_eq!(manager.account_balance(1, 2).frozen, Decimal::new(20, 0));
+
+        manager.cancel_withdrawal(cancelled).unwrap();
+        assert_eq!(manager.account_balance(1, 2).total, Decimal::new(70, 0));
+        assert_eq!(manager.account_balance(1, 2).frozen, Decimal::ZERO);
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::new(70, 0));
+        assert!(manager.withdrawal_holds.is_empty());
+
+        // 余额不足时不创建冻结单
+        assert!(matches!(
+            manager.request_withdrawal(1, 2, Decimal::new(71, 0)),
+            Err(BalanceError::InsufficientBalance)
+        ));
+        assert!(manager.withdrawal_holds.is_empty());
+    }
+
+    #[test]
+    fn test_withdrawal_hold_cannot_be_settled_twice() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+        let hold_id = manager.request_withdrawal(1, 2, Decimal::new(30, 0)).unwrap();
+
+        manager.confirm_withdrawal(hold_id).unwrap();
+        assert!(matches!(
+            manager.confirm_withdrawal(hold_id),
+            Err(BalanceError::HoldNotFound)
+        ));
+        assert!(matches!(
+            manager.cancel_withdrawal(hold_id),
+            Err(BalanceError::HoldNotFound)
+        ));
+        assert!(matches!(
+            manager.confirm_withdrawal(42),
+            Err(BalanceError::HoldNotFound)
+        ));
+
+        // 已取消的冻结单不能再次取消或确认
+        let hold_id = manager.request_withdrawal(1, 2, Decimal::new(10, 0)).unwrap();
+        manager.cancel_withdrawal(hold_id).unwrap();
+        assert!(manager.cancel_withdrawal(hold_id).is_err());
+        assert!(manager.confirm_withdrawal(hold_id).is_err());
+        assert_eq!(manager.account_balance(1, 2).total, Decimal::new(70, 0));
+        assert_eq!(manager.account_balance(1, 2).available, Decimal::new(70, 0));
+        assert_eq!(manager.account_balance(1, 2).frozen, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_audit_log_records_balance_changes_in_order() {
+        let mut manager = BalanceManager::new();
+        let _ = manager.handle_increase(1, 2, "100");
+        let _ = manager.handle_decrease(1, 2, "10");
+        manager.freeze(1, 2, Decimal::new(50, 0)).unwrap();
+        manager.release_frozen(1, 2, Decimal::new(20, 0));
+        manager.settle(1, 2, Decimal::new(30, 0), 1, Decimal::new(3, 1)).unwrap();
+        // 失败的操作不记录
+        let _ = manager.handle_decrease(1, 2, "1000");
+        let _ = manager.handle_increase(2, 2, "5");
+
+        let changes: Vec<_> = manager
+            .get_balance_history(1, None, 10)
+            .into_iter()
+            .map(|entry| {
+                (entry.currency_id, entry.available_delta, entry.frozen_delta, entry.reason)
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (1, Decimal::new(3, 1), Decimal::ZERO, AuditReason::TradeSettle),
+                (2, Decimal::ZERO, Decimal::new(-30, 0), AuditReason::TradeSettle),
+                (2, Decimal::new(20, 0), Decimal::new(-20, 0), AuditReason::Unfreeze),
+                (2, Decimal::new(-50, 0), Decimal::new(50, 0), AuditReason::Freeze),
+                (2, Decimal::new(-10, 0), Decimal::ZERO, AuditReason::Decrease),
+                (2, Decimal::new(100, 0), Decimal::ZERO, AuditReason::Increase),
+            ]
+        );
+
+        let usdt = manager.get_balance_history(1, Some(2), 2);
+        assert_eq!(usdt.len(), 2);
+        assert_eq!(usdt[0].reason, AuditReason::TradeSettle);
+        assert_eq!(usdt[1].reason, AuditReason::Unfreeze);
+        assert_eq!(manager.get_balance_history(2, None, 10).len(), 1);
+    }
+
+    #[test]
+    fn test_audit_log_drops_oldest_entries_when_full() {
+        let mut manager = BalanceManager::new();
+        manager.audit_log_capacity = 3;
+        for amount in ["1", "2", "3", "4", "5"] {
+            let _ = manager.handle_increase(1, 2, amount);
+        }
+
+        let deltas: Vec<_> = manager
+            .get_balance_history(1, Some(2), 10)
+            .into_iter()
+            .map(|entry| entry.available_delta)
+            .collect();
+        assert_eq!(deltas, vec![Decimal::new(5, 0), Decimal::new(4, 0), Decimal::new(3, 0)]);
+    }
+
     #[test]
     fn test_bid_order_processing() {
-        ensure_test_config();
+        let management = ensure_test_config();
+        let symbol = management.get_symbol(1).unwrap();
         let mut manager = BalanceManager::new();
 
         // 先给账户充值 USDT (quote currency)
         let _ = manager.handle_increase(1, 2, "1000.0");
 
         // 测试买入订单 (BID): 应该冻结 USDT
-        let result = manager.handle_place_order(1, 1, 0, "50000.0", "0.01");
+        let result = manager.handle_place_order(1, 1, 0, "50000.0", "0.01", &symbol);
         assert!(result.is_ok());
 
         let (frozen_currency, frozen_amount) = result.unwrap();
@@ -610,14 +2084,15 @@ mod tests {
 
     #[test]
     fn test_ask_order_processing() {
-        ensure_test_config();
+        let management = ensure_test_config();
+        let symbol = management.get_symbol(1).unwrap();
         let mut manager = BalanceManager::new();
 
         // 先给账户充值 BTC (base currency)
         let _ = manager.handle_increase(1, 1, "1.0");
 
         // 测试卖出订单 (ASK): 应该冻结 BTC
-        let result = manager.handle_place_order(1, 1, 1, "50000.0", "0.5");
+        let result = manager.handle_place_order(1, 1, 1, "50000.0", "0.5", &symbol);
         assert!(result.is_ok());
 
         let (frozen_currency, frozen_amount) = result.unwrap();
@@ -634,11 +2109,12 @@ mod tests {
 
     #[test]
     fn test_insufficient_balance_order() {
-        ensure_test_config();
+        let management = ensure_test_config();
+        let symbol = management.get_symbol(1).unwrap();
         let mut manager = BalanceManager::new();
 
         // 不给账户充值，直接下单
-        let result = manager.handle_place_order(1, 1, 0, "50000.0", "0.01");
+        let result = manager.handle_place_order(1, 1, 0, "50000.0", "0.01", &symbol);
         assert!(result.is_err());
 
         match result {
@@ -649,11 +2125,14 @@ mod tests {
 
     #[test]
     fn test_invalid_symbol_order() {
-        ensure_test_config();
+        let management = ensure_test_config();
         let mut manager = BalanceManager::new();
 
         // 使用不存在的交易对
-        let result = manager.handle_place_order(1, 999, 0, "50000.0", "0.01");
+        let result = management
+            .get_symbol(999)
+            .ok_or(BalanceError::CurrencyNotFound)
+            .and_then(|symbol| manager.handle_place_order(1, 999, 0, "50000.0", "0.01", &symbol));
         assert!(result.is_err());
 
         match result {
@@ -661,4 +2140,82 @@ mod tests {
             _ => panic!("Expected CurrencyNotFound error"),
         }
     }
+
+    #[test]
+    fn test_market_config_loads_currencies_and_symbols() {
+        let path =
+            std::env::temp_dir().join(format!("lightning-markets-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{
+                "currencies": [
+                    {"id": 1, "name": "BTC", "display_name": "Bitcoin", "scale": 8},
+                    {"id": 2, "name": "USDT", "display_name": "Tether USD"},
+                    {"id": 5, "name": "ETH", "display_name": "Ethereum"}
+                ],
+                "symbols": [
+                    {"id": 1, "name": "BTC-USDT", "base": 1, "quote": 2, "price_tick": "0.01"},
+                    {"id": 3, "name": "ETH-USDT", "base": 5, "quote": 2}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let config = MarketConfig::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let manager = ManagementManager::from_market_config(config.unwrap()).unwrap();
+
+        assert_eq!(manager.get_currency(1).unwrap().scale, Some(8));
+        assert_eq!(manager.get_currency(5).unwrap().name, "ETH");
+        assert!(manager.get_currency(3).is_none());
+        let symbol = manager.get_symbol(3).unwrap();
+        assert_eq!((symbol.base, symbol.quote), (5, 2));
+        assert_eq!(manager.get_symbol(1).unwrap().price_tick, Decimal::new(1, 2));
+        // 之后新建的ID排在配置的最大ID之后
+        assert_eq!(manager.create_currency("SOL".to_string(), "Solana".to_string()).id, 6);
+        let symbol = manager
+            .create_symbol("SOL-USDT".to_string(), 6, 2, TradingRules::default())
+            .unwrap();
+        assert_eq!(symbol.id, 4);
+    }
+
+    #[test]
+    fn test_market_config_rejects_duplicates_and_unknown_currencies() {
+        let currency = |id: i32| Currency {
+            id,
+            name: format!("C{}", id),
+            display_name: format!("Currency {}", id),
+            scale: None,
+            display_scale: None,
+        };
+        let symbol = |id: i32, base: i32, quote: i32| Symbol {
+            id,
+            name: format!("S{}", id),
+            base,
+            quote,
+            price_tick: Decimal::ZERO,
+            quantity_step: Decimal::ZERO,
+            min_notional: Decimal::ZERO,
+            min_quantity: Decimal::ZERO,
+            max_quantity: Decimal::ZERO,
+            max_notional: Decimal::ZERO,
+            status: SymbolStatus::Active,
+        };
+        let load = |currencies, symbols| {
+            ManagementManager::from_market_config(MarketConfig { currencies, symbols }).map(|_| ())
+        };
+
+        assert_eq!(load(vec![currency(1), currency(2)], vec![symbol(1, 1, 2)]), Ok(()));
+        assert_eq!(
+            load(vec![currency(1), currency(1)], vec![]),
+            Err("Duplicate currency id 1".to_string())
+        );
+        assert_eq!(
+            load(vec![currency(1), currency(2)], vec![symbol(1, 1, 2), symbol(1, 2, 1)]),
+            Err("Duplicate symbol id 1".to_string())
+        );
+        assert_eq!(
+            load(vec![currency(1)], vec![symbol(1, 1, 3)]),
+            Err("Symbol 1 references unknown currency 3".to_string())
+        );
+    }
 }
@@ -0,0 +1,100 @@
+use crate::models::schema::GetAccountResponse;
+use crate::models::{account_response, Account, ManagementManager};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::oneshot;
+use tracing::error;
+
+// 热点分片的查询溢出：账户所在分片的请求队列积压达到阈值时，余额查询放入所有分片共享的
+// 有界溢出队列，由空闲的 Sequencer 工作线程读取该分片发布的账户视图后回复。
+// 修改余额的请求仍只由所在分片按顺序处理；视图在分片每处理完一条消息后更新，
+// 被转走的查询看不到分片正在处理的那条消息
+pub struct OverflowRead {
+    pub shard: usize,
+    pub account_id: i32,
+    pub currency_id: Option<i32>,
+    pub response_sender: oneshot::Sender<GetAccountResponse>,
+}
+
+pub struct ReadOverflow {
+    threshold: usize,
+    sender: Sender<OverflowRead>,
+    receiver: Receiver<OverflowRead>,
+    // 每个分片最近发布的账户余额
+    views: Vec<RwLock<HashMap<i32, Account>>>,
+    // 每个工作线程（以其第一个分片编号标识）回复的溢出查询数
+    served: Vec<AtomicU64>,
+    management_manager: Arc<ManagementManager>,
+}
+
+impl ReadOverflow {
+    pub fn new(
+        shard_count: usize,
+        threshold: usize,
+        capacity: usize,
+        management_manager: Arc<ManagementManager>,
+    ) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        Self {
+            threshold,
+            sender,
+            receiver,
+            views: (0..shard_count).map(|_| RwLock::new(HashMap::new())).collect(),
+            served: (0..shard_count).map(|_| AtomicU64::new(0)).collect(),
+            management_manager,
+        }
+    }
+
+    // 分片队列中排队的消息数达到阈值时放入溢出队列；未达到阈值或溢出队列已满时
+    // 原样返回，由调用方发给所在分片
+    pub fn offer(&self, queued: usize, read: OverflowRead) -> Result<(), OverflowRead> {
+        if queued < self.threshold {
+            return Err(read);
+        }
+        self.sender.try_send(read).map_err(|e| match e {
+            TrySendError::Full(read) | TrySendError::Disconnected(read) => read,
+        })
+    }
+
+    pub fn receiver(&self) -> &Receiver<OverflowRead> {
+        &self.receiver
+    }
+
+    // 分片线程发布变化过的账户，None 表示账户已不存在
+    pub fn publish(&self, shard: usize, accounts: impl IntoIterator<Item = (i32, Option<Account>)>) {
+        let mut view = self.views[shard].write().unwrap();
+        for (account_id, account) in accounts {
+            match account {
+                Some(account) => view.insert(account_id, account),
+                None => view.remove(&account_id),
+            };
+        }
+    }
+
+    // worker 回复过的溢出查询数
+    pub fn served_by(&self, worker: usize) -> u64 {
+        self.served[worker].load(Ordering::Relaxed)
+    }
+
+    // 在窃取到查询的工作线程 worker 上回复，与分片处理 GetAccount 的响应相同
+    pub fn serve(&self, worker: usize, read: OverflowRead) {
+        self.served[worker].fetch_add(1, Ordering::Relaxed);
+        let response = {
+            let view = self.views[read.shard].read().unwrap();
+            account_response(view.get(&read.account_id), read.currency_id, |currency_id| {
+                self.management_manager.currency_display_scale(currency_id)
+            })
+        };
+        if response.code == 500 {
+            error!(
+                sequencer = read.shard,
+                account_id = read.account_id,
+                message = ?response.message,
+                "Account balance invariant violated"
+            );
+        }
+        let _ = read.response_sender.send(response);
+    }
+}
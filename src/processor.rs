@@ -1,15 +1,76 @@
-use crate::matching::{MatchingEngine, Trade};
-use crate::messages::{MatchMessage, SequencerMessage, TradeExecutionMessage};
-use crate::models::{BalanceError, ManagementManager};
+use crate::health::Liveness;
+use crate::market_data::{
+    trade_event, DepthCache, OrderBookPublisher, TradePublisher, DEFAULT_TRADES_LIMIT,
+    MAX_TRADES_LIMIT, ORDER_BOOK_STREAM_LEVELS,
+};
+use crate::matching::{
+    now_millis, FeeRates, MatchingEngine, Order, OrderBook, OrderParams, OrderSide, OrderStatus,
+    OrderType, TimeInForce, Trade, ALL_ACCOUNTS,
+};
+use crate::dead_letter::{DeadLetter, DeadLetterSink};
+use crate::idempotency::{idempotency_key, CachedResponse, RequestKey, SettlementKey};
+use crate::messages::{MatchMessage, SequencerMessage, SettlementAck, TradeExecutionMessage};
+use crate::metrics::metrics;
+use crate::models::{
+    transfer_response, withdrawal_response, AuditReason, BalanceError, BalanceManager, ManagementManager, Symbol,
+    SymbolStatus, DEFAULT_BALANCE_HISTORY_LIMIT, FEE_ACCOUNT_ID, MAX_BALANCE_HISTORY_LIMIT,
+};
+use crate::models::schema::{PlaceOrderResponse, RejectReason};
+use crate::overflow::ReadOverflow;
+use crate::risk::{OpenOrderTracker, PlacementMode, RiskLimits};
+use crate::wal::{self, WalRecord, WriteAheadLog, SNAPSHOT_INTERVAL};
+use tracing::{debug, error, info, warn};
+use crossbeam_channel::TrySendError;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 撮合队列空闲时检查订单到期的间隔
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+// 撮合队列已满时返回给客户端的提示，客户端应稍后重试
+const SERVER_BUSY_MESSAGE: &str = "Server busy, please retry later";
+
+// 撮合线程已退出时返回给客户端的提示
+const MATCHER_UNAVAILABLE_MESSAGE: &str = "Matching engine unavailable";
+
+// 预写日志曾写入失败，分片已停止修改状态，需要重启后按日志恢复
+const WAL_STOPPED_MESSAGE: &str = "Write-ahead log unavailable, shard stopped";
+
+// 同一账户的幂等键已被其他类型的请求使用
+const IDEMPOTENCY_KEY_REUSED_MESSAGE: &str = "Idempotency key already used by another request";
+
+// 序号检查失败的响应码：序号不连续返回 409，写入预写日志失败返回 500
+fn nonce_error_code(error: &BalanceError) -> i32 {
+    match error {
+        BalanceError::WalWrite(_) => 500,
+        _ => 409,
+    }
+}
+
+// 转发到撮合失败，消息原样退回以便解冻余额并回复调用方
+struct ForwardError {
+    code: i32, // 队列已满为 503，撮合线程已退出为 500
+    message: &'static str,
+    returned: MatchMessage,
+}
 
 pub struct SequencerProcessor {
     id: usize,
+    shard_count: usize,
     receiver: crossbeam_channel::Receiver<SequencerMessage>,
-    balance_manager: crate::models::BalanceManager,
+    balance_manager: BalanceManager,
     match_senders: Vec<crossbeam_channel::Sender<MatchMessage>>,
     trade_execution_receiver: crossbeam_channel::Receiver<TradeExecutionMessage>,
     management_manager: Arc<ManagementManager>,
+    wal: WriteAheadLog,
+    trade_execution_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>, // 用于向手续费账户所在分片转发手续费
+    liveness: Liveness,
+    dead_letters: Option<DeadLetterSink>,
+    open_orders: OpenOrderTracker, // 本分片账户的未完成订单，用于账户级风控限制
+    read_overflow: Option<Arc<ReadOverflow>>,
+    placement_mode: PlacementMode,
+    wal_failed: bool, // 预写日志写入失败后不再修改余额，见 append_wal
 }
 
 pub struct MatchProcessor {
@@ -18,832 +79,8115 @@ pub struct MatchProcessor {
     matching_engine: MatchingEngine,
     sequencer_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>,
     management_manager: Arc<ManagementManager>,
+    order_book_publisher: Arc<OrderBookPublisher>,
+    trade_publisher: Arc<TradePublisher>,
+    wal: WriteAheadLog,
+    records_since_snapshot: u64,
+    liveness: Liveness,
+    dead_letters: Option<DeadLetterSink>,
+    depth_cache: Option<Arc<DepthCache>>,
+    confirm_settlement: bool,
+    symbol_messages: HashMap<i32, u64>, // 每个交易对处理过的请求数
+    batch_size: usize,
+    pending_depth: Option<BTreeSet<i32>>, // 批处理中订单簿有变化、批结束后再发布深度的交易对
+    max_maker_rebate: u32, // maker 返佣费率上限，单位百万分之一，0 表示不支持返佣
+    wal_failed: bool, // 预写日志写入失败后不再修改订单簿，见 write_ahead
 }
 
 impl MatchProcessor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: usize,
         receiver: crossbeam_channel::Receiver<MatchMessage>,
         sequencer_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>,
         management_manager: Arc<ManagementManager>,
+        order_book_publisher: Arc<OrderBookPublisher>,
+        trade_publisher: Arc<TradePublisher>,
+        mut matching_engine: MatchingEngine,
+        wal: WriteAheadLog,
     ) -> Self {
+        matching_engine.set_management_manager(management_manager.clone());
         Self {
             id,
             receiver,
-            matching_engine: MatchingEngine::new(),
+            matching_engine,
             sequencer_senders,
             management_manager,
+            order_book_publisher,
+            trade_publisher,
+            wal,
+            records_since_snapshot: 0,
+            liveness: Liveness::new(),
+            dead_letters: None,
+            depth_cache: None,
+            confirm_settlement: false,
+            symbol_messages: HashMap::new(),
+            batch_size: 1,
+            pending_depth: None,
+            max_maker_rebate: 0,
+            wal_failed: false,
         }
     }
 
-    pub fn run(mut self) {
-        println!("Match processor {} started", self.id);
-        loop {
-            match self.receiver.recv() {
-                Ok(message) => match message {
-                    MatchMessage::PlaceOrder {
-                        request_id,
-                        symbol_id,
-                        account_id,
-                        order_type,
-                        side,
-                        price,
-                        quantity,
-                        response_sender,
-                    } => {
-                        self.handle_place_order(
-                            request_id,
-                            symbol_id,
-                            account_id,
-                            order_type,
-                            side,
-                            price,
-                            quantity,
-                            response_sender,
-                        );
-                    }
-                    MatchMessage::GetOrderBook {
-                        request_id,
-                        symbol_id,
-                        levels,
-                        response_sender,
-                    } => {
-                        self.handle_get_order_book(request_id, symbol_id, levels, response_sender);
-                    }
-                    MatchMessage::CancelOrder {
-                        request_id,
-                        symbol_id,
-                        account_id,
-                        order_id,
-                        response_sender,
-                    } => {
-                        self.handle_cancel_order(
-                            request_id,
-                            symbol_id,
-                            account_id,
-                            order_id,
-                            response_sender,
-                        );
-                    }
-                },
-                Err(_) => {
-                    println!("Match processor {} stopped - channel closed", self.id);
-                    break;
-                }
-            }
-        }
+    // 本分片上有订单簿的交易对数量
+    pub fn symbol_count(&self) -> usize {
+        self.matching_engine.symbol_count()
     }
 
-    fn handle_place_order(
-        &mut self,
-        request_id: uuid::Uuid,
-        symbol_id: i32,
-        account_id: i32,
-        order_type: i32,
-        side: i32,
-        price: String,
-        quantity: String,
-        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::PlaceOrderResponse>,
-    ) {
-        println!(
-            "MatchProcessor {}: Processing order - symbol={}, account={}, type={}, side={}, price={}, quantity={}",
-            self.id, symbol_id, account_id, order_type, side, price, quantity
-        );
-
-        // 执行撮合
-        match self.matching_engine.place_order(
-            request_id, symbol_id, account_id, order_type, side, &price, &quantity,
-        ) {
-            Ok((order_id, trades)) => {
-                println!(
-                    "MatchProcessor {}: Order {} placed successfully, {} trades generated",
-                    self.id,
-                    order_id,
-                    trades.len()
-                );
+    // 本分片处理过的该交易对请求数，用于观察分片内各交易对的负载
+    pub fn symbol_message_count(&self, symbol_id: i32) -> u64 {
+        self.symbol_messages.get(&symbol_id).copied().unwrap_or_default()
+    }
 
-                // 如果有成交，发送成交记录到余额管理器执行
-                if !trades.is_empty() {
-                    self.execute_trades(trades, order_id, account_id, response_sender);
-                } else {
-                    // 没有成交，直接返回成功响应
-                    let response = crate::models::schema::PlaceOrderResponse {
-                        code: 0,
-                        message: Some("Order placed successfully".to_string()),
-                        id: order_id as i64,
-                    };
-                    let _ = response_sender.send(response);
-                }
+    // 存活标记，启动线程前注册到健康检查
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
 
-                // 显示当前市场深度
-                if let Some(order_book) = self.matching_engine.get_order_book(symbol_id) {
-                    let (bids, asks) = order_book.get_market_depth(5);
-                    println!("Market depth for symbol {}:", symbol_id);
-                    println!("  Bids: {:?}", bids);
-                    println!("  Asks: {:?}", asks);
-                    if let Some(spread) = order_book.get_spread() {
-                        println!("  Spread: {}", spread);
-                    }
-                }
-            }
-            Err(e) => {
-                println!("MatchProcessor {}: Order failed - {}", self.id, e);
-                let response = crate::models::schema::PlaceOrderResponse {
-                    code: 400,
-                    message: Some(format!("Order failed: {}", e)),
-                    id: 0,
-                };
-                let _ = response_sender.send(response);
-            }
-        }
+    // 成交回调发送失败时写入死信日志，未设置时只打印
+    pub fn set_dead_letters(&mut self, dead_letters: DeadLetterSink) {
+        self.dead_letters = Some(dead_letters);
     }
 
-    fn execute_trades(
-        &self,
-        trades: Vec<Trade>,
-        order_id: u64,
-        taker_account_id: i32,
-        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::PlaceOrderResponse>,
-    ) {
-        println!(
-            "MatchProcessor {}: Executing {} trades for order {} (taker account: {})",
-            self.id,
-            trades.len(),
-            order_id,
-            taker_account_id
-        );
+    // 订单簿变更后更新深度快照缓存，供查询直接读取；未设置时查询都由撮合线程处理
+    pub fn set_depth_cache(&mut self, depth_cache: Arc<DepthCache>) {
+        self.depth_cache = Some(depth_cache);
+    }
 
-        if trades.is_empty() {
-            return;
-        }
+    // 允许 maker 负费率（返佣）的上限，单位百万分之一；默认 0，负费率按 0 处理
+    pub fn set_max_maker_rebate(&mut self, max_maker_rebate: u32) {
+        self.max_maker_rebate = max_maker_rebate;
+    }
 
-        // 获取交易对信息（所有 trades 应该有相同的 symbol_id）
-        let symbol_id = trades[0].symbol_id;
-        let symbol = match self.management_manager.get_symbol(symbol_id) {
-            Some(s) => s,
-            None => {
-                println!("MatchProcessor {}: Symbol {} not found", self.id, symbol_id);
-                return;
-            }
-        };
+    // 开启后有成交的下单响应等所有结算消息处理完再回复，默认撮合后立即回复
+    pub fn set_confirm_settlement(&mut self, confirm_settlement: bool) {
+        self.confirm_settlement = confirm_settlement;
+    }
 
-        // 汇总 taker 的所有 trades（taker 只处理一次）
-        let mut taker_total_base = rust_decimal::Decimal::ZERO;
-        let mut taker_total_quote = rust_decimal::Decimal::ZERO;
-        let mut is_taker_buyer = false;
+    // 每次最多连续处理的消息数，默认 1。大于 1 时批内每笔订单照常回复，
+    // 深度快照和推送在批结束后按交易对合并发布，批内的响应可能早于深度缓存更新
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
 
-        // 遍历所有 trades，汇总 taker 的结算金额，并为每个 maker 发送结算消息
-        for trade in &trades {
-            // 判断 taker 是买方还是卖方
-            is_taker_buyer = order_id == trade.buy_order_id;
-            let taker_account_id_in_trade = if is_taker_buyer {
-                trade.buy_account_id
-            } else {
-                trade.sell_account_id
-            };
-            let maker_account_id_in_trade = if is_taker_buyer {
-                trade.sell_account_id
-            } else {
-                trade.buy_account_id
+    // 处理已收到的消息，再不等待地取出队列中的消息直到凑满一批
+    fn handle_batch(&mut self, message: MatchMessage) {
+        if self.batch_size == 1 {
+            self.handle_message(message);
+            return;
+        }
+        self.pending_depth = Some(BTreeSet::new());
+        self.handle_message(message);
+        for _ in 1..self.batch_size {
+            let Ok(message) = self.receiver.try_recv() else {
+                break;
             };
-
-            // 汇总 taker 的结算金额
-            if taker_account_id_in_trade == taker_account_id {
-                let quote_amount = trade.price * trade.quantity;
-                taker_total_base += trade.quantity;
-                taker_total_quote += quote_amount;
+            self.handle_message(message);
+        }
+        for symbol_id in self.pending_depth.take().unwrap_or_default() {
+            // 批内删除的交易对不再发布，避免重新写入深度缓存
+            if self.management_manager.get_symbol(symbol_id).is_some() {
+                self.publish_order_book(symbol_id);
             }
+        }
+    }
 
-            // 为每个 maker 发送结算消息（每个 trade 都需要处理，因为可能涉及不同的 maker）
-            let maker_shard =
-                (maker_account_id_in_trade % self.sequencer_senders.len() as i32).abs() as usize;
-            
-            if let Some(sender) = self.sequencer_senders.get(maker_shard) {
-                let quote_amount = trade.price * trade.quantity;
-                
-                // maker 的结算：如果 maker 是买方，则扣除 quote，增加 base；如果 maker 是卖方，则扣除 base，增加 quote
-                let (deduct_currency_id, deduct_amount, add_currency_id, add_amount) = 
-                    if is_taker_buyer {
-                        // maker 是卖方：扣除 base currency，增加 quote currency
-                        (symbol.base, trade.quantity, symbol.quote, quote_amount)
-                    } else {
-                        // maker 是买方：扣除 quote currency，增加 base currency
-                        (symbol.quote, quote_amount, symbol.base, trade.quantity)
-                    };
-
-                let settle_msg = TradeExecutionMessage::SettleAccount {
-                    account_id: maker_account_id_in_trade,
-                    symbol_id: trade.symbol_id,
-                    deduct_currency_id,
-                    deduct_amount,
-                    add_currency_id,
-                    add_amount,
-                };
+    fn dead_letter(&self, shard: usize, message: &TradeExecutionMessage) {
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.record(shard, message);
+        }
+    }
 
-                if let Err(e) = sender.send(settle_msg) {
-                    println!("Failed to send maker settle message to sequencer {}: {}", maker_shard, e);
-                } else {
-                    println!(
-                        "Maker settle routed: sequencer={}, account={}, symbol={}, deduct={} {}, add={} {}",
-                        maker_shard,
-                        maker_account_id_in_trade,
-                        trade.symbol_id,
-                        deduct_amount,
-                        deduct_currency_id,
-                        add_amount,
-                        add_currency_id
-                    );
-                }
+    // 订单簿变更前先写预写日志，写入失败时不修改订单簿，由调用方拒绝请求（下单时解冻余额）；
+    // 第一次失败后本分片停止修改订单簿，之后的变更请求都被拒绝，健康检查报告该分片已停止，
+    // 重启后按日志重放，内存状态与日志一致
+    fn write_ahead(&mut self, record: WalRecord) -> Result<(), BalanceError> {
+        if self.wal_failed {
+            return Err(BalanceError::WalWrite(WAL_STOPPED_MESSAGE.to_string()));
+        }
+        match self.wal.append(&record) {
+            Ok(()) => {
+                self.records_since_snapshot += 1;
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    matcher = self.id,
+                    path = %self.wal.path().display(),
+                    error = %e,
+                    "Failed to write WAL, matcher stops applying changes"
+                );
+                self.wal_failed = true;
+                self.liveness.set_alive(false);
+                Err(BalanceError::WalWrite(e.to_string()))
             }
         }
+    }
 
-        // 为 taker 发送汇总的结算消息（只处理一次）
-        if taker_total_base > rust_decimal::Decimal::ZERO || taker_total_quote > rust_decimal::Decimal::ZERO {
-            let taker_shard =
-                (taker_account_id % self.sequencer_senders.len() as i32).abs() as usize;
-            
-            if let Some(sender) = self.sequencer_senders.get(taker_shard) {
-                // taker 的结算：如果 taker 是买方，则扣除 quote，增加 base；如果 taker 是卖方，则扣除 base，增加 quote
-                let (deduct_currency_id, deduct_amount, add_currency_id, add_amount) = 
-                    if is_taker_buyer {
-                        // taker 是买方：扣除 quote currency，增加 base currency
-                        (symbol.quote, taker_total_quote, symbol.base, taker_total_base)
-                    } else {
-                        // taker 是卖方：扣除 base currency，增加 quote currency
-                        (symbol.base, taker_total_base, symbol.quote, taker_total_quote)
-                    };
-
-                let settle_msg = TradeExecutionMessage::SettleAccount {
-                    account_id: taker_account_id,
-                    symbol_id,
-                    deduct_currency_id,
-                    deduct_amount,
-                    add_currency_id,
-                    add_amount,
-                };
-
-                if let Err(e) = sender.send(settle_msg) {
-                    println!("Failed to send taker settle message to sequencer {}: {}", taker_shard, e);
-                } else {
-                    println!(
-                        "Taker settle routed: sequencer={}, account={}, symbol={}, deduct={} {}, add={} {}",
-                        taker_shard,
-                        taker_account_id,
-                        symbol_id,
-                        deduct_amount,
-                        deduct_currency_id,
-                        add_amount,
-                        add_currency_id
-                    );
-                }
+    // 定期生成订单簿快照，恢复时只需重放快照之后的日志
+    fn maybe_snapshot(&mut self) {
+        if self.records_since_snapshot < SNAPSHOT_INTERVAL {
+            return;
+        }
+        let snapshot_path = wal::snapshot_path(self.wal.path());
+        match wal::write_snapshot(&snapshot_path, self.wal.position(), &self.matching_engine) {
+            Ok(()) => {
+                self.records_since_snapshot = 0;
+                info!(
+                    matcher = self.id,
+                    path = %snapshot_path.display(),
+                    offset = self.wal.position(),
+                    "Snapshot written"
+                );
             }
+            Err(e) => error!(
+                matcher = self.id,
+                path = %snapshot_path.display(),
+                error = %e,
+                "Failed to write snapshot"
+            ),
         }
-
-        // 立即返回撮合成功响应
-        let response = crate::models::schema::PlaceOrderResponse {
-            code: 0,
-            message: Some(format!("Order matched with {} trades", trades.len())),
-            id: order_id as i64,
-        };
-        let _ = response_sender.send(response);
     }
 
-    fn handle_get_order_book(
-        &self,
-        _request_id: uuid::Uuid,
-        symbol_id: i32,
-        levels: i32,
-        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::GetOrderBookResponse>,
-    ) {
-        println!(
-            "MatchProcessor {}: Getting orderbook for symbol {}, levels {}",
-            self.id, symbol_id, levels
-        );
-
-        let levels = if levels <= 0 { 20 } else { levels as usize };
-
-        let response = if let Some(order_book) = self.matching_engine.get_order_book(symbol_id) {
-            let (bids, asks) = order_book.get_market_depth(levels);
-
-            let bid_levels: Vec<crate::models::schema::PriceLevel> = bids
-                .into_iter()
-                .map(|(price, quantity)| crate::models::schema::PriceLevel {
-                    price: price.to_string(),
-                    quantity: quantity.to_string(),
-                })
-                .collect();
+    // 独占一个线程运行，等同于只驱动一个分片的工作线程
+    pub fn run(self) {
+        run_matchers(vec![self]);
+    }
 
-            let ask_levels: Vec<crate::models::schema::PriceLevel> = asks
-                .into_iter()
-                .map(|(price, quantity)| crate::models::schema::PriceLevel {
-                    price: price.to_string(),
-                    quantity: quantity.to_string(),
-                })
-                .collect();
+    // 撤销到期订单并解冻剩余部分；先记录扫描时间，重放时按同一时间撤销
+    fn expire_orders(&mut self, now: u64) {
+        if self
+            .matching_engine
+            .next_expiry()
+            .is_none_or(|expires_at| expires_at > now)
+        {
+            return;
+        }
+        if self.write_ahead(WalRecord::ExpireOrders { now }).is_err() {
+            return;
+        }
 
-            let best_bid = order_book.get_best_bid().map(|p| p.to_string());
-            let best_ask = order_book.get_best_ask().map(|p| p.to_string());
-            let spread = order_book.get_spread().map(|s| s.to_string());
+        let expired_orders = self.matching_engine.expire_orders(now);
+        for expired_order in &expired_orders {
+            debug!(
+                matcher = self.id,
+                order_id = expired_order.id,
+                remaining = %expired_order.remaining_quantity(),
+                "Order expired"
+            );
+            self.unfreeze_remaining(expired_order);
+        }
+        // 按交易对ID顺序返回，相邻去重即可
+        let mut symbol_ids: Vec<i32> = expired_orders.iter().map(|order| order.symbol_id).collect();
+        symbol_ids.dedup();
+        for symbol_id in symbol_ids {
+            self.publish_order_book(symbol_id);
+        }
+    }
 
-            crate::models::schema::GetOrderBookResponse {
-                code: 0,
-                message: Some("Success".to_string()),
+    fn handle_message(&mut self, message: MatchMessage) {
+        *self.symbol_messages.entry(message.symbol_id()).or_default() += 1;
+        match message {
+            MatchMessage::PlaceOrder {
+                request_id,
                 symbol_id,
-                bids: bid_levels,
-                asks: ask_levels,
-                best_bid,
-                best_ask,
-                spread,
-                timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64,
+                account_id,
+                order_type,
+                side,
+                time_in_force,
+                price,
+                quantity,
+                taker_rate,
+                maker_rate,
+                post_only,
+                display_quantity,
+                stop_price,
+                trigger_direction,
+                protection_price,
+                expires_at,
+                volume,
+                client_order_id,
+                span,
+                response_sender,
+            } => {
+                let _match = tracing::info_span!(parent: &span, "match", %request_id, symbol_id)
+                    .entered();
+                self.handle_place_order(
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    order_type,
+                    side,
+                    time_in_force,
+                    price,
+                    quantity,
+                    FeeRates::from_ppm(taker_rate, maker_rate, self.max_maker_rebate),
+                    post_only,
+                    display_quantity,
+                    stop_price,
+                    trigger_direction,
+                    protection_price,
+                    expires_at,
+                    volume,
+                    client_order_id,
+                    response_sender,
+                );
             }
-        } else {
-            crate::models::schema::GetOrderBookResponse {
-                code: 404,
-                message: Some("OrderBook not found".to_string()),
+            MatchMessage::ValidateOrder {
+                request_id,
                 symbol_id,
-                bids: vec![],
-                asks: vec![],
-                best_bid: None,
-                best_ask: None,
-                spread: None,
-                timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64,
-            }
-        };
-
-        let _ = response_sender.send(response);
-    }
+                account_id,
+                order_type,
+                side,
+                time_in_force,
+                price,
+                quantity,
+                taker_rate,
+                maker_rate,
+                post_only,
+                display_quantity,
+                stop_price,
+                trigger_direction,
+                protection_price,
+                expires_at,
+                volume,
+                client_order_id,
+                frozen_currency_id,
+                frozen_amount,
+                response_sender,
+            } => {
+                let result = self.validate_order(
+                    OrderParams {
+                        request_id,
+                        symbol_id,
+                        account_id,
+                        order_type,
+                        side,
+                        time_in_force,
+                        price: &price,
+                        quantity: &quantity,
+                        fee_rates: FeeRates::from_ppm(taker_rate, maker_rate, self.max_maker_rebate),
+                        post_only,
+                        display_quantity: display_quantity.as_deref(),
+                        stop_price: stop_price.as_deref(),
+                        trigger_direction,
+                        protection_price: protection_price.as_deref(),
+                        expires_at,
+                        client_order_id: client_order_id.as_deref(),
+                        ..OrderParams::default()
+                    },
+                    volume.as_deref(),
+                );
+                let response = match result {
+                    Ok(()) => PlaceOrderResponse {
+                        frozen_currency_id: Some(frozen_currency_id),
+                        frozen_amount: Some(frozen_amount),
+                        ..PlaceOrderResponse::with_reason(
+                            0,
+                            "Order is valid".to_string(),
+                            0,
+                            RejectReason::NoReject,
+                        )
+                    },
+                    Err((message, reason)) => {
+                        PlaceOrderResponse::with_reason(400, message, 0, reason)
+                    }
+                };
+                let _ = response_sender.send(response);
+            }
+            MatchMessage::GetOrderBook {
+                request_id,
+                symbol_id,
+                levels,
+                bucket,
+                response_sender,
+            } => {
+                self.handle_get_order_book(request_id, symbol_id, levels, bucket, response_sender);
+            }
+            MatchMessage::GetTrades {
+                request_id,
+                symbol_id,
+                limit,
+                response_sender,
+            } => {
+                self.handle_get_trades(request_id, symbol_id, limit, response_sender);
+            }
+            MatchMessage::GetTicker {
+                request_id,
+                symbol_id,
+                response_sender,
+            } => {
+                self.handle_get_ticker(request_id, symbol_id, response_sender);
+            }
+            MatchMessage::GetBbo {
+                request_id: _,
+                symbol_id,
+                response_sender,
+            } => {
+                let response = bbo_response(self.matching_engine.get_order_book(symbol_id), symbol_id);
+                let _ = response_sender.send(response);
+            }
+            MatchMessage::EstimateOrder {
+                request_id,
+                symbol_id,
+                side,
+                quantity,
+                response_sender,
+            } => {
+                self.handle_estimate_order(request_id, symbol_id, side, quantity, response_sender);
+            }
+            MatchMessage::DeleteSymbol {
+                request_id,
+                symbol_id,
+                response_sender,
+            } => {
+                self.handle_delete_symbol(request_id, symbol_id, response_sender);
+            }
+            MatchMessage::GetOpenInterest {
+                request_id,
+                symbol_id,
+                response_sender,
+            } => {
+                self.handle_get_open_interest(request_id, symbol_id, response_sender);
+            }
+            MatchMessage::DumpOrderBook {
+                request_id,
+                symbol_id,
+                response_sender,
+            } => {
+                self.handle_dump_order_book(request_id, symbol_id, response_sender);
+            }
+            MatchMessage::LoadOrderBook {
+                request_id,
+                symbol_id,
+                snapshot,
+                force,
+                funded,
+                response_sender,
+            } => {
+                self.handle_load_order_book(
+                    request_id,
+                    symbol_id,
+                    snapshot,
+                    force,
+                    funded,
+                    response_sender,
+                );
+            }
+            MatchMessage::GetOpenOrders {
+                request_id,
+                account_id,
+                symbol_id,
+                response_sender,
+            } => {
+                self.handle_get_open_orders(request_id, account_id, symbol_id, response_sender);
+            }
+            MatchMessage::GetOrder {
+                request_id,
+                symbol_id,
+                order_id,
+                response_sender,
+            } => {
+                self.handle_get_order(request_id, symbol_id, order_id, response_sender);
+            }
+            MatchMessage::CancelOrder {
+                request_id,
+                symbol_id,
+                account_id,
+                order_id,
+                client_order_id,
+                response_sender,
+            } => {
+                self.handle_cancel_order(
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    order_id,
+                    client_order_id,
+                    response_sender,
+                );
+            }
+            MatchMessage::ForceCancelOrder {
+                request_id,
+                symbol_id,
+                order_id,
+                reason,
+                response_sender,
+            } => {
+                self.handle_force_cancel_order(
+                    request_id,
+                    symbol_id,
+                    order_id,
+                    reason,
+                    response_sender,
+                );
+            }
+            MatchMessage::CancelAllOrders {
+                request_id,
+                symbol_id,
+                account_id,
+                response_sender,
+            } => {
+                self.handle_cancel_all_orders(request_id, symbol_id, account_id, response_sender);
+            }
+            MatchMessage::AmendOrder {
+                request_id,
+                symbol_id,
+                account_id,
+                order_id,
+                side,
+                price,
+                quantity,
+                prefrozen_amount,
+                response_sender,
+            } => {
+                self.handle_amend_order(
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    order_id,
+                    side,
+                    price,
+                    quantity,
+                    prefrozen_amount,
+                    response_sender,
+                );
+            }
+        }
+    }
 
-    fn handle_cancel_order(
+    // 与下单相同的检查：交易对状态、精度规则、客户端订单ID是否重复、只做 maker 的订单是否会立即成交；
+    // 不写预写日志，不分配订单ID，也不改动订单簿
+    fn validate_order(
+        &self,
+        params: OrderParams,
+        volume: Option<&str>,
+    ) -> Result<(), (String, RejectReason)> {
+        let symbol_id = params.symbol_id;
+        let rejected = |e: BalanceError| (format!("Failed to process order: {}", e), e.reject_reason());
+        let symbol = self.management_manager.get_symbol(symbol_id);
+        let status = self.symbol_status(symbol_id);
+        if !status.accepts_orders() {
+            return Err(rejected(BalanceError::MarketHalted(format!(
+                "Symbol {} is {}",
+                symbol_id,
+                status.as_str()
+            ))));
+        }
+        let Some(symbol) = symbol else {
+            return Err(rejected(BalanceError::CurrencyNotFound));
+        };
+        let trading_rules = symbol.trading_rules();
+        let order = match volume {
+            Some(volume) => self.matching_engine.prepare_quote_order(
+                params.request_id,
+                symbol_id,
+                params.account_id,
+                volume,
+                params.fee_rates,
+                trading_rules,
+                params.protection_price,
+                params.client_order_id,
+            ),
+            None => self.matching_engine.prepare_order(OrderParams {
+                trading_rules,
+                ..params
+            }),
+        }
+        .map_err(rejected)?;
+        if self.matching_engine.would_take_liquidity(&order) {
+            return Err((
+                "Order rejected: post-only order would take liquidity".to_string(),
+                RejectReason::PostOnlyCross,
+            ));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_place_order(
         &mut self,
-        _request_id: uuid::Uuid,
+        request_id: uuid::Uuid,
         symbol_id: i32,
         account_id: i32,
-        order_id: u64,
-        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::CancelOrderResponse>,
+        order_type: i32,
+        side: i32,
+        time_in_force: i32,
+        price: String,
+        quantity: String,
+        fee_rates: FeeRates,
+        post_only: bool,
+        display_quantity: Option<String>,
+        stop_price: Option<String>,
+        trigger_direction: i32,
+        protection_price: Option<String>,
+        expires_at: Option<u64>,
+        volume: Option<String>,
+        client_order_id: Option<String>,
+        response_sender: tokio::sync::oneshot::Sender<PlaceOrderResponse>,
     ) {
-        println!(
-            "MatchProcessor {}: Cancelling order {} for account {} on symbol {}",
-            self.id, order_id, account_id, symbol_id
+        debug!(
+            matcher = self.id,
+            symbol_id,
+            account_id,
+            order_type,
+            side,
+            time_in_force,
+            %price,
+            %quantity,
+            "Processing order"
         );
 
-        let response =
-            if let Some(cancelled_order) = self.matching_engine.cancel_order(symbol_id, order_id) {
-                // 检查订单是否属于请求的账户
-                if cancelled_order.account_id != account_id {
-                    crate::models::schema::CancelOrderResponse {
-                        code: 403,
-                        message: Some("Order does not belong to this account".to_string()),
-                        order_id: order_id as i64,
-                        cancelled_quantity: None,
-                        refund_amount: None,
+        let symbol = self.management_manager.get_symbol(symbol_id);
+        let trading_rules = symbol.as_ref().map(Symbol::trading_rules).unwrap_or_default();
+        // 暂停、只可撤单或熔断期间拒绝下单，不写预写日志
+        let status = self.symbol_status(symbol_id);
+        if !status.accepts_orders() {
+            self.reject_order(
+                request_id,
+                symbol_id,
+                account_id,
+                side,
+                &price,
+                &quantity,
+                volume.as_deref(),
+                BalanceError::MarketHalted(format!("Symbol {} is {}", symbol_id, status.as_str())),
+                response_sender,
+            );
+            return;
+        }
+        // 交易对已删除时撮合引擎同样会拒绝，这里提前拒绝，不写预写日志，重放时也不会创建订单簿
+        if symbol.is_none() {
+            self.reject_order(
+                request_id,
+                symbol_id,
+                account_id,
+                side,
+                &price,
+                &quantity,
+                volume.as_deref(),
+                BalanceError::CurrencyNotFound,
+                response_sender,
+            );
+            return;
+        }
+
+        if let Err(e) = self.write_ahead(WalRecord::PlaceOrder {
+            symbol_id,
+            account_id,
+            order_type,
+            side,
+            time_in_force,
+            price: price.clone(),
+            quantity: quantity.clone(),
+            fee_rates,
+            trading_rules: Box::new(trading_rules),
+            post_only,
+            display_quantity: display_quantity.clone(),
+            stop_price: stop_price.clone(),
+            trigger_direction,
+            protection_price: protection_price.clone(),
+            expires_at,
+            volume: volume.clone(),
+            client_order_id: client_order_id.clone(),
+        }) {
+            self.reject_order(
+                request_id,
+                symbol_id,
+                account_id,
+                side,
+                &price,
+                &quantity,
+                volume.as_deref(),
+                e,
+                response_sender,
+            );
+            return;
+        }
+
+        // 执行撮合
+        let started = Instant::now();
+        let result = match &volume {
+            Some(volume) => self.matching_engine.place_quote_order(
+                request_id,
+                symbol_id,
+                account_id,
+                volume,
+                fee_rates,
+                trading_rules,
+                protection_price.as_deref(),
+                client_order_id.as_deref(),
+            ),
+            None => self.matching_engine.place_order(OrderParams {
+                request_id,
+                symbol_id,
+                account_id,
+                order_type,
+                side,
+                time_in_force,
+                price: &price,
+                quantity: &quantity,
+                fee_rates,
+                trading_rules,
+                post_only,
+                display_quantity: display_quantity.as_deref(),
+                stop_price: stop_price.as_deref(),
+                trigger_direction,
+                protection_price: protection_price.as_deref(),
+                expires_at,
+                client_order_id: client_order_id.as_deref(),
+            }),
+        };
+        metrics().match_latency.observe(started.elapsed());
+
+        match result {
+            Ok((order, trades)) => {
+                metrics().orders_placed.inc();
+                metrics().trades.add(trades.len() as u64);
+                let order_id = order.id;
+                debug!(matcher = self.id, order_id, trades = trades.len(), "Order placed");
+                if order.circuit_breaker_tripped {
+                    warn!(
+                        matcher = self.id,
+                        symbol_id,
+                        order_id,
+                        "Circuit breaker tripped, symbol halted"
+                    );
+                }
+
+                // 市价买单冻结了最多花费的 quote，在结算后解冻冻结额与实付金额之差
+                if order.quote_volume.is_none() && order.status == OrderStatus::Cancelled {
+                    // IOC/FOK、市价单或自成交保护撤销的剩余部分，解冻对应余额
+                    let mut unfreeze_order = order.clone();
+                    // 市价买单按请求报价冻结 quote，剩余部分同样按报价解冻
+                    if order.order_type == OrderType::Market && order.side == OrderSide::Bid {
+                        unfreeze_order.price =
+                            rust_decimal::Decimal::from_str_exact(&price).unwrap_or_default();
                     }
+                    self.unfreeze_remaining(&unfreeze_order);
+                }
+                for maker_order in self.matching_engine.take_cancelled_makers(symbol_id) {
+                    self.unfreeze_remaining(&maker_order);
+                }
+
+                // 如果有成交，发送成交记录到余额管理器执行
+                let mut taker_paid = rust_decimal::Decimal::ZERO;
+                if !trades.is_empty() {
+                    taker_paid = self.execute_trades(trades, order_id, account_id, response_sender);
+                } else if order.status == OrderStatus::Cancelled && order.post_only {
+                    // 只做 maker 的订单会立即成交，整单撤销
+                    let response = PlaceOrderResponse::with_reason(
+                        0,
+                        "Order cancelled: post-only order would take liquidity".to_string(),
+                        order_id as i64,
+                        RejectReason::PostOnlyCross,
+                    );
+                    let _ = response_sender.send(response);
+                } else if order.status == OrderStatus::Cancelled && order.circuit_breaker_tripped {
+                    // 对手盘最优价已超出熔断范围，整单撤销并暂停交易对
+                    let response = PlaceOrderResponse::with_reason(
+                        0,
+                        "Order cancelled: circuit breaker tripped".to_string(),
+                        order_id as i64,
+                        RejectReason::MarketHalted,
+                    );
+                    let _ = response_sender.send(response);
+                } else if order.status == OrderStatus::Cancelled && order.self_trade_prevented {
+                    // 对手方只有本账户的订单，自成交保护撤销了 taker
+                    let response = PlaceOrderResponse::with_reason(
+                        0,
+                        "Order cancelled: self-trade prevention".to_string(),
+                        order_id as i64,
+                        RejectReason::SelfTrade,
+                    );
+                    let _ = response_sender.send(response);
+                } else if order.status == OrderStatus::Cancelled {
+                    // IOC/FOK 订单没有任何成交，整单撤销
+                    let response = PlaceOrderResponse::with_reason(
+                        0,
+                        "Order cancelled: no matching liquidity".to_string(),
+                        order_id as i64,
+                        RejectReason::NoLiquidity,
+                    );
+                    let _ = response_sender.send(response);
                 } else {
-                    let cancelled_quantity = cancelled_order.remaining_quantity();
-                    println!(
-                        "MatchProcessor {}: Order {} cancelled, remaining quantity: {}",
-                        self.id, order_id, cancelled_quantity
+                    // 没有成交，直接返回成功响应
+                    let response = PlaceOrderResponse::with_reason(
+                        0,
+                        "Order placed successfully".to_string(),
+                        order_id as i64,
+                        RejectReason::NoReject,
                     );
+                    let _ = response_sender.send(response);
+                }
+                if let Some(quote_volume) = order.quote_volume {
+                    self.release_unspent_quote(&order, quote_volume.volume - taker_paid);
+                }
 
-                    // 发送余额解冻消息到对应的SequencerProcessor
-                    let unfreeze_shard =
-                        (account_id % self.sequencer_senders.len() as i32).abs() as usize;
-                    if let Some(sender) = self.sequencer_senders.get(unfreeze_shard) {
-                        let unfreeze_msg = crate::messages::TradeExecutionMessage::UnfreezeOrder {
-                            order: cancelled_order.clone(),
-                        };
-                        if let Err(e) = sender.send(unfreeze_msg) {
-                            println!("Failed to send unfreeze message: {}", e);
-                        }
+                // 本次成交激活的止损单：结算其成交，解冻撤销的剩余部分
+                for (triggered_order, triggered_trades) in
+                    self.matching_engine.take_triggered_orders(symbol_id)
+                {
+                    if triggered_order.status == OrderStatus::Cancelled
+                        && triggered_order.quote_volume.is_none()
+                    {
+                        self.unfreeze_remaining(&triggered_order);
                     }
-
-                    crate::models::schema::CancelOrderResponse {
-                        code: 0,
-                        message: Some("Order cancelled successfully".to_string()),
-                        order_id: order_id as i64,
-                        cancelled_quantity: Some(cancelled_quantity.to_string()),
-                        refund_amount: None, // Will be calculated in SequencerProcessor
+                    metrics().trades.add(triggered_trades.len() as u64);
+                    let paid = self.settle_trades(
+                        &triggered_trades,
+                        triggered_order.id,
+                        triggered_order.account_id,
+                        None,
+                    );
+                    if let Some(quote_volume) = triggered_order.quote_volume {
+                        self.release_unspent_quote(&triggered_order, quote_volume.volume - paid);
                     }
+                    self.send_order_progress(&triggered_order);
                 }
-            } else {
-                crate::models::schema::CancelOrderResponse {
-                    code: 404,
-                    message: Some("Order not found".to_string()),
-                    order_id: order_id as i64,
-                    cancelled_quantity: None,
-                    refund_amount: None,
+
+                // 同步有成交的订单剩余数量到排序器的风控计数
+                if order.filled_quantity > rust_decimal::Decimal::ZERO {
+                    self.send_order_progress(&order);
                 }
-            };
+                for maker_order in self.matching_engine.take_filled_makers(symbol_id) {
+                    self.send_order_progress(&maker_order);
+                }
+
+                // 显示当前市场深度
+                if tracing::enabled!(tracing::Level::DEBUG) {
+                    if let Some(order_book) = self.matching_engine.get_order_book(symbol_id) {
+                        let (bids, asks) = order_book.get_market_depth(5);
+                        debug!(
+                            symbol_id,
+                            ?bids,
+                            ?asks,
+                            spread = ?order_book.get_spread(),
+                            "Market depth"
+                        );
+                    }
+                }
+
+                self.publish_order_book(symbol_id);
+            }
+            Err(e) => self.reject_order(
+                request_id,
+                symbol_id,
+                account_id,
+                side,
+                &price,
+                &quantity,
+                volume.as_deref(),
+                e,
+                response_sender,
+            ),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn reject_order(
+        &self,
+        request_id: uuid::Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+        volume: Option<&str>,
+        e: BalanceError,
+        response_sender: tokio::sync::oneshot::Sender<PlaceOrderResponse>,
+    ) {
+        metrics().rejects.inc();
+        debug!(matcher = self.id, error = %e, "Order rejected");
+        match volume {
+            Some(volume) => {
+                self.unfreeze_rejected(request_id, symbol_id, account_id, side, "1", volume)
+            }
+            None => {
+                self.unfreeze_rejected(request_id, symbol_id, account_id, side, price, quantity)
+            }
+        }
+        let response = PlaceOrderResponse::with_reason(
+            400,
+            format!("Order failed: {}", e),
+            0,
+            e.reject_reason(),
+        );
+        let _ = response_sender.send(response);
+    }
+
+    // 管理接口设置的交易对状态，熔断暂停期间按暂停处理
+    fn symbol_status(&self, symbol_id: i32) -> SymbolStatus {
+        if self.matching_engine.circuit_breaker_halted(symbol_id) {
+            return SymbolStatus::Halted;
+        }
+        self.management_manager
+            .get_symbol(symbol_id)
+            .map(|symbol| symbol.status)
+            .unwrap_or_default()
+    }
+
+    // 排序器转发前已按报价冻结余额，撮合引擎拒绝的订单（如按订单簿估算超过最大成交额的市价单）
+    // 需要原样解冻；价格或数量无法解析的订单不会被冻结
+    fn unfreeze_rejected(
+        &self,
+        request_id: uuid::Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+    ) {
+        let side = OrderSide::from(side);
+        let Ok(quantity) = rust_decimal::Decimal::from_str_exact(quantity) else {
+            return;
+        };
+        let price = match rust_decimal::Decimal::from_str_exact(price) {
+            Ok(price) => price,
+            Err(_) if side == OrderSide::Ask => rust_decimal::Decimal::ZERO,
+            Err(_) => return,
+        };
+        let rejected = Order::new(
+            0,
+            request_id,
+            symbol_id,
+            account_id,
+            OrderType::Limit,
+            side,
+            TimeInForce::Gtc,
+            price,
+            quantity,
+            now_millis(),
+        );
+        self.unfreeze_remaining(&rejected);
+    }
+
+    fn execute_trades(
+        &self,
+        trades: Vec<Trade>,
+        order_id: u64,
+        taker_account_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<PlaceOrderResponse>,
+    ) -> rust_decimal::Decimal {
+        let response = PlaceOrderResponse::with_reason(
+            0,
+            format!("Order matched with {} trades", trades.len()),
+            order_id as i64,
+            RejectReason::NoReject,
+        );
+        if self.confirm_settlement {
+            // 响应随结算消息送到各分片，最后一条结算处理完时回复
+            let ack = SettlementAck::new(response_sender, response);
+            self.settle_trades(&trades, order_id, taker_account_id, Some(&ack))
+        } else {
+            // 立即返回撮合成功响应
+            let paid = self.settle_trades(&trades, order_id, taker_account_id, None);
+            let _ = response_sender.send(response);
+            paid
+        }
+    }
+
+    // 将一个 taker 订单的成交路由到 maker 和 taker 所在分片结算，返回 taker 买入时实付的 quote
+    // ack 为 Some 时每条结算消息都持有一份，用于结算确认
+    fn settle_trades(
+        &self,
+        trades: &[Trade],
+        order_id: u64,
+        taker_account_id: i32,
+        ack: Option<&Arc<SettlementAck>>,
+    ) -> rust_decimal::Decimal {
+        debug!(
+            matcher = self.id,
+            trades = trades.len(),
+            order_id,
+            taker_account_id,
+            "Executing trades"
+        );
+
+        if trades.is_empty() {
+            return rust_decimal::Decimal::ZERO;
+        }
+
+        // 获取交易对信息（所有 trades 应该有相同的 symbol_id）
+        let symbol_id = trades[0].symbol_id;
+        let symbol = match self.management_manager.get_symbol(symbol_id) {
+            Some(s) => s,
+            None => {
+                error!(matcher = self.id, symbol_id, "Cannot settle trades, symbol not found");
+                return rust_decimal::Decimal::ZERO;
+            }
+        };
+
+        // 成交金额按 quote 币种精度和舍入策略逐笔舍入，taker 付款时按汇总金额舍入，与冻结一致
+        let rounding = self.matching_engine.fee_config.rounding;
+        let quote_scale = self
+            .management_manager
+            .get_currency(symbol.quote)
+            .and_then(|currency| currency.scale);
+        let mut makers_paid = rust_decimal::Decimal::ZERO;
+        let mut makers_received = rust_decimal::Decimal::ZERO;
+        let mut taker_gross_quote = rust_decimal::Decimal::ZERO;
+        let mut taker_received_quote = rust_decimal::Decimal::ZERO;
+
+        // 汇总 taker 的所有 trades（taker 只处理一次）
+        let mut taker_total_base = rust_decimal::Decimal::ZERO;
+        let mut taker_total_fee = rust_decimal::Decimal::ZERO;
+        let mut taker_fee_reserve = rust_decimal::Decimal::ZERO;
+        let mut taker_fee_currency_id = symbol.quote;
+        let mut is_taker_buyer = false;
+
+        // 遍历所有 trades，汇总 taker 的结算金额，并为每个 maker 发送结算消息
+        for trade in trades {
+            // 判断 taker 是买方还是卖方
+            is_taker_buyer = order_id == trade.buy_order_id;
+            // 买方手续费按配置收取 quote 或 base，卖方手续费收取 quote
+            let (buyer_fee_currency_id, seller_fee_currency_id) =
+                trade.fee_currency_ids(symbol.base, symbol.quote);
+            let (taker_fee_currency, maker_fee_currency_id) = if is_taker_buyer {
+                (buyer_fee_currency_id, seller_fee_currency_id)
+            } else {
+                (seller_fee_currency_id, buyer_fee_currency_id)
+            };
+            taker_fee_currency_id = taker_fee_currency;
+            let taker_account_id_in_trade = if is_taker_buyer {
+                trade.buy_account_id
+            } else {
+                trade.sell_account_id
+            };
+            let maker_account_id_in_trade = if is_taker_buyer {
+                trade.sell_account_id
+            } else {
+                trade.buy_account_id
+            };
+
+            let gross_quote = trade.price * trade.quantity;
+            let (paid_quote, received_quote) = rounding.split_quote(gross_quote, quote_scale);
+
+            // 汇总 taker 的结算金额
+            if taker_account_id_in_trade == taker_account_id {
+                taker_total_base += trade.quantity;
+                taker_gross_quote += gross_quote;
+                taker_received_quote += received_quote;
+                taker_total_fee += trade.taker_fee;
+                if is_taker_buyer {
+                    taker_fee_reserve += trade.buyer_fee_reserve;
+                }
+            }
+
+            // 为每个 maker 发送结算消息（每个 trade 都需要处理，因为可能涉及不同的 maker）
+            let maker_shard =
+                (maker_account_id_in_trade % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+            
+            if let Some(sender) = self.sequencer_senders.get(maker_shard) {
+                // maker 的结算：如果 maker 是买方，则扣除 quote，增加 base；如果 maker 是卖方，则扣除 base，增加 quote
+                let (deduct_currency_id, deduct_amount, add_currency_id, add_amount) = 
+                    if is_taker_buyer {
+                        // maker 是卖方：扣除 base currency，增加 quote currency
+                        makers_received += received_quote;
+                        (symbol.base, trade.quantity, symbol.quote, received_quote)
+                    } else {
+                        // maker 是买方：扣除 quote currency，增加 base currency
+                        makers_paid += paid_quote;
+                        (symbol.quote, paid_quote, symbol.base, trade.quantity)
+                    };
+
+                let settle_msg = TradeExecutionMessage::SettleAccount {
+                    account_id: maker_account_id_in_trade,
+                    symbol_id: trade.symbol_id,
+                    trade_id: trade.id,
+                    taker: false,
+                    deduct_currency_id,
+                    deduct_amount,
+                    add_currency_id,
+                    add_amount,
+                    fee_currency_id: maker_fee_currency_id,
+                    fee_amount: trade.maker_fee,
+                    fee_reserve: if is_taker_buyer {
+                        rust_decimal::Decimal::ZERO
+                    } else {
+                        trade.buyer_fee_reserve
+                    },
+                    span: tracing::Span::current(),
+                    ack: ack.cloned(),
+                };
+
+                if let Err(e) = sender.send(settle_msg) {
+                    error!(sequencer = maker_shard, error = %e, "Failed to send maker settlement");
+                    self.dead_letter(maker_shard, &e.0);
+                } else {
+                    debug!(
+                        sequencer = maker_shard,
+                        account_id = maker_account_id_in_trade,
+                        symbol_id = trade.symbol_id,
+                        %deduct_amount,
+                        deduct_currency_id,
+                        %add_amount,
+                        add_currency_id,
+                        "Maker settlement routed"
+                    );
+                }
+            }
+        }
+
+        // 付款方实付与收款方实收之差即零头，计入手续费账户，保证双方变动加零头等于成交金额
+        let (taker_total_quote, dust) = if is_taker_buyer {
+            let taker_paid = rounding.split_quote(taker_gross_quote, quote_scale).0;
+            (taker_paid, taker_paid - makers_received)
+        } else {
+            (taker_received_quote, makers_paid - taker_received_quote)
+        };
+        if !dust.is_zero() {
+            self.collect_dust(symbol.quote, dust);
+        }
+
+        // 为 taker 发送汇总的结算消息（只处理一次）
+        if taker_total_base > rust_decimal::Decimal::ZERO || taker_total_quote > rust_decimal::Decimal::ZERO {
+            let taker_shard =
+                (taker_account_id % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+            
+            if let Some(sender) = self.sequencer_senders.get(taker_shard) {
+                // taker 的结算：如果 taker 是买方，则扣除 quote，增加 base；如果 taker 是卖方，则扣除 base，增加 quote
+                let (deduct_currency_id, deduct_amount, add_currency_id, add_amount) = 
+                    if is_taker_buyer {
+                        // taker 是买方：扣除 quote currency，增加 base currency
+                        (symbol.quote, taker_total_quote, symbol.base, taker_total_base)
+                    } else {
+                        // taker 是卖方：扣除 base currency，增加 quote currency
+                        (symbol.base, taker_total_base, symbol.quote, taker_total_quote)
+                    };
+
+                let settle_msg = TradeExecutionMessage::SettleAccount {
+                    account_id: taker_account_id,
+                    symbol_id,
+                    trade_id: trades[0].id,
+                    taker: true,
+                    deduct_currency_id,
+                    deduct_amount,
+                    add_currency_id,
+                    add_amount,
+                    fee_currency_id: taker_fee_currency_id,
+                    fee_amount: taker_total_fee,
+                    fee_reserve: taker_fee_reserve,
+                    span: tracing::Span::current(),
+                    ack: ack.cloned(),
+                };
+
+                if let Err(e) = sender.send(settle_msg) {
+                    error!(sequencer = taker_shard, error = %e, "Failed to send taker settlement");
+                    self.dead_letter(taker_shard, &e.0);
+                } else {
+                    debug!(
+                        sequencer = taker_shard,
+                        account_id = taker_account_id,
+                        symbol_id,
+                        %deduct_amount,
+                        deduct_currency_id,
+                        %add_amount,
+                        add_currency_id,
+                        "Taker settlement routed"
+                    );
+                }
+            }
+        }
+
+        self.publish_trades(trades);
+        if is_taker_buyer {
+            taker_total_quote
+        } else {
+            rust_decimal::Decimal::ZERO
+        }
+    }
+
+    // 舍入零头发往手续费账户所在分片入账；HalfEven 下 taker 按汇总金额付款时零头可能为负，由交易所承担
+    fn collect_dust(&self, currency_id: i32, amount: rust_decimal::Decimal) {
+        let fee_shard =
+            (FEE_ACCOUNT_ID % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+        let Some(sender) = self.sequencer_senders.get(fee_shard) else {
+            return;
+        };
+        if let Err(e) = sender.send(TradeExecutionMessage::CollectFee {
+            currency_id,
+            amount,
+        }) {
+            error!(sequencer = fee_shard, error = %e, "Failed to send rounding dust");
+            self.dead_letter(fee_shard, &e.0);
+        }
+    }
+
+    fn handle_get_order_book(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        levels: i32,
+        bucket: Option<rust_decimal::Decimal>,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::GetOrderBookResponse>,
+    ) {
+        debug!(matcher = self.id, symbol_id, levels, "Getting order book");
+
+        let levels = if levels <= 0 { 20 } else { levels as usize };
+
+        // 重启后还没有变更过的订单簿在第一次查询时补上快照
+        if let Some(depth_cache) = &self.depth_cache {
+            let order_book = self.matching_engine.get_order_book(symbol_id);
+            if order_book.is_some() && !depth_cache.contains(symbol_id) {
+                let snapshot =
+                    order_book_response(order_book, symbol_id, ORDER_BOOK_STREAM_LEVELS, None);
+                depth_cache.store(symbol_id, snapshot);
+            }
+        }
+
+        let response = order_book_response(
+            self.matching_engine.get_order_book(symbol_id),
+            symbol_id,
+            levels,
+            bucket,
+        );
+
+        let _ = response_sender.send(response);
+    }
+
+    fn handle_get_trades(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        limit: i32,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::GetTradesResponse>,
+    ) {
+        debug!(matcher = self.id, symbol_id, limit, "Getting trades");
+
+        let limit = if limit <= 0 {
+            DEFAULT_TRADES_LIMIT
+        } else {
+            (limit as usize).min(MAX_TRADES_LIMIT)
+        };
+
+        let trades = self
+            .matching_engine
+            .get_recent_trades(symbol_id, limit)
+            .into_iter()
+            .map(trade_event)
+            .collect();
+
+        let response = crate::models::schema::GetTradesResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            symbol_id,
+            trades,
+        };
+
+        let _ = response_sender.send(response);
+    }
+
+    fn handle_get_ticker(
+        &mut self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::TickerResponse>,
+    ) {
+        debug!(matcher = self.id, symbol_id, "Getting ticker");
+
+        let now = now_millis();
+
+        let response = match self.matching_engine.get_order_book_mut(symbol_id) {
+            Some(order_book) => {
+                let last_price = order_book.last_trade_price().map(|p| p.to_string());
+                let spread_bps = order_book.spread_bps().map(|bps| bps.to_string());
+                let stats = order_book.stats_24h(now);
+                crate::models::schema::TickerResponse {
+                    code: 0,
+                    message: Some("Success".to_string()),
+                    symbol_id,
+                    last_price,
+                    open: stats.open_24h.map(|p| p.to_string()),
+                    high: stats.high_24h.map(|p| p.to_string()),
+                    low: stats.low_24h.map(|p| p.to_string()),
+                    volume: stats.volume_24h.to_string(),
+                    timestamp: now as i64,
+                    spread_bps,
+                }
+            }
+            None => crate::models::schema::TickerResponse {
+                code: 404,
+                message: Some("OrderBook not found".to_string()),
+                symbol_id,
+                last_price: None,
+                open: None,
+                high: None,
+                low: None,
+                volume: "0".to_string(),
+                timestamp: now as i64,
+                spread_bps: None,
+            },
+        };
+
+        let _ = response_sender.send(response);
+    }
+
+    // 订单簿还不存在时按空订单簿估算
+    fn handle_estimate_order(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        side: i32,
+        quantity: rust_decimal::Decimal,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::EstimateOrderResponse>,
+    ) {
+        let side = OrderSide::from(side);
+        let estimate = match self.matching_engine.get_order_book(symbol_id) {
+            Some(order_book) => order_book.estimate_market_fill(&side, quantity),
+            None => OrderBook::new(symbol_id).estimate_market_fill(&side, quantity),
+        };
+        let response = crate::models::schema::EstimateOrderResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            symbol_id,
+            filled_quantity: estimate.filled_quantity.to_string(),
+            average_price: estimate.average_price.map(|p| p.to_string()),
+            worst_price: estimate.worst_price.map(|p| p.to_string()),
+            partial: estimate.partial,
+        };
+        let _ = response_sender.send(response);
+    }
+
+    // 交易对上的订单都在本线程撮合，检查与删除之间不会有新订单挂入
+    fn handle_delete_symbol(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::DeleteSymbolResponse>,
+    ) {
+        let resting = self
+            .matching_engine
+            .get_order_book(symbol_id)
+            .is_some_and(OrderBook::has_open_orders);
+        let (code, message) = if resting {
+            (409, "Symbol has open orders")
+        } else if self.management_manager.delete_symbol(symbol_id) {
+            if let Some(depth_cache) = &self.depth_cache {
+                depth_cache.remove(symbol_id);
+            }
+            (0, "Success")
+        } else {
+            (404, "Symbol not found")
+        };
+        let _ = response_sender.send(crate::models::schema::DeleteSymbolResponse {
+            code,
+            message: Some(message.to_string()),
+        });
+    }
+
+    fn handle_dump_order_book(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<
+            crate::models::schema::DumpOrderBookSnapshotResponse,
+        >,
+    ) {
+        let response = match self.matching_engine.dump_order_book(symbol_id) {
+            Some(snapshot) => crate::models::schema::DumpOrderBookSnapshotResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                snapshot,
+            },
+            None => crate::models::schema::DumpOrderBookSnapshotResponse {
+                code: 404,
+                message: Some("OrderBook not found".to_string()),
+                snapshot: Vec::new(),
+            },
+        };
+        let _ = response_sender.send(response);
+    }
+
+    // 写入预写日志后装入，重启后重放时覆盖为同一订单簿；force 替换已有挂单时先按撤单撤销并解冻。
+    // 快照中的未完成订单要由排序器先冻结余额（funded），否则只能装入没有未完成订单的快照
+    #[allow(clippy::too_many_arguments)]
+    fn handle_load_order_book(
+        &mut self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        snapshot: Vec<u8>,
+        force: bool,
+        funded: bool,
+        response_sender: tokio::sync::oneshot::Sender<
+            crate::models::schema::LoadOrderBookSnapshotResponse,
+        >,
+    ) {
+        let resting = self
+            .matching_engine
+            .get_order_book(symbol_id)
+            .is_some_and(OrderBook::has_open_orders);
+        let (code, message) = if self.management_manager.get_symbol(symbol_id).is_none() {
+            (404, "Symbol not found".to_string())
+        } else if !funded
+            && MatchingEngine::snapshot_open_orders(symbol_id, &snapshot)
+                .is_ok_and(|orders| !orders.is_empty())
+        {
+            (409, "Snapshot has open orders without frozen balances".to_string())
+        } else if resting && !force {
+            (409, "Symbol has open orders".to_string())
+        } else if let Err(e) = self.matching_engine.check_order_book_snapshot(symbol_id, &snapshot) {
+            (400, e.to_string())
+        } else if let Err(e) = self.cancel_replaced_orders(symbol_id, resting) {
+            (500, e.to_string())
+        } else if let Err(e) = self.write_ahead(WalRecord::LoadOrderBook {
+            symbol_id,
+            snapshot: snapshot.clone(),
+        }) {
+            (500, e.to_string())
+        } else {
+            // 装入失败时重放同一条记录也会失败，订单簿保持不变
+            match self.matching_engine.load_order_book(symbol_id, &snapshot, force) {
+                Ok(()) => {
+                    info!(matcher = self.id, symbol_id, force, "Order book loaded");
+                    self.publish_order_book(symbol_id);
+                    (0, "Success".to_string())
+                }
+                Err(e) => (400, e.to_string()),
+            }
+        };
+        let _ = response_sender.send(crate::models::schema::LoadOrderBookSnapshotResponse {
+            code,
+            message: Some(message),
+        });
+    }
+
+    // 被替换的订单和撤销所有订单一样先写日志再撤销，剩余部分的冻结余额退回排序器
+    fn cancel_replaced_orders(&mut self, symbol_id: i32, resting: bool) -> Result<(), BalanceError> {
+        if !resting {
+            return Ok(());
+        }
+        self.write_ahead(WalRecord::CancelAllOrders {
+            symbol_id,
+            account_id: ALL_ACCOUNTS,
+        })?;
+        let cancelled_orders = self.matching_engine.cancel_all(symbol_id, ALL_ACCOUNTS);
+        for cancelled_order in &cancelled_orders {
+            self.unfreeze_remaining(cancelled_order);
+        }
+        metrics().cancels.add(cancelled_orders.len() as u64);
+        info!(
+            matcher = self.id,
+            symbol_id,
+            cancelled = cancelled_orders.len(),
+            "Cancelled orders replaced by loaded order book"
+        );
+        Ok(())
+    }
+
+    // 合计随订单簿变更增量维护，读取不遍历挂单；已配置但还没有订单簿的交易对返回 0
+    fn handle_get_open_interest(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<
+            crate::models::schema::GetOpenInterestResponse,
+        >,
+    ) {
+        let response = if self.management_manager.get_symbol(symbol_id).is_none() {
+            crate::models::schema::GetOpenInterestResponse {
+                code: 404,
+                message: Some("Symbol not found".to_string()),
+                symbol_id,
+                ..Default::default()
+            }
+        } else {
+            let open_interest = self
+                .matching_engine
+                .get_order_book(symbol_id)
+                .map(OrderBook::open_interest)
+                .unwrap_or_default();
+            crate::models::schema::GetOpenInterestResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                symbol_id,
+                bid_quantity: open_interest.bid_quantity.normalize().to_string(),
+                bid_notional: open_interest.bid_notional.normalize().to_string(),
+                ask_quantity: open_interest.ask_quantity.normalize().to_string(),
+                ask_notional: open_interest.ask_notional.normalize().to_string(),
+            }
+        };
+        let _ = response_sender.send(response);
+    }
+
+    fn handle_get_open_orders(
+        &self,
+        _request_id: uuid::Uuid,
+        account_id: i32,
+        symbol_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::GetOpenOrdersResponse>,
+    ) {
+        debug!(matcher = self.id, account_id, symbol_id, "Getting open orders");
+
+        let orders = self
+            .matching_engine
+            .get_open_orders(account_id, symbol_id)
+            .into_iter()
+            .map(open_order)
+            .collect();
+
+        let response = crate::models::schema::GetOpenOrdersResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            orders,
+        };
+
+        let _ = response_sender.send(response);
+    }
+
+    // 已完成的订单在保留数量内仍可查询，被清理后返回 410，与从未存在的订单（404）区分
+    fn handle_get_order(
+        &self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        order_id: u64,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::GetOrderResponse>,
+    ) {
+        let response = match self.matching_engine.get_order(symbol_id, order_id) {
+            Some(order) => crate::models::schema::GetOrderResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                order: Some(open_order(order)),
+                status: order.status.clone() as i32,
+                remaining_quantity: Some(order.remaining_quantity().to_string()),
+            },
+            None if self.matching_engine.is_order_pruned(order_id) => {
+                crate::models::schema::GetOrderResponse {
+                    code: 410,
+                    message: Some("Order is no longer retained".to_string()),
+                    ..Default::default()
+                }
+            }
+            None => crate::models::schema::GetOrderResponse {
+                code: 404,
+                message: Some("Order not found".to_string()),
+                ..Default::default()
+            },
+        };
+        let _ = response_sender.send(response);
+    }
+
+    fn handle_cancel_order(
+        &mut self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_id: u64,
+        client_order_id: Option<String>,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::CancelOrderResponse>,
+    ) {
+        // 按客户端订单ID撤单时先换成订单ID，预写日志只记录订单ID
+        let order_id = match &client_order_id {
+            Some(client_order_id) => {
+                match self.matching_engine.find_client_order(symbol_id, account_id, client_order_id)
+                {
+                    Some(order) => order.id,
+                    None => {
+                        let _ = response_sender.send(crate::models::schema::CancelOrderResponse {
+                            code: 404,
+                            message: Some("Order not found".to_string()),
+                            order_id: 0,
+                            cancelled_quantity: None,
+                            refund_amount: None,
+                        });
+                        return;
+                    }
+                }
+            }
+            None => order_id,
+        };
+        debug!(matcher = self.id, order_id, account_id, symbol_id, "Cancelling order");
+
+        let status = self.symbol_status(symbol_id);
+        if !status.accepts_cancels() {
+            let _ = response_sender.send(crate::models::schema::CancelOrderResponse {
+                code: 400,
+                message: Some(format!("Symbol {} is {}", symbol_id, status.as_str())),
+                order_id: order_id as i64,
+                cancelled_quantity: None,
+                refund_amount: None,
+            });
+            return;
+        }
+
+        if let Err(e) = self.write_ahead(WalRecord::CancelOrder {
+            symbol_id,
+            order_id,
+        }) {
+            let _ = response_sender.send(crate::models::schema::CancelOrderResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                order_id: order_id as i64,
+                cancelled_quantity: None,
+                refund_amount: None,
+            });
+            return;
+        }
+
+        let response =
+            if let Some(cancelled_order) = self.matching_engine.cancel_order(symbol_id, order_id) {
+                // 检查订单是否属于请求的账户
+                if cancelled_order.account_id != account_id {
+                    crate::models::schema::CancelOrderResponse {
+                        code: 403,
+                        message: Some("Order does not belong to this account".to_string()),
+                        order_id: order_id as i64,
+                        cancelled_quantity: None,
+                        refund_amount: None,
+                    }
+                } else {
+                    debug!(
+                        matcher = self.id,
+                        order_id,
+                        remaining = %cancelled_order.remaining_quantity(),
+                        "Order cancelled"
+                    );
+
+                    // 由 SequencerProcessor 解冻余额后带上退还金额回复
+                    self.send_unfreeze(&cancelled_order, Some(response_sender), None);
+                    self.publish_order_book(symbol_id);
+                    metrics().cancels.inc();
+                    return;
+                }
+            } else {
+                crate::models::schema::CancelOrderResponse {
+                    code: 404,
+                    message: Some("Order not found".to_string()),
+                    order_id: order_id as i64,
+                    cancelled_quantity: None,
+                    refund_amount: None,
+                }
+            };
+
+        let _ = response_sender.send(response);
+    }
+
+    // 管理员强制撤单：不校验订单归属，解冻时审计记录带上操作原因
+    fn handle_force_cancel_order(
+        &mut self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        order_id: u64,
+        reason: String,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::CancelOrderResponse>,
+    ) {
+        info!(matcher = self.id, order_id, symbol_id, %reason, "Force cancelling order");
+
+        if let Err(e) = self.write_ahead(WalRecord::CancelOrder {
+            symbol_id,
+            order_id,
+        }) {
+            let _ = response_sender.send(crate::models::schema::CancelOrderResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                order_id: order_id as i64,
+                cancelled_quantity: None,
+                refund_amount: None,
+            });
+            return;
+        }
+
+        match self.matching_engine.cancel_order(symbol_id, order_id) {
+            Some(cancelled_order) => {
+                self.send_unfreeze(&cancelled_order, Some(response_sender), Some(reason));
+                self.publish_order_book(symbol_id);
+                metrics().cancels.inc();
+            }
+            None => {
+                let _ = response_sender.send(crate::models::schema::CancelOrderResponse {
+                    code: 404,
+                    message: Some("Order not found".to_string()),
+                    order_id: order_id as i64,
+                    cancelled_quantity: None,
+                    refund_amount: None,
+                });
+            }
+        }
+    }
+
+    fn handle_cancel_all_orders(
+        &mut self,
+        _request_id: uuid::Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        response_sender: tokio::sync::oneshot::Sender<
+            crate::models::schema::CancelAllOrdersResponse,
+        >,
+    ) {
+        debug!(matcher = self.id, account_id, symbol_id, "Cancelling all orders");
+
+        let status = self.symbol_status(symbol_id);
+        if !status.accepts_cancels() {
+            let _ = response_sender.send(crate::models::schema::CancelAllOrdersResponse {
+                code: 400,
+                message: Some(format!("Symbol {} is {}", symbol_id, status.as_str())),
+                order_ids: vec![],
+            });
+            return;
+        }
+
+        if let Err(e) = self.write_ahead(WalRecord::CancelAllOrders {
+            symbol_id,
+            account_id,
+        }) {
+            let _ = response_sender.send(crate::models::schema::CancelAllOrdersResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                order_ids: vec![],
+            });
+            return;
+        }
+
+        let cancelled_orders = self.matching_engine.cancel_all(symbol_id, account_id);
+
+        // 每个被撤销的订单都解冻剩余部分对应的余额
+        for cancelled_order in &cancelled_orders {
+            self.unfreeze_remaining(cancelled_order);
+        }
+        if !cancelled_orders.is_empty() {
+            self.publish_order_book(symbol_id);
+        }
+        metrics().cancels.add(cancelled_orders.len() as u64);
+
+        let response = crate::models::schema::CancelAllOrdersResponse {
+            code: 0,
+            message: Some(format!("{} orders cancelled", cancelled_orders.len())),
+            order_ids: cancelled_orders.iter().map(|order| order.id as i64).collect(),
+        };
+
+        let _ = response_sender.send(response);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_amend_order(
+        &mut self,
+        request_id: uuid::Uuid,
+        symbol_id: i32,
+        account_id: i32,
+        order_id: u64,
+        side: i32,
+        price: String,
+        quantity: String,
+        prefrozen_amount: Option<rust_decimal::Decimal>,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::AmendOrderResponse>,
+    ) {
+        debug!(
+            matcher = self.id,
+            order_id,
+            account_id,
+            symbol_id,
+            %price,
+            %quantity,
+            "Amending order"
+        );
+
+        // 检查订单是否属于请求的账户且方向一致
+        let current = self
+            .matching_engine
+            .get_order_book(symbol_id)
+            .and_then(|order_book| order_book.orders.get(&order_id))
+            .cloned();
+        let owner = current
+            .as_ref()
+            .map(|order| (order.account_id, order.side.clone() as i32));
+
+        // 改单只需为新增的占用冻结余额：新价格和数量的剩余冻结额减去原订单的剩余冻结额；
+        // 名义价值增加时同样要由排序器检查账户风控
+        let amended = current.as_ref().map(|order| {
+            let mut amended = order.clone();
+            amended.price = rust_decimal::Decimal::from_str_exact(&price).unwrap_or(order.price);
+            amended.quantity =
+                rust_decimal::Decimal::from_str_exact(&quantity).unwrap_or(order.quantity);
+            amended
+        });
+        let (additional_freeze, notional, raises_notional) = match (&current, &amended) {
+            (Some(order), Some(amended)) => {
+                let notional = amended.price * amended.remaining_quantity();
+                (
+                    amended.remaining_freeze_amount() - order.remaining_freeze_amount(),
+                    notional,
+                    notional > order.price * order.remaining_quantity(),
+                )
+            }
+            _ => (rust_decimal::Decimal::ZERO, rust_decimal::Decimal::ZERO, false),
+        };
+        let funded = prefrozen_amount.unwrap_or_default();
+
+        let status = self.symbol_status(symbol_id);
+        let result = match owner {
+            _ if !status.accepts_orders() => {
+                Err((400, format!("Symbol {} is {}", symbol_id, status.as_str())))
+            }
+            None => Err((404, "Order not found".to_string())),
+            Some((owner_account_id, _)) if owner_account_id != account_id => {
+                Err((403, "Order does not belong to this account".to_string()))
+            }
+            Some((_, order_side)) if order_side != side => {
+                Err((400, "Order side mismatch".to_string()))
+            }
+            Some(_) if (additional_freeze > funded || raises_notional) && prefrozen_amount.is_none() => {
+                // 退回排序器检查风控并冻结差额，之后重新转发
+                let shard =
+                    (account_id % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+                if let Some(sender) = self.sequencer_senders.get(shard) {
+                    let message = TradeExecutionMessage::AmendNeedsFreeze {
+                        request_id,
+                        symbol_id,
+                        account_id,
+                        order_id,
+                        side,
+                        price,
+                        quantity,
+                        amount: additional_freeze.max(rust_decimal::Decimal::ZERO),
+                        order_request_id: current
+                            .as_ref()
+                            .map(|order| order.request_id)
+                            .unwrap_or_default(),
+                        notional,
+                        response_sender,
+                    };
+                    if let Err(e) = sender.send(message) {
+                        error!(sequencer = shard, error = %e, "Failed to request amend freeze");
+                    }
+                }
+                return;
+            }
+            Some(_) if additional_freeze > funded => {
+                // 冻结期间订单有成交，所需差额已变化
+                Err((409, "Order changed while amending, please retry".to_string()))
+            }
+            Some(_) => self
+                .write_ahead(WalRecord::AmendOrder {
+                    symbol_id,
+                    order_id,
+                    price: price.clone(),
+                    quantity: quantity.clone(),
+                })
+                .map_err(|e| (500, e.to_string()))
+                .and_then(|()| {
+                    self.matching_engine
+                        .amend_order(symbol_id, order_id, &price, &quantity)
+                        .map_err(|e| (400, format!("Amend failed: {}", e)))
+                }),
+        };
+
+        let (orders, response) = match result {
+            Ok((previous, amended)) => {
+                self.publish_order_book(symbol_id);
+                let response = crate::models::schema::AmendOrderResponse {
+                    code: 0,
+                    message: Some("Order amended successfully".to_string()),
+                    order_id: order_id as i64,
+                    price: Some(amended.price.to_string()),
+                    quantity: Some(amended.quantity.to_string()),
+                };
+                (Some(Box::new((previous, amended))), response)
+            }
+            Err((code, message)) => {
+                let response = crate::models::schema::AmendOrderResponse {
+                    code,
+                    message: Some(message),
+                    order_id: order_id as i64,
+                    price: None,
+                    quantity: None,
+                };
+                (None, response)
+            }
+        };
+
+        // 无论成功与否都回到 SequencerProcessor 释放预冻结余额，再由其返回响应
+        let shard = (account_id % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+        if let Some(sender) = self.sequencer_senders.get(shard) {
+            let amended_msg = TradeExecutionMessage::OrderAmended {
+                account_id,
+                symbol_id,
+                side,
+                prefrozen_amount: funded,
+                orders,
+                response,
+                response_sender,
+            };
+            if let Err(e) = sender.send(amended_msg) {
+                error!(sequencer = shard, error = %e, "Failed to send amended order");
+                self.dead_letter(shard, &e.0);
+            }
+        }
+    }
+
+    // 订单簿变化后更新快照缓存并推送给订阅者；慢订阅者由广播队列丢弃旧消息，不阻塞撮合线程
+    fn publish_order_book(&mut self, symbol_id: i32) {
+        if let Some(pending_depth) = &mut self.pending_depth {
+            pending_depth.insert(symbol_id);
+            return;
+        }
+        let has_subscribers = self.order_book_publisher.has_subscribers(symbol_id);
+        if !has_subscribers && self.depth_cache.is_none() {
+            return;
+        }
+        let snapshot = order_book_response(
+            self.matching_engine.get_order_book(symbol_id),
+            symbol_id,
+            ORDER_BOOK_STREAM_LEVELS,
+            None,
+        );
+        if let Some(depth_cache) = &self.depth_cache {
+            depth_cache.store(symbol_id, snapshot.clone());
+        }
+        if has_subscribers {
+            self.order_book_publisher.publish(symbol_id, snapshot);
+        }
+    }
+
+    // 逐笔推送成交给订阅者，广播队列有界，不阻塞撮合线程
+    fn publish_trades(&self, trades: &[Trade]) {
+        for trade in trades {
+            if !self.trade_publisher.has_subscribers(trade.symbol_id) {
+                continue;
+            }
+            self.trade_publisher
+                .publish(trade.symbol_id, trade_event(trade));
+        }
+    }
+
+    fn unfreeze_remaining(&self, order: &Order) {
+        self.send_unfreeze(order, None, None);
+    }
+
+    // 市价买单冻结额中没有花掉的 quote，包括未成交部分和低于报价成交省下的部分；
+    // 按单价 1、数量为该金额构造订单交给排序器解冻
+    fn release_unspent_quote(&self, order: &Order, amount: rust_decimal::Decimal) {
+        if amount <= rust_decimal::Decimal::ZERO {
+            return;
+        }
+        let mut unfreeze_order = order.clone();
+        unfreeze_order.price = rust_decimal::Decimal::ONE;
+        unfreeze_order.quantity = amount;
+        unfreeze_order.filled_quantity = rust_decimal::Decimal::ZERO;
+        self.unfreeze_remaining(&unfreeze_order);
+    }
+
+    // 已完成的订单剩余数量按 0 发送；发送失败时风控计数会多算该订单，不影响余额
+    fn send_order_progress(&self, order: &Order) {
+        let shard = (order.account_id % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+        let remaining_quantity = if order.is_terminal() {
+            rust_decimal::Decimal::ZERO
+        } else {
+            order.remaining_quantity()
+        };
+        if let Some(sender) = self.sequencer_senders.get(shard) {
+            let progress_msg = TradeExecutionMessage::OrderProgress {
+                account_id: order.account_id,
+                request_id: order.request_id,
+                remaining_quantity,
+            };
+            if let Err(e) = sender.send(progress_msg) {
+                error!(sequencer = shard, error = %e, "Failed to send order progress");
+            }
+        }
+    }
+
+    // 带撤单响应时由解冻所在分片回复；消息发送失败则直接回复，不带退还金额
+    fn send_unfreeze(
+        &self,
+        order: &Order,
+        response_sender: Option<
+            tokio::sync::oneshot::Sender<crate::models::schema::CancelOrderResponse>,
+        >,
+        note: Option<String>,
+    ) {
+        let unfreeze_shard =
+            (order.account_id % self.sequencer_senders.len() as i32).unsigned_abs() as usize;
+        let unfreeze_msg = TradeExecutionMessage::UnfreezeOrder {
+            order: order.clone(),
+            response_sender,
+            note,
+        };
+        let returned = match self.sequencer_senders.get(unfreeze_shard) {
+            Some(sender) => match sender.send(unfreeze_msg) {
+                Ok(()) => return,
+                Err(e) => {
+                    error!(sequencer = unfreeze_shard, error = %e, "Failed to send unfreeze");
+                    self.dead_letter(unfreeze_shard, &e.0);
+                    e.0
+                }
+            },
+            None => unfreeze_msg,
+        };
+        if let TradeExecutionMessage::UnfreezeOrder {
+            order,
+            response_sender: Some(response_sender),
+            ..
+        } = returned
+        {
+            let _ = response_sender.send(cancel_order_response(&order, None));
+        }
+    }
+}
+
+impl SequencerProcessor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: usize,
+        shard_count: usize,
+        receiver: crossbeam_channel::Receiver<SequencerMessage>,
+        match_senders: Vec<crossbeam_channel::Sender<MatchMessage>>,
+        trade_execution_receiver: crossbeam_channel::Receiver<TradeExecutionMessage>,
+        management_manager: Arc<ManagementManager>,
+        balance_manager: BalanceManager,
+        wal: WriteAheadLog,
+        trade_execution_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>,
+    ) -> Self {
+        Self {
+            id,
+            shard_count,
+            receiver,
+            balance_manager,
+            match_senders,
+            trade_execution_receiver,
+            management_manager,
+            wal,
+            trade_execution_senders,
+            liveness: Liveness::new(),
+            dead_letters: None,
+            open_orders: OpenOrderTracker::default(),
+            read_overflow: None,
+            placement_mode: PlacementMode::default(),
+            wal_failed: false,
+        }
+    }
+
+    // 存活标记，启动线程前注册到健康检查
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
+
+    // 手续费转发失败时写入死信日志，未设置时只打印
+    pub fn set_dead_letters(&mut self, dead_letters: DeadLetterSink) {
+        self.dead_letters = Some(dead_letters);
+    }
+
+    // 本分片积压时余额查询可由其他工作线程读取账户视图回复；设置时发布全部账户，
+    // 之后每处理完一条消息发布余额变化过的账户
+    pub fn set_read_overflow(&mut self, read_overflow: Arc<ReadOverflow>) {
+        self.balance_manager.track_dirty_accounts();
+        read_overflow.publish(
+            self.id,
+            self.balance_manager
+                .accounts
+                .iter()
+                .map(|(&account_id, account)| (account_id, Some(account.clone()))),
+        );
+        self.read_overflow = Some(read_overflow);
+    }
+
+    fn publish_accounts(&mut self) {
+        let Some(read_overflow) = &self.read_overflow else {
+            return;
+        };
+        let account_ids = self.balance_manager.take_dirty_accounts();
+        if account_ids.is_empty() {
+            return;
+        }
+        let accounts = &self.balance_manager.accounts;
+        read_overflow.publish(
+            self.id,
+            account_ids
+                .into_iter()
+                .map(|account_id| (account_id, accounts.get(&account_id).cloned())),
+        );
+    }
+
+    // 账户级未完成订单数和名义价值上限，未设置时不限制
+    pub fn set_risk_limits(&mut self, limits: RiskLimits) {
+        self.open_orders.set_limits(limits);
+    }
+
+    // 未填写序号时不检查；序号通过检查即写入预写日志并被消耗，与请求本身是否成功无关，
+    // 重启后从日志恢复。带幂等键的请求先查去重表，重复请求返回首次响应，不再检查序号
+    fn check_nonce(&mut self, account_id: i32, nonce: Option<i64>) -> Result<(), BalanceError> {
+        let Some(nonce) = nonce else {
+            return Ok(());
+        };
+        self.balance_manager.check_nonce(account_id, nonce)?;
+        self.write_ahead(WalRecord::AcceptNonce { account_id, nonce })?;
+        self.balance_manager.accept_nonce(account_id, nonce);
+        Ok(())
+    }
+
+    // 启动时按预写日志中最后的下单占用方式和订单簿重建本分片账户的未完成订单，
+    // 在 set_placement_mode 之前调用；交易对配置已删除的订单簿无法确定币种，跳过
+    pub fn restore_open_orders<'a>(
+        &mut self,
+        placement_mode: PlacementMode,
+        matching_engines: impl IntoIterator<Item = &'a MatchingEngine>,
+    ) {
+        self.placement_mode = placement_mode;
+        for order_book in matching_engines.into_iter().flat_map(|engine| engine.order_books.values()) {
+            let Some(symbol) = self.management_manager.get_symbol(order_book.symbol_id) else {
+                continue;
+            };
+            for account_id in order_book.open_order_accounts() {
+                if (account_id % self.shard_count as i32).unsigned_abs() as usize != self.id {
+                    continue;
+                }
+                for order in order_book.open_orders(account_id) {
+                    let (price, quantity) = (order.price, order.remaining_quantity());
+                    match placement_mode {
+                        PlacementMode::PrefreezePerOrder => {
+                            self.open_orders.open(account_id, order.request_id, price, quantity)
+                        }
+                        PlacementMode::MarginCheck => {
+                            let bid = order.side == OrderSide::Bid;
+                            let currency_id = if bid { symbol.quote } else { symbol.base };
+                            self.open_orders.open_on_margin(
+                                account_id,
+                                order.request_id,
+                                price,
+                                quantity,
+                                currency_id,
+                                bid,
+                            )
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 下单时预冻结余额还是只做保证金检查；本分片有未完成订单时拒绝切换，
+    // 切换先写入预写日志，重启后按日志中的方式重建未完成订单
+    pub fn set_placement_mode(&mut self, placement_mode: PlacementMode) -> Result<(), BalanceError> {
+        if placement_mode == self.placement_mode {
+            return Ok(());
+        }
+        if !self.open_orders.is_empty() {
+            return Err(BalanceError::InvalidOrder(format!(
+                "Cannot switch placement mode to {:?} on sequencer {} while orders are open",
+                placement_mode, self.id
+            )));
+        }
+        self.write_ahead(WalRecord::SetPlacementMode {
+            mode: placement_mode,
+        })?;
+        self.placement_mode = placement_mode;
+        Ok(())
+    }
+
+    fn dead_letter(&self, shard: usize, message: &TradeExecutionMessage) {
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.record(shard, message);
+        }
+    }
+
+    // 启动时重新处理上次运行留下的死信，在 run() 之前调用；预写日志写入失败时消息没有生效，
+    // 返回错误，由调用方把死信留到下次启动
+    pub fn redeliver(&mut self, letter: &DeadLetter) -> Result<(), BalanceError> {
+        self.apply_trade_execution_message(letter.clone().into_message())
+    }
+
+    // 启动时补记停在两个阶段之间的跨分片划转，调用方已不在等待回复
+    pub fn complete_transfer(&mut self, transfer: wal::PendingTransfer) {
+        warn!(
+            sequencer = self.id,
+            from_account_id = transfer.from_account_id,
+            to_account_id = transfer.to_account_id,
+            currency_id = transfer.currency_id,
+            amount = %transfer.amount,
+            "Completing interrupted transfer"
+        );
+        self.process_trade_execution_message(TradeExecutionMessage::TransferIn {
+            transfer_id: transfer.transfer_id,
+            account_id: transfer.to_account_id,
+            currency_id: transfer.currency_id,
+            amount: transfer.amount,
+            response_sender: tokio::sync::oneshot::channel().0,
+        });
+    }
+
+    // 状态变更前先写预写日志，涉及的币种精度有变化时先记录精度
+    fn write_ahead(&mut self, record: WalRecord) -> Result<(), BalanceError> {
+        for currency_id in record.currency_ids() {
+            self.sync_currency_scale(currency_id)?;
+        }
+        self.append_wal(&record)
+    }
+
+    // 币种精度以管理配置为准，写入日志后再应用，重放时按相同精度校验和舍入
+    fn sync_currency_scale(&mut self, currency_id: i32) -> Result<(), BalanceError> {
+        let scale = self.management_manager.currency_scale(currency_id);
+        if scale == self.balance_manager.currency_scale(currency_id) {
+            return Ok(());
+        }
+        self.append_wal(&WalRecord::SetCurrencyScale { currency_id, scale })?;
+        self.balance_manager.set_currency_scale(currency_id, scale);
+        Ok(())
+    }
+
+    // 显示精度以管理配置为准，只影响返回给客户端的余额字符串，不写预写日志；
+    // 未指定币种时同步账户已有的所有币种
+    fn sync_display_scales(&mut self, account_id: i32, currency_id: Option<i32>) {
+        let currency_ids: Vec<i32> = match currency_id {
+            Some(currency_id) => vec![currency_id],
+            None => self
+                .balance_manager
+                .accounts
+                .get(&account_id)
+                .map(|account| account.balances.keys().copied().collect())
+                .unwrap_or_default(),
+        };
+        for currency_id in currency_ids {
+            let display_scale = self.management_manager.currency_display_scale(currency_id);
+            self.balance_manager.set_display_scale(currency_id, display_scale);
+        }
+    }
+
+    // 第一次写入失败后本分片停止修改余额：之后的请求都被拒绝，成交回调写入死信文件，
+    // 健康检查报告该分片已停止，重启后按日志重放，内存状态与日志一致
+    fn append_wal(&mut self, record: &WalRecord) -> Result<(), BalanceError> {
+        if self.wal_failed {
+            return Err(BalanceError::WalWrite(WAL_STOPPED_MESSAGE.to_string()));
+        }
+        self.wal.append(record).map_err(|e| {
+            error!(
+                sequencer = self.id,
+                path = %self.wal.path().display(),
+                error = %e,
+                "Failed to write WAL, sequencer stops applying changes"
+            );
+            self.wal_failed = true;
+            self.liveness.set_alive(false);
+            BalanceError::WalWrite(e.to_string())
+        })
+    }
+
+    // 独占一个线程运行，等同于只驱动一个分片的工作线程
+    pub fn run(self) {
+        run_sequencers(vec![self]);
+    }
+
+    fn process_sequencer_message(&mut self, message: SequencerMessage) {
+        match message {
+            SequencerMessage::GetAccount {
+                request_id: _,
+                account_id,
+                currency_id,
+                response_sender,
+            } => {
+                self.sync_display_scales(account_id, currency_id);
+                let response = self
+                    .balance_manager
+                    .handle_get_account(account_id, currency_id);
+                if response.code == 500 {
+                    error!(
+                        sequencer = self.id,
+                        account_id,
+                        message = ?response.message,
+                        "Account balance invariant violated"
+                    );
+                }
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::GetBalanceHistory {
+                request_id: _,
+                account_id,
+                currency_id,
+                limit,
+                response_sender,
+            } => {
+                let limit = if limit <= 0 {
+                    DEFAULT_BALANCE_HISTORY_LIMIT
+                } else {
+                    (limit as usize).min(MAX_BALANCE_HISTORY_LIMIT)
+                };
+                let changes = self
+                    .balance_manager
+                    .get_balance_history(account_id, currency_id, limit)
+                    .into_iter()
+                    .map(Into::into)
+                    .collect();
+                let response = crate::models::schema::GetBalanceHistoryResponse {
+                    code: 0,
+                    message: Some("Success".to_string()),
+                    changes,
+                };
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::Increase {
+                request_id: _,
+                account_id,
+                currency_id,
+                amount,
+                idempotency_key,
+                response_sender,
+            } => {
+                let response = self.increase(account_id, currency_id, &amount, idempotency_key);
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::BatchIncrease {
+                request_id: _,
+                entries,
+                response_sender,
+            } => {
+                let responses = entries
+                    .into_iter()
+                    .map(|entry| {
+                        self.increase(
+                            entry.account_id,
+                            entry.currency_id,
+                            &entry.amount,
+                            idempotency_key(entry.request_id),
+                        )
+                    })
+                    .collect();
+                let _ = response_sender.send(responses);
+            }
+            SequencerMessage::Decrease {
+                request_id: _,
+                account_id,
+                currency_id,
+                amount,
+                idempotency_key,
+                nonce,
+                response_sender,
+            } => {
+                let request_key = match self.check_idempotency(account_id, idempotency_key) {
+                    Ok(request_key) => request_key,
+                    Err(cached) => {
+                        let response = match cached {
+                            CachedResponse::Decrease(response) => response,
+                            _ => crate::models::schema::DecreaseResponse {
+                                code: 409,
+                                message: Some(IDEMPOTENCY_KEY_REUSED_MESSAGE.to_string()),
+                                data: None,
+                            },
+                        };
+                        let _ = response_sender.send(response);
+                        return;
+                    }
+                };
+                if let Err(e) = self.check_nonce(account_id, nonce) {
+                    let _ = response_sender.send(crate::models::schema::DecreaseResponse {
+                        code: nonce_error_code(&e),
+                        message: Some(e.to_string()),
+                        data: None,
+                    });
+                    return;
+                }
+                // 格式错误留给 handle_decrease 报告
+                let debit = rust_decimal::Decimal::from_str_exact(&amount).unwrap_or_default();
+                if let Err(e) = self.check_margin_debit(account_id, currency_id, debit) {
+                    let _ = response_sender.send(crate::models::schema::DecreaseResponse {
+                        code: 400,
+                        message: Some(e.to_string()),
+                        data: None,
+                    });
+                    return;
+                }
+                let response = match self.write_ahead(WalRecord::Decrease {
+                    account_id,
+                    currency_id,
+                    amount: amount.clone(),
+                    request_key,
+                }) {
+                    Ok(()) => {
+                        self.sync_display_scales(account_id, Some(currency_id));
+                        let response = self
+                            .balance_manager
+                            .handle_decrease(account_id, currency_id, &amount);
+                        if let Some(request) = request_key {
+                            self.balance_manager.remember_response(
+                                account_id,
+                                request,
+                                CachedResponse::Decrease(response.clone()),
+                            );
+                        }
+                        response
+                    }
+                    Err(e) => crate::models::schema::DecreaseResponse {
+                        code: 500,
+                        message: Some(e.to_string()),
+                        data: None,
+                    },
+                };
+
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::PlaceOrder {
+                request_id,
+                symbol_id,
+                account_id,
+                order_type,
+                side,
+                time_in_force,
+                price,
+                quantity,
+                taker_rate,
+                maker_rate,
+                post_only,
+                display_quantity,
+                stop_price,
+                trigger_direction,
+                protection_price,
+                expires_at,
+                volume,
+                client_order_id,
+                validate_only,
+                nonce,
+                span,
+                response_sender,
+            } => {
+                let _freeze =
+                    tracing::info_span!(parent: &span, "freeze", %request_id, account_id).entered();
+                // 只校验时不改变状态，不检查也不消耗序号
+                if !validate_only {
+                    if let Err(e) = self.check_nonce(account_id, nonce) {
+                        let response = PlaceOrderResponse::with_reason(
+                            nonce_error_code(&e),
+                            format!("Failed to process order: {}", e),
+                            0,
+                            e.reject_reason(),
+                        );
+                        let _ = response_sender.send(response);
+                        return;
+                    }
+                }
+                // 获取交易对信息
+                if let Some(symbol) = self.management_manager.get_symbol(symbol_id) {
+                    // 按金额下单的市价买单按 1 × 金额冻结 quote，风控按同样的价格和数量计算
+                    let (freeze_price, freeze_quantity) = match &volume {
+                        Some(volume) => ("1".to_string(), volume.clone()),
+                        None => (price.clone(), quantity.clone()),
+                    };
+                    let (order_price, order_quantity) =
+                        Self::order_exposure(&freeze_price, &freeze_quantity);
+                    // 买单按 taker、maker 中较高的费率冻结手续费，与撮合引擎中订单的冻结金额一致
+                    let fee_reserve_rate = FeeRates::from_ppm(taker_rate, maker_rate, 0).reserve_rate();
+                    let checked = match &volume {
+                        Some(volume) => Self::check_quote_volume(
+                            &symbol,
+                            order_type,
+                            side,
+                            time_in_force,
+                            stop_price.as_deref(),
+                            volume,
+                        ),
+                        None => Self::check_trading_rules(
+                            &symbol,
+                            order_type,
+                            &price,
+                            &quantity,
+                            stop_price.as_deref(),
+                        ),
+                    }
+                    .and_then(|_| Self::check_expiry(time_in_force, expires_at))
+                    .and_then(|_| self.open_orders.check(account_id, order_price, order_quantity));
+
+                    // 只校验：检查余额是否足够，再由撮合分片按下单时的规则校验，返回需要冻结的金额
+                    if validate_only {
+                        let result = checked.and_then(|_| match self.placement_mode {
+                            PlacementMode::PrefreezePerOrder => self.check_freeze_for_order(
+                                account_id,
+                                side,
+                                &freeze_price,
+                                &freeze_quantity,
+                                fee_reserve_rate,
+                                &symbol,
+                            ),
+                            PlacementMode::MarginCheck => self.check_margin(
+                                account_id,
+                                side,
+                                &freeze_price,
+                                &freeze_quantity,
+                                fee_reserve_rate,
+                                &symbol,
+                            ),
+                        });
+                        let (frozen_currency_id, frozen_amount) = match result {
+                            Ok(frozen) => frozen,
+                            Err(e) => {
+                                let response = PlaceOrderResponse::with_reason(
+                                    400,
+                                    format!("Failed to process order: {}", e),
+                                    0,
+                                    e.reject_reason(),
+                                );
+                                let _ = response_sender.send(response);
+                                return;
+                            }
+                        };
+                        let match_message = MatchMessage::ValidateOrder {
+                            request_id,
+                            symbol_id,
+                            account_id,
+                            order_type,
+                            side,
+                            time_in_force,
+                            price,
+                            quantity,
+                            taker_rate,
+                            maker_rate,
+                            post_only,
+                            display_quantity,
+                            stop_price,
+                            trigger_direction,
+                            protection_price,
+                            expires_at,
+                            volume,
+                            client_order_id,
+                            frozen_currency_id,
+                            frozen_amount: frozen_amount.to_string(),
+                            response_sender,
+                        };
+                        if let Err(ForwardError {
+                            code,
+                            message,
+                            returned: MatchMessage::ValidateOrder { response_sender, .. },
+                        }) = self.forward_to_matcher(symbol_id, match_message)
+                        {
+                            let reason = match code {
+                                503 => RejectReason::ServerBusy,
+                                _ => RejectReason::InternalError,
+                            };
+                            let response =
+                                PlaceOrderResponse::with_reason(code, message.to_string(), 0, reason);
+                            let _ = response_sender.send(response);
+                        }
+                        return;
+                    }
+
+                    // 校验精度规则后计算并冻结下单所需余额，保证金模式下只检查不冻结
+                    match checked.and_then(|_| match self.placement_mode {
+                        PlacementMode::PrefreezePerOrder => self.freeze_for_order(
+                            account_id,
+                            side,
+                            &freeze_price,
+                            &freeze_quantity,
+                            fee_reserve_rate,
+                            &symbol,
+                        ),
+                        PlacementMode::MarginCheck => self.check_margin(
+                            account_id,
+                            side,
+                            &freeze_price,
+                            &freeze_quantity,
+                            fee_reserve_rate,
+                            &symbol,
+                        ),
+                    }) {
+                        Ok((freeze_currency_id, freeze_amount)) => {
+                            debug!(
+                                account_id,
+                                symbol_id,
+                                side,
+                                freeze_currency_id,
+                                %freeze_amount,
+                                "Order frozen"
+                            );
+
+                            // 余额足够，发送到 MatchProcessor
+                            let match_message = MatchMessage::PlaceOrder {
+                                request_id,
+                                symbol_id,
+                                account_id,
+                                order_type,
+                                side,
+                                time_in_force,
+                                price,
+                                quantity,
+                                taker_rate,
+                                maker_rate,
+                                post_only,
+                                display_quantity,
+                                stop_price,
+                                trigger_direction,
+                                protection_price,
+                                expires_at,
+                                volume,
+                                client_order_id,
+                                span,
+                                response_sender,
+                            };
+
+                            match self.forward_to_matcher(symbol_id, match_message) {
+                                Ok(()) => match self.placement_mode {
+                                    PlacementMode::PrefreezePerOrder => self.open_orders.open(
+                                        account_id,
+                                        request_id,
+                                        order_price,
+                                        order_quantity,
+                                    ),
+                                    PlacementMode::MarginCheck => self.open_orders.open_on_margin(
+                                        account_id,
+                                        request_id,
+                                        order_price,
+                                        order_quantity,
+                                        freeze_currency_id,
+                                        side == 0,
+                                    ),
+                                },
+                                Err(ForwardError {
+                                    code,
+                                    message,
+                                    returned: MatchMessage::PlaceOrder { response_sender, .. },
+                                }) => {
+                                    if self.placement_mode == PlacementMode::PrefreezePerOrder {
+                                        self.rollback_freeze(
+                                            account_id,
+                                            freeze_currency_id,
+                                            freeze_amount,
+                                        );
+                                    }
+                                    // 撮合队列已满返回 503
+                                    let reason = match code {
+                                        503 => RejectReason::ServerBusy,
+                                        _ => RejectReason::InternalError,
+                                    };
+                                    let response = PlaceOrderResponse::with_reason(
+                                        code,
+                                        message.to_string(),
+                                        0,
+                                        reason,
+                                    );
+                                    let _ = response_sender.send(response);
+                                }
+                                Err(_) => {}
+                            }
+                        }
+                        Err(e) => {
+                            metrics().rejects.inc();
+                            let response = PlaceOrderResponse::with_reason(
+                                400,
+                                format!("Failed to process order: {}", e),
+                                0,
+                                e.reject_reason(),
+                            );
+                            let _ = response_sender.send(response);
+                        }
+                    }
+                } else {
+                    let response = PlaceOrderResponse::with_reason(
+                        404,
+                        "Symbol not found".to_string(),
+                        0,
+                        RejectReason::UnknownSymbol,
+                    );
+                    let _ = response_sender.send(response);
+                }
+            }
+            SequencerMessage::CancelOrder {
+                request_id,
+                symbol_id,
+                account_id,
+                order_id,
+                client_order_id,
+                nonce,
+                response_sender,
+            } => {
+                if let Err(e) = self.check_nonce(account_id, nonce) {
+                    let response = crate::models::schema::CancelOrderResponse {
+                        code: nonce_error_code(&e),
+                        message: Some(e.to_string()),
+                        order_id: order_id as i64,
+                        cancelled_quantity: None,
+                        refund_amount: None,
+                    };
+                    let _ = response_sender.send(response);
+                    return;
+                }
+                // 转发取消订单请求到对应的 MatchProcessor
+                let match_message = MatchMessage::CancelOrder {
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    order_id,
+                    client_order_id,
+                    response_sender,
+                };
+
+                if let Err(ForwardError {
+                    code,
+                    message,
+                    returned: MatchMessage::CancelOrder { response_sender, .. },
+                }) = self.forward_to_matcher(symbol_id, match_message)
+                {
+                    let response = crate::models::schema::CancelOrderResponse {
+                        code,
+                        message: Some(message.to_string()),
+                        order_id: order_id as i64,
+                        cancelled_quantity: None,
+                        refund_amount: None,
+                    };
+                    let _ = response_sender.send(response);
+                }
+            }
+            SequencerMessage::CancelAllOrders {
+                request_id,
+                symbol_id,
+                account_id,
+                nonce,
+                response_sender,
+            } => {
+                if let Err(e) = self.check_nonce(account_id, nonce) {
+                    let response = crate::models::schema::CancelAllOrdersResponse {
+                        code: nonce_error_code(&e),
+                        message: Some(e.to_string()),
+                        order_ids: vec![],
+                    };
+                    let _ = response_sender.send(response);
+                    return;
+                }
+                // 转发批量撤单请求到对应的 MatchProcessor，解冻由 MatchProcessor 逐单发回
+                let match_message = MatchMessage::CancelAllOrders {
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    response_sender,
+                };
+
+                if let Err(ForwardError {
+                    code,
+                    message,
+                    returned: MatchMessage::CancelAllOrders { response_sender, .. },
+                }) = self.forward_to_matcher(symbol_id, match_message)
+                {
+                    let response = crate::models::schema::CancelAllOrdersResponse {
+                        code,
+                        message: Some(message.to_string()),
+                        order_ids: vec![],
+                    };
+                    let _ = response_sender.send(response);
+                }
+            }
+            SequencerMessage::AmendOrder {
+                request_id,
+                symbol_id,
+                account_id,
+                order_id,
+                side,
+                price,
+                quantity,
+                nonce,
+                response_sender,
+            } => {
+                if let Err(e) = self.check_nonce(account_id, nonce) {
+                    let response = crate::models::schema::AmendOrderResponse {
+                        code: nonce_error_code(&e),
+                        message: Some(e.to_string()),
+                        order_id: order_id as i64,
+                        price: None,
+                        quantity: None,
+                    };
+                    let _ = response_sender.send(response);
+                    return;
+                }
+                let Some(symbol) = self.management_manager.get_symbol(symbol_id) else {
+                    let response = crate::models::schema::AmendOrderResponse {
+                        code: 404,
+                        message: Some("Symbol not found".to_string()),
+                        order_id: order_id as i64,
+                        price: None,
+                        quantity: None,
+                    };
+                    let _ = response_sender.send(response);
+                    return;
+                };
+                // 改单后的价格和数量与限价单下单一样要符合交易对的精度规则和单笔数量、成交额上限，
+                // 不能先下小单再改大绕过上限
+                if let Err(e) =
+                    Self::check_trading_rules(&symbol, OrderType::Limit as i32, &price, &quantity, None)
+                {
+                    let response = crate::models::schema::AmendOrderResponse {
+                        code: 400,
+                        message: Some(e.to_string()),
+                        order_id: order_id as i64,
+                        price: None,
+                        quantity: None,
+                    };
+                    let _ = response_sender.send(response);
+                    return;
+                }
+
+                // 先不冻结：撮合线程按原订单的剩余冻结额算出需要增加的占用，需要时退回本分片冻结差额
+                let match_message = MatchMessage::AmendOrder {
+                    request_id,
+                    symbol_id,
+                    account_id,
+                    order_id,
+                    side,
+                    price,
+                    quantity,
+                    prefrozen_amount: None,
+                    response_sender,
+                };
+                self.forward_amend(symbol_id, order_id, match_message);
+            }
+            SequencerMessage::Transfer {
+                request_id: _,
+                from_account_id,
+                to_account_id,
+                currency_id,
+                amount,
+                idempotency_key,
+                nonce,
+                response_sender,
+            } => {
+                self.transfer(
+                    from_account_id,
+                    to_account_id,
+                    currency_id,
+                    &amount,
+                    idempotency_key,
+                    nonce,
+                    response_sender,
+                );
+            }
+            SequencerMessage::RequestWithdrawal {
+                request_id: _,
+                account_id,
+                currency_id,
+                amount,
+                response_sender,
+            } => {
+                let response = self.request_withdrawal(account_id, currency_id, &amount);
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::FinishWithdrawal {
+                request_id: _,
+                account_id,
+                hold_id,
+                confirm,
+                response_sender,
+            } => {
+                let response = self.finish_withdrawal(account_id, hold_id, confirm);
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::AdjustReserve {
+                request_id: _,
+                account_id,
+                currency_id,
+                tag,
+                amount,
+                release,
+                response_sender,
+            } => {
+                let response = self.adjust_reserve(account_id, currency_id, tag, &amount, release);
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::AdminAdjustBalance {
+                request_id: _,
+                account_id,
+                currency_id,
+                delta,
+                reason,
+                response_sender,
+            } => {
+                let response = self.admin_adjust(account_id, currency_id, &delta, reason);
+                let _ = response_sender.send(response);
+            }
+            SequencerMessage::FundLoadedOrders {
+                request_id: _,
+                symbol_id,
+                orders,
+                release,
+                response_sender,
+            } => {
+                let (code, message) = match self.management_manager.get_symbol(symbol_id) {
+                    None => (404, "Symbol not found".to_string()),
+                    Some(symbol) => match self.fund_loaded_orders(&symbol, &orders, release) {
+                        Ok(()) => (0, "Success".to_string()),
+                        Err(e @ BalanceError::WalWrite(_)) => (500, e.to_string()),
+                        Err(e) => (400, e.to_string()),
+                    },
+                };
+                let _ = response_sender.send(
+                    crate::models::schema::LoadOrderBookSnapshotResponse {
+                        code,
+                        message: Some(message),
+                    },
+                );
+            }
+        }
+    }
+
+    // 为装入的订单簿中本分片账户的订单冻结余额（保证金模式下只检查占用）并计入未完成订单，
+    // 按账户和币种合计后检查，任一账户余额不足时都不冻结；release 退回同样的金额
+    fn fund_loaded_orders(
+        &mut self,
+        symbol: &crate::models::Symbol,
+        orders: &[crate::matching::Order],
+        release: bool,
+    ) -> Result<(), BalanceError> {
+        let orders: Vec<&crate::matching::Order> = orders
+            .iter()
+            .filter(|order| (order.account_id % self.shard_count as i32).unsigned_abs() as usize == self.id)
+            .collect();
+        let mut amounts: std::collections::BTreeMap<(i32, i32), rust_decimal::Decimal> =
+            std::collections::BTreeMap::new();
+        for order in &orders {
+            let currency_id = match order.side {
+                OrderSide::Bid => symbol.quote,
+                OrderSide::Ask => symbol.base,
+            };
+            let amount = self
+                .balance_manager
+                .round_amount(currency_id, order.remaining_freeze_amount());
+            *amounts.entry((order.account_id, currency_id)).or_default() += amount;
+        }
+
+        if release {
+            for order in &orders {
+                self.open_orders.close(order.account_id, order.request_id);
+            }
+            if self.placement_mode == PlacementMode::PrefreezePerOrder {
+                for ((account_id, currency_id), amount) in amounts {
+                    self.rollback_freeze(account_id, currency_id, amount);
+                }
+            }
+            return Ok(());
+        }
+
+        match self.placement_mode {
+            PlacementMode::PrefreezePerOrder => {
+                for (&(account_id, currency_id), &amount) in &amounts {
+                    self.balance_manager.check_freeze(account_id, currency_id, amount)?;
+                }
+                for (&(account_id, currency_id), &amount) in &amounts {
+                    self.write_ahead(WalRecord::Freeze {
+                        account_id,
+                        currency_id,
+                        amount,
+                    })?;
+                    self.balance_manager.freeze(account_id, currency_id, amount)?;
+                }
+            }
+            PlacementMode::MarginCheck => {
+                for (&(account_id, currency_id), &amount) in &amounts {
+                    self.check_margin_amount(account_id, currency_id, amount)?;
+                }
+            }
+        }
+        for order in &orders {
+            let (price, quantity) = (order.price, order.remaining_quantity());
+            match self.placement_mode {
+                PlacementMode::PrefreezePerOrder => {
+                    self.open_orders.open(order.account_id, order.request_id, price, quantity)
+                }
+                PlacementMode::MarginCheck => {
+                    let bid = order.side == OrderSide::Bid;
+                    self.open_orders.open_on_margin(
+                        order.account_id,
+                        order.request_id,
+                        price,
+                        quantity,
+                        if bid { symbol.quote } else { symbol.base },
+                        bid,
+                    )
+                }
+            }
+        }
+        info!(
+            sequencer = self.id,
+            symbol_id = symbol.id,
+            orders = orders.len(),
+            "Funded orders of loaded order book"
+        );
+        Ok(())
+    }
+
+    // 管理员调整余额：先写日志再调整，调整后可用余额不能为负
+    fn admin_adjust(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        delta: &str,
+        reason: String,
+    ) -> crate::models::schema::AdminAdjustBalanceResponse {
+        let delta = match rust_decimal::Decimal::from_str_exact(delta) {
+            Ok(delta) => delta,
+            Err(_) => {
+                return crate::models::schema::AdminAdjustBalanceResponse {
+                    code: 400,
+                    message: Some("Invalid amount format".to_string()),
+                    data: None,
+                }
+            }
+        };
+        if let Err(e) = self.check_margin_debit(account_id, currency_id, -delta) {
+            return crate::models::schema::AdminAdjustBalanceResponse {
+                code: 400,
+                message: Some(e.to_string()),
+                data: None,
+            };
+        }
+        if let Err(e) = self.write_ahead(WalRecord::AdminAdjust {
+            account_id,
+            currency_id,
+            delta,
+            note: reason.clone(),
+        }) {
+            return crate::models::schema::AdminAdjustBalanceResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                data: None,
+            };
+        }
+        match self
+            .balance_manager
+            .admin_adjust(account_id, currency_id, delta, &reason)
+        {
+            Ok(balance) => crate::models::schema::AdminAdjustBalanceResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                data: Some(balance),
+            },
+            Err(e) => crate::models::schema::AdminAdjustBalanceResponse {
+                code: 400,
+                message: Some(e.to_string()),
+                data: None,
+            },
+        }
+    }
+
+    // 划入或退回保留余额：先写日志再调整，保留余额不足或可用余额不足时不修改
+    fn adjust_reserve(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        tag: String,
+        amount: &str,
+        release: bool,
+    ) -> crate::models::schema::AdjustReserveResponse {
+        let Ok(amount) = rust_decimal::Decimal::from_str_exact(amount) else {
+            return crate::models::schema::AdjustReserveResponse {
+                code: 400,
+                message: Some("Invalid amount format".to_string()),
+                data: None,
+            };
+        };
+        if !release {
+            if let Err(e) = self.check_margin_debit(account_id, currency_id, amount) {
+                return crate::models::schema::AdjustReserveResponse {
+                    code: 400,
+                    message: Some(e.to_string()),
+                    data: None,
+                };
+            }
+        }
+        if let Err(e) = self.write_ahead(WalRecord::AdjustReserve {
+            account_id,
+            currency_id,
+            tag: tag.clone(),
+            amount,
+            release,
+        }) {
+            return crate::models::schema::AdjustReserveResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                data: None,
+            };
+        }
+        self.sync_display_scales(account_id, Some(currency_id));
+        match self
+            .balance_manager
+            .adjust_reserve(account_id, currency_id, &tag, amount, release)
+        {
+            Ok(balance) => crate::models::schema::AdjustReserveResponse {
+                code: 0,
+                message: Some("Success".to_string()),
+                data: Some(balance),
+            },
+            Err(e) => crate::models::schema::AdjustReserveResponse {
+                code: 400,
+                message: Some(e.to_string()),
+                data: None,
+            },
+        }
+    }
+
+    // 发起提现：先写日志再冻结，冻结单ID按发起顺序分配，重放时得到相同的ID
+    fn request_withdrawal(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: &str,
+    ) -> crate::models::schema::WithdrawalResponse {
+        let Ok(amount) = rust_decimal::Decimal::from_str_exact(amount) else {
+            return withdrawal_response(Err(BalanceError::InvalidAmount(
+                "Invalid amount format".to_string(),
+            )));
+        };
+        let result = self
+            .check_margin_debit(account_id, currency_id, amount)
+            .and_then(|()| {
+                self.write_ahead(WalRecord::RequestWithdrawal {
+                    account_id,
+                    currency_id,
+                    amount,
+                })
+            })
+            .and_then(|()| {
+                self.sync_display_scales(account_id, Some(currency_id));
+                self.balance_manager
+                    .request_withdrawal(account_id, currency_id, amount)
+            })
+            .map(|hold_id| {
+                (
+                    hold_id,
+                    self.balance_manager.balance_snapshot(account_id, currency_id),
+                )
+            });
+        withdrawal_response(result)
+    }
+
+    // 确认或取消提现：冻结单必须属于请求的账户，不存在时不写日志
+    fn finish_withdrawal(
+        &mut self,
+        account_id: i32,
+        hold_id: u64,
+        confirm: bool,
+    ) -> crate::models::schema::WithdrawalResponse {
+        let Some(hold) = self
+            .balance_manager
+            .withdrawal_holds
+            .get(&hold_id)
+            .filter(|hold| hold.account_id == account_id)
+            .cloned()
+        else {
+            return withdrawal_response(Err(BalanceError::HoldNotFound));
+        };
+        let record = if confirm {
+            WalRecord::ConfirmWithdrawal { hold_id }
+        } else {
+            WalRecord::CancelWithdrawal { hold_id }
+        };
+        let result = self
+            .write_ahead(record)
+            .and_then(|()| {
+                self.sync_display_scales(account_id, Some(hold.currency_id));
+                if confirm {
+                    self.balance_manager.confirm_withdrawal(hold_id)
+                } else {
+                    self.balance_manager.cancel_withdrawal(hold_id)
+                }
+            })
+            .map(|hold| {
+                (
+                    hold_id,
+                    self.balance_manager
+                        .balance_snapshot(hold.account_id, hold.currency_id),
+                )
+            });
+        withdrawal_response(result)
+    }
+
+    // 充值：先写日志再入账，带幂等键的请求记住响应
+    fn increase(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: &str,
+        idempotency_key: Option<i64>,
+    ) -> crate::models::schema::IncreaseResponse {
+        let request_key = match self.check_idempotency(account_id, idempotency_key) {
+            Ok(request_key) => request_key,
+            Err(CachedResponse::Increase(response)) => return response,
+            Err(_) => {
+                return crate::models::schema::IncreaseResponse {
+                    code: 409,
+                    message: Some(IDEMPOTENCY_KEY_REUSED_MESSAGE.to_string()),
+                    data: None,
+                }
+            }
+        };
+        match self.write_ahead(WalRecord::Increase {
+            account_id,
+            currency_id,
+            amount: amount.to_string(),
+            request_key,
+        }) {
+            Ok(()) => {
+                self.sync_display_scales(account_id, Some(currency_id));
+                let response = self
+                    .balance_manager
+                    .handle_increase(account_id, currency_id, amount);
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        account_id,
+                        request,
+                        CachedResponse::Increase(response.clone()),
+                    );
+                }
+                response
+            }
+            Err(e) => crate::models::schema::IncreaseResponse {
+                code: 500,
+                message: Some(e.to_string()),
+                data: None,
+            },
+        }
+    }
+
+    // 幂等键已处理过时返回首次处理的响应，否则返回本次请求随日志写入的键
+    #[allow(clippy::result_large_err)]
+    fn check_idempotency(
+        &mut self,
+        account_id: i32,
+        idempotency_key: Option<i64>,
+    ) -> Result<Option<RequestKey>, CachedResponse> {
+        let Some(key) = idempotency_key else {
+            return Ok(None);
+        };
+        let now = now_millis();
+        match self.balance_manager.cached_response(account_id, key, now) {
+            Some(response) => Err(response),
+            None => Ok(Some(RequestKey { key, seen_at: now })),
+        }
+    }
+
+    // 账户间划转：同分片直接完成；跨分片先扣减转出账户，再交给转入账户所在分片入账并回复
+    // 幂等键按转出账户去重，跨分片时转出分片扣减成功即记为成功
+    #[allow(clippy::too_many_arguments)]
+    fn transfer(
+        &mut self,
+        from_account_id: i32,
+        to_account_id: i32,
+        currency_id: i32,
+        amount: &str,
+        idempotency_key: Option<i64>,
+        nonce: Option<i64>,
+        response_sender: tokio::sync::oneshot::Sender<crate::models::schema::TransferResponse>,
+    ) {
+        let request_key = match self.check_idempotency(from_account_id, idempotency_key) {
+            Ok(request_key) => request_key,
+            Err(cached) => {
+                let response = match cached {
+                    CachedResponse::Transfer(response) => response,
+                    _ => crate::models::schema::TransferResponse {
+                        code: 409,
+                        message: Some(IDEMPOTENCY_KEY_REUSED_MESSAGE.to_string()),
+                    },
+                };
+                let _ = response_sender.send(response);
+                return;
+            }
+        };
+        if let Err(e) = self.check_nonce(from_account_id, nonce) {
+            let _ = response_sender.send(crate::models::schema::TransferResponse {
+                code: nonce_error_code(&e),
+                message: Some(e.to_string()),
+            });
+            return;
+        }
+        let Ok(amount) = rust_decimal::Decimal::from_str_exact(amount) else {
+            let error = BalanceError::InvalidAmount("Invalid amount format".to_string());
+            let _ = response_sender.send(transfer_response(Err(error)));
+            return;
+        };
+        if let Err(e) = self.check_margin_debit(from_account_id, currency_id, amount) {
+            let _ = response_sender.send(transfer_response(Err(e)));
+            return;
+        }
+        let to_shard = (to_account_id % self.shard_count as i32).unsigned_abs() as usize;
+        let result = if to_shard == self.id {
+            self.write_ahead(WalRecord::Transfer {
+                from_account_id,
+                to_account_id,
+                currency_id,
+                amount,
+                request_key,
+            })
+            .and_then(|_| {
+                self.balance_manager
+                    .transfer(from_account_id, to_account_id, currency_id, amount)
+            })
+        } else {
+            // 第一阶段：扣减失败时两个账户都不变
+            let transfer_id = uuid::Uuid::new_v4();
+            let debited = self
+                .write_ahead(WalRecord::TransferOut {
+                    account_id: from_account_id,
+                    currency_id,
+                    amount,
+                    request_key,
+                    to_account_id,
+                    transfer_id: Some(transfer_id),
+                })
+                .and_then(|_| self.balance_manager.debit(from_account_id, currency_id, amount));
+            if debited.is_ok() {
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        from_account_id,
+                        request,
+                        CachedResponse::Transfer(transfer_response(Ok(()))),
+                    );
+                }
+                let message = TradeExecutionMessage::TransferIn {
+                    transfer_id,
+                    account_id: to_account_id,
+                    currency_id,
+                    amount,
+                    response_sender,
+                };
+                // 转入分片也会阻塞发送消息给本分片，这里阻塞会造成双向等待，队列已满时同样退回
+                let (message, code, reason) =
+                    match self.trade_execution_senders[to_shard].try_send(message) {
+                        Ok(()) => return,
+                        Err(TrySendError::Full(message)) => {
+                            warn!(sequencer = to_shard, "Failed to send transfer, queue full");
+                            (message, 503, "Transfer target busy")
+                        }
+                        Err(TrySendError::Disconnected(message)) => {
+                            error!(sequencer = to_shard, "Failed to send transfer, channel closed");
+                            (message, 500, "Transfer target unavailable")
+                        }
+                    };
+                // 退回转出账户，退回记录与转出记录配对；写入失败时本分片已停止，不修改内存，
+                // 重启后按未配对的转出记录补记入账
+                match self.write_ahead(WalRecord::TransferIn {
+                    account_id: from_account_id,
+                    currency_id,
+                    amount,
+                    request_key,
+                    transfer_id: Some(transfer_id),
+                }) {
+                    Ok(()) => self.balance_manager.credit(
+                        from_account_id,
+                        currency_id,
+                        amount,
+                        AuditReason::Transfer,
+                    ),
+                    Err(e) => error!(
+                        sequencer = self.id,
+                        account_id = from_account_id,
+                        currency_id,
+                        %amount,
+                        error = %e,
+                        "Failed to refund transfer"
+                    ),
+                }
+                if let Some(request) = request_key {
+                    self.balance_manager.forget_request(from_account_id, request.key);
+                }
+                if let TradeExecutionMessage::TransferIn { response_sender, .. } = message {
+                    let _ = response_sender.send(crate::models::schema::TransferResponse {
+                        code,
+                        message: Some(reason.to_string()),
+                    });
+                }
+                return;
+            }
+            debited
+        };
+
+        // 日志写入失败时请求没有生效，不记住响应，客户端可用同一个键重试
+        let logged = !matches!(result, Err(BalanceError::WalWrite(_)));
+        let response = transfer_response(result);
+        if let Some(request) = request_key.filter(|_| logged) {
+            self.balance_manager.remember_response(
+                from_account_id,
+                request,
+                CachedResponse::Transfer(response.clone()),
+            );
+        }
+        let _ = response_sender.send(response);
+    }
+
+    // 转发到 MatchProcessor，队列已满或已关闭时不阻塞，把消息退回给调用方
+    // MatchProcessor 会阻塞发送成交结果给 SequencerProcessor，这里阻塞会造成双向等待
+    #[allow(clippy::result_large_err)]
+    fn forward_to_matcher(&self, symbol_id: i32, message: MatchMessage) -> Result<(), ForwardError> {
+        let shard_index = match_shard(symbol_id, self.match_senders.len());
+        match self.match_senders[shard_index].try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(message)) => {
+                warn!(
+                    sequencer = self.id,
+                    matcher = shard_index,
+                    "Matcher queue is full, rejecting request"
+                );
+                Err(ForwardError {
+                    code: 503,
+                    message: SERVER_BUSY_MESSAGE,
+                    returned: message,
+                })
+            }
+            Err(TrySendError::Disconnected(message)) => {
+                error!(matcher = shard_index, "Failed to forward to matcher, channel closed");
+                Err(ForwardError {
+                    code: 500,
+                    message: MATCHER_UNAVAILABLE_MESSAGE,
+                    returned: message,
+                })
+            }
+        }
+    }
+
+    // 请求未能转发到撮合，撤销预先冻结的余额；解冻记录写入失败时本分片已停止，冻结保持不变
+    fn rollback_freeze(&mut self, account_id: i32, currency_id: i32, amount: rust_decimal::Decimal) {
+        if let Err(e) = self.write_ahead(WalRecord::Unfreeze {
+            account_id,
+            currency_id,
+            amount,
+        }) {
+            error!(
+                sequencer = self.id,
+                account_id,
+                currency_id,
+                %amount,
+                error = %e,
+                "Failed to roll back freeze"
+            );
+            return;
+        }
+        self.balance_manager
+            .release_frozen(account_id, currency_id, amount);
+    }
+
+    // 转发改单；转发失败时退回已为改单冻结的差额
+    fn forward_amend(&mut self, symbol_id: i32, order_id: u64, message: MatchMessage) {
+        if let Err(ForwardError {
+            code,
+            message,
+            returned:
+                MatchMessage::AmendOrder {
+                    account_id,
+                    side,
+                    prefrozen_amount,
+                    response_sender,
+                    ..
+                },
+        }) = self.forward_to_matcher(symbol_id, message)
+        {
+            let funded = prefrozen_amount.unwrap_or_default();
+            if self.placement_mode == PlacementMode::PrefreezePerOrder
+                && funded > rust_decimal::Decimal::ZERO
+            {
+                if let Some(symbol) = self.management_manager.get_symbol(symbol_id) {
+                    let currency_id = if side == 0 { symbol.quote } else { symbol.base };
+                    self.rollback_freeze(account_id, currency_id, funded);
+                }
+            }
+            let response = crate::models::schema::AmendOrderResponse {
+                code,
+                message: Some(message.to_string()),
+                order_id: order_id as i64,
+                price: None,
+                quantity: None,
+            };
+            let _ = response_sender.send(response);
+        }
+    }
+
+    // 为改单增加的占用冻结余额，保证金模式下只检查；返回实际冻结（或检查）的金额
+    fn fund_amend(
+        &mut self,
+        account_id: i32,
+        currency_id: i32,
+        amount: rust_decimal::Decimal,
+    ) -> Result<rust_decimal::Decimal, BalanceError> {
+        // 卖单只改价时名义价值增加但冻结额不变，只检查风控
+        if amount <= rust_decimal::Decimal::ZERO {
+            return Ok(rust_decimal::Decimal::ZERO);
+        }
+        // 向上取整到币种精度，保证冻结额不少于撮合线程算出的差额
+        let amount = match self.balance_manager.currency_scale(currency_id) {
+            Some(scale) => amount.round_dp_with_strategy(
+                scale,
+                rust_decimal::RoundingStrategy::AwayFromZero,
+            ),
+            None => amount,
+        };
+        match self.placement_mode {
+            PlacementMode::PrefreezePerOrder => {
+                self.balance_manager.check_freeze(account_id, currency_id, amount)?;
+                self.write_ahead(WalRecord::Freeze {
+                    account_id,
+                    currency_id,
+                    amount,
+                })?;
+                self.balance_manager.freeze(account_id, currency_id, amount)?;
+            }
+            PlacementMode::MarginCheck => {
+                self.check_margin_amount(account_id, currency_id, amount)?;
+            }
+        }
+        Ok(amount)
+    }
+
+    // 不符合交易对精度规则的订单在冻结余额前拒绝，格式错误留给冻结和撮合时报告
+    fn check_trading_rules(
+        symbol: &crate::models::Symbol,
+        order_type: i32,
+        price: &str,
+        quantity: &str,
+        stop_price: Option<&str>,
+    ) -> Result<(), BalanceError> {
+        // 止损单按激活后的类型校验，必须带触发价
+        let requested_type = OrderType::from(order_type);
+        if requested_type.is_stop() && stop_price.is_none() {
+            return Err(BalanceError::InvalidOrder(
+                "Stop orders require a stop price".to_string(),
+            ));
+        }
+        let order_type = requested_type.on_trigger();
+        let Ok(quantity) = rust_decimal::Decimal::from_str_exact(quantity) else {
+            return Ok(());
+        };
+        let price = match rust_decimal::Decimal::from_str_exact(price) {
+            Ok(price) => price,
+            Err(_) if order_type == OrderType::Market => rust_decimal::Decimal::ZERO,
+            Err(_) => return Ok(()),
+        };
+        let stop_price =
+            stop_price.and_then(|value| rust_decimal::Decimal::from_str_exact(value).ok());
+        symbol
+            .trading_rules()
+            .check_order(&order_type, price, quantity, stop_price)
+    }
+
+    // 按金额下单只支持不带触发价的市价买单；FOK 无法按金额判断能否全部成交
+    fn check_quote_volume(
+        symbol: &crate::models::Symbol,
+        order_type: i32,
+        side: i32,
+        time_in_force: i32,
+        stop_price: Option<&str>,
+        volume: &str,
+    ) -> Result<(), BalanceError> {
+        if OrderType::from(order_type) != OrderType::Market
+            || OrderSide::from(side) != OrderSide::Bid
+            || stop_price.is_some()
+        {
+            return Err(BalanceError::InvalidOrder(
+                "Volume is only supported for market buy orders".to_string(),
+            ));
+        }
+        if TimeInForce::from(time_in_force) == TimeInForce::Fok {
+            return Err(BalanceError::InvalidOrder(
+                "Volume is not supported for FOK orders".to_string(),
+            ));
+        }
+        let volume = rust_decimal::Decimal::from_str_exact(volume)
+            .map_err(|_| BalanceError::InvalidAmount("Invalid volume format".to_string()))?;
+        symbol.trading_rules().check_quote_volume(volume)
+    }
+
+    // 到期时间只对 GTC 订单有效，且必须晚于当前时间
+    fn check_expiry(time_in_force: i32, expires_at: Option<u64>) -> Result<(), BalanceError> {
+        let Some(expires_at) = expires_at else {
+            return Ok(());
+        };
+        if TimeInForce::from(time_in_force) != TimeInForce::Gtc {
+            return Err(BalanceError::InvalidOrder(
+                "Expiry is only supported for GTC orders".to_string(),
+            ));
+        }
+        if expires_at <= now_millis() {
+            return Err(BalanceError::InvalidOrder(
+                "Expiry must be in the future".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // 风控计数使用的订单价格和数量，市价卖单没有报价时按 0 计算名义价值
+    fn order_exposure(price: &str, quantity: &str) -> (rust_decimal::Decimal, rust_decimal::Decimal) {
+        (
+            rust_decimal::Decimal::from_str_exact(price).unwrap_or_default(),
+            rust_decimal::Decimal::from_str_exact(quantity).unwrap_or_default(),
+        )
+    }
+
+    fn check_freeze_for_order(
+        &self,
+        account_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+        fee_reserve_rate: rust_decimal::Decimal,
+        symbol: &crate::models::Symbol,
+    ) -> Result<(i32, rust_decimal::Decimal), BalanceError> {
+        let (currency_id, amount) =
+            self.balance_manager.order_freeze_amount(
+                side,
+                price,
+                quantity,
+                fee_reserve_rate,
+                symbol,
+            )?;
+        self.balance_manager.check_freeze(account_id, currency_id, amount)?;
+        Ok((currency_id, amount))
+    }
+
+    // 保证金模式：不冻结，本单加上未完成订单的占用不能超过可用余额
+    fn check_margin(
+        &self,
+        account_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+        fee_reserve_rate: rust_decimal::Decimal,
+        symbol: &crate::models::Symbol,
+    ) -> Result<(i32, rust_decimal::Decimal), BalanceError> {
+        let (currency_id, amount) =
+            self.check_freeze_for_order(
+                account_id,
+                side,
+                price,
+                quantity,
+                fee_reserve_rate,
+                symbol,
+            )?;
+        self.check_margin_amount(account_id, currency_id, amount)?;
+        Ok((currency_id, amount))
+    }
+
+    // 保证金模式下未完成订单占用可用余额而不冻结，划转、提现、扣减等从可用余额扣除的操作
+    // 之后，可用余额仍要覆盖订单的占用；预冻结模式下由冻结保证，不检查
+    fn check_margin_debit(
+        &self,
+        account_id: i32,
+        currency_id: i32,
+        amount: rust_decimal::Decimal,
+    ) -> Result<(), BalanceError> {
+        if self.placement_mode != PlacementMode::MarginCheck || amount <= rust_decimal::Decimal::ZERO {
+            return Ok(());
+        }
+        self.check_margin_amount(account_id, currency_id, amount)
+    }
+
+    fn check_margin_amount(
+        &self,
+        account_id: i32,
+        currency_id: i32,
+        amount: rust_decimal::Decimal,
+    ) -> Result<(), BalanceError> {
+        let available = self
+            .balance_manager
+            .accounts
+            .get(&account_id)
+            .and_then(|account| account.balances.get(&currency_id))
+            .map(|balance| balance.available)
+            .unwrap_or_default();
+        if self.open_orders.margin_exposure(account_id, currency_id) + amount > available {
+            return Err(BalanceError::InsufficientBalance);
+        }
+        Ok(())
+    }
+
+    fn freeze_for_order(
+        &mut self,
+        account_id: i32,
+        side: i32,
+        price: &str,
+        quantity: &str,
+        fee_reserve_rate: rust_decimal::Decimal,
+        symbol: &crate::models::Symbol,
+    ) -> Result<(i32, rust_decimal::Decimal), BalanceError> {
+        let (currency_id, amount) =
+            self.balance_manager.order_freeze_amount(
+                side,
+                price,
+                quantity,
+                fee_reserve_rate,
+                symbol,
+            )?;
+        self.write_ahead(WalRecord::Freeze {
+            account_id,
+            currency_id,
+            amount,
+        })?;
+        self.balance_manager.freeze(account_id, currency_id, amount)?;
+        Ok((currency_id, amount))
+    }
+
+    // 预写日志写入失败时消息没有生效，影响余额的消息写入死信，重启按日志恢复后重新投递
+    fn process_trade_execution_message(&mut self, message: TradeExecutionMessage) {
+        let letter = self
+            .dead_letters
+            .as_ref()
+            .and_then(|_| DeadLetter::from_message(self.id, &message));
+        if let Err(e) = self.apply_trade_execution_message(message) {
+            error!(sequencer = self.id, error = %e, "Trade execution not applied");
+            if let (Some(dead_letters), Some(letter)) = (&self.dead_letters, letter) {
+                dead_letters.record_letter(letter);
+            }
+        }
+    }
+
+    // 只有预写日志写入失败、消息完全没有生效时返回错误，其他错误在这里记录
+    fn apply_trade_execution_message(
+        &mut self,
+        message: TradeExecutionMessage,
+    ) -> Result<(), BalanceError> {
+        match message {
+            TradeExecutionMessage::SettleAccount {
+                account_id,
+                symbol_id,
+                trade_id,
+                taker,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+                fee_currency_id,
+                fee_amount,
+                fee_reserve,
+                span,
+                ack,
+            } => {
+                let _settle = tracing::info_span!(parent: &span, "settle", account_id, symbol_id)
+                    .entered();
+                let started = Instant::now();
+                // 成交ID为 0 的旧消息无法去重，照常结算
+                let settlement = (trade_id != 0).then_some(SettlementKey {
+                    symbol_id,
+                    trade_id,
+                    account_id,
+                    taker,
+                });
+                let settled = self.settle_account_balance(
+                    settlement,
+                    account_id,
+                    deduct_currency_id,
+                    deduct_amount,
+                    add_currency_id,
+                    add_amount,
+                    fee_currency_id,
+                    fee_amount,
+                    fee_reserve,
+                );
+                metrics().settlement_latency.observe(started.elapsed());
+                // 本条结算已完成，释放确认；最后一份释放时回复下单请求
+                drop(ack);
+                match settled {
+                    Err(e @ BalanceError::WalWrite(_)) => return Err(e),
+                    Err(e) => {
+                        error!(sequencer = self.id, account_id, error = %e, "Failed to settle account")
+                    }
+                    Ok(()) => {}
+                }
+            }
+            TradeExecutionMessage::CollectFee {
+                currency_id,
+                amount,
+            } => {
+                self.collect_fee(currency_id, amount)?;
+            }
+            TradeExecutionMessage::UnfreezeOrder {
+                order,
+                response_sender,
+                note,
+            } => {
+                // 撤单、到期、撤销剩余部分或撮合拒绝后不再计入风控；保证金模式下没有冻结，不解冻
+                self.open_orders.close(order.account_id, order.request_id);
+                let unfrozen = match self.placement_mode {
+                    PlacementMode::PrefreezePerOrder => self.unfreeze_order_balance(&order, note),
+                    PlacementMode::MarginCheck => Ok(rust_decimal::Decimal::ZERO),
+                };
+                let refund_amount = match &unfrozen {
+                    Ok(refund_amount) => Some(*refund_amount),
+                    Err(BalanceError::WalWrite(_)) => None,
+                    Err(e) => {
+                        error!(
+                            sequencer = self.id,
+                            order_id = order.id,
+                            error = %e,
+                            "Failed to unfreeze order"
+                        );
+                        None
+                    }
+                };
+                if let Some(response_sender) = response_sender {
+                    let _ = response_sender.send(cancel_order_response(&order, refund_amount));
+                }
+                if let Err(e @ BalanceError::WalWrite(_)) = unfrozen {
+                    return Err(e);
+                }
+            }
+            TradeExecutionMessage::OrderAmended {
+                account_id,
+                symbol_id,
+                side,
+                prefrozen_amount,
+                orders,
+                response,
+                response_sender,
+            } => {
+                if let Some((_, amended)) = orders.as_deref() {
+                    self.open_orders.update(
+                        account_id,
+                        amended.request_id,
+                        Some(amended.price),
+                        amended.remaining_quantity(),
+                    );
+                }
+                if self.placement_mode == PlacementMode::MarginCheck {
+                    let _ = response_sender.send(response);
+                    return Ok(());
+                }
+                let settled =
+                    self.settle_amended_order(account_id, symbol_id, side, prefrozen_amount, orders);
+                if let Err(e) = &settled {
+                    error!(
+                        sequencer = self.id,
+                        order_id = response.order_id,
+                        error = %e,
+                        "Failed to settle amended order"
+                    );
+                }
+                let _ = response_sender.send(response);
+                if let Err(e @ BalanceError::WalWrite(_)) = settled {
+                    return Err(e);
+                }
+            }
+            TradeExecutionMessage::AmendNeedsFreeze {
+                request_id,
+                symbol_id,
+                account_id,
+                order_id,
+                side,
+                price,
+                quantity,
+                amount,
+                order_request_id,
+                notional,
+                response_sender,
+            } => {
+                // 与下单相同的账户风控：改单后的名义价值替换原订单的名义价值
+                let funded = self
+                    .open_orders
+                    .check_amend(account_id, order_request_id, notional)
+                    .and_then(|()| match self.management_manager.get_symbol(symbol_id) {
+                        Some(symbol) => {
+                            let currency_id = if side == 0 { symbol.quote } else { symbol.base };
+                            self.fund_amend(account_id, currency_id, amount)
+                        }
+                        None => Err(BalanceError::CurrencyNotFound),
+                    });
+                match funded {
+                    Ok(funded) => {
+                        let match_message = MatchMessage::AmendOrder {
+                            request_id,
+                            symbol_id,
+                            account_id,
+                            order_id,
+                            side,
+                            price,
+                            quantity,
+                            prefrozen_amount: Some(funded),
+                            response_sender,
+                        };
+                        self.forward_amend(symbol_id, order_id, match_message);
+                    }
+                    Err(e) => {
+                        let response = crate::models::schema::AmendOrderResponse {
+                            code: 400,
+                            message: Some(format!("Failed to process amend: {}", e)),
+                            order_id: order_id as i64,
+                            price: None,
+                            quantity: None,
+                        };
+                        let _ = response_sender.send(response);
+                    }
+                }
+            }
+            TradeExecutionMessage::OrderProgress {
+                account_id,
+                request_id,
+                remaining_quantity,
+            } => {
+                self.open_orders
+                    .update(account_id, request_id, None, remaining_quantity);
+            }
+            TradeExecutionMessage::TransferIn {
+                transfer_id,
+                account_id,
+                currency_id,
+                amount,
+                response_sender,
+            } => {
+                // 第二阶段：入账不会失败，转出分片已扣减；日志写入失败时本分片已停止，不入账，
+                // 重启后按未配对的转出记录补记
+                if let Err(e) = self.write_ahead(WalRecord::TransferIn {
+                    account_id,
+                    currency_id,
+                    amount,
+                    request_key: None,
+                    transfer_id: Some(transfer_id),
+                }) {
+                    let _ = response_sender.send(crate::models::schema::TransferResponse {
+                        code: 500,
+                        message: Some(e.to_string()),
+                    });
+                    return Err(e);
+                }
+                self.balance_manager
+                    .credit(account_id, currency_id, amount, AuditReason::Transfer);
+                let _ = response_sender.send(crate::models::schema::TransferResponse {
+                    code: 0,
+                    message: Some("Success".to_string()),
+                });
+            }
+            TradeExecutionMessage::Drain => {
+                // 此前的结算（包括手续费转发）都已处理，释放发往其他分片的发送端让成交回调队列关闭
+                self.trade_execution_senders.clear();
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn settle_account_balance(
+        &mut self,
+        settlement: Option<SettlementKey>,
+        account_id: i32,
+        deduct_currency_id: i32,
+        deduct_amount: rust_decimal::Decimal,
+        add_currency_id: i32,
+        add_amount: rust_decimal::Decimal,
+        fee_currency_id: i32,
+        fee_amount: rust_decimal::Decimal,
+        fee_reserve: rust_decimal::Decimal,
+    ) -> Result<(), BalanceError> {
+        // 检查账户是否属于当前分片
+        let account_shard = (account_id % self.shard_count as i32).unsigned_abs() as usize;
+        if account_shard != self.id {
+            // 不属于当前分片，不处理
+            return Ok(());
+        }
+
+        // 重复投递的结算（如死信重新投递了已经处理过的消息）不再记账；
+        // 只有结算成功后才记录，被拒绝或写日志失败的结算重新投递时再次处理
+        if let Some(key) = settlement {
+            if self.balance_manager.is_settled(&key) {
+                warn!(
+                    sequencer = self.id,
+                    account_id,
+                    symbol_id = key.symbol_id,
+                    trade_id = key.trade_id,
+                    "Skipping duplicate settlement"
+                );
+                return Ok(());
+            }
+        }
+
+        // 日志写入失败时不结算，由调用方写入死信，重启后重新投递
+        let margin = self.placement_mode == PlacementMode::MarginCheck;
+        self.write_ahead(WalRecord::Settle {
+            account_id,
+            deduct_currency_id,
+            deduct_amount,
+            add_currency_id,
+            add_amount,
+            fee_currency_id,
+            fee_amount,
+            fee_reserve,
+            margin,
+            settlement,
+        })?;
+
+        // 从冻结余额中扣除 deduct_currency，增加 add_currency 到可用余额；
+        // 冻结余额不足说明路由或精度有误，拒绝整笔结算，不收取手续费。
+        // 保证金模式下没有冻结，直接从可用余额扣除
+        if margin {
+            self.balance_manager.settle_on_margin(
+                account_id,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+            )?;
+        } else {
+            self.balance_manager.settle(
+                account_id,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+            )?;
+        }
+        if let Some(key) = settlement {
+            self.balance_manager.record_settlement(key);
+        }
+
+        // 买单下单时按最高费率冻结了手续费，先解冻本次成交的预留部分，再从可用余额扣除手续费，
+        // 不会使余额为负；实际扣除的部分转入手续费账户。
+        // 返佣（负手续费）计入账户，并从手续费账户扣除，手续费账户余额可以为负
+        if !margin && fee_reserve > rust_decimal::Decimal::ZERO {
+            self.balance_manager
+                .release_frozen(account_id, deduct_currency_id, fee_reserve);
+        }
+        let actual_fee = self
+            .balance_manager
+            .charge_fee(account_id, fee_currency_id, fee_amount);
+        if actual_fee < fee_amount {
+            // 预留按冻结精度舍入，只有舍入零头可能不足
+            error!(
+                account_id,
+                currency_id = fee_currency_id,
+                required = %fee_amount,
+                reserved = %fee_reserve,
+                charged = %actual_fee,
+                "Fee exceeds the reserved amount"
+            );
+        }
+        if !actual_fee.is_zero() {
+            let fee_shard =
+                (FEE_ACCOUNT_ID % self.shard_count as i32).unsigned_abs() as usize;
+            if fee_shard == self.id {
+                // 手续费账户在当前分片时直接入账，避免阻塞在发给自己的有界队列上；
+                // 结算已经生效，入账记录写入失败时只把手续费写入死信
+                if self.collect_fee(fee_currency_id, actual_fee).is_err() {
+                    self.dead_letter(
+                        self.id,
+                        &TradeExecutionMessage::CollectFee {
+                            currency_id: fee_currency_id,
+                            amount: actual_fee,
+                        },
+                    );
+                }
+            } else {
+                let collect_msg = TradeExecutionMessage::CollectFee {
+                    currency_id: fee_currency_id,
+                    amount: actual_fee,
+                };
+                if let Err(e) = self.trade_execution_senders[fee_shard].send(collect_msg) {
+                    error!(sequencer = fee_shard, error = %e, "Failed to send fee");
+                    self.dead_letter(fee_shard, &e.0);
+                }
+            }
+        }
+
+        debug!(
+            sequencer = self.id,
+            account_id,
+            %deduct_amount,
+            deduct_currency_id,
+            %add_amount,
+            add_currency_id,
+            fee = %actual_fee,
+            fee_currency_id,
+            "Settled account"
+        );
+
+        Ok(())
+    }
+
+    fn collect_fee(&mut self, currency_id: i32, amount: rust_decimal::Decimal) -> Result<(), BalanceError> {
+        self.write_ahead(WalRecord::CollectFee {
+            currency_id,
+            amount,
+        })?;
+        self.balance_manager
+            .credit(FEE_ACCOUNT_ID, currency_id, amount, AuditReason::Fee);
+        Ok(())
+    }
+
+    fn settle_amended_order(
+        &mut self,
+        account_id: i32,
+        symbol_id: i32,
+        side: i32,
+        prefrozen_amount: rust_decimal::Decimal,
+        orders: Option<Box<(Order, Order)>>,
+    ) -> Result<(), BalanceError> {
+        let symbol = self.management_manager.get_symbol(symbol_id).ok_or(BalanceError::CurrencyNotFound)?;
+        let currency_id = if side == 0 { symbol.quote } else { symbol.base };
+
+        // 改单失败释放全部预冻结金额；成功则释放原订单冻结额与新订单实际所需之外的部分
+        let release_amount = match orders.as_deref() {
+            Some((previous, amended)) => {
+                prefrozen_amount + previous.remaining_freeze_amount()
+                    - amended.remaining_freeze_amount()
+            }
+            None => prefrozen_amount,
+        };
+
+        if release_amount <= rust_decimal::Decimal::ZERO {
+            return Ok(());
+        }
+
+        self.write_ahead(WalRecord::Unfreeze {
+            account_id,
+            currency_id,
+            amount: release_amount,
+        })?;
+        self.balance_manager
+            .release_frozen(account_id, currency_id, release_amount);
+
+        debug!(
+            sequencer = self.id,
+            account_id,
+            currency_id,
+            amount = %release_amount,
+            "Released frozen balance after amend"
+        );
+
+        Ok(())
+    }
+
+    // 返回实际解冻的金额
+    fn unfreeze_order_balance(
+        &mut self,
+        order: &crate::matching::Order,
+        note: Option<String>,
+    ) -> Result<rust_decimal::Decimal, BalanceError> {
+        use crate::matching::OrderSide;
+
+        // 获取交易对信息
+        let symbol = self.management_manager.get_symbol(order.symbol_id).ok_or(BalanceError::CurrencyNotFound)?;
+
+        // 计算需要解冻的金额：买单解冻 quote currency，卖单解冻 base currency
+        let unfreeze_currency_id = match order.side {
+            OrderSide::Bid => symbol.quote,
+            OrderSide::Ask => symbol.base,
+        };
+        let unfreeze_amount = order.remaining_freeze_amount();
+
+        // 检查订单是否属于当前分片
+        let account_shard = (order.account_id % self.shard_count as i32).unsigned_abs() as usize;
+        if account_shard != self.id {
+            // 不属于当前分片，不处理
+            return Ok(rust_decimal::Decimal::ZERO);
+        }
+
+        self.write_ahead(WalRecord::Unfreeze {
+            account_id: order.account_id,
+            currency_id: unfreeze_currency_id,
+            amount: unfreeze_amount,
+        })?;
+
+        // 解冻余额，冻结余额不足时解冻所有剩余的冻结余额
+        let actual_unfreeze =
+            self.balance_manager
+                .release_frozen_noted(
+                    order.account_id,
+                    unfreeze_currency_id,
+                    unfreeze_amount,
+                    note,
+                );
+        if actual_unfreeze < unfreeze_amount {
+            warn!(
+                account_id = order.account_id,
+                currency_id = unfreeze_currency_id,
+                required = %unfreeze_amount,
+                available = %actual_unfreeze,
+                "Insufficient frozen balance"
+            );
+        }
+
+        debug!(
+            sequencer = self.id,
+            account_id = order.account_id,
+            currency_id = unfreeze_currency_id,
+            amount = %actual_unfreeze,
+            order_id = order.id,
+            "Unfroze order balance"
+        );
+
+        Ok(actual_unfreeze)
+    }
+}
+
+// 最优买卖价响应，只读取两侧的最优价位
+fn bbo_response(order_book: Option<&OrderBook>, symbol_id: i32) -> crate::models::schema::BboResponse {
+    let Some(order_book) = order_book else {
+        return crate::models::schema::BboResponse {
+            code: 404,
+            message: Some("OrderBook not found".to_string()),
+            symbol_id,
+            timestamp: now_millis() as i64,
+            ..Default::default()
+        };
+    };
+    let best_bid = order_book.best_bid_level();
+    let best_ask = order_book.best_ask_level();
+    crate::models::schema::BboResponse {
+        code: 0,
+        message: Some("Success".to_string()),
+        symbol_id,
+        best_bid: best_bid.map(|(price, _)| price.to_string()),
+        best_bid_quantity: best_bid.map(|(_, quantity)| quantity.to_string()),
+        best_ask: best_ask.map(|(price, _)| price.to_string()),
+        best_ask_quantity: best_ask.map(|(_, quantity)| quantity.to_string()),
+        spread: order_book.get_spread().map(|spread| spread.to_string()),
+        spread_bps: order_book.spread_bps().map(|bps| bps.to_string()),
+        timestamp: now_millis() as i64,
+        sequence: order_book.sequence() as i64,
+    }
+}
+
+// 构建订单簿深度响应，供快照查询和行情推送共用
+fn order_book_response(
+    order_book: Option<&OrderBook>,
+    symbol_id: i32,
+    levels: usize,
+    bucket: Option<rust_decimal::Decimal>,
+) -> crate::models::schema::GetOrderBookResponse {
+    if let Some(order_book) = order_book {
+        let (bids, asks) = match bucket {
+            Some(bucket) => order_book.get_aggregated_depth(levels, bucket),
+            None => order_book.get_market_depth(levels),
+        };
+
+        let bid_levels: Vec<crate::models::schema::PriceLevel> = bids
+            .into_iter()
+            .map(|(price, quantity)| crate::models::schema::PriceLevel {
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+            })
+            .collect();
+
+        let ask_levels: Vec<crate::models::schema::PriceLevel> = asks
+            .into_iter()
+            .map(|(price, quantity)| crate::models::schema::PriceLevel {
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+            })
+            .collect();
+
+        let best_bid = order_book.get_best_bid().map(|p| p.to_string());
+        let best_ask = order_book.get_best_ask().map(|p| p.to_string());
+        let spread = order_book.get_spread().map(|s| s.to_string());
+
+        crate::models::schema::GetOrderBookResponse {
+            code: 0,
+            message: Some("Success".to_string()),
+            symbol_id,
+            bids: bid_levels,
+            asks: ask_levels,
+            best_bid,
+            best_ask,
+            spread,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            sequence: order_book.sequence() as i64,
+            checksum: order_book.checksum(),
+        }
+    } else {
+        crate::models::schema::GetOrderBookResponse {
+            code: 404,
+            message: Some("OrderBook not found".to_string()),
+            symbol_id,
+            bids: vec![],
+            asks: vec![],
+            best_bid: None,
+            best_ask: None,
+            spread: None,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            sequence: 0,
+            checksum: 0,
+        }
+    }
+}
+
+// 将未完成订单转换为查询响应，止损单附带触发价
+// 撤单成功的响应，退还金额为实际解冻的余额（买单为 quote，卖单为 base）
+fn cancel_order_response(
+    order: &Order,
+    refund_amount: Option<rust_decimal::Decimal>,
+) -> crate::models::schema::CancelOrderResponse {
+    crate::models::schema::CancelOrderResponse {
+        code: 0,
+        message: Some("Order cancelled successfully".to_string()),
+        order_id: order.id as i64,
+        cancelled_quantity: Some(order.remaining_quantity().to_string()),
+        refund_amount: refund_amount.map(|amount| amount.to_string()),
+    }
+}
+
+fn open_order(order: &Order) -> crate::models::schema::OpenOrder {
+    crate::models::schema::OpenOrder {
+        order_id: order.id as i64,
+        symbol_id: order.symbol_id,
+        account_id: order.account_id,
+        r#type: order.order_type.clone() as i32,
+        side: order.side.clone() as i32,
+        time_in_force: order.time_in_force.clone() as i32,
+        price: order.price.to_string(),
+        quantity: order.quantity.to_string(),
+        filled_quantity: order.filled_quantity.to_string(),
+        stop_price: order.stop_price.map(|p| p.to_string()),
+        created_at: order.created_at as i64,
+        expires_at: order.expires_at.map(|expires_at| expires_at as i64),
+        client_order_id: order.client_order_id.clone(),
+    }
+}
+
+// 按顺序停机，调用前应已停止接受新的 gRPC 请求：
+// 1. 关闭请求队列，SequencerProcessor 处理完已入队的请求后释放撮合队列发送端
+// 2. 撮合队列排空后 MatchProcessor 退出，此后不再产生新的结算消息
+// 3. 通知 SequencerProcessor 停止转发手续费，最后关闭成交回调队列，结算完成后退出
+// 把处理器按相邻分片分组，每组由一个工作线程驱动
+pub fn group_shards<T>(processors: Vec<T>, shards_per_worker: usize) -> Vec<Vec<T>> {
+    let shards_per_worker = shards_per_worker.max(1);
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    for processor in processors {
+        match groups.last_mut() {
+            Some(group) if group.len() < shards_per_worker => group.push(processor),
+            _ => groups.push(vec![processor]),
+        }
+    }
+    groups
+}
+
+// 交易对所在的撮合分片。交易对ID由管理接口按顺序分配，取模后新建的交易对轮流落在各分片上，
+// 每个分片的交易对数量相差不超过一个；同一交易对的所有消息都在同一分片内按顺序处理，
+// 分片内各交易对的订单簿相互独立，只共享线程
+pub fn match_shard(symbol_id: i32, shard_count: usize) -> usize {
+    (symbol_id % shard_count as i32).unsigned_abs() as usize
+}
+
+// 一个工作线程驱动多个 SequencerProcessor，每个分片内的处理顺序与独占线程时相同：
+// 运行阶段同时处理请求和成交回调；请求队列关闭后释放撮合队列发送端让 MatchProcessor 排空退出，
+// 继续结算直到成交回调队列关闭。设置了查询溢出时，本组分片都没有待处理消息才回复溢出队列中的查询
+pub fn run_sequencers(processors: Vec<SequencerProcessor>) {
+    // 队列只在发送端全部释放后才不再等待，此时保留接收端不影响发送方
+    let receivers: Vec<_> = processors
+        .iter()
+        .map(|p| (p.receiver.clone(), p.trade_execution_receiver.clone()))
+        .collect();
+    let read_overflow = processors.iter().find_map(|p| p.read_overflow.clone());
+    let worker = processors.first().map_or(0, |p| p.id);
+    let mut alive: Vec<_> = processors.iter().map(|p| Some(p.liveness.guard())).collect();
+    let mut draining = vec![false; processors.len()];
+    let mut processors: Vec<_> = processors.into_iter().map(Some).collect();
+    for processor in processors.iter().flatten() {
+        info!(sequencer = processor.id, "SequencerProcessor started");
+    }
+
+    loop {
+        // 分片进入排空或停止后重建 Select，(分片下标, 是否为请求队列)
+        let mut select = crossbeam_channel::Select::new();
+        let mut operations = Vec::new();
+        for (index, (receiver, trade_execution_receiver)) in receivers.iter().enumerate() {
+            if processors[index].is_none() {
+                continue;
+            }
+            if !draining[index] {
+                select.recv(receiver);
+                operations.push((index, true));
+            }
+            select.recv(trade_execution_receiver);
+            operations.push((index, false));
+        }
+        if operations.is_empty() {
+            break;
+        }
+        // 溢出队列排在本组分片的队列之后，下标为 operations.len()
+        let mut stealing = select.clone();
+        if let Some(read_overflow) = &read_overflow {
+            stealing.recv(read_overflow.receiver());
+        }
+
+        loop {
+            let operation = match select.try_select() {
+                Ok(operation) => operation,
+                Err(_) => stealing.select(),
+            };
+            let Some(&(index, is_request)) = operations.get(operation.index()) else {
+                if let Some(read_overflow) = &read_overflow {
+                    // 溢出队列的发送端由 read_overflow 持有，不会关闭
+                    if let Ok(read) = operation.recv(read_overflow.receiver()) {
+                        read_overflow.serve(worker, read);
+                    }
+                }
+                continue;
+            };
+            let (receiver, trade_execution_receiver) = &receivers[index];
+            // 选中的操作必须先完成接收
+            if is_request {
+                let message = operation.recv(receiver);
+                let Some(processor) = processors[index].as_mut() else {
+                    break;
+                };
+                match message {
+                    Ok(msg) => {
+                        processor.process_sequencer_message(msg);
+                        processor.publish_accounts();
+                    }
+                    Err(_) => {
+                        info!(sequencer = processor.id, "Draining, sequencer channel closed");
+                        processor.match_senders.clear();
+                        draining[index] = true;
+                        break;
+                    }
+                }
+            } else {
+                let message = operation.recv(trade_execution_receiver);
+                let Some(processor) = processors[index].as_mut() else {
+                    break;
+                };
+                match message {
+                    Ok(msg) => {
+                        processor.process_trade_execution_message(msg);
+                        processor.publish_accounts();
+                    }
+                    Err(_) => {
+                        info!(
+                            sequencer = processor.id,
+                            "Stopped, trade execution channel closed"
+                        );
+                        processors[index] = None;
+                        alive[index] = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 一个工作线程驱动多个 MatchProcessor：每条消息处理后检查该分片的到期订单，
+// 空闲超时或距上次全部检查超过间隔时检查所有分片，忙碌的分片不会耽误其他分片的订单到期
+pub fn run_matchers(processors: Vec<MatchProcessor>) {
+    let receivers: Vec<_> = processors.iter().map(|p| p.receiver.clone()).collect();
+    let mut alive: Vec<_> = processors.iter().map(|p| Some(p.liveness.guard())).collect();
+    let mut processors: Vec<_> = processors.into_iter().map(Some).collect();
+    for processor in processors.iter().flatten() {
+        info!(matcher = processor.id, "MatchProcessor started");
+    }
+    let mut last_sweep = Instant::now();
+
+    loop {
+        // 分片停止后重建 Select
+        let mut select = crossbeam_channel::Select::new();
+        let mut indexes = Vec::new();
+        for (index, receiver) in receivers.iter().enumerate() {
+            if processors[index].is_some() {
+                select.recv(receiver);
+                indexes.push(index);
+            }
+        }
+        if indexes.is_empty() {
+            break;
+        }
+
+        loop {
+            // 上一条消息已处理完成，此时的引擎状态与日志末尾一致
+            for processor in processors.iter_mut().flatten() {
+                processor.maybe_snapshot();
+            }
+            let timed_out = match select.select_timeout(EXPIRY_SWEEP_INTERVAL) {
+                Ok(operation) => {
+                    let index = indexes[operation.index()];
+                    let message = operation.recv(&receivers[index]);
+                    let Some(processor) = processors[index].as_mut() else {
+                        break;
+                    };
+                    match message {
+                        Ok(message) => {
+                            processor.handle_batch(message);
+                            processor.expire_orders(now_millis());
+                        }
+                        Err(_) => {
+                            info!(matcher = processor.id, "Stopped, channel closed");
+                            processors[index] = None;
+                            alive[index] = None;
+                            break;
+                        }
+                    }
+                    false
+                }
+                Err(_) => true,
+            };
+            if timed_out || last_sweep.elapsed() >= EXPIRY_SWEEP_INTERVAL {
+                let now = now_millis();
+                for processor in processors.iter_mut().flatten() {
+                    processor.expire_orders(now);
+                }
+                last_sweep = Instant::now();
+            }
+        }
+    }
+}
+
+pub fn drain_processors(
+    sequencer_senders: Vec<crossbeam_channel::Sender<SequencerMessage>>,
+    match_senders: Vec<crossbeam_channel::Sender<MatchMessage>>,
+    trade_execution_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>,
+    sequencer_handles: Vec<std::thread::JoinHandle<()>>,
+    match_handles: Vec<std::thread::JoinHandle<()>>,
+) {
+    drop(sequencer_senders);
+    drop(match_senders);
+    for handle in match_handles {
+        let _ = handle.join();
+    }
+
+    for sender in &trade_execution_senders {
+        let _ = sender.send(TradeExecutionMessage::Drain);
+    }
+    drop(trade_execution_senders);
+    for handle in sequencer_handles {
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::{ORDER_BOOK_CHANNEL_CAPACITY, TRADE_CHANNEL_CAPACITY};
+    use crate::models::schema::PlaceOrderResponse;
+    use crate::matching::{CircuitBreaker, ManualClock, RoundingPolicy, TradingRules};
+    use crate::models::{AuditEntry, FEE_ACCOUNT_ID};
+    use crate::overflow::OverflowRead;
+    use rust_decimal::Decimal;
+    use std::path::PathBuf;
+    use tokio::sync::oneshot;
+
+    const SYMBOL_ID: i32 = 1;
+    const BTC: i32 = 1;
+    const USDT: i32 = 2;
+    // 默认单分片，账户都落在分片 0
+    const BUYER: i32 = 10;
+    const SELLER: i32 = 20;
+
+    // 一组 SequencerProcessor + MatchProcessor，同步驱动消息流转
+    struct Harness {
+        shard_count: usize,
+        management: Arc<ManagementManager>,
+        sequencers: Vec<SequencerProcessor>,
+        matchers: Vec<MatchProcessor>,
+        order_book_publisher: Arc<OrderBookPublisher>,
+        sequencer_senders: Vec<crossbeam_channel::Sender<SequencerMessage>>,
+        match_senders: Vec<crossbeam_channel::Sender<MatchMessage>>,
+        trade_execution_senders: Vec<crossbeam_channel::Sender<TradeExecutionMessage>>,
+        wal_paths: Vec<PathBuf>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            Self::with_shards(1)
+        }
+
+        fn with_shards(shard_count: usize) -> Self {
+            Self::build(shard_count, None)
+        }
+
+        // match_capacity 为 Some 时撮合队列有界，用于测试队列满的情况
+        fn build(shard_count: usize, match_capacity: Option<usize>) -> Self {
+            let management = Arc::new(ManagementManager::new());
+            management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+            management.create_currency("USDT".to_string(), "Tether USD".to_string());
+            management
+                .create_symbol("BTC-USDT".to_string(), BTC, USDT, TradingRules::default())
+                .unwrap();
+
+            let wal_dir = std::env::temp_dir().join(format!("lightning-test-{}", uuid::Uuid::new_v4()));
+            let mut wal_paths = Vec::new();
+
+            let (match_senders, match_receivers): (Vec<_>, Vec<_>) = (0..shard_count)
+                .map(|_| match match_capacity {
+                    Some(capacity) => crossbeam_channel::bounded(capacity),
+                    None => crossbeam_channel::unbounded(),
+                })
+                .unzip();
+            let (trade_execution_senders, trade_execution_receivers): (Vec<_>, Vec<_>) =
+                (0..shard_count).map(|_| crossbeam_channel::unbounded()).unzip();
+
+            let mut sequencers = Vec::new();
+            let mut sequencer_senders = Vec::new();
+            for (i, trade_execution_receiver) in trade_execution_receivers.into_iter().enumerate() {
+                let wal_path = wal::sequencer_log_path(&wal_dir, i);
+                let (sequencer_sender, sequencer_receiver) = crossbeam_channel::unbounded();
+                sequencer_senders.push(sequencer_sender);
+                sequencers.push(SequencerProcessor::new(
+                    i,
+                    shard_count,
+                    sequencer_receiver,
+                    match_senders.clone(),
+                    trade_execution_receiver,
+                    management.clone(),
+                    BalanceManager::new(),
+                    WriteAheadLog::open(&wal_path).unwrap(),
+                    trade_execution_senders.clone(),
+                ));
+                wal_paths.push(wal_path);
+            }
+
+            let order_book_publisher = Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY));
+            let mut matchers = Vec::new();
+            for (i, match_receiver) in match_receivers.into_iter().enumerate() {
+                let wal_path = wal::match_log_path(&wal_dir, i);
+                matchers.push(MatchProcessor::new(
+                    i,
+                    match_receiver,
+                    trade_execution_senders.clone(),
+                    management.clone(),
+                    order_book_publisher.clone(),
+                    Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+                    MatchingEngine::new(),
+                    WriteAheadLog::open(&wal_path).unwrap(),
+                ));
+                wal_paths.push(wal_path);
+            }
+
+            Self {
+                shard_count,
+                management,
+                sequencers,
+                matchers,
+                order_book_publisher,
+                sequencer_senders,
+                match_senders,
+                trade_execution_senders,
+                wal_paths,
+            }
+        }
+
+        // 在独立线程中运行所有处理器，之后只能通过发送端和预写日志观察结果
+        fn spawn(&mut self) -> (Vec<std::thread::JoinHandle<()>>, Vec<std::thread::JoinHandle<()>>) {
+            let sequencer_handles = self
+                .sequencers
+                .drain(..)
+                .map(|sequencer| std::thread::spawn(move || sequencer.run()))
+                .collect();
+            let match_handles = self
+                .matchers
+                .drain(..)
+                .map(|matcher| std::thread::spawn(move || matcher.run()))
+                .collect();
+            (sequencer_handles, match_handles)
+        }
+
+        fn shard(&self, id: i32) -> usize {
+            (id % self.shard_count as i32).unsigned_abs() as usize
+        }
+
+        // 处理所有在途消息，直到所有处理器都空闲
+        fn pump(&mut self) {
+            loop {
+                let mut progressed = false;
+                for matcher in &mut self.matchers {
+                    while let Ok(message) = matcher.receiver.try_recv() {
+                        matcher.handle_message(message);
+                        progressed = true;
+                    }
+                }
+                for sequencer in &mut self.sequencers {
+                    while let Ok(message) = sequencer.trade_execution_receiver.try_recv() {
+                        sequencer.process_trade_execution_message(message);
+                        progressed = true;
+                    }
+                }
+                if !progressed {
+                    break;
+                }
+            }
+        }
+
+        fn deposit(&mut self, account_id: i32, currency_id: i32, amount: &str) {
+            self.deposit_with_key(account_id, currency_id, amount, None);
+        }
+
+        fn deposit_with_key(
+            &mut self,
+            account_id: i32,
+            currency_id: i32,
+            amount: &str,
+            idempotency_key: Option<i64>,
+        ) -> crate::models::schema::IncreaseResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::Increase {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                currency_id,
+                amount: amount.to_string(),
+                idempotency_key,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 通过排序器查询账户所有币种余额
+        fn account(&mut self, account_id: i32) -> crate::models::schema::GetAccountResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::GetAccount {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                currency_id: None,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn place(
+            &mut self,
+            account_id: i32,
+            order_type: OrderType,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+        ) -> PlaceOrderResponse {
+            self.place_with_fees(account_id, order_type, side, price, quantity, 0, 0)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn place_with_fees(
+            &mut self,
+            account_id: i32,
+            order_type: OrderType,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            taker_rate: i32,
+            maker_rate: i32,
+        ) -> PlaceOrderResponse {
+            self.submit(account_id, order_type, side, price, quantity, taker_rate, maker_rate, false)
+        }
+
+        fn place_post_only(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+        ) -> PlaceOrderResponse {
+            self.submit(account_id, OrderType::Limit, side, price, quantity, 0, 0, true)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn submit(
+            &mut self,
+            account_id: i32,
+            order_type: OrderType,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            taker_rate: i32,
+            maker_rate: i32,
+            post_only: bool,
+        ) -> PlaceOrderResponse {
+            self.submit_on(
+                SYMBOL_ID,
+                account_id,
+                order_type,
+                side,
+                price,
+                quantity,
+                taker_rate,
+                maker_rate,
+                post_only,
+            )
+        }
+
+        // 在指定交易对上下不带手续费的限价单
+        fn place_on(
+            &mut self,
+            symbol_id: i32,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+        ) -> PlaceOrderResponse {
+            self.submit_on(
+                symbol_id,
+                account_id,
+                OrderType::Limit,
+                side,
+                price,
+                quantity,
+                0,
+                0,
+                false,
+            )
+        }
+
+        // 下带到期时间的 GTC 限价单
+        fn place_expiring(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            expires_at: u64,
+        ) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: Some(expires_at),
+                volume: None,
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 按金额下单的市价买单
+        fn place_quote(&mut self, account_id: i32, volume: &str) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Market as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: TimeInForce::Ioc as i32,
+                price: String::new(),
+                quantity: String::new(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: Some(volume.to_string()),
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 在撮合分片上按指定时间执行到期扫描
+        fn expire_at(&mut self, now: u64) {
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].expire_orders(now);
+            self.pump();
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn submit_on(
+            &mut self,
+            symbol_id: i32,
+            account_id: i32,
+            order_type: OrderType,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            taker_rate: i32,
+            maker_rate: i32,
+            post_only: bool,
+        ) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id,
+                account_id,
+                order_type: order_type as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                taker_rate,
+                maker_rate,
+                post_only,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 携带 nonce 的限价单
+        fn place_with_nonce(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            nonce: i64,
+        ) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: None,
+                validate_only: false,
+                nonce: Some(nonce),
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 只校验的限价单，不冻结也不进入撮合
+        fn validate(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+        ) -> PlaceOrderResponse {
+            self.validate_with(account_id, side, price, quantity, false, None)
+        }
+
+        fn validate_with(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            post_only: bool,
+            client_order_id: Option<&str>,
+        ) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: client_order_id.map(str::to_string),
+                validate_only: true,
+                nonce: None,
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn transfer(
+            &mut self,
+            from_account_id: i32,
+            to_account_id: i32,
+            currency_id: i32,
+            amount: &str,
+        ) -> crate::models::schema::TransferResponse {
+            self.transfer_with_key(from_account_id, to_account_id, currency_id, amount, None, None)
+        }
+
+        fn transfer_with_key(
+            &mut self,
+            from_account_id: i32,
+            to_account_id: i32,
+            currency_id: i32,
+            amount: &str,
+            idempotency_key: Option<i64>,
+            nonce: Option<i64>,
+        ) -> crate::models::schema::TransferResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(from_account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::Transfer {
+                request_id: uuid::Uuid::new_v4(),
+                from_account_id,
+                to_account_id,
+                currency_id,
+                amount: amount.to_string(),
+                idempotency_key,
+                nonce,
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn request_withdrawal(
+            &mut self,
+            account_id: i32,
+            currency_id: i32,
+            amount: &str,
+        ) -> crate::models::schema::WithdrawalResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::RequestWithdrawal {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                currency_id,
+                amount: amount.to_string(),
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn finish_withdrawal(
+            &mut self,
+            account_id: i32,
+            hold_id: u64,
+            confirm: bool,
+        ) -> crate::models::schema::WithdrawalResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::FinishWithdrawal {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                hold_id,
+                confirm,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn adjust_reserve(
+            &mut self,
+            account_id: i32,
+            currency_id: i32,
+            tag: &str,
+            amount: &str,
+            release: bool,
+        ) -> crate::models::schema::AdjustReserveResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::AdjustReserve {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                currency_id,
+                tag: tag.to_string(),
+                amount: amount.to_string(),
+                release,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 与 gRPC 接口的流程相同：先在各分片冻结快照中订单的余额，装入失败时退回
+        fn load_order_book(&mut self, snapshot: Vec<u8>, force: bool) -> i32 {
+            let orders = MatchingEngine::snapshot_open_orders(SYMBOL_ID, &snapshot).unwrap();
+            let mut funded = Vec::new();
+            for shard in 0..self.shard_count {
+                let orders: Vec<Order> = orders
+                    .iter()
+                    .filter(|order| self.shard(order.account_id) == shard)
+                    .cloned()
+                    .collect();
+                let code = self.fund_loaded_orders(shard, orders.clone(), false);
+                if code != 0 {
+                    for (shard, orders) in funded {
+                        self.fund_loaded_orders(shard, orders, true);
+                    }
+                    return code;
+                }
+                funded.push((shard, orders));
+            }
+
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::LoadOrderBook {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                snapshot,
+                force,
+                funded: true,
+                response_sender,
+            });
+            let code = response_receiver.try_recv().unwrap().code;
+            self.pump();
+            if code != 0 {
+                for (shard, orders) in funded {
+                    self.fund_loaded_orders(shard, orders, true);
+                }
+            }
+            code
+        }
+
+        fn fund_loaded_orders(&mut self, shard: usize, orders: Vec<Order>, release: bool) -> i32 {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::FundLoadedOrders {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                orders,
+                release,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap().code
+        }
+
+        fn trades(&mut self, limit: i32) -> crate::models::schema::GetTradesResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::GetTrades {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                limit,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn ticker(&mut self) -> crate::models::schema::TickerResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::GetTicker {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn bbo(&mut self) -> crate::models::schema::BboResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::GetBbo {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn open_orders(&mut self, account_id: i32) -> crate::models::schema::GetOpenOrdersResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::GetOpenOrders {
+                request_id: uuid::Uuid::new_v4(),
+                account_id,
+                symbol_id: SYMBOL_ID,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn get_order(&mut self, order_id: i64) -> crate::models::schema::GetOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(SYMBOL_ID);
+            self.matchers[shard].handle_message(MatchMessage::GetOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                order_id: order_id as u64,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn cancel(
+            &mut self,
+            account_id: i32,
+            order_id: i64,
+        ) -> crate::models::schema::CancelOrderResponse {
+            self.submit_cancel(account_id, order_id as u64, None, None)
+        }
+
+        fn cancel_by_client_id(
+            &mut self,
+            account_id: i32,
+            client_order_id: &str,
+        ) -> crate::models::schema::CancelOrderResponse {
+            self.submit_cancel(account_id, 0, Some(client_order_id.to_string()), None)
+        }
+
+        fn submit_cancel(
+            &mut self,
+            account_id: i32,
+            order_id: u64,
+            client_order_id: Option<String>,
+            nonce: Option<i64>,
+        ) -> crate::models::schema::CancelOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::CancelOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_id,
+                client_order_id,
+                nonce,
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn amend(
+            &mut self,
+            account_id: i32,
+            order_id: i64,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+        ) -> crate::models::schema::AmendOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::AmendOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_id: order_id as u64,
+                side: side as i32,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                nonce: None,
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 带客户端订单ID的限价单
+        fn place_with_client_id(
+            &mut self,
+            account_id: i32,
+            side: OrderSide,
+            price: &str,
+            quantity: &str,
+            client_order_id: &str,
+        ) -> PlaceOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: Some(client_order_id.to_string()),
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::current(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn cancel_all(&mut self, account_id: i32) -> crate::models::schema::CancelAllOrdersResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(SequencerMessage::CancelAllOrders {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                nonce: None,
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        // 账户所在分片上的余额 (总额, 冻结, 可用)
+        fn force_cancel(
+            &mut self,
+            order_id: i64,
+            reason: &str,
+        ) -> crate::models::schema::CancelOrderResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = match_shard(SYMBOL_ID, self.shard_count);
+            self.matchers[shard].handle_message(MatchMessage::ForceCancelOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                order_id: order_id as u64,
+                reason: reason.to_string(),
+                response_sender,
+            });
+            self.pump();
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn admin_adjust(
+            &mut self,
+            account_id: i32,
+            currency_id: i32,
+            delta: &str,
+            reason: &str,
+        ) -> crate::models::schema::AdminAdjustBalanceResponse {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = self.shard(account_id);
+            self.sequencers[shard].process_sequencer_message(
+                SequencerMessage::AdminAdjustBalance {
+                    request_id: uuid::Uuid::new_v4(),
+                    account_id,
+                    currency_id,
+                    delta: delta.to_string(),
+                    reason: reason.to_string(),
+                    response_sender,
+                },
+            );
+            response_receiver.try_recv().unwrap()
+        }
+
+        fn latest_audit(&self, account_id: i32, currency_id: i32) -> &AuditEntry {
+            let shard = self.shard(account_id);
+            self.sequencers[shard]
+                .balance_manager
+                .get_balance_history(account_id, Some(currency_id), 1)[0]
+        }
+
+        fn balance(&self, account_id: i32, currency_id: i32) -> (String, String, String) {
+            self.balance_on_shard(self.shard(account_id), account_id, currency_id)
+        }
+
+        fn balance_on_shard(
+            &self,
+            shard: usize,
+            account_id: i32,
+            currency_id: i32,
+        ) -> (String, String, String) {
+            let response = self.sequencers[shard]
+                .balance_manager
+                .handle_get_account(account_id, Some(currency_id));
+            let Some(balance) = response.data.get(&currency_id) else {
+                return balance("0", "0", "0");
+            };
+            (
+                balance.value.clone(),
+                balance.frozen.clone(),
+                balance.available.clone(),
+            )
+        }
+    }
+
+    impl Harness {
+        // 模拟崩溃后重启：丢弃内存状态，按 main 的方式从预写日志恢复余额和订单簿，队列保持不变
+        fn restart(&mut self) {
+            let shard_count = self.shard_count;
+            for i in 0..shard_count {
+                let wal_path = self.wal_paths[shard_count + i].clone();
+                let matcher = &self.matchers[i];
+                self.matchers[i] = MatchProcessor::new(
+                    i,
+                    matcher.receiver.clone(),
+                    self.trade_execution_senders.clone(),
+                    self.management.clone(),
+                    self.order_book_publisher.clone(),
+                    Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+                    wal::recover_matching_engine(&wal_path, None).unwrap(),
+                    WriteAheadLog::open(&wal_path).unwrap(),
+                );
+            }
+            // 与启动时相同，按恢复后的订单簿重建未完成订单
+            for i in 0..shard_count {
+                let wal_path = self.wal_paths[i].clone();
+                let sequencer = &self.sequencers[i];
+                let replayed = wal::replay(&wal_path).unwrap();
+                self.sequencers[i] = SequencerProcessor::new(
+                    i,
+                    shard_count,
+                    sequencer.receiver.clone(),
+                    self.match_senders.clone(),
+                    sequencer.trade_execution_receiver.clone(),
+                    self.management.clone(),
+                    replayed.balance_manager,
+                    WriteAheadLog::open(&wal_path).unwrap(),
+                    self.trade_execution_senders.clone(),
+                );
+                let matching_engines = self.matchers.iter().map(|matcher| &matcher.matching_engine);
+                self.sequencers[i].restore_open_orders(replayed.placement_mode, matching_engines);
+            }
+        }
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            for path in &self.wal_paths {
+                let _ = std::fs::remove_file(path);
+            }
+            if let Some(dir) = self.wal_paths[0].parent() {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+    }
+
+    fn balance(total: &str, frozen: &str, available: &str) -> (String, String, String) {
+        (total.to_string(), frozen.to_string(), available.to_string())
+    }
+
+    #[test]
+    fn test_market_buy_on_thin_book_releases_leftover() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "10000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "0.4");
+
+        // 市价买 1 BTC，报价 100，冻结 100 USDT；卖盘只有 0.4
+        let response = harness.place(BUYER, OrderType::Market, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+
+        assert_eq!(harness.balance(BUYER, USDT), balance("9960.0", "0.0", "9960.0"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("0.4", "0", "0.4"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("0.6", "0.0", "0.6"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("40.0", "0", "40.0"));
+    }
+
+    #[test]
+    fn test_market_buy_below_quote_releases_price_improvement() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "90", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "95", "1");
+
+        // 报价 100 冻结 150 USDT，实付 90 + 47.5，省下的 12.5 全部解冻
+        let response = harness.place(BUYER, OrderType::Market, OrderSide::Bid, "100", "1.5");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("862.5", "0.0", "862.5"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1.5", "0", "1.5"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("137.5", "0", "137.5"));
+    }
+
+    #[test]
+    fn test_market_buy_spends_at_most_the_frozen_quote() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "50", "2");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "125", "1");
+
+        // 报价 100 只冻结 100 USDT，卖价高于报价时按冻结金额买 0.8，不动用其他挂单的冻结
+        let response = harness.place(BUYER, OrderType::Market, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("900.00", "100.00", "800"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("0.80", "0", "0.80"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("100.00", "0", "100.00"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("1.20", "0.20", "1"));
+    }
+
+    #[test]
+    fn test_market_sell_on_empty_book_releases_all() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+
+        let response = harness.place(SELLER, OrderType::Market, OrderSide::Ask, "", "1.5");
+        assert_eq!(response.code, 0);
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Order cancelled: no matching liquidity")
+        );
+
+        assert_eq!(harness.balance(SELLER, BTC), balance("2", "0.0", "2.0"));
+    }
+
+    #[test]
+    fn test_quote_volume_market_buy_spends_volume_and_releases_leftover() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "120", "0.6");
+
+        // 花 160 USDT：100 买 1 BTC，60 在 120 买 0.5 BTC
+        let response = harness.place_quote(BUYER, "160");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("840.00", "0.00", "840"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1.50", "0", "1.50"));
+
+        // 卖盘只剩 0.1 BTC，花 12 USDT 后解冻剩余的 88
+        let response = harness.place_quote(BUYER, "100");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("828.00", "0.00", "828.00"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1.60", "0", "1.60"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("172.00", "0", "172.00"));
+
+        // 只支持市价买单，不冻结余额
+        let (response_sender, mut response_receiver) = oneshot::channel();
+        let shard = harness.shard(BUYER);
+        harness.sequencers[shard].process_sequencer_message(
+            SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id: BUYER,
+                order_type: OrderType::Limit as i32,
+                side: OrderSide::Bid as i32,
+                time_in_force: 0,
+                price: "100".to_string(),
+                quantity: "1".to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: Some("100".to_string()),
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::none(),
+                response_sender,
+            },
+        );
+        assert_eq!(response_receiver.try_recv().unwrap().code, 400);
+        assert_eq!(harness.balance(BUYER, USDT), balance("828.00", "0.00", "828.00"));
+    }
+
+    #[test]
+    fn test_symbol_created_at_runtime_settles_trades() {
+        let mut harness = Harness::with_shards(2);
+        let eth = harness
+            .management
+            .create_currency("ETH".to_string(), "Ethereum".to_string());
+        let eth_usdt = harness
+            .management
+            .create_symbol("ETH-USDT".to_string(), eth.id, USDT, TradingRules::default())
+            .unwrap();
+        assert_ne!(eth_usdt.id, SYMBOL_ID);
+
+        harness.deposit(SELLER, eth.id, "3");
+        harness.deposit(BUYER, USDT, "1000");
+
+        let response = harness.place_on(eth_usdt.id, SELLER, OrderSide::Ask, "200", "2");
+        assert_eq!(response.code, 0);
+        let response = harness.place_on(eth_usdt.id, BUYER, OrderSide::Bid, "200", "2");
+        assert_eq!(response.code, 0);
+
+        assert_eq!(harness.balance(BUYER, eth.id), balance("2", "0", "2"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("600", "0", "600"));
+        assert_eq!(harness.balance(SELLER, eth.id), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("400", "0", "400"));
+    }
+
+    #[test]
+    fn test_symbols_sharing_a_shard_never_match_each_other() {
+        let mut harness = Harness::with_shards(1);
+        let symbol_ids: Vec<i32> = (0..8)
+            .map(|i| {
+                harness
+                    .management
+                    .create_symbol(format!("BTC-USDT-{}", i), BTC, USDT, TradingRules::default())
+                    .unwrap()
+                    .id
+            })
+            .collect();
+        harness.deposit(SELLER, BTC, "100");
+        harness.deposit(BUYER, USDT, "100000");
+
+        // 交叉的买卖单分别挂在不同交易对上，同一分片内也不会互相成交
+        for (i, &symbol_id) in symbol_ids.iter().enumerate() {
+            let response = if i % 2 == 0 {
+                harness.place_on(symbol_id, SELLER, OrderSide::Ask, "100", "1")
+            } else {
+                harness.place_on(symbol_id, BUYER, OrderSide::Bid, "101", "1")
+            };
+            assert_eq!(response.code, 0);
+        }
+        assert_eq!(harness.balance(SELLER, USDT).0, "0");
+        assert_eq!(harness.balance(BUYER, BTC).0, "0");
+        for &symbol_id in &symbol_ids {
+            let book = harness.matchers[0].matching_engine.get_order_book(symbol_id).unwrap();
+            assert_eq!(book.bids.len() + book.asks.len(), 1);
+            assert_eq!(harness.matchers[0].symbol_message_count(symbol_id), 1);
+        }
+        assert_eq!(harness.matchers[0].symbol_count(), symbol_ids.len());
+
+        // 同一交易对上的对手单正常成交
+        let response = harness.place_on(symbol_ids[0], BUYER, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, BTC).0, "1");
+        assert_eq!(harness.matchers[0].symbol_message_count(symbol_ids[0]), 2);
+        assert_eq!(harness.matchers[0].symbol_message_count(symbol_ids[1]), 1);
+    }
+
+    #[test]
+    fn test_order_violating_trading_rules_rejected_before_freeze() {
+        let mut harness = Harness::new();
+        let trading_rules = TradingRules {
+            price_tick: rust_decimal::Decimal::from_str_exact("0.01").unwrap(),
+            quantity_step: rust_decimal::Decimal::from_str_exact("0.01").unwrap(),
+            min_notional: rust_decimal::Decimal::from(100),
+            ..TradingRules::default()
+        };
+        let symbol = harness
+            .management
+            .create_symbol("BTC-USDT-R".to_string(), BTC, USDT, trading_rules)
+            .unwrap();
+        harness.deposit(BUYER, USDT, "1000");
+
+        for (price, quantity, reason) in [
+            ("50000.001", "0.01", RejectReason::InvalidPrice),
+            ("50000", "0.005", RejectReason::InvalidQuantity),
+            ("99", "1", RejectReason::BelowMinNotional),
+        ] {
+            let response = harness.place_on(symbol.id, BUYER, OrderSide::Bid, price, quantity);
+            assert_eq!(response.code, 400, "{} x {}", price, quantity);
+            assert_eq!(response.reject_reason(), reason);
+            assert_eq!(response.reason_code, reason.as_str_name());
+            assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        }
+
+        let response = harness.place_on(symbol.id, BUYER, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "100", "900"));
+    }
+
+    #[test]
+    fn test_validate_only_reports_freeze_without_side_effects() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "1");
+
+        let response = harness.validate(BUYER, OrderSide::Bid, "100", "2.5");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.reject_reason(), RejectReason::NoReject);
+        assert_eq!(response.frozen_currency_id, Some(USDT));
+        assert_eq!(response.frozen_amount.as_deref(), Some("250.0"));
+        let response = harness.validate(SELLER, OrderSide::Ask, "100", "0.5");
+        assert_eq!(response.frozen_currency_id, Some(BTC));
+        assert_eq!(response.frozen_amount.as_deref(), Some("0.5"));
+
+        // 余额不足时返回与正常下单相同的拒绝原因
+        let response = harness.validate(BUYER, OrderSide::Bid, "100", "11");
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        assert_eq!(response.frozen_amount, None);
+
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0", "1"));
+        let matcher = &harness.matchers[harness.shard(SYMBOL_ID)];
+        assert!(matcher.matching_engine.get_order_book(SYMBOL_ID).is_none());
+    }
+
+    #[test]
+    fn test_validate_only_runs_the_matcher_checks() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.place_with_client_id(BUYER, OrderSide::Bid, "90", "1", "bid-1");
+
+        // 只做 maker 的订单会立即成交时拒绝，订单簿不变
+        let response = harness.validate_with(BUYER, OrderSide::Bid, "100", "1", true, None);
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::PostOnlyCross);
+        let response = harness.validate_with(BUYER, OrderSide::Bid, "99", "1", true, None);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.frozen_amount.as_deref(), Some("99"));
+
+        // 客户端订单ID与未完成订单重复
+        let response = harness.validate_with(BUYER, OrderSide::Bid, "80", "1", false, Some("bid-1"));
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::InvalidOrder);
+        let response = harness.validate_with(BUYER, OrderSide::Bid, "80", "1", false, Some("bid-2"));
+        assert_eq!(response.code, 0);
+
+        // 暂停和只可撤单期间同样拒绝
+        for status in [SymbolStatus::Halted, SymbolStatus::CancelOnly] {
+            harness.management.set_symbol_status(SYMBOL_ID, status).unwrap();
+            let response = harness.validate(BUYER, OrderSide::Bid, "80", "1");
+            assert_eq!(response.code, 400);
+            assert_eq!(response.reject_reason(), RejectReason::MarketHalted);
+        }
+
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "90", "910"));
+        assert_eq!(harness.open_orders(BUYER).orders.len(), 1);
+        assert_eq!(harness.open_orders(SELLER).orders.len(), 1);
+    }
+
+    #[test]
+    fn test_account_risk_limits_track_open_orders_and_notional() {
+        let mut harness = Harness::new();
+        for sequencer in &mut harness.sequencers {
+            sequencer.set_risk_limits(RiskLimits {
+                max_open_orders: Some(3),
+                max_open_notional: Some(Decimal::from(1000)),
+            });
+        }
+        harness.deposit(BUYER, USDT, "10000");
+        harness.deposit(SELLER, BTC, "10");
+
+        let first = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "4");
+        assert_eq!(first.code, 0);
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "5").code, 0);
+        // 名义价值 900 + 200 超过上限
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::RiskLimitExceeded);
+        let third = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1");
+        assert_eq!(third.code, 0);
+        // 未完成订单数已达上限，只校验时同样拒绝
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1");
+        assert_eq!(response.reject_reason(), RejectReason::RiskLimitExceeded);
+        let response = harness.validate(BUYER, OrderSide::Bid, "10", "1");
+        assert_eq!(response.reject_reason(), RejectReason::RiskLimitExceeded);
+        // 被拒绝的订单不冻结余额
+        assert_eq!(harness.balance(BUYER, USDT), balance("10000", "910", "9090"));
+
+        // 第一笔挂单全部成交后释放名额和名义价值
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "4").code, 0);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1");
+        assert_eq!(response.code, 0);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1");
+        assert_eq!(response.reject_reason(), RejectReason::RiskLimitExceeded);
+
+        // 撤单后同样释放名额
+        assert_eq!(harness.cancel(BUYER, third.id).code, 0);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1");
+        assert_eq!(response.code, 0);
+
+        // 部分成交后按剩余数量计算名义价值：剩余 100 x 2 + 10 x 2
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "3").code, 0);
+        let shard = harness.shard(BUYER);
+        assert_eq!(harness.sequencers[shard].open_orders.open_orders(BUYER), 3);
+        assert_eq!(
+            harness.sequencers[shard].open_orders.open_notional(BUYER),
+            Decimal::from(220)
+        );
+    }
+
+    #[test]
+    fn test_amend_is_checked_against_open_notional_limit() {
+        let mut harness = Harness::new();
+        for sequencer in &mut harness.sequencers {
+            sequencer.set_risk_limits(RiskLimits {
+                max_open_orders: None,
+                max_open_notional: Some(Decimal::from(1000)),
+            });
+        }
+        harness.deposit(BUYER, USDT, "10000");
+        harness.deposit(SELLER, BTC, "10");
+        let first = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "4");
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "5").code, 0);
+
+        // 改单后 600 + 500 或 550 + 500 超过上限，订单和冻结保持不变
+        for (price, quantity) in [("100", "6"), ("110", "5")] {
+            let amended = harness.amend(BUYER, first.id, OrderSide::Bid, price, quantity);
+            assert_eq!(amended.code, 400, "{} x {}", price, quantity);
+            assert!(amended.message().contains("Risk limit exceeded"));
+            assert_eq!(harness.balance(BUYER, USDT), balance("10000", "900", "9100"));
+        }
+        assert_eq!(harness.amend(BUYER, first.id, OrderSide::Bid, "100", "5").code, 0);
+        let shard = harness.shard(BUYER);
+        assert_eq!(harness.sequencers[shard].open_orders.open_notional(BUYER), Decimal::from(1000));
+
+        // 卖单只提价不需要增加冻结，名义价值超过上限时同样拒绝
+        let ask = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "200", "3");
+        let amended = harness.amend(SELLER, ask.id, OrderSide::Ask, "400", "3");
+        assert_eq!(amended.code, 400);
+        assert!(amended.message().contains("Risk limit exceeded"));
+        assert_eq!(harness.amend(SELLER, ask.id, OrderSide::Ask, "300", "3").code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("10", "3", "7"));
+        let shard = harness.shard(SELLER);
+        assert_eq!(harness.sequencers[shard].open_orders.open_notional(SELLER), Decimal::from(900));
+    }
+
+    #[test]
+    fn test_account_nonce_must_increase_by_one() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 首个 nonce 建立基线，之后必须逐一递增
+        assert_eq!(harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 5).code, 0);
+        let second = harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 6);
+        assert_eq!(second.code, 0);
+
+        // 跳号和重放都被拒绝，且不消耗 nonce
+        let skipped = harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 8);
+        assert_eq!(skipped.code, 409);
+        assert_eq!(skipped.reject_reason, RejectReason::InvalidNonce as i32);
+        let replayed = harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 6);
+        assert_eq!(replayed.code, 409);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "20", "980"));
+
+        // 撤单共用同一序列
+        let stale = harness.submit_cancel(BUYER, second.id as u64, None, Some(6));
+        assert_eq!(stale.code, 409);
+        let cancelled = harness.submit_cancel(BUYER, second.id as u64, None, Some(7));
+        assert_eq!(cancelled.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "10", "990"));
+
+        // 不带 nonce 的请求不受影响
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "1").code, 0);
+        assert_eq!(harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 8).code, 0);
+    }
+
+    #[test]
+    fn test_account_nonce_survives_restart_and_skips_idempotent_retries() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let transfer = |harness: &mut Harness, key: i64, nonce: i64| {
+            harness.transfer_with_key(BUYER, 11, USDT, "100", Some(key), Some(nonce)).code
+        };
+
+        // 重复的幂等请求返回首次响应，不按重放的序号拒绝
+        assert_eq!(transfer(&mut harness, 7, 1), 0);
+        assert_eq!(transfer(&mut harness, 7, 1), 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "0", "900"));
+        assert_eq!(transfer(&mut harness, 8, 1), 409);
+
+        // 最后接受的序号随预写日志恢复，重启后不会重新建立基线
+        harness.restart();
+        assert_eq!(transfer(&mut harness, 9, 1), 409);
+        assert_eq!(transfer(&mut harness, 9, 2), 0);
+        let replayed = harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 2);
+        assert_eq!(replayed.reject_reason, RejectReason::InvalidNonce as i32);
+        assert_eq!(harness.place_with_nonce(BUYER, OrderSide::Bid, "10", "1", 3).code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("800", "10", "790"));
+    }
+
+    #[test]
+    fn test_open_interest_query_reports_resting_book() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "5");
+
+        let open_interest = |harness: &mut Harness, symbol_id: i32| {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = harness.shard(symbol_id);
+            harness.matchers[shard].handle_message(MatchMessage::GetOpenInterest {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap()
+        };
+        // 已配置但还没有订单簿的交易对返回 0
+        let empty = open_interest(&mut harness, SYMBOL_ID);
+        assert_eq!(empty.code, 0);
+        assert_eq!(empty.bid_notional, "0");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "110", "3");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "110", "1");
+        let response = open_interest(&mut harness, SYMBOL_ID);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.bid_quantity, "2");
+        assert_eq!(response.bid_notional, "200");
+        assert_eq!(response.ask_quantity, "2");
+        assert_eq!(response.ask_notional, "220");
+
+        assert_eq!(open_interest(&mut harness, 999).code, 404);
+    }
+
+    #[test]
+    fn test_order_book_snapshot_warms_up_another_node() {
+        let dump = |harness: &mut Harness| {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = harness.shard(SYMBOL_ID);
+            harness.matchers[shard].handle_message(MatchMessage::DumpOrderBook {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                response_sender,
+            });
+            let dumped = response_receiver.try_recv().unwrap();
+            assert_eq!(dumped.code, 0);
+            dumped.snapshot
+        };
+        // 最优价位相同即可，时间戳和序号属于各自的节点
+        let levels = |harness: &mut Harness| {
+            let bbo = harness.bbo();
+            (bbo.best_bid, bbo.best_bid_quantity, bbo.best_ask, bbo.best_ask_quantity)
+        };
+        let balances = |harness: &Harness| {
+            (harness.balance(BUYER, USDT), harness.balance(SELLER, BTC))
+        };
+
+        let mut source = Harness::new();
+        source.deposit(BUYER, USDT, "1000");
+        source.deposit(SELLER, BTC, "5");
+        source.place(SELLER, OrderType::Limit, OrderSide::Ask, "110", "2");
+        source.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        let snapshot = dump(&mut source);
+
+        let mut target = Harness::new();
+        let load = |harness: &mut Harness, symbol_id: i32, snapshot: Vec<u8>| {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = harness.shard(symbol_id);
+            harness.matchers[shard].handle_message(MatchMessage::LoadOrderBook {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id,
+                snapshot,
+                force: false,
+                funded: false,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap().code
+        };
+        assert_eq!(load(&mut target, 999, snapshot.clone()), 404);
+        assert_eq!(load(&mut target, SYMBOL_ID, vec![1, 2, 3]), 400);
+        // 订单的余额没有在排序器上冻结时不能装入
+        assert_eq!(load(&mut target, SYMBOL_ID, snapshot.clone()), 409);
+
+        // 余额不足时不冻结也不装入
+        target.deposit(SELLER, BTC, "5");
+        assert_eq!(target.load_order_book(snapshot.clone(), false), 400);
+        assert_eq!(target.balance(SELLER, BTC), balance("5", "0", "5"));
+        assert_eq!(target.bbo().best_ask, None);
+
+        target.deposit(BUYER, USDT, "1000");
+        assert_eq!(target.load_order_book(snapshot.clone(), false), 0);
+        assert_eq!(levels(&mut target), levels(&mut source));
+        assert_eq!(balances(&target), balances(&source));
+
+        // 已有挂单时需要 force 才能覆盖；快照中的订单ID已由本节点分配时拒绝，冻结的余额都退回
+        assert_eq!(target.load_order_book(snapshot.clone(), false), 409);
+        assert_eq!(target.load_order_book(snapshot, true), 400);
+        assert_eq!(balances(&target), balances(&source));
+
+        // force 覆盖时按撤单解冻被替换的订单，再冻结新装入的订单
+        source.cancel_all(BUYER);
+        source.cancel_all(SELLER);
+        source.place(SELLER, OrderType::Limit, OrderSide::Ask, "120", "1");
+        source.place(BUYER, OrderType::Limit, OrderSide::Bid, "105", "1");
+        let shard = source.shard(SYMBOL_ID);
+        source.matchers[shard].matching_engine.prune_completed_orders();
+        let snapshot = dump(&mut source);
+        assert_eq!(target.load_order_book(snapshot, true), 0);
+        assert_eq!(levels(&mut target), levels(&mut source));
+        assert_eq!(balances(&target), balances(&source));
+        assert_eq!(target.balance(BUYER, USDT), balance("1000", "105", "895"));
+
+        // 重放日志后订单簿仍然存在
+        let match_wal = &target.wal_paths[target.shard_count + target.shard(SYMBOL_ID)];
+        let engine = wal::replay(match_wal).unwrap().matching_engine;
+        let book = engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_best_bid(), Some(Decimal::from(105)));
+        assert_eq!(book.get_best_ask(), Some(Decimal::from(120)));
+    }
+
+    #[test]
+    fn test_placement_mode_prefreeze_versus_margin_check() {
+        // 预冻结：每笔订单冻结所需余额，第二笔超出剩余可用余额被拒绝，撤单后解冻
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let first = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "6");
+        assert_eq!(first.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "600", "400"));
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "5");
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        let cancelled = harness.cancel(BUYER, first.id);
+        assert_eq!(cancelled.refund_amount.as_deref(), Some("600"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        // 保证金模式：不冻结，本单加上未完成订单的占用超过可用余额时拒绝
+        let mut harness = Harness::new();
+        for sequencer in &mut harness.sequencers {
+            sequencer.set_placement_mode(PlacementMode::MarginCheck).unwrap();
+        }
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "5");
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "6").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        let response = harness.validate(BUYER, OrderSide::Bid, "100", "5");
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "5");
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        let second = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "4");
+        assert_eq!(second.code, 0);
+
+        // 撤单不解冻，只释放占用
+        let cancelled = harness.cancel(BUYER, second.id);
+        assert_eq!(cancelled.code, 0);
+        assert_eq!(cancelled.refund_amount.as_deref(), Some("0"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        // 成交时直接从可用余额扣除，剩余 4 BTC 的挂单仍占用 400 USDT
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "2").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("800", "0", "800"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("3", "0", "3"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("200", "0", "200"));
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "4").code, 0);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "1", "1");
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+    }
+
+    #[test]
+    fn test_margin_exposure_guards_debits_and_survives_restart() {
+        let mut harness = Harness::new();
+        for sequencer in &mut harness.sequencers {
+            sequencer.set_placement_mode(PlacementMode::MarginCheck).unwrap();
+        }
+        harness.deposit(BUYER, USDT, "1000");
+        let order = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "6");
+        assert_eq!(order.code, 0);
+
+        // 挂单占用 600 USDT，扣除后可用余额不足以覆盖占用的操作都被拒绝
+        assert_eq!(harness.transfer(BUYER, SELLER, USDT, "500").code, 400);
+        assert_eq!(harness.request_withdrawal(BUYER, USDT, "500").code, 400);
+        assert_eq!(harness.adjust_reserve(BUYER, USDT, "margin", "500", false).code, 400);
+        assert_eq!(harness.admin_adjust(BUYER, USDT, "-500", "manual").code, 400);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.transfer(BUYER, SELLER, USDT, "400").code, 0);
+
+        // 重启后按订单簿重建占用，日志中记录的占用方式同样恢复
+        harness.restart();
+        assert_eq!(harness.transfer(BUYER, SELLER, USDT, "1").code, 400);
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "1", "1");
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+
+        // 有未完成订单时拒绝切换占用方式，撤单后才能切换
+        let shard = harness.shard(BUYER);
+        let switched = harness.sequencers[shard].set_placement_mode(PlacementMode::PrefreezePerOrder);
+        assert!(matches!(switched, Err(BalanceError::InvalidOrder(_))));
+        assert_eq!(harness.cancel(BUYER, order.id).code, 0);
+        harness.sequencers[shard]
+            .set_placement_mode(PlacementMode::PrefreezePerOrder)
+            .unwrap();
+        harness.restart();
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "6").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("600", "600", "0"));
+    }
+
+    #[test]
+    fn test_partially_filled_maker_keeps_unfilled_portion_frozen() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "5");
+
+        // 卖方挂单分两次成交，冻结的 BTC 逐步减少，全部成交后才归零
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "3").code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("5", "3", "2"));
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1").code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("4", "2", "2"));
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2").code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("2", "0", "2"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("300", "0", "300"));
+
+        // 买方挂单同样按成交部分扣除冻结的 USDT
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "90", "4").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "360", "340"));
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "90", "1.5").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("565.0", "225.0", "340"));
+        assert_eq!(harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "90", "0.5").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("520.0", "180.0", "340"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("5.0", "0", "5.0"));
+    }
+
+    #[test]
+    fn test_runtime_symbol_settles_and_delete_waits_for_open_orders() {
+        let mut harness = Harness::new();
+        let eth = harness.management.create_currency("ETH".to_string(), "Ethereum".to_string());
+        let symbol = harness
+            .management
+            .create_symbol("ETH-USDT".to_string(), eth.id, USDT, TradingRules::default())
+            .unwrap();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, eth.id, "5");
+
+        let ask = harness.place_on(symbol.id, SELLER, OrderSide::Ask, "100", "3");
+        assert_eq!(ask.code, 0);
+        assert_eq!(harness.place_on(symbol.id, BUYER, OrderSide::Bid, "100", "2").code, 0);
+        assert_eq!(harness.balance(BUYER, eth.id), balance("2", "0", "2"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("800", "0", "800"));
+        assert_eq!(harness.balance(SELLER, eth.id), balance("3", "1", "2"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("200", "0", "200"));
+
+        let delete_symbol = |harness: &mut Harness| {
+            let (response_sender, mut response_receiver) = oneshot::channel();
+            let shard = harness.shard(symbol.id);
+            harness.matchers[shard].handle_message(MatchMessage::DeleteSymbol {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: symbol.id,
+                response_sender,
+            });
+            response_receiver.try_recv().unwrap().code
+        };
+        // 还有未成交完的卖单，拒绝删除
+        assert_eq!(delete_symbol(&mut harness), 409);
+        assert!(harness.management.get_symbol(symbol.id).is_some());
+
+        let (response_sender, mut response_receiver) = oneshot::channel();
+        let shard = harness.shard(SELLER);
+        harness.sequencers[shard].process_sequencer_message(SequencerMessage::CancelOrder {
+            request_id: uuid::Uuid::new_v4(),
+            symbol_id: symbol.id,
+            account_id: SELLER,
+            order_id: ask.id as u64,
+            client_order_id: None,
+            nonce: None,
+            response_sender,
+        });
+        harness.pump();
+        assert_eq!(response_receiver.try_recv().unwrap().code, 0);
+        assert_eq!(harness.balance(SELLER, eth.id), balance("3", "0", "3"));
+
+        assert_eq!(delete_symbol(&mut harness), 0);
+        assert!(harness.management.get_symbol(symbol.id).is_none());
+        assert_eq!(delete_symbol(&mut harness), 404);
+    }
+
+    #[test]
+    fn test_order_size_limits_rejected_without_freezing() {
+        let mut harness = Harness::new();
+        let trading_rules = TradingRules {
+            min_quantity: rust_decimal::Decimal::from_str_exact("0.1").unwrap(),
+            max_quantity: rust_decimal::Decimal::from(5),
+            max_notional: rust_decimal::Decimal::from(500),
+            ..TradingRules::default()
+        };
+        let symbol = harness
+            .management
+            .create_symbol("BTC-USDT-S".to_string(), BTC, USDT, trading_rules)
+            .unwrap();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "10");
+
+        for (price, quantity, reason) in [
+            ("100", "0.05", RejectReason::InvalidQuantity),
+            ("100", "6", RejectReason::InvalidQuantity),
+            ("101", "5", RejectReason::AboveMaxNotional),
+        ] {
+            let response = harness.place_on(symbol.id, BUYER, OrderSide::Bid, price, quantity);
+            assert_eq!(response.code, 400, "{} x {}", price, quantity);
+            assert_eq!(response.reject_reason(), reason);
+            assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        }
+
+        // 没有报价的市价卖单由撮合引擎按买盘估算成交额：200 x 1 + 100 x 4 超过上限，
+        // 拒绝后解冻已冻结的 BTC
+        let response = harness.place_on(symbol.id, BUYER, OrderSide::Bid, "100", "5");
+        assert_eq!(response.code, 0);
+        let response = harness.submit_on(
+            symbol.id,
+            SELLER,
+            OrderType::Market,
+            OrderSide::Ask,
+            "",
+            "0.1",
+            0,
+            0,
+            false,
+        );
+        assert_eq!(response.code, 0);
+        let response = harness.place_on(symbol.id, BUYER, OrderSide::Bid, "200", "1");
+        assert_eq!(response.code, 0);
+        let response = harness.submit_on(
+            symbol.id,
+            SELLER,
+            OrderType::Market,
+            OrderSide::Ask,
+            "",
+            "5",
+            0,
+            0,
+            false,
+        );
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::AboveMaxNotional);
+        assert_eq!(harness.balance(SELLER, BTC), balance("9.9", "0", "9.9"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_counts_placed_orders() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::metrics::serve(listener));
+
+        // 指标为进程内共享，其他并行测试也会累加计数
+        let placed_before = metrics().orders_placed.get();
+        let trades_before = metrics().trades.get();
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        let scraped = |name: &str| -> u64 {
+            response
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        assert!(scraped("lightning_orders_placed_total") >= placed_before + 2);
+        assert!(scraped("lightning_trades_total") > trades_before);
+        assert!(response.contains("lightning_match_latency_seconds_count"));
+    }
+
+    #[test]
+    fn test_drain_settles_order_enqueued_during_shutdown() {
+        let mut harness = Harness::with_shards(2);
+        let (sequencer_handles, match_handles) = harness.spawn();
+        // 买卖双方在分片 1，手续费需要转发到手续费账户所在的分片 0
+        let (buyer, seller) = (11, 21);
+
+        let send = |harness: &Harness, account_id: i32, message: SequencerMessage| {
+            harness.sequencer_senders[harness.shard(account_id)].send(message).unwrap();
+        };
+        let limit_order = |account_id: i32, side: OrderSide| {
+            let (response_sender, _response_receiver) = oneshot::channel();
+            SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: "100".to_string(),
+                quantity: "1".to_string(),
+                taker_rate: 1000,
+                maker_rate: 1000,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::none(),
+                response_sender,
+            }
+        };
+        for (account_id, currency_id, amount) in [(seller, BTC, "1"), (buyer, USDT, "1000")] {
+            let (response_sender, _response_receiver) = oneshot::channel();
+            send(
+                &harness,
+                account_id,
+                SequencerMessage::Increase {
+                    request_id: uuid::Uuid::new_v4(),
+                    account_id,
+                    currency_id,
+                    amount: amount.to_string(),
+                    idempotency_key: None,
+                    response_sender,
+                },
+            );
+        }
+
+        // 两笔订单入队后立即停机，撮合产生的结算和手续费转发都要在退出前完成
+        send(&harness, seller, limit_order(seller, OrderSide::Ask));
+        send(&harness, buyer, limit_order(buyer, OrderSide::Bid));
+        drain_processors(
+            std::mem::take(&mut harness.sequencer_senders),
+            std::mem::take(&mut harness.match_senders),
+            std::mem::take(&mut harness.trade_execution_senders),
+            sequencer_handles,
+            match_handles,
+        );
+
+        // 处理器已退出，从预写日志重放余额
+        let total = |account_id: i32, currency_id: i32| {
+            let shard = harness.shard(account_id);
+            let state = wal::replay(&harness.wal_paths[shard]).unwrap();
+            let response = state.balance_manager.handle_get_account(account_id, Some(currency_id));
+            rust_decimal::Decimal::from_str_exact(&response.data[&currency_id].value).unwrap()
+        };
+        assert_eq!(total(buyer, BTC), rust_decimal::Decimal::ONE);
+        assert_eq!(total(seller, USDT), rust_decimal::Decimal::new(999, 1));
+        assert_eq!(total(FEE_ACCOUNT_ID, USDT), rust_decimal::Decimal::new(2, 1));
+    }
+
+    #[test]
+    fn test_zero_fee_trade_settles_gross_amounts() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "0", "900"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("100", "0", "100"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0", "0", "0"));
+    }
+
+    #[test]
+    fn test_maker_taker_fees_collected_into_fee_account() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+
+        // maker 费率 0.05%，taker 费率 0.1%
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 1000, 500);
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 500);
+
+        // 买方 taker 手续费 0.1 USDT，卖方 maker 手续费 0.05 USDT
+        assert_eq!(harness.balance(BUYER, USDT), balance("899.9", "0.0", "899.9"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("99.95", "0", "99.95"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.15", "0", "0.15"));
+    }
+
+    #[test]
+    fn test_maker_rebate_credits_maker_and_debits_fee_account() {
+        let mut harness = Harness::new();
+        for matcher in &mut harness.matchers {
+            matcher.set_max_maker_rebate(500);
+        }
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+
+        // maker 费率 -0.09% 超过上限，按 -0.05% 返佣；taker 不收手续费时手续费账户为负
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 0, -900);
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 0, 0);
+        assert_eq!(harness.balance(SELLER, USDT), balance("100.05", "0", "100.05"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "0", "900"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("-0.05", "0", "-0.05"));
+
+        // maker 返佣 0.02%，taker 费率 0.1%：手续费账户净收 0.08，USDT 总量不变
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 1000, -200);
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, -200);
+        assert_eq!(harness.balance(SELLER, USDT), balance("200.07", "0", "200.07"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("799.9", "0.0", "799.9"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.03", "0", "0.03"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+
+        // 未设置上限时负费率按 0 处理
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "100");
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 0, -200);
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 0, 0);
+        assert_eq!(harness.balance(SELLER, USDT), balance("100", "0", "100"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0", "0", "0"));
+    }
+
+    #[test]
+    fn test_bid_freezes_worst_case_fee_and_pays_it_in_full() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "100");
+
+        // 买单按较高的费率预留手续费，只够支付成交金额时拒绝下单
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 1000, 1000);
+        let response =
+            harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 500);
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        assert_eq!(harness.balance(BUYER, USDT), balance("100", "0", "100"));
+
+        // 冻结全部余额的买单成交后全额支付手续费
+        harness.deposit(BUYER, USDT, "0.1");
+        let response =
+            harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 500);
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("0.0", "0.0", "0.0"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("99.9", "0", "99.9"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.2", "0", "0.2"));
+    }
+
+    #[test]
+    fn test_maker_bid_releases_unused_fee_reserve() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 挂单按 taker 费率 0.2% 预留 0.4，作为 maker 成交只收取 0.05%
+        let order =
+            harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2", 2000, 500);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "200.4", "799.6"));
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        assert_eq!(harness.balance(BUYER, USDT), balance("899.95", "100.2", "799.75"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.05", "0", "0.05"));
+
+        // 撤销剩余部分时连同预留一起解冻
+        harness.cancel(BUYER, order.id);
+        assert_eq!(harness.balance(BUYER, USDT), balance("899.95", "0.0", "899.95"));
+
+        // 重放日志得到相同的余额
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        let usdt = &replayed.accounts[&BUYER].balances[&USDT];
+        assert_eq!(usdt.available.to_string(), "899.95");
+        assert_eq!(usdt.frozen, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_get_trades_returns_newest_first() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "0.5");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "101", "0.5");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "0.2");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "101", "0.8");
+
+        let response = harness.trades(0);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.symbol_id, SYMBOL_ID);
+        let trades: Vec<_> = response
+            .trades
+            .iter()
+            .map(|trade| (trade.price.as_str(), trade.quantity.as_str()))
+            .collect();
+        assert_eq!(trades, vec![("101", "0.5"), ("100", "0.3"), ("100", "0.2")]);
+        assert!(response
+            .trades
+            .iter()
+            .all(|trade| trade.taker_side == crate::models::schema::Side::Bid as i32));
+        assert!(response.trades[0].trade_id > response.trades[2].trade_id);
+
+        assert_eq!(harness.trades(1).trades.len(), 1);
+    }
+
+    #[test]
+    fn test_trade_settles_on_account_shards_with_custom_shard_count() {
+        // 3 个分片：买方 10 -> 分片 1，卖方 20 -> 分片 2，手续费账户 -1 -> 分片 1
+        let mut harness = Harness::with_shards(3);
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place_with_fees(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1", 1000, 1000);
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 1000);
+
+        assert_eq!(harness.balance_on_shard(1, BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance_on_shard(1, BUYER, USDT), balance("899.9", "0.0", "899.9"));
+        assert_eq!(harness.balance_on_shard(2, SELLER, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance_on_shard(2, SELLER, USDT), balance("99.9", "0", "99.9"));
+        assert_eq!(harness.balance_on_shard(1, FEE_ACCOUNT_ID, USDT), balance("0.2", "0", "0.2"));
+
+        // 其他分片不会收到不属于自己的账户结算
+        for shard in [0, 2] {
+            assert_eq!(harness.balance_on_shard(shard, BUYER, BTC), balance("0", "0", "0"));
+        }
+        for shard in [0, 1] {
+            assert_eq!(harness.balance_on_shard(shard, SELLER, USDT), balance("0", "0", "0"));
+            assert_eq!(harness.balance_on_shard(shard, SELLER, BTC), balance("0", "0", "0"));
+        }
+        for shard in [0, 2] {
+            assert_eq!(harness.balance_on_shard(shard, FEE_ACCOUNT_ID, USDT), balance("0", "0", "0"));
+        }
+    }
+
+    #[test]
+    fn test_taker_sell_settles_makers_on_both_shards() {
+        // 2 个分片：买方 10 -> 分片 0，买方 11 和卖方 21 -> 分片 1
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(11, USDT, "1000");
+        harness.deposit(21, BTC, "2");
+
+        harness.place_with_fees(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 1000);
+        harness.place_with_fees(11, OrderType::Limit, OrderSide::Bid, "100", "1", 1000, 1000);
+        let response =
+            harness.place_with_fees(21, OrderType::Limit, OrderSide::Ask, "100", "2", 1000, 1000);
+        assert_eq!(response.code, 0);
+
+        assert_eq!(harness.balance_on_shard(0, BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance_on_shard(0, BUYER, USDT), balance("899.9", "0.0", "899.9"));
+        assert_eq!(harness.balance_on_shard(1, 11, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("899.9", "0.0", "899.9"));
+        assert_eq!(harness.balance_on_shard(1, 21, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance_on_shard(1, 21, USDT), balance("199.8", "0", "199.8"));
+        assert_eq!(harness.balance_on_shard(1, FEE_ACCOUNT_ID, USDT), balance("0.4", "0", "0.4"));
+        assert_eq!(harness.balance_on_shard(1, BUYER, BTC), balance("0", "0", "0"));
+    }
+
+    #[test]
+    fn test_settle_account_applies_only_on_owning_shard() {
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(11, USDT, "1000");
+        harness.sequencers[1]
+            .balance_manager
+            .freeze(11, USDT, Decimal::from(100))
+            .unwrap();
+
+        let settle = || TradeExecutionMessage::SettleAccount {
+            account_id: 11,
+            symbol_id: SYMBOL_ID,
+            trade_id: 0,
+            taker: false,
+            deduct_currency_id: USDT,
+            deduct_amount: Decimal::from(100),
+            add_currency_id: BTC,
+            add_amount: Decimal::ONE,
+            fee_currency_id: BTC,
+            fee_amount: Decimal::new(1, 3),
+            fee_reserve: Decimal::ZERO,
+            span: tracing::Span::none(),
+            ack: None,
+        };
+        // 账户 11 属于分片 1，分片 0 收到的结算消息被忽略
+        harness.sequencers[0].process_trade_execution_message(settle());
+        assert_eq!(harness.balance_on_shard(0, 11, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("1000", "100", "900"));
+
+        harness.sequencers[1].process_trade_execution_message(settle());
+        harness.pump();
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("900", "0", "900"));
+        assert_eq!(harness.balance_on_shard(1, 11, BTC), balance("0.999", "0", "0.999"));
+        assert_eq!(harness.balance_on_shard(1, FEE_ACCOUNT_ID, BTC), balance("0.001", "0", "0.001"));
+    }
+
+    #[test]
+    fn test_duplicate_settlement_is_applied_once() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+
+        let settle = |trade_id| TradeExecutionMessage::SettleAccount {
+            account_id: BUYER,
+            symbol_id: SYMBOL_ID,
+            trade_id,
+            taker: false,
+            deduct_currency_id: USDT,
+            deduct_amount: Decimal::from(100),
+            add_currency_id: BTC,
+            add_amount: Decimal::ONE,
+            fee_currency_id: BTC,
+            fee_amount: Decimal::ZERO,
+            fee_reserve: Decimal::ZERO,
+            span: tracing::Span::none(),
+            ack: None,
+        };
+        // 同一笔成交的结算投递两次，只记账一次
+        harness.sequencers[0].process_trade_execution_message(settle(7));
+        harness.sequencers[0].process_trade_execution_message(settle(7));
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "100", "800"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+
+        // 重放日志后去重集合随之恢复，之后再投递同一笔仍然跳过，新的成交照常结算
+        let mut replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        assert_eq!(replayed.handle_get_account(BUYER, Some(BTC)).data[&BTC].value, "1");
+        assert!(!replayed.record_settlement(SettlementKey {
+            symbol_id: SYMBOL_ID,
+            trade_id: 7,
+            account_id: BUYER,
+            taker: false,
+        }));
+        harness.sequencers[0].process_trade_execution_message(settle(8));
+        assert_eq!(harness.balance(BUYER, USDT), balance("800", "0", "800"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+    }
+
+    #[test]
+    fn test_self_trade_settles_both_legs() {
+        // 同一账户的买单被自己的卖单成交，maker 和 taker 两条结算都要记账
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(BUYER, BTC, "10");
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1").code, 0);
+        assert_eq!(harness.place(BUYER, OrderType::Limit, OrderSide::Ask, "100", "1").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("10", "0", "10"));
+
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        assert_eq!(replayed.accounts[&BUYER].balances[&USDT].total, Decimal::from(1000));
+        assert_eq!(replayed.accounts[&BUYER].balances[&BTC].total, Decimal::from(10));
+    }
+
+    #[test]
+    fn test_refused_settlement_is_not_recorded_as_settled() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+
+        let settle = || TradeExecutionMessage::SettleAccount {
+            account_id: BUYER,
+            symbol_id: SYMBOL_ID,
+            trade_id: 7,
+            taker: false,
+            deduct_currency_id: USDT,
+            deduct_amount: Decimal::from(200),
+            add_currency_id: BTC,
+            add_amount: Decimal::from(2),
+            fee_currency_id: BTC,
+            fee_amount: Decimal::ZERO,
+            fee_reserve: Decimal::ZERO,
+            span: tracing::Span::none(),
+            ack: None,
+        };
+        let key = SettlementKey {
+            symbol_id: SYMBOL_ID,
+            trade_id: 7,
+            account_id: BUYER,
+            taker: false,
+        };
+        // 冻结余额不足时拒绝结算，不记为已结算
+        harness.sequencers[0].process_trade_execution_message(settle());
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "100", "900"));
+        assert!(!harness.sequencers[0].balance_manager.is_settled(&key));
+
+        // 冻结足够后再次投递同一笔成交照常结算，重放日志得到相同的结果
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        harness.sequencers[0].process_trade_execution_message(settle());
+        assert_eq!(harness.balance(BUYER, USDT), balance("800", "0", "800"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+        assert!(harness.sequencers[0].balance_manager.is_settled(&key));
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        assert!(replayed.is_settled(&key));
+        assert_eq!(replayed.accounts[&BUYER].balances[&USDT].total, Decimal::from(800));
+    }
+
+    #[test]
+    fn test_transfer_within_shard() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        let response = harness.transfer(BUYER, SELLER, USDT, "300");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "0", "700"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("300", "0", "300"));
+
+        // 转给自己不改变余额
+        assert_eq!(harness.transfer(BUYER, BUYER, USDT, "700").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "0", "700"));
+
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        let response = replayed.handle_get_account(SELLER, Some(USDT));
+        assert_eq!(response.data[&USDT].available, "300");
+    }
+
+    #[test]
+    fn test_transfer_across_shards() {
+        // 账户 10 -> 分片 0，账户 11 -> 分片 1
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "1000");
+
+        let response = harness.transfer(BUYER, 11, USDT, "250.5");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance_on_shard(0, BUYER, USDT), balance("749.5", "0", "749.5"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("250.5", "0", "250.5"));
+        assert_eq!(harness.balance_on_shard(0, 11, USDT), balance("0", "0", "0"));
+
+        // 两个分片的日志分别重放出各自账户的余额
+        let replayed = wal::replay(&harness.wal_paths[1]).unwrap().balance_manager;
+        let response = replayed.handle_get_account(11, Some(USDT));
+        assert_eq!(response.data[&USDT].available, "250.5");
+    }
+
+    #[test]
+    fn test_transfer_insufficient_balance_leaves_both_accounts_untouched() {
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "100");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "5");
+
+        // 可用只有 50，冻结部分不能划转
+        for to_account_id in [SELLER, 11] {
+            let response = harness.transfer(BUYER, to_account_id, USDT, "60");
+            assert_eq!(response.code, 400);
+            assert_eq!(response.message.as_deref(), Some("Insufficient balance"));
+        }
+        assert_eq!(harness.balance_on_shard(0, BUYER, USDT), balance("100", "50", "50"));
+        assert_eq!(harness.balance_on_shard(0, SELLER, USDT), balance("0", "0", "0"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("0", "0", "0"));
+
+        assert_eq!(harness.transfer(BUYER, 11, USDT, "abc").code, 400);
+    }
+
+    #[test]
+    fn test_transfer_to_full_shard_queue_is_refunded() {
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 转入分片的成交回调队列已满时不阻塞，退回转出账户
+        let (full_sender, _full_receiver) = crossbeam_channel::bounded(1);
+        full_sender.send(TradeExecutionMessage::Drain).unwrap();
+        harness.sequencers[0].trade_execution_senders[1] = full_sender;
+        let response = harness.transfer_with_key(BUYER, 11, USDT, "100", Some(7), None);
+        assert_eq!(response.code, 503);
+        assert_eq!(harness.balance_on_shard(0, BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("0", "0", "0"));
+
+        // 退回记录与转出记录配对，重启时没有需要补记的划转
+        let states: Vec<_> = harness.wal_paths[..2]
+            .iter()
+            .map(|path| wal::replay(path).unwrap())
+            .collect();
+        assert!(wal::unmatched_transfers(&states).is_empty());
+        let usdt = &states[0].balance_manager.accounts[&BUYER].balances[&USDT];
+        assert_eq!(usdt.available.to_string(), "1000");
+    }
+
+    #[test]
+    fn test_interrupted_transfer_is_completed_after_restart() {
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 转出分片已扣减，转入消息还在队列中时停机
+        let (response_sender, _response_receiver) = oneshot::channel();
+        harness.sequencers[0].process_sequencer_message(SequencerMessage::Transfer {
+            request_id: uuid::Uuid::new_v4(),
+            from_account_id: BUYER,
+            to_account_id: 11,
+            currency_id: USDT,
+            amount: "100".to_string(),
+            idempotency_key: None,
+            nonce: None,
+            response_sender,
+        });
+        while harness.sequencers[1].trade_execution_receiver.try_recv().is_ok() {}
+        harness.restart();
+
+        let states: Vec<_> = harness.wal_paths[..2]
+            .iter()
+            .map(|path| wal::replay(path).unwrap())
+            .collect();
+        let pending = wal::unmatched_transfers(&states);
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].from_account_id, pending[0].to_account_id), (BUYER, 11));
+        harness.sequencers[1].complete_transfer(pending[0].clone());
+        assert_eq!(harness.balance_on_shard(0, BUYER, USDT), balance("900", "0", "900"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("100", "0", "100"));
+
+        // 补记的入账写入日志，再次重启不会重复入账
+        let states: Vec<_> = harness.wal_paths[..2]
+            .iter()
+            .map(|path| wal::replay(path).unwrap())
+            .collect();
+        assert!(wal::unmatched_transfers(&states).is_empty());
+    }
+
+    #[test]
+    fn test_withdrawal_hold_is_confirmed_or_cancelled_through_the_sequencer() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        let confirmed = harness.request_withdrawal(BUYER, USDT, "300");
+        assert_eq!(confirmed.code, 0);
+        let cancelled = harness.request_withdrawal(BUYER, USDT, "200");
+        assert_eq!(cancelled.code, 0);
+        assert_ne!(confirmed.hold_id, cancelled.hold_id);
+        assert_eq!(cancelled.data.unwrap().frozen, "500");
+        assert_eq!(harness.request_withdrawal(BUYER, USDT, "501").code, 400);
+
+        // 冻结单只能由所属账户确认或取消
+        let response = harness.finish_withdrawal(SELLER, confirmed.hold_id, true);
+        assert_eq!(response.code, 404);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "500", "500"));
+
+        let response = harness.finish_withdrawal(BUYER, confirmed.hold_id, true);
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "200", "500"));
+        assert_eq!(harness.finish_withdrawal(BUYER, confirmed.hold_id, false).code, 404);
+
+        let response = harness.finish_withdrawal(BUYER, cancelled.hold_id, false);
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "0", "700"));
+    }
+
+    #[test]
+    fn test_withdrawal_holds_survive_restart() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let confirmed = harness.request_withdrawal(BUYER, USDT, "300").hold_id;
+        let pending = harness.request_withdrawal(BUYER, USDT, "200").hold_id;
+        harness.finish_withdrawal(BUYER, confirmed, true);
+
+        // 重放后未完成的冻结单保持冻结，冻结单ID与重启前一致
+        harness.restart();
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "200", "500"));
+        assert_eq!(harness.finish_withdrawal(BUYER, confirmed, false).code, 404);
+        assert_eq!(harness.finish_withdrawal(BUYER, pending, false).code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("700", "0", "700"));
+
+        // 重启后新的冻结单不会复用已分配的ID
+        let next = harness.request_withdrawal(BUYER, USDT, "100").hold_id;
+        assert!(next > pending);
+    }
+
+    #[test]
+    fn test_reserved_balance_is_logged_and_unavailable_for_orders() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        let response = harness.adjust_reserve(BUYER, USDT, "margin", "800", false);
+        assert_eq!(response.code, 0);
+        let data = response.data.unwrap();
+        assert_eq!((data.value.as_str(), data.available.as_str()), ("1000", "200"));
+        assert_eq!(data.reserved.get("margin").map(String::as_str), Some("800"));
+
+        // 保留余额不能用于下单
+        let order = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "3");
+        assert_ne!(order.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "200"));
+
+        // 退回超过保留余额或不存在的用途时不修改
+        assert_eq!(harness.adjust_reserve(BUYER, USDT, "margin", "801", true).code, 400);
+        assert_eq!(harness.adjust_reserve(BUYER, USDT, "staking", "1", true).code, 400);
+        assert_eq!(harness.adjust_reserve(BUYER, USDT, "margin", "300", true).code, 0);
+
+        // 重放日志后保留余额不变
+        harness.restart();
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "500"));
+        let account = harness.account(BUYER);
+        let usdt = &account.data[&USDT];
+        assert_eq!(usdt.reserved.get("margin").map(String::as_str), Some("500"));
+        let order = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "3");
+        assert_eq!(order.code, 0);
+    }
+
+    #[test]
+    fn test_settlement_larger_than_frozen_is_refused() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+
+        let result = harness.sequencers[0].settle_account_balance(
+            None,
+            BUYER,
+            USDT,
+            Decimal::from(150),
+            BTC,
+            Decimal::new(15, 1),
+            USDT,
+            Decimal::ONE,
+            Decimal::ZERO,
+        );
+        assert!(matches!(result, Err(BalanceError::InsufficientBalance)));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "100", "900"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0", "0", "0"));
+
+        // 重放日志同样拒绝这笔结算
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        let response = replayed.handle_get_account(BUYER, Some(USDT));
+        assert_eq!(response.data[&USDT].frozen, "100");
+        assert_eq!(response.data[&USDT].value, "1000");
+    }
+
+    #[test]
+    fn test_place_order_rejected_when_matcher_queue_full() {
+        let mut harness = Harness::build(1, Some(1));
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 占满撮合队列
+        let (response_sender, _response_receiver) = oneshot::channel();
+        harness.sequencers[0].match_senders[0]
+            .try_send(MatchMessage::GetOrderBook {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                levels: 1,
+                bucket: None,
+                response_sender,
+            })
+            .unwrap();
+
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 503);
+        assert_eq!(response.message.as_deref(), Some(SERVER_BUSY_MESSAGE));
+        assert_eq!(response.reject_reason(), RejectReason::ServerBusy);
+        // 被拒绝的订单不占用冻结余额
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        // 队列消费后可以正常下单
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "100", "900"));
+    }
+
+    #[test]
+    fn test_requests_rejected_when_matcher_stopped() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        // 撮合线程退出，撮合队列的接收端被释放
+        harness.matchers.clear();
+
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 500);
+        assert_eq!(response.message.as_deref(), Some(MATCHER_UNAVAILABLE_MESSAGE));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        let (response_sender, mut response_receiver) = oneshot::channel();
+        harness.sequencers[0].process_sequencer_message(SequencerMessage::CancelOrder {
+            request_id: uuid::Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: BUYER,
+            order_id: 1,
+            client_order_id: None,
+            nonce: None,
+            response_sender,
+        });
+        let response = response_receiver.try_recv().unwrap();
+        assert_eq!(response.code, 500);
+
+        assert_eq!(harness.cancel_all(BUYER).code, 500);
+    }
+
+    #[test]
+    fn test_rejected_post_only_releases_frozen_balance() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        let response = harness.place_post_only(BUYER, OrderSide::Bid, "100", "1");
+
+        assert_eq!(response.code, 0);
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Order cancelled: post-only order would take liquidity")
+        );
+        assert_eq!(response.reject_reason(), RejectReason::PostOnlyCross);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "1", "0"));
+    }
+
+    #[test]
+    fn test_place_order_responses_carry_reject_reasons() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "100");
+        harness.deposit(BUYER, BTC, "1");
+
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.reject_reason(), RejectReason::NoReject);
+        assert_eq!(response.reason_code, "");
+
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::InsufficientBalance);
+        assert_eq!(response.reason_code, "INSUFFICIENT_BALANCE");
+
+        let response = harness.place_on(999, BUYER, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 404);
+        assert_eq!(response.reject_reason(), RejectReason::UnknownSymbol);
+
+        // 对手方只有自己的买单，开启自成交保护后 taker 被撤销
+        for matcher in &mut harness.matchers {
+            matcher
+                .matching_engine
+                .set_self_trade_prevention(crate::matching::SelfTradePrevention::CancelTaker);
+        }
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.reject_reason(), RejectReason::SelfTrade);
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+    }
+
+    #[test]
+    fn test_get_ticker_reports_last_price_and_24h_stats() {
+        let mut harness = Harness::new();
+        assert_eq!(harness.ticker().code, 404);
+
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "0.5");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "102", "0.5");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "102", "0.8");
+
+        let response = harness.ticker();
+        assert_eq!(response.code, 0);
+        assert_eq!(response.last_price.as_deref(), Some("102"));
+        assert_eq!(response.open.as_deref(), Some("100"));
+        assert_eq!(response.high.as_deref(), Some("102"));
+        assert_eq!(response.low.as_deref(), Some("100"));
+        assert_eq!(response.volume, "0.8");
+    }
+
+    #[test]
+    fn test_get_open_orders_lists_resting_orders() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "101", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+
+        let response = harness.open_orders(SELLER);
+        assert_eq!(response.code, 0);
+        let orders: Vec<_> = response
+            .orders
+            .iter()
+            .map(|order| (order.price.as_str(), order.side))
+            .collect();
+        assert_eq!(orders, vec![("101", crate::models::schema::Side::Ask as i32)]);
+        assert!(harness.open_orders(BUYER).orders.is_empty());
+    }
+
+    #[test]
+    fn test_get_order_reports_fills_and_distinguishes_pruned_orders() {
+        let mut harness = Harness::new();
+        // 已完成的订单不保留，成交后立即清理
+        let shard = harness.shard(SYMBOL_ID);
+        harness.matchers[shard].matching_engine.set_completed_order_retention(0);
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+
+        let ask = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "2");
+        let bid = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "0.5");
+
+        let response = harness.get_order(ask.id);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.status, crate::models::schema::OrderStatus::Partial as i32);
+        assert_eq!(response.remaining_quantity.as_deref(), Some("1.5"));
+        let order = response.order.unwrap();
+        assert_eq!(order.filled_quantity, "0.5");
+        assert_eq!(order.quantity, "2");
+        assert!(order.created_at > 0);
+
+        // 已完成并被清理的订单与从未存在的订单返回不同的状态码
+        assert_eq!(harness.get_order(bid.id).code, 410);
+        assert_eq!(harness.get_order(ask.id + 100).code, 404);
+    }
+
+    #[test]
+    fn test_cancel_all_orders_unfreezes_account_funds() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "4");
+        harness.deposit(BUYER, USDT, "1000");
+
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "101", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "102", "2");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "99", "2");
+        assert_eq!(harness.balance(SELLER, BTC), balance("4", "3", "1"));
+
+        let response = harness.cancel_all(SELLER);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.order_ids.len(), 2);
+        assert_eq!(harness.balance(SELLER, BTC), balance("4", "0", "4"));
+
+        // 其他账户的挂单和冻结余额不受影响
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "198", "802"));
+        assert_eq!(harness.open_orders(BUYER).orders.len(), 1);
+        assert!(harness.open_orders(SELLER).orders.is_empty());
+    }
+
+    #[test]
+    fn test_expired_order_is_cancelled_and_unfrozen() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        let expires_at = now_millis() + 50;
+        let response = harness.place_expiring(BUYER, OrderSide::Bid, "100", "2", expires_at);
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "200", "800"));
+        let orders = harness.open_orders(BUYER).orders;
+        assert_eq!(orders[0].expires_at, Some(expires_at as i64));
+
+        // 到期前扫描不影响挂单
+        harness.expire_at(expires_at - 1);
+        assert_eq!(harness.open_orders(BUYER).orders.len(), 1);
+
+        harness.expire_at(expires_at);
+        assert!(harness.open_orders(BUYER).orders.is_empty());
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        // 重放撮合日志时按记录的扫描时间撤销
+        let match_wal = &harness.wal_paths[harness.shard_count + harness.shard(SYMBOL_ID)];
+        let engine = wal::replay(match_wal).unwrap().matching_engine;
+        assert!(engine.get_open_orders(BUYER, SYMBOL_ID).is_empty());
+    }
+
+    #[test]
+    fn test_order_filled_before_expiry_is_not_reaped() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "3");
+        harness.deposit(BUYER, USDT, "1000");
+
+        let expires_at = now_millis() + 50;
+        harness.place_expiring(SELLER, OrderSide::Ask, "100", "1", expires_at);
+        harness.place_expiring(SELLER, OrderSide::Ask, "101", "2", expires_at);
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "101", "2");
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "1", "0"));
+
+        // 已成交的订单不再撤销，部分成交的订单只解冻剩余部分
+        harness.expire_at(expires_at);
+        assert!(harness.open_orders(SELLER).orders.is_empty());
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("201", "0", "201"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+    }
+
+    #[test]
+    fn test_invalid_expiry_is_rejected_before_freezing() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+
+        let response = harness.place_expiring(BUYER, OrderSide::Bid, "100", "1", now_millis() - 1);
+        assert_eq!(response.code, 400);
+        assert!(response.message.unwrap().contains("Expiry must be in the future"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert!(harness.open_orders(BUYER).orders.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_order_reports_refund_amount() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "1000");
+
+        // 部分成交后撤销，退还剩余数量对应的报价金额
+        let bid = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2.5");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        let response = harness.cancel(BUYER, bid.id);
+        assert_eq!(response.code, 0);
+        assert_eq!(response.cancelled_quantity.as_deref(), Some("1.5"));
+        assert_eq!(response.refund_amount.as_deref(), Some("150.0"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "0.0", "900.0"));
+
+        // 卖单退还 base 数量
+        harness.deposit(SELLER, BTC, "1");
+        let ask = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "120", "0.4");
+        let response = harness.cancel(SELLER, ask.id);
+        assert_eq!(response.refund_amount.as_deref(), Some("0.4"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0.0", "1.0"));
+    }
+
+    #[test]
+    fn test_currency_scale_normalizes_balances_and_survives_replay() {
+        let mut harness = Harness::new();
+        harness.management.set_currency_scale(USDT, Some(2)).unwrap();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "1");
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000.00", "0.00", "1000.00"));
+
+        // 冻结金额超过币种精度时按精度舍入，撤单后全部解冻
+        let rounded = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100.001", "1");
+        assert_eq!(rounded.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000.00", "100.00", "900.00"));
+        assert_eq!(harness.cancel(BUYER, rounded.id).code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000.00", "0.00", "1000.00"));
+
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2.5");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        assert_eq!(harness.balance(BUYER, USDT), balance("900.00", "150.00", "750.00"));
+
+        let replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        let response = replayed.handle_get_account(BUYER, Some(USDT));
+        assert_eq!(response.data[&USDT].value, "900.00");
+        assert_eq!(response.data[&USDT].frozen, "150.00");
+        assert_eq!(response.data[&USDT].available, "750.00");
+    }
+
+    #[test]
+    fn test_get_account_uses_currency_display_scale() {
+        let mut harness = Harness::new();
+        harness.management.set_currency_scale(USDT, Some(6)).unwrap();
+        harness.management.set_currency_display_scale(USDT, Some(2)).unwrap();
+        harness.deposit(BUYER, USDT, "100.123456");
+        harness.deposit(BUYER, BTC, "1.5");
+
+        let response = harness.account(BUYER);
+        assert_eq!(response.data[&USDT].value, "100.12");
+        assert_eq!(response.data[&USDT].available, "100.12");
+        assert_eq!(response.data[&BTC].value, "1.5");
+
+        // 修改显示精度后下一次查询生效，余额本身不变
+        harness.management.set_currency_display_scale(USDT, Some(4)).unwrap();
+        assert_eq!(harness.account(BUYER).data[&USDT].value, "100.1234");
+        harness.management.set_currency_display_scale(USDT, None).unwrap();
+        assert_eq!(harness.account(BUYER).data[&USDT].value, "100.123456");
+    }
+
+    #[test]
+    fn test_floor_to_exchange_rounding_conserves_quote() {
+        let mut harness = Harness::new();
+        harness.management.set_currency_scale(USDT, Some(2)).unwrap();
+        harness.matchers[0]
+            .matching_engine
+            .set_rounding_policy(RoundingPolicy::FloorToExchange);
+        harness.deposit(BUYER, USDT, "10");
+        harness.deposit(SELLER, BTC, "10");
+
+        // 两笔 0.335 的成交：taker 买方按汇总金额支付 0.67，卖方逐笔向下取整共收到 0.66
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "0.335", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "0.335", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "0.335", "2");
+        assert_eq!(harness.balance(BUYER, USDT), balance("9.33", "0.00", "9.33"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("0.66", "0.00", "0.66"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.01", "0.00", "0.01"));
+
+        // maker 买方逐笔支付 0.34，taker 卖方逐笔向下取整收到 0.33
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "0.335", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "0.335", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "0.335", "2");
+        assert_eq!(harness.balance(BUYER, USDT), balance("8.65", "0.00", "8.65"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("1.32", "0.00", "1.32"));
+        assert_eq!(harness.balance(FEE_ACCOUNT_ID, USDT), balance("0.03", "0.00", "0.03"));
+    }
+
+    #[test]
+    fn test_amend_freezes_only_the_additional_requirement() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "200");
+        let resting = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+        assert_eq!(harness.balance(BUYER, USDT), balance("200", "200", "0"));
+
+        // 可用余额为 0 时减量改单不需要冻结，释放减少的部分
+        let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, "100", "1");
+        assert_eq!(amended.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("200", "100", "100"));
+
+        // 增量只冻结差额，正好用完可用余额
+        let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, "99", "2");
+        assert_eq!(amended.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("200", "198", "2"));
+
+        // 差额超过可用余额时拒绝，原订单和冻结保持不变
+        let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, "100", "2.1");
+        assert_eq!(amended.code, 400);
+        assert_eq!(harness.balance(BUYER, USDT), balance("200", "198", "2"));
+        let orders = harness.open_orders(BUYER).orders;
+        assert_eq!((orders[0].price.as_str(), orders[0].quantity.as_str()), ("99", "2"));
+    }
+
+    #[test]
+    fn test_amend_violating_trading_rules_is_rejected() {
+        let mut harness = Harness::new();
+        let trading_rules = TradingRules {
+            price_tick: Decimal::ONE,
+            quantity_step: Decimal::ONE,
+            min_notional: Decimal::from(100),
+            ..TradingRules::default()
+        };
+        harness.management.update_symbol(SYMBOL_ID, None, None, None, Some(trading_rules)).unwrap();
+        harness.deposit(BUYER, USDT, "10000");
+        let resting = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+        assert_eq!(resting.code, 0);
+
+        // 与下单相同的价格、数量和最小成交额规则，拒绝时订单和冻结保持不变
+        for (price, quantity) in [("99.5", "2"), ("100", "50.5"), ("49", "2")] {
+            let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, price, quantity);
+            assert_eq!(amended.code, 400, "{} x {}", price, quantity);
+            assert_eq!(harness.balance(BUYER, USDT), balance("10000", "200", "9800"));
+        }
+        let orders = harness.open_orders(BUYER).orders;
+        assert_eq!((orders[0].price.as_str(), orders[0].quantity.as_str()), ("100", "2"));
+
+        let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, "99", "3");
+        assert_eq!(amended.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("10000", "297", "9703"));
+    }
+
+    #[test]
+    fn test_amend_above_order_size_limits_is_rejected() {
+        let mut harness = Harness::new();
+        let trading_rules = TradingRules {
+            max_quantity: Decimal::from(10),
+            max_notional: Decimal::from(1000),
+            ..TradingRules::default()
+        };
+        harness.management.update_symbol(SYMBOL_ID, None, None, None, Some(trading_rules)).unwrap();
+        harness.deposit(BUYER, USDT, "10000");
+        let resting = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "90", "1");
+        assert_eq!(resting.code, 0);
+
+        // 先下小单再改大：超过单笔最大数量或最大成交额都拒绝
+        for (price, quantity) in [("90", "50.5"), ("99", "10.2")] {
+            let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, price, quantity);
+            assert_eq!(amended.code, 400, "{} x {}", price, quantity);
+            assert_eq!(harness.balance(BUYER, USDT), balance("10000", "90", "9910"));
+        }
+        let orders = harness.open_orders(BUYER).orders;
+        assert_eq!((orders[0].price.as_str(), orders[0].quantity.as_str()), ("90", "1"));
+
+        let amended = harness.amend(BUYER, resting.id, OrderSide::Bid, "100", "10");
+        assert_eq!(amended.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("10000", "1000", "9000"));
+    }
+
+    #[test]
+    fn test_symbol_status_transitions() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "1");
+        let resting = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+
+        // 暂停：拒绝下单、撤单和改单，余额不冻结，挂单保留
+        harness.management.set_symbol_status(SYMBOL_ID, SymbolStatus::Halted).unwrap();
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::MarketHalted);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.cancel(SELLER, resting.id).code, 400);
+        let amended = harness.amend(SELLER, resting.id, OrderSide::Ask, "110", "1");
+        assert_eq!(amended.code, 400);
+        assert_eq!(harness.open_orders(SELLER).orders.len(), 1);
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "1", "0"));
+
+        // 只可撤单：下单和改单仍被拒绝，撤单解冻
+        harness.management.set_symbol_status(SYMBOL_ID, SymbolStatus::CancelOnly).unwrap();
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "90", "1");
+        assert_eq!(response.reject_reason(), RejectReason::MarketHalted);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        let amended = harness.amend(SELLER, resting.id, OrderSide::Ask, "110", "1");
+        assert_eq!(amended.code, 400);
+        assert_eq!(harness.cancel(SELLER, resting.id).code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0", "1"));
+
+        // 暂停期间的挂单在恢复后继续撮合
+        harness.management.set_symbol_status(SYMBOL_ID, SymbolStatus::Active).unwrap();
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        harness.management.set_symbol_status(SYMBOL_ID, SymbolStatus::Halted).unwrap();
+        harness.management.set_symbol_status(SYMBOL_ID, SymbolStatus::Active).unwrap();
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("100", "0", "100"));
+        assert!(harness.open_orders(SELLER).orders.is_empty());
+    }
+
+    #[test]
+    fn test_circuit_breaker_halts_symbol_on_price_gap() {
+        let mut harness = Harness::new();
+        let clock = Arc::new(ManualClock::new(1_000_000, 0));
+        let matching_engine = &mut harness.matchers[0].matching_engine;
+        matching_engine.set_clock(clock.clone());
+        matching_engine.set_circuit_breaker(Some(CircuitBreaker {
+            band: Decimal::new(1, 1),
+            halt_ms: 60_000,
+        }));
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "4");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "95", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "50", "2");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+
+        // 95 在参考价 100 的 10% 以内照常成交，50 超出范围触发熔断，剩余部分撤销而不挂单
+        let response = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "50", "3");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("2", "0", "2"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("195", "0", "195"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+        assert!(harness.open_orders(SELLER).orders.is_empty());
+        assert_eq!(harness.open_orders(BUYER).orders.len(), 1);
+
+        // 暂停期间拒绝下单，余额不冻结
+        let response = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "50", "1");
+        assert_eq!(response.code, 400);
+        assert_eq!(response.reject_reason(), RejectReason::MarketHalted);
+        assert_eq!(harness.balance(SELLER, BTC), balance("2", "0", "2"));
+
+        // 暂停结束后参考价重新确定，50 的买单可以成交
+        clock.set(1_060_000);
+        let response = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "50", "1");
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("245", "0", "245"));
+    }
+
+    #[test]
+    fn test_overflowing_freeze_amount_is_rejected() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let max = rust_decimal::Decimal::MAX.to_string();
+
+        // 价格 * 数量超出 Decimal 范围时返回错误，不冻结余额
+        for (price, quantity) in [(max.as_str(), "2"), ("100000000000000000000", "100000000000")] {
+            let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, price, quantity);
+            assert_eq!(response.code, 400);
+            assert_eq!(response.reject_reason(), RejectReason::InvalidAmount);
+        }
+        let huge = "10000000000000000000000000000";
+        let response = harness.place(BUYER, OrderType::Market, OrderSide::Bid, huge, "10");
+        assert_eq!(response.reject_reason(), RejectReason::InvalidAmount);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+    }
+
+    #[test]
+    fn test_duplicate_request_id_is_applied_once_and_survives_replay() {
+        let mut harness = Harness::with_shards(2);
+        let first = harness.deposit_with_key(10, USDT, "100", Some(7));
+        assert_eq!(first.code, 0);
+        let retried = harness.deposit_with_key(10, USDT, "100", Some(7));
+        assert_eq!(retried, first);
+        assert_eq!(harness.balance_on_shard(0, 10, USDT), balance("100", "0", "100"));
+
+        // 幂等键按账户划分，其他账户使用同一个键照常入账
+        harness.deposit_with_key(12, USDT, "50", Some(7));
+        assert_eq!(harness.balance_on_shard(0, 12, USDT), balance("50", "0", "50"));
+
+        // 同一个键不能用于其他类型的请求
+        assert_eq!(harness.transfer_with_key(10, 11, USDT, "40", Some(7), None).code, 409);
+
+        // 跨分片划转重试只扣减一次
+        for _ in 0..2 {
+            assert_eq!(harness.transfer_with_key(10, 11, USDT, "40", Some(8), None).code, 0);
+        }
+        assert_eq!(harness.balance_on_shard(0, 10, USDT), balance("60", "0", "60"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("40", "0", "40"));
+
+        // 重启后重放日志恢复去重表，重试仍然不会重复入账
+        let mut replayed = wal::replay(&harness.wal_paths[0]).unwrap().balance_manager;
+        let now = now_millis();
+        assert_eq!(
+            replayed.cached_response(10, 7, now),
+            Some(CachedResponse::Increase(first))
+        );
+        assert!(matches!(
+            replayed.cached_response(10, 8, now),
+            Some(CachedResponse::Transfer(response)) if response.code == 0
+        ));
+        assert_eq!(replayed.cached_response(10, 9, now), None);
+    }
+
+    #[test]
+    fn test_settlement_to_closed_shard_goes_to_dead_letters() {
+        use crate::dead_letter::{self, DeadLetterLog};
+
+        // 账户 10 -> 分片 0，账户 11 -> 分片 1，交易对 1 在分片 1 撮合
+        let mut harness = Harness::with_shards(2);
+        let wal_dir = harness.wal_paths[0].parent().unwrap().to_path_buf();
+        let dead_letter_path = dead_letter::dead_letter_path(&wal_dir);
+        let sink = DeadLetterSink::new(DeadLetterLog::open(&dead_letter_path).unwrap());
+        for matcher in &mut harness.matchers {
+            matcher.set_dead_letters(sink.clone());
+        }
+        harness.deposit(10, BTC, "1");
+        harness.deposit(11, USDT, "1000");
+        harness.place(10, OrderType::Limit, OrderSide::Ask, "100", "1");
+
+        // 分片 1 的成交回调队列关闭后，taker 的结算写入死信而不是丢失
+        let (closed, _) = crossbeam_channel::unbounded();
+        harness.matchers[1].sequencer_senders[1] = closed;
+        harness.place(11, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(harness.balance_on_shard(0, 10, USDT), balance("100", "0", "100"));
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("1000", "100", "900"));
+
+        let letters = dead_letter::read_dead_letters(&dead_letter_path).unwrap();
+        assert_eq!(letters.len(), 1);
+        let DeadLetter::SettleAccount {
+            shard,
+            account_id,
+            deduct_currency_id,
+            deduct_amount,
+            add_currency_id,
+            add_amount,
+            ..
+        } = letters[0].clone()
+        else {
+            panic!("unexpected dead letter {:?}", letters[0]);
+        };
+        assert_eq!((shard, account_id), (1, 11));
+        assert_eq!((deduct_currency_id, deduct_amount), (USDT, Decimal::from(100)));
+        assert_eq!((add_currency_id, add_amount), (BTC, Decimal::ONE));
+
+        // 重新投递后余额与正常结算一致
+        harness.sequencers[1].redeliver(&letters[0]).unwrap();
+        assert_eq!(harness.balance_on_shard(1, 11, USDT), balance("900", "0", "900"));
+        assert_eq!(harness.balance_on_shard(1, 11, BTC), balance("1", "0", "1"));
+    }
+
+    #[test]
+    fn test_balances_and_book_survive_crash_and_replay() {
+        let mut harness = Harness::new();
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "1000");
+        let resting = harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "2");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "90", "1");
+
+        // 处理器不经停机直接丢弃，重启后的状态只来自预写日志
+        harness.restart();
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "1", "0"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("100", "0", "100"));
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("900", "90", "810"));
+        assert_eq!(harness.open_orders(SELLER).orders.len(), 1);
+        assert_eq!(harness.open_orders(BUYER).orders.len(), 1);
+
+        // 恢复出的挂单可以继续撮合和撤销，解冻金额与崩溃前一致
+        assert_eq!(harness.cancel(SELLER, resting.id).code, 0);
+        assert_eq!(harness.balance(SELLER, BTC), balance("1", "0", "1"));
+        harness.deposit(SELLER, BTC, "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "90", "1");
+        assert_eq!(harness.balance(BUYER, BTC), balance("2", "0", "2"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("810", "0", "810"));
+    }
+
+    #[test]
+    fn test_wal_write_failure_rejects_changes_and_stops_shard() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let liveness = harness.sequencers[0].liveness();
+        liveness.set_alive(true);
 
-        let _ = response_sender.send(response);
+        // 排序器日志不可写：余额不变，分片停止
+        harness.sequencers[0].wal = WriteAheadLog::open_read_only(&harness.wal_paths[0]).unwrap();
+        assert_eq!(harness.deposit_with_key(BUYER, USDT, "500", None).code, 500);
+        assert!(!liveness.is_alive());
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_ne!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+
+        // 撮合日志不可写：下单被拒绝，冻结余额退回，订单簿不变
+        harness.restart();
+        let match_wal = harness.wal_paths[1].clone();
+        harness.matchers[0].wal = WriteAheadLog::open_read_only(&match_wal).unwrap();
+        let response = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(response.reject_reason(), RejectReason::InternalError);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert!(harness.open_orders(BUYER).orders.is_empty());
+        harness.restart();
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert!(harness.open_orders(BUYER).orders.is_empty());
     }
-}
 
-impl SequencerProcessor {
-    pub fn new(
-        id: usize,
-        receiver: crossbeam_channel::Receiver<SequencerMessage>,
-        match_senders: Vec<crossbeam_channel::Sender<MatchMessage>>,
-        trade_execution_receiver: crossbeam_channel::Receiver<TradeExecutionMessage>,
-        management_manager: Arc<ManagementManager>,
-    ) -> Self {
-        Self {
-            id,
-            receiver,
-            balance_manager: crate::models::BalanceManager::new(),
-            match_senders,
-            trade_execution_receiver,
-            management_manager,
+    #[test]
+    fn test_settlement_on_failed_wal_goes_to_dead_letters_and_replays() {
+        use crate::dead_letter::{self, DeadLetterLog};
+
+        // 账户 20 -> 分片 0，账户 11 -> 分片 1，交易对 1 在分片 1 撮合
+        let mut harness = Harness::with_shards(2);
+        let wal_dir = harness.wal_paths[0].parent().unwrap().to_path_buf();
+        let dead_letter_path = dead_letter::dead_letter_path(&wal_dir);
+        let sink = DeadLetterSink::new(DeadLetterLog::open(&dead_letter_path).unwrap());
+        harness.sequencers[0].set_dead_letters(sink);
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(11, USDT, "1000");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+
+        // 卖方分片的日志写入失败，结算没有生效，写入死信
+        harness.sequencers[0].wal = WriteAheadLog::open_read_only(&harness.wal_paths[0]).unwrap();
+        harness.place(11, OrderType::Limit, OrderSide::Bid, "100", "1");
+        assert_eq!(harness.balance_on_shard(0, SELLER, BTC), balance("1", "1", "0"));
+        assert_eq!(harness.balance_on_shard(1, 11, BTC), balance("1", "0", "1"));
+        let letters = dead_letter::read_dead_letters(&dead_letter_path).unwrap();
+        assert_eq!(letters.len(), 1);
+
+        // 重启后日志仍然无法写入时重新投递失败，死信留到下次启动
+        harness.restart();
+        harness.sequencers[0].wal = WriteAheadLog::open_read_only(&harness.wal_paths[0]).unwrap();
+        let undelivered: Vec<_> = letters
+            .iter()
+            .filter(|letter| harness.sequencers[letter.shard()].redeliver(letter).is_err())
+            .cloned()
+            .collect();
+        assert_eq!(undelivered.len(), 1);
+        dead_letter::archive_dead_letters(&dead_letter_path, &undelivered).unwrap();
+        let letters = dead_letter::read_dead_letters(&dead_letter_path).unwrap();
+        assert_eq!(letters.len(), 1);
+
+        // 重启后按日志恢复，重新投递死信，结果与正常结算一致
+        harness.restart();
+        assert_eq!(harness.balance_on_shard(0, SELLER, BTC), balance("1", "1", "0"));
+        for letter in &letters {
+            harness.sequencers[letter.shard()].redeliver(letter).unwrap();
         }
+        dead_letter::archive_dead_letters(&dead_letter_path, &[]).unwrap();
+        assert!(dead_letter::read_dead_letters(&dead_letter_path).unwrap().is_empty());
+        assert_eq!(harness.balance_on_shard(0, SELLER, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance_on_shard(0, SELLER, USDT), balance("100", "0", "100"));
+        let _ = std::fs::remove_dir_all(&wal_dir);
     }
 
-    pub fn run(mut self) {
-        println!("SequencerProcessor {} started", self.id);
-        loop {
-            crossbeam_channel::select! {
-                recv(self.receiver) -> message => {
-                    match message {
-                        Ok(msg) => self.process_sequencer_message(msg),
-                        Err(_) => {
-                            println!("SequencerProcessor {} stopped - sequencer channel closed", self.id);
-                            break;
-                        }
-                    }
-                }
-                recv(self.trade_execution_receiver) -> trade_message => {
-                    match trade_message {
-                        Ok(msg) => self.process_trade_execution_message(msg),
-                        Err(_) => {
-                            println!("SequencerProcessor {} stopped - trade execution channel closed", self.id);
-                            break;
-                        }
-                    }
-                }
-            }
-        }
+    #[test]
+    fn test_admin_force_cancel_bypasses_ownership_and_audits_reason() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let bid = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "200", "800"));
+
+        // 不需要提供订单所属账户，解冻记录带上操作原因
+        let response = harness.force_cancel(bid.id, "stuck order");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.refund_amount.as_deref(), Some("200"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert!(harness.open_orders(BUYER).orders.is_empty());
+        let entry = harness.latest_audit(BUYER, USDT);
+        assert_eq!(entry.reason, AuditReason::AdminCancel);
+        assert_eq!(entry.note.as_deref(), Some("stuck order"));
+
+        assert_eq!(harness.force_cancel(bid.id, "stuck order").code, 404);
     }
 
-    fn process_sequencer_message(&mut self, message: SequencerMessage) {
-        match message {
-            SequencerMessage::GetAccount {
-                request_id: _,
-                account_id,
-                currency_id,
-                response_sender,
-            } => {
-                let response = self
-                    .balance_manager
-                    .handle_get_account(account_id, currency_id);
-                let _ = response_sender.send(response);
-            }
-            SequencerMessage::Increase {
-                request_id: _,
-                account_id,
-                currency_id,
-                amount,
-                response_sender,
-            } => {
-                let response =
-                    self.balance_manager
-                        .handle_increase(account_id, currency_id, &amount);
-                let _ = response_sender.send(response);
-            }
-            SequencerMessage::Decrease {
-                request_id: _,
-                account_id,
-                currency_id,
-                amount,
-                response_sender,
-            } => {
-                let response =
-                    self.balance_manager
-                        .handle_decrease(account_id, currency_id, &amount);
+    #[test]
+    fn test_admin_adjust_balance_rejects_negative_result() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "100");
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "10", "3");
 
-                let _ = response_sender.send(response);
-            }
-            SequencerMessage::PlaceOrder {
-                request_id,
-                symbol_id,
-                account_id,
-                order_type,
-                side,
-                price,
-                quantity,
-                response_sender,
-            } => {
-                // 获取交易对信息
-                if let Some(symbol) = self.management_manager.get_symbol(symbol_id) {
-                    // 使用新的 handle_place_order 方法来处理订单和冻结余额
-                    match self
-                        .balance_manager
-                        .handle_place_order(account_id, symbol_id, side, &price, &quantity, &symbol)
-                    {
-                        Ok((freeze_currency_id, freeze_amount)) => {
-                            println!("Order processed: account_id={}, symbol_id={}, side={}, frozen_currency={}, frozen_amount={}",
-                                account_id, symbol_id, side, freeze_currency_id, freeze_amount);
+        let response = harness.admin_adjust(BUYER, USDT, "25", "compensation");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.data.unwrap().available, "95");
+        let entry = harness.latest_audit(BUYER, USDT);
+        assert_eq!(entry.reason, AuditReason::AdminAdjustment);
+        assert_eq!(entry.note.as_deref(), Some("compensation"));
 
-                            // 余额足够，发送到 MatchProcessor
-                            let match_message = MatchMessage::PlaceOrder {
-                                request_id,
-                                symbol_id,
-                                account_id,
-                                order_type,
-                                side,
-                                price,
-                                quantity,
-                                response_sender,
-                            };
+        // 冻结部分不能被扣减，可用余额不足时不修改
+        let response = harness.admin_adjust(BUYER, USDT, "-96", "clawback");
+        assert_eq!(response.code, 400);
+        assert_eq!(harness.balance(BUYER, USDT), balance("125", "30", "95"));
+
+        assert_eq!(harness.admin_adjust(BUYER, USDT, "-95", "clawback").code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("30", "30", "0"));
+        assert_eq!(harness.latest_audit(BUYER, USDT).note.as_deref(), Some("clawback"));
+    }
 
-                            let shard_index =
-                                (symbol_id % self.match_senders.len() as i32).abs() as usize;
-                            let sender = &self.match_senders[shard_index];
+    #[test]
+    fn test_cancel_by_client_order_id_unfreezes_balance() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        let placed = harness.place_with_client_id(BUYER, OrderSide::Bid, "100", "2", "bid-1");
+        assert_eq!(placed.code, 0);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "200", "800"));
 
-                            if let Err(_) = sender.send(match_message) {
-                                println!("Failed to forward to matcher - channel closed");
-                                // response_sender is moved to match_message, so we can't send response here
-                            }
-                        }
-                        Err(e) => {
-                            let response = crate::models::schema::PlaceOrderResponse {
-                                code: 400,
-                                message: Some(format!("Failed to process order: {}", e)),
-                                id: 0,
-                            };
-                            let _ = response_sender.send(response);
-                        }
-                    }
-                } else {
-                    let response = crate::models::schema::PlaceOrderResponse {
-                        code: 404,
-                        message: Some("Symbol not found".to_string()),
-                        id: 0,
-                    };
-                    let _ = response_sender.send(response);
-                }
-            }
-            SequencerMessage::CancelOrder {
-                request_id,
-                symbol_id,
-                account_id,
-                order_id,
-                response_sender,
-            } => {
-                // 转发取消订单请求到对应的 MatchProcessor
-                let match_message = MatchMessage::CancelOrder {
-                    request_id,
-                    symbol_id,
-                    account_id,
-                    order_id,
-                    response_sender,
-                };
+        // 重复的客户端订单ID被拒绝，预先冻结的余额原样解冻
+        let duplicate = harness.place_with_client_id(BUYER, OrderSide::Bid, "100", "1", "bid-1");
+        assert_eq!(duplicate.code, 400);
+        assert_eq!(duplicate.reject_reason(), RejectReason::InvalidOrder);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "200", "800"));
 
-                let shard_index = (symbol_id % self.match_senders.len() as i32).abs() as usize;
-                let sender = &self.match_senders[shard_index];
+        let open_orders = harness.open_orders(BUYER);
+        assert_eq!(open_orders.orders[0].client_order_id.as_deref(), Some("bid-1"));
 
-                if let Err(_) = sender.send(match_message) {
-                    println!("Failed to forward cancel order to matcher - channel closed");
-                    // response_sender was moved to match_message, so we can't send response here
-                }
+        assert_eq!(harness.cancel_by_client_id(SELLER, "bid-1").code, 404);
+        let response = harness.cancel_by_client_id(BUYER, "bid-1");
+        assert_eq!(response.code, 0);
+        assert_eq!(response.order_id, placed.id);
+        assert_eq!(harness.balance(BUYER, USDT), balance("1000", "0", "1000"));
+        assert_eq!(harness.cancel_by_client_id(BUYER, "bid-1").code, 404);
+    }
+
+    #[test]
+    fn test_confirmed_settlement_responds_after_balances_update() {
+        // 买卖双方在不同分片，响应要等两个分片都结算完
+        let mut harness = Harness::with_shards(3);
+        for matcher in &mut harness.matchers {
+            matcher.set_confirm_settlement(true);
+        }
+        harness.deposit(SELLER, BTC, "1");
+        harness.deposit(BUYER, USDT, "100");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "50", "1");
+
+        let (response_sender, mut response_receiver) = oneshot::channel();
+        let shard = harness.shard(BUYER);
+        harness.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+            request_id: uuid::Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: BUYER,
+            order_type: OrderType::Limit as i32,
+            side: OrderSide::Bid as i32,
+            time_in_force: 0,
+            price: "50".to_string(),
+            quantity: "1".to_string(),
+            taker_rate: 0,
+            maker_rate: 0,
+            post_only: false,
+            display_quantity: None,
+            stop_price: None,
+            trigger_direction: 0,
+            protection_price: None,
+            expires_at: None,
+            volume: None,
+            client_order_id: None,
+            validate_only: false,
+            nonce: None,
+            span: tracing::Span::current(),
+            response_sender,
+        });
+        for matcher in &mut harness.matchers {
+            while let Ok(message) = matcher.receiver.try_recv() {
+                matcher.handle_message(message);
             }
         }
-    }
+        // 撮合完成但还没有结算，响应不会返回
+        assert!(response_receiver.try_recv().is_err());
 
-    fn process_trade_execution_message(&mut self, message: TradeExecutionMessage) {
-        match message {
-            TradeExecutionMessage::ExecuteTrade {
-                trade,
-                original_response_sender: _,
-            } => {
-                if let Err(e) = self.execute_single_trade(&trade) {
-                    println!(
-                        "SequencerProcessor {}: Failed to execute trade {}: {}",
-                        self.id, trade.id, e
-                    );
-                }
+        // 逐条处理结算消息，响应到达时双方余额都已更新
+        let response = loop {
+            let (shard, message) = harness
+                .sequencers
+                .iter()
+                .enumerate()
+                .find_map(|(i, sequencer)| {
+                    sequencer.trade_execution_receiver.try_recv().ok().map(|m| (i, m))
+                })
+                .expect("settlement pending but no message in flight");
+            harness.sequencers[shard].process_trade_execution_message(message);
+            if let Ok(response) = response_receiver.try_recv() {
+                break response;
             }
-            TradeExecutionMessage::SettleAccount {
-                account_id,
-                symbol_id: _,
-                deduct_currency_id,
-                deduct_amount,
-                add_currency_id,
-                add_amount,
-            } => {
-                if let Err(e) = self.settle_account_balance(
-                    account_id,
-                    deduct_currency_id,
-                    deduct_amount,
-                    add_currency_id,
-                    add_amount,
-                ) {
-                    println!(
-                        "SequencerProcessor {}: Failed to settle account {}: {}",
-                        self.id, account_id, e
-                    );
-                }
+        };
+        assert_eq!(response.code, 0);
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("50", "0", "50"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("0", "0", "0"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("50", "0", "50"));
+    }
+
+    #[test]
+    fn test_get_account_between_settlements_is_consistent() {
+        let mut harness = Harness::with_shards(3);
+        harness.deposit(SELLER, BTC, "2");
+        harness.deposit(BUYER, USDT, "100");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "25", "1");
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "25", "1");
+
+        // 撮合后暂停结算，逐条处理结算消息，每条之间查询双方账户
+        let shard = harness.shard(BUYER);
+        let (response_sender, _response_receiver) = oneshot::channel();
+        harness.sequencers[shard].process_sequencer_message(SequencerMessage::PlaceOrder {
+            request_id: uuid::Uuid::new_v4(),
+            symbol_id: SYMBOL_ID,
+            account_id: BUYER,
+            order_type: OrderType::Limit as i32,
+            side: OrderSide::Bid as i32,
+            time_in_force: 0,
+            price: "25".to_string(),
+            quantity: "2".to_string(),
+            taker_rate: 0,
+            maker_rate: 0,
+            post_only: false,
+            display_quantity: None,
+            stop_price: None,
+            trigger_direction: 0,
+            protection_price: None,
+            expires_at: None,
+            volume: None,
+            client_order_id: None,
+            validate_only: false,
+            nonce: None,
+            span: tracing::Span::current(),
+            response_sender,
+        });
+        for matcher in &mut harness.matchers {
+            while let Ok(message) = matcher.receiver.try_recv() {
+                matcher.handle_message(message);
             }
-            TradeExecutionMessage::UnfreezeOrder { order } => {
-                if let Err(e) = self.unfreeze_order_balance(&order) {
-                    println!(
-                        "SequencerProcessor {}: Failed to unfreeze order {}: {}",
-                        self.id, order.id, e
-                    );
+        }
+        let assert_consistent = |harness: &Harness| {
+            for account_id in [BUYER, SELLER] {
+                let response = harness.sequencers[harness.shard(account_id)]
+                    .balance_manager
+                    .handle_get_account(account_id, None);
+                assert_eq!(response.code, 0);
+                for balance in response.data.values() {
+                    let value = Decimal::from_str_exact(&balance.value).unwrap();
+                    let available = Decimal::from_str_exact(&balance.available).unwrap();
+                    let frozen = Decimal::from_str_exact(&balance.frozen).unwrap();
+                    assert_eq!(value, available + frozen);
                 }
             }
+        };
+        let mut settled = 0;
+        loop {
+            assert_consistent(&harness);
+            let Some((shard, message)) =
+                harness.sequencers.iter().enumerate().find_map(|(i, sequencer)| {
+                    sequencer.trade_execution_receiver.try_recv().ok().map(|m| (i, m))
+                })
+            else {
+                break;
+            };
+            harness.sequencers[shard].process_trade_execution_message(message);
+            settled += 1;
         }
+        assert!(settled >= 3);
+        assert_eq!(harness.balance(BUYER, USDT), balance("50", "0", "50"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("50", "0", "50"));
     }
 
-    fn execute_single_trade(&mut self, trade: &Trade) -> Result<(), BalanceError> {
-        // 获取交易对信息
-        let symbol = self.management_manager.get_symbol(trade.symbol_id).ok_or(BalanceError::CurrencyNotFound)?;
+    #[test]
+    fn test_bbo_updates_immediately_when_best_level_changes() {
+        let mut harness = Harness::new();
+        let depth_cache = Arc::new(DepthCache::new());
+        let shard = match_shard(SYMBOL_ID, harness.shard_count);
+        harness.matchers[shard].set_depth_cache(depth_cache.clone());
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "10");
+        assert_eq!(harness.bbo().code, 404);
 
-        // 买方：扣除冻结的 quote currency，增加 base currency
-        let quote_amount = trade.price * trade.quantity;
+        // 撮合线程直接读取的结果与深度缓存第一档一致；(价格, 数量)
+        type Level<'a> = Option<(&'a str, &'a str)>;
+        let expect_bbo = |harness: &mut Harness, bid: Level, ask: Level, spread: Option<&str>| {
+            let snapshot = depth_cache.load(SYMBOL_ID).unwrap();
+            let responses = [harness.bbo(), crate::market_data::bbo_from_depth(&snapshot)];
+            assert_eq!(responses[0].spread_bps, responses[1].spread_bps);
+            for response in responses {
+                assert_eq!(response.code, 0);
+                assert_eq!(response.best_bid.as_deref(), bid.map(|(price, _)| price));
+                assert_eq!(response.best_bid_quantity.as_deref(), bid.map(|(_, quantity)| quantity));
+                assert_eq!(response.best_ask.as_deref(), ask.map(|(price, _)| price));
+                assert_eq!(response.best_ask_quantity.as_deref(), ask.map(|(_, quantity)| quantity));
+                assert_eq!(response.spread.as_deref(), spread);
+            }
+        };
 
-        // 处理买方账户（如果属于当前分片）
-        let buy_shard = (trade.buy_account_id % 10).abs() as usize; // 假设10个分片
-        if buy_shard == self.id {
-            let buy_account = self
-                .balance_manager
-                .accounts
-                .entry(trade.buy_account_id)
-                .or_insert_with(|| crate::models::Account::new(trade.buy_account_id));
-
-            // 1. 减少冻结的 quote currency
-            let buy_quote_balance = buy_account.get_balance(symbol.quote);
-            buy_quote_balance.frozen -= quote_amount;
-            buy_quote_balance.total -= quote_amount;
-
-            // 2. 增加 base currency
-            let buy_base_balance = buy_account.get_balance(symbol.base);
-            buy_base_balance.total += trade.quantity;
-            buy_base_balance.available += trade.quantity;
-
-            println!(
-                "SequencerProcessor {}: Buy account {} - deducted {} {} from frozen, added {} {}",
-                self.id,
-                trade.buy_account_id,
-                quote_amount,
-                symbol.quote,
-                trade.quantity,
-                symbol.base
-            );
-        }
+        // 只有买盘时卖方和价差为空
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "99", "1");
+        expect_bbo(&mut harness, Some(("99", "1")), None, None);
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "101", "2");
+        expect_bbo(&mut harness, Some(("99", "1")), Some(("101", "2")), Some("2"));
+        // 价差 2 / 中间价 100 × 10000 = 200 基点，行情中同样返回
+        assert_eq!(harness.bbo().spread_bps.as_deref(), Some("200"));
+        assert_eq!(harness.ticker().spread_bps.as_deref(), Some("200"));
+        // 更优的卖价成为最优价位
+        harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+        expect_bbo(&mut harness, Some(("99", "1")), Some(("100", "1")), Some("1"));
+        // 最优卖价整档成交后回到下一档
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1");
+        expect_bbo(&mut harness, Some(("99", "1")), Some(("101", "2")), Some("2"));
+        harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "99", "0.5");
+        expect_bbo(&mut harness, Some(("99", "1.5")), Some(("101", "2")), Some("2"));
+    }
 
-        // 处理卖方账户（如果属于当前分片）
-        let sell_shard = (trade.sell_account_id % 10).abs() as usize;
-        if sell_shard == self.id {
-            let sell_account = self
-                .balance_manager
-                .accounts
-                .entry(trade.sell_account_id)
-                .or_insert_with(|| crate::models::Account::new(trade.sell_account_id));
-
-            // 3. 减少冻结的 base currency
-            let sell_base_balance = sell_account.get_balance(symbol.base);
-            sell_base_balance.frozen -= trade.quantity;
-            sell_base_balance.total -= trade.quantity;
-
-            // 4. 增加 quote currency
-            let sell_quote_balance = sell_account.get_balance(symbol.quote);
-            sell_quote_balance.total += quote_amount;
-            sell_quote_balance.available += quote_amount;
-
-            println!(
-                "SequencerProcessor {}: Sell account {} - deducted {} {} from frozen, added {} {}",
-                self.id,
-                trade.sell_account_id,
-                trade.quantity,
-                symbol.base,
-                quote_amount,
-                symbol.quote
-            );
+    #[test]
+    fn test_depth_cache_reads_are_consistent_while_orders_are_placed() {
+        let mut harness = Harness::new();
+        let depth_cache = Arc::new(DepthCache::new());
+        let shard = match_shard(SYMBOL_ID, harness.shard_count);
+        harness.matchers[shard].set_depth_cache(depth_cache.clone());
+        harness.deposit(BUYER, USDT, "100000");
+
+        // 每笔买单挂在不同价位，快照的档数必须与其序号一致，最优价与第一档一致
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let depth_cache = depth_cache.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut last_sequence = 0;
+                    while !done.load(std::sync::atomic::Ordering::Acquire) {
+                        let Some(snapshot) = depth_cache.load(SYMBOL_ID) else {
+                            continue;
+                        };
+                        assert_eq!(snapshot.bids.len() as i64, snapshot.sequence);
+                        assert_eq!(snapshot.best_bid.as_ref(), Some(&snapshot.bids[0].price));
+                        assert!(snapshot.sequence >= last_sequence);
+                        last_sequence = snapshot.sequence;
+                    }
+                })
+            })
+            .collect();
+
+        for i in 1..=50 {
+            harness.place(BUYER, OrderType::Limit, OrderSide::Bid, &(100 + i).to_string(), "1");
+        }
+        done.store(true, std::sync::atomic::Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
         }
 
-        Ok(())
+        let snapshot = depth_cache.load(SYMBOL_ID).unwrap();
+        assert_eq!(snapshot.sequence, 50);
+        assert_eq!(snapshot.best_bid.as_deref(), Some("150"));
     }
 
-    fn settle_account_balance(
-        &mut self,
-        account_id: i32,
-        deduct_currency_id: i32,
-        deduct_amount: rust_decimal::Decimal,
-        add_currency_id: i32,
-        add_amount: rust_decimal::Decimal,
-    ) -> Result<(), BalanceError> {
-        // 检查账户是否属于当前分片
-        let account_shard = (account_id % 10).abs() as usize;
-        if account_shard != self.id {
-            // 不属于当前分片，不处理
-            return Ok(());
-        }
+    // (span 名称, 父 span 名称)
+    type RecordedSpan = (&'static str, Option<&'static str>);
 
-        // 获取或创建账户
-        let account = self
-            .balance_manager
-            .accounts
-            .entry(account_id)
-            .or_insert_with(|| crate::models::Account::new(account_id));
-
-        // 1. 从冻结余额中扣除 deduct_currency
-        let deduct_balance = account.get_balance(deduct_currency_id);
-        if deduct_balance.frozen < deduct_amount {
-            println!(
-                "Warning: Insufficient frozen balance for account {}, currency {}, required: {}, available: {}",
-                account_id, deduct_currency_id, deduct_amount, deduct_balance.frozen
-            );
-            // 扣除所有可用的冻结余额
-            let actual_deduct = deduct_balance.frozen;
-            deduct_balance.frozen = rust_decimal::Decimal::ZERO;
-            deduct_balance.total -= actual_deduct;
-        } else {
-            deduct_balance.frozen -= deduct_amount;
-            deduct_balance.total -= deduct_amount;
+    // 记录每个 span 的名称和父 span 名称
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<std::sync::Mutex<Vec<RecordedSpan>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for SpanRecorder
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            _attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.span(id).unwrap();
+            let parent = span.parent().map(|parent| parent.name());
+            self.0.lock().unwrap().push((span.name(), parent));
         }
+    }
 
-        // 2. 增加 add_currency 到可用余额
-        let add_balance = account.get_balance(add_currency_id);
-        add_balance.available += add_amount;
-        add_balance.total += add_amount;
+    #[test]
+    fn test_placed_order_records_span_hierarchy() {
+        use tracing_subscriber::layer::SubscriberExt;
 
-        println!(
-            "SequencerProcessor {}: Settled account {} - deducted {} {} from frozen, added {} {}",
-            self.id,
-            account_id,
-            deduct_amount,
-            deduct_currency_id,
-            add_amount,
-            add_currency_id
-        );
+        let recorder = SpanRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut harness = Harness::new();
+            harness.deposit(SELLER, BTC, "1");
+            harness.deposit(BUYER, USDT, "1000");
+            harness.place(SELLER, OrderType::Limit, OrderSide::Ask, "100", "1");
+            recorder.0.lock().unwrap().clear();
 
-        Ok(())
+            // 与 gRPC 层一样在请求 span 内提交，撮合和结算在各自的处理器中记录子 span
+            let span = crate::telemetry::place_order_span(uuid::Uuid::new_v4(), BUYER, SYMBOL_ID);
+            span.in_scope(|| harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "1"));
+        });
+
+        let spans = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            spans,
+            vec![
+                ("place_order", None),
+                ("freeze", Some("place_order")),
+                ("match", Some("place_order")),
+                ("settle", Some("match")),
+                ("settle", Some("match")),
+            ]
+        );
     }
 
-    fn unfreeze_order_balance(
-        &mut self,
-        order: &crate::matching::Order,
-    ) -> Result<(), BalanceError> {
-        use crate::matching::OrderSide;
+    // 记录每条日志的级别和消息
+    #[derive(Clone, Default)]
+    struct EventRecorder(Arc<std::sync::Mutex<Vec<(tracing::Level, String)>>>);
 
-        // 获取交易对信息
-        let symbol = self.management_manager.get_symbol(order.symbol_id).ok_or(BalanceError::CurrencyNotFound)?;
+    struct MessageVisitor(String);
 
-        // 计算需要解冻的金额
-        let remaining_quantity = order.remaining_quantity();
-        let (unfreeze_currency_id, unfreeze_amount) = match order.side {
-            OrderSide::Bid => {
-                // 买单：解冻 quote currency
-                let quote_amount = order.price * remaining_quantity;
-                (symbol.quote, quote_amount)
-            }
-            OrderSide::Ask => {
-                // 卖单：解冻 base currency
-                (symbol.base, remaining_quantity)
+    impl tracing::field::Visit for MessageVisitor {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
             }
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for EventRecorder {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut visitor = MessageVisitor(String::new());
+            event.record(&mut visitor);
+            self.0.lock().unwrap().push((*event.metadata().level(), visitor.0));
+        }
+    }
+
+    #[test]
+    fn test_insufficient_frozen_balance_logs_warning() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorder = EventRecorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut harness = Harness::new();
+            harness.deposit(BUYER, USDT, "1000");
+            let bid = harness.place(BUYER, OrderType::Limit, OrderSide::Bid, "100", "2");
+
+            // 冻结余额被提前释放一部分，撤单时只能解冻剩余的部分
+            let shard = harness.shard(BUYER);
+            harness.sequencers[shard]
+                .balance_manager
+                .release_frozen(BUYER, USDT, Decimal::from(50));
+            assert_eq!(harness.cancel(BUYER, bid.id).refund_amount.as_deref(), Some("150"));
+        });
+
+        let events = recorder.0.lock().unwrap().clone();
+        let warnings: Vec<&str> = events
+            .iter()
+            .filter(|(level, _)| *level == tracing::Level::WARN)
+            .map(|(_, message)| message.as_str())
+            .collect();
+        assert_eq!(warnings, vec!["Insufficient frozen balance"]);
+        // 逐笔订单的日志为 debug 级别
+        assert!(events
+            .iter()
+            .any(|(level, message)| *level == tracing::Level::DEBUG && message == "Order frozen"));
+    }
+
+    #[test]
+    fn test_match_batch_coalesces_depth_updates() {
+        let mut harness = Harness::new();
+        harness.deposit(BUYER, USDT, "1000");
+        harness.deposit(SELLER, BTC, "10");
+        harness.matchers[0].set_batch_size(4);
+        let mut depth_updates = harness.order_book_publisher.subscribe(SYMBOL_ID);
+
+        // 订单只经过 SequencerProcessor 冻结，在撮合队列中排队
+        let mut enqueue = |account_id: i32, side: OrderSide, price: &str| {
+            let (response_sender, response_receiver) = oneshot::channel();
+            harness.sequencers[0].process_sequencer_message(SequencerMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: SYMBOL_ID,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: 0,
+                price: price.to_string(),
+                quantity: "1".to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: None,
+                validate_only: false,
+                nonce: None,
+                span: tracing::Span::none(),
+                response_sender,
+            });
+            response_receiver
         };
+        let mut responses = vec![
+            enqueue(SELLER, OrderSide::Ask, "101"),
+            enqueue(SELLER, OrderSide::Ask, "102"),
+            enqueue(BUYER, OrderSide::Bid, "99"),
+            enqueue(BUYER, OrderSide::Bid, "101"),
+        ];
+        let mut next_batch = enqueue(SELLER, OrderSide::Ask, "103");
 
-        // 检查订单是否属于当前分片
-        let account_shard = (order.account_id % 10).abs() as usize;
-        if account_shard != self.id {
-            // 不属于当前分片，不处理
-            return Ok(());
+        // 一批处理前四条消息，每笔订单都有响应，深度只发布一次且是批结束时的订单簿
+        let message = harness.matchers[0].receiver.try_recv().unwrap();
+        harness.matchers[0].handle_batch(message);
+        for response in &mut responses {
+            assert_eq!(response.try_recv().unwrap().code, 0);
         }
+        assert!(next_batch.try_recv().is_err());
+        assert_eq!(harness.matchers[0].receiver.len(), 1);
+        let snapshot = depth_updates.try_recv().unwrap();
+        assert!(depth_updates.try_recv().is_err());
+        let prices = |levels: &[crate::models::schema::PriceLevel]| -> Vec<String> {
+            levels.iter().map(|level| level.price.clone()).collect()
+        };
+        assert_eq!(prices(&snapshot.bids), vec!["99"]);
+        assert_eq!(prices(&snapshot.asks), vec!["102"]);
 
-        // 解冻余额
-        let account = self
-            .balance_manager
-            .accounts
-            .entry(order.account_id)
-            .or_insert_with(|| crate::models::Account::new(order.account_id));
+        // 剩余消息和结算照常处理，余额与逐条处理时相同
+        harness.pump();
+        assert_eq!(next_batch.try_recv().unwrap().code, 0);
+        assert_eq!(prices(&depth_updates.try_recv().unwrap().asks), vec!["102", "103"]);
+        assert_eq!(harness.balance(BUYER, BTC), balance("1", "0", "1"));
+        assert_eq!(harness.balance(BUYER, USDT), balance("899", "99", "800"));
+        assert_eq!(harness.balance(SELLER, BTC), balance("9", "2", "7"));
+        assert_eq!(harness.balance(SELLER, USDT), balance("101", "0", "101"));
+    }
 
-        let balance = account.get_balance(unfreeze_currency_id);
+    // 热点账户在分片 0 积压 WRITES 笔充值后同时提交 READS 笔余额查询，返回每个工作线程
+    // 回复的溢出查询数，以及最后一笔响应到达时分片 0 队列中仍在排队的消息数
+    fn hot_account_reads(read_overflow: bool) -> (Vec<u64>, usize) {
+        const WRITES: usize = 5_000;
+        const READS: usize = 100;
+        let mut harness = Harness::with_shards(2);
+        harness.deposit(BUYER, USDT, "1");
+        let overflow = Arc::new(ReadOverflow::new(2, 1, READS, harness.management.clone()));
+        if read_overflow {
+            for sequencer in &mut harness.sequencers {
+                sequencer.set_read_overflow(overflow.clone());
+            }
+        }
+        // 两个分片各由一个线程驱动，分片 1 没有请求
+        let (sequencer_handles, match_handles) = harness.spawn();
+        let shard = harness.shard(BUYER);
+        let sender = harness.sequencer_senders[shard].clone();
+        for _ in 0..WRITES {
+            let (response_sender, _response_receiver) = oneshot::channel();
+            sender
+                .send(SequencerMessage::Increase {
+                    request_id: uuid::Uuid::new_v4(),
+                    account_id: BUYER,
+                    currency_id: USDT,
+                    amount: "1".to_string(),
+                    idempotency_key: None,
+                    response_sender,
+                })
+                .unwrap();
+        }
 
-        // 检查冻结余额是否足够
-        if balance.frozen < unfreeze_amount {
-            println!(
-                "Warning: Insufficient frozen balance for account {}, currency {}, required: {}, available: {}",
-                order.account_id, unfreeze_currency_id, unfreeze_amount, balance.frozen
-            );
-            // 解冻所有剩余的冻结余额
-            let actual_unfreeze = balance.frozen;
-            balance.frozen = rust_decimal::Decimal::ZERO;
-            balance.available += actual_unfreeze;
-        } else {
-            // 正常解冻
-            balance.frozen -= unfreeze_amount;
-            balance.available += unfreeze_amount;
+        // 与 gRPC 层相同的路由：设置了溢出时先尝试放入溢出队列
+        let mut pending: Vec<_> = (0..READS)
+            .map(|_| {
+                let (response_sender, response_receiver) = oneshot::channel();
+                let read = OverflowRead {
+                    shard,
+                    account_id: BUYER,
+                    currency_id: Some(USDT),
+                    response_sender,
+                };
+                let read = match read_overflow {
+                    true => overflow.offer(sender.len(), read).err(),
+                    false => Some(read),
+                };
+                if let Some(read) = read {
+                    sender
+                        .send(SequencerMessage::GetAccount {
+                            request_id: uuid::Uuid::new_v4(),
+                            account_id: BUYER,
+                            currency_id: Some(USDT),
+                            response_sender: read.response_sender,
+                        })
+                        .unwrap();
+                }
+                response_receiver
+            })
+            .collect();
+        while !pending.is_empty() {
+            pending.retain_mut(|response_receiver| match response_receiver.try_recv() {
+                Ok(response) => {
+                    assert_eq!(response.code, 0);
+                    false
+                }
+                Err(_) => true,
+            });
+            std::thread::yield_now();
         }
+        let backlog = sender.len();
 
-        println!(
-            "SequencerProcessor {}: Unfroze {} {} for account {} (order {})",
-            self.id, unfreeze_amount, unfreeze_currency_id, order.account_id, order.id
+        drop(sender);
+        drain_processors(
+            std::mem::take(&mut harness.sequencer_senders),
+            std::mem::take(&mut harness.match_senders),
+            std::mem::take(&mut harness.trade_execution_senders),
+            sequencer_handles,
+            match_handles,
         );
+        let served = (0..2).map(|worker| overflow.served_by(worker)).collect();
+        (served, backlog)
+    }
 
-        Ok(())
+    #[test]
+    fn test_read_overflow_is_served_by_idle_shard() {
+        // 查询排在全部充值之后，要等分片处理完积压的消息
+        let (served, backlog) = hot_account_reads(false);
+        assert_eq!(served, vec![0, 0]);
+        assert_eq!(backlog, 0);
+
+        // 查询全部回复时分片 0 仍有积压，查询都由空闲的分片 1 线程读取账户视图回复
+        let (served, backlog) = hot_account_reads(true);
+        assert!(backlog > 0);
+        assert_eq!(served, vec![0, 100]);
     }
 }
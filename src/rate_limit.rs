@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// 令牌桶按账户ID分到多个加锁的分段，不同账户的请求很少竞争同一把锁
+const BUCKET_SHARDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// 账户级下单/撤单/改单限流：每个账户一个令牌桶，按固定速率补充，最多积累 burst 个令牌
+#[derive(Debug)]
+pub struct OrderRateLimiter {
+    rate_per_second: f64,
+    burst: f64,
+    shards: Vec<Mutex<HashMap<i32, Bucket>>>,
+}
+
+impl OrderRateLimiter {
+    pub fn new(rate_per_second: u32, burst: u32) -> Self {
+        Self {
+            rate_per_second: f64::from(rate_per_second),
+            burst: f64::from(burst.max(1)),
+            shards: (0..BUCKET_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    // 取一个令牌，令牌不足时返回 false
+    pub fn try_acquire(&self, account_id: i32) -> bool {
+        self.try_acquire_at(account_id, Instant::now())
+    }
+
+    pub fn try_acquire_at(&self, account_id: i32, now: Instant) -> bool {
+        let shard = (account_id % BUCKET_SHARDS as i32).unsigned_abs() as usize;
+        let mut buckets = self.shards[shard].lock().unwrap();
+        // 新账户从满桶开始
+        let bucket = buckets.entry(account_id).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_second).min(self.burst);
+        bucket.refilled_at = bucket.refilled_at.max(now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fast_orders_rejected_and_slow_cadence_passes() {
+        let limiter = OrderRateLimiter::new(10, 5);
+        let start = Instant::now();
+
+        // 同一时刻连续下单：桶里的 5 个令牌用完后拒绝
+        let accepted = (0..20).filter(|_| limiter.try_acquire_at(1, start)).count();
+        assert_eq!(accepted, 5);
+        // 其他账户的令牌桶不受影响
+        assert!(limiter.try_acquire_at(2, start));
+
+        // 每 100ms 补充 1 个令牌，按该节奏下单全部通过
+        for step in 1..=50 {
+            assert!(limiter.try_acquire_at(1, start + Duration::from_millis(100 * step)));
+        }
+        // 比补充速度快一倍时约一半被拒绝
+        let base = start + Duration::from_secs(5);
+        let accepted = (1..=40)
+            .filter(|step| limiter.try_acquire_at(1, base + Duration::from_millis(50 * step)))
+            .count();
+        assert_eq!(accepted, 20);
+
+        // 空闲后最多积累 burst 个令牌
+        let idle = base + Duration::from_secs(60);
+        let accepted = (0..20).filter(|_| limiter.try_acquire_at(1, idle)).count();
+        assert_eq!(accepted, 5);
+    }
+}
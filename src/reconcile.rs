@@ -0,0 +1,167 @@
+use crate::matching::{MatchingEngine, OrderSide};
+use crate::models::{BalanceManager, ManagementManager};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// 冻结余额与未完成订单、未确认提现所需的冻结金额不一致的账户币种
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenDiscrepancy {
+    pub account_id: i32,
+    pub currency_id: i32,
+    pub expected: Decimal, // 未完成订单剩余部分和未确认提现应冻结的金额
+    pub frozen: Decimal,   // BalanceManager 中的冻结余额
+}
+
+impl FrozenDiscrepancy {
+    // 多冻结为正，少冻结为负
+    pub fn delta(&self) -> Decimal {
+        self.frozen - self.expected
+    }
+}
+
+// 核对冻结余额：balance_managers 按排序分片排列，账户属于 account_id % 分片数；
+// 未完成订单（挂单和未激活的止损单）来自所有撮合分片，买单按剩余数量和委托价冻结 quote，
+// 卖单冻结剩余的 base。只能在处理器停止处理消息时调用（如启动恢复后），否则在途的冻结
+// 和结算会被误报；交易对配置已删除的订单簿无法确定币种，跳过
+pub fn reconcile(
+    balance_managers: &[BalanceManager],
+    matching_engines: &[MatchingEngine],
+    management: &ManagementManager,
+) -> Vec<FrozenDiscrepancy> {
+    if balance_managers.is_empty() {
+        return Vec::new();
+    }
+    let owner = |account_id: i32| {
+        let shard = (account_id % balance_managers.len() as i32).unsigned_abs() as usize;
+        &balance_managers[shard]
+    };
+
+    let mut expected: BTreeMap<(i32, i32), Decimal> = BTreeMap::new();
+    for order_book in matching_engines.iter().flat_map(|engine| engine.order_books.values()) {
+        let Some(symbol) = management.get_symbol(order_book.symbol_id) else {
+            continue;
+        };
+        for account_id in order_book.open_order_accounts() {
+            for order in order_book.open_orders(account_id) {
+                let (currency_id, amount) = match order.side {
+                    OrderSide::Bid => (
+                        symbol.quote,
+                        owner(account_id).round_amount(symbol.quote, order.remaining_freeze_amount()),
+                    ),
+                    OrderSide::Ask => (symbol.base, order.remaining_freeze_amount()),
+                };
+                *expected.entry((account_id, currency_id)).or_default() += amount;
+            }
+        }
+    }
+    for hold in balance_managers.iter().flat_map(|manager| manager.withdrawal_holds.values()) {
+        *expected.entry((hold.account_id, hold.currency_id)).or_default() += hold.amount;
+    }
+
+    let mut frozen: BTreeMap<(i32, i32), Decimal> = BTreeMap::new();
+    for account in balance_managers.iter().flat_map(|manager| manager.accounts.values()) {
+        for balance in account.balances.values() {
+            if !balance.frozen.is_zero() {
+                frozen.insert((account.id, balance.currency_id), balance.frozen);
+            }
+        }
+    }
+
+    let mut keys: Vec<(i32, i32)> = expected.keys().chain(frozen.keys()).copied().collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys.into_iter()
+        .map(|(account_id, currency_id)| FrozenDiscrepancy {
+            account_id,
+            currency_id,
+            expected: expected.get(&(account_id, currency_id)).copied().unwrap_or_default(),
+            frozen: frozen.get(&(account_id, currency_id)).copied().unwrap_or_default(),
+        })
+        .filter(|discrepancy| !discrepancy.delta().is_zero())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::{OrderParams, OrderType, TimeInForce, TradingRules};
+    use uuid::Uuid;
+
+    const BTC: i32 = 1;
+    const USDT: i32 = 2;
+
+    fn place(
+        engine: &mut MatchingEngine,
+        symbol_id: i32,
+        account_id: i32,
+        side: OrderSide,
+        price: &str,
+        quantity: &str,
+    ) {
+        engine
+            .place_order(OrderParams {
+                request_id: Uuid::new_v4(),
+                symbol_id,
+                account_id,
+                order_type: OrderType::Limit as i32,
+                side: side as i32,
+                time_in_force: TimeInForce::Gtc as i32,
+                price,
+                quantity,
+                ..OrderParams::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reconcile_reports_frozen_drift_with_exact_delta() {
+        let management = ManagementManager::new();
+        management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        management.create_currency("USDT".to_string(), "Tether USD".to_string());
+        let symbol = management
+            .create_symbol("BTC-USDT".to_string(), BTC, USDT, TradingRules::default())
+            .unwrap();
+
+        // 两个排序分片：账户 10 在分片 0，账户 11 在分片 1；两个账户的订单在同一个撮合分片
+        let mut balance_managers = vec![BalanceManager::new(), BalanceManager::new()];
+        balance_managers[0].handle_increase(10, USDT, "1000");
+        balance_managers[0].freeze(10, USDT, Decimal::from(200)).unwrap();
+        balance_managers[1].handle_increase(11, BTC, "5");
+        balance_managers[1].freeze(11, BTC, Decimal::from(3)).unwrap();
+        balance_managers[1].request_withdrawal(11, BTC, Decimal::ONE).unwrap();
+        let mut engine = MatchingEngine::new();
+        place(&mut engine, symbol.id, 10, OrderSide::Bid, "100", "2");
+        place(&mut engine, symbol.id, 11, OrderSide::Ask, "110", "3");
+        let engines = vec![engine];
+        assert!(reconcile(&balance_managers, &engines, &management).is_empty());
+
+        // 人为制造偏差：账户 10 少冻结 25，账户 11 多冻结 0.5
+        let balance = balance_managers[0].accounts.get_mut(&10).unwrap().get_balance(USDT);
+        balance.frozen -= Decimal::from(25);
+        balance.available += Decimal::from(25);
+        let balance = balance_managers[1].accounts.get_mut(&11).unwrap().get_balance(BTC);
+        balance.frozen += Decimal::new(5, 1);
+        balance.available -= Decimal::new(5, 1);
+
+        let discrepancies = reconcile(&balance_managers, &engines, &management);
+        assert_eq!(
+            discrepancies,
+            vec![
+                FrozenDiscrepancy {
+                    account_id: 10,
+                    currency_id: USDT,
+                    expected: Decimal::from(200),
+                    frozen: Decimal::from(175),
+                },
+                FrozenDiscrepancy {
+                    account_id: 11,
+                    currency_id: BTC,
+                    expected: Decimal::from(4),
+                    frozen: Decimal::new(45, 1),
+                },
+            ]
+        );
+        assert_eq!(discrepancies[0].delta(), Decimal::from(-25));
+        assert_eq!(discrepancies[1].delta(), Decimal::new(5, 1));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::grpc::LightningService;
+use crate::models::schema::lightning_server::Lightning;
+use crate::models::schema::{
+    CancelOrderRequest, GetAccountRequest, GetOrderBookRequest, IncreaseRequest, PlaceOrderRequest,
+};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tonic::{Code, Request, Status};
+
+// HTTP/JSON 网关：请求转换为 proto 请求后交给 gRPC 服务处理，分片路由和校验完全一致
+// 响应体即 proto 响应结构的 JSON（camelCase 字段）
+pub fn router(service: Arc<LightningService>) -> Router {
+    Router::new()
+        .route("/accounts/{account_id}", get(get_account))
+        .route("/accounts/{account_id}/increase", post(increase))
+        .route("/orders", post(place_order))
+        .route("/orders/{order_id}/cancel", post(cancel_order))
+        .route("/orderbook/{symbol_id}", get(get_order_book))
+        .with_state(service)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountQuery {
+    currency_id: Option<i32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OrderBookQuery {
+    levels: Option<i32>,
+    bucket: Option<String>,
+}
+
+// 响应码沿用 HTTP 状态码（0 表示成功），直接作为 HTTP 状态返回
+fn json_response<T: Serialize>(
+    result: Result<tonic::Response<T>, Status>,
+    code: fn(&T) -> i32,
+) -> Response {
+    match result {
+        Ok(response) => {
+            let response = response.into_inner();
+            let status = match code(&response) {
+                0 => StatusCode::OK,
+                code => u16::try_from(code)
+                    .ok()
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            (status, Json(response)).into_response()
+        }
+        Err(status) => status_response(status),
+    }
+}
+
+// 队列已满等 gRPC 错误转换为对应的 HTTP 状态
+fn status_response(status: Status) -> Response {
+    let http_status = match status.code() {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::ResourceExhausted | Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let body = serde_json::json!({
+        "code": http_status.as_u16(),
+        "message": status.message(),
+    });
+    (http_status, Json(body)).into_response()
+}
+
+async fn get_account(
+    State(service): State<Arc<LightningService>>,
+    Path(account_id): Path<i32>,
+    Query(query): Query<AccountQuery>,
+) -> Response {
+    let request = GetAccountRequest {
+        account_id,
+        currency_id: query.currency_id,
+    };
+    json_response(service.get_account(Request::new(request)).await, |r| r.code)
+}
+
+async fn increase(
+    State(service): State<Arc<LightningService>>,
+    Path(account_id): Path<i32>,
+    Json(request): Json<IncreaseRequest>,
+) -> Response {
+    let request = IncreaseRequest {
+        account_id,
+        ..request
+    };
+    json_response(service.increase(Request::new(request)).await, |r| r.code)
+}
+
+async fn place_order(
+    State(service): State<Arc<LightningService>>,
+    Json(request): Json<PlaceOrderRequest>,
+) -> Response {
+    json_response(service.place_order(Request::new(request)).await, |r| r.code)
+}
+
+async fn cancel_order(
+    State(service): State<Arc<LightningService>>,
+    Path(order_id): Path<i64>,
+    Json(request): Json<CancelOrderRequest>,
+) -> Response {
+    let request = CancelOrderRequest { order_id, ..request };
+    json_response(service.cancel_order(Request::new(request)).await, |r| r.code)
+}
+
+async fn get_order_book(
+    State(service): State<Arc<LightningService>>,
+    Path(symbol_id): Path<i32>,
+    Query(query): Query<OrderBookQuery>,
+) -> Response {
+    let request = GetOrderBookRequest {
+        request_id: 0,
+        symbol_id,
+        levels: query.levels,
+        bucket: query.bucket,
+    };
+    json_response(service.get_order_book(Request::new(request)).await, |r| r.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::ProcessorHealth;
+    use crate::market_data::{
+        OrderBookPublisher, TradePublisher, ORDER_BOOK_CHANNEL_CAPACITY, TRADE_CHANNEL_CAPACITY,
+    };
+    use crate::matching::{MatchingEngine, TradingRules};
+    use crate::models::{BalanceManager, ManagementManager};
+    use crate::processor::{drain_processors, MatchProcessor, SequencerProcessor};
+    use crate::wal::{self, WriteAheadLog};
+    use axum::body::Body;
+    use axum::http::Method;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn call(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rest_gateway_deposits_places_and_cancels() {
+        let management = Arc::new(ManagementManager::new());
+        management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        management.create_currency("USDT".to_string(), "Tether USD".to_string());
+        management
+            .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+            .unwrap();
+        let wal_dir =
+            std::env::temp_dir().join(format!("lightning-test-{}", uuid::Uuid::new_v4()));
+
+        // 单分片：一个 SequencerProcessor 和一个 MatchProcessor
+        let (sequencer_sender, sequencer_receiver) = crossbeam_channel::unbounded();
+        let (match_sender, match_receiver) = crossbeam_channel::unbounded();
+        let (trade_execution_sender, trade_execution_receiver) = crossbeam_channel::unbounded();
+        let sequencer = SequencerProcessor::new(
+            0,
+            1,
+            sequencer_receiver,
+            vec![match_sender.clone()],
+            trade_execution_receiver,
+            management.clone(),
+            BalanceManager::new(),
+            WriteAheadLog::open(wal::sequencer_log_path(&wal_dir, 0)).unwrap(),
+            vec![trade_execution_sender.clone()],
+        );
+        let matcher = MatchProcessor::new(
+            0,
+            match_receiver,
+            vec![trade_execution_sender.clone()],
+            management.clone(),
+            Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+            Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+            MatchingEngine::new(),
+            WriteAheadLog::open(wal::match_log_path(&wal_dir, 0)).unwrap(),
+        );
+        let sequencer_handles = vec![std::thread::spawn(move || sequencer.run())];
+        let match_handles = vec![std::thread::spawn(move || matcher.run())];
+
+        let service = LightningService::new(
+            vec![sequencer_sender.clone()],
+            vec![match_sender.clone()],
+            1,
+            (*management).clone(),
+            Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY)),
+            Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY)),
+            ProcessorHealth::new(),
+        );
+        let router = router(Arc::new(service));
+
+        let (status, body) = call(
+            &router,
+            Method::POST,
+            "/accounts/1/increase",
+            Some(json!({ "currencyId": 2, "amount": "1000" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["available"], "1000");
+
+        // 金额格式错误返回 400 和错误信息
+        let (status, body) = call(
+            &router,
+            Method::POST,
+            "/accounts/1/increase",
+            Some(json!({ "currencyId": 2, "amount": "1,000" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], 400);
+        assert_eq!(body["message"], "Invalid amount format");
+
+        let order = json!({
+            "symbolId": 1,
+            "accountId": 1,
+            "side": 0,
+            "price": "100",
+            "quantity": "2",
+        });
+        let (status, body) = call(&router, Method::POST, "/orders", Some(order)).await;
+        assert_eq!(status, StatusCode::OK);
+        let order_id = body["id"].as_i64().unwrap();
+        assert!(order_id > 0);
+
+        let (status, body) = call(&router, Method::GET, "/accounts/1?currencyId=2", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["2"]["frozen"], "200");
+        assert_eq!(body["data"]["2"]["available"], "800");
+
+        let (status, body) = call(&router, Method::GET, "/orderbook/1?levels=5", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["bids"], json!([{ "price": "100", "quantity": "2" }]));
+
+        let (status, body) = call(
+            &router,
+            Method::POST,
+            &format!("/orders/{}/cancel", order_id),
+            Some(json!({ "symbolId": 1, "accountId": 1 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["refundAmount"], "200");
+
+        let (_, body) = call(&router, Method::GET, "/accounts/1?currencyId=2", None).await;
+        assert_eq!(body["data"]["2"]["frozen"], "0");
+        assert_eq!(body["data"]["2"]["available"], "1000");
+
+        drop(router);
+        tokio::task::spawn_blocking(move || {
+            drain_processors(
+                vec![sequencer_sender],
+                vec![match_sender],
+                vec![trade_execution_sender],
+                sequencer_handles,
+                match_handles,
+            )
+        })
+        .await
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&wal_dir);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::models::BalanceError;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// 账户级风控限制：None 表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RiskLimits {
+    pub max_open_orders: Option<usize>,    // 每个账户最多的未完成订单数
+    pub max_open_notional: Option<Decimal>, // 每个账户未完成订单按价格计算的最大名义价值
+}
+
+// 下单占用余额的方式；切换前需要撤销全部未完成订单
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum PlacementMode {
+    #[default]
+    PrefreezePerOrder, // 每笔订单下单时冻结所需余额，撤单时解冻
+    MarginCheck, // 下单时只检查未完成订单的总占用不超过可用余额，不冻结，成交时从可用余额扣除
+}
+
+impl PlacementMode {
+    // 配置取值：prefreeze、margin
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "prefreeze" => Some(Self::PrefreezePerOrder),
+            "margin" => Some(Self::MarginCheck),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OpenOrder {
+    price: Decimal,
+    remaining_quantity: Decimal,
+    margin: Option<Margin>, // 保证金模式下占用的币种和方向，预冻结模式下为 None
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Margin {
+    currency_id: i32,
+    bid: bool,
+}
+
+// 排序器按请求ID跟踪本分片账户的未完成订单；只保存在内存中，启动时按订单簿重建
+#[derive(Debug, Default)]
+pub struct OpenOrderTracker {
+    limits: RiskLimits,
+    accounts: HashMap<i32, HashMap<Uuid, OpenOrder>>,
+}
+
+impl OpenOrderTracker {
+    pub fn new(limits: RiskLimits) -> Self {
+        Self {
+            limits,
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: RiskLimits) {
+        self.limits = limits;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn open_orders(&self, account_id: i32) -> usize {
+        self.accounts.get(&account_id).map_or(0, HashMap::len)
+    }
+
+    pub fn open_notional(&self, account_id: i32) -> Decimal {
+        self.accounts
+            .get(&account_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .map(|order| order.price * order.remaining_quantity)
+            .sum()
+    }
+
+    // 保证金模式下账户未完成订单占用的某个币种数量：买单按价格 × 剩余数量占用 quote，
+    // 卖单按剩余数量占用 base
+    pub fn margin_exposure(&self, account_id: i32, currency_id: i32) -> Decimal {
+        self.accounts
+            .get(&account_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .filter_map(|order| {
+                let margin = order.margin.filter(|margin| margin.currency_id == currency_id)?;
+                Some(if margin.bid {
+                    order.price * order.remaining_quantity
+                } else {
+                    order.remaining_quantity
+                })
+            })
+            .sum()
+    }
+
+    // 加上新订单后超过任一限制时拒绝
+    pub fn check(
+        &self,
+        account_id: i32,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> Result<(), BalanceError> {
+        if let Some(max_open_orders) = self.limits.max_open_orders {
+            if self.open_orders(account_id) >= max_open_orders {
+                return Err(BalanceError::RiskLimitExceeded(format!(
+                    "open orders would exceed {}",
+                    max_open_orders
+                )));
+            }
+        }
+        if let Some(max_open_notional) = self.limits.max_open_notional {
+            // 超出 Decimal 范围的名义价值同样视为超限
+            let notional = price
+                .checked_mul(quantity)
+                .and_then(|notional| notional.checked_add(self.open_notional(account_id)));
+            if notional.is_none_or(|notional| notional > max_open_notional) {
+                return Err(BalanceError::RiskLimitExceeded(format!(
+                    "open notional would exceed {}",
+                    max_open_notional
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // 改单后的名义价值替换该订单原来的名义价值，超过上限时拒绝；订单数不变
+    pub fn check_amend(
+        &self,
+        account_id: i32,
+        request_id: Uuid,
+        notional: Decimal,
+    ) -> Result<(), BalanceError> {
+        let Some(max_open_notional) = self.limits.max_open_notional else {
+            return Ok(());
+        };
+        let current = self
+            .accounts
+            .get(&account_id)
+            .and_then(|orders| orders.get(&request_id))
+            .map_or(Decimal::ZERO, |order| order.price * order.remaining_quantity);
+        let notional = notional.checked_add(self.open_notional(account_id) - current);
+        if notional.is_none_or(|notional| notional > max_open_notional) {
+            return Err(BalanceError::RiskLimitExceeded(format!(
+                "open notional would exceed {}",
+                max_open_notional
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn open(&mut self, account_id: i32, request_id: Uuid, price: Decimal, quantity: Decimal) {
+        self.insert(account_id, request_id, price, quantity, None);
+    }
+
+    // 保证金模式下开仓，同时记录占用的币种
+    pub fn open_on_margin(
+        &mut self,
+        account_id: i32,
+        request_id: Uuid,
+        price: Decimal,
+        quantity: Decimal,
+        currency_id: i32,
+        bid: bool,
+    ) {
+        let margin = Margin { currency_id, bid };
+        self.insert(account_id, request_id, price, quantity, Some(margin));
+    }
+
+    fn insert(
+        &mut self,
+        account_id: i32,
+        request_id: Uuid,
+        price: Decimal,
+        quantity: Decimal,
+        margin: Option<Margin>,
+    ) {
+        self.accounts.entry(account_id).or_default().insert(
+            request_id,
+            OpenOrder {
+                price,
+                remaining_quantity: quantity,
+                margin,
+            },
+        );
+    }
+
+    // 成交或改单后更新剩余数量和价格，剩余为 0 时移除；未跟踪的订单忽略
+    pub fn update(
+        &mut self,
+        account_id: i32,
+        request_id: Uuid,
+        price: Option<Decimal>,
+        remaining_quantity: Decimal,
+    ) {
+        if remaining_quantity <= Decimal::ZERO {
+            self.close(account_id, request_id);
+            return;
+        }
+        let Some(order) = self
+            .accounts
+            .get_mut(&account_id)
+            .and_then(|orders| orders.get_mut(&request_id))
+        else {
+            return;
+        };
+        if let Some(price) = price {
+            order.price = price;
+        }
+        order.remaining_quantity = remaining_quantity;
+    }
+
+    // 撤单、过期或撮合拒绝后移除
+    pub fn close(&mut self, account_id: i32, request_id: Uuid) {
+        if let Some(orders) = self.accounts.get_mut(&account_id) {
+            orders.remove(&request_id);
+            if orders.is_empty() {
+                self.accounts.remove(&account_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_follow_fills_amends_and_closes() {
+        let mut tracker = OpenOrderTracker::new(RiskLimits {
+            max_open_orders: Some(2),
+            max_open_notional: Some(Decimal::from(1000)),
+        });
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        tracker.open(1, first, Decimal::from(100), Decimal::from(4));
+        tracker.open(1, second, Decimal::from(50), Decimal::from(2));
+        assert_eq!(tracker.open_orders(1), 2);
+        assert_eq!(tracker.open_notional(1), Decimal::from(500));
+        assert!(tracker.check(1, Decimal::ONE, Decimal::ONE).is_err());
+        // 其他账户单独计数
+        assert!(tracker.check(2, Decimal::from(100), Decimal::from(10)).is_ok());
+
+        // 部分成交后名义价值减少，全部成交后移除
+        tracker.update(1, first, None, Decimal::ONE);
+        assert_eq!(tracker.open_notional(1), Decimal::from(200));
+        tracker.update(1, second, None, Decimal::ZERO);
+        assert_eq!(tracker.open_orders(1), 1);
+        assert!(tracker.check(1, Decimal::from(100), Decimal::from(9)).is_ok());
+        assert!(tracker.check(1, Decimal::from(100), Decimal::from(10)).is_err());
+
+        // 改价后按新价格计算，撤单后移除，未跟踪的订单忽略
+        tracker.update(1, first, Some(Decimal::from(300)), Decimal::from(2));
+        assert_eq!(tracker.open_notional(1), Decimal::from(600));
+        tracker.update(1, Uuid::new_v4(), None, Decimal::ONE);
+        // 改单按新的名义价值替换原订单的名义价值
+        assert!(tracker.check_amend(1, first, Decimal::from(1000)).is_ok());
+        assert!(tracker.check_amend(1, first, Decimal::from(1001)).is_err());
+        assert!(tracker.check_amend(1, Uuid::new_v4(), Decimal::from(401)).is_err());
+        tracker.close(1, first);
+        assert_eq!(tracker.open_orders(1), 0);
+        assert_eq!(tracker.open_notional(1), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_margin_exposure_by_currency() {
+        let mut tracker = OpenOrderTracker::default();
+        let (bid, ask, prefrozen) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        tracker.open_on_margin(1, bid, Decimal::from(100), Decimal::from(2), 2, true);
+        tracker.open_on_margin(1, ask, Decimal::from(100), Decimal::from(3), 1, false);
+        // 预冻结模式下的订单不计入保证金占用
+        tracker.open(1, prefrozen, Decimal::from(100), Decimal::from(5));
+        assert_eq!(tracker.margin_exposure(1, 2), Decimal::from(200));
+        assert_eq!(tracker.margin_exposure(1, 1), Decimal::from(3));
+
+        tracker.update(1, bid, None, Decimal::ONE);
+        tracker.close(1, ask);
+        assert_eq!(tracker.margin_exposure(1, 2), Decimal::from(100));
+        assert_eq!(tracker.margin_exposure(1, 1), Decimal::ZERO);
+        assert_eq!(tracker.margin_exposure(2, 2), Decimal::ZERO);
+    }
+}
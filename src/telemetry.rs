@@ -0,0 +1,73 @@
+// 日志和请求链路追踪：日志按级别输出到标准输出；下单请求在 gRPC 层打开 place_order span，
+// 经消息字段带到排序和撮合线程，在冻结、撮合和结算步骤分别记录子 span；
+// 启用 otlp feature 时通过 OTLP 导出，导出地址等由 OTEL_EXPORTER_OTLP_ENDPOINT 等标准环境变量配置
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[cfg(feature = "otlp")]
+const SERVICE_NAME: &str = "lightning";
+
+// 未设置 RUST_LOG 时的日志过滤规则
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+// 下单请求的根 span，request_id 与消息中的一致
+pub fn place_order_span(request_id: uuid::Uuid, account_id: i32, symbol_id: i32) -> tracing::Span {
+    tracing::info_span!("place_order", %request_id, account_id, symbol_id)
+}
+
+// 导出器句柄，停机时刷新缓冲中的 span
+pub struct Telemetry {
+    #[cfg(feature = "otlp")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+// 日志级别由 RUST_LOG 配置，默认 info：启动停止等生命周期事件为 info，逐笔订单为 debug，
+// 余额异常为 warn，消息发送失败为 error
+fn env_filter() -> tracing_subscriber::EnvFilter {
+    tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(DEFAULT_LOG_FILTER))
+}
+
+#[cfg(feature = "otlp")]
+pub fn init() -> Result<Telemetry, Box<dyn std::error::Error>> {
+    use opentelemetry::trace::TracerProvider;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(SERVICE_NAME)
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    tracing_subscriber::registry()
+        .with(env_filter())
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    tracing::info!("Exporting traces via OTLP");
+    Ok(Telemetry { provider })
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn init() -> Result<Telemetry, Box<dyn std::error::Error>> {
+    tracing_subscriber::registry()
+        .with(env_filter())
+        .with(tracing_subscriber::fmt::layer())
+        .try_init()?;
+    Ok(Telemetry {})
+}
@@ -0,0 +1,156 @@
+use crate::models::Symbol;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+
+// 价格来源：返回 1 个 currency_id 值多少 quote_currency_id，没有价格时返回 None
+pub trait PriceSource {
+    fn price(&self, currency_id: i32, quote_currency_id: i32) -> Option<Decimal>;
+}
+
+// 调用方直接提供的价格表，价格均以同一个计价币种表示
+#[derive(Debug, Clone, Default)]
+pub struct PriceMap {
+    pub quote_currency_id: i32,
+    pub prices: HashMap<i32, Decimal>,
+}
+
+impl PriceSource for PriceMap {
+    fn price(&self, currency_id: i32, quote_currency_id: i32) -> Option<Decimal> {
+        if quote_currency_id != self.quote_currency_id {
+            return None;
+        }
+        self.prices.get(&currency_id).copied()
+    }
+}
+
+// 撮合引擎的最新成交价：有 currency/quote 交易对时直接使用，只有 quote/currency 交易对时取倒数
+#[derive(Debug, Clone, Default)]
+pub struct LastTradePrices {
+    pub symbols: Vec<Symbol>,
+    pub last_prices: HashMap<i32, Decimal>, // 交易对ID -> 最新成交价
+}
+
+impl PriceSource for LastTradePrices {
+    fn price(&self, currency_id: i32, quote_currency_id: i32) -> Option<Decimal> {
+        let last_price = |base: i32, quote: i32| {
+            let symbol = self.symbols.iter().find(|s| s.base == base && s.quote == quote)?;
+            self.last_prices.get(&symbol.id).copied()
+        };
+        last_price(currency_id, quote_currency_id).or_else(|| {
+            last_price(quote_currency_id, currency_id)
+                .filter(|price| !price.is_zero())
+                .map(|price| Decimal::ONE / price)
+        })
+    }
+}
+
+// 单个币种的估值
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrencyValue {
+    pub currency_id: i32,
+    pub balance: Decimal,
+    pub price: Decimal,
+    pub value: Decimal,
+}
+
+// 账户估值：没有价格的币种单独列出，不计入总值
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountValue {
+    pub total: Decimal,
+    pub values: Vec<CurrencyValue>,
+    pub unpriced_currency_ids: Vec<i32>,
+}
+
+// 按计价币种估值，依次查询价格来源，使用第一个有价格的来源
+pub fn value_account(
+    balances: &BTreeMap<i32, Decimal>,
+    quote_currency_id: i32,
+    sources: &[&dyn PriceSource],
+) -> AccountValue {
+    let mut account_value = AccountValue::default();
+    for (&currency_id, &balance) in balances {
+        if balance.is_zero() {
+            continue;
+        }
+        let price = if currency_id == quote_currency_id {
+            Some(Decimal::ONE)
+        } else {
+            sources
+                .iter()
+                .find_map(|source| source.price(currency_id, quote_currency_id))
+        };
+        match price {
+            Some(price) => {
+                let value = balance * price;
+                account_value.total += value;
+                account_value.values.push(CurrencyValue {
+                    currency_id,
+                    balance,
+                    price,
+                    value,
+                });
+            }
+            None => account_value.unpriced_currency_ids.push(currency_id),
+        }
+    }
+    account_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BTC: i32 = 1;
+    const USDT: i32 = 2;
+    const ETH: i32 = 3;
+
+    fn symbol(id: i32, base: i32, quote: i32) -> Symbol {
+        Symbol {
+            id,
+            name: format!("{}-{}", base, quote),
+            base,
+            quote,
+            price_tick: Decimal::ZERO,
+            quantity_step: Decimal::ZERO,
+            min_notional: Decimal::ZERO,
+            min_quantity: Decimal::ZERO,
+            max_quantity: Decimal::ZERO,
+            max_notional: Decimal::ZERO,
+            status: crate::models::SymbolStatus::Active,
+        }
+    }
+
+    #[test]
+    fn test_values_btc_and_usdt_and_reports_unpriced_currencies() {
+        let balances = BTreeMap::from([
+            (BTC, Decimal::new(15, 1)),
+            (USDT, Decimal::from(1000)),
+            (ETH, Decimal::from(2)),
+        ]);
+        let last_trades = LastTradePrices {
+            symbols: vec![symbol(1, BTC, USDT)],
+            last_prices: HashMap::from([(1, Decimal::from(40000))]),
+        };
+
+        let account_value = value_account(&balances, USDT, &[&last_trades]);
+        assert_eq!(account_value.total, Decimal::from(61000));
+        assert_eq!(account_value.values.len(), 2);
+        assert_eq!(account_value.values[0].price, Decimal::from(40000));
+        // ETH 没有价格，单独列出而不是按 0 计算
+        assert_eq!(account_value.unpriced_currency_ids, vec![ETH]);
+
+        // 调用方提供的价格优先于最新成交价
+        let provided = PriceMap {
+            quote_currency_id: USDT,
+            prices: HashMap::from([(BTC, Decimal::from(50000)), (ETH, Decimal::from(3000))]),
+        };
+        let account_value = value_account(&balances, USDT, &[&provided, &last_trades]);
+        assert_eq!(account_value.total, Decimal::from(82000));
+        assert!(account_value.unpriced_currency_ids.is_empty());
+
+        // 以 BTC 计价时使用 BTC-USDT 价格的倒数
+        let account_value = value_account(&balances, BTC, &[&last_trades]);
+        assert_eq!(account_value.total, Decimal::new(1525, 3));
+        assert_eq!(account_value.unpriced_currency_ids, vec![ETH]);
+    }
+}
@@ -0,0 +1,1015 @@
+use crate::idempotency::{CachedResponse, RequestKey, SettlementKey};
+use crate::matching::{CircuitBreaker, FeeRates, MatchingEngine, OrderParams, TradingRules};
+use crate::models::{transfer_response, AuditReason, BalanceManager, FEE_ACCOUNT_ID};
+use crate::risk::PlacementMode;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// 记录头：4 字节小端长度，后跟 JSON 序列化的记录
+const RECORD_HEADER_LEN: usize = 4;
+
+// MatchProcessor 每写入这么多条记录生成一次订单簿快照
+pub const SNAPSHOT_INTERVAL: u64 = 10_000;
+
+// 预写日志记录：每个分片处理器在修改状态前先追加一条记录
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum WalRecord {
+    // SequencerProcessor：余额变更
+    // request_key 为客户端提供的幂等键，重放时恢复请求去重表
+    Increase {
+        account_id: i32,
+        currency_id: i32,
+        amount: String,
+        #[serde(default)]
+        request_key: Option<RequestKey>,
+    },
+    Decrease {
+        account_id: i32,
+        currency_id: i32,
+        amount: String,
+        #[serde(default)]
+        request_key: Option<RequestKey>,
+    },
+    Freeze {
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    },
+    Unfreeze {
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    },
+    Settle {
+        account_id: i32,
+        deduct_currency_id: i32,
+        deduct_amount: Decimal,
+        add_currency_id: i32,
+        add_amount: Decimal,
+        #[serde(default)]
+        fee_currency_id: i32,
+        #[serde(default)]
+        fee_amount: Decimal,
+        #[serde(default)]
+        fee_reserve: Decimal, // 结算时从冻结余额解冻的买单手续费预留
+        #[serde(default)]
+        margin: bool, // 保证金模式下从可用余额扣除
+        #[serde(default)]
+        settlement: Option<SettlementKey>, // 重放时恢复已结算成交的去重集合
+    },
+    CollectFee {
+        currency_id: i32,
+        amount: Decimal,
+    },
+    // 同分片划转
+    Transfer {
+        from_account_id: i32,
+        to_account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+        #[serde(default)]
+        request_key: Option<RequestKey>,
+    },
+    // 跨分片划转：转出分片扣减，转入分片入账（转入分片停止时也用于退回转出账户）
+    TransferOut {
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+        #[serde(default)]
+        request_key: Option<RequestKey>,
+        #[serde(default)]
+        to_account_id: i32,
+        #[serde(default)]
+        transfer_id: Option<Uuid>, // 与转入或退回记录配对，早期记录没有ID，不参与核对
+    },
+    // 退回转出账户时带上原划转的幂等键，退回后允许用同一个键重试
+    TransferIn {
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+        #[serde(default)]
+        request_key: Option<RequestKey>,
+        #[serde(default)]
+        transfer_id: Option<Uuid>,
+    },
+    // 提现冻结单：冻结单ID按发起顺序分配，重放时得到相同的ID
+    RequestWithdrawal {
+        account_id: i32,
+        currency_id: i32,
+        amount: Decimal,
+    },
+    ConfirmWithdrawal {
+        hold_id: u64,
+    },
+    CancelWithdrawal {
+        hold_id: u64,
+    },
+    // 运维调账
+    AdminAdjust {
+        account_id: i32,
+        currency_id: i32,
+        delta: Decimal,
+        note: String,
+    },
+    // 可用余额与保留余额之间的划转
+    AdjustReserve {
+        account_id: i32,
+        currency_id: i32,
+        tag: String,
+        amount: Decimal,
+        release: bool,
+    },
+    // 币种精度变化，之后的余额变更按新精度校验和舍入
+    SetCurrencyScale {
+        currency_id: i32,
+        scale: Option<u32>,
+    },
+    // 通过检查的客户端序号，重放时恢复每个账户最后接受的序号
+    AcceptNonce {
+        account_id: i32,
+        nonce: i64,
+    },
+    // 下单占用余额的方式变化，只在没有未完成订单时写入
+    SetPlacementMode {
+        mode: PlacementMode,
+    },
+    // MatchProcessor：订单簿变更，成交由重放撮合重新产生
+    PlaceOrder {
+        symbol_id: i32,
+        account_id: i32,
+        order_type: i32,
+        side: i32,
+        time_in_force: i32,
+        price: String,
+        quantity: String,
+        #[serde(default)]
+        fee_rates: FeeRates,
+        #[serde(default)]
+        trading_rules: Box<TradingRules>, // 下单时交易对的精度规则，重放时按原规则校验
+        #[serde(default)]
+        post_only: bool,
+        #[serde(default)]
+        display_quantity: Option<String>,
+        #[serde(default)]
+        stop_price: Option<String>,
+        #[serde(default)]
+        trigger_direction: i32,
+        #[serde(default)]
+        protection_price: Option<String>,
+        #[serde(default)]
+        expires_at: Option<u64>,
+        #[serde(default)]
+        volume: Option<String>,
+        #[serde(default)]
+        client_order_id: Option<String>,
+    },
+    CancelOrder {
+        symbol_id: i32,
+        order_id: u64,
+    },
+    CancelAllOrders {
+        symbol_id: i32,
+        account_id: i32,
+    },
+    AmendOrder {
+        symbol_id: i32,
+        order_id: u64,
+        price: String,
+        quantity: String,
+    },
+    // 到期扫描的时间，重放时按同一时间撤销到期订单
+    ExpireOrders {
+        now: u64,
+    },
+    // 装入其他节点导出的订单簿，挂单检查通过后、装入前写入，重放时直接覆盖；快照无法解析时重放同样失败
+    LoadOrderBook {
+        symbol_id: i32,
+        snapshot: Vec<u8>,
+    },
+}
+
+impl WalRecord {
+    // 记录涉及的余额币种
+    pub fn currency_ids(&self) -> Vec<i32> {
+        match self {
+            WalRecord::Increase { currency_id, .. }
+            | WalRecord::Decrease { currency_id, .. }
+            | WalRecord::Freeze { currency_id, .. }
+            | WalRecord::Unfreeze { currency_id, .. }
+            | WalRecord::CollectFee { currency_id, .. }
+            | WalRecord::Transfer { currency_id, .. }
+            | WalRecord::TransferOut { currency_id, .. }
+            | WalRecord::TransferIn { currency_id, .. }
+            | WalRecord::RequestWithdrawal { currency_id, .. }
+            | WalRecord::AdjustReserve { currency_id, .. }
+            | WalRecord::AdminAdjust { currency_id, .. } => vec![*currency_id],
+            WalRecord::Settle {
+                deduct_currency_id,
+                add_currency_id,
+                fee_currency_id,
+                ..
+            } => vec![*deduct_currency_id, *add_currency_id, *fee_currency_id],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// 已扣减转出账户、还没有转入或退回记录的跨分片划转
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingTransfer {
+    pub transfer_id: Uuid,
+    pub from_account_id: i32,
+    pub to_account_id: i32,
+    pub currency_id: i32,
+    pub amount: Decimal,
+}
+
+// 重放日志后恢复的状态
+#[derive(Debug, Default)]
+pub struct ReplayedState {
+    pub balance_manager: BalanceManager,
+    pub matching_engine: MatchingEngine,
+    pub transfers_out: HashMap<Uuid, PendingTransfer>, // 本分片转出成功的划转
+    pub transfers_in: HashSet<Uuid>,                   // 本分片完成转入或退回的划转
+    pub placement_mode: PlacementMode,                 // 本分片最后使用的下单占用方式
+}
+
+impl ReplayedState {
+    // 将一条记录应用到状态上，与处理器在线处理时的变更一致
+    pub fn apply(&mut self, record: &WalRecord) {
+        match record {
+            WalRecord::Increase {
+                account_id,
+                currency_id,
+                amount,
+                request_key,
+            } => {
+                let response = self
+                    .balance_manager
+                    .handle_increase(*account_id, *currency_id, amount);
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        *account_id,
+                        *request,
+                        CachedResponse::Increase(response),
+                    );
+                }
+            }
+            WalRecord::Decrease {
+                account_id,
+                currency_id,
+                amount,
+                request_key,
+            } => {
+                let response = self
+                    .balance_manager
+                    .handle_decrease(*account_id, *currency_id, amount);
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        *account_id,
+                        *request,
+                        CachedResponse::Decrease(response),
+                    );
+                }
+            }
+            WalRecord::Freeze {
+                account_id,
+                currency_id,
+                amount,
+            } => {
+                let _ = self
+                    .balance_manager
+                    .freeze(*account_id, *currency_id, *amount);
+            }
+            WalRecord::Unfreeze {
+                account_id,
+                currency_id,
+                amount,
+            } => {
+                self.balance_manager
+                    .release_frozen(*account_id, *currency_id, *amount);
+            }
+            WalRecord::Settle {
+                account_id,
+                deduct_currency_id,
+                deduct_amount,
+                add_currency_id,
+                add_amount,
+                fee_currency_id,
+                fee_amount,
+                fee_reserve,
+                margin,
+                settlement,
+            } => {
+                // 在线处理时被拒绝的结算重放时同样被拒绝，不收取手续费，也不记为已结算
+                let settle = if *margin {
+                    BalanceManager::settle_on_margin
+                } else {
+                    BalanceManager::settle
+                };
+                let settled = settle(
+                    &mut self.balance_manager,
+                    *account_id,
+                    *deduct_currency_id,
+                    *deduct_amount,
+                    *add_currency_id,
+                    *add_amount,
+                );
+                if settled.is_ok() {
+                    if let Some(key) = settlement {
+                        self.balance_manager.record_settlement(*key);
+                    }
+                    if !*margin && *fee_reserve > Decimal::ZERO {
+                        self.balance_manager
+                            .release_frozen(*account_id, *deduct_currency_id, *fee_reserve);
+                    }
+                    self.balance_manager
+                        .charge_fee(*account_id, *fee_currency_id, *fee_amount);
+                }
+            }
+            WalRecord::CollectFee {
+                currency_id,
+                amount,
+            } => {
+                self.balance_manager
+                    .credit(FEE_ACCOUNT_ID, *currency_id, *amount, AuditReason::Fee);
+            }
+            WalRecord::Transfer {
+                from_account_id,
+                to_account_id,
+                currency_id,
+                amount,
+                request_key,
+            } => {
+                let result = self.balance_manager.transfer(
+                    *from_account_id,
+                    *to_account_id,
+                    *currency_id,
+                    *amount,
+                );
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        *from_account_id,
+                        *request,
+                        CachedResponse::Transfer(transfer_response(result)),
+                    );
+                }
+            }
+            WalRecord::TransferOut {
+                account_id,
+                currency_id,
+                amount,
+                request_key,
+                to_account_id,
+                transfer_id,
+            } => {
+                let result = self
+                    .balance_manager
+                    .debit(*account_id, *currency_id, *amount);
+                if let (Ok(()), Some(transfer_id)) = (&result, transfer_id) {
+                    self.transfers_out.insert(
+                        *transfer_id,
+                        PendingTransfer {
+                            transfer_id: *transfer_id,
+                            from_account_id: *account_id,
+                            to_account_id: *to_account_id,
+                            currency_id: *currency_id,
+                            amount: *amount,
+                        },
+                    );
+                }
+                if let Some(request) = request_key {
+                    self.balance_manager.remember_response(
+                        *account_id,
+                        *request,
+                        CachedResponse::Transfer(transfer_response(result)),
+                    );
+                }
+            }
+            WalRecord::TransferIn {
+                account_id,
+                currency_id,
+                amount,
+                request_key,
+                transfer_id,
+            } => {
+                if let Some(transfer_id) = transfer_id {
+                    self.transfers_in.insert(*transfer_id);
+                }
+                self.balance_manager
+                    .credit(*account_id, *currency_id, *amount, AuditReason::Transfer);
+                if let Some(request) = request_key {
+                    self.balance_manager.forget_request(*account_id, request.key);
+                }
+            }
+            WalRecord::RequestWithdrawal {
+                account_id,
+                currency_id,
+                amount,
+            } => {
+                let _ = self
+                    .balance_manager
+                    .request_withdrawal(*account_id, *currency_id, *amount);
+            }
+            WalRecord::ConfirmWithdrawal { hold_id } => {
+                let _ = self.balance_manager.confirm_withdrawal(*hold_id);
+            }
+            WalRecord::CancelWithdrawal { hold_id } => {
+                let _ = self.balance_manager.cancel_withdrawal(*hold_id);
+            }
+            WalRecord::AdminAdjust {
+                account_id,
+                currency_id,
+                delta,
+                note,
+            } => {
+                let _ = self
+                    .balance_manager
+                    .admin_adjust(*account_id, *currency_id, *delta, note);
+            }
+            WalRecord::AdjustReserve {
+                account_id,
+                currency_id,
+                tag,
+                amount,
+                release,
+            } => {
+                let _ = self.balance_manager.adjust_reserve(
+                    *account_id,
+                    *currency_id,
+                    tag,
+                    *amount,
+                    *release,
+                );
+            }
+            WalRecord::SetCurrencyScale { currency_id, scale } => {
+                self.balance_manager.set_currency_scale(*currency_id, *scale);
+            }
+            WalRecord::AcceptNonce { account_id, nonce } => {
+                self.balance_manager.accept_nonce(*account_id, *nonce);
+            }
+            WalRecord::SetPlacementMode { mode } => {
+                self.placement_mode = *mode;
+            }
+            WalRecord::PlaceOrder {
+                symbol_id,
+                account_id,
+                order_type,
+                side,
+                time_in_force,
+                price,
+                quantity,
+                fee_rates,
+                trading_rules,
+                post_only,
+                display_quantity,
+                stop_price,
+                trigger_direction,
+                protection_price,
+                expires_at,
+                volume,
+                client_order_id,
+            } => {
+                let _ = match volume {
+                    Some(volume) => self.matching_engine.place_quote_order(
+                        uuid::Uuid::nil(),
+                        *symbol_id,
+                        *account_id,
+                        volume,
+                        *fee_rates,
+                        **trading_rules,
+                        protection_price.as_deref(),
+                        client_order_id.as_deref(),
+                    ),
+                    None => self.matching_engine.place_order(OrderParams {
+                        request_id: uuid::Uuid::nil(),
+                        symbol_id: *symbol_id,
+                        account_id: *account_id,
+                        order_type: *order_type,
+                        side: *side,
+                        time_in_force: *time_in_force,
+                        price,
+                        quantity,
+                        fee_rates: *fee_rates,
+                        trading_rules: **trading_rules,
+                        post_only: *post_only,
+                        display_quantity: display_quantity.as_deref(),
+                        stop_price: stop_price.as_deref(),
+                        trigger_direction: *trigger_direction,
+                        protection_price: protection_price.as_deref(),
+                        expires_at: *expires_at,
+                        client_order_id: client_order_id.as_deref(),
+                    }),
+                };
+                // 被自成交保护撤销的挂单和激活的止损单，余额已由余额记录恢复；风控计数不持久化
+                self.matching_engine.take_cancelled_makers(*symbol_id);
+                self.matching_engine.take_triggered_orders(*symbol_id);
+                self.matching_engine.take_filled_makers(*symbol_id);
+            }
+            WalRecord::CancelOrder {
+                symbol_id,
+                order_id,
+            } => {
+                self.matching_engine.cancel_order(*symbol_id, *order_id);
+            }
+            WalRecord::CancelAllOrders {
+                symbol_id,
+                account_id,
+            } => {
+                self.matching_engine.cancel_all(*symbol_id, *account_id);
+            }
+            WalRecord::AmendOrder {
+                symbol_id,
+                order_id,
+                price,
+                quantity,
+            } => {
+                let _ = self
+                    .matching_engine
+                    .amend_order(*symbol_id, *order_id, price, quantity);
+            }
+            WalRecord::ExpireOrders { now } => {
+                self.matching_engine.expire_orders(*now);
+            }
+            WalRecord::LoadOrderBook {
+                symbol_id,
+                snapshot,
+            } => {
+                let _ = self.matching_engine.load_order_book(*symbol_id, snapshot, true);
+            }
+        }
+    }
+}
+
+// 仅追加的预写日志文件
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+    position: u64,
+}
+
+impl WriteAheadLog {
+    // 打开日志用于追加；崩溃时写了一半的末尾记录会先被截断
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        read_records(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let position = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            position,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 当前日志末尾的字节偏移，快照记录该位置以便只重放之后的记录
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // 追加一条记录并落盘，返回后才允许修改内存状态。每条记录单独 sync_data，不做批量提交：
+    // 处理器处理完一条消息就回复调用方，批量提交需要把回复推迟到整批落盘之后
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        self.position += append_frame(&mut self.file, record)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl WriteAheadLog {
+    // 只读打开已有日志，之后的追加都会失败，用于测试写入失败的处理
+    pub(crate) fn open_read_only(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let position = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            position,
+        })
+    }
+}
+
+// 按日志格式追加一条记录并落盘，返回写入的字节数；死信日志使用相同的格式
+pub(crate) fn append_frame<T: Serialize>(file: &mut File, record: &T) -> io::Result<u64> {
+    let payload = serde_json::to_vec(record)?;
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "WAL record too large"))?;
+
+    let mut buffer = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    buffer.extend_from_slice(&len.to_le_bytes());
+    buffer.extend_from_slice(&payload);
+    file.write_all(&buffer)?;
+    file.sync_data()?;
+    Ok(buffer.len() as u64)
+}
+
+// 分片日志文件路径
+pub fn sequencer_log_path(dir: impl AsRef<Path>, shard: usize) -> PathBuf {
+    dir.as_ref().join(format!("sequencer-{}.wal", shard))
+}
+
+pub fn match_log_path(dir: impl AsRef<Path>, shard: usize) -> PathBuf {
+    dir.as_ref().join(format!("match-{}.wal", shard))
+}
+
+// 日志对应的快照文件路径
+pub fn snapshot_path(wal_path: impl AsRef<Path>) -> PathBuf {
+    wal_path.as_ref().with_extension("snapshot")
+}
+
+// 读取全部完整记录；末尾不完整的记录视为崩溃残留，截断到最后一条完整记录
+pub fn read_records(path: impl AsRef<Path>) -> io::Result<Vec<WalRecord>> {
+    read_records_from(path, 0)
+}
+
+// 从指定字节偏移开始读取记录
+pub fn read_records_from(path: impl AsRef<Path>, start_offset: u64) -> io::Result<Vec<WalRecord>> {
+    read_frames_from(path, start_offset)
+}
+
+pub(crate) fn read_frames_from<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    start_offset: u64,
+) -> io::Result<Vec<T>> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            if file.metadata()?.len() < start_offset {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("WAL {} is shorter than offset {}", path.display(), start_offset),
+                ));
+            }
+            file.seek(SeekFrom::Start(start_offset))?;
+            file.read_to_end(&mut data)?;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound && start_offset == 0 => {
+            return Ok(Vec::new())
+        }
+        Err(e) => return Err(e),
+    }
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let Some(header) = data.get(offset..offset + RECORD_HEADER_LEN) else {
+            break;
+        };
+        let len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+        let start = offset + RECORD_HEADER_LEN;
+        let Some(payload) = data.get(start..start + len) else {
+            break;
+        };
+        match serde_json::from_slice(payload) {
+            Ok(record) => records.push(record),
+            // 只有末尾记录允许损坏，中间损坏说明日志不可信
+            Err(_) if start + len == data.len() => break,
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Corrupted WAL record at offset {}: {}",
+                        start_offset + offset as u64,
+                        e
+                    ),
+                ));
+            }
+        }
+        offset = start + len;
+    }
+
+    if offset < data.len() {
+        let valid_len = start_offset + offset as u64;
+        println!(
+            "WAL {}: truncating {} bytes of partial record at offset {}",
+            path.display(),
+            data.len() - offset,
+            valid_len
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(valid_len)?;
+    }
+
+    Ok(records)
+}
+
+// 重放日志重建余额和订单簿状态
+pub fn replay(path: impl AsRef<Path>) -> io::Result<ReplayedState> {
+    let mut state = ReplayedState::default();
+    for record in read_records(path)? {
+        state.apply(&record);
+    }
+    Ok(state)
+}
+
+// 转出分片记录了扣减、转入分片还没有记录入账（或转出分片还没有记录退回）时停机，
+// 划转停在两个阶段之间；返回这些划转，由转入账户所在分片补记入账
+pub fn unmatched_transfers(states: &[ReplayedState]) -> Vec<PendingTransfer> {
+    let completed: HashSet<&Uuid> = states.iter().flat_map(|state| &state.transfers_in).collect();
+    let mut pending: Vec<PendingTransfer> = states
+        .iter()
+        .flat_map(|state| state.transfers_out.values())
+        .filter(|transfer| !completed.contains(&transfer.transfer_id))
+        .cloned()
+        .collect();
+    pending.sort_by_key(|transfer| transfer.transfer_id);
+    pending
+}
+
+// 写入订单簿快照：8 字节小端日志偏移 + 引擎快照；先写临时文件再重命名，避免半个快照
+pub fn write_snapshot(
+    path: impl AsRef<Path>,
+    wal_offset: u64,
+    engine: &MatchingEngine,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("snapshot.tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&wal_offset.to_le_bytes())?;
+    file.write_all(&engine.snapshot())?;
+    file.sync_data()?;
+    fs::rename(&tmp_path, path)
+}
+
+// 读取快照，返回引擎状态及其对应的日志偏移
+pub fn read_snapshot(path: impl AsRef<Path>) -> io::Result<(u64, MatchingEngine)> {
+    let data = fs::read(path)?;
+    if data.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Snapshot too short"));
+    }
+    let wal_offset = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let engine = MatchingEngine::restore(&data[8..])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok((wal_offset, engine))
+}
+
+// 恢复撮合引擎：加载最新快照，再重放快照之后的日志记录；没有快照时从头重放
+pub fn recover_matching_engine(
+    wal_path: impl AsRef<Path>,
+    circuit_breaker: Option<CircuitBreaker>,
+) -> io::Result<MatchingEngine> {
+    let wal_path = wal_path.as_ref();
+    let snapshot_path = snapshot_path(wal_path);
+    let (wal_offset, matching_engine) = if snapshot_path.exists() {
+        read_snapshot(&snapshot_path)?
+    } else {
+        (0, MatchingEngine::new())
+    };
+    let mut state = ReplayedState {
+        matching_engine,
+        ..ReplayedState::default()
+    };
+    // 熔断在重放前设置，触发过熔断的订单重放时同样撤销剩余部分
+    state.matching_engine.set_circuit_breaker(circuit_breaker);
+    for record in read_records_from(wal_path, wal_offset)? {
+        state.apply(&record);
+    }
+    Ok(state.matching_engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::OrderStatus;
+
+    const SYMBOL_ID: i32 = 1;
+    const BTC: i32 = 1;
+    const USDT: i32 = 2;
+
+    fn temp_log_path() -> PathBuf {
+        std::env::temp_dir().join(format!("lightning-wal-{}.wal", uuid::Uuid::new_v4()))
+    }
+
+    fn log_and_apply(wal: &mut WriteAheadLog, state: &mut ReplayedState, record: WalRecord) {
+        wal.append(&record).unwrap();
+        state.apply(&record);
+    }
+
+    fn place_record(account_id: i32, side: i32, price: &str, quantity: &str) -> WalRecord {
+        WalRecord::PlaceOrder {
+            symbol_id: SYMBOL_ID,
+            account_id,
+            order_type: 0,
+            side,
+            time_in_force: 0,
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+            fee_rates: FeeRates::default(),
+            trading_rules: Box::default(),
+            post_only: false,
+            display_quantity: None,
+            stop_price: None,
+            trigger_direction: 0,
+            protection_price: None,
+            expires_at: None,
+            volume: None,
+            client_order_id: None,
+        }
+    }
+
+    // (币种, 总额, 冻结, 可用)，按币种排序
+    type BalanceRows = Vec<(i32, String, String, String)>;
+
+    fn balances(state: &ReplayedState, account_id: i32) -> BalanceRows {
+        let mut data: Vec<_> = state
+            .balance_manager
+            .handle_get_account(account_id, None)
+            .data
+            .into_iter()
+            .map(|(currency_id, balance)| (currency_id, balance.value, balance.frozen, balance.available))
+            .collect();
+        data.sort();
+        data
+    }
+
+    #[test]
+    fn test_replay_restores_book_and_balances() {
+        let path = temp_log_path();
+        let mut state = ReplayedState::default();
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+
+        let records = vec![
+            WalRecord::Increase {
+                account_id: 1,
+                currency_id: USDT,
+                amount: "10000".to_string(),
+                request_key: None,
+            },
+            WalRecord::Increase {
+                account_id: 2,
+                currency_id: BTC,
+                amount: "5".to_string(),
+                request_key: None,
+            },
+            WalRecord::Freeze {
+                account_id: 2,
+                currency_id: BTC,
+                amount: Decimal::from(3),
+            },
+            place_record(2, 1, "100", "3"),
+            WalRecord::Freeze {
+                account_id: 1,
+                currency_id: USDT,
+                amount: Decimal::from(200),
+            },
+            place_record(1, 0, "100", "2"),
+            WalRecord::Settle {
+                account_id: 2,
+                deduct_currency_id: BTC,
+                deduct_amount: Decimal::from(2),
+                add_currency_id: USDT,
+                add_amount: Decimal::from(200),
+                fee_currency_id: USDT,
+                fee_amount: Decimal::ZERO,
+                fee_reserve: Decimal::ZERO,
+                margin: false,
+                settlement: None,
+            },
+            WalRecord::Settle {
+                account_id: 1,
+                deduct_currency_id: USDT,
+                deduct_amount: Decimal::from(200),
+                add_currency_id: BTC,
+                add_amount: Decimal::from(2),
+                fee_currency_id: USDT,
+                fee_amount: Decimal::ZERO,
+                fee_reserve: Decimal::ZERO,
+                margin: false,
+                settlement: None,
+            },
+            WalRecord::Freeze {
+                account_id: 1,
+                currency_id: USDT,
+                amount: Decimal::from(90),
+            },
+            place_record(1, 0, "90", "1"),
+            WalRecord::AmendOrder {
+                symbol_id: SYMBOL_ID,
+                order_id: 1,
+                price: "100".to_string(),
+                quantity: "2.5".to_string(),
+            },
+        ];
+        for record in records {
+            log_and_apply(&mut wal, &mut state, record);
+        }
+        drop(wal);
+
+        let replayed = replay(&path).unwrap();
+
+        let book = state.matching_engine.get_order_book(SYMBOL_ID).unwrap();
+        let replayed_book = replayed.matching_engine.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_market_depth(10), replayed_book.get_market_depth(10));
+        assert_eq!(replayed_book.get_market_depth(10).1, vec![(Decimal::from(100), Decimal::new(5, 1))]);
+        assert_eq!(replayed_book.orders.get(&1).unwrap().status, OrderStatus::Partial);
+        assert_eq!(replayed_book.orders.len(), book.orders.len());
+
+        for account_id in [1, 2] {
+            assert_eq!(balances(&state, account_id), balances(&replayed, account_id));
+        }
+        assert_eq!(
+            balances(&replayed, 1),
+            vec![
+                (BTC, "2".to_string(), "0".to_string(), "2".to_string()),
+                (USDT, "9800".to_string(), "90".to_string(), "9710".to_string()),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_partial_final_record_is_truncated() {
+        let path = temp_log_path();
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let record = WalRecord::Increase {
+            account_id: 1,
+            currency_id: USDT,
+            amount: "100".to_string(),
+            request_key: None,
+        };
+        wal.append(&record).unwrap();
+        drop(wal);
+        let complete_len = fs::metadata(&path).unwrap().len();
+
+        // 模拟崩溃：只写入了下一条记录的头部和部分内容
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&64u32.to_le_bytes()).unwrap();
+        file.write_all(b"{\"Increase\"").unwrap();
+        drop(file);
+
+        assert_eq!(read_records(&path).unwrap(), vec![record.clone()]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete_len);
+
+        // 截断后可以继续追加
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&record).unwrap();
+        drop(wal);
+        assert_eq!(read_records(&path).unwrap().len(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_partial_header_is_truncated() {
+        let path = temp_log_path();
+        fs::write(&path, [1u8, 0]).unwrap();
+
+        let state = replay(&path).unwrap();
+        assert!(state.balance_manager.accounts.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover_from_snapshot_and_tail() {
+        let path = temp_log_path();
+        let mut state = ReplayedState::default();
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+
+        log_and_apply(&mut wal, &mut state, place_record(2, 1, "100", "3"));
+        log_and_apply(&mut wal, &mut state, place_record(3, 1, "101", "1"));
+        write_snapshot(snapshot_path(&path), wal.position(), &state.matching_engine).unwrap();
+
+        // 快照之后的记录只存在于日志尾部
+        log_and_apply(&mut wal, &mut state, place_record(1, 0, "100", "2"));
+        log_and_apply(
+            &mut wal,
+            &mut state,
+            WalRecord::CancelOrder {
+                symbol_id: SYMBOL_ID,
+                order_id: 2,
+            },
+        );
+        drop(wal);
+
+        let recovered = recover_matching_engine(&path, None).unwrap();
+        let book = state.matching_engine.get_order_book(SYMBOL_ID).unwrap();
+        let recovered_book = recovered.get_order_book(SYMBOL_ID).unwrap();
+        assert_eq!(book.get_market_depth(10), recovered_book.get_market_depth(10));
+        assert_eq!(recovered.next_order_id(), state.matching_engine.next_order_id());
+        assert_eq!(recovered.trades.len(), 1);
+
+        fs::remove_file(snapshot_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
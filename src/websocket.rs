@@ -0,0 +1,396 @@
+use crate::market_data::{OrderBookPublisher, TradePublisher};
+use crate::models::schema::{GetOrderBookResponse, PriceLevel, Side, TradeEvent};
+use crate::models::ManagementManager;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+// 每个连接待发送帧的队列容量，客户端读取过慢时丢弃新帧，不阻塞行情广播
+const CLIENT_QUEUE_CAPACITY: usize = 256;
+
+// 订阅主题：orderbook:<symbol> 或 trades:<symbol>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    OrderBook(i32),
+    Trades(i32),
+}
+
+impl Topic {
+    pub fn parse(topic: &str) -> Result<Self, String> {
+        let (channel, symbol) = topic
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid topic '{}'", topic))?;
+        let symbol_id = symbol
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid symbol in topic '{}'", topic))?;
+        match channel {
+            "orderbook" => Ok(Topic::OrderBook(symbol_id)),
+            "trades" => Ok(Topic::Trades(symbol_id)),
+            _ => Err(format!("Unknown topic '{}'", topic)),
+        }
+    }
+
+    pub fn symbol_id(&self) -> i32 {
+        match self {
+            Topic::OrderBook(symbol_id) | Topic::Trades(symbol_id) => *symbol_id,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Topic::OrderBook(symbol_id) => format!("orderbook:{}", symbol_id),
+            Topic::Trades(symbol_id) => format!("trades:{}", symbol_id),
+        }
+    }
+}
+
+// 浏览器可用的行情网关：复用 gRPC 推送接口的按交易对广播队列，以 JSON 帧推送
+// 订阅后只推送之后的变化，初始深度通过 getOrderBook 获取
+#[derive(Clone)]
+pub struct WsGateway {
+    management_manager: ManagementManager,
+    order_book_publisher: Arc<OrderBookPublisher>,
+    trade_publisher: Arc<TradePublisher>,
+}
+
+impl WsGateway {
+    pub fn new(
+        management_manager: ManagementManager,
+        order_book_publisher: Arc<OrderBookPublisher>,
+        trade_publisher: Arc<TradePublisher>,
+    ) -> Self {
+        Self {
+            management_manager,
+            order_book_publisher,
+            trade_publisher,
+        }
+    }
+
+    // 每个连接一个任务，连接错误只影响该连接
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let gateway = self.clone();
+            tokio::spawn(async move { gateway.handle_connection(stream, peer).await });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream, peer: SocketAddr) {
+        let websocket = match tokio_tungstenite::accept_async(stream).await {
+            Ok(websocket) => websocket,
+            Err(e) => {
+                println!("WebSocket handshake with {} failed: {}", peer, e);
+                return;
+            }
+        };
+        let (mut sink, mut source) = websocket.split();
+
+        // 单独的写任务按顺序发送回复和行情帧
+        let (frame_sender, mut frame_receiver) = mpsc::channel::<Message>(CLIENT_QUEUE_CAPACITY);
+        let writer = tokio::spawn(async move {
+            while let Some(frame) = frame_receiver.recv().await {
+                if sink.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut subscriptions: HashMap<Topic, JoinHandle<()>> = HashMap::new();
+        while let Some(message) = source.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    let reply =
+                        self.handle_request(text.as_str(), &frame_sender, &mut subscriptions);
+                    if frame_sender.send(Message::text(reply.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Message::Close(_)) => break,
+                // ping 由 tungstenite 自动回复 pong，其他帧忽略
+                Ok(_) => {}
+                Err(e) => {
+                    println!("WebSocket connection {} closed with error: {}", peer, e);
+                    break;
+                }
+            }
+        }
+
+        // 断开连接时取消所有订阅，释放广播接收端
+        for (_, subscription) in subscriptions {
+            subscription.abort();
+        }
+        drop(frame_sender);
+        let _ = writer.await;
+    }
+
+    // 客户端请求：{"op": "subscribe" | "unsubscribe", "topic": "orderbook:1"}
+    fn handle_request(
+        &self,
+        request: &str,
+        frame_sender: &mpsc::Sender<Message>,
+        subscriptions: &mut HashMap<Topic, JoinHandle<()>>,
+    ) -> Value {
+        let request: Value = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(_) => return error_frame("Invalid JSON request"),
+        };
+        let op = request["op"].as_str().unwrap_or_default();
+        let topic = match Topic::parse(request["topic"].as_str().unwrap_or_default()) {
+            Ok(topic) => topic,
+            Err(message) => return error_frame(&message),
+        };
+
+        match op {
+            "subscribe" => {
+                if self.management_manager.get_symbol(topic.symbol_id()).is_none() {
+                    return error_frame(&format!("Symbol {} not found", topic.symbol_id()));
+                }
+                // 重复订阅保留已有的推送任务
+                subscriptions
+                    .entry(topic)
+                    .or_insert_with(|| self.subscribe(topic, frame_sender.clone()));
+                json!({ "event": "subscribed", "topic": topic.name() })
+            }
+            "unsubscribe" => {
+                if let Some(subscription) = subscriptions.remove(&topic) {
+                    subscription.abort();
+                }
+                json!({ "event": "unsubscribed", "topic": topic.name() })
+            }
+            _ => error_frame(&format!("Unknown op '{}'", op)),
+        }
+    }
+
+    fn subscribe(&self, topic: Topic, frame_sender: mpsc::Sender<Message>) -> JoinHandle<()> {
+        match topic {
+            Topic::OrderBook(symbol_id) => forward(
+                self.order_book_publisher.subscribe(symbol_id),
+                frame_sender,
+                topic,
+                order_book_frame,
+            ),
+            Topic::Trades(symbol_id) => forward(
+                self.trade_publisher.subscribe(symbol_id),
+                frame_sender,
+                topic,
+                trade_frame,
+            ),
+        }
+    }
+}
+
+// 将广播队列中的行情转成 JSON 帧放入连接的发送队列
+fn forward<T, F>(
+    mut receiver: broadcast::Receiver<T>,
+    frame_sender: mpsc::Sender<Message>,
+    topic: Topic,
+    to_frame: F,
+) -> JoinHandle<()>
+where
+    T: Clone + Send + 'static,
+    F: Fn(&Topic, &T) -> Value + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let frame = Message::text(to_frame(&topic, &event).to_string());
+                    match frame_sender.try_send(frame) {
+                        Ok(()) => {}
+                        // 客户端读取过慢，丢弃本帧
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            println!(
+                                "WebSocket subscriber for {} is slow, dropped a frame",
+                                topic.name()
+                            );
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => break,
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    println!(
+                        "WebSocket subscriber for {} lagged, skipped {} updates",
+                        topic.name(),
+                        skipped
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+fn error_frame(message: &str) -> Value {
+    json!({ "event": "error", "message": message })
+}
+
+fn price_levels(levels: &[PriceLevel]) -> Value {
+    levels
+        .iter()
+        .map(|level| json!([level.price, level.quantity]))
+        .collect()
+}
+
+// 深度帧，bids/asks 为 [价格, 数量] 数组
+fn order_book_frame(topic: &Topic, order_book: &GetOrderBookResponse) -> Value {
+    json!({
+        "topic": topic.name(),
+        "symbolId": order_book.symbol_id,
+        "bids": price_levels(&order_book.bids),
+        "asks": price_levels(&order_book.asks),
+        "bestBid": order_book.best_bid,
+        "bestAsk": order_book.best_ask,
+        "spread": order_book.spread,
+        "timestamp": order_book.timestamp,
+        "checksum": order_book.checksum,
+    })
+}
+
+fn trade_frame(topic: &Topic, trade: &TradeEvent) -> Value {
+    let taker_side = if trade.taker_side == Side::Bid as i32 {
+        "bid"
+    } else {
+        "ask"
+    };
+    json!({
+        "topic": topic.name(),
+        "tradeId": trade.trade_id,
+        "symbolId": trade.symbol_id,
+        "price": trade.price,
+        "quantity": trade.quantity,
+        "buyOrderId": trade.buy_order_id,
+        "sellOrderId": trade.sell_order_id,
+        "takerSide": taker_side,
+        "timestamp": trade.timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::{ORDER_BOOK_CHANNEL_CAPACITY, TRADE_CHANNEL_CAPACITY};
+    use crate::matching::{MatchingEngine, TradingRules};
+    use crate::messages::MatchMessage;
+    use crate::processor::MatchProcessor;
+    use crate::wal::{self, WriteAheadLog};
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use tokio_tungstenite::tungstenite;
+
+    async fn next_frame<S>(client: &mut S) -> Value
+    where
+        S: futures_util::Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .expect("frame should arrive")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_topic() {
+        assert_eq!(Topic::parse("orderbook:1"), Ok(Topic::OrderBook(1)));
+        assert_eq!(Topic::parse("trades:42"), Ok(Topic::Trades(42)));
+        assert_eq!(Topic::Trades(42).name(), "trades:42");
+        assert!(Topic::parse("orderbook").is_err());
+        assert!(Topic::parse("orderbook:abc").is_err());
+        assert!(Topic::parse("ticker:1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_receives_depth_update_after_order_placed() {
+        let management = Arc::new(ManagementManager::new());
+        management.create_currency("BTC".to_string(), "Bitcoin".to_string());
+        management.create_currency("USDT".to_string(), "Tether USD".to_string());
+        management
+            .create_symbol("BTC-USDT".to_string(), 1, 2, TradingRules::default())
+            .unwrap();
+        let order_book_publisher = Arc::new(OrderBookPublisher::new(ORDER_BOOK_CHANNEL_CAPACITY));
+        let trade_publisher = Arc::new(TradePublisher::new(TRADE_CHANNEL_CAPACITY));
+
+        // 撮合线程与网关共用同一个发布器，解冻等回调消息直接丢弃
+        let wal_dir = std::env::temp_dir().join(format!("lightning-test-{}", uuid::Uuid::new_v4()));
+        let (match_sender, match_receiver) = crossbeam_channel::unbounded();
+        let (trade_execution_sender, _trade_execution_receiver) = crossbeam_channel::unbounded();
+        let matcher = MatchProcessor::new(
+            0,
+            match_receiver,
+            vec![trade_execution_sender],
+            management.clone(),
+            order_book_publisher.clone(),
+            trade_publisher.clone(),
+            MatchingEngine::new(),
+            WriteAheadLog::open(wal::match_log_path(&wal_dir, 0)).unwrap(),
+        );
+        let match_handle = std::thread::spawn(move || matcher.run());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let gateway = WsGateway::new((*management).clone(), order_book_publisher, trade_publisher);
+        tokio::spawn(gateway.serve(listener));
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        client
+            .send(Message::text(r#"{"op":"subscribe","topic":"orderbook:99"}"#))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut client).await["event"], "error");
+
+        client
+            .send(Message::text(r#"{"op":"subscribe","topic":"orderbook:1"}"#))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_frame(&mut client).await,
+            json!({ "event": "subscribed", "topic": "orderbook:1" })
+        );
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        match_sender
+            .send(MatchMessage::PlaceOrder {
+                request_id: uuid::Uuid::new_v4(),
+                symbol_id: 1,
+                account_id: 10,
+                order_type: 0,
+                side: 0,
+                time_in_force: 0,
+                price: "100".to_string(),
+                quantity: "1.5".to_string(),
+                taker_rate: 0,
+                maker_rate: 0,
+                post_only: false,
+                display_quantity: None,
+                stop_price: None,
+                trigger_direction: 0,
+                protection_price: None,
+                expires_at: None,
+                volume: None,
+                client_order_id: None,
+                span: tracing::Span::none(),
+                response_sender,
+            })
+            .unwrap();
+        assert_eq!(response_receiver.await.unwrap().code, 0);
+
+        let frame = next_frame(&mut client).await;
+        assert_eq!(frame["topic"], "orderbook:1");
+        assert_eq!(frame["bids"], json!([["100", "1.5"]]));
+        assert_eq!(frame["asks"], json!([]));
+        assert_eq!(frame["bestBid"], "100");
+
+        drop(client);
+        drop(match_sender);
+        match_handle.join().unwrap();
+        let _ = std::fs::remove_dir_all(&wal_dir);
+    }
+}